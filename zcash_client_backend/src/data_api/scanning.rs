@@ -5,6 +5,7 @@ use std::ops::Range;
 
 use zcash_primitives::consensus::BlockHeight;
 
+pub mod fetch_plan;
 #[cfg(feature = "unstable-spanning-tree")]
 pub mod spanning_tree;
 
@@ -0,0 +1,331 @@
+//! Sequencing of the chain data that a light client must fetch on behalf of a wallet.
+//!
+//! A wallet exposes two queues of work: the block ranges returned by
+//! [`WalletRead::suggest_scan_ranges`], and the requests for transaction data returned by
+//! [`WalletRead::transaction_data_requests`]. A [`FetchPlan`] merges the two queues into a single
+//! ordered list of [`FetchAction`]s, so that requests that can only be answered usefully once a
+//! range has been scanned are not made before that range is scanned.
+
+use std::num::NonZeroU32;
+use std::ops::Range;
+
+use zcash_primitives::{consensus::BlockHeight, transaction::TxId};
+
+#[cfg(feature = "transparent-inputs")]
+use zcash_primitives::legacy::TransparentAddress;
+
+use super::ScanRange;
+use crate::data_api::{TransactionDataRequest, WalletRead};
+
+/// A unit of work in a [`FetchPlan`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FetchAction {
+    /// Scan the given range of blocks. The range is no longer than the maximum batch size
+    /// with which the plan was constructed.
+    Scan(ScanRange),
+    /// Determine the status of each of the given transactions with respect to the main chain.
+    /// See [`TransactionDataRequest::GetStatus`].
+    GetStatus(Vec<TxId>),
+    /// Download the complete data of each of the given transactions. See
+    /// [`TransactionDataRequest::Enhancement`].
+    Enhancement(Vec<TxId>),
+    /// Detect transactions involving the given transparent address within the given block
+    /// range. See [`TransactionDataRequest::SpendsFromAddress`].
+    #[cfg(feature = "transparent-inputs")]
+    SpendsFromAddress {
+        address: TransparentAddress,
+        block_range_start: BlockHeight,
+        block_range_end: Option<BlockHeight>,
+    },
+}
+
+/// An ordered list of the [`FetchAction`]s required to bring a wallet up to date.
+///
+/// Requests that do not depend on any pending scan range come first. Each scan batch is then
+/// followed by the requests whose last dependency is that batch:
+/// - a [`TransactionDataRequest::GetStatus`] or [`TransactionDataRequest::Enhancement`] request
+///   depends on the batch containing the height at which the wallet believes the transaction
+///   was mined, if any;
+/// - a `SpendsFromAddress` request depends on every batch that overlaps its block range.
+///
+/// Within each group, status requests precede enhancement requests, which precede address
+/// queries. A transaction for which enhancement is requested is not also given a status
+/// request, because enhancement subsumes it. The plan is a pure function of the wallet's
+/// state, so the same state always produces the same plan.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FetchPlan {
+    actions: Vec<FetchAction>,
+}
+
+impl FetchPlan {
+    /// Constructs a plan from the wallet's suggested scan ranges (in the order in which they
+    /// should be scanned) and its pending transaction data requests.
+    ///
+    /// `mined_height` returns the height at which the wallet believes the given transaction
+    /// was mined, if any.
+    pub fn from_parts(
+        scan_ranges: &[ScanRange],
+        requests: &[TransactionDataRequest],
+        max_blocks_per_batch: NonZeroU32,
+        mut mined_height: impl FnMut(&TxId) -> Option<BlockHeight>,
+    ) -> Self {
+        let batches = scan_ranges
+            .iter()
+            .flat_map(|range| split_range(range, max_blocks_per_batch))
+            .collect::<Vec<_>>();
+
+        let last_overlapping = |range: Range<BlockHeight>| {
+            batches.iter().rposition(|batch| {
+                batch.block_range().start < range.end && range.start < batch.block_range().end
+            })
+        };
+
+        let mut requests = requests.to_vec();
+        requests.sort();
+        requests.dedup();
+        let enhanced = requests
+            .iter()
+            .filter_map(|request| match request {
+                TransactionDataRequest::Enhancement(txid) => Some(*txid),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        // Index 0 holds the requests that precede every batch; index `i + 1` holds the
+        // requests that follow batch `i`.
+        let mut groups = vec![RequestGroup::default(); batches.len() + 1];
+        for request in requests {
+            match request {
+                TransactionDataRequest::GetStatus(txid) => {
+                    if !enhanced.contains(&txid) {
+                        let dependency = mined_height(&txid)
+                            .and_then(|height| last_overlapping(height..height + 1));
+                        groups[dependency.map_or(0, |i| i + 1)]
+                            .get_status
+                            .push(txid);
+                    }
+                }
+                TransactionDataRequest::Enhancement(txid) => {
+                    let dependency =
+                        mined_height(&txid).and_then(|height| last_overlapping(height..height + 1));
+                    groups[dependency.map_or(0, |i| i + 1)]
+                        .enhancement
+                        .push(txid);
+                }
+                #[cfg(feature = "transparent-inputs")]
+                TransactionDataRequest::SpendsFromAddress {
+                    address,
+                    block_range_start,
+                    block_range_end,
+                } => {
+                    let dependency = last_overlapping(
+                        block_range_start
+                            ..block_range_end.unwrap_or(BlockHeight::from_u32(u32::MAX)),
+                    );
+                    groups[dependency.map_or(0, |i| i + 1)]
+                        .spends_from_address
+                        .push(FetchAction::SpendsFromAddress {
+                            address,
+                            block_range_start,
+                            block_range_end,
+                        });
+                }
+            }
+        }
+
+        let mut groups = groups.into_iter();
+        let mut actions = vec![];
+        groups
+            .next()
+            .expect("there is always a leading group")
+            .append_to(&mut actions);
+        for (batch, group) in batches.into_iter().zip(groups) {
+            actions.push(FetchAction::Scan(batch));
+            group.append_to(&mut actions);
+        }
+
+        FetchPlan { actions }
+    }
+
+    /// Returns the actions of the plan, in the order in which they should be performed.
+    pub fn actions(&self) -> &[FetchAction] {
+        &self.actions
+    }
+
+    /// Returns whether the plan has no actions, meaning that the wallet is up to date.
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+}
+
+impl IntoIterator for FetchPlan {
+    type Item = FetchAction;
+    type IntoIter = std::vec::IntoIter<FetchAction>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.actions.into_iter()
+    }
+}
+
+/// Returns the [`FetchPlan`] for the current state of the given wallet.
+///
+/// Scan ranges are split into batches of at most `max_blocks_per_batch` blocks. This only reads
+/// from the wallet.
+pub fn fetch_plan<DbT: WalletRead>(
+    wallet: &DbT,
+    max_blocks_per_batch: NonZeroU32,
+) -> Result<FetchPlan, DbT::Error> {
+    let scan_ranges = wallet.suggest_scan_ranges()?;
+    let requests = wallet.transaction_data_requests()?;
+
+    let mut mined_heights = vec![];
+    for request in &requests {
+        if let TransactionDataRequest::GetStatus(txid) | TransactionDataRequest::Enhancement(txid) =
+            request
+        {
+            mined_heights.push((*txid, wallet.get_tx_height(*txid)?));
+        }
+    }
+
+    Ok(FetchPlan::from_parts(
+        &scan_ranges,
+        &requests,
+        max_blocks_per_batch,
+        |txid| {
+            mined_heights
+                .iter()
+                .find(|(t, _)| t == txid)
+                .and_then(|(_, height)| *height)
+        },
+    ))
+}
+
+/// The requests that follow a single scan batch (or precede all of them).
+#[derive(Clone, Default)]
+struct RequestGroup {
+    get_status: Vec<TxId>,
+    enhancement: Vec<TxId>,
+    #[cfg(feature = "transparent-inputs")]
+    spends_from_address: Vec<FetchAction>,
+}
+
+impl RequestGroup {
+    fn append_to(self, actions: &mut Vec<FetchAction>) {
+        if !self.get_status.is_empty() {
+            actions.push(FetchAction::GetStatus(self.get_status));
+        }
+        if !self.enhancement.is_empty() {
+            actions.push(FetchAction::Enhancement(self.enhancement));
+        }
+        #[cfg(feature = "transparent-inputs")]
+        actions.extend(self.spends_from_address);
+    }
+}
+
+/// Splits a scan range into consecutive ranges of at most `max_len` blocks.
+fn split_range(range: &ScanRange, max_len: NonZeroU32) -> Vec<ScanRange> {
+    let mut batches = vec![];
+    let mut remaining = Some(range.clone());
+    while let Some(current) = remaining.take() {
+        let split_height = BlockHeight::from_u32(
+            u32::from(current.block_range().start).saturating_add(max_len.get()),
+        );
+        match current.split_at(split_height) {
+            Some((batch, rest)) => {
+                batches.push(batch);
+                remaining = Some(rest);
+            }
+            None => batches.push(current),
+        }
+    }
+    batches.retain(|batch| !batch.is_empty());
+    batches
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+
+    use zcash_primitives::{consensus::BlockHeight, transaction::TxId};
+
+    use super::{FetchAction, FetchPlan};
+    use crate::data_api::{
+        scanning::{ScanPriority, ScanRange},
+        TransactionDataRequest,
+    };
+
+    fn scan_range(start: u32, end: u32, priority: ScanPriority) -> ScanRange {
+        ScanRange::from_parts(
+            BlockHeight::from_u32(start)..BlockHeight::from_u32(end),
+            priority,
+        )
+    }
+
+    fn txid(n: u8) -> TxId {
+        TxId::from_bytes([n; 32])
+    }
+
+    #[test]
+    fn requests_follow_the_batches_they_depend_on() {
+        let ranges = [
+            scan_range(100, 105, ScanPriority::Verify),
+            scan_range(50, 60, ScanPriority::Historic),
+        ];
+        let requests = [
+            TransactionDataRequest::Enhancement(txid(3)),
+            TransactionDataRequest::GetStatus(txid(1)),
+            TransactionDataRequest::GetStatus(txid(2)),
+            // Subsumed by the enhancement request.
+            TransactionDataRequest::GetStatus(txid(3)),
+            TransactionDataRequest::Enhancement(txid(4)),
+            TransactionDataRequest::GetStatus(txid(1)),
+        ];
+        let mined_height = |t: &TxId| match t.as_ref()[0] {
+            // Mined in the second batch of the historic range.
+            1 => Some(BlockHeight::from_u32(57)),
+            // Mined in the verify range.
+            3 => Some(BlockHeight::from_u32(101)),
+            // Mined in a range that has already been scanned.
+            4 => Some(BlockHeight::from_u32(70)),
+            _ => None,
+        };
+
+        let plan = FetchPlan::from_parts(
+            &ranges,
+            &requests,
+            NonZeroU32::new(5).unwrap(),
+            mined_height,
+        );
+        assert_eq!(
+            plan.actions(),
+            &[
+                FetchAction::GetStatus(vec![txid(2)]),
+                FetchAction::Enhancement(vec![txid(4)]),
+                FetchAction::Scan(scan_range(100, 105, ScanPriority::Verify)),
+                FetchAction::Enhancement(vec![txid(3)]),
+                FetchAction::Scan(scan_range(50, 55, ScanPriority::Historic)),
+                FetchAction::Scan(scan_range(55, 60, ScanPriority::Historic)),
+                FetchAction::GetStatus(vec![txid(1)]),
+            ]
+        );
+
+        // The plan does not depend on the order in which requests were queued.
+        let mut reversed = requests.to_vec();
+        reversed.reverse();
+        assert_eq!(
+            FetchPlan::from_parts(
+                &ranges,
+                &reversed,
+                NonZeroU32::new(5).unwrap(),
+                mined_height
+            ),
+            plan
+        );
+    }
+
+    #[test]
+    fn empty_plan() {
+        let plan = FetchPlan::from_parts(&[], &[], NonZeroU32::new(10).unwrap(), |_| None);
+        assert!(plan.is_empty());
+    }
+}
@@ -1827,8 +1827,6 @@ where
     assert!(tx.is_shielding());
 }
 
-// FIXME: This requires fixes to the test framework.
-#[allow(dead_code)]
 pub fn birthday_in_anchor_shard<T: ShieldedPoolTester>(
     ds_factory: impl DataStoreFactory,
     cache: impl TestCache,
@@ -4,7 +4,8 @@ use crate::{
             AddressType, DataStoreFactory, ShieldedProtocol, TestBuilder, TestCache, TestState,
         },
         wallet::input_selection::GreedyInputSelector,
-        Account as _, InputSource, WalletRead, WalletWrite,
+        Account as _, DecryptedTransaction, InputSource, TransactionDataRequest, WalletRead,
+        WalletWrite, GAP_LIMIT,
     },
     fees::{standard, DustOutputPolicy, StandardFeeRule},
     wallet::WalletTransparentOutput,
@@ -13,7 +14,12 @@ use assert_matches::assert_matches;
 use sapling::zip32::ExtendedSpendingKey;
 use zcash_primitives::{
     block::BlockHash,
-    transaction::components::{amount::NonNegativeAmount, OutPoint, TxOut},
+    consensus::BranchId,
+    legacy::Script,
+    transaction::{
+        components::{amount::NonNegativeAmount, transparent, OutPoint, TxOut},
+        Authorized, Transaction, TransactionData, TxVersion,
+    },
 };
 
 pub fn put_received_transparent_utxo<DSF>(dsf: DSF)
@@ -245,3 +251,222 @@ where
 
     check_balance(&st, 0, value);
 }
+
+/// Checks that each output of a transaction that pays the same wallet address more than once
+/// is tracked separately in balances, spendable outputs, and spend detection requests.
+pub fn multiple_outputs_to_same_address<DSF>(dsf: DSF)
+where
+    DSF: DataStoreFactory,
+{
+    let mut st = TestBuilder::new()
+        .with_data_store_factory(dsf)
+        .with_account_from_sapling_activation(BlockHash([0; 32]))
+        .build();
+
+    let birthday = st.test_account().unwrap().birthday().height();
+    let account_id = st.test_account().unwrap().id();
+    let uaddr = st
+        .wallet()
+        .get_current_address(account_id)
+        .unwrap()
+        .unwrap();
+    let taddr = *uaddr.transparent().unwrap();
+
+    let height = birthday + 12345;
+    st.wallet_mut().update_chain_tip(height).unwrap();
+
+    let transparent_tx = |bundle: transparent::Bundle<transparent::Authorized>| -> Transaction {
+        TransactionData::<Authorized>::from_parts(
+            TxVersion::Zip225,
+            BranchId::Nu5,
+            0,
+            0.into(),
+            Some(bundle),
+            None,
+            None,
+            None,
+        )
+        .freeze()
+        .unwrap()
+    };
+
+    // A consolidation transaction that pays the same address twice, with different values.
+    let values = [
+        NonNegativeAmount::const_from_u64(100000),
+        NonNegativeAmount::const_from_u64(20000),
+    ];
+    let funding_tx = transparent_tx(transparent::Bundle {
+        vin: vec![],
+        vout: values
+            .iter()
+            .map(|value| TxOut {
+                value: *value,
+                script_pubkey: taddr.script(),
+            })
+            .collect(),
+        authorization: transparent::Authorized,
+    });
+    st.wallet_mut()
+        .store_decrypted_tx(DecryptedTransaction::new(
+            Some(height),
+            &funding_tx,
+            vec![],
+            #[cfg(feature = "orchard")]
+            vec![],
+        ))
+        .unwrap();
+    let outpoints = [0, 1].map(|n| OutPoint::new(funding_tx.txid().into(), n));
+
+    let spendable_outpoints = |wallet: &DSF::DataStore| {
+        let mut outpoints = wallet
+            .get_spendable_transparent_outputs(&taddr, height, 0)
+            .unwrap()
+            .iter()
+            .map(|output| output.outpoint().clone())
+            .collect::<Vec<_>>();
+        outpoints.sort_by_key(|outpoint| outpoint.n());
+        outpoints
+    };
+    let spend_search_requested = |requests: Vec<TransactionDataRequest>| {
+        requests.iter().any(|request| {
+            matches!(
+                request,
+                TransactionDataRequest::SpendsFromAddress { address, .. } if address == &taddr
+            )
+        })
+    };
+
+    assert_eq!(
+        st.wallet()
+            .get_transparent_balances(account_id, height)
+            .unwrap()
+            .get(&taddr),
+        Some(&(values[0] + values[1]).unwrap())
+    );
+    assert_eq!(spendable_outpoints(st.wallet()), outpoints.to_vec());
+    assert!(spend_search_requested(
+        st.wallet().transaction_data_requests().unwrap()
+    ));
+
+    // Spending one of the outputs leaves the other in the balance, and its spend is still
+    // searched for.
+    let spending_tx = transparent_tx(transparent::Bundle {
+        vin: vec![transparent::TxIn {
+            prevout: outpoints[0].clone(),
+            script_sig: Script(vec![]),
+            sequence: 0,
+        }],
+        vout: vec![],
+        authorization: transparent::Authorized,
+    });
+    st.wallet_mut()
+        .store_decrypted_tx(DecryptedTransaction::new(
+            Some(height),
+            &spending_tx,
+            vec![],
+            #[cfg(feature = "orchard")]
+            vec![],
+        ))
+        .unwrap();
+
+    assert_eq!(
+        st.wallet()
+            .get_transparent_balances(account_id, height)
+            .unwrap()
+            .get(&taddr),
+        Some(&values[1])
+    );
+    assert_eq!(spendable_outpoints(st.wallet()), vec![outpoints[1].clone()]);
+    assert!(spend_search_requested(
+        st.wallet().transaction_data_requests().unwrap()
+    ));
+}
+
+pub fn ephemeral_address_gap_rewound_on_truncation<DSF>(dsf: DSF, cache: impl TestCache)
+where
+    DSF: DataStoreFactory,
+{
+    let mut st = TestBuilder::new()
+        .with_data_store_factory(dsf)
+        .with_block_cache(cache)
+        .with_account_from_sapling_activation(BlockHash([0; 32]))
+        .build();
+    let account_id = st.test_account().unwrap().id();
+
+    let not_our_key = ExtendedSpendingKey::master(&[]).to_diversifiable_full_viewing_key();
+    let not_our_value = NonNegativeAmount::const_from_u64(10000);
+    let (height, _, _) =
+        st.generate_next_block(&not_our_key, AddressType::DefaultExternal, not_our_value);
+    st.generate_next_block(&not_our_key, AddressType::DefaultExternal, not_our_value);
+    st.scan_cached_blocks(height, 2);
+
+    // Reserve the addresses at indices 0 to 10. As none of them has been observed in a mined
+    // transaction, no more than `GAP_LIMIT` addresses may be reserved in total.
+    let reserved = st
+        .wallet_mut()
+        .reserve_next_n_ephemeral_addresses(account_id, 11)
+        .unwrap();
+    let ephemeral_address = reserved[10].0;
+    let remaining = GAP_LIMIT as usize - reserved.len();
+    assert!(st
+        .wallet_mut()
+        .reserve_next_n_ephemeral_addresses(account_id, remaining + 1)
+        .is_err());
+
+    // A transaction paying the address at index 10, mined in the last scanned block.
+    let tx = TransactionData::<Authorized>::from_parts(
+        TxVersion::Zip225,
+        BranchId::Nu5,
+        0,
+        0.into(),
+        Some(transparent::Bundle {
+            vin: vec![],
+            vout: vec![TxOut {
+                value: NonNegativeAmount::const_from_u64(100000),
+                script_pubkey: ephemeral_address.script(),
+            }],
+            authorization: transparent::Authorized,
+        }),
+        None,
+        None,
+        None,
+    )
+    .freeze()
+    .unwrap();
+    let store_mined = |wallet: &mut DSF::DataStore| {
+        wallet
+            .store_decrypted_tx(DecryptedTransaction::new(
+                Some(height + 1),
+                &tx,
+                vec![],
+                #[cfg(feature = "orchard")]
+                vec![],
+            ))
+            .unwrap();
+    };
+    store_mined(st.wallet_mut());
+
+    // Truncating below the block that contains the transaction returns the gap to its
+    // pre-mining bound, and the transaction is requested again so that its output can be
+    // re-detected.
+    st.truncate_to_height(height);
+    assert!(st
+        .wallet_mut()
+        .reserve_next_n_ephemeral_addresses(account_id, remaining + 1)
+        .is_err());
+    assert!(st
+        .wallet()
+        .transaction_data_requests()
+        .unwrap()
+        .contains(&TransactionDataRequest::Enhancement(tx.txid())));
+
+    // Once the transaction is observed as mined in the new chain, the gap advances again.
+    st.generate_next_block(&not_our_key, AddressType::DefaultExternal, not_our_value);
+    st.scan_cached_blocks(height + 1, 1);
+    store_mined(st.wallet_mut());
+    let reserved = st
+        .wallet_mut()
+        .reserve_next_n_ephemeral_addresses(account_id, remaining + 1)
+        .unwrap();
+    assert_eq!(reserved.len(), remaining + 1);
+}
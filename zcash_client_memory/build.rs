@@ -28,6 +28,7 @@ fn build() -> io::Result<()> {
             "src/proto/memory_wallet.proto",
             "src/proto/notes.proto",
             "src/proto/primitives.proto",
+            "src/proto/redacted_diagnostics.proto",
             "src/proto/shardtree.proto",
             "src/proto/transparent.proto",
         ],
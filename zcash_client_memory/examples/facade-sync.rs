@@ -0,0 +1,50 @@
+//! Imports a unified full viewing key into an in-memory wallet, syncs it with a
+//! `lightwalletd` server, and prints the account's balance.
+//!
+//! ```text
+//! cargo run --example facade-sync --features facade -- <lightwalletd URL> <UFVK> <birthday height>
+//! ```
+
+use tonic::transport::Channel;
+use zcash_client_backend::{
+    data_api::{AccountBirthday, AccountPurpose},
+    proto::service::{compact_tx_streamer_client::CompactTxStreamerClient, BlockId},
+};
+use zcash_client_memory::facade::MemoryWallet;
+use zcash_keys::keys::UnifiedFullViewingKey;
+use zcash_primitives::consensus::Network;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let [url, ufvk, birthday_height] = &args[..] else {
+        return Err("usage: facade-sync <lightwalletd URL> <UFVK> <birthday height>".into());
+    };
+    let network = Network::MainNetwork;
+    let ufvk = UnifiedFullViewingKey::decode(&network, ufvk)?;
+
+    let channel = Channel::from_shared(url.clone())?.connect().await?;
+    let mut client = CompactTxStreamerClient::new(channel);
+
+    // The birthday is given by the state of the note commitment trees as of the block
+    // before the first block in which the account could have received funds.
+    let tree_state = client
+        .get_tree_state(BlockId {
+            height: birthday_height.parse::<u64>()? - 1,
+            hash: vec![],
+        })
+        .await?
+        .into_inner();
+    let birthday = AccountBirthday::from_treestate(tree_state, None)
+        .map_err(|_| "lightwalletd returned an invalid tree state")?;
+
+    let mut wallet = MemoryWallet::new(network);
+    let account = wallet.import_ufvk(&ufvk, &birthday, AccountPurpose::ViewOnly)?;
+    wallet.sync_once(&mut client).await?;
+
+    match wallet.balance(account)? {
+        Some(balance) => println!("Balance: {} zatoshis", balance.total().into_u64()),
+        None => println!("The wallet has not been synced."),
+    }
+    Ok(())
+}
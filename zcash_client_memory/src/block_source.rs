@@ -1,28 +1,248 @@
 use async_trait::async_trait;
 use std::collections::BTreeMap;
-use std::convert::Infallible;
+use std::ops::Range;
 use wasm_sync::RwLock;
-use zcash_client_backend::data_api::chain::{BlockCache, BlockSource};
+use zcash_client_backend::data_api::chain::{self, BlockCache, BlockSource};
 use zcash_client_backend::data_api::scanning::ScanRange;
 use zcash_client_backend::proto::compact_formats::CompactBlock;
+use zcash_client_backend::scanning::ScanError;
 use zcash_protocol::consensus::BlockHeight;
 
-/// A block cache that just holds blocks in a map in memory
+/// The first block missing from a block source within a range of heights.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("Block {missing_height} is missing from the block source ({contiguous_blocks} contiguous blocks available)")]
+pub struct CacheGap {
+    missing_height: BlockHeight,
+    contiguous_blocks: usize,
+}
+
+impl CacheGap {
+    /// Returns the height of the first block in the range that is not available.
+    pub fn missing_height(&self) -> BlockHeight {
+        self.missing_height
+    }
+
+    /// Returns the number of blocks at the start of the range that are available. These
+    /// blocks may be scanned without encountering the gap.
+    pub fn contiguous_blocks(&self) -> usize {
+        self.contiguous_blocks
+    }
+}
+
+/// Errors that can occur while checking the coverage of a block source.
+#[derive(Debug, thiserror::Error)]
+pub enum CacheCoverageError<E> {
+    #[error(transparent)]
+    Gap(CacheGap),
+    #[error("Block source error: {0}")]
+    BlockSource(E),
+    #[error("Scan error: {0}")]
+    Scan(ScanError),
+}
+
+/// Checks that the given block source contains every block in `range`, so that the range can
+/// be passed to `scan_cached_blocks` without it failing partway through for lack of data.
+///
+/// Blocks are visited in height order starting from `range.start`, and iteration stops at the
+/// first missing height.
+pub fn validate_cache_coverage<S: BlockSource>(
+    source: &S,
+    range: Range<BlockHeight>,
+) -> Result<(), CacheCoverageError<S::Error>> {
+    let mut next_height = range.start;
+    let limit = usize::try_from(u32::from(range.end).saturating_sub(u32::from(range.start)))
+        .expect("u32 fits in usize");
+    if limit == 0 {
+        return Ok(());
+    }
+
+    let gap_at = |height: BlockHeight| CacheGap {
+        missing_height: height,
+        contiguous_blocks: usize::try_from(height - range.start).expect("u32 fits in usize"),
+    };
+
+    source
+        .with_blocks::<_, CacheGap>(Some(range.start), Some(limit), |block| {
+            if block.height() == next_height {
+                next_height = next_height + 1;
+                Ok(())
+            } else {
+                Err(chain::error::Error::Wallet(gap_at(next_height)))
+            }
+        })
+        .map_err(|e| match e {
+            chain::error::Error::Wallet(gap) => CacheCoverageError::Gap(gap),
+            chain::error::Error::BlockSource(e) => CacheCoverageError::BlockSource(e),
+            chain::error::Error::Scan(e) => CacheCoverageError::Scan(e),
+        })?;
+
+    if next_height < range.end {
+        Err(CacheCoverageError::Gap(gap_at(next_height)))
+    } else {
+        Ok(())
+    }
+}
+
+/// The span of heights for which a [`MemBlockCache`] holds blocks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedRange {
+    heights: Range<BlockHeight>,
+    holes: Vec<Range<BlockHeight>>,
+}
+
+impl CachedRange {
+    /// Returns the range from the lowest cached height to one past the highest cached height.
+    pub fn heights(&self) -> &Range<BlockHeight> {
+        &self.heights
+    }
+
+    /// Returns the ranges of heights within [`Self::heights`] for which no block is cached,
+    /// in ascending order.
+    pub fn holes(&self) -> &[Range<BlockHeight>] {
+        &self.holes
+    }
+}
+
+/// A block cache that just holds blocks in a map in memory.
+///
+/// By default every inserted block is retained until it is deleted. A cache constructed with
+/// [`MemBlockCache::with_capacity`] instead evicts the lowest blocks once it holds more than
+/// its capacity, but only blocks below the wallet's fully-scanned height, as reported by
+/// [`MemBlockCache::set_fully_scanned_height`]; blocks that may not yet have been scanned are
+/// never evicted, so the cache may temporarily exceed its capacity.
 #[derive(Default)]
-pub struct MemBlockCache(pub(crate) RwLock<BTreeMap<BlockHeight, CompactBlock>>);
+pub struct MemBlockCache {
+    pub(crate) blocks: RwLock<BTreeMap<BlockHeight, CompactBlock>>,
+    capacity: Option<usize>,
+    fully_scanned_height: RwLock<Option<BlockHeight>>,
+}
 
 impl MemBlockCache {
     pub fn new() -> Self {
         Default::default()
     }
 
+    /// Returns an empty cache that retains at most `max_blocks` blocks, subject to the
+    /// eviction rules described on [`MemBlockCache`].
+    pub fn with_capacity(max_blocks: usize) -> Self {
+        MemBlockCache {
+            capacity: Some(max_blocks),
+            ..Default::default()
+        }
+    }
+
+    /// Returns the maximum number of blocks that the cache retains, if it is bounded.
+    pub fn capacity(&self) -> Option<usize> {
+        self.capacity
+    }
+
+    /// Records the height up to which the wallet has scanned, and evicts blocks below it if the
+    /// cache holds more blocks than its capacity.
+    ///
+    /// This should be called with the height of [`WalletRead::block_fully_scanned`] after each
+    /// call to `scan_cached_blocks`. The `facade` wallet does so after each sync.
+    ///
+    /// [`WalletRead::block_fully_scanned`]: zcash_client_backend::data_api::WalletRead::block_fully_scanned
+    pub fn set_fully_scanned_height(&self, height: BlockHeight) {
+        *self.fully_scanned_height.write().unwrap() = Some(height);
+        self.evict(&mut self.blocks.write().unwrap());
+    }
+
+    /// Returns the number of blocks in the cache.
+    pub fn len(&self) -> usize {
+        self.blocks.read().unwrap().len()
+    }
+
+    /// Returns whether the cache holds no blocks.
+    pub fn is_empty(&self) -> bool {
+        self.blocks.read().unwrap().is_empty()
+    }
+
+    /// Returns the height of the highest cached block, or `None` if the cache is empty.
+    pub fn max_height(&self) -> Option<BlockHeight> {
+        self.blocks
+            .read()
+            .unwrap()
+            .last_key_value()
+            .map(|(h, _)| *h)
+    }
+
     pub fn find_block(&self, block_height: BlockHeight) -> Option<CompactBlock> {
-        self.0.read().unwrap().get(&block_height).cloned()
+        self.blocks.read().unwrap().get(&block_height).cloned()
+    }
+
+    /// Returns up to `limit` blocks of the contiguous run of cached blocks starting at
+    /// `from_height`, in height order. The result is empty if the block at `from_height` is
+    /// not cached, and ends before `limit` blocks at the first height that is not cached.
+    pub fn get_range(&self, from_height: BlockHeight, limit: usize) -> Vec<CompactBlock> {
+        let inner = self.blocks.read().unwrap();
+        let mut next_height = from_height;
+        inner
+            .range(from_height..)
+            .take(limit)
+            .take_while(|(height, _)| {
+                let contiguous = **height == next_height;
+                next_height = next_height + 1;
+                contiguous
+            })
+            .map(|(_, cb)| cb.clone())
+            .collect()
+    }
+
+    /// Returns the span of heights for which blocks are cached, along with any holes in it,
+    /// or `None` if the cache is empty.
+    pub fn cached_range(&self) -> Option<CachedRange> {
+        let inner = self.blocks.read().unwrap();
+        let mut heights = inner.keys().copied();
+        let start = heights.next()?;
+
+        let mut holes = vec![];
+        let mut end = start + 1;
+        for height in heights {
+            if height > end {
+                holes.push(end..height);
+            }
+            end = height + 1;
+        }
+
+        Some(CachedRange {
+            heights: start..end,
+            holes,
+        })
+    }
+
+    pub(crate) fn insert_block(&self, compact_block: CompactBlock) {
+        let mut inner = self.blocks.write().unwrap();
+        inner.insert(compact_block.height(), compact_block);
+        self.evict(&mut inner);
+    }
+
+    /// Evicts the lowest blocks below the fully-scanned height until the cache is within its
+    /// capacity, or no more blocks may be evicted.
+    fn evict(&self, inner: &mut BTreeMap<BlockHeight, CompactBlock>) {
+        let (capacity, fully_scanned_height) =
+            match (self.capacity, *self.fully_scanned_height.read().unwrap()) {
+                (Some(capacity), Some(height)) => (capacity, height),
+                _ => return,
+            };
+        while inner.len() > capacity {
+            match inner.first_key_value() {
+                Some((height, _)) if *height < fully_scanned_height => {
+                    inner.pop_first();
+                }
+                _ => break,
+            }
+        }
     }
 }
 
+/// Blocks are provided in height order, starting at `from_height` (or at the lowest cached
+/// block) and spanning at most `limit` heights. If the cache holds a block in that range above
+/// a height for which it holds no block, a [`CacheGap`] naming the first missing height is
+/// returned, so that the caller can narrow the range that it downloads. Blocks missing from the
+/// end of the range are not an error; fewer blocks are provided instead.
 impl BlockSource for MemBlockCache {
-    type Error = Infallible;
+    type Error = CacheGap;
 
     fn with_blocks<F, WalletErrT>(
         &self,
@@ -38,20 +258,29 @@ impl BlockSource for MemBlockCache {
             zcash_client_backend::data_api::chain::error::Error<WalletErrT, Self::Error>,
         >,
     {
-        let inner = self.0.read().unwrap();
-        let block_iter = inner
-            .iter()
-            .filter(|(_, cb)| {
-                if let Some(from_height) = from_height {
-                    cb.height() >= from_height
-                } else {
-                    true
-                }
-            })
-            .take(limit.unwrap_or(usize::MAX));
+        let inner = self.blocks.read().unwrap();
+        let start = match from_height.or_else(|| inner.keys().next().copied()) {
+            Some(start) => start,
+            None => return Ok(()),
+        };
+        let end = limit.map(|limit| {
+            u64::from(u32::from(start)).saturating_add(u64::try_from(limit).unwrap_or(u64::MAX))
+        });
 
-        for (_, cb) in block_iter {
+        let mut next_height = start;
+        for (height, cb) in inner.range(start..) {
+            if end.map_or(false, |end| u64::from(u32::from(*height)) >= end) {
+                break;
+            }
+            if *height != next_height {
+                return Err(chain::error::Error::BlockSource(CacheGap {
+                    missing_height: next_height,
+                    contiguous_blocks: usize::try_from(next_height - start)
+                        .expect("u32 fits in usize"),
+                }));
+            }
             with_block(cb.clone())?;
+            next_height = *height + 1;
         }
         Ok(())
     }
@@ -63,44 +292,35 @@ impl BlockCache for MemBlockCache {
         &self,
         range: Option<&ScanRange>,
     ) -> Result<Option<BlockHeight>, Self::Error> {
-        let inner = self.0.read().unwrap();
-        if let Some(range) = range {
-            let range = range.block_range();
-            for h in (u32::from(range.start)..u32::from(range.end)).rev() {
-                if let Some(cb) = inner.get(&h.into()) {
-                    return Ok(Some(cb.height()));
-                }
-            }
-        } else {
-            return Ok(inner.last_key_value().map(|(h, _)| *h));
-        }
-
-        Ok(None)
+        let inner = self.blocks.read().unwrap();
+        Ok(match range {
+            Some(range) => inner
+                .range(range.block_range().clone())
+                .next_back()
+                .map(|(h, _)| *h),
+            None => inner.last_key_value().map(|(h, _)| *h),
+        })
     }
 
     async fn read(&self, range: &ScanRange) -> Result<Vec<CompactBlock>, Self::Error> {
-        let inner = self.0.read().unwrap();
-        let mut ret = Vec::with_capacity(range.len());
-        let range = range.block_range();
-        for height in u32::from(range.start)..u32::from(range.end) {
-            if let Some(cb) = inner.get(&height.into()) {
-                ret.push(cb.clone());
-            }
-        }
-
-        Ok(ret)
+        let inner = self.blocks.read().unwrap();
+        Ok(inner
+            .range(range.block_range().clone())
+            .map(|(_, cb)| cb.clone())
+            .collect())
     }
 
     async fn insert(&self, compact_blocks: Vec<CompactBlock>) -> Result<(), Self::Error> {
-        let mut inner = self.0.write().unwrap();
+        let mut inner = self.blocks.write().unwrap();
         compact_blocks.into_iter().for_each(|compact_block| {
             inner.insert(compact_block.height(), compact_block);
         });
+        self.evict(&mut inner);
         Ok(())
     }
 
     async fn delete(&self, range: ScanRange) -> Result<(), Self::Error> {
-        let mut inner = self.0.write().unwrap();
+        let mut inner = self.blocks.write().unwrap();
         let range = range.block_range();
         for height in u32::from(range.start)..u32::from(range.end) {
             inner.remove(&height.into());
@@ -108,3 +328,201 @@ impl BlockCache for MemBlockCache {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Range;
+
+    use zcash_client_backend::{
+        data_api::{
+            chain,
+            testing::{AddressType, TestBuilder},
+            Account as _, WalletRead,
+        },
+        proto::compact_formats::CompactBlock,
+    };
+    use zcash_primitives::{block::BlockHash, transaction::components::amount::NonNegativeAmount};
+    use zcash_protocol::consensus::BlockHeight;
+
+    use super::{validate_cache_coverage, CacheCoverageError, CacheGap, MemBlockCache};
+    use crate::testing::TestMemDbFactory;
+
+    fn cache_with_heights(heights: &[u32]) -> MemBlockCache {
+        let cache = MemBlockCache::new();
+        {
+            let mut inner = cache.blocks.write().unwrap();
+            for height in heights {
+                inner.insert(
+                    BlockHeight::from(*height),
+                    CompactBlock {
+                        height: u64::from(*height),
+                        ..Default::default()
+                    },
+                );
+            }
+        }
+        cache
+    }
+
+    fn range(start: u32, end: u32) -> Range<BlockHeight> {
+        BlockHeight::from(start)..BlockHeight::from(end)
+    }
+
+    fn expect_gap(result: Result<(), CacheCoverageError<CacheGap>>) -> CacheGap {
+        match result {
+            // The cache reports holes within the range itself, and the coverage check reports
+            // blocks missing from the end of the range.
+            Err(CacheCoverageError::Gap(gap)) | Err(CacheCoverageError::BlockSource(gap)) => gap,
+            other => panic!("Expected a cache gap, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn complete_cache_passes() {
+        let cache = cache_with_heights(&[10, 11, 12, 13]);
+        assert!(validate_cache_coverage(&cache, range(10, 14)).is_ok());
+        assert!(validate_cache_coverage(&cache, range(11, 13)).is_ok());
+
+        let cached = cache.cached_range().unwrap();
+        assert_eq!(cached.heights(), &range(10, 14));
+        assert!(cached.holes().is_empty());
+    }
+
+    #[test]
+    fn hole_reports_missing_height() {
+        let cache = cache_with_heights(&[10, 11, 12, 15, 16, 18]);
+        let gap = expect_gap(validate_cache_coverage(&cache, range(10, 19)));
+        assert_eq!(gap.missing_height(), BlockHeight::from(13));
+        assert_eq!(gap.contiguous_blocks(), 3);
+        // A range within a contiguous run of blocks is fully covered.
+        assert!(validate_cache_coverage(&cache, range(15, 17)).is_ok());
+        // The gap is reported relative to the start of the requested range.
+        let gap = expect_gap(validate_cache_coverage(&cache, range(15, 20)));
+        assert_eq!(gap.missing_height(), BlockHeight::from(17));
+        assert_eq!(gap.contiguous_blocks(), 2);
+
+        let cached = cache.cached_range().unwrap();
+        assert_eq!(cached.heights(), &range(10, 19));
+        assert_eq!(cached.holes(), &[range(13, 15), range(17, 18)]);
+    }
+
+    #[test]
+    fn empty_cache_reports_start_height() {
+        let cache = MemBlockCache::new();
+        let gap = expect_gap(validate_cache_coverage(&cache, range(10, 20)));
+        assert_eq!(gap.missing_height(), BlockHeight::from(10));
+        assert_eq!(gap.contiguous_blocks(), 0);
+        assert_eq!(cache.cached_range(), None);
+    }
+
+    #[test]
+    fn get_range_stops_at_first_hole() {
+        let cache = cache_with_heights(&[10, 11, 12, 14, 15]);
+        let heights = |blocks: Vec<CompactBlock>| {
+            blocks
+                .iter()
+                .map(|cb| u32::from(cb.height()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(heights(cache.get_range(10.into(), 10)), vec![10, 11, 12]);
+        assert_eq!(heights(cache.get_range(11.into(), 1)), vec![11]);
+        assert_eq!(heights(cache.get_range(14.into(), 10)), vec![14, 15]);
+        assert!(cache.get_range(13.into(), 10).is_empty());
+    }
+
+    #[test]
+    fn eviction_is_bounded_by_fully_scanned_height() {
+        let cache = MemBlockCache::with_capacity(3);
+        for height in 10..16 {
+            cache.insert_block(CompactBlock {
+                height,
+                ..Default::default()
+            });
+        }
+        // Without a fully-scanned height, no block may be evicted.
+        assert_eq!(cache.len(), 6);
+
+        // Only blocks below the fully-scanned height are evicted, even if the cache remains
+        // over capacity.
+        cache.set_fully_scanned_height(12.into());
+        assert_eq!(cache.cached_range().unwrap().heights(), &range(12, 16));
+
+        cache.set_fully_scanned_height(15.into());
+        assert_eq!(cache.cached_range().unwrap().heights(), &range(13, 16));
+
+        // Inserting a block evicts the lowest scanned block.
+        cache.insert_block(CompactBlock {
+            height: 16,
+            ..Default::default()
+        });
+        assert_eq!(cache.cached_range().unwrap().heights(), &range(14, 17));
+    }
+
+    #[test]
+    fn scanning_succeeds_after_eviction() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::with_capacity(3))
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let account = st.test_account().cloned().unwrap();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let value = NonNegativeAmount::const_from_u64(10000);
+
+        let (h, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        for _ in 1..6 {
+            st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        }
+        st.scan_cached_blocks(h, 3);
+        let fully_scanned = st.wallet().block_fully_scanned().unwrap().unwrap();
+        st.cache()
+            .set_fully_scanned_height(fully_scanned.block_height());
+        // The unscanned blocks are retained although the cache is over capacity.
+        assert_eq!(st.cache().cached_range().unwrap().heights().start, h + 2);
+        assert_eq!(st.cache().len(), 4);
+
+        for _ in 0..4 {
+            st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        }
+        st.scan_cached_blocks(h + 3, 7);
+        assert_eq!(
+            st.get_spendable_balance(account.id(), 1),
+            NonNegativeAmount::const_from_u64(100000)
+        );
+    }
+
+    #[test]
+    fn scanning_reports_missing_block() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let value = NonNegativeAmount::const_from_u64(10000);
+
+        let (h, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        for _ in 1..6 {
+            st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        }
+        assert_eq!(st.cache().max_height(), Some(h + 5));
+        st.cache().blocks.write().unwrap().remove(&(h + 3));
+
+        let result = st.try_scan_cached_blocks(h, 6);
+        assert!(matches!(
+            result,
+            Err(chain::error::Error::BlockSource(gap))
+                if gap.missing_height() == h + 3 && gap.contiguous_blocks() == 3
+        ));
+
+        // The blocks before the gap can be scanned.
+        st.scan_cached_blocks(h, 3);
+        assert_eq!(
+            st.wallet()
+                .block_fully_scanned()
+                .unwrap()
+                .map(|b| b.block_height()),
+            Some(h + 2)
+        );
+    }
+}
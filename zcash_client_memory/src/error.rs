@@ -2,11 +2,16 @@ use std::{array::TryFromSliceError, convert::Infallible};
 
 use shardtree::error::ShardTreeError;
 use zcash_address::ConversionError;
+use zcash_client_backend::wallet::NoteId;
 use zcash_keys::{
     encoding::TransparentCodecError,
     keys::{AddressGenerationError, DerivationError},
 };
-use zcash_primitives::{legacy::TransparentAddress, transaction::TxId};
+use zcash_primitives::{
+    block::BlockHash,
+    legacy::{Script, TransparentAddress},
+    transaction::{components::OutPoint, TxId},
+};
 use zcash_protocol::{consensus::BlockHeight, memo};
 
 use crate::AccountId;
@@ -25,8 +30,26 @@ macro_rules! read_optional {
     };
 }
 
+/// Reads a protobuf enumeration field, failing if its value is not a known discriminant.
+pub(crate) fn read_enum<E: TryFrom<i32>>(field: &'static str, value: i32) -> Result<E> {
+    E::try_from(value).map_err(|_| Error::InvalidProtoEnum { field, value })
+}
+
+/// Reads a fixed-length protobuf bytes field.
+pub(crate) fn read_array<const N: usize>(field: &'static str, bytes: Vec<u8>) -> Result<[u8; N]> {
+    <[u8; N]>::try_from(bytes).map_err(|bytes| Error::InvalidProtoFieldLength {
+        field,
+        expected: N,
+        actual: bytes.len(),
+    })
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
+    #[error(
+        "An account derived from the same seed and ZIP 32 account index already exists: {0:?}"
+    )]
+    AccountCollision(AccountId),
     #[error("Account not found: {0:?}")]
     AccountUnknown(AccountId),
     #[error("Account out of range.")]
@@ -43,6 +66,16 @@ pub enum Error {
     BadAccountData(String),
     #[error("Error converting byte vec to array: {0:?}")]
     ByteVecToArrayConversion(Vec<u8>),
+    #[error("The chain height is unknown; the wallet must be synced with the chain tip")]
+    ChainHeightUnknown,
+    #[error("Chain state at height {0} has block hash {2}, but the wallet has block hash {1}")]
+    ChainStateMismatch(BlockHeight, BlockHash, BlockHash),
+    #[error(
+        "Chain state at height {0} does not immediately precede the first block at height {1}"
+    )]
+    ChainStateHeightMismatch(BlockHeight, BlockHeight),
+    #[error("Wallet file checksum mismatch: the wallet file is corrupted")]
+    ChecksumMismatch,
     #[error("Conflicting Tx Locator map entry")]
     ConflictingTxLocator,
     #[error("Corrupted Data: {0}")]
@@ -51,26 +84,64 @@ pub enum Error {
     KeyDerivation(DerivationError),
     #[error("Failed to convert between integer types")]
     IntegerConversion(#[from] std::num::TryFromIntError),
+    #[error("Deriving accounts from a seed requires the `hd-derivation` feature")]
+    HdDerivationDisabled,
     #[error("Infallible")]
     Infallible(#[from] Infallible),
+    #[error("Invalid memo of length {len} for note {note_id:?}")]
+    InvalidMemo { note_id: Option<NoteId>, len: usize },
+    #[error("Invalid outpoint hash of length {0}: must be 32 bytes")]
+    InvalidOutPointHash(usize),
+    #[error("Invalid value {value} for proto enumeration field {field}")]
+    InvalidProtoEnum { field: &'static str, value: i32 },
+    #[error("Proto field {field} has length {actual}, but must be {expected} bytes")]
+    InvalidProtoFieldLength {
+        field: &'static str,
+        expected: usize,
+        actual: usize,
+    },
+    #[error("Invalid subtree chunk size {0}: must be a power of two of at least 256")]
+    InvalidSubtreeChunkSize(usize),
     #[error("Invalid scan range start {0}, end {1}: {2}")]
     InvalidScanRange(BlockHeight, BlockHeight, String),
     #[error("Seed must be between 32 and 252 bytes in length.")]
     InvalidSeedLength,
+    #[error("Invalid transparent output value {0}: must not exceed MAX_MONEY")]
+    InvalidTransparentValue(u64),
     #[error("Io Error: {0}")]
     Io(std::io::Error),
+    #[error("The wallet lock was poisoned by a panic in another thread")]
+    LockPoisoned,
+    #[error("Not a wallet file: expected magic bytes {expected:?}, found {found:?}")]
+    MagicMismatch { expected: [u8; 4], found: [u8; 4] },
     #[error("Memo decryption failed: {0}")]
     MemoDecryption(memo::Error),
     #[error("Expected field missing: {0}")]
     Missing(String),
+    #[error("Cannot merge wallets with differing accounts: {0:?}")]
+    MergeAccountMismatch(AccountId),
+    #[error("Cannot merge wallets for different networks")]
+    MergeNetworkMismatch,
     #[error("Note not found")]
     NoteNotFound,
     #[error("Blocks are non sequental")]
     NonSequentialBlocks,
+    #[error("Proto field {0} is not a canonical encoding")]
+    NonCanonicalEncoding(&'static str),
     #[error("Orchard specific code was called without the 'orchard' feature enabled")]
     OrchardNotEnabled,
     #[error("Other error: {0}")]
     Other(String),
+    #[error("Output index {index} of transaction {txid} is out of range")]
+    OutputIndexOutOfRange { txid: TxId, index: usize },
+    #[error(
+        "Block at height {at_height} has hash {received}, but the wallet has block hash {stored}; the wallet must be truncated below this height"
+    )]
+    PrevHashMismatch {
+        at_height: BlockHeight,
+        stored: BlockHash,
+        received: BlockHash,
+    },
     #[error("Proto Decoding Error: {0}")]
     ProtoDecodingError(#[from] prost::DecodeError),
     #[error("Proto Encoding Error: {0}")]
@@ -78,25 +149,50 @@ pub enum Error {
     #[error("Missing proto field: {0}")]
     ProtoMissingField(&'static str),
     #[error(
-        "Requested rewind to invalid block height. Safe height: {0:?}, requested height {1:?}"
+        "Requested rewind to invalid block height. Safe height: {safe_height:?}, requested height {requested:?}"
     )]
-    RequestedRewindInvalid(Option<BlockHeight>, BlockHeight),
+    RequestedRewindInvalid {
+        safe_height: Option<BlockHeight>,
+        requested: BlockHeight,
+    },
     #[cfg(feature = "transparent-inputs")]
-    #[error("Requested gap limit {1} reached for account {0:?}")]
-    ReachedGapLimit(AccountId, u32),
+    #[error("Requested gap limit {bad_index} reached for account {account:?}")]
+    ReachedGapLimit { account: AccountId, bad_index: u32 },
+    #[error(
+        "Proposal targets height {target}, but the wallet has already scanned up to height {current}"
+    )]
+    StaleProposal {
+        target: BlockHeight,
+        current: BlockHeight,
+    },
     #[error("ShardTree error: {0}")]
     ShardTree(ShardTreeError<Infallible>),
     #[error("String Conversion error: {0}")]
     StringConversion(#[from] std::string::FromUtf8Error),
     #[error("Transaction not in table: {0}")]
     TransactionNotFound(TxId),
+    #[error("Raw data for transaction {0} is not stored in the wallet")]
+    TransactionRawDataMissing(TxId),
+    #[error("Transparent output is recorded as received at {address:?}, but its script pays to {script_address:?}")]
+    TransparentAddressScriptMismatch {
+        address: TransparentAddress,
+        script_address: TransparentAddress,
+    },
     #[error("Error converting transparent address: {0}")]
     TransparentCodec(#[from] TransparentCodecError),
     #[cfg(feature = "transparent-inputs")]
     #[error("Transparent derivation: {0}")]
     TransparentDerivation(bip32::Error),
+    #[error("Transparent output not found: {0:?}")]
+    TransparentOutputNotFound(OutPoint),
+    #[error("Transparent output {outpoint:?} is spent by wallet transaction {txid}, which must be purged first")]
+    TransparentOutputSpentByWalletTx { outpoint: OutPoint, txid: TxId },
     #[error("Unsupported proto version: {1} (expected {0})")]
     UnsupportedProtoVersion(u32, u32),
+    #[error("Unsupported wallet snapshot version: {0}")]
+    UnsupportedWalletVersion(u32),
+    #[error("Unsupported protocol data: {context}")]
+    UnsupportedProtocolData { context: &'static str },
     #[error("Error converting nullifier from slice: {0}")]
     NullifierFromSlice(#[from] TryFromSliceError),
     #[error("Error decoding ufvk string: {0}")]
@@ -105,6 +201,10 @@ pub enum Error {
     ViewingKeyNotFound(AccountId),
     #[error("Error parsing zcash address: {0}")]
     ParseZcashAddress(#[from] zcash_address::ParseError),
+    #[error("Unified address not recognized for account {0:?}")]
+    UnifiedAddressNotRecognized(AccountId),
+    #[error("Transparent output script is neither P2PKH nor P2SH: {0:?}")]
+    UnrecognizedTransparentScript(Script),
     #[error("Unknown zip32 derivation error")]
     UnknownZip32Derivation,
     #[error("Error converting int to zip32: {0}")]
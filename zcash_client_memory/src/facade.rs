@@ -0,0 +1,689 @@
+//! A high-level in-memory wallet that bundles a [`MemoryWalletDb`] with a [`MemBlockCache`].
+//!
+//! [`MemoryWallet`] is a thin layer over the existing wallet APIs for applications that only
+//! need to import a viewing key, keep it in sync with a `lightwalletd` server, and read its
+//! balance. Applications that need more control can reach the underlying wallet through
+//! [`MemoryWallet::db`] and [`MemoryWallet::db_mut`].
+//!
+//! ```ignore
+//! let channel = tonic::transport::Channel::from_shared(url)?.connect().await?;
+//! let mut client = CompactTxStreamerClient::new(channel);
+//!
+//! let mut wallet = MemoryWallet::new(Network::MainNetwork);
+//! let account = wallet.import_ufvk(&ufvk, &birthday, AccountPurpose::ViewOnly)?;
+//! wallet.sync_once(&mut client).await?;
+//! let balance = wallet.balance(account)?;
+//! ```
+//!
+//! See `examples/facade-sync.rs` for a complete program.
+
+use std::{convert::Infallible, fmt, num::NonZeroU32};
+
+use tonic::{
+    body::BoxBody,
+    client::GrpcService,
+    codegen::{Body, Bytes, StdError},
+};
+use zcash_client_backend::{
+    data_api::{
+        wallet::{decrypt_and_store_transaction, propose_standard_transfer_to_address},
+        Account as _, AccountBalance, AccountBirthday, AccountPurpose, TransactionDataRequest,
+        TransactionStatus, WalletRead, WalletWrite,
+    },
+    fees::StandardFeeRule,
+    proposal::Proposal,
+    proto::service::{
+        compact_tx_streamer_client::CompactTxStreamerClient, RawTransaction, TxFilter,
+    },
+    sync,
+    wallet::NoteId,
+};
+use zcash_keys::{address::Address, keys::UnifiedFullViewingKey};
+use zcash_primitives::{
+    consensus::{self, BlockHeight, BranchId, NetworkUpgrade},
+    memo::MemoBytes,
+    transaction::Transaction,
+};
+use zcash_protocol::{
+    value::{BalanceError, Zatoshis},
+    ShieldedProtocol,
+};
+
+#[cfg(feature = "transparent-inputs")]
+use {
+    zcash_client_backend::proto::service::{BlockId, BlockRange, TransparentAddressBlockFilter},
+    zcash_keys::encoding::AddressCodec,
+};
+
+use crate::{AccountId, CacheGap, MemBlockCache, MemoryWalletDb, PRUNING_DEPTH};
+
+/// The number of note commitment tree checkpoints retained by a [`MemoryWallet`].
+pub const DEFAULT_MAX_CHECKPOINTS: usize = PRUNING_DEPTH as usize;
+
+/// The number of blocks downloaded and scanned at a time by [`MemoryWallet::sync_once`].
+pub const DEFAULT_BATCH_SIZE: u32 = 1000;
+
+/// The number of confirmations required for notes to be spent or counted as spendable by a
+/// [`MemoryWallet`].
+pub const DEFAULT_MIN_CONFIRMATIONS: u32 = 10;
+
+/// Errors that can occur while using a [`MemoryWallet`].
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Wallet error: {0}")]
+    Wallet(#[from] crate::Error),
+    #[error("Sync error: {0}")]
+    Sync(#[from] sync::Error<CacheGap, crate::Error, Infallible>),
+    #[error("Error while communicating with lightwalletd server: {0}")]
+    Server(#[from] tonic::Status),
+    #[error("lightwalletd returned a transaction that could not be parsed: {0}")]
+    InvalidTransaction(std::io::Error),
+    #[error("Could not construct a proposal: {0}")]
+    Proposal(String),
+    #[error("Balance error: {0}")]
+    Balance(#[from] BalanceError),
+}
+
+/// An in-memory wallet together with the block cache used to sync it.
+pub struct MemoryWallet<P> {
+    db: MemoryWalletDb<P>,
+    cache: MemBlockCache,
+    batch_size: u32,
+    min_confirmations: NonZeroU32,
+}
+
+impl<P: consensus::Parameters + Clone + Send + 'static> MemoryWallet<P> {
+    /// Returns an empty wallet for the given network.
+    pub fn new(params: P) -> Self {
+        Self::from_db(MemoryWalletDb::new(params, DEFAULT_MAX_CHECKPOINTS))
+    }
+
+    /// Restores a wallet from a snapshot produced by [`Self::snapshot`].
+    ///
+    /// The block cache is not part of the snapshot, and starts out empty.
+    pub fn restore(params: P, snapshot: &[u8]) -> Result<Self, Error> {
+        Ok(Self::from_db(MemoryWalletDb::decode_new(
+            snapshot,
+            params,
+            DEFAULT_MAX_CHECKPOINTS,
+        )?))
+    }
+
+    fn from_db(db: MemoryWalletDb<P>) -> Self {
+        Self {
+            db,
+            cache: MemBlockCache::new(),
+            batch_size: DEFAULT_BATCH_SIZE,
+            min_confirmations: NonZeroU32::new(DEFAULT_MIN_CONFIRMATIONS).unwrap(),
+        }
+    }
+
+    /// Returns the wallet with the given number of blocks downloaded and scanned at a time.
+    pub fn with_batch_size(mut self, batch_size: u32) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Returns the wallet with a block cache that retains at most `max_blocks` blocks. See
+    /// [`MemBlockCache::with_capacity`].
+    pub fn with_cache_capacity(mut self, max_blocks: usize) -> Self {
+        self.cache = MemBlockCache::with_capacity(max_blocks);
+        self
+    }
+
+    /// Returns the wallet with the given number of confirmations required for notes to be
+    /// spent or counted as spendable.
+    pub fn with_min_confirmations(mut self, min_confirmations: NonZeroU32) -> Self {
+        self.min_confirmations = min_confirmations;
+        self
+    }
+
+    /// Returns the underlying wallet database.
+    pub fn db(&self) -> &MemoryWalletDb<P> {
+        &self.db
+    }
+
+    /// Returns the underlying wallet database, for operations not offered by the facade.
+    pub fn db_mut(&mut self) -> &mut MemoryWalletDb<P> {
+        &mut self.db
+    }
+
+    /// Returns the block cache used by [`Self::sync_once`].
+    pub fn cache(&self) -> &MemBlockCache {
+        &self.cache
+    }
+
+    /// Imports an account with the given viewing key, returning its id.
+    pub fn import_ufvk(
+        &mut self,
+        ufvk: &UnifiedFullViewingKey,
+        birthday: &AccountBirthday,
+        purpose: AccountPurpose,
+    ) -> Result<AccountId, Error> {
+        Ok(self.db.import_account_ufvk(ufvk, birthday, purpose)?.id())
+    }
+
+    /// Brings the wallet up to date with the chain, and then answers the wallet's pending
+    /// requests for transaction data.
+    ///
+    /// Blocks are downloaded and scanned by [`zcash_client_backend::sync::run`]. The requests
+    /// returned by [`WalletRead::transaction_data_requests`] at the end of the scan are then
+    /// answered once each; requests queued as a result are answered by the next call.
+    pub async fn sync_once<ChT>(
+        &mut self,
+        client: &mut CompactTxStreamerClient<ChT>,
+    ) -> Result<(), Error>
+    where
+        ChT: GrpcService<BoxBody>,
+        ChT::Error: Into<StdError>,
+        ChT::ResponseBody: Body<Data = Bytes> + Send + 'static,
+        <ChT::ResponseBody as Body>::Error: Into<StdError> + Send,
+    {
+        let params = self.db.params().clone();
+        sync::run(client, &params, &self.cache, &mut self.db, self.batch_size).await?;
+        if let Some(metadata) = self.db.block_fully_scanned()? {
+            self.cache.set_fully_scanned_height(metadata.block_height());
+        }
+
+        for request in self.db.transaction_data_requests()? {
+            match request {
+                TransactionDataRequest::GetStatus(txid) => {
+                    let status = match client.get_transaction(tx_filter(&txid)).await {
+                        Ok(response) => mined_height(response.into_inner().height)
+                            .map_or(TransactionStatus::NotInMainChain, TransactionStatus::Mined),
+                        Err(status) if status.code() == tonic::Code::NotFound => {
+                            TransactionStatus::TxidNotRecognized
+                        }
+                        Err(status) => return Err(status.into()),
+                    };
+                    self.db.set_transaction_status(txid, status)?;
+                }
+                TransactionDataRequest::Enhancement(txid) => {
+                    match client.get_transaction(tx_filter(&txid)).await {
+                        Ok(response) => self.store_raw_transaction(response.into_inner())?,
+                        Err(status) if status.code() == tonic::Code::NotFound => self
+                            .db
+                            .set_transaction_status(txid, TransactionStatus::TxidNotRecognized)?,
+                        Err(status) => return Err(status.into()),
+                    }
+                }
+                #[cfg(feature = "transparent-inputs")]
+                TransactionDataRequest::SpendsFromAddress {
+                    address,
+                    block_range_start,
+                    block_range_end,
+                } => {
+                    // The requested range is end-exclusive, but the range of a
+                    // `GetTaddressTxids` request is end-inclusive.
+                    let end = block_range_end
+                        .map(|end| end - 1)
+                        .or(self.db.chain_height()?);
+                    let filter = TransparentAddressBlockFilter {
+                        address: address.encode(&params),
+                        range: Some(BlockRange {
+                            start: Some(block_id(block_range_start)),
+                            end: end.map(block_id),
+                        }),
+                    };
+                    let mut txs = client.get_taddress_txids(filter).await?.into_inner();
+                    while let Some(raw_tx) = txs.message().await? {
+                        self.store_raw_transaction(raw_tx)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn store_raw_transaction(&mut self, raw_tx: RawTransaction) -> Result<(), Error> {
+        let mined_height = mined_height(raw_tx.height);
+        // The consensus branch is only needed to parse pre-v5 transactions, for which the
+        // branch at the next block height is the best guess for an unmined transaction.
+        let parse_height = match mined_height {
+            Some(height) => height,
+            None => self
+                .db
+                .chain_height()?
+                .map(|height| height + 1)
+                .or_else(|| self.db.params().activation_height(NetworkUpgrade::Sapling))
+                .expect("Sapling activation height must be known."),
+        };
+        let tx = Transaction::read(
+            &raw_tx.data[..],
+            BranchId::for_height(self.db.params(), parse_height),
+        )
+        .map_err(Error::InvalidTransaction)?;
+        let params = self.db.params().clone();
+        decrypt_and_store_transaction(&params, &mut self.db, &tx, mined_height)?;
+        Ok(())
+    }
+
+    /// Returns the balance of the given account, or `None` if the wallet has not yet been
+    /// synced.
+    pub fn balance(&self, account: AccountId) -> Result<Option<AccountBalance>, Error> {
+        self.db
+            .get_account(account)?
+            .ok_or(crate::Error::AccountUnknown(account))?;
+        Ok(self
+            .db
+            .get_wallet_summary(self.min_confirmations.get())?
+            .and_then(|summary| summary.account_balances().get(&account).cloned()))
+    }
+
+    /// Proposes a payment of the given amount from the given account, using the standard
+    /// ZIP 317 fee rule, and describes the result.
+    ///
+    /// The proposal may be passed to
+    /// [`zcash_client_backend::data_api::wallet::create_proposed_transactions`] along with the
+    /// account's spending key to create the transactions.
+    pub fn propose_and_describe(
+        &mut self,
+        account: AccountId,
+        to: &Address,
+        amount: Zatoshis,
+        memo: Option<MemoBytes>,
+    ) -> Result<(Proposal<StandardFeeRule, NoteId>, ProposalDescription), Error> {
+        #[cfg(feature = "orchard")]
+        let fallback_change_pool = ShieldedProtocol::Orchard;
+        #[cfg(not(feature = "orchard"))]
+        let fallback_change_pool = ShieldedProtocol::Sapling;
+
+        let params = self.db.params().clone();
+        let proposal = propose_standard_transfer_to_address::<_, _, Infallible>(
+            &mut self.db,
+            &params,
+            StandardFeeRule::Zip317,
+            account,
+            self.min_confirmations,
+            to,
+            amount,
+            memo,
+            None,
+            fallback_change_pool,
+        )
+        // The proposal error is generic over the note reference type, which does not
+        // implement `Display`.
+        .map_err(|e| Error::Proposal(format!("{:?}", e)))?;
+        let description = ProposalDescription::new(&proposal)?;
+        Ok((proposal, description))
+    }
+
+    /// Serializes the wallet, for later use with [`Self::restore`].
+    pub fn snapshot(&self) -> Result<Vec<u8>, Error> {
+        let mut buf = vec![];
+        self.db.encode(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+/// A summary of a [`Proposal`] produced by [`MemoryWallet::propose_and_describe`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProposalDescription {
+    /// The number of transactions that the proposal would create.
+    pub step_count: usize,
+    /// The total value paid to the recipients of the final transaction.
+    pub payment_total: Zatoshis,
+    /// The total fee paid by all of the proposed transactions.
+    pub fee_total: Zatoshis,
+    /// The minimum height at which the proposed transactions may be mined.
+    pub min_target_height: BlockHeight,
+}
+
+impl ProposalDescription {
+    fn new(proposal: &Proposal<StandardFeeRule, NoteId>) -> Result<Self, Error> {
+        let fee_total = proposal
+            .steps()
+            .iter()
+            .try_fold(Zatoshis::ZERO, |total, step| {
+                total + step.balance().fee_required()
+            })
+            .ok_or(BalanceError::Overflow)?;
+        Ok(Self {
+            step_count: proposal.steps().len(),
+            payment_total: proposal.steps().last().transaction_request().total()?,
+            fee_total,
+            min_target_height: proposal.min_target_height(),
+        })
+    }
+}
+
+impl fmt::Display for ProposalDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} transaction(s) paying {} zatoshis with a total fee of {} zatoshis, minable from height {}",
+            self.step_count,
+            self.payment_total.into_u64(),
+            self.fee_total.into_u64(),
+            self.min_target_height
+        )
+    }
+}
+
+fn tx_filter(txid: &zcash_primitives::transaction::TxId) -> TxFilter {
+    TxFilter {
+        hash: txid.as_ref().to_vec(),
+        ..Default::default()
+    }
+}
+
+#[cfg(feature = "transparent-inputs")]
+fn block_id(height: BlockHeight) -> BlockId {
+    BlockId {
+        height: u32::from(height).into(),
+        ..Default::default()
+    }
+}
+
+/// Interprets the height of a [`RawTransaction`], which is zero for a transaction in the
+/// mempool and `u64::MAX` for a transaction that is not in the main chain.
+fn mined_height(height: u64) -> Option<BlockHeight> {
+    u32::try_from(height)
+        .ok()
+        .filter(|height| *height != 0)
+        .map(BlockHeight::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        future::Future,
+        num::NonZeroU32,
+        pin::Pin,
+        str::FromStr,
+        sync::Arc,
+        task::{Context, Poll},
+    };
+
+    use tonic::{
+        body::BoxBody,
+        codec::ProstCodec,
+        codegen::{http, tokio_stream, Service},
+        server::{Grpc, ServerStreamingService, UnaryService},
+        Status,
+    };
+    use zcash_client_backend::{
+        data_api::{Account as _, AccountBirthday, AccountPurpose, WalletRead},
+        proto::{
+            compact_formats::CompactBlock,
+            service::{
+                compact_tx_streamer_client::CompactTxStreamerClient, BlockId, BlockRange,
+                ChainSpec, GetAddressUtxosArg, GetAddressUtxosReply, GetSubtreeRootsArg,
+                RawTransaction, SubtreeRoot, TransparentAddressBlockFilter, TreeState, TxFilter,
+            },
+        },
+    };
+    use zcash_keys::{address::Address, keys::UnifiedSpendingKey};
+    use zcash_primitives::{
+        block::BlockHash, consensus::Network, transaction::components::amount::NonNegativeAmount,
+    };
+    use zcash_protocol::memo::{Memo, MemoBytes};
+
+    use crate::testing::{external_tx, test_state};
+
+    use super::{Error, MemoryWallet};
+
+    /// Adapts a function to a unary gRPC method.
+    struct Unary<F>(F);
+
+    impl<Req, Resp, F> UnaryService<Req> for Unary<F>
+    where
+        F: FnMut(Req) -> Result<Resp, Status>,
+    {
+        type Response = Resp;
+        type Future = std::future::Ready<Result<tonic::Response<Resp>, Status>>;
+
+        fn call(&mut self, request: tonic::Request<Req>) -> Self::Future {
+            std::future::ready((self.0)(request.into_inner()).map(tonic::Response::new))
+        }
+    }
+
+    /// Adapts a function to a server-streaming gRPC method.
+    struct Streaming<F>(F);
+
+    impl<Req, Resp, F> ServerStreamingService<Req> for Streaming<F>
+    where
+        F: FnMut(Req) -> Vec<Resp>,
+    {
+        type Response = Resp;
+        type ResponseStream = tokio_stream::Iter<std::vec::IntoIter<Result<Resp, Status>>>;
+        type Future = std::future::Ready<Result<tonic::Response<Self::ResponseStream>, Status>>;
+
+        fn call(&mut self, request: tonic::Request<Req>) -> Self::Future {
+            let items = (self.0)(request.into_inner()).into_iter().map(Ok);
+            std::future::ready(Ok(tonic::Response::new(tokio_stream::iter(
+                items.collect::<Vec<_>>(),
+            ))))
+        }
+    }
+
+    /// A `lightwalletd` server that serves a fixed chain starting at Sapling activation, and
+    /// the full transactions mined in it.
+    #[derive(Clone)]
+    struct MockLightwalletd {
+        blocks: Arc<Vec<CompactBlock>>,
+        txs: Arc<Vec<RawTransaction>>,
+    }
+
+    impl MockLightwalletd {
+        fn tip(&self) -> BlockId {
+            let tip = self.blocks.last().unwrap();
+            BlockId {
+                height: tip.height,
+                hash: tip.hash.clone(),
+            }
+        }
+
+        fn block_range(&self, range: BlockRange) -> Vec<CompactBlock> {
+            let start = range.start.map_or(0, |id| id.height);
+            let end = range.end.map_or(u64::MAX, |id| id.height);
+            self.blocks
+                .iter()
+                .filter(|block| (start..=end).contains(&block.height))
+                .cloned()
+                .collect()
+        }
+
+        /// Returns the state of the note commitment trees before the first block, when they
+        /// are empty.
+        fn tree_state(&self, id: BlockId) -> Result<TreeState, Status> {
+            let first = self.blocks.first().unwrap();
+            if id.height + 1 != first.height {
+                return Err(Status::not_found(format!("no tree state at {}", id.height)));
+            }
+            let mut hash = first.prev_hash.clone();
+            hash.reverse();
+            Ok(TreeState {
+                height: id.height,
+                hash: hash.iter().map(|b| format!("{:02x}", b)).collect(),
+                ..Default::default()
+            })
+        }
+
+        fn transaction(&self, filter: TxFilter) -> Result<RawTransaction, Status> {
+            self.txs
+                .iter()
+                .find(|raw_tx| {
+                    let tx = zcash_primitives::transaction::Transaction::read(
+                        &raw_tx.data[..],
+                        zcash_primitives::consensus::BranchId::Nu5,
+                    )
+                    .unwrap();
+                    tx.txid().as_ref()[..] == filter.hash[..]
+                })
+                .cloned()
+                .ok_or_else(|| Status::not_found("transaction not found"))
+        }
+    }
+
+    impl Service<http::Request<BoxBody>> for MockLightwalletd {
+        type Response = http::Response<BoxBody>;
+        type Error = Infallible;
+        type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Infallible>> + Send>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: http::Request<BoxBody>) -> Self::Future {
+            let lwd = self.clone();
+            Box::pin(async move {
+                let method = req.uri().path().rsplit('/').next().unwrap_or("").to_owned();
+                let response = match method.as_str() {
+                    "GetLatestBlock" => {
+                        let service = Unary(|_: ChainSpec| Ok(lwd.tip()));
+                        Grpc::new(ProstCodec::default()).unary(service, req).await
+                    }
+                    "GetBlockRange" => {
+                        let service = Streaming(|range| lwd.block_range(range));
+                        Grpc::new(ProstCodec::default())
+                            .server_streaming(service, req)
+                            .await
+                    }
+                    "GetTreeState" => {
+                        let service = Unary(|id| lwd.tree_state(id));
+                        Grpc::new(ProstCodec::default()).unary(service, req).await
+                    }
+                    "GetSubtreeRoots" => {
+                        let service = Streaming(|_: GetSubtreeRootsArg| Vec::<SubtreeRoot>::new());
+                        Grpc::new(ProstCodec::default())
+                            .server_streaming(service, req)
+                            .await
+                    }
+                    "GetTransaction" => {
+                        let service = Unary(|filter| lwd.transaction(filter));
+                        Grpc::new(ProstCodec::default()).unary(service, req).await
+                    }
+                    "GetAddressUtxosStream" => {
+                        let service =
+                            Streaming(|_: GetAddressUtxosArg| Vec::<GetAddressUtxosReply>::new());
+                        Grpc::new(ProstCodec::default())
+                            .server_streaming(service, req)
+                            .await
+                    }
+                    "GetTaddressTxids" => {
+                        let service = Streaming(|_: TransparentAddressBlockFilter| {
+                            Vec::<RawTransaction>::new()
+                        });
+                        Grpc::new(ProstCodec::default())
+                            .server_streaming(service, req)
+                            .await
+                    }
+                    _ => Status::unimplemented(method).into_http(),
+                };
+                Ok(response)
+            })
+        }
+    }
+
+    fn wallet_with_account() -> (MemoryWallet<Network>, crate::AccountId) {
+        let network = Network::TestNetwork;
+        let usk =
+            UnifiedSpendingKey::from_seed(&network, &[7; 32], zip32::AccountId::ZERO).unwrap();
+        let birthday = AccountBirthday::from_sapling_activation(&network, BlockHash([0; 32]));
+        let mut wallet = MemoryWallet::new(network);
+        let account = wallet
+            .import_ufvk(
+                &usk.to_unified_full_viewing_key(),
+                &birthday,
+                AccountPurpose::ViewOnly,
+            )
+            .unwrap();
+        (wallet, account)
+    }
+
+    #[test]
+    fn balance_is_unknown_until_synced() {
+        let (wallet, account) = wallet_with_account();
+        assert_eq!(wallet.balance(account).unwrap(), None);
+    }
+
+    #[test]
+    fn snapshot_roundtrip() {
+        let (wallet, account) = wallet_with_account();
+        let restored =
+            MemoryWallet::restore(Network::TestNetwork, &wallet.snapshot().unwrap()).unwrap();
+        assert!(restored.db() == wallet.db());
+        assert!(matches!(restored.balance(account), Ok(None)));
+    }
+
+    #[test]
+    fn proposal_without_funds_fails() {
+        let (mut wallet, account) = wallet_with_account();
+        let to: Address = wallet
+            .db()
+            .get_current_address(account)
+            .unwrap()
+            .unwrap()
+            .into();
+        assert!(matches!(
+            wallet.propose_and_describe(
+                account,
+                &to,
+                zcash_protocol::value::Zatoshis::const_from_u64(10000),
+                None
+            ),
+            Err(Error::Proposal(_))
+        ));
+    }
+    #[tokio::test]
+    async fn sync_once_finds_received_funds() {
+        // Build a chain in which the test account receives a note with a memo.
+        let mut st = test_state();
+        let account = st.test_account().cloned().unwrap();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let value = NonNegativeAmount::const_from_u64(50000);
+        let memo = Memo::from_str("synced").unwrap();
+        let tx = external_tx(vec![(
+            Address::Sapling(dfvk.default_address().1),
+            value,
+            Some(MemoBytes::from(&memo)),
+        )]);
+        let (h, _) = st.generate_next_block_from_tx(0, &tx);
+        let mut data = vec![];
+        tx.write(&mut data).unwrap();
+        let lwd = MockLightwalletd {
+            blocks: Arc::new(
+                st.cache()
+                    .blocks
+                    .read()
+                    .unwrap()
+                    .values()
+                    .cloned()
+                    .collect(),
+            ),
+            txs: Arc::new(vec![RawTransaction {
+                data,
+                height: u32::from(h).into(),
+            }]),
+        };
+        let mut client = CompactTxStreamerClient::new(lwd);
+
+        let mut wallet =
+            MemoryWallet::new(*st.network()).with_min_confirmations(NonZeroU32::new(1).unwrap());
+        let account_id = wallet
+            .import_ufvk(
+                &account.usk().to_unified_full_viewing_key(),
+                account.birthday(),
+                AccountPurpose::ViewOnly,
+            )
+            .unwrap();
+        assert_eq!(wallet.balance(account_id).unwrap(), None);
+
+        wallet.sync_once(&mut client).await.unwrap();
+
+        let balance = wallet.balance(account_id).unwrap().unwrap();
+        assert_eq!(balance.total(), value);
+        assert_eq!(wallet.db().chain_height().unwrap(), Some(h));
+        // The received transaction was enhanced, recovering the memo of its note.
+        let note = wallet
+            .db()
+            .received_notes
+            .iter()
+            .find(|note| note.txid == tx.txid())
+            .unwrap();
+        assert_eq!(wallet.db().get_memo(note.note_id).unwrap(), Some(memo));
+    }
+}
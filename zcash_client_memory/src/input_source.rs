@@ -1,12 +1,18 @@
+use std::num::NonZeroU64;
+
 use zcash_client_backend::{
-    data_api::{AccountMeta, InputSource, NoteFilter, PoolMeta, TransactionStatus, WalletRead},
-    wallet::NoteId,
+    data_api::{AccountMeta, InputSource, NoteFilter, PoolMeta, WalletRead},
+    wallet::{NoteId, Recipient},
 };
 use zcash_primitives::transaction::components::OutPoint;
 #[cfg(feature = "orchard")]
 use zcash_protocol::ShieldedProtocol::Orchard;
 use zcash_protocol::{
-    consensus, consensus::BlockHeight, value::Zatoshis, ShieldedProtocol, ShieldedProtocol::Sapling,
+    consensus,
+    consensus::BlockHeight,
+    value::{BalanceError, Zatoshis},
+    ShieldedProtocol,
+    ShieldedProtocol::Sapling,
 };
 #[cfg(feature = "transparent-inputs")]
 use {
@@ -14,7 +20,7 @@ use {
     zcash_primitives::legacy::TransparentAddress,
 };
 
-use crate::{error::Error, to_spendable_notes, AccountId, MemoryWalletDb};
+use crate::{error::Error, output_index_u16, to_spendable_notes, AccountId, MemoryWalletDb};
 
 impl<P: consensus::Parameters> InputSource for MemoryWalletDb<P> {
     type Error = crate::error::Error;
@@ -48,7 +54,7 @@ impl<P: consensus::Parameters> InputSource for MemoryWalletDb<P> {
                 Some(zcash_client_backend::wallet::ReceivedNote::from_parts(
                     note.note_id,
                     *txid,
-                    index.try_into().unwrap(), // this overflow can never happen or else the chain is broken
+                    output_index_u16(*txid, index as usize)?,
                     note.note.clone(),
                     note.recipient_key_scope
                         .ok_or(Error::Missing("recipient key scope".into()))?,
@@ -103,9 +109,10 @@ impl<P: consensus::Parameters> InputSource for MemoryWalletDb<P> {
 
     /// Returns the list of spendable transparent outputs received by this wallet at `address`
     /// such that, at height `target_height`:
-    /// * the transaction that produced the output had or will have at least `min_confirmations`
-    ///   confirmations; and
-    /// * the output is unspent as of the current chain tip.
+    /// * the transaction that produced the output has been mined with at least
+    ///   `min_confirmations` confirmations; and
+    /// * the output is not spent by a transaction that is mined or may be mined at
+    ///   `target_height`.
     ///
     /// An output that is potentially spent by an unmined transaction in the mempool is excluded
     /// iff the spending transaction will not be expired at `target_height`.
@@ -116,19 +123,21 @@ impl<P: consensus::Parameters> InputSource for MemoryWalletDb<P> {
         target_height: BlockHeight,
         min_confirmations: u32,
     ) -> Result<Vec<WalletTransparentOutput>, Self::Error> {
-        let txos = self
+        let mut txos = vec![];
+        for (outpoint, txo) in self
             .transparent_received_outputs
             .iter()
             .filter(|(_, txo)| txo.address == *address)
-            .map(|(outpoint, txo)| (outpoint, txo, self.tx_table.get(&txo.transaction_id)))
-            .filter(|(outpoint, _, _)| {
-                self.utxo_is_spendable(outpoint, target_height, min_confirmations)
-                    .unwrap_or(false)
-            })
-            .filter_map(|(outpoint, txo, tx)| {
-                txo.to_wallet_transparent_output(outpoint, tx.and_then(|tx| tx.mined_height()))
-            })
-            .collect();
+        {
+            if !self.utxo_is_spendable(outpoint, target_height, min_confirmations)? {
+                continue;
+            }
+            let mined_height = self
+                .tx_table
+                .get(&txo.transaction_id)
+                .and_then(|tx| tx.mined_height());
+            txos.extend(txo.to_wallet_transparent_output(outpoint, mined_height));
+        }
         Ok(txos)
     }
 
@@ -203,17 +212,22 @@ impl<P: consensus::Parameters> MemoryWalletDb<P> {
                 return Ok(Vec::new());
             }
         };
-        // First grab all eligible (unspent, spendable, fully scanned) notes into a vec.
-        let mut eligible_notes = self
+        // First grab all eligible (unspent, spendable, fully scanned) notes into a vec. Notes
+        // spent by pending transactions that have not yet expired are not eligible, and nor are
+        // notes that cannot be witnessed at the anchor checkpoint.
+        let mut eligible_notes = vec![];
+        for note in self
             .received_notes
             .iter()
             .filter(|note| note.account_id == account)
             .filter(|note| note.note.protocol() == *pool)
-            .filter(|note| {
-                self.note_is_spendable(note, birthday_height, anchor_height, exclude)
-                    .unwrap()
-            })
-            .collect::<Vec<_>>();
+        {
+            if self.note_is_spendable(note, birthday_height, anchor_height, exclude)?
+                && self.note_is_witnessable(note, anchor_height)?
+            {
+                eligible_notes.push(note);
+            }
+        }
 
         // sort by oldest first (use location in commitment tree since this gives a total order)
         eligible_notes.sort_by(|a, b| a.commitment_tree_position.cmp(&b.commitment_tree_position));
@@ -232,50 +246,56 @@ impl<P: consensus::Parameters> MemoryWalletDb<P> {
         Ok(selection)
     }
 
+    /// Returns whether the transparent output at `outpoint` can be spent in a transaction
+    /// targeting `target_height`: the transaction that created it must have been mined with at
+    /// least `min_confirmations` confirmations as of that height, and the output must not be
+    /// spent by a transaction that is mined or could still be mined at that height.
+    ///
+    /// If zero-conf spends are allowed and `min_confirmations` is zero, the output of an
+    /// unmined transaction that could still be mined at `target_height` is also spendable.
     pub fn utxo_is_spendable(
         &self,
         outpoint: &OutPoint,
         target_height: BlockHeight,
         min_confirmations: u32,
     ) -> Result<bool, Error> {
+        // A transaction mined at `confirmed_height` has exactly `min_confirmations`
+        // confirmations as of `target_height`.
         let confirmed_height = target_height - min_confirmations;
         let utxo = self
             .transparent_received_outputs
             .get(outpoint)
             .ok_or(Error::NoteNotFound)?;
-        if let Some(tx) = self.tx_table.get(&utxo.transaction_id) {
-            Ok(
-                tx.is_mined_or_unexpired_at(confirmed_height) // tx that created it is mined
-                && !self.utxo_is_spent(outpoint, min_confirmations)?, // not spent
-            )
-        } else {
-            Ok(false)
-        }
+        let is_confirmed = self.tx_table.get(&utxo.transaction_id).map_or(false, |tx| {
+            tx.mined_height()
+                .map_or(false, |mined_height| mined_height <= confirmed_height)
+                || (self.allow_zero_conf_spends
+                    && min_confirmations == 0
+                    && tx.is_unmined_and_unexpired_at(target_height))
+        });
+
+        Ok(is_confirmed && !self.utxo_is_spent(outpoint, target_height)?)
     }
 
-    fn utxo_is_spent(&self, outpoint: &OutPoint, min_confirmations: u32) -> Result<bool, Error> {
-        let spend = self.transparent_received_output_spends.get(outpoint);
-
-        let spent = match spend {
-            Some(txid) => {
-                let spending_tx = self
-                    .tx_table
-                    .get(txid)
-                    .ok_or_else(|| Error::TransactionNotFound(*txid))?;
-                match spending_tx.status() {
-                    TransactionStatus::Mined(_height) => true,
-                    TransactionStatus::TxidNotRecognized => unreachable!(),
-                    TransactionStatus::NotInMainChain => {
-                        // check the expiry
-                        spending_tx.expiry_height().is_none() // no expiry, tx could be mined any time so we consider it spent
-                            // expiry is in the future so it could still be mined
-                            || spending_tx.expiry_height() > self.summary_height(min_confirmations)?
-                    }
-                }
-            }
-            None => false,
-        };
-        Ok(spent)
+    /// Returns whether the transparent output at `outpoint` is spent by a transaction that is
+    /// mined, cannot expire, or has not expired as of `target_height`.
+    ///
+    /// This is intentionally conservative: an output spent by a mempool transaction that may
+    /// still be mined at `target_height` is treated as spent, even if that transaction expires
+    /// before the chain tip reaches it.
+    fn utxo_is_spent(
+        &self,
+        outpoint: &OutPoint,
+        target_height: BlockHeight,
+    ) -> Result<bool, Error> {
+        match self.transparent_received_output_spends.get(outpoint) {
+            Some(txid) => Ok(self
+                .tx_table
+                .get(txid)
+                .ok_or_else(|| Error::TransactionNotFound(*txid))?
+                .is_mined_or_unexpired_spend(target_height)),
+            None => Ok(false),
+        }
     }
 
     fn spendable_notes_meta(
@@ -292,6 +312,12 @@ impl<P: consensus::Parameters> MemoryWalletDb<P> {
                 return Ok(None);
             }
         };
+        let min_value = match self.min_note_value(account, filter)? {
+            Some(min_value) => min_value,
+            None => {
+                return Ok(None);
+            }
+        };
         let (count, total) = self
             .received_notes
             .iter()
@@ -301,11 +327,7 @@ impl<P: consensus::Parameters> MemoryWalletDb<P> {
                 self.note_is_spendable(note, birthday_height, chain_tip_height, exclude)
                     .unwrap()
             })
-            .filter(|note| {
-                self.matches_note_filter(note, filter)
-                    .unwrap()
-                    .is_some_and(|b| b)
-            })
+            .filter(|note| note.note.value() >= min_value)
             .fold((0, Zatoshis::ZERO), |(count, total), note| {
                 (count + 1, (total + note.note.value()).unwrap())
             });
@@ -313,35 +335,356 @@ impl<P: consensus::Parameters> MemoryWalletDb<P> {
         Ok(Some(PoolMeta::new(count, total)))
     }
 
-    #[allow(clippy::only_used_in_recursion)]
-    fn matches_note_filter(
+    /// Evaluates the given filter for the notes of the given account, returning the minimum
+    /// value of the notes that it selects, or `None` if the filter cannot be evaluated.
+    fn min_note_value(
         &self,
-        note: &crate::ReceivedNote,
+        account: AccountId,
         filter: &NoteFilter,
-    ) -> Result<Option<bool>, Error> {
+    ) -> Result<Option<Zatoshis>, Error> {
         match filter {
-            NoteFilter::ExceedsMinValue(min_value) => Ok(Some(note.note.value() > *min_value)),
-            NoteFilter::ExceedsPriorSendPercentile(_n) => todo!(),
-            NoteFilter::ExceedsBalancePercentage(_p) => todo!(),
-            // evaluate both conditions.
-            // If one cannot be evaluated (e.g. it returns None) it is ignored
+            NoteFilter::ExceedsMinValue(min_value) => Ok(Some(*min_value)),
+            NoteFilter::ExceedsPriorSendPercentile(n) => {
+                // The values of the mined sends from the account, excluding change and other
+                // sends to the account itself.
+                let mut sent_values = self
+                    .sent_notes
+                    .iter()
+                    .filter(|(_, note)| note.from_account_id == account)
+                    .filter(|(_, note)| match &note.to {
+                        Recipient::External(..) => true,
+                        Recipient::EphemeralTransparent {
+                            receiving_account, ..
+                        }
+                        | Recipient::InternalAccount {
+                            receiving_account, ..
+                        } => *receiving_account != account,
+                    })
+                    .filter(|(id, _)| {
+                        self.tx_table
+                            .get(id.txid())
+                            .is_some_and(|tx| tx.mined_height().is_some())
+                    })
+                    .map(|(_, note)| note.value)
+                    .collect::<Vec<_>>();
+                sent_values.sort();
+
+                // Pick a bucket by scaling the requested percentile to the number of buckets.
+                let bucket_maxima = bucket_maxima(&sent_values, 10);
+                let i = (bucket_maxima.len() * usize::from(*n) / 100).saturating_sub(1);
+                Ok(bucket_maxima.get(i).copied())
+            }
+            NoteFilter::ExceedsBalancePercentage(p) => {
+                let mut balance = None;
+                for note in self
+                    .received_notes
+                    .iter()
+                    .filter(|note| note.account_id == account)
+                {
+                    let is_mined = self
+                        .tx_table
+                        .get(&note.txid())
+                        .is_some_and(|tx| tx.mined_height().is_some());
+                    if is_mined && !self.note_is_spent(note, 0)? {
+                        balance = Some(
+                            (balance.unwrap_or(Zatoshis::ZERO) + note.note.value())
+                                .ok_or(BalanceError::Overflow)?,
+                        );
+                    }
+                }
+
+                balance
+                    .map(|balance| {
+                        let numerator =
+                            (balance * u64::from(p.value())).ok_or(BalanceError::Overflow)?;
+                        Ok(numerator / NonZeroU64::new(100).expect("Constant is nonzero."))
+                    })
+                    .transpose()
+            }
+            // All of the filters set lower bounds on note value, so both conditions are
+            // satisfied by the greater of the two bounds. A condition that cannot be evaluated
+            // is ignored.
             NoteFilter::Combine(a, b) => {
-                let matches_a = self.matches_note_filter(note, a)?.unwrap_or(true);
-                let matches_b = self.matches_note_filter(note, b)?.unwrap_or(true);
-                Ok(Some(matches_a && matches_b))
+                let a_min_value = self.min_note_value(account, a)?;
+                let b_min_value = self.min_note_value(account, b)?;
+                Ok(a_min_value
+                    .zip(b_min_value)
+                    .map(|(av, bv)| std::cmp::max(av, bv))
+                    .or(a_min_value)
+                    .or(b_min_value))
             }
-            // Evaluate the first condition and return the result.
-            // If the first condition cannot be evaluated then use the fallback instead
+            // Evaluate the first condition, and if it cannot be evaluated then use the fallback
+            // instead.
             NoteFilter::Attempt {
                 condition,
                 fallback,
-            } => {
-                if let Some(b) = self.matches_note_filter(note, condition)? {
-                    Ok(Some(b))
-                } else {
-                    self.matches_note_filter(note, fallback)
-                }
-            }
+            } => match self.min_note_value(account, condition)? {
+                Some(min_value) => Ok(Some(min_value)),
+                None => self.min_note_value(account, fallback),
+            },
         }
     }
 }
+
+/// Returns the greatest value in each of up to `buckets` buckets of nearly equal size into which
+/// `sorted_values` are divided in order, in the manner of SQL's `NTILE` window function.
+fn bucket_maxima(sorted_values: &[Zatoshis], buckets: usize) -> Vec<Zatoshis> {
+    let bucket_count = buckets.min(sorted_values.len());
+    if bucket_count == 0 {
+        return vec![];
+    }
+    // The first `larger` buckets hold one more value than the rest.
+    let (size, larger) = (
+        sorted_values.len() / bucket_count,
+        sorted_values.len() % bucket_count,
+    );
+    let mut end = 0;
+    (0..bucket_count)
+        .map(|i| {
+            end += size + usize::from(i < larger);
+            sorted_values[end - 1]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, num::NonZeroU32};
+
+    use sapling::zip32::ExtendedSpendingKey;
+    use zcash_client_backend::{
+        data_api::{
+            testing::{AddressType, TestBuilder},
+            Account as _, InputSource, WalletRead,
+        },
+        fees::StandardFeeRule,
+        wallet::{NoteId, OvkPolicy},
+    };
+    use zcash_keys::address::Address;
+    use zcash_primitives::{block::BlockHash, transaction::components::amount::NonNegativeAmount};
+    use zcash_protocol::{consensus::BlockHeight, local_consensus::LocalNetwork, ShieldedProtocol};
+
+    use super::bucket_maxima;
+    use crate::{testing::TestMemDbFactory, MemBlockCache, MemoryWalletDb};
+    #[cfg(feature = "transparent-inputs")]
+    use {
+        zcash_client_backend::{
+            data_api::{wallet::input_selection::GreedyInputSelector, WalletWrite},
+            fees::{standard, DustOutputPolicy},
+            wallet::WalletTransparentOutput,
+        },
+        zcash_primitives::transaction::components::{OutPoint, TxOut},
+    };
+
+    #[test]
+    fn bucket_maxima_match_ntile() {
+        let values = (1..=25)
+            .map(NonNegativeAmount::const_from_u64)
+            .collect::<Vec<_>>();
+        let maxima = |n, buckets| {
+            bucket_maxima(&values[..n], buckets)
+                .into_iter()
+                .map(|v| v.into_u64())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(maxima(0, 10), Vec::<u64>::new());
+        assert_eq!(maxima(3, 10), vec![1, 2, 3]);
+        assert_eq!(maxima(10, 10), (1..=10).collect::<Vec<_>>());
+        // The first five of ten buckets hold three values, and the rest hold two.
+        assert_eq!(maxima(25, 10), vec![3, 6, 9, 12, 15, 17, 19, 21, 23, 25]);
+    }
+
+    #[test]
+    fn spendable_notes_exclude_pending_spends() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let account = st.test_account().cloned().unwrap();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+
+        let value = NonNegativeAmount::const_from_u64(50000);
+        let (h, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        st.scan_cached_blocks(h, 2);
+
+        let selectable = |wallet: &MemoryWalletDb<LocalNetwork>,
+                          anchor_height: BlockHeight,
+                          exclude: &[NoteId]| {
+            let mut note_ids = wallet
+                .select_spendable_notes(
+                    account.id(),
+                    NonNegativeAmount::const_from_u64(1000000),
+                    &[ShieldedProtocol::Sapling],
+                    anchor_height,
+                    exclude,
+                )
+                .unwrap()
+                .sapling()
+                .iter()
+                .map(|note| *note.internal_note_id())
+                .collect::<Vec<_>>();
+            note_ids.sort();
+            note_ids
+        };
+
+        let mut all_notes = selectable(st.wallet(), h + 1, &[]);
+        assert_eq!(all_notes.len(), 2);
+
+        // Explicitly excluded notes are not selected.
+        assert_eq!(
+            selectable(st.wallet(), h + 1, &all_notes[..1]),
+            all_notes[1..].to_vec()
+        );
+
+        // A note spent by a transaction that has been created but not yet mined is locked.
+        let not_our_key = ExtendedSpendingKey::master(&[]).to_diversifiable_full_viewing_key();
+        let proposal = st
+            .propose_standard_transfer::<Infallible>(
+                account.id(),
+                StandardFeeRule::Zip317,
+                NonZeroU32::new(1).unwrap(),
+                &Address::Sapling(not_our_key.default_address().1),
+                NonNegativeAmount::const_from_u64(20000),
+                None,
+                None,
+                ShieldedProtocol::Sapling,
+            )
+            .unwrap();
+        let txid = st
+            .create_proposed_transactions::<Infallible, _, Infallible>(
+                account.usk(),
+                OvkPolicy::Sender,
+                &proposal,
+            )
+            .unwrap()[0];
+        let expiry_height = st
+            .wallet()
+            .tx_table
+            .get(&txid)
+            .and_then(|tx| tx.expiry_height())
+            .unwrap();
+        all_notes.sort_by_key(|note_id| st.wallet().received_note_spends.get(note_id).is_some());
+        let (unspent, locked) = (all_notes[0], all_notes[1]);
+        assert_eq!(st.wallet().received_note_spends.get(&locked), Some(&txid));
+        assert_eq!(selectable(st.wallet(), h + 1, &[]), vec![unspent]);
+        assert_eq!(selectable(st.wallet(), h + 1, &[unspent]), vec![]);
+
+        // The note remains locked while the spending transaction may still be mined, and is
+        // released once the chain tip reaches its expiry height.
+        let mut tip = h + 1;
+        while tip < expiry_height - 1 {
+            tip = st.generate_empty_block().0;
+        }
+        st.scan_cached_blocks(h + 2, usize::try_from(tip - (h + 1)).unwrap());
+        assert_eq!(st.wallet().chain_height().unwrap(), Some(expiry_height - 1));
+        assert_eq!(selectable(st.wallet(), h + 1, &[]), vec![unspent]);
+
+        let (tip, _) = st.generate_empty_block();
+        assert_eq!(tip, expiry_height);
+        st.scan_cached_blocks(tip, 1);
+        let mut expected = vec![unspent, locked];
+        expected.sort();
+        assert_eq!(selectable(st.wallet(), h + 1, &[]), expected);
+    }
+
+    #[test]
+    #[cfg(feature = "transparent-inputs")]
+    fn spendable_transparent_outputs_respect_confirmations_and_spends() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let account = st.test_account().cloned().unwrap();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let taddr = *st
+            .wallet()
+            .get_current_address(account.id())
+            .unwrap()
+            .unwrap()
+            .transparent()
+            .unwrap();
+
+        let (h, _, _) = st.generate_next_block(
+            &dfvk,
+            AddressType::DefaultExternal,
+            NonNegativeAmount::const_from_u64(10000),
+        );
+        st.scan_cached_blocks(h, 1);
+
+        let value = NonNegativeAmount::const_from_u64(100000);
+        let utxo = |seed: u8, mined_height| {
+            WalletTransparentOutput::from_parts(
+                OutPoint::new([seed; 32], 0),
+                TxOut {
+                    value,
+                    script_pubkey: taddr.script(),
+                },
+                mined_height,
+            )
+            .unwrap()
+        };
+        let mined = utxo(1, Some(h));
+        let unmined = utxo(2, None);
+        st.wallet_mut()
+            .put_received_transparent_utxo(&mined)
+            .unwrap();
+        st.wallet_mut()
+            .put_received_transparent_utxo(&unmined)
+            .unwrap();
+
+        let spendable = |wallet: &MemoryWalletDb<LocalNetwork>,
+                         target_height: BlockHeight,
+                         min_confirmations: u32| {
+            wallet
+                .get_spendable_transparent_outputs(&taddr, target_height, min_confirmations)
+                .unwrap()
+                .iter()
+                .map(|output| output.outpoint().clone())
+                .collect::<Vec<_>>()
+        };
+
+        // An output mined at `h` has `target_height - h` confirmations, and an unmined output
+        // is never spendable.
+        let wallet = st.wallet();
+        assert_eq!(spendable(wallet, h + 1, 0), vec![mined.outpoint().clone()]);
+        assert_eq!(spendable(wallet, h + 1, 1), vec![mined.outpoint().clone()]);
+        assert_eq!(spendable(wallet, h + 1, 2), vec![]);
+        assert_eq!(spendable(wallet, h + 2, 2), vec![mined.outpoint().clone()]);
+
+        let change_strategy = standard::SingleOutputChangeStrategy::new(
+            StandardFeeRule::Zip317,
+            None,
+            ShieldedProtocol::Sapling,
+            DustOutputPolicy::default(),
+        );
+        let txid = st
+            .shield_transparent_funds(
+                &GreedyInputSelector::new(),
+                &change_strategy,
+                value,
+                account.usk(),
+                &[taddr],
+                account.id(),
+                1,
+            )
+            .unwrap()[0];
+        let expiry_height = st
+            .wallet()
+            .tx_table
+            .get(&txid)
+            .and_then(|tx| tx.expiry_height())
+            .unwrap();
+
+        // The output is unavailable while the unmined shielding transaction may still be mined.
+        let wallet = st.wallet();
+        assert_eq!(spendable(wallet, expiry_height, 1), vec![]);
+        assert_eq!(
+            spendable(wallet, expiry_height + 1, 1),
+            vec![mined.outpoint().clone()]
+        );
+    }
+}
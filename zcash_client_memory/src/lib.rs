@@ -1,18 +1,36 @@
 mod block_source;
 mod error;
+#[cfg(feature = "facade")]
+pub mod facade;
 mod input_source;
 pub mod proto;
+mod shared;
 mod types;
 mod wallet_commitment_trees;
 mod wallet_read;
 mod wallet_write;
 
-#[cfg(test)]
+#[cfg(any(test, feature = "test-dependencies"))]
 pub mod testing;
 pub use block_source::*;
 pub use error::Error;
-pub use types::MemoryWalletDb;
+pub use shared::{SharedMemoryWalletDb, SharedShardStore};
+#[cfg(feature = "metrics")]
+pub use types::metric_names;
+#[cfg(feature = "csv-export")]
+pub use types::write_note_export_csv;
+#[cfg(feature = "telemetry")]
+pub use types::ScanTelemetry;
 pub(crate) use types::*;
+pub use types::{
+    AddressReuse, CoverageState, MemoryWalletDb, MergeReport, NoteExportFilter, NoteExportRecord,
+    ReorgStats, ScanTuning, TruncationRecord, TxValueBreakdown, WalletProvenance,
+    WALLET_SERIALIZATION_VERSION,
+};
+#[cfg(feature = "snapshot-compression")]
+pub use types::{CompressedSnapshotMetadata, SnapshotSection, SnapshotSectionInfo};
+#[cfg(feature = "diagnostics")]
+pub use types::{DiagnosticStats, RedactionOptions};
 
 /// The maximum number of blocks the wallet is allowed to rewind. This is
 /// consistent with the bound in zcashd, and allows block data deeper than
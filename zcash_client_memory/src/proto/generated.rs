@@ -11,6 +11,19 @@ pub struct Address {
     pub diversifier_index: ::prost::alloc::vec::Vec<u8>,
     #[prost(string, tag = "2")]
     pub address: ::prost::alloc::string::String,
+    /// the receivers that were requested when the address was generated
+    #[prost(message, optional, tag = "3")]
+    pub request: ::core::option::Option<AddressRequest>,
+}
+/// The receiver types requested for a unified address
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct AddressRequest {
+    #[prost(bool, tag = "1")]
+    pub orchard: bool,
+    #[prost(bool, tag = "2")]
+    pub sapling: bool,
+    #[prost(bool, tag = "3")]
+    pub p2pkh: bool,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct NoteId {
@@ -446,13 +459,81 @@ pub struct MemoryWallet {
     /// Queue of transaction data requests the wallet should make to the lightwalletd provided to obtain more complete information
     #[prost(message, repeated, tag = "18")]
     pub transaction_data_requests: ::prost::alloc::vec::Vec<TransactionDataRequest>,
+    /// Wallet-created transactions that were removed after becoming permanently invalid
+    #[prost(message, repeated, tag = "19")]
+    pub purged_transactions: ::prost::alloc::vec::Vec<PurgedTransactionRecord>,
+    /// Diagnostic information about the creation and restoration of this wallet
+    #[prost(message, optional, tag = "20")]
+    pub provenance: ::core::option::Option<WalletProvenance>,
+    /// The part of the block range currently being scanned that has not yet been committed, with its original priority
+    #[prost(message, optional, tag = "21")]
+    pub in_progress_scan: ::core::option::Option<ScanQueueRecord>,
+    /// Statistics about the truncations of this wallet caused by chain reorganizations
+    #[prost(message, optional, tag = "22")]
+    pub reorg_stats: ::core::option::Option<ReorgStats>,
+    /// The block ranges that this wallet has scanned, independent of the retained block records
+    #[prost(message, repeated, tag = "23")]
+    pub scanned_heights: ::prost::alloc::vec::Vec<ScannedRangeRecord>,
+    /// The received transparent outputs whose spends the wallet should search for
+    #[prost(message, repeated, tag = "24")]
+    pub transparent_spend_searches: ::prost::alloc::vec::Vec<OutPoint>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct WalletProvenance {
+    /// unix timestamp (in seconds) at which the wallet was created
+    #[prost(int64, optional, tag = "1")]
+    pub created_at: ::core::option::Option<i64>,
+    /// the crate version that created the wallet
+    #[prost(string, optional, tag = "2")]
+    pub created_with_version: ::core::option::Option<::prost::alloc::string::String>,
+    /// the number of times the wallet has been restored from a snapshot
+    #[prost(uint32, tag = "3")]
+    pub restore_count: u32,
+    /// unix timestamp (in seconds) of the most recent restore
+    #[prost(int64, optional, tag = "4")]
+    pub last_restored_at: ::core::option::Option<i64>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ReorgStats {
+    /// the number of times the wallet has been truncated
+    #[prost(uint32, tag = "1")]
+    pub truncation_count: u32,
+    /// the largest number of scanned blocks removed by a single truncation
+    #[prost(uint32, tag = "2")]
+    pub max_depth: u32,
+    /// the most recent truncation
+    #[prost(message, optional, tag = "3")]
+    pub last_truncation: ::core::option::Option<TruncationRecord>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct TruncationRecord {
+    /// unix timestamp (in seconds) of the truncation
+    #[prost(int64, optional, tag = "1")]
+    pub truncated_at: ::core::option::Option<i64>,
+    /// the height to which the wallet was truncated
+    #[prost(uint32, tag = "2")]
+    pub height: u32,
+    /// the number of scanned blocks removed
+    #[prost(uint32, tag = "3")]
+    pub depth: u32,
+    /// the height below which the removed blocks have been scanned again
+    #[prost(uint32, tag = "4")]
+    pub rescanned_to: u32,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PurgedTransactionRecord {
+    #[prost(message, optional, tag = "1")]
+    pub tx_id: ::core::option::Option<TxId>,
+    /// the fully-scanned height at which the transaction was removed
+    #[prost(uint32, tag = "2")]
+    pub purge_height: u32,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Accounts {
     /// map from account index to account data
     #[prost(message, repeated, tag = "1")]
     pub accounts: ::prost::alloc::vec::Vec<Account>,
-    /// the nonce for the next account
+    /// the nonce for the next account (unused since version 2)
     #[prost(uint32, tag = "2")]
     pub account_nonce: u32,
 }
@@ -484,6 +565,17 @@ pub struct Account {
     /// map from index to encoded unified address
     #[prost(message, repeated, tag = "10")]
     pub ephemeral_addresses: ::prost::alloc::vec::Vec<EphemeralAddressRecord>,
+    /// addresses that have been given out to a counterparty
+    #[prost(message, repeated, tag = "11")]
+    pub exposed_addresses: ::prost::alloc::vec::Vec<AddressExposure>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct AddressExposure {
+    #[prost(bytes = "vec", tag = "1")]
+    pub diversifier_index: ::prost::alloc::vec::Vec<u8>,
+    /// the height at which the address was first exposed
+    #[prost(uint32, tag = "2")]
+    pub exposed_at_height: u32,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct AccountBirthday {
@@ -588,6 +680,9 @@ pub struct TransactionEntry {
     pub target_height: ::core::option::Option<u32>,
     #[prost(uint32, optional, tag = "8")]
     pub mined_height: ::core::option::Option<u32>,
+    /// the chain tip when the wallet stored the transaction for sending
+    #[prost(uint32, optional, tag = "9")]
+    pub stored_at_chain_tip: ::core::option::Option<u32>,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct TransactionDataRequest {
@@ -603,6 +698,9 @@ pub struct TransactionDataRequest {
     pub block_range_start: ::core::option::Option<u32>,
     #[prost(uint32, optional, tag = "5")]
     pub block_range_end: ::core::option::Option<u32>,
+    /// the chain height at which a GetStatus or Enhancement request was first queued
+    #[prost(uint32, optional, tag = "6")]
+    pub queued_at_height: ::core::option::Option<u32>,
 }
 #[derive(Clone, Copy, PartialEq, ::prost::Message)]
 pub struct ScanQueueRecord {
@@ -613,6 +711,15 @@ pub struct ScanQueueRecord {
     #[prost(enumeration = "ScanPriority", tag = "3")]
     pub priority: i32,
 }
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct ScannedRangeRecord {
+    /// the first scanned height
+    #[prost(uint32, tag = "1")]
+    pub start_height: u32,
+    /// the height following the last scanned height
+    #[prost(uint32, tag = "2")]
+    pub end_height: u32,
+}
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
 #[repr(i32)]
 pub enum AccountKind {
@@ -743,3 +850,215 @@ impl ScanPriority {
         }
     }
 }
+/// A description of the structure of a wallet, from which keys, addresses, memos, and
+/// (optionally) exact values have been removed, for sharing with support.
+///
+/// Transaction ids are replaced by hashes salted with a value that is chosen for each export
+/// and is not included, so that they cannot be matched against the chain. Addresses are
+/// replaced by tokens derived in the same way, which are stable within an export.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RedactedDiagnostics {
+    /// the version of the redacted diagnostics format
+    #[prost(uint32, tag = "1")]
+    pub version: u32,
+    /// whether values are exact, rather than rounded down to a power of ten
+    #[prost(bool, tag = "2")]
+    pub exact_values: bool,
+    #[prost(message, repeated, tag = "3")]
+    pub accounts: ::prost::alloc::vec::Vec<RedactedAccount>,
+    #[prost(message, repeated, tag = "4")]
+    pub blocks: ::prost::alloc::vec::Vec<RedactedBlock>,
+    #[prost(message, repeated, tag = "5")]
+    pub transactions: ::prost::alloc::vec::Vec<RedactedTransaction>,
+    #[prost(message, repeated, tag = "6")]
+    pub received_notes: ::prost::alloc::vec::Vec<RedactedReceivedNote>,
+    #[prost(message, repeated, tag = "7")]
+    pub sent_notes: ::prost::alloc::vec::Vec<RedactedSentNote>,
+    #[prost(message, repeated, tag = "8")]
+    pub transparent_outputs: ::prost::alloc::vec::Vec<RedactedTransparentOutput>,
+    #[prost(message, repeated, tag = "9")]
+    pub transaction_data_requests: ::prost::alloc::vec::Vec<RedactedDataRequest>,
+    /// the scan queue, unchanged from the wallet
+    #[prost(message, repeated, tag = "10")]
+    pub scan_queue: ::prost::alloc::vec::Vec<ScanQueueRecord>,
+    #[prost(message, optional, tag = "11")]
+    pub in_progress_scan: ::core::option::Option<ScanQueueRecord>,
+    #[prost(message, repeated, tag = "12")]
+    pub scanned_heights: ::prost::alloc::vec::Vec<ScannedRangeRecord>,
+    #[prost(message, optional, tag = "13")]
+    pub sapling_tree: ::core::option::Option<RedactedTreeShape>,
+    #[prost(message, optional, tag = "14")]
+    pub orchard_tree: ::core::option::Option<RedactedTreeShape>,
+    /// the number of nullifiers observed while scanning
+    #[prost(uint32, tag = "15")]
+    pub nullifier_count: u32,
+    #[prost(uint32, tag = "16")]
+    pub purged_transaction_count: u32,
+    #[prost(message, optional, tag = "17")]
+    pub reorg_stats: ::core::option::Option<ReorgStats>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RedactedAccount {
+    /// the position of the account in the wallet, in place of its id
+    #[prost(uint32, tag = "1")]
+    pub account: u32,
+    #[prost(enumeration = "AccountKind", tag = "2")]
+    pub kind: i32,
+    /// salted hash of the encoded viewing key
+    #[prost(bytes = "vec", tag = "3")]
+    pub viewing_key_hash: ::prost::alloc::vec::Vec<u8>,
+    #[prost(uint32, tag = "4")]
+    pub birthday_height: u32,
+    #[prost(uint32, optional, tag = "5")]
+    pub recover_until: ::core::option::Option<u32>,
+    #[prost(uint32, tag = "6")]
+    pub address_count: u32,
+    #[prost(uint32, tag = "7")]
+    pub exposed_address_count: u32,
+    #[prost(uint32, tag = "8")]
+    pub ephemeral_address_count: u32,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RedactedBlock {
+    #[prost(uint32, tag = "1")]
+    pub height: u32,
+    /// block hashes are public, and are retained
+    #[prost(bytes = "vec", tag = "2")]
+    pub hash: ::prost::alloc::vec::Vec<u8>,
+    #[prost(uint32, tag = "3")]
+    pub block_time: u32,
+    #[prost(uint32, tag = "4")]
+    pub transaction_count: u32,
+    #[prost(uint32, optional, tag = "5")]
+    pub sapling_commitment_tree_size: ::core::option::Option<u32>,
+    #[prost(uint32, optional, tag = "6")]
+    pub sapling_output_count: ::core::option::Option<u32>,
+    #[prost(uint32, optional, tag = "7")]
+    pub orchard_commitment_tree_size: ::core::option::Option<u32>,
+    #[prost(uint32, optional, tag = "8")]
+    pub orchard_action_count: ::core::option::Option<u32>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RedactedTransaction {
+    #[prost(bytes = "vec", tag = "1")]
+    pub tx_hash: ::prost::alloc::vec::Vec<u8>,
+    #[prost(enumeration = "TransactionStatus", tag = "2")]
+    pub status: i32,
+    #[prost(uint32, optional, tag = "3")]
+    pub mined_height: ::core::option::Option<u32>,
+    #[prost(uint32, optional, tag = "4")]
+    pub expiry_height: ::core::option::Option<u32>,
+    #[prost(uint32, optional, tag = "5")]
+    pub target_height: ::core::option::Option<u32>,
+    #[prost(uint64, optional, tag = "6")]
+    pub fee: ::core::option::Option<u64>,
+    #[prost(bool, tag = "7")]
+    pub has_raw_tx: bool,
+    #[prost(bool, tag = "8")]
+    pub wallet_created: bool,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RedactedReceivedNote {
+    #[prost(bytes = "vec", tag = "1")]
+    pub tx_hash: ::prost::alloc::vec::Vec<u8>,
+    #[prost(enumeration = "ShieldedProtocol", tag = "2")]
+    pub protocol: i32,
+    #[prost(uint32, tag = "3")]
+    pub output_index: u32,
+    #[prost(uint32, tag = "4")]
+    pub account: u32,
+    #[prost(uint64, tag = "5")]
+    pub value: u64,
+    #[prost(bool, tag = "6")]
+    pub is_change: bool,
+    #[prost(uint64, optional, tag = "7")]
+    pub commitment_tree_position: ::core::option::Option<u64>,
+    #[prost(bool, tag = "8")]
+    pub has_nullifier: bool,
+    #[prost(bytes = "vec", optional, tag = "9")]
+    pub spent_in_tx_hash: ::core::option::Option<::prost::alloc::vec::Vec<u8>>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RedactedSentNote {
+    #[prost(bytes = "vec", tag = "1")]
+    pub tx_hash: ::prost::alloc::vec::Vec<u8>,
+    #[prost(uint32, tag = "2")]
+    pub output_index: u32,
+    #[prost(uint32, tag = "3")]
+    pub from_account: u32,
+    /// the pool of the output
+    #[prost(enumeration = "PoolType", tag = "4")]
+    pub pool: i32,
+    /// for outputs to an address
+    #[prost(bytes = "vec", optional, tag = "5")]
+    pub recipient_token: ::core::option::Option<::prost::alloc::vec::Vec<u8>>,
+    /// for outputs to an account of the wallet
+    #[prost(uint32, optional, tag = "6")]
+    pub receiving_account: ::core::option::Option<u32>,
+    #[prost(uint64, tag = "7")]
+    pub value: u64,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RedactedTransparentOutput {
+    #[prost(bytes = "vec", tag = "1")]
+    pub tx_hash: ::prost::alloc::vec::Vec<u8>,
+    #[prost(uint32, tag = "2")]
+    pub output_index: u32,
+    #[prost(uint32, tag = "3")]
+    pub account: u32,
+    #[prost(bytes = "vec", tag = "4")]
+    pub address_token: ::prost::alloc::vec::Vec<u8>,
+    #[prost(uint64, tag = "5")]
+    pub value: u64,
+    #[prost(uint32, optional, tag = "6")]
+    pub max_observed_unspent_height: ::core::option::Option<u32>,
+    #[prost(bytes = "vec", optional, tag = "7")]
+    pub spent_in_tx_hash: ::core::option::Option<::prost::alloc::vec::Vec<u8>>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RedactedDataRequest {
+    #[prost(enumeration = "TransactionDataRequestType", tag = "1")]
+    pub request_type: i32,
+    /// for the GetStatus and Enhancement variants
+    #[prost(bytes = "vec", optional, tag = "2")]
+    pub tx_hash: ::core::option::Option<::prost::alloc::vec::Vec<u8>>,
+    /// for the SpendsFromAddress variant
+    #[prost(bytes = "vec", optional, tag = "3")]
+    pub address_token: ::core::option::Option<::prost::alloc::vec::Vec<u8>>,
+    #[prost(uint32, optional, tag = "4")]
+    pub block_range_start: ::core::option::Option<u32>,
+    #[prost(uint32, optional, tag = "5")]
+    pub block_range_end: ::core::option::Option<u32>,
+    #[prost(uint32, optional, tag = "6")]
+    pub queued_at_height: ::core::option::Option<u32>,
+}
+/// The shape of a note commitment tree, without any of its node hashes
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RedactedTreeShape {
+    #[prost(message, repeated, tag = "1")]
+    pub shards: ::prost::alloc::vec::Vec<RedactedShard>,
+    #[prost(message, repeated, tag = "2")]
+    pub checkpoints: ::prost::alloc::vec::Vec<RedactedCheckpoint>,
+    #[prost(message, repeated, tag = "3")]
+    pub shard_end_heights: ::prost::alloc::vec::Vec<TreeEndHeightsRecord>,
+}
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct RedactedShard {
+    #[prost(uint64, tag = "1")]
+    pub shard_index: u64,
+    /// the position of the last leaf in the shard, if any
+    #[prost(uint64, optional, tag = "2")]
+    pub max_position: ::core::option::Option<u64>,
+    /// the number of leaves marked for witnessing
+    #[prost(uint32, tag = "3")]
+    pub marked_count: u32,
+}
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct RedactedCheckpoint {
+    /// the block height of the checkpoint
+    #[prost(uint32, tag = "1")]
+    pub checkpoint_id: u32,
+    /// absent if the tree was empty at the checkpoint
+    #[prost(uint64, optional, tag = "2")]
+    pub position: ::core::option::Option<u64>,
+}
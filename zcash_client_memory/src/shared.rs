@@ -0,0 +1,765 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    convert::Infallible,
+    num::NonZeroU32,
+    sync::{Arc, RwLockReadGuard, RwLockWriteGuard},
+};
+
+#[cfg(feature = "transparent-inputs")]
+use std::ops::Range;
+
+use incrementalmerkletree::Address;
+use secrecy::SecretVec;
+use shardtree::{
+    error::ShardTreeError,
+    store::{memory::MemoryShardStore, Checkpoint, ShardStore},
+    LocatedPrunableTree, PrunableTree, ShardTree,
+};
+use wasm_sync::RwLock;
+use zcash_client_backend::{
+    address::UnifiedAddress,
+    data_api::{
+        chain::{ChainState, CommitmentTreeRoot},
+        scanning::ScanRange,
+        AccountBirthday, AccountMeta, AccountPurpose, BlockMetadata, DecryptedTransaction,
+        InputSource, NoteFilter, NullifierQuery, ScannedBlock, SeedRelevance, SentTransaction,
+        SpendableNotes, TransactionDataRequest, TransactionStatus, WalletCommitmentTrees,
+        WalletRead, WalletSummary, WalletWrite, SAPLING_SHARD_HEIGHT,
+    },
+    keys::{UnifiedAddressRequest, UnifiedFullViewingKey, UnifiedSpendingKey},
+    wallet::{Note, NoteId, ReceivedNote, WalletTransparentOutput},
+};
+use zcash_primitives::{
+    block::BlockHash,
+    transaction::{components::OutPoint, Transaction, TxId},
+};
+use zcash_protocol::{
+    consensus::{self, BlockHeight},
+    memo::Memo,
+    value::Zatoshis,
+    ShieldedProtocol,
+};
+use zip32::fingerprint::SeedFingerprint;
+
+#[cfg(feature = "orchard")]
+use zcash_client_backend::data_api::ORCHARD_SHARD_HEIGHT;
+
+#[cfg(feature = "transparent-inputs")]
+use {
+    zcash_client_backend::wallet::TransparentAddressMetadata,
+    zcash_primitives::legacy::TransparentAddress,
+};
+
+use crate::{error::Error, Account, AccountId, MemoryWalletDb};
+
+/// A [`MemoryWalletDb`] that can be shared between threads, for example so that blocks can be
+/// scanned into the wallet on one task while another task reads its balance.
+///
+/// Cloning a `SharedMemoryWalletDb` produces another handle to the same wallet. Each method of
+/// [`WalletRead`] and [`InputSource`] takes a read lock on the wallet for the duration of the
+/// call, and each method of [`WalletWrite`] and [`WalletCommitmentTrees`] takes a write lock,
+/// so every call observes and leaves the wallet in a consistent state. Operations that span
+/// several calls, such as scanning a range of blocks, are not atomic: readers may observe the
+/// wallet between any two of the calls that make up the operation.
+///
+/// If a thread panics while it holds the write lock, the wallet may have been left partially
+/// updated. All subsequent calls then fail with [`Error::LockPoisoned`], which the methods of
+/// [`WalletCommitmentTrees`] report as a [`ShardTreeError::Storage`] error.
+///
+/// # Lock ordering
+///
+/// The lock is not reentrant. A thread must not call any method of a `SharedMemoryWalletDb`
+/// while it holds a guard returned by [`SharedMemoryWalletDb::read`] or
+/// [`SharedMemoryWalletDb::write`] for the same wallet, and the callbacks passed to
+/// [`WalletCommitmentTrees::with_sapling_tree_mut`] and `with_orchard_tree_mut`, which run
+/// while the write lock is held, must not call back into the wallet. If a wallet's lock is held
+/// together with other locks, it should always be acquired last.
+pub struct SharedMemoryWalletDb<P: consensus::Parameters>(Arc<RwLock<MemoryWalletDb<P>>>);
+
+impl<P: consensus::Parameters> Clone for SharedMemoryWalletDb<P> {
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+}
+
+impl<P: consensus::Parameters> From<MemoryWalletDb<P>> for SharedMemoryWalletDb<P> {
+    fn from(wallet: MemoryWalletDb<P>) -> Self {
+        Self::new(wallet)
+    }
+}
+
+impl<P: consensus::Parameters> SharedMemoryWalletDb<P> {
+    /// Returns a shareable handle to the given wallet.
+    pub fn new(wallet: MemoryWalletDb<P>) -> Self {
+        Self(Arc::new(RwLock::new(wallet)))
+    }
+
+    /// Takes a read lock on the wallet, for access to the methods of [`MemoryWalletDb`] that
+    /// are not part of the wallet traits.
+    pub fn read(&self) -> Result<RwLockReadGuard<'_, MemoryWalletDb<P>>, Error> {
+        self.0.read().map_err(|_| Error::LockPoisoned)
+    }
+
+    /// Takes a write lock on the wallet, for access to the methods of [`MemoryWalletDb`] that
+    /// are not part of the wallet traits.
+    pub fn write(&self) -> Result<RwLockWriteGuard<'_, MemoryWalletDb<P>>, Error> {
+        self.0.write().map_err(|_| Error::LockPoisoned)
+    }
+
+    fn write_trees(
+        &self,
+    ) -> Result<RwLockWriteGuard<'_, MemoryWalletDb<P>>, ShardTreeError<Error>> {
+        self.write().map_err(ShardTreeError::Storage)
+    }
+}
+
+/// Converts an error of the note commitment trees of a [`MemoryWalletDb`], which has no storage
+/// errors, into one of a [`SharedMemoryWalletDb`].
+fn shared_tree_error(e: ShardTreeError<Infallible>) -> ShardTreeError<Error> {
+    match e {
+        ShardTreeError::Query(e) => ShardTreeError::Query(e),
+        ShardTreeError::Insert(e) => ShardTreeError::Insert(e),
+        ShardTreeError::Storage(e) => match e {},
+    }
+}
+
+fn infallible<T>(result: Result<T, Infallible>) -> Result<T, Error> {
+    result.map_err(|e| match e {})
+}
+
+/// The shard store of a note commitment tree of a [`SharedMemoryWalletDb`].
+///
+/// This borrows the [`MemoryShardStore`] of the wallet while its write lock is held, and reports
+/// errors as [`Error`], so that a poisoned lock can be reported through the same
+/// [`ShardTreeError::Storage`] errors as the trees themselves.
+pub struct SharedShardStore<'a, H>(&'a mut MemoryShardStore<H, BlockHeight>);
+
+impl<H: Clone> ShardStore for SharedShardStore<'_, H> {
+    type H = H;
+    type CheckpointId = BlockHeight;
+    type Error = Error;
+
+    fn get_shard(&self, shard_root: Address) -> Result<Option<LocatedPrunableTree<H>>, Error> {
+        infallible(self.0.get_shard(shard_root))
+    }
+
+    fn last_shard(&self) -> Result<Option<LocatedPrunableTree<H>>, Error> {
+        infallible(self.0.last_shard())
+    }
+
+    fn put_shard(&mut self, subtree: LocatedPrunableTree<H>) -> Result<(), Error> {
+        infallible(self.0.put_shard(subtree))
+    }
+
+    fn get_shard_roots(&self) -> Result<Vec<Address>, Error> {
+        infallible(self.0.get_shard_roots())
+    }
+
+    fn truncate_shards(&mut self, shard_index: u64) -> Result<(), Error> {
+        infallible(self.0.truncate_shards(shard_index))
+    }
+
+    fn get_cap(&self) -> Result<PrunableTree<H>, Error> {
+        infallible(self.0.get_cap())
+    }
+
+    fn put_cap(&mut self, cap: PrunableTree<H>) -> Result<(), Error> {
+        infallible(self.0.put_cap(cap))
+    }
+
+    fn min_checkpoint_id(&self) -> Result<Option<BlockHeight>, Error> {
+        infallible(self.0.min_checkpoint_id())
+    }
+
+    fn max_checkpoint_id(&self) -> Result<Option<BlockHeight>, Error> {
+        infallible(self.0.max_checkpoint_id())
+    }
+
+    fn add_checkpoint(
+        &mut self,
+        checkpoint_id: BlockHeight,
+        checkpoint: Checkpoint,
+    ) -> Result<(), Error> {
+        infallible(self.0.add_checkpoint(checkpoint_id, checkpoint))
+    }
+
+    fn checkpoint_count(&self) -> Result<usize, Error> {
+        infallible(self.0.checkpoint_count())
+    }
+
+    fn get_checkpoint_at_depth(
+        &self,
+        checkpoint_depth: usize,
+    ) -> Result<Option<(BlockHeight, Checkpoint)>, Error> {
+        infallible(self.0.get_checkpoint_at_depth(checkpoint_depth))
+    }
+
+    fn get_checkpoint(&self, checkpoint_id: &BlockHeight) -> Result<Option<Checkpoint>, Error> {
+        infallible(self.0.get_checkpoint(checkpoint_id))
+    }
+
+    fn with_checkpoints<F>(&mut self, limit: usize, mut callback: F) -> Result<(), Error>
+    where
+        F: FnMut(&BlockHeight, &Checkpoint) -> Result<(), Error>,
+    {
+        // The callback's first error ends the iteration; the remaining checkpoints are skipped.
+        let mut result = Ok(());
+        infallible(self.0.with_checkpoints(limit, |id, checkpoint| {
+            if result.is_ok() {
+                result = callback(id, checkpoint);
+            }
+            Ok(())
+        }))?;
+        result
+    }
+
+    fn for_each_checkpoint<F>(&self, limit: usize, mut callback: F) -> Result<(), Error>
+    where
+        F: FnMut(&BlockHeight, &Checkpoint) -> Result<(), Error>,
+    {
+        let mut result = Ok(());
+        infallible(self.0.for_each_checkpoint(limit, |id, checkpoint| {
+            if result.is_ok() {
+                result = callback(id, checkpoint);
+            }
+            Ok(())
+        }))?;
+        result
+    }
+
+    fn update_checkpoint_with<F>(
+        &mut self,
+        checkpoint_id: &BlockHeight,
+        update: F,
+    ) -> Result<bool, Error>
+    where
+        F: Fn(&mut Checkpoint) -> Result<(), Error>,
+    {
+        let error = RefCell::new(None);
+        let updated = infallible(self.0.update_checkpoint_with(checkpoint_id, |checkpoint| {
+            if let Err(e) = update(checkpoint) {
+                *error.borrow_mut() = Some(e);
+            }
+            Ok(())
+        }))?;
+        error.into_inner().map_or(Ok(updated), Err)
+    }
+
+    fn remove_checkpoint(&mut self, checkpoint_id: &BlockHeight) -> Result<(), Error> {
+        infallible(self.0.remove_checkpoint(checkpoint_id))
+    }
+
+    fn truncate_checkpoints_retaining(&mut self, checkpoint_id: &BlockHeight) -> Result<(), Error> {
+        infallible(self.0.truncate_checkpoints_retaining(checkpoint_id))
+    }
+}
+
+impl<P: consensus::Parameters> WalletRead for SharedMemoryWalletDb<P> {
+    type Error = Error;
+    type AccountId = AccountId;
+    type Account = Account;
+
+    fn get_account_ids(&self) -> Result<Vec<Self::AccountId>, Self::Error> {
+        self.read()?.get_account_ids()
+    }
+
+    fn get_account(
+        &self,
+        account_id: Self::AccountId,
+    ) -> Result<Option<Self::Account>, Self::Error> {
+        self.read()?.get_account(account_id)
+    }
+
+    fn get_derived_account(
+        &self,
+        seed: &SeedFingerprint,
+        account_id: zip32::AccountId,
+    ) -> Result<Option<Self::Account>, Self::Error> {
+        self.read()?.get_derived_account(seed, account_id)
+    }
+
+    fn validate_seed(
+        &self,
+        account_id: Self::AccountId,
+        seed: &SecretVec<u8>,
+    ) -> Result<bool, Self::Error> {
+        self.read()?.validate_seed(account_id, seed)
+    }
+
+    fn seed_relevance_to_derived_accounts(
+        &self,
+        seed: &SecretVec<u8>,
+    ) -> Result<SeedRelevance<Self::AccountId>, Self::Error> {
+        self.read()?.seed_relevance_to_derived_accounts(seed)
+    }
+
+    fn get_account_for_ufvk(
+        &self,
+        ufvk: &UnifiedFullViewingKey,
+    ) -> Result<Option<Self::Account>, Self::Error> {
+        self.read()?.get_account_for_ufvk(ufvk)
+    }
+
+    fn get_current_address(
+        &self,
+        account: Self::AccountId,
+    ) -> Result<Option<UnifiedAddress>, Self::Error> {
+        self.read()?.get_current_address(account)
+    }
+
+    fn get_account_birthday(&self, account: Self::AccountId) -> Result<BlockHeight, Self::Error> {
+        self.read()?.get_account_birthday(account)
+    }
+
+    fn get_wallet_birthday(&self) -> Result<Option<BlockHeight>, Self::Error> {
+        self.read()?.get_wallet_birthday()
+    }
+
+    fn get_wallet_summary(
+        &self,
+        min_confirmations: u32,
+    ) -> Result<Option<WalletSummary<Self::AccountId>>, Self::Error> {
+        self.read()?.get_wallet_summary(min_confirmations)
+    }
+
+    fn chain_height(&self) -> Result<Option<BlockHeight>, Self::Error> {
+        self.read()?.chain_height()
+    }
+
+    fn get_block_hash(&self, block_height: BlockHeight) -> Result<Option<BlockHash>, Self::Error> {
+        self.read()?.get_block_hash(block_height)
+    }
+
+    fn block_metadata(&self, height: BlockHeight) -> Result<Option<BlockMetadata>, Self::Error> {
+        self.read()?.block_metadata(height)
+    }
+
+    fn block_fully_scanned(&self) -> Result<Option<BlockMetadata>, Self::Error> {
+        self.read()?.block_fully_scanned()
+    }
+
+    fn get_max_height_hash(&self) -> Result<Option<(BlockHeight, BlockHash)>, Self::Error> {
+        self.read()?.get_max_height_hash()
+    }
+
+    fn block_max_scanned(&self) -> Result<Option<BlockMetadata>, Self::Error> {
+        self.read()?.block_max_scanned()
+    }
+
+    fn suggest_scan_ranges(&self) -> Result<Vec<ScanRange>, Self::Error> {
+        self.read()?.suggest_scan_ranges()
+    }
+
+    fn get_target_and_anchor_heights(
+        &self,
+        min_confirmations: NonZeroU32,
+    ) -> Result<Option<(BlockHeight, BlockHeight)>, Self::Error> {
+        self.read()?
+            .get_target_and_anchor_heights(min_confirmations)
+    }
+
+    fn get_tx_height(&self, txid: TxId) -> Result<Option<BlockHeight>, Self::Error> {
+        self.read()?.get_tx_height(txid)
+    }
+
+    fn get_unified_full_viewing_keys(
+        &self,
+    ) -> Result<HashMap<Self::AccountId, UnifiedFullViewingKey>, Self::Error> {
+        self.read()?.get_unified_full_viewing_keys()
+    }
+
+    fn get_memo(&self, id_note: NoteId) -> Result<Option<Memo>, Self::Error> {
+        self.read()?.get_memo(id_note)
+    }
+
+    fn get_transaction(&self, txid: TxId) -> Result<Option<Transaction>, Self::Error> {
+        self.read()?.get_transaction(txid)
+    }
+
+    fn get_sapling_nullifiers(
+        &self,
+        query: NullifierQuery,
+    ) -> Result<Vec<(Self::AccountId, sapling::Nullifier)>, Self::Error> {
+        self.read()?.get_sapling_nullifiers(query)
+    }
+
+    #[cfg(feature = "orchard")]
+    fn get_orchard_nullifiers(
+        &self,
+        query: NullifierQuery,
+    ) -> Result<Vec<(Self::AccountId, orchard::note::Nullifier)>, Self::Error> {
+        self.read()?.get_orchard_nullifiers(query)
+    }
+
+    #[cfg(feature = "transparent-inputs")]
+    fn get_transparent_receivers(
+        &self,
+        account_id: Self::AccountId,
+    ) -> Result<HashMap<TransparentAddress, Option<TransparentAddressMetadata>>, Self::Error> {
+        self.read()?.get_transparent_receivers(account_id)
+    }
+
+    #[cfg(feature = "transparent-inputs")]
+    fn get_transparent_balances(
+        &self,
+        account_id: Self::AccountId,
+        summary_height: BlockHeight,
+    ) -> Result<HashMap<TransparentAddress, Zatoshis>, Self::Error> {
+        self.read()?
+            .get_transparent_balances(account_id, summary_height)
+    }
+
+    #[cfg(feature = "transparent-inputs")]
+    fn get_transparent_address_metadata(
+        &self,
+        account_id: Self::AccountId,
+        address: &TransparentAddress,
+    ) -> Result<Option<TransparentAddressMetadata>, Self::Error> {
+        self.read()?
+            .get_transparent_address_metadata(account_id, address)
+    }
+
+    #[cfg(feature = "transparent-inputs")]
+    fn get_known_ephemeral_addresses(
+        &self,
+        account_id: Self::AccountId,
+        index_range: Option<Range<u32>>,
+    ) -> Result<Vec<(TransparentAddress, TransparentAddressMetadata)>, Self::Error> {
+        self.read()?
+            .get_known_ephemeral_addresses(account_id, index_range)
+    }
+
+    #[cfg(feature = "transparent-inputs")]
+    fn find_account_for_ephemeral_address(
+        &self,
+        address: &TransparentAddress,
+    ) -> Result<Option<Self::AccountId>, Self::Error> {
+        self.read()?.find_account_for_ephemeral_address(address)
+    }
+
+    fn transaction_data_requests(&self) -> Result<Vec<TransactionDataRequest>, Self::Error> {
+        self.read()?.transaction_data_requests()
+    }
+}
+
+impl<P: consensus::Parameters> WalletWrite for SharedMemoryWalletDb<P> {
+    type UtxoRef = OutPoint;
+
+    fn create_account(
+        &mut self,
+        seed: &SecretVec<u8>,
+        birthday: &AccountBirthday,
+    ) -> Result<(Self::AccountId, UnifiedSpendingKey), Self::Error> {
+        self.write()?.create_account(seed, birthday)
+    }
+
+    fn import_account_hd(
+        &mut self,
+        seed: &SecretVec<u8>,
+        account_index: zip32::AccountId,
+        birthday: &AccountBirthday,
+    ) -> Result<(Self::Account, UnifiedSpendingKey), Self::Error> {
+        self.write()?
+            .import_account_hd(seed, account_index, birthday)
+    }
+
+    fn import_account_ufvk(
+        &mut self,
+        unified_key: &UnifiedFullViewingKey,
+        birthday: &AccountBirthday,
+        purpose: AccountPurpose,
+    ) -> Result<Self::Account, Self::Error> {
+        self.write()?
+            .import_account_ufvk(unified_key, birthday, purpose)
+    }
+
+    fn get_next_available_address(
+        &mut self,
+        account: Self::AccountId,
+        request: UnifiedAddressRequest,
+    ) -> Result<Option<UnifiedAddress>, Self::Error> {
+        self.write()?.get_next_available_address(account, request)
+    }
+
+    fn update_chain_tip(&mut self, tip_height: BlockHeight) -> Result<(), Self::Error> {
+        self.write()?.update_chain_tip(tip_height)
+    }
+
+    fn put_blocks(
+        &mut self,
+        from_state: &ChainState,
+        blocks: Vec<ScannedBlock<Self::AccountId>>,
+    ) -> Result<(), Self::Error> {
+        self.write()?.put_blocks(from_state, blocks)
+    }
+
+    fn put_received_transparent_utxo(
+        &mut self,
+        output: &WalletTransparentOutput,
+    ) -> Result<Self::UtxoRef, Self::Error> {
+        self.write()?.put_received_transparent_utxo(output)
+    }
+
+    fn store_decrypted_tx(
+        &mut self,
+        d_tx: DecryptedTransaction<Self::AccountId>,
+    ) -> Result<(), Self::Error> {
+        self.write()?.store_decrypted_tx(d_tx)
+    }
+
+    fn store_transactions_to_be_sent(
+        &mut self,
+        transactions: &[SentTransaction<Self::AccountId>],
+    ) -> Result<(), Self::Error> {
+        self.write()?.store_transactions_to_be_sent(transactions)
+    }
+
+    fn truncate_to_height(&mut self, max_height: BlockHeight) -> Result<BlockHeight, Self::Error> {
+        self.write()?.truncate_to_height(max_height)
+    }
+
+    fn set_transaction_status(
+        &mut self,
+        txid: TxId,
+        status: TransactionStatus,
+    ) -> Result<(), Self::Error> {
+        self.write()?.set_transaction_status(txid, status)
+    }
+
+    #[cfg(feature = "transparent-inputs")]
+    fn reserve_next_n_ephemeral_addresses(
+        &mut self,
+        account_id: Self::AccountId,
+        n: usize,
+    ) -> Result<Vec<(TransparentAddress, TransparentAddressMetadata)>, Self::Error> {
+        self.write()?
+            .reserve_next_n_ephemeral_addresses(account_id, n)
+    }
+}
+
+impl<P: consensus::Parameters> InputSource for SharedMemoryWalletDb<P> {
+    type Error = Error;
+    type AccountId = AccountId;
+    type NoteRef = NoteId;
+
+    fn get_spendable_note(
+        &self,
+        txid: &TxId,
+        protocol: ShieldedProtocol,
+        index: u32,
+    ) -> Result<Option<ReceivedNote<Self::NoteRef, Note>>, Self::Error> {
+        self.read()?.get_spendable_note(txid, protocol, index)
+    }
+
+    fn select_spendable_notes(
+        &self,
+        account: Self::AccountId,
+        target_value: Zatoshis,
+        sources: &[ShieldedProtocol],
+        anchor_height: BlockHeight,
+        exclude: &[Self::NoteRef],
+    ) -> Result<SpendableNotes<Self::NoteRef>, Self::Error> {
+        self.read()?
+            .select_spendable_notes(account, target_value, sources, anchor_height, exclude)
+    }
+
+    fn get_account_metadata(
+        &self,
+        account_id: Self::AccountId,
+        selector: &NoteFilter,
+        exclude: &[Self::NoteRef],
+    ) -> Result<AccountMeta, Self::Error> {
+        self.read()?
+            .get_account_metadata(account_id, selector, exclude)
+    }
+
+    #[cfg(feature = "transparent-inputs")]
+    fn get_unspent_transparent_output(
+        &self,
+        outpoint: &OutPoint,
+    ) -> Result<Option<WalletTransparentOutput>, Self::Error> {
+        self.read()?.get_unspent_transparent_output(outpoint)
+    }
+
+    #[cfg(feature = "transparent-inputs")]
+    fn get_spendable_transparent_outputs(
+        &self,
+        address: &TransparentAddress,
+        target_height: BlockHeight,
+        min_confirmations: u32,
+    ) -> Result<Vec<WalletTransparentOutput>, Self::Error> {
+        self.read()?
+            .get_spendable_transparent_outputs(address, target_height, min_confirmations)
+    }
+}
+
+impl<P: consensus::Parameters> WalletCommitmentTrees for SharedMemoryWalletDb<P> {
+    type Error = Error;
+    type SaplingShardStore<'a> = SharedShardStore<'a, sapling::Node>;
+
+    fn with_sapling_tree_mut<F, A, E>(&mut self, callback: F) -> Result<A, E>
+    where
+        for<'a> F: FnMut(
+            &'a mut ShardTree<
+                Self::SaplingShardStore<'a>,
+                { sapling::NOTE_COMMITMENT_TREE_DEPTH },
+                SAPLING_SHARD_HEIGHT,
+            >,
+        ) -> Result<A, E>,
+        E: From<ShardTreeError<Self::Error>>,
+    {
+        let mut wallet = self.write_trees()?;
+        let tree = &mut wallet.sapling_tree;
+        let max_checkpoints = tree.max_checkpoints();
+        callback(&mut ShardTree::new(
+            SharedShardStore(tree.store_mut()),
+            max_checkpoints,
+        ))
+    }
+
+    fn put_sapling_subtree_roots(
+        &mut self,
+        start_index: u64,
+        roots: &[CommitmentTreeRoot<sapling::Node>],
+    ) -> Result<(), ShardTreeError<Self::Error>> {
+        self.write_trees()?
+            .put_sapling_subtree_roots(start_index, roots)
+            .map_err(shared_tree_error)
+    }
+
+    #[cfg(feature = "orchard")]
+    type OrchardShardStore<'a> = SharedShardStore<'a, orchard::tree::MerkleHashOrchard>;
+
+    #[cfg(feature = "orchard")]
+    fn with_orchard_tree_mut<F, A, E>(&mut self, callback: F) -> Result<A, E>
+    where
+        for<'a> F: FnMut(
+            &'a mut ShardTree<
+                Self::OrchardShardStore<'a>,
+                { ORCHARD_SHARD_HEIGHT * 2 },
+                ORCHARD_SHARD_HEIGHT,
+            >,
+        ) -> Result<A, E>,
+        E: From<ShardTreeError<Self::Error>>,
+    {
+        let mut wallet = self.write_trees()?;
+        let tree = &mut wallet.orchard_tree;
+        let max_checkpoints = tree.max_checkpoints();
+        callback(&mut ShardTree::new(
+            SharedShardStore(tree.store_mut()),
+            max_checkpoints,
+        ))
+    }
+
+    #[cfg(feature = "orchard")]
+    fn put_orchard_subtree_roots(
+        &mut self,
+        start_index: u64,
+        roots: &[CommitmentTreeRoot<orchard::tree::MerkleHashOrchard>],
+    ) -> Result<(), ShardTreeError<Self::Error>> {
+        self.write_trees()?
+            .put_orchard_subtree_roots(start_index, roots)
+            .map_err(shared_tree_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicBool, Ordering},
+        thread,
+    };
+
+    use shardtree::error::ShardTreeError;
+    use zcash_client_backend::data_api::{
+        chain::{scan_cached_blocks, ChainState},
+        testing::{AddressType, TestBuilder},
+        WalletCommitmentTrees, WalletRead,
+    };
+    use zcash_primitives::{block::BlockHash, transaction::components::amount::NonNegativeAmount};
+
+    use super::SharedMemoryWalletDb;
+    use crate::{
+        error::Error,
+        testing::{test_state, TestMemDbFactory},
+        MemBlockCache,
+    };
+
+    fn assert_send_sync<T: Clone + Send + Sync>() {}
+
+    #[test]
+    fn blocks_can_be_scanned_while_balances_are_read() {
+        assert_send_sync::<SharedMemoryWalletDb<zcash_protocol::consensus::Network>>();
+
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let value = NonNegativeAmount::const_from_u64(50000);
+        let block_count = 20;
+        let (h, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        for _ in 1..block_count {
+            st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        }
+
+        // Scan the blocks into a shared copy of the wallet while polling its balance.
+        let shared = SharedMemoryWalletDb::new(st.wallet().clone_for_snapshot());
+        let scanned = AtomicBool::new(false);
+        thread::scope(|s| {
+            let mut writer = shared.clone();
+            let (network, cache, scanned) = (*st.network(), st.cache(), &scanned);
+            s.spawn(move || {
+                scan_cached_blocks(
+                    &network,
+                    cache,
+                    &mut writer,
+                    h,
+                    &ChainState::empty(h - 1, BlockHash([0; 32])),
+                    block_count,
+                )
+                .unwrap();
+                scanned.store(true, Ordering::SeqCst);
+            });
+
+            let reader = shared.clone();
+            s.spawn(move || {
+                let mut polls = 0;
+                while !scanned.load(Ordering::SeqCst) || polls == 0 {
+                    reader.get_wallet_summary(1).unwrap();
+                    polls += 1;
+                }
+            });
+        });
+
+        st.scan_cached_blocks(h, block_count);
+        assert_eq!(
+            shared.get_wallet_summary(1).unwrap(),
+            st.wallet().get_wallet_summary(1).unwrap()
+        );
+    }
+    #[test]
+    fn poisoned_lock_is_reported_as_an_error() {
+        let st = test_state();
+        let mut shared = SharedMemoryWalletDb::new(st.wallet().clone_for_snapshot());
+        let poisoner = shared.clone();
+        thread::spawn(move || {
+            let _guard = poisoner.write().unwrap();
+            panic!("poisoning the wallet lock");
+        })
+        .join()
+        .unwrap_err();
+
+        assert!(matches!(shared.chain_height(), Err(Error::LockPoisoned)));
+        assert!(matches!(
+            shared.put_sapling_subtree_roots(0, &[]),
+            Err(ShardTreeError::Storage(Error::LockPoisoned))
+        ));
+        assert!(matches!(
+            shared.with_sapling_tree_mut::<_, _, ShardTreeError<Error>>(|_| Ok(())),
+            Err(ShardTreeError::Storage(Error::LockPoisoned))
+        ));
+    }
+}
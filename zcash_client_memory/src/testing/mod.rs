@@ -1,4 +1,4 @@
-use std::convert::{identity, Infallible};
+use std::convert::identity;
 use std::fmt::Debug;
 
 use zcash_client_backend::data_api::InputSource;
@@ -16,6 +16,7 @@ use zcash_client_backend::{
 };
 use zcash_keys::address::Address;
 use zcash_primitives::transaction::components::amount::NonNegativeAmount;
+use zcash_protocol::memo::Memo;
 use zcash_protocol::value::ZatBalance;
 use zcash_protocol::ShieldedProtocol;
 
@@ -27,8 +28,18 @@ use zcash_primitives::transaction::TxId;
 use zcash_protocol::consensus::BlockHeight;
 use zcash_protocol::local_consensus::LocalNetwork;
 
-use crate::{Account, AccountId, Error, MemBlockCache, MemoryWalletDb, SentNoteId};
+use crate::{Account, AccountId, CacheGap, Error, MemBlockCache, MemoryWalletDb, SentNoteId};
 
+#[cfg(all(test, feature = "transparent-inputs"))]
+use zcash_primitives::{
+    consensus::BranchId,
+    transaction::{
+        components::{transparent, TxIn, TxOut},
+        Authorized, Transaction, TransactionData, TxVersion,
+    },
+};
+
+#[cfg(test)]
 pub mod pool;
 
 #[cfg(test)]
@@ -37,10 +48,14 @@ mod transparent;
 
 /// A test data store factory for in-memory databases
 /// Very simple implementation just creates a new MemoryWalletDb
-pub(crate) struct TestMemDbFactory;
+///
+/// This allows other crates to run the shared `zcash_client_backend` test suites against
+/// the in-memory wallet.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TestMemDbFactory;
 
 impl TestMemDbFactory {
-    pub(crate) fn new() -> Self {
+    pub fn new() -> Self {
         Self
     }
 }
@@ -58,7 +73,7 @@ impl DataStoreFactory for TestMemDbFactory {
 }
 
 impl TestCache for MemBlockCache {
-    type BsError = Infallible;
+    type BsError = CacheGap;
     type BlockSource = MemBlockCache;
     type InsertResult = ();
 
@@ -67,14 +82,127 @@ impl TestCache for MemBlockCache {
     }
 
     fn insert(&mut self, cb: &CompactBlock) -> Self::InsertResult {
-        self.0.write().unwrap().insert(cb.height(), cb.clone());
+        self.insert_block(cb.clone());
     }
 
     fn truncate_to_height(&mut self, height: BlockHeight) {
-        self.0.write().unwrap().retain(|k, _| *k <= height);
+        self.blocks.write().unwrap().retain(|k, _| *k <= height);
     }
 }
 
+/// The [`TestState`] used by the unit tests of this crate.
+#[cfg(test)]
+pub(crate) type MemTestState = TestState<MemBlockCache, MemoryWalletDb<LocalNetwork>, LocalNetwork>;
+
+/// Returns a [`TestState`] for an in-memory wallet with a block cache and a single account
+/// whose birthday is at Sapling activation.
+#[cfg(test)]
+pub(crate) fn test_state() -> MemTestState {
+    zcash_client_backend::data_api::testing::TestBuilder::new()
+        .with_data_store_factory(TestMemDbFactory::new())
+        .with_block_cache(MemBlockCache::new())
+        .with_account_from_sapling_activation(zcash_primitives::block::BlockHash([0; 32]))
+        .build()
+}
+
+/// Returns a transaction, created by a wallet that shares no keys with the one returned by
+/// [`test_state`], that makes the given payments.
+#[cfg(test)]
+pub(crate) fn external_tx(
+    payments: Vec<(
+        Address,
+        NonNegativeAmount,
+        Option<zcash_protocol::memo::MemoBytes>,
+    )>,
+) -> zcash_primitives::transaction::Transaction {
+    use zcash_client_backend::{
+        data_api::{
+            testing::{AddressType, TestBuilder},
+            wallet::input_selection::GreedyInputSelector,
+            Account as _,
+        },
+        fees::{standard, DustOutputPolicy, StandardFeeRule},
+        wallet::OvkPolicy,
+        zip321::{Payment, TransactionRequest},
+    };
+
+    let mut sender = TestBuilder::new()
+        .with_data_store_factory(TestMemDbFactory::new())
+        .with_block_cache(MemBlockCache::new())
+        .with_account_from_sapling_activation(zcash_primitives::block::BlockHash([0; 32]))
+        .set_account_index(zip32::AccountId::try_from(1).unwrap())
+        .build();
+    let account = sender.test_account().cloned().unwrap();
+    let dfvk = sender.test_account_sapling().unwrap().clone();
+    let (h, _, _) = sender.generate_next_block(
+        &dfvk,
+        AddressType::DefaultExternal,
+        NonNegativeAmount::const_from_u64(1_000_000),
+    );
+    sender.scan_cached_blocks(h, 1);
+
+    let network = *sender.network();
+    let request = TransactionRequest::new(
+        payments
+            .into_iter()
+            .map(|(to, amount, memo)| {
+                Payment::new(
+                    to.to_zcash_address(&network),
+                    amount,
+                    memo,
+                    None,
+                    None,
+                    vec![],
+                )
+                .unwrap()
+            })
+            .collect(),
+    )
+    .unwrap();
+    let change_strategy = standard::SingleOutputChangeStrategy::new(
+        StandardFeeRule::Zip317,
+        None,
+        ShieldedProtocol::Sapling,
+        DustOutputPolicy::default(),
+    );
+    let txid = sender
+        .spend(
+            &GreedyInputSelector::new(),
+            &change_strategy,
+            account.usk(),
+            request,
+            OvkPolicy::Sender,
+            std::num::NonZeroU32::new(1).unwrap(),
+        )
+        .unwrap()[0];
+
+    sender.wallet().get_transaction(txid).unwrap().unwrap()
+}
+
+/// Returns a fully transparent transaction with the given inputs and outputs.
+#[cfg(all(test, feature = "transparent-inputs"))]
+pub(crate) fn transparent_tx(
+    vin: Vec<TxIn<transparent::Authorized>>,
+    vout: Vec<TxOut>,
+) -> Transaction {
+    TransactionData::<Authorized>::from_parts(
+        TxVersion::Zip225,
+        BranchId::Nu5,
+        0,
+        BlockHeight::from_u32(0),
+        Some(transparent::Bundle {
+            vin,
+            vout,
+            authorization: transparent::Authorized,
+        }),
+        None,
+        None,
+        None,
+    )
+    .freeze()
+    .unwrap()
+}
+
 impl<P> Reset for MemoryWalletDb<P>
 where
     P: zcash_primitives::consensus::Parameters + Clone + Debug + PartialEq,
@@ -82,7 +210,8 @@ where
     type Handle = ();
 
     fn reset<C>(st: &mut TestState<C, Self, LocalNetwork>) -> Self::Handle {
-        let new_wallet = MemoryWalletDb::new(st.wallet().params.clone(), 100);
+        let new_wallet =
+            MemoryWalletDb::new(st.wallet().params.clone(), st.wallet().max_checkpoints);
         let _ = std::mem::replace(st.wallet_mut(), new_wallet);
     }
 }
@@ -113,15 +242,17 @@ where
                 } => {
                     #[cfg(feature = "transparent-inputs")]
                     {
-                        let account = self.get_account(receiving_account)?.unwrap();
+                        // The index is resolved from the receiving account's ephemeral
+                        // address records, as the address was reserved by that account.
+                        let account = self
+                            .get_account(receiving_account)?
+                            .ok_or(Error::AccountUnknown(receiving_account))?;
                         let (_addr, meta) = account
                             .ephemeral_addresses()?
                             .into_iter()
                             .find(|(addr, _)| addr == &ephemeral_address)
-                            .unwrap();
+                            .ok_or(Error::AddressNotRecognized(ephemeral_address))?;
                         Ok((
-                            // TODO: Use the ephemeral address index to look up the address
-                            // and find the correct index
                             note.value.into_u64(),
                             Some(Address::from(ephemeral_address)),
                             Some((
@@ -182,7 +313,8 @@ where
     }
 
     /// Returns the note IDs for shielded notes sent by the wallet in a particular
-    /// transaction.
+    /// transaction. Transparent outputs have no note ID, and are counted by
+    /// `get_tx_history` instead.
     fn get_sent_note_ids(
         &self,
         txid: &TxId,
@@ -214,6 +346,7 @@ where
         &self,
     ) -> Result<Vec<zcash_client_backend::data_api::testing::TransactionSummary<AccountId>>, Error>
     {
+        let max_scanned_height = self.scanned_heights.max_height();
         let mut history = self
             .tx_table
             .iter()
@@ -239,13 +372,7 @@ where
                     .sent_notes
                     .iter()
                     .filter(|(note_id, _)| note_id.txid() == txid)
-                    .filter(|(note_id, _)| {
-                        // use a join on the received notes table to detect which are change
-                        self.received_notes.iter().any(|received_note| {
-                            SentNoteId::from(received_note.note_id) == **note_id
-                                && !received_note.is_change
-                        })
-                    })
+                    .filter(|(note_id, _)| !self.is_change_output(note_id))
                     .collect::<Vec<_>>();
 
                 let received_txo = self
@@ -320,6 +447,17 @@ where
 
                 let has_change = received_notes.iter().any(|note| note.is_change);
 
+                // As in the sqlite backend, memos of received notes (including change) and of
+                // sent notes other than change are counted if they are not empty.
+                let memo_count = received_notes
+                    .iter()
+                    .filter(|note| note.memo != Memo::Empty)
+                    .count()
+                    + sent_notes
+                        .iter()
+                        .filter(|(_, note)| note.memo != Memo::Empty)
+                        .count();
+
                 Ok(
                     zcash_client_backend::data_api::testing::TransactionSummary::from_parts(
                         account_id,                                                                  // account_id
@@ -332,17 +470,22 @@ where
                         has_change,                            // has_change
                         sent_notes.len(),                      // sent_note_count (excluding change)
                         received_notes.iter().filter(|note| !note.is_change).count(), // received_note_count (excluding change)
-                        0,            // Unimplemented: memo_count
-                        false,        // Unimplemented: expired_unmined
+                        memo_count,                                                   // memo_count
+                        max_scanned_height.map_or(false, |h| tx.is_expired_unmined(h)), // expired_unmined
                         is_shielding, // is_shielding
                     ),
                 )
             })
             .collect::<Result<Vec<_>, Error>>()?;
-        history.sort_by(|a, b| {
-            b.mined_height()
-                .cmp(&a.mined_height())
-                .then(b.txid().cmp(&a.txid()))
+        // Order transactions by their position in the chain, most recent first, so that
+        // transactions mined in the same block are ordered deterministically.
+        history.sort_by_key(|summary| {
+            let tx_index = self
+                .tx_table
+                .get(&summary.txid())
+                .and_then(|tx| tx.mined_location())
+                .map(|(_, tx_index)| tx_index);
+            std::cmp::Reverse((summary.mined_height(), tx_index, summary.txid()))
         });
         Ok(history)
     }
@@ -388,6 +531,9 @@ where
 
         assert_eq!(self, &recovered_wallet);
 
+        // ensure the transaction table agrees with the locations observed while scanning
+        self.check_tx_locations().unwrap();
+
         // ensure the trees can be roundtripped
         use crate::wallet_commitment_trees::serialization::{tree_from_protobuf, tree_to_protobuf};
 
@@ -16,6 +16,13 @@ pub(crate) fn send_single_step_proposed_transfer<T: ShieldedPoolTester>() {
     )
 }
 
+pub(crate) fn send_with_multiple_change_outputs<T: ShieldedPoolTester>() {
+    zcash_client_backend::data_api::testing::pool::send_with_multiple_change_outputs::<T>(
+        TestMemDbFactory::new(),
+        MemBlockCache::new(),
+    )
+}
+
 #[cfg(feature = "transparent-inputs")]
 pub(crate) fn send_multi_step_proposed_transfer<T: ShieldedPoolTester>() {
     zcash_client_backend::data_api::testing::pool::send_multi_step_proposed_transfer::<T, _>(
@@ -24,8 +31,8 @@ pub(crate) fn send_multi_step_proposed_transfer<T: ShieldedPoolTester>() {
         |e, account_id, expected_bad_index| {
             matches!(
                 e,
-                crate::Error::ReachedGapLimit(acct, bad_index)
-                if acct == &account_id && bad_index == &expected_bad_index)
+                crate::Error::ReachedGapLimit { account, bad_index }
+                if account == &account_id && bad_index == &expected_bad_index)
         },
     )
 }
@@ -87,7 +94,6 @@ pub(crate) fn change_note_spends_succeed<T: ShieldedPoolTester>() {
     )
 }
 
-// TODO: Implement reset for memdb
 pub(crate) fn external_address_change_spends_detected_in_restore_from_seed<
     T: ShieldedPoolTester,
 >() {
@@ -188,6 +194,13 @@ pub(crate) fn data_db_truncation<T: ShieldedPoolTester>() {
     )
 }
 
+pub(crate) fn reorg_to_checkpoint<T: ShieldedPoolTester>() {
+    zcash_client_backend::data_api::testing::pool::reorg_to_checkpoint::<T, _, _>(
+        TestMemDbFactory::new(),
+        MemBlockCache::new(),
+    )
+}
+
 pub(crate) fn scan_cached_blocks_allows_blocks_out_of_order<T: ShieldedPoolTester>() {
     zcash_client_backend::data_api::testing::pool::scan_cached_blocks_allows_blocks_out_of_order::<T>(
         TestMemDbFactory::new(),
@@ -215,3 +228,10 @@ pub(crate) fn scan_cached_blocks_detects_spends_out_of_order<T: ShieldedPoolTest
         _,
     >(TestMemDbFactory::new(), MemBlockCache::new())
 }
+
+pub(crate) fn metadata_queries_exclude_unwanted_notes<T: ShieldedPoolTester>() {
+    zcash_client_backend::data_api::testing::pool::metadata_queries_exclude_unwanted_notes::<T, _, _>(
+        TestMemDbFactory::new(),
+        MemBlockCache::new(),
+    )
+}
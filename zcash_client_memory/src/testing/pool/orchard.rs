@@ -8,6 +8,11 @@ fn send_single_step_proposed_transfer() {
     testing::pool::send_single_step_proposed_transfer::<OrchardPoolTester>()
 }
 
+#[test]
+fn send_with_multiple_change_outputs() {
+    testing::pool::send_with_multiple_change_outputs::<OrchardPoolTester>()
+}
+
 #[test]
 #[cfg(feature = "transparent-inputs")]
 fn send_multi_step_proposed_transfer() {
@@ -131,6 +136,11 @@ fn data_db_truncation() {
     testing::pool::data_db_truncation::<OrchardPoolTester>()
 }
 
+#[test]
+fn reorg_to_checkpoint() {
+    testing::pool::reorg_to_checkpoint::<OrchardPoolTester>()
+}
+
 #[test]
 fn scan_cached_blocks_allows_blocks_out_of_order() {
     testing::pool::scan_cached_blocks_allows_blocks_out_of_order::<OrchardPoolTester>()
@@ -150,3 +160,8 @@ fn scan_cached_blocks_finds_change_notes() {
 fn scan_cached_blocks_detects_spends_out_of_order() {
     testing::pool::scan_cached_blocks_detects_spends_out_of_order::<OrchardPoolTester>()
 }
+
+#[test]
+fn metadata_queries_exclude_unwanted_notes() {
+    testing::pool::metadata_queries_exclude_unwanted_notes::<OrchardPoolTester>()
+}
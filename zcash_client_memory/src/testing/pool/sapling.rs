@@ -9,6 +9,11 @@ fn send_single_step_proposed_transfer() {
     testing::pool::send_single_step_proposed_transfer::<SaplingPoolTester>()
 }
 
+#[test]
+fn send_with_multiple_change_outputs() {
+    testing::pool::send_with_multiple_change_outputs::<SaplingPoolTester>()
+}
+
 #[test]
 #[cfg(feature = "transparent-inputs")]
 fn send_multi_step_proposed_transfer() {
@@ -132,6 +137,11 @@ fn data_db_truncation() {
     testing::pool::data_db_truncation::<SaplingPoolTester>()
 }
 
+#[test]
+fn reorg_to_checkpoint() {
+    testing::pool::reorg_to_checkpoint::<SaplingPoolTester>()
+}
+
 #[test]
 fn scan_cached_blocks_allows_blocks_out_of_order() {
     testing::pool::scan_cached_blocks_allows_blocks_out_of_order::<SaplingPoolTester>()
@@ -151,3 +161,8 @@ fn scan_cached_blocks_detects_change_notes() {
 fn scan_cached_blocks_detects_spends_out_of_order() {
     testing::pool::scan_cached_blocks_detects_spends_out_of_order::<SaplingPoolTester>()
 }
+
+#[test]
+fn metadata_queries_exclude_unwanted_notes() {
+    testing::pool::metadata_queries_exclude_unwanted_notes::<SaplingPoolTester>()
+}
@@ -14,3 +14,18 @@ fn transparent_balance_across_shielding() {
         MemBlockCache::new(),
     );
 }
+
+#[test]
+fn multiple_outputs_to_same_address() {
+    zcash_client_backend::data_api::testing::transparent::multiple_outputs_to_same_address(
+        TestMemDbFactory::new(),
+    );
+}
+
+#[test]
+fn ephemeral_address_gap_rewound_on_truncation() {
+    zcash_client_backend::data_api::testing::transparent::ephemeral_address_gap_rewound_on_truncation(
+        TestMemDbFactory::new(),
+        MemBlockCache::new(),
+    );
+}
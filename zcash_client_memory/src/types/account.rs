@@ -1,5 +1,5 @@
 use std::{
-    collections::{BTreeMap, BTreeSet},
+    collections::{btree_map::Entry, BTreeMap, BTreeSet},
     ops::{Deref, DerefMut},
 };
 
@@ -22,15 +22,54 @@ use zcash_primitives::legacy::keys::{
     AccountPubKey, EphemeralIvk, IncomingViewingKey, NonHardenedChildIndex, TransparentKeyScope,
 };
 use zcash_primitives::{legacy::TransparentAddress, transaction::TxId};
-use zcash_protocol::consensus::NetworkType;
+use zcash_protocol::consensus::{BlockHeight, NetworkType};
 use zip32::DiversifierIndex;
 
-use crate::error::Error;
+use crate::{error::Error, TransactionTable};
+
+const ACCOUNT_ID_PERSONALIZATION: &[u8; 16] = b"ZcashMemAcctId__";
 
 /// Internal representation of ID type for accounts. Will be unique for each account.
+///
+/// Account ids are derived from the source of the account, so that wallets to which the same
+/// accounts were added in a different order assign them the same ids.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default, PartialOrd, Ord)]
 pub struct AccountId(u32);
 
+impl AccountId {
+    /// Returns the preferred id of an account with the given source and viewing key. This is
+    /// derived from the seed fingerprint and ZIP 32 account index of derived accounts, and from
+    /// the encoding of the viewing key of imported accounts.
+    pub(crate) fn for_source(kind: &AccountSource, viewing_key: &UnifiedFullViewingKey) -> Self {
+        let mut state = blake2b_simd::Params::new()
+            .hash_length(4)
+            .personal(ACCOUNT_ID_PERSONALIZATION)
+            .to_state();
+        match kind {
+            AccountSource::Derived {
+                seed_fingerprint,
+                account_index,
+            } => {
+                state.update(&[0]);
+                state.update(&seed_fingerprint.to_bytes());
+                state.update(&u32::from(*account_index).to_le_bytes());
+            }
+            AccountSource::Imported { .. } => {
+                state.update(&[1]);
+                state.update(
+                    viewing_key
+                        .encode(&zcash_primitives::consensus::MainNetwork)
+                        .as_bytes(),
+                );
+            }
+        }
+        let hash = state.finalize();
+        AccountId(u32::from_le_bytes(
+            hash.as_bytes().try_into().expect("hash length is 4 bytes"),
+        ))
+    }
+}
+
 impl From<u32> for AccountId {
     fn from(id: u32) -> Self {
         AccountId(id)
@@ -57,19 +96,18 @@ impl ConditionallySelectable for AccountId {
 /// but we want to have control over the internal AccountId values. The account ids are unique.
 #[derive(Debug, Clone, PartialEq)]
 pub(crate) struct Accounts {
-    pub(crate) nonce: u32,
     pub(crate) accounts: BTreeMap<AccountId, Account>,
 }
 
 impl Accounts {
     pub(crate) fn new() -> Self {
         Self {
-            nonce: 0,
             accounts: BTreeMap::new(),
         }
     }
 
-    /// Creates a new account. The account id will be determined by the internal nonce.
+    /// Creates a new account. The account id is derived from the account source, see
+    /// [`AccountId::for_source`].
     /// Do not call this directly, use the `Wallet` methods instead.
     /// Otherwise the scan queue will not be correctly updated
     pub(crate) fn new_account(
@@ -78,8 +116,7 @@ impl Accounts {
         viewing_key: UnifiedFullViewingKey,
         birthday: AccountBirthday,
     ) -> Result<(AccountId, Account), Error> {
-        self.nonce += 1;
-        let account_id = AccountId(self.nonce);
+        let account_id = self.unused_account_id(AccountId::for_source(&kind, &viewing_key));
 
         let acc = Account::new(account_id, kind, viewing_key, birthday)?;
 
@@ -88,6 +125,17 @@ impl Accounts {
         Ok((account_id, acc))
     }
 
+    /// Returns the given account id if no account has it, or otherwise the first unused id
+    /// following it. Collisions between derived ids are rare, so in practice this is almost
+    /// always the preferred id.
+    pub(crate) fn unused_account_id(&self, preferred: AccountId) -> AccountId {
+        let mut account_id = preferred;
+        while self.accounts.contains_key(&account_id) {
+            account_id = AccountId(account_id.0.wrapping_add(1));
+        }
+        account_id
+    }
+
     pub(crate) fn get(&self, account_id: AccountId) -> Option<&Account> {
         self.accounts.get(&account_id)
     }
@@ -124,8 +172,10 @@ impl Accounts {
                 Ok(Some(id))
             } else {
                 for (account_id, account) in self.accounts.iter() {
-                    if account.get_legacy_transparent_address()?.is_some() {
-                        return Ok(Some(*account_id));
+                    if let Some((legacy_taddr, _)) = account.get_legacy_transparent_address()? {
+                        if &legacy_taddr == address {
+                            return Ok(Some(*account_id));
+                        }
                     }
                 }
                 Ok(None)
@@ -153,11 +203,12 @@ impl Accounts {
     #[cfg(feature = "transparent-inputs")]
     pub(crate) fn mark_ephemeral_address_as_seen(
         &mut self,
+        txns: &TransactionTable,
         address: &TransparentAddress,
         tx_id: TxId,
     ) -> Result<(), Error> {
         for (_, account) in self.accounts.iter_mut() {
-            account.mark_ephemeral_address_as_seen(address, tx_id)?
+            account.mark_ephemeral_address_as_seen(txns, address, tx_id)?
         }
         Ok(())
     }
@@ -208,6 +259,9 @@ pub struct Account {
     birthday: AccountBirthday,
     /// Stores diversified Unified Addresses that have been generated from accounts in the wallet.
     addresses: BTreeMap<DiversifierIndex, UnifiedAddress>,
+    /// The heights at which addresses of this account were first given out to a counterparty,
+    /// keyed by diversifier index.
+    exposed_addresses: BTreeMap<DiversifierIndex, BlockHeight>,
     pub(crate) ephemeral_addresses: BTreeMap<u32, EphemeralAddress>, // NonHardenedChildIndex (< 1 << 31)
     _notes: BTreeSet<NoteId>,
 }
@@ -224,6 +278,7 @@ impl PartialEq for Account {
                     .encode(&zcash_primitives::consensus::MainNetwork)
             && self.birthday == other.birthday
             && self.addresses == other.addresses
+            && self.exposed_addresses == other.exposed_addresses
             && self.ephemeral_addresses == other.ephemeral_addresses
             && self._notes == other._notes
     }
@@ -243,19 +298,12 @@ impl Account {
             birthday,
             ephemeral_addresses: BTreeMap::new(),
             addresses: BTreeMap::new(),
+            exposed_addresses: BTreeMap::new(),
             _notes: BTreeSet::new(),
         };
 
         // populate the addresses map with the default address
-        let ua_request = acc
-            .viewing_key
-            .to_unified_incoming_viewing_key()
-            .to_address_request()
-            .and_then(|ua_request| ua_request.intersect(&UnifiedAddressRequest::all().unwrap()))
-            .ok_or_else(|| {
-                Error::AddressGeneration(AddressGenerationError::ShieldedReceiverRequired)
-            })?;
-        let (ua, diversifier_index) = acc.default_address(ua_request)?;
+        let (ua, diversifier_index) = acc.default_address(acc.default_address_request()?)?;
         acc.addresses.insert(diversifier_index, ua);
         #[cfg(feature = "transparent-inputs")]
         acc.reserve_until(0)?;
@@ -266,6 +314,28 @@ impl Account {
         &self.addresses
     }
 
+    /// Returns the heights at which addresses of this account were first exposed, keyed by
+    /// diversifier index.
+    pub(crate) fn exposed_addresses(&self) -> &BTreeMap<DiversifierIndex, BlockHeight> {
+        &self.exposed_addresses
+    }
+
+    /// Records that the given address has been given out at the given height. If the address
+    /// has already been exposed, the earlier exposure height is retained.
+    pub(crate) fn mark_exposed(
+        &mut self,
+        address: &UnifiedAddress,
+        height: BlockHeight,
+    ) -> Result<(), Error> {
+        let index = self
+            .addresses
+            .iter()
+            .find_map(|(index, ua)| (ua == address).then_some(*index))
+            .ok_or(Error::UnifiedAddressNotRecognized(self.account_id))?;
+        self.exposed_addresses.entry(index).or_insert(height);
+        Ok(())
+    }
+
     pub fn select_receiving_address(
         &self,
         network: NetworkType,
@@ -290,23 +360,51 @@ impl Account {
         self.uivk().default_address(request)
     }
 
+    /// Returns the request for every receiver type that the account's viewing key supports.
+    fn default_address_request(&self) -> Result<UnifiedAddressRequest, Error> {
+        self.uivk()
+            .to_address_request()
+            .and_then(|ua_request| ua_request.intersect(&UnifiedAddressRequest::all().unwrap()))
+            .ok_or_else(|| {
+                Error::AddressGeneration(AddressGenerationError::ShieldedReceiverRequired)
+            })
+    }
+
     pub(crate) fn birthday(&self) -> &AccountBirthday {
         &self.birthday
     }
 
+    /// Returns the most recently generated Unified Address for the account, which is the one
+    /// at the highest diversifier index that has been recorded, along with that index.
+    ///
+    /// If no address has been recorded for the account, its default address is derived
+    /// instead. The derived address is not persisted; it will be recorded when the account
+    /// next generates an address.
     pub(crate) fn current_address(&self) -> Result<(UnifiedAddress, DiversifierIndex), Error> {
-        Ok(self
-            .addresses
-            .iter()
-            .last()
-            .map(|(diversifier_index, ua)| (ua.clone(), *diversifier_index))
-            .unwrap()) // can unwrap as the map is never empty
+        match self.addresses.last_key_value() {
+            Some((diversifier_index, ua)) => Ok((ua.clone(), *diversifier_index)),
+            None => Ok(self.default_address(self.default_address_request()?)?),
+        }
     }
 
     pub(crate) fn kind(&self) -> &AccountSource {
         &self.kind
     }
 
+    pub(crate) fn viewing_key(&self) -> &UnifiedFullViewingKey {
+        &self.viewing_key
+    }
+
+    pub(crate) fn set_account_id(&mut self, account_id: AccountId) {
+        self.account_id = account_id;
+    }
+
+    /// Generates and records the next Unified Address for the account, containing exactly
+    /// the receivers in `request`.
+    ///
+    /// The search starts at the diversifier index after the highest one recorded for the
+    /// account, and skips any index that cannot produce a valid Sapling receiver. Errors from
+    /// address generation are returned as [`Error::AddressGeneration`] without modification.
     pub(crate) fn next_available_address(
         &mut self,
         request: UnifiedAddressRequest,
@@ -314,8 +412,10 @@ impl Account {
         match self.ufvk() {
             Some(ufvk) => {
                 let search_from = self
-                    .current_address()
-                    .map(|(_, mut diversifier_index)| {
+                    .addresses
+                    .last_key_value()
+                    .map(|(diversifier_index, _)| {
+                        let mut diversifier_index = *diversifier_index;
                         diversifier_index.increment().map_err(|_| {
                             Error::AddressGeneration(
                                 AddressGenerationError::DiversifierSpaceExhausted,
@@ -332,6 +432,60 @@ impl Account {
         }
     }
 
+    /// Returns the Unified Address at the given diversifier index.
+    ///
+    /// If an address was previously generated at this index, the stored address is returned.
+    /// Otherwise the address is derived from the account's viewing key without being persisted.
+    /// Returns `None` if the index does not produce a valid address for the given request.
+    pub(crate) fn address_for_index(
+        &self,
+        index: DiversifierIndex,
+        request: UnifiedAddressRequest,
+    ) -> Result<Option<UnifiedAddress>, Error> {
+        if let Some(ua) = self.addresses.get(&index) {
+            return Ok(Some(ua.clone()));
+        }
+        match self.uivk().address(index, request) {
+            Ok(ua) => Ok(Some(ua)),
+            #[cfg(feature = "transparent-inputs")]
+            Err(AddressGenerationError::InvalidTransparentChildIndex(_)) => Ok(None),
+            Err(AddressGenerationError::InvalidSaplingDiversifierIndex(_)) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Adds the addresses known to another snapshot of this account that are not yet known
+    /// to this one.
+    pub(crate) fn merge(&mut self, other: Account) {
+        for (index, ua) in other.addresses {
+            self.addresses.entry(index).or_insert(ua);
+        }
+        for (index, height) in other.exposed_addresses {
+            match self.exposed_addresses.entry(index) {
+                Entry::Occupied(mut entry) => {
+                    if height < *entry.get() {
+                        entry.insert(height);
+                    }
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(height);
+                }
+            }
+        }
+        for (index, other_address) in other.ephemeral_addresses {
+            match self.ephemeral_addresses.entry(index) {
+                Entry::Occupied(mut entry) => {
+                    let address = entry.get_mut();
+                    address.used = address.used.or(other_address.used);
+                    address.seen = address.seen.or(other_address.seen);
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(other_address);
+                }
+            }
+        }
+    }
+
     pub(crate) fn account_id(&self) -> AccountId {
         self.account_id
     }
@@ -366,6 +520,68 @@ impl Account {
             })
             .collect())
     }
+
+    /// Returns the key scope and child index from which the given transparent address of
+    /// this account was derived, or `None` if the address does not belong to this account.
+    ///
+    /// Transparent receivers of the account's unified addresses, its legacy transparent
+    /// address, the internal (change) addresses within the gap limit, and its stored ephemeral
+    /// addresses (including those within the gap limit) are searched.
+    pub(crate) fn transparent_address_metadata(
+        &self,
+        address: &TransparentAddress,
+    ) -> Result<Option<TransparentAddressMetadata>, Error> {
+        if let Some(address_index) = self
+            .addresses()
+            .iter()
+            .find(|(_, ua)| ua.transparent() == Some(address))
+            .and_then(|(diversifier_index, _)| {
+                u32::try_from(*diversifier_index)
+                    .ok()
+                    .and_then(NonHardenedChildIndex::from_index)
+            })
+        {
+            return Ok(Some(TransparentAddressMetadata::new(
+                TransparentKeyScope::EXTERNAL,
+                address_index,
+            )));
+        }
+
+        if let Some((legacy_taddr, address_index)) = self.get_legacy_transparent_address()? {
+            if &legacy_taddr == address {
+                return Ok(Some(TransparentAddressMetadata::new(
+                    TransparentKeyScope::EXTERNAL,
+                    address_index,
+                )));
+            }
+        }
+
+        // The wallet never generates internal addresses, so only the gap window from the first
+        // index is searched.
+        if let Some(account_pubkey) = self.ufvk().and_then(|ufvk| ufvk.transparent()) {
+            let internal_ivk = account_pubkey.derive_internal_ivk()?;
+            if let Some(address_index) = (0..GAP_LIMIT)
+                .filter_map(NonHardenedChildIndex::from_index)
+                .find(|address_index| {
+                    internal_ivk
+                        .derive_address(*address_index)
+                        .map_or(false, |internal| &internal == address)
+                })
+            {
+                return Ok(Some(TransparentAddressMetadata::new(
+                    TransparentKeyScope::INTERNAL,
+                    address_index,
+                )));
+            }
+        }
+
+        Ok(self
+            .ephemeral_addresses()?
+            .into_iter()
+            .find(|(ephemeral_addr, _)| ephemeral_addr == address)
+            .map(|(_, metadata)| metadata))
+    }
+
     pub fn ephemeral_ivk(&self) -> Result<Option<EphemeralIvk>, Error> {
         self.viewing_key
             .transparent()
@@ -459,23 +675,30 @@ impl Account {
     #[cfg(feature = "transparent-inputs")]
     pub fn mark_ephemeral_address_as_seen(
         &mut self,
-        // txns: &TransactionTable,
+        txns: &TransactionTable,
         address: &TransparentAddress,
         tx_id: TxId,
     ) -> Result<(), Error> {
         for (idx, addr) in self.ephemeral_addresses.iter_mut() {
             if addr.address == *address {
-                // TODO: this
-                // Figure out which transaction was mined earlier: `tx_ref`, or any existing
-                // tx referenced by `seen_in_tx` for the given address. Prefer the existing
+                // Figure out which transaction was mined earlier: `tx_id`, or any existing
+                // tx referenced by `seen` for the given address. Prefer the existing
                 // reference in case of a tie or if both transactions are unmined.
                 // This slightly reduces the chance of unnecessarily reaching the gap limit
                 // too early in some corner cases (because the earlier transaction is less
                 // likely to be unmined).
-                //
-                // The query should always return a value if `tx_ref` is valid.
-
-                addr.mark_seen(tx_id);
+                let mined_height = |txid: &TxId| txns.get(txid).and_then(|tx| tx.mined_height());
+                let prefer_existing = addr.seen.is_some_and(|seen| {
+                    match (mined_height(&seen), mined_height(&tx_id)) {
+                        (Some(seen_height), Some(new_height)) => seen_height <= new_height,
+                        (Some(_), None) => true,
+                        (None, Some(_)) => false,
+                        (None, None) => true,
+                    }
+                });
+                if !prefer_existing {
+                    addr.mark_seen(tx_id);
+                }
                 // Maintain the invariant that the last `GAP_LIMIT` addresses are used and unseen.
                 let next_to_reserve = idx.checked_add(1).expect("ensured by constraint");
                 self.reserve_until(next_to_reserve)?;
@@ -521,7 +744,8 @@ mod serialization {
     impl From<Accounts> for proto::Accounts {
         fn from(accounts: Accounts) -> Self {
             Self {
-                account_nonce: accounts.nonce,
+                // Account ids are no longer assigned from a nonce.
+                account_nonce: 0,
                 accounts: accounts
                     .accounts
                     .into_values()
@@ -534,7 +758,6 @@ mod serialization {
     impl From<proto::Accounts> for Accounts {
         fn from(accounts: proto::Accounts) -> Self {
             Self {
-                nonce: accounts.account_nonce,
                 accounts: accounts
                     .accounts
                     .into_iter()
@@ -577,6 +800,15 @@ mod serialization {
                     .map(|(di, a)| proto::Address {
                         diversifier_index: di.as_bytes().to_vec(),
                         address: a.encode(&EncodingParams), // convention is to encode using mainnet encoding regardless of network
+                        request: Some(address_request(a)),
+                    })
+                    .collect(),
+                exposed_addresses: acc
+                    .exposed_addresses
+                    .iter()
+                    .map(|(di, height)| proto::AddressExposure {
+                        diversifier_index: di.as_bytes().to_vec(),
+                        exposed_at_height: (*height).into(),
                     })
                     .collect(),
                 #[cfg(feature = "transparent-inputs")]
@@ -627,12 +859,33 @@ mod serialization {
                     .addresses
                     .into_iter()
                     .map(|a| {
+                        let diversifier_index = DiversifierIndex::from(
+                            TryInto::<[u8; 11]>::try_into(a.diversifier_index)?,
+                        );
+                        let ua = UnifiedAddress::decode(&EncodingParams, &a.address)
+                            .map_err(Error::UfvkDecodeError)?;
+                        // Addresses written before requests were recorded have no request.
+                        if a.request
+                            .is_some_and(|request| request != address_request(&ua))
+                        {
+                            return Err(Error::CorruptedData(format!(
+                                "address at diversifier index {:?} does not have the receivers \
+                                 that were requested for it",
+                                diversifier_index
+                            )));
+                        }
+                        Ok((diversifier_index, ua))
+                    })
+                    .collect::<Result<_, Error>>()?,
+                exposed_addresses: acc
+                    .exposed_addresses
+                    .into_iter()
+                    .map(|e| {
                         Ok((
                             DiversifierIndex::from(TryInto::<[u8; 11]>::try_into(
-                                a.diversifier_index,
+                                e.diversifier_index,
                             )?),
-                            UnifiedAddress::decode(&EncodingParams, &a.address)
-                                .map_err(Error::UfvkDecodeError)?,
+                            BlockHeight::from(e.exposed_at_height),
                         ))
                     })
                     .collect::<Result<_, Error>>()?,
@@ -672,6 +925,16 @@ mod serialization {
         }
     }
 
+    /// Returns the request for exactly the receivers of the given address, which are the
+    /// receivers that were requested when it was generated.
+    fn address_request(ua: &UnifiedAddress) -> proto::AddressRequest {
+        proto::AddressRequest {
+            orchard: ua.has_orchard(),
+            sapling: ua.has_sapling(),
+            p2pkh: ua.has_transparent(),
+        }
+    }
+
     impl TryFrom<AccountBirthday> for proto::AccountBirthday {
         type Error = crate::Error;
         fn try_from(birthday: AccountBirthday) -> Result<Self, Self::Error> {
@@ -750,5 +1013,229 @@ mod serialization {
 
             assert_eq!(proto_acc, proto_acc2);
         }
+
+        #[test]
+        fn address_requests_roundtrip() {
+            let mut acc = Account::new(
+                AccountId(0),
+                AccountSource::Imported {
+                    purpose: AccountPurpose::Spending,
+                },
+                UnifiedFullViewingKey::decode(&EncodingParams, TEST_VK).unwrap(),
+                AccountBirthday::from_sapling_activation(
+                    &EncodingParams,
+                    BlockHash::from_slice(&[0; 32]),
+                ),
+            )
+            .unwrap();
+            let sapling_only = UnifiedAddressRequest::new(false, true, false).unwrap();
+            let ua = acc.next_available_address(sapling_only).unwrap().unwrap();
+
+            let proto_acc: proto::Account = acc.clone().into();
+            let recorded = proto_acc
+                .addresses
+                .iter()
+                .find(|a| a.address == ua.encode(&EncodingParams))
+                .and_then(|a| a.request)
+                .unwrap();
+            assert_eq!(
+                recorded,
+                proto::AddressRequest {
+                    orchard: false,
+                    sapling: true,
+                    p2pkh: false,
+                }
+            );
+            let acc2: Account = proto_acc.clone().try_into().unwrap();
+            assert_eq!(acc2.addresses(), acc.addresses());
+
+            // Addresses written without a request are accepted.
+            let mut legacy = proto_acc.clone();
+            for address in &mut legacy.addresses {
+                address.request = None;
+            }
+            let acc3: Account = legacy.try_into().unwrap();
+            assert_eq!(acc3.addresses(), acc.addresses());
+
+            // An address whose receivers do not match its request is rejected.
+            let mut corrupted = proto_acc;
+            for address in &mut corrupted.addresses {
+                if let Some(request) = &mut address.request {
+                    request.p2pkh = !request.p2pkh;
+                }
+            }
+            assert!(matches!(
+                Account::try_from(corrupted),
+                Err(Error::CorruptedData(_))
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use zcash_primitives::{block::BlockHash, consensus::Network::MainNetwork};
+
+    const TEST_VK: &str = "uview1tg6rpjgju2s2j37gkgjq79qrh5lvzr6e0ed3n4sf4hu5qd35vmsh7avl80xa6mx7ryqce9hztwaqwrdthetpy4pc0kce25x453hwcmax02p80pg5savlg865sft9reat07c5vlactr6l2pxtlqtqunt2j9gmvr8spcuzf07af80h5qmut38h0gvcfa9k4rwujacwwca9vu8jev7wq6c725huv8qjmhss3hdj2vh8cfxhpqcm2qzc34msyrfxk5u6dqttt4vv2mr0aajreww5yufpk0gn4xkfm888467k7v6fmw7syqq6cceu078yw8xja502jxr0jgum43lhvpzmf7eu5dmnn6cr6f7p43yw8znzgxg598mllewnx076hljlvynhzwn5es94yrv65tdg3utuz2u3sras0wfcq4adxwdvlk387d22g3q98t5z74quw2fa4wed32escx8dwh4mw35t4jwf35xyfxnu83mk5s4kw2glkgsshmxk";
+
+    fn test_account() -> Account {
+        Account::new(
+            AccountId(0),
+            AccountSource::Imported {
+                purpose: AccountPurpose::ViewOnly,
+            },
+            UnifiedFullViewingKey::decode(&MainNetwork, TEST_VK).unwrap(),
+            AccountBirthday::from_sapling_activation(&MainNetwork, BlockHash::from_slice(&[0; 32])),
+        )
+        .unwrap()
+    }
+
+    fn sapling_request() -> UnifiedAddressRequest {
+        UnifiedAddressRequest::new(false, true, false).unwrap()
+    }
+
+    #[test]
+    fn address_for_index_returns_stored_address() {
+        let acc = test_account();
+        let (index, stored) = acc
+            .addresses()
+            .iter()
+            .next()
+            .map(|(index, ua)| (*index, ua.clone()))
+            .unwrap();
+
+        assert_eq!(
+            acc.address_for_index(index, sapling_request()).unwrap(),
+            Some(stored)
+        );
+    }
+
+    #[test]
+    fn address_for_index_derives_without_persisting() {
+        let mut generated = test_account();
+        let ua = generated
+            .next_available_address(sapling_request())
+            .unwrap()
+            .unwrap();
+        let (_, index) = generated.current_address().unwrap();
+
+        // A fresh copy of the account has not stored this index, but derives the same address.
+        let acc = test_account();
+        assert!(!acc.addresses().contains_key(&index));
+        assert_eq!(
+            acc.address_for_index(index, sapling_request()).unwrap(),
+            Some(ua)
+        );
+        assert!(!acc.addresses().contains_key(&index));
+    }
+
+    #[test]
+    fn current_address_is_the_highest_generated_address() {
+        let mut acc = test_account();
+        let (default_ua, default_index) = acc.current_address().unwrap();
+        assert_eq!(acc.addresses().len(), 1);
+        assert_eq!(acc.addresses().get(&default_index), Some(&default_ua));
+
+        let first = acc
+            .next_available_address(sapling_request())
+            .unwrap()
+            .unwrap();
+        let second = acc
+            .next_available_address(sapling_request())
+            .unwrap()
+            .unwrap();
+        assert_ne!(first, second);
+
+        let (current, index) = acc.current_address().unwrap();
+        assert_eq!(current, second);
+        assert_eq!(acc.addresses().keys().next_back(), Some(&index));
+    }
+
+    #[test]
+    fn current_address_defaults_when_no_address_is_recorded() {
+        let mut acc = test_account();
+        let (default_ua, default_index) = acc.current_address().unwrap();
+        acc.addresses.clear();
+
+        // The default address is derived without being recorded.
+        assert_eq!(
+            acc.current_address().unwrap(),
+            (default_ua.clone(), default_index)
+        );
+        assert!(acc.addresses().is_empty());
+
+        // The first generated address is the default address.
+        let ua = acc
+            .next_available_address(acc.default_address_request().unwrap())
+            .unwrap()
+            .unwrap();
+        assert_eq!(ua, default_ua);
+        assert_eq!(acc.addresses().keys().collect::<Vec<_>>(), [&default_index]);
+    }
+
+    #[test]
+    fn generated_addresses_honor_mixed_requests() {
+        use std::collections::HashSet;
+        use zcash_keys::encoding::AddressCodec;
+
+        let mut acc = test_account();
+        let dfvk = acc.ufvk().and_then(|ufvk| ufvk.sapling()).unwrap().clone();
+        let (default_ua, _) = acc.current_address().unwrap();
+
+        // Every combination of receivers that the account's viewing key can provide.
+        let requests = [false, true]
+            .into_iter()
+            .flat_map(|orchard| [false, true].map(|sapling| (orchard, sapling)))
+            .flat_map(|(orchard, sapling)| [false, true].map(|p2pkh| (orchard, sapling, p2pkh)))
+            .filter(|(orchard, sapling, p2pkh)| {
+                (*orchard || *sapling)
+                    && (!orchard || default_ua.has_orchard())
+                    && (!sapling || default_ua.has_sapling())
+                    && (!p2pkh || default_ua.has_transparent())
+            })
+            .collect::<Vec<_>>();
+
+        let mut encodings = HashSet::from([default_ua.encode(&MainNetwork)]);
+        let mut last_index = acc.current_address().unwrap().1;
+        for i in 0..50 {
+            let receivers = requests[i % requests.len()];
+            let (orchard, sapling, p2pkh) = receivers;
+            let request = UnifiedAddressRequest::new(orchard, sapling, p2pkh).unwrap();
+            let ua = acc.next_available_address(request).unwrap().unwrap();
+            let (current, index) = acc.current_address().unwrap();
+            assert_eq!(current, ua);
+
+            assert_eq!(
+                (ua.has_orchard(), ua.has_sapling(), ua.has_transparent()),
+                receivers
+            );
+            assert!(index > last_index);
+            if sapling {
+                assert!(dfvk.address(index).is_some());
+            }
+            last_index = index;
+
+            let encoded = ua.encode(&MainNetwork);
+            assert_eq!(UnifiedAddress::decode(&MainNetwork, &encoded).unwrap(), ua);
+            assert!(encodings.insert(encoded));
+        }
+        assert_eq!(acc.addresses().len(), 51);
+    }
+
+    #[test]
+    fn address_for_index_invalid_index_is_none() {
+        let acc = test_account();
+        let dfvk = acc.ufvk().and_then(|ufvk| ufvk.sapling()).unwrap().clone();
+        let invalid = (0u32..)
+            .map(DiversifierIndex::from)
+            .find(|j| dfvk.address(*j).is_none())
+            .unwrap();
+
+        assert_eq!(
+            acc.address_for_index(invalid, sapling_request()).unwrap(),
+            None
+        );
     }
 }
@@ -40,6 +40,7 @@ impl Ord for MemoryWalletBlock {
 
 mod serialization {
     use super::*;
+    use crate::decode_memo;
     use crate::error::{Error, Result};
     use crate::proto::memwallet as proto;
     use crate::read_optional;
@@ -93,24 +94,8 @@ mod serialization {
                     .memos
                     .into_iter()
                     .map(|memo| {
-                        let note_id = read_optional!(memo, note_id)?;
-                        Ok((
-                            NoteId::new(
-                                read_optional!(note_id.clone(), tx_id)?.try_into()?,
-                                match note_id.pool() {
-                                    proto::PoolType::ShieldedSapling => {
-                                        zcash_protocol::ShieldedProtocol::Sapling
-                                    }
-                                    #[cfg(feature = "orchard")]
-                                    proto::PoolType::ShieldedOrchard => {
-                                        zcash_protocol::ShieldedProtocol::Orchard
-                                    }
-                                    _ => unreachable!(),
-                                },
-                                note_id.output_index as u16,
-                            ),
-                            MemoBytes::from_bytes(&memo.memo)?,
-                        ))
+                        let note_id = NoteId::try_from(read_optional!(memo, note_id)?)?;
+                        Ok((note_id, decode_memo(Some(note_id), &memo.memo)?.encode()))
                     })
                     .collect::<Result<_>>()?,
                 sapling_commitment_tree_size: block.sapling_commitment_tree_size,
@@ -151,5 +136,38 @@ mod serialization {
 
             assert_eq!(block, recovered);
         }
+
+        #[test]
+        fn block_memo_with_out_of_range_output_index_is_rejected() {
+            let block = MemoryWalletBlock {
+                height: 1.into(),
+                hash: BlockHash([0; 32]),
+                block_time: 2,
+                _transactions: HashSet::new(),
+                _memos: HashMap::new(),
+                sapling_commitment_tree_size: Some(3),
+                sapling_output_count: Some(4),
+                #[cfg(feature = "orchard")]
+                orchard_commitment_tree_size: Some(5),
+                #[cfg(feature = "orchard")]
+                orchard_action_count: Some(6),
+            };
+
+            let mut proto: proto::WalletBlock = block.into();
+            proto.memos.push(proto::Memo {
+                note_id: Some(proto::NoteId {
+                    tx_id: Some(proto::TxId { hash: vec![7; 32] }),
+                    pool: proto::PoolType::ShieldedSapling.into(),
+                    output_index: u32::from(u16::MAX) + 1,
+                }),
+                memo: MemoBytes::empty().as_slice().to_vec(),
+            });
+
+            assert!(matches!(
+                MemoryWalletBlock::try_from(proto),
+                Err(Error::OutputIndexOutOfRange { txid, index })
+                    if txid == TxId::from_bytes([7; 32]) && index == usize::from(u16::MAX) + 1
+            ));
+        }
     }
 }
@@ -1,18 +1,102 @@
-use std::{collections::VecDeque, ops::Deref};
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    ops::Deref,
+};
 
 use zcash_client_backend::data_api::TransactionDataRequest;
-use zcash_primitives::transaction::TxId;
+use zcash_primitives::{
+    consensus::BlockHeight,
+    transaction::{components::OutPoint, TxId},
+};
 
-#[derive(Debug, Default, PartialEq)]
-pub struct TransactionDataRequestQueue(pub(crate) VecDeque<TransactionDataRequest>);
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TransactionDataRequestQueue(
+    pub(crate) VecDeque<TransactionDataRequest>,
+    /// The chain height at which requests for each transaction were first queued, used to
+    /// expire requests that are never answered.
+    pub(crate) BTreeMap<TxId, BlockHeight>,
+    /// The received transparent outputs whose spends must be searched for. These are keyed
+    /// by outpoint rather than by address, so that a transaction paying the same address more
+    /// than once has each of its outputs searched for until that output is known to be spent.
+    pub(crate) BTreeSet<OutPoint>,
+);
 
 impl TransactionDataRequestQueue {
     pub fn new() -> Self {
-        Self(VecDeque::new())
+        Self(VecDeque::new(), BTreeMap::new(), BTreeSet::new())
     }
 
-    pub fn queue_status_retrieval(&mut self, txid: &TxId) {
-        self.0.push_back(TransactionDataRequest::GetStatus(*txid));
+    /// Queues a search for the transaction that spends the given received transparent output.
+    pub fn queue_spend_detection(&mut self, outpoint: OutPoint) {
+        self.2.insert(outpoint);
+    }
+
+    /// Removes the search for the transaction that spends the given output, returning whether
+    /// one was queued.
+    pub fn remove_spend_detection(&mut self, outpoint: &OutPoint) -> bool {
+        self.2.remove(outpoint)
+    }
+
+    /// Returns the received transparent outputs whose spends are being searched for.
+    pub fn spend_detections(&self) -> impl Iterator<Item = &OutPoint> {
+        self.2.iter()
+    }
+
+    /// Queues a request for the mined status of the given transaction, unless one is already
+    /// queued.
+    pub fn queue_status_retrieval(&mut self, txid: &TxId, chain_height: Option<BlockHeight>) {
+        let request = TransactionDataRequest::GetStatus(*txid);
+        if !self.0.contains(&request) {
+            self.0.push_back(request);
+        }
+        if let Some(chain_height) = chain_height {
+            self.1.entry(*txid).or_insert(chain_height);
+        }
+    }
+
+    /// Queues a request for the complete data of the given transaction, unless one is
+    /// already queued.
+    pub fn queue_enhancement(&mut self, txid: &TxId, chain_height: Option<BlockHeight>) {
+        let request = TransactionDataRequest::Enhancement(*txid);
+        if !self.0.contains(&request) {
+            self.0.push_back(request);
+        }
+        if let Some(chain_height) = chain_height {
+            self.1.entry(*txid).or_insert(chain_height);
+        }
+    }
+
+    /// Removes the request for the complete data of the given transaction, returning whether
+    /// one was queued. The queue height of the transaction is forgotten if it has no other
+    /// pending request.
+    pub fn remove_enhancement(&mut self, txid: &TxId) -> bool {
+        let request = TransactionDataRequest::Enhancement(*txid);
+        let queued = self.0.contains(&request);
+        if queued {
+            self.0.retain(|r| r != &request);
+            if !self.0.contains(&TransactionDataRequest::GetStatus(*txid)) {
+                self.1.remove(txid);
+            }
+        }
+        queued
+    }
+
+    /// Removes the status and enhancement requests for the given transaction, along with its
+    /// queue height.
+    pub fn remove_transaction_requests(&mut self, txid: &TxId) {
+        self.0.retain(|request| match request {
+            TransactionDataRequest::GetStatus(request_txid)
+            | TransactionDataRequest::Enhancement(request_txid) => request_txid != txid,
+            #[cfg(feature = "transparent-inputs")]
+            TransactionDataRequest::SpendsFromAddress { .. } => true,
+        });
+        self.1.remove(txid);
+    }
+
+    /// Returns the chain height at which a request for the given transaction was first
+    /// queued, if known.
+    pub fn queued_at(&self, txid: &TxId) -> Option<BlockHeight> {
+        self.1.get(txid).copied()
     }
 }
 
@@ -41,6 +125,7 @@ mod serialization {
                     address: None,
                     block_range_start: None,
                     block_range_end: None,
+                    queued_at_height: None,
                 },
                 TransactionDataRequest::Enhancement(txid) => Self {
                     request_type: proto::TransactionDataRequestType::Enhancement as i32,
@@ -48,6 +133,7 @@ mod serialization {
                     address: None,
                     block_range_start: None,
                     block_range_end: None,
+                    queued_at_height: None,
                 },
                 #[cfg(feature = "transparent-inputs")]
                 TransactionDataRequest::SpendsFromAddress {
@@ -60,6 +146,7 @@ mod serialization {
                     address: Some(address.encode(&EncodingParams).as_bytes().to_vec()),
                     block_range_start: Some(block_range_start.into()),
                     block_range_end: block_range_end.map(Into::into),
+                    queued_at_height: None,
                 },
             }
         }
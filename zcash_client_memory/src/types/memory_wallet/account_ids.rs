@@ -0,0 +1,147 @@
+use zcash_client_backend::wallet::Recipient;
+
+use super::*;
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    /// Reassigns the id of every account to the id derived from its source, and updates every
+    /// table that refers to an account accordingly.
+    ///
+    /// This is used to migrate snapshots written before account ids were derived from account
+    /// sources. Accounts are reassigned in the order of their previous ids, so that collisions
+    /// are resolved in the same way that they were when the accounts were added.
+    pub(crate) fn remap_account_ids(&mut self) {
+        let mut remapped = BTreeMap::new();
+        for (old_id, mut account) in std::mem::take(&mut self.accounts.accounts) {
+            let new_id = self
+                .accounts
+                .unused_account_id(AccountId::for_source(account.kind(), account.viewing_key()));
+            account.set_account_id(new_id);
+            self.accounts.insert(new_id, account);
+            remapped.insert(old_id, new_id);
+        }
+        let remap = |id: &mut AccountId| {
+            if let Some(new_id) = remapped.get(id) {
+                *id = *new_id;
+            }
+        };
+
+        for note in self.received_notes.0.iter_mut() {
+            remap(&mut note.account_id);
+        }
+        for note in self.sent_notes.0.values_mut() {
+            remap(&mut note.from_account_id);
+            match &mut note.to {
+                Recipient::External(..) => {}
+                Recipient::EphemeralTransparent {
+                    receiving_account, ..
+                }
+                | Recipient::InternalAccount {
+                    receiving_account, ..
+                } => remap(receiving_account),
+            }
+        }
+        for output in self.transparent_received_outputs.0.values_mut() {
+            remap(&mut output.account_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::SecretVec;
+    use zcash_client_backend::{
+        data_api::{
+            testing::{AddressType, TestBuilder},
+            Account as _, AccountBirthday, AccountPurpose, WalletRead, WalletWrite,
+        },
+        keys::UnifiedSpendingKey,
+    };
+    use zcash_primitives::{
+        block::BlockHash, consensus::Network, transaction::components::amount::NonNegativeAmount,
+    };
+
+    use crate::{
+        proto::memwallet as proto, testing::TestMemDbFactory, MemBlockCache, MemoryWalletDb,
+    };
+
+    #[cfg(feature = "hd-derivation")]
+    #[test]
+    fn account_ids_do_not_depend_on_insertion_order() {
+        let params = Network::TestNetwork;
+        let birthday = AccountBirthday::from_sapling_activation(&params, BlockHash([0; 32]));
+        let seed = SecretVec::new(vec![1u8; 32]);
+        let imported = UnifiedSpendingKey::from_seed(&params, &[2u8; 32], zip32::AccountId::ZERO)
+            .unwrap()
+            .to_unified_full_viewing_key();
+        let index = zip32::AccountId::try_from(3).unwrap();
+
+        let mut derived_first = MemoryWalletDb::new(params, 100);
+        let (derived_account, _) = derived_first
+            .import_account_hd(&seed, index, &birthday)
+            .unwrap();
+        let imported_account = derived_first
+            .import_account_ufvk(&imported, &birthday, AccountPurpose::ViewOnly)
+            .unwrap();
+
+        let mut imported_first = MemoryWalletDb::new(params, 100);
+        assert_eq!(
+            imported_first
+                .import_account_ufvk(&imported, &birthday, AccountPurpose::ViewOnly)
+                .unwrap()
+                .id(),
+            imported_account.id()
+        );
+        assert_eq!(
+            imported_first
+                .import_account_hd(&seed, index, &birthday)
+                .unwrap()
+                .0
+                .id(),
+            derived_account.id()
+        );
+
+        let normalized = |wallet: &MemoryWalletDb<Network>| proto::MemoryWallet {
+            provenance: None,
+            ..proto::MemoryWallet::from(wallet)
+        };
+        assert_eq!(normalized(&derived_first), normalized(&imported_first));
+        assert_eq!(
+            derived_first.get_account_ids().unwrap(),
+            imported_first.get_account_ids().unwrap()
+        );
+    }
+
+    #[test]
+    fn version_1_snapshots_are_migrated() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let (h, _, _) = st.generate_next_block(
+            &dfvk,
+            AddressType::DefaultExternal,
+            NonNegativeAmount::const_from_u64(10000),
+        );
+        st.scan_cached_blocks(h, 1);
+        let account_id = st.test_account().unwrap().id();
+
+        // Rewrite the snapshot as a version 1 wallet would have written it, with the account
+        // id assigned from the account nonce.
+        let mut snapshot = proto::MemoryWallet::from(st.wallet());
+        snapshot.version = 1;
+        let accounts = snapshot.accounts.as_mut().unwrap();
+        accounts.account_nonce = 1;
+        accounts.accounts[0].account_id = 1;
+        for note in snapshot.received_note_table.iter_mut() {
+            note.account_id = 1;
+        }
+
+        let restored =
+            MemoryWalletDb::new_from_proto(snapshot, st.wallet().params.clone(), 100).unwrap();
+        assert_eq!(restored.get_account_ids().unwrap(), vec![account_id]);
+        assert_eq!(restored.accounts, st.wallet().accounts);
+        assert_eq!(restored.received_notes, st.wallet().received_notes);
+    }
+}
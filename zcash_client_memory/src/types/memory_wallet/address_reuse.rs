@@ -0,0 +1,179 @@
+use zcash_client_backend::wallet::Note;
+
+use super::*;
+
+/// An exposed address of an account that has received funds in more than one transaction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AddressReuse {
+    diversifier_index: DiversifierIndex,
+    address: UnifiedAddress,
+    exposed_at_height: BlockHeight,
+    txids: Vec<TxId>,
+}
+
+impl AddressReuse {
+    /// Returns the diversifier index of the reused address.
+    pub fn diversifier_index(&self) -> DiversifierIndex {
+        self.diversifier_index
+    }
+
+    /// Returns the reused address.
+    pub fn address(&self) -> &UnifiedAddress {
+        &self.address
+    }
+
+    /// Returns the height at which the address was first exposed.
+    pub fn exposed_at_height(&self) -> BlockHeight {
+        self.exposed_at_height
+    }
+
+    /// Returns the IDs of the transactions in which the address received funds, in ascending
+    /// order.
+    pub fn txids(&self) -> &[TxId] {
+        &self.txids
+    }
+}
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    /// Records that the given address of the account has been given out to a counterparty.
+    ///
+    /// The address must be one that was generated by the wallet for this account. The exposure
+    /// is recorded at the current chain tip height, or at the account's birthday height if the
+    /// chain tip is not yet known. Marking an address that has already been exposed has no
+    /// effect.
+    pub fn mark_address_exposed(
+        &mut self,
+        account: AccountId,
+        address: &UnifiedAddress,
+    ) -> Result<(), Error> {
+        let chain_height = self.chain_height()?;
+        let account = self
+            .accounts
+            .get_mut(account)
+            .ok_or(Error::AccountUnknown(account))?;
+        let height = chain_height.unwrap_or_else(|| account.birthday().height());
+        account.mark_exposed(address, height)
+    }
+
+    /// Returns the exposed addresses of the given account that have received funds in more
+    /// than one transaction, ordered by diversifier index.
+    ///
+    /// Both shielded notes and transparent outputs received by the wallet are considered.
+    pub fn address_reuse_report(&self, account: AccountId) -> Vec<AddressReuse> {
+        let account = match self.accounts.get(account) {
+            Some(account) => account,
+            None => return vec![],
+        };
+
+        account
+            .exposed_addresses()
+            .iter()
+            .filter_map(|(index, exposed_at_height)| {
+                let address = account.addresses().get(index)?;
+                let txids = self.receiving_txids(account.id(), address);
+                (txids.len() > 1).then(|| AddressReuse {
+                    diversifier_index: *index,
+                    address: address.clone(),
+                    exposed_at_height: *exposed_at_height,
+                    txids: txids.into_iter().collect(),
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the IDs of the transactions in which the account received funds at any of the
+    /// receivers of the given address.
+    fn receiving_txids(&self, account_id: AccountId, address: &UnifiedAddress) -> BTreeSet<TxId> {
+        let shielded = self
+            .received_notes
+            .iter()
+            .filter(|note| note.account_id() == account_id)
+            .filter(|note| match &note.note {
+                Note::Sapling(note) => address.sapling() == Some(&note.recipient()),
+                #[cfg(feature = "orchard")]
+                Note::Orchard(note) => address.orchard() == Some(&note.recipient()),
+            })
+            .map(|note| note.txid());
+
+        let transparent = self
+            .transparent_received_outputs
+            .0
+            .values()
+            .filter(|output| {
+                output.account_id == account_id && address.transparent() == Some(&output.address)
+            })
+            .map(|output| output.transaction_id);
+
+        shielded.chain(transparent).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zcash_client_backend::data_api::{
+        testing::{AddressType, TestBuilder},
+        Account as _, WalletRead,
+    };
+    use zcash_primitives::{block::BlockHash, transaction::components::amount::NonNegativeAmount};
+
+    use crate::{testing::TestMemDbFactory, MemBlockCache};
+
+    #[test]
+    fn reuse_of_exposed_address_is_reported() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let account_id = st.test_account().unwrap().id();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+
+        let address = st
+            .wallet()
+            .get_current_address(account_id)
+            .unwrap()
+            .unwrap();
+        st.wallet_mut()
+            .mark_address_exposed(account_id, &address)
+            .unwrap();
+        // Exposing the address again does not change the recorded exposure.
+        st.wallet_mut()
+            .mark_address_exposed(account_id, &address)
+            .unwrap();
+
+        let value = NonNegativeAmount::const_from_u64(50000);
+        let (h, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        st.scan_cached_blocks(h, 2);
+
+        let report = st.wallet().address_reuse_report(account_id);
+        assert_eq!(report.len(), 1);
+        assert_eq!(report[0].address(), &address);
+        assert_eq!(report[0].txids().len(), 2);
+
+        // The exposure survives a round trip through serialization.
+        let mut buf = vec![];
+        st.wallet().encode(&mut buf).unwrap();
+        let decoded =
+            crate::MemoryWalletDb::decode_new(&buf[..], st.wallet().params().clone(), 100).unwrap();
+        assert_eq!(decoded.address_reuse_report(account_id), report);
+    }
+
+    #[test]
+    fn unexposed_address_is_not_reported() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let account_id = st.test_account().unwrap().id();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+
+        let value = NonNegativeAmount::const_from_u64(50000);
+        let (h, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        st.scan_cached_blocks(h, 2);
+
+        assert!(st.wallet().address_reuse_report(account_id).is_empty());
+    }
+}
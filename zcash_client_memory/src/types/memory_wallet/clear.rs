@@ -0,0 +1,147 @@
+use super::*;
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    /// Discards all of the wallet's state that was derived from the chain, so that the chain
+    /// can be rescanned from the account birthdays.
+    ///
+    /// This removes the wallet's blocks, transactions, notes, transparent outputs, note
+    /// commitment trees, and pending transaction data requests. Accounts and their addresses
+    /// are retained, and the birthday frontier of each account is reinserted into the note
+    /// commitment trees. If the chain tip is known, the scan queue is reseeded so that every
+    /// block from the wallet birthday to the chain tip will be scanned; otherwise, it will be
+    /// reseeded by the next call to [`WalletWrite::update_chain_tip`].
+    ///
+    /// Unlike constructing a new wallet, this preserves the wallet's configuration.
+    pub fn clear(&mut self) -> Result<(), Error> {
+        let chain_tip = self.chain_height()?;
+
+        self.blocks.clear();
+        self.sapling_tree = ShardTree::new(
+            MemoryShardStore::empty(),
+            tree_checkpoint_limit(self.max_checkpoints),
+        );
+        self.sapling_tree_shard_end_heights.clear();
+        #[cfg(feature = "orchard")]
+        {
+            self.orchard_tree = ShardTree::new(
+                MemoryShardStore::empty(),
+                tree_checkpoint_limit(self.max_checkpoints),
+            );
+            self.orchard_tree_shard_end_heights.clear();
+        }
+        self.tx_table = TransactionTable::new();
+        self.received_notes = ReceivedNoteTable::new();
+        self.sent_notes = SentNoteTable::new();
+        self.nullifiers = NullifierMap::new();
+        self.tx_locator = TxLocatorMap::new();
+        self.received_note_spends = ReceievedNoteSpends::new();
+        self.transparent_received_outputs = TransparentReceivedOutputs::new();
+        self.transparent_received_output_spends = TransparentReceivedOutputSpends::new();
+        self.transparent_spend_map = TransparentSpendCache::new();
+        self.transaction_data_request_queue = TransactionDataRequestQueue::new();
+        self.purged_transactions.clear();
+        self.scan_queue = ScanQueue::new();
+        self.in_progress_scan = None;
+        self.scanned_heights = ScannedHeights::new();
+
+        let mut birthdays = self
+            .accounts
+            .iter()
+            .map(|(_, account)| account.birthday().clone())
+            .collect::<Vec<_>>();
+        birthdays.sort_by_key(|birthday| birthday.height());
+        for birthday in &birthdays {
+            self.seed_birthday(birthday, chain_tip)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zcash_client_backend::data_api::{
+        testing::{AddressType, TestBuilder},
+        WalletRead,
+    };
+    use zcash_primitives::{block::BlockHash, transaction::components::amount::NonNegativeAmount};
+
+    use crate::{testing::TestMemDbFactory, MemBlockCache, MemoryWalletDb};
+
+    #[test]
+    fn rescanning_after_clear_reproduces_balances() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let value = NonNegativeAmount::const_from_u64(50000);
+        let (h, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        st.generate_next_block(&dfvk, AddressType::Internal, value);
+        st.generate_empty_block();
+        st.scan_cached_blocks(h, 3);
+
+        let summary = |wallet: &MemoryWalletDb<_>| wallet.get_wallet_summary(1).unwrap();
+        let expected = summary(st.wallet());
+        assert!(expected.is_some());
+        let snapshot = st.wallet().clone_for_snapshot();
+
+        st.wallet_mut().set_request_expiry(5);
+        st.wallet_mut().clear().unwrap();
+        let wallet = st.wallet();
+        assert_eq!(wallet.block_max_scanned().unwrap(), None);
+        assert!(wallet.get_tx_history().unwrap().is_empty());
+        assert_eq!(
+            wallet.get_wallet_birthday().unwrap(),
+            Some(st.sapling_activation_height())
+        );
+        assert_eq!(wallet.chain_height().unwrap(), Some(h + 2));
+        assert_eq!(wallet.request_expiry(), 5);
+        wallet.validate_scan_queue().unwrap();
+
+        // The snapshot is unaffected by clearing the wallet that it was taken from.
+        assert_eq!(summary(&snapshot), expected);
+
+        st.scan_cached_blocks(h, 3);
+        assert_eq!(summary(st.wallet()), expected);
+    }
+
+    #[test]
+    fn snapshots_can_be_scanned_independently() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let value = NonNegativeAmount::const_from_u64(50000);
+        let (h, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        st.scan_cached_blocks(h, 1);
+
+        let mut snapshot = st.wallet().clone_for_snapshot();
+        assert!(&snapshot == st.wallet());
+
+        // Scanning a further block into the wallet does not change the snapshot.
+        let (h2, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        st.scan_cached_blocks(h2, 1);
+        assert!(&snapshot != st.wallet());
+        assert_eq!(
+            snapshot
+                .block_max_scanned()
+                .unwrap()
+                .unwrap()
+                .block_height(),
+            h
+        );
+
+        // The snapshot's note commitment trees were copied, so the same block can be scanned
+        // into it to reach the same state as the wallet.
+        std::mem::swap(st.wallet_mut(), &mut snapshot);
+        st.scan_cached_blocks(h2, 1);
+        assert_eq!(
+            st.wallet().get_wallet_summary(1).unwrap(),
+            snapshot.get_wallet_summary(1).unwrap()
+        );
+    }
+}
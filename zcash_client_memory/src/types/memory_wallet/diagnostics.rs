@@ -0,0 +1,273 @@
+use zcash_client_backend::wallet::{Note, ReceivedNote as WalletReceivedNote};
+
+use super::*;
+
+/// Summary statistics about the contents and history of a [`MemoryWalletDb`].
+///
+/// This is unstable, and intended only for diagnostics.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiagnosticStats {
+    account_count: usize,
+    block_count: usize,
+    transaction_count: usize,
+    received_note_count: usize,
+    sent_note_count: usize,
+    reorg_stats: ReorgStats,
+}
+
+impl DiagnosticStats {
+    /// Returns the number of accounts in the wallet.
+    pub fn account_count(&self) -> usize {
+        self.account_count
+    }
+
+    /// Returns the number of scanned blocks stored by the wallet.
+    pub fn block_count(&self) -> usize {
+        self.block_count
+    }
+
+    /// Returns the number of transactions stored by the wallet.
+    pub fn transaction_count(&self) -> usize {
+        self.transaction_count
+    }
+
+    /// Returns the number of shielded notes received by the wallet.
+    pub fn received_note_count(&self) -> usize {
+        self.received_note_count
+    }
+
+    /// Returns the number of outputs sent by the wallet.
+    pub fn sent_note_count(&self) -> usize {
+        self.sent_note_count
+    }
+
+    /// Returns statistics about the truncations of the wallet caused by chain reorganizations.
+    pub fn reorg_stats(&self) -> &ReorgStats {
+        &self.reorg_stats
+    }
+}
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    /// Returns summary statistics about the contents and history of the wallet.
+    ///
+    /// This is unstable, and intended only for diagnostics.
+    pub fn diagnostic_stats(&self) -> DiagnosticStats {
+        DiagnosticStats {
+            account_count: self.accounts.len(),
+            block_count: self.blocks.len(),
+            transaction_count: self.tx_table.len(),
+            received_note_count: self.received_notes.len(),
+            sent_note_count: self.sent_notes.len(),
+            reorg_stats: self.reorg_stats.clone(),
+        }
+    }
+
+    /// Returns at most `limit` of the notes received by the wallet in the given pool, skipping
+    /// the first `offset`. Notes are returned in the order in which the wallet stored them,
+    /// whether or not they have been spent.
+    ///
+    /// This is unstable, and intended only for diagnostics.
+    pub fn diagnostic_notes(
+        &self,
+        protocol: ShieldedProtocol,
+        offset: usize,
+        limit: usize,
+    ) -> Vec<WalletReceivedNote<NoteId, Note>> {
+        self.received_notes
+            .iter()
+            .filter(|note| note.note.protocol() == protocol)
+            .skip(offset)
+            .take(limit)
+            .cloned()
+            .map(Into::into)
+            .collect()
+    }
+
+    /// Returns the height and tree position of at most `limit` of the checkpoints of the
+    /// given pool's note commitment tree, starting from the lowest height.
+    ///
+    /// This is unstable, and intended only for diagnostics.
+    pub fn diagnostic_checkpoint_history(
+        &self,
+        protocol: ShieldedProtocol,
+        limit: usize,
+    ) -> Result<Vec<(BlockHeight, Option<Position>)>, Error> {
+        let mut checkpoints = Vec::new();
+
+        match protocol {
+            ShieldedProtocol::Sapling => {
+                self.sapling_tree
+                    .store()
+                    .for_each_checkpoint(limit, |id, cp| {
+                        checkpoints.push((*id, cp.position()));
+                        Ok(())
+                    })?;
+            }
+            #[cfg(feature = "orchard")]
+            ShieldedProtocol::Orchard => {
+                self.orchard_tree
+                    .store()
+                    .for_each_checkpoint(limit, |id, cp| {
+                        checkpoints.push((*id, cp.position()));
+                        Ok(())
+                    })?;
+            }
+            #[cfg(not(feature = "orchard"))]
+            ShieldedProtocol::Orchard => {
+                self.unsupported_protocol_data("checkpoint history of the Orchard tree")?;
+            }
+        }
+
+        checkpoints.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        Ok(checkpoints)
+    }
+
+    /// Returns the ids of the shielded notes in the given pool that were sent by the wallet in
+    /// the given transaction. Transparent outputs have no note id, and are not included.
+    ///
+    /// This is unstable, and intended only for diagnostics.
+    pub fn diagnostic_sent_note_ids(&self, txid: &TxId, protocol: ShieldedProtocol) -> Vec<NoteId> {
+        self.sent_notes
+            .iter()
+            .filter_map(|(id, _)| match id {
+                SentNoteId::Shielded(id) if id.txid() == txid && id.protocol() == protocol => {
+                    Some(*id)
+                }
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, num::NonZeroU32};
+
+    use sapling::zip32::ExtendedSpendingKey;
+    use zcash_client_backend::{
+        data_api::{
+            testing::{AddressType, TestBuilder},
+            Account as _, WalletTest,
+        },
+        fees::StandardFeeRule,
+        wallet::OvkPolicy,
+    };
+    use zcash_keys::address::Address;
+    use zcash_primitives::{block::BlockHash, transaction::components::amount::NonNegativeAmount};
+    use zcash_protocol::ShieldedProtocol;
+
+    use crate::{testing::TestMemDbFactory, MemBlockCache};
+
+    #[test]
+    fn diagnostics_match_wallet_test() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let (h, _, _) = st.generate_next_block(
+            &dfvk,
+            AddressType::DefaultExternal,
+            NonNegativeAmount::const_from_u64(50000),
+        );
+        for _ in 0..4 {
+            st.generate_next_block(
+                &dfvk,
+                AddressType::DefaultExternal,
+                NonNegativeAmount::const_from_u64(10000),
+            );
+        }
+        st.scan_cached_blocks(h, 5);
+
+        let wallet = st.wallet();
+        let stats = wallet.diagnostic_stats();
+        assert_eq!(stats.account_count(), 1);
+        assert_eq!(stats.block_count(), 5);
+        assert_eq!(stats.received_note_count(), 5);
+        assert_eq!(stats.reorg_stats(), wallet.reorg_stats());
+
+        let protocol = ShieldedProtocol::Sapling;
+        let notes = WalletTest::get_notes(wallet, protocol).unwrap();
+        assert_eq!(notes.len(), 5);
+        assert_eq!(wallet.diagnostic_notes(protocol, 0, usize::MAX), notes);
+        assert_eq!(
+            [
+                wallet.diagnostic_notes(protocol, 0, 2),
+                wallet.diagnostic_notes(protocol, 2, 2),
+                wallet.diagnostic_notes(protocol, 4, 2),
+            ]
+            .concat(),
+            notes
+        );
+
+        let checkpoints = WalletTest::get_checkpoint_history(wallet, &protocol).unwrap();
+        assert!(!checkpoints.is_empty());
+        assert_eq!(
+            wallet
+                .diagnostic_checkpoint_history(protocol, usize::MAX)
+                .unwrap(),
+            checkpoints
+        );
+        assert_eq!(
+            wallet.diagnostic_checkpoint_history(protocol, 1).unwrap(),
+            checkpoints[..1]
+        );
+
+        let account = st.test_account().cloned().unwrap();
+        let not_our_key = ExtendedSpendingKey::master(&[]).to_diversifiable_full_viewing_key();
+        let proposal = st
+            .propose_standard_transfer::<Infallible>(
+                account.id(),
+                StandardFeeRule::Zip317,
+                NonZeroU32::new(1).unwrap(),
+                &Address::Sapling(not_our_key.default_address().1),
+                NonNegativeAmount::const_from_u64(20000),
+                None,
+                None,
+                protocol,
+            )
+            .unwrap();
+        let txid = st
+            .create_proposed_transactions::<Infallible, _, Infallible>(
+                account.usk(),
+                OvkPolicy::Sender,
+                &proposal,
+            )
+            .unwrap()[0];
+
+        let wallet = st.wallet();
+        let sent_note_ids = WalletTest::get_sent_note_ids(wallet, &txid, protocol).unwrap();
+        assert!(!sent_note_ids.is_empty());
+        assert_eq!(
+            wallet.diagnostic_sent_note_ids(&txid, protocol),
+            sent_note_ids
+        );
+    }
+
+    #[cfg(not(feature = "orchard"))]
+    #[test]
+    fn orchard_checkpoint_history_is_rejected_in_strict_mode() {
+        use zcash_primitives::consensus::Network;
+
+        use crate::{error::Error, MemoryWalletDb};
+
+        let mut wallet = MemoryWalletDb::new(Network::TestNetwork, 100);
+        assert_eq!(
+            wallet
+                .diagnostic_checkpoint_history(ShieldedProtocol::Orchard, usize::MAX)
+                .unwrap(),
+            vec![]
+        );
+
+        wallet.set_strict(true);
+        assert!(matches!(
+            wallet.diagnostic_checkpoint_history(ShieldedProtocol::Orchard, usize::MAX),
+            Err(Error::UnsupportedProtocolData { .. })
+        ));
+        assert!(wallet
+            .diagnostic_checkpoint_history(ShieldedProtocol::Sapling, usize::MAX)
+            .is_ok());
+    }
+}
@@ -0,0 +1,144 @@
+use super::*;
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    /// Queues a [`TransactionDataRequest::Enhancement`] request for each of the given
+    /// transactions for which the wallet has a transaction table entry but not the raw
+    /// transaction data.
+    ///
+    /// Scanning a compact block only reveals the nullifiers and note commitments of a
+    /// transaction, so the full transaction must be fetched to recover its memos and any
+    /// outputs that the wallet cannot detect by trial decryption. A request is queued at most
+    /// once per transaction, however many times the transaction is scanned, and is removed
+    /// when the transaction data is stored with [`WalletWrite::store_decrypted_tx`].
+    ///
+    /// [`TransactionDataRequest::Enhancement`]: zcash_client_backend::data_api::TransactionDataRequest::Enhancement
+    /// [`WalletWrite::store_decrypted_tx`]: zcash_client_backend::data_api::WalletWrite::store_decrypted_tx
+    pub(crate) fn queue_missing_enhancements<'a>(
+        &mut self,
+        txids: impl IntoIterator<Item = &'a TxId>,
+    ) -> Result<(), Error> {
+        let chain_height = self.chain_height()?;
+        for txid in txids {
+            if self.tx_table.get(txid).is_some_and(|tx| tx.raw().is_none()) {
+                self.transaction_data_request_queue
+                    .queue_enhancement(txid, chain_height);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, num::NonZeroU32, str::FromStr};
+
+    use sapling::zip32::ExtendedSpendingKey;
+    use zcash_client_backend::{
+        data_api::{
+            testing::{AddressType, WalletTest},
+            wallet::decrypt_and_store_transaction,
+            Account as _, TransactionDataRequest, WalletRead,
+        },
+        fees::StandardFeeRule,
+        wallet::OvkPolicy,
+    };
+    use zcash_keys::address::Address;
+    use zcash_primitives::transaction::components::amount::NonNegativeAmount;
+    use zcash_protocol::{
+        memo::{Memo, MemoBytes},
+        ShieldedProtocol,
+    };
+
+    use crate::{
+        testing::{external_tx, test_state},
+        MemoryWalletDb,
+    };
+
+    #[test]
+    fn scanned_transactions_without_raw_data_are_enhanced() {
+        let mut st = test_state();
+        let account = st.test_account().cloned().unwrap();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let (h, _, _) = st.generate_next_block(
+            &dfvk,
+            AddressType::DefaultExternal,
+            NonNegativeAmount::const_from_u64(60000),
+        );
+        let enhancements = |wallet: &MemoryWalletDb<_>, txid| {
+            wallet
+                .transaction_data_requests()
+                .unwrap()
+                .into_iter()
+                .filter(|request| *request == TransactionDataRequest::Enhancement(txid))
+                .count()
+        };
+
+        // A received note is only known from its compact output, so the full transaction is
+        // requested, however many times its block is scanned.
+        st.scan_cached_blocks(h, 1);
+        let received_txid = st.wallet().get_tx_history().unwrap()[0].txid();
+        assert_eq!(enhancements(st.wallet(), received_txid), 1);
+        st.scan_cached_blocks(h, 1);
+        assert_eq!(enhancements(st.wallet(), received_txid), 1);
+
+        // A transaction created by the wallet already has its raw data when it is scanned.
+        let not_our_key = ExtendedSpendingKey::master(&[]).to_diversifiable_full_viewing_key();
+        let proposal = st
+            .propose_standard_transfer::<Infallible>(
+                account.id(),
+                StandardFeeRule::Zip317,
+                NonZeroU32::new(1).unwrap(),
+                &Address::Sapling(not_our_key.default_address().1),
+                NonNegativeAmount::const_from_u64(10000),
+                None,
+                None,
+                ShieldedProtocol::Sapling,
+            )
+            .unwrap();
+        let sent_txid = st
+            .create_proposed_transactions::<Infallible, _, Infallible>(
+                account.usk(),
+                OvkPolicy::Sender,
+                &proposal,
+            )
+            .unwrap()[0];
+        let (spent_height, _) = st.generate_next_block_including(sent_txid);
+        st.scan_cached_blocks(spent_height, 1);
+        assert_eq!(enhancements(st.wallet(), sent_txid), 0);
+        assert_eq!(enhancements(st.wallet(), received_txid), 1);
+
+        // Storing the data of a received transaction answers its request and recovers the
+        // memo that the compact output of its note omits.
+        let memo = Memo::from_str("for the wallet").unwrap();
+        let tx = external_tx(vec![(
+            Address::Sapling(dfvk.default_address().1),
+            NonNegativeAmount::const_from_u64(20000),
+            Some(MemoBytes::from(&memo)),
+        )]);
+        let (h, _) = st.generate_next_block_from_tx(0, &tx);
+        st.scan_cached_blocks(h, 1);
+        assert_eq!(enhancements(st.wallet(), tx.txid()), 1);
+        let scanned = st
+            .wallet()
+            .received_notes
+            .iter()
+            .find(|note| note.txid == tx.txid())
+            .cloned()
+            .unwrap();
+        assert_eq!(scanned.memo, Memo::Empty);
+
+        let network = *st.network();
+        decrypt_and_store_transaction(&network, st.wallet_mut(), &tx, Some(h)).unwrap();
+        assert_eq!(enhancements(st.wallet(), tx.txid()), 0);
+        assert_eq!(enhancements(st.wallet(), received_txid), 1);
+        assert_eq!(st.wallet().get_memo(scanned.note_id).unwrap(), Some(memo));
+
+        // The note keeps what scanning learned about it.
+        let enhanced = st.wallet().get_received_note(scanned.note_id).unwrap();
+        assert_eq!(enhanced.nf, scanned.nf);
+        assert_eq!(
+            enhanced.commitment_tree_position,
+            scanned.commitment_tree_position
+        );
+    }
+}
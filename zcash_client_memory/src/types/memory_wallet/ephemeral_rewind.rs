@@ -0,0 +1,112 @@
+use super::*;
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    /// Reverts the observations of ephemeral addresses in the given transactions, which have
+    /// been un-mined by a truncation to the given height.
+    ///
+    /// An observation in a transaction that is not mined does not extend the range of addresses
+    /// that are safe to reserve, and if the new chain does not include the transaction it must
+    /// not prevent the address from being observed in another. The `seen` reference of each such
+    /// address is therefore cleared, and [`Self::first_unsafe_index`] returns to the bound implied
+    /// by the remaining mined observations. A transaction created by this wallet remains pending
+    /// after the truncation, so the `used` reference is retained and the address continues to be
+    /// treated as seen in that transaction.
+    ///
+    /// Each transaction whose observation is cleared is queued for enhancement, so that the
+    /// address is marked as seen again if the transaction is mined in the new chain.
+    pub(crate) fn rewind_ephemeral_addresses(
+        &mut self,
+        unmined: &[TxId],
+        truncation_height: BlockHeight,
+    ) {
+        let mut requeue = BTreeSet::new();
+        for account in self.accounts.values_mut() {
+            for address in account.ephemeral_addresses.values_mut() {
+                if let Some(seen) = address.seen.filter(|txid| unmined.contains(txid)) {
+                    if address.used != Some(seen) {
+                        address.seen = address.used;
+                        requeue.insert(seen);
+                    }
+                }
+            }
+        }
+
+        for txid in requeue {
+            tracing::debug!(
+                "Requeueing transaction {} for ephemeral address detection",
+                txid
+            );
+            self.transaction_data_request_queue
+                .queue_enhancement(&txid, Some(truncation_height));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sapling::zip32::ExtendedSpendingKey;
+    use zcash_client_backend::data_api::{
+        testing::{AddressType, TestBuilder},
+        Account as _, TransactionDataRequest, WalletRead, WalletWrite,
+    };
+    use zcash_primitives::{
+        block::BlockHash,
+        transaction::{components::amount::NonNegativeAmount, TxId},
+    };
+
+    use crate::{testing::TestMemDbFactory, MemBlockCache};
+
+    #[test]
+    fn used_address_keeps_pending_transaction() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let account_id = st.test_account().unwrap().id();
+        let not_our_key = ExtendedSpendingKey::master(&[]).to_diversifiable_full_viewing_key();
+        let value = NonNegativeAmount::const_from_u64(10000);
+        let (h, _, _) = st.generate_next_block(&not_our_key, AddressType::DefaultExternal, value);
+        st.generate_next_block(&not_our_key, AddressType::DefaultExternal, value);
+        st.scan_cached_blocks(h, 2);
+
+        let address = st
+            .wallet_mut()
+            .reserve_next_n_ephemeral_addresses(account_id, 1)
+            .unwrap()[0]
+            .0;
+        let used_txid = TxId::from_bytes([1; 32]);
+        let seen_txid = TxId::from_bytes([2; 32]);
+        {
+            let wallet = st.wallet_mut();
+            for txid in [used_txid, seen_txid] {
+                wallet.tx_table.put_tx_partial(&txid, &None, Some(h + 1));
+            }
+            let ephemeral = wallet
+                .accounts
+                .get_mut(account_id)
+                .unwrap()
+                .ephemeral_addresses
+                .get_mut(&0)
+                .unwrap();
+            assert_eq!(ephemeral.address, address);
+            ephemeral.used = Some(used_txid);
+            ephemeral.seen = Some(seen_txid);
+        }
+
+        st.truncate_to_height(h);
+
+        let wallet = st.wallet();
+        let ephemeral = &wallet.accounts.get(account_id).unwrap().ephemeral_addresses[&0];
+        assert_eq!(ephemeral.used, Some(used_txid));
+        assert_eq!(ephemeral.seen, Some(used_txid));
+        assert!(wallet
+            .transaction_data_requests()
+            .unwrap()
+            .contains(&TransactionDataRequest::Enhancement(seen_txid)));
+        assert!(!wallet
+            .transaction_data_requests()
+            .unwrap()
+            .contains(&TransactionDataRequest::Enhancement(used_txid)));
+    }
+}
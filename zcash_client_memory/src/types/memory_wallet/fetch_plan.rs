@@ -0,0 +1,105 @@
+use zcash_client_backend::data_api::scanning::fetch_plan::{self, FetchPlan};
+
+use super::*;
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    /// Returns the order in which the wallet's suggested scan ranges and pending transaction
+    /// data requests should be fetched, with scan ranges split into batches of at most
+    /// `max_blocks_per_batch` blocks.
+    ///
+    /// See [`FetchPlan`] for the ordering guarantees. This does not modify the wallet.
+    pub fn fetch_plan(&self, max_blocks_per_batch: NonZeroU32) -> Result<FetchPlan, Error> {
+        fetch_plan::fetch_plan(self, max_blocks_per_batch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+
+    use zcash_client_backend::data_api::{
+        scanning::{fetch_plan::FetchAction, ScanPriority, ScanRange},
+        TransactionDataRequest, TransactionStatus,
+    };
+    use zcash_primitives::{
+        consensus::{BlockHeight, BranchId, Network},
+        transaction::{Authorized, Transaction, TransactionData, TxVersion},
+    };
+
+    use crate::{types::scanning::ScanQueue, MemoryWalletDb};
+
+    fn tx(expiry_height: u32) -> Transaction {
+        TransactionData::<Authorized>::from_parts(
+            TxVersion::Zip225,
+            BranchId::Nu5,
+            0,
+            BlockHeight::from_u32(expiry_height),
+            None,
+            None,
+            None,
+            None,
+        )
+        .freeze()
+        .unwrap()
+    }
+
+    #[test]
+    fn fetch_plan_orders_requests_after_their_ranges() {
+        let mut wallet = MemoryWalletDb::new(Network::TestNetwork, 100);
+        let height = BlockHeight::from_u32;
+        wallet.scan_queue = ScanQueue(vec![
+            (height(1000), height(1010), ScanPriority::Historic),
+            (height(1010), height(1020), ScanPriority::Scanned),
+            (height(1020), height(1025), ScanPriority::ChainTip),
+        ]);
+
+        // One transaction mined in each of the historic and scanned ranges, and one that is
+        // not known to have been mined.
+        let mined_historic = tx(1);
+        let mined_scanned = tx(2);
+        let unmined = tx(3);
+        for (tx, mined_height) in [
+            (&mined_historic, Some(height(1005))),
+            (&mined_scanned, Some(height(1015))),
+            (&unmined, None),
+        ] {
+            wallet.tx_table.put_tx_data(tx, None, None);
+            if let Some(mined_height) = mined_height {
+                wallet
+                    .tx_table
+                    .set_transaction_status(&tx.txid(), TransactionStatus::Mined(mined_height))
+                    .unwrap();
+            }
+        }
+        for request in [
+            TransactionDataRequest::Enhancement(mined_historic.txid()),
+            TransactionDataRequest::GetStatus(unmined.txid()),
+            TransactionDataRequest::Enhancement(mined_scanned.txid()),
+        ] {
+            wallet.transaction_data_request_queue.0.push_back(request);
+        }
+
+        let before = crate::proto::memwallet::MemoryWallet::from(&wallet);
+        let plan = wallet.fetch_plan(NonZeroU32::new(8).unwrap()).unwrap();
+        assert_eq!(crate::proto::memwallet::MemoryWallet::from(&wallet), before);
+        assert_eq!(
+            wallet.fetch_plan(NonZeroU32::new(8).unwrap()).unwrap(),
+            plan
+        );
+
+        let scan = |start, end, priority| {
+            FetchAction::Scan(ScanRange::from_parts(height(start)..height(end), priority))
+        };
+        assert_eq!(
+            plan.actions(),
+            &[
+                FetchAction::GetStatus(vec![unmined.txid()]),
+                FetchAction::Enhancement(vec![mined_scanned.txid()]),
+                scan(1020, 1025, ScanPriority::ChainTip),
+                scan(1000, 1008, ScanPriority::Historic),
+                FetchAction::Enhancement(vec![mined_historic.txid()]),
+                scan(1008, 1010, ScanPriority::Historic),
+            ]
+        );
+    }
+}
@@ -0,0 +1,265 @@
+use zcash_client_backend::data_api::TransactionDataRequest;
+
+use super::*;
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    /// Removes a transparent output that was erroneously recorded as having been received by
+    /// the wallet, such as an output reported by a misbehaving light wallet server or one
+    /// imported for an address that does not belong to the wallet.
+    ///
+    /// The received output is removed along with any record of its spend and any pending
+    /// request to search for its spend. Transparent balances are computed from the received
+    /// outputs, so they no longer include the output once it has been removed.
+    ///
+    /// Returns [`Error::TransparentOutputSpentByWalletTx`] without modifying the wallet if the
+    /// output is spent by a transaction that was created by this wallet; removing the output
+    /// would leave that transaction's inputs unaccounted for, so such a transaction must be
+    /// purged first. Spends by transactions that the wallet did not create are removed along
+    /// with the output.
+    ///
+    /// Returns whether any record of the output was removed.
+    pub fn forget_transparent_utxo(&mut self, outpoint: &OutPoint) -> Result<bool, Error> {
+        let spending_txids = self
+            .transparent_received_output_spends
+            .get(outpoint)
+            .into_iter()
+            .chain(
+                self.transparent_spend_map
+                    .iter()
+                    .filter(|(_, spent)| spent == outpoint)
+                    .map(|(txid, _)| txid),
+            );
+        for txid in spending_txids {
+            if self
+                .tx_table
+                .get(txid)
+                .map_or(false, |tx| tx.is_wallet_created())
+            {
+                return Err(Error::TransparentOutputSpentByWalletTx {
+                    outpoint: outpoint.clone(),
+                    txid: *txid,
+                });
+            }
+        }
+
+        let removed_output = self.transparent_received_outputs.0.remove(outpoint);
+        let removed_spend = self
+            .transparent_received_output_spends
+            .0
+            .remove(outpoint)
+            .is_some();
+        let spend_map_len = self.transparent_spend_map.len();
+        self.transparent_spend_map
+            .0
+            .retain(|(_, spent)| spent != outpoint);
+        let removed_spend_map = self.transparent_spend_map.len() != spend_map_len;
+        let removed_search = self
+            .transaction_data_request_queue
+            .remove_spend_detection(outpoint);
+
+        // Spend search requests queued by address, as restored from older snapshots, are only
+        // dropped once no other output received at the address remains.
+        if let Some(output) = &removed_output {
+            let address = output.address;
+            if !self
+                .transparent_received_outputs
+                .values()
+                .any(|other| other.address == address)
+            {
+                self.transaction_data_request_queue
+                    .0
+                    .retain(|request| match request {
+                        TransactionDataRequest::SpendsFromAddress {
+                            address: request_address,
+                            ..
+                        } => request_address != &address,
+                        _ => true,
+                    });
+            }
+        }
+
+        if removed_output.is_some() {
+            tracing::debug!("Forgot transparent output {:?}", outpoint);
+        }
+        Ok(removed_output.is_some() || removed_spend || removed_spend_map || removed_search)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zcash_client_backend::{
+        data_api::{
+            testing::{AddressType, TestBuilder, TestState},
+            wallet::input_selection::GreedyInputSelector,
+            Account as _, InputSource, WalletRead, WalletWrite,
+        },
+        fees::{standard, DustOutputPolicy, StandardFeeRule},
+        wallet::WalletTransparentOutput,
+    };
+    use zcash_primitives::{
+        block::BlockHash,
+        legacy::TransparentAddress,
+        transaction::{
+            components::{amount::NonNegativeAmount, OutPoint, TxOut},
+            TxId,
+        },
+    };
+    use zcash_protocol::{local_consensus::LocalNetwork, ShieldedProtocol};
+
+    use crate::{testing::TestMemDbFactory, Error, MemBlockCache, MemoryWalletDb};
+
+    type MemTestState = TestState<MemBlockCache, MemoryWalletDb<LocalNetwork>, LocalNetwork>;
+
+    fn test_state() -> MemTestState {
+        TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build()
+    }
+
+    fn transparent_address(st: &MemTestState) -> TransparentAddress {
+        let account_id = st.test_account().unwrap().id();
+        *st.wallet()
+            .get_current_address(account_id)
+            .unwrap()
+            .unwrap()
+            .transparent()
+            .unwrap()
+    }
+
+    fn utxo(seed: u8, address: &TransparentAddress) -> WalletTransparentOutput {
+        WalletTransparentOutput::from_parts(
+            OutPoint::new([seed; 32], 0),
+            TxOut {
+                value: NonNegativeAmount::const_from_u64(100000),
+                script_pubkey: address.script(),
+            },
+            None,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn forget_unspent_utxo() {
+        let mut st = test_state();
+        let taddr = transparent_address(&st);
+
+        let kept = utxo(1, &taddr);
+        let forgotten = utxo(2, &taddr);
+        st.wallet_mut()
+            .put_received_transparent_utxo(&kept)
+            .unwrap();
+        st.wallet_mut()
+            .put_received_transparent_utxo(&forgotten)
+            .unwrap();
+
+        assert!(st
+            .wallet_mut()
+            .forget_transparent_utxo(forgotten.outpoint())
+            .unwrap());
+        assert!(st
+            .wallet()
+            .get_unspent_transparent_output(forgotten.outpoint())
+            .unwrap()
+            .is_none());
+        assert!(st
+            .wallet()
+            .get_unspent_transparent_output(kept.outpoint())
+            .unwrap()
+            .is_some());
+
+        // Forgetting an output that is no longer known is a no-op.
+        assert!(!st
+            .wallet_mut()
+            .forget_transparent_utxo(forgotten.outpoint())
+            .unwrap());
+    }
+
+    #[test]
+    fn forget_utxo_spent_by_foreign_tx() {
+        let mut st = test_state();
+        let taddr = transparent_address(&st);
+
+        let output = utxo(1, &taddr);
+        st.wallet_mut()
+            .put_received_transparent_utxo(&output)
+            .unwrap();
+        let foreign_txid = TxId::from_bytes([9; 32]);
+        st.wallet_mut()
+            .mark_transparent_output_spent(&foreign_txid, output.outpoint())
+            .unwrap();
+
+        assert!(st
+            .wallet_mut()
+            .forget_transparent_utxo(output.outpoint())
+            .unwrap());
+        assert!(st
+            .wallet()
+            .transparent_received_outputs
+            .get(output.outpoint())
+            .is_none());
+        assert!(st
+            .wallet()
+            .transparent_received_output_spends
+            .get(output.outpoint())
+            .is_none());
+    }
+
+    #[test]
+    fn forget_utxo_spent_by_wallet_tx_is_refused() {
+        let mut st = test_state();
+        let account = st.test_account().cloned().unwrap();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let taddr = transparent_address(&st);
+
+        let (h, _, _) = st.generate_next_block(
+            &dfvk,
+            AddressType::DefaultExternal,
+            NonNegativeAmount::const_from_u64(10000),
+        );
+        st.scan_cached_blocks(h, 1);
+
+        let value = NonNegativeAmount::const_from_u64(100000);
+        let output = WalletTransparentOutput::from_parts(
+            OutPoint::fake(),
+            TxOut {
+                value,
+                script_pubkey: taddr.script(),
+            },
+            Some(h),
+        )
+        .unwrap();
+        st.wallet_mut()
+            .put_received_transparent_utxo(&output)
+            .unwrap();
+
+        let change_strategy = standard::SingleOutputChangeStrategy::new(
+            StandardFeeRule::Zip317,
+            None,
+            ShieldedProtocol::Sapling,
+            DustOutputPolicy::default(),
+        );
+        let txid = st
+            .shield_transparent_funds(
+                &GreedyInputSelector::new(),
+                &change_strategy,
+                value,
+                account.usk(),
+                &[taddr],
+                account.id(),
+                1,
+            )
+            .unwrap()[0];
+
+        assert!(matches!(
+            st.wallet_mut().forget_transparent_utxo(output.outpoint()),
+            Err(Error::TransparentOutputSpentByWalletTx { txid: spent_in, .. }) if spent_in == txid
+        ));
+        assert!(st
+            .wallet()
+            .transparent_received_outputs
+            .get(output.outpoint())
+            .is_some());
+    }
+}
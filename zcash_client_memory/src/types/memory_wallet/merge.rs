@@ -0,0 +1,466 @@
+use std::{
+    cmp::{max, min},
+    iter,
+};
+
+use incrementalmerkletree::Hashable;
+use shardtree::error::ShardTreeError;
+
+use super::*;
+
+/// A summary of the changes made to a wallet by [`MemoryWalletDb::merge`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MergeReport {
+    blocks_added: usize,
+    transactions_added: usize,
+    notes_added: usize,
+    conflicting_blocks: Vec<BlockHeight>,
+    ranges_requiring_rescan: Vec<(ShieldedProtocol, Range<BlockHeight>)>,
+}
+
+impl MergeReport {
+    /// Returns the number of scanned blocks that were only known to the merged-in wallet.
+    pub fn blocks_added(&self) -> usize {
+        self.blocks_added
+    }
+
+    /// Returns the number of transactions that were only known to the merged-in wallet.
+    pub fn transactions_added(&self) -> usize {
+        self.transactions_added
+    }
+
+    /// Returns the number of received notes that were only known to the merged-in wallet.
+    pub fn notes_added(&self) -> usize {
+        self.notes_added
+    }
+
+    /// Returns the heights at which the two wallets recorded different block hashes. The
+    /// block recorded by the wallet being merged into is retained.
+    pub fn conflicting_blocks(&self) -> &[BlockHeight] {
+        &self.conflicting_blocks
+    }
+
+    /// Returns the block ranges for which note commitment tree data could not be merged,
+    /// and which must be rescanned in order for notes in those ranges to be spendable.
+    pub fn ranges_requiring_rescan(&self) -> &[(ShieldedProtocol, Range<BlockHeight>)] {
+        &self.ranges_requiring_rescan
+    }
+}
+
+impl<P: consensus::Parameters + PartialEq> MemoryWalletDb<P> {
+    /// Merges another snapshot of the same wallet into this one.
+    ///
+    /// Both wallets must be for the same network and contain the same accounts with the same
+    /// viewing keys. Data known to either wallet is combined: a mined transaction status takes
+    /// precedence over an unmined one, known note commitment tree positions take precedence
+    /// over unknown ones, and the most recent height at which a transparent output was observed
+    /// to be unspent is retained. A block range scanned by either wallet is treated as scanned.
+    ///
+    /// Note commitment trees are merged by retaining the tree with greater coverage and
+    /// inserting the shards of the other tree into it. The block ranges of any shards that
+    /// conflict are reported via [`MergeReport::ranges_requiring_rescan`].
+    pub fn merge(&mut self, other: MemoryWalletDb<P>) -> Result<MergeReport, Error> {
+        if self.params != other.params {
+            return Err(Error::MergeNetworkMismatch);
+        }
+        self.check_same_accounts(&other)?;
+
+        let MemoryWalletDb {
+            params: _,
+            accounts,
+            blocks,
+            tx_table,
+            received_notes,
+            received_note_spends,
+            nullifiers,
+            sent_notes,
+            tx_locator,
+            sapling_tree,
+            sapling_tree_shard_end_heights,
+            #[cfg(feature = "orchard")]
+            orchard_tree,
+            #[cfg(feature = "orchard")]
+            orchard_tree_shard_end_heights,
+            transparent_received_outputs,
+            transparent_received_output_spends,
+            transparent_spend_map,
+            transaction_data_request_queue,
+            scan_queue,
+            in_progress_scan: _,
+            scanned_heights,
+            purged_transactions,
+            provenance: _,
+            reorg_stats: _,
+            max_checkpoints: _,
+            scan_tuning: _,
+            #[cfg(feature = "telemetry")]
+            scan_telemetry,
+            strict: _,
+            allow_stale_proposals: _,
+            allow_zero_conf_spends: _,
+            request_expiry: _,
+            max_scan_range_len: _,
+        } = other;
+        // The telemetry of the other wallet describes its own scans, and is not merged.
+        #[cfg(feature = "telemetry")]
+        let _ = scan_telemetry;
+
+        let mut report = MergeReport::default();
+
+        for (account_id, account) in accounts.accounts {
+            if let Some(existing) = self.accounts.get_mut(account_id) {
+                existing.merge(account);
+            }
+        }
+
+        for (height, block) in blocks {
+            match self.blocks.entry(height) {
+                Entry::Occupied(entry) => {
+                    if entry.get().hash != block.hash {
+                        report.conflicting_blocks.push(height);
+                    }
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(block);
+                    report.blocks_added += 1;
+                }
+            }
+        }
+
+        report.transactions_added = self.tx_table.merge(tx_table);
+
+        for note in received_notes.0 {
+            if !self
+                .received_notes
+                .iter()
+                .any(|n| n.note_id() == note.note_id())
+            {
+                report.notes_added += 1;
+            }
+            self.received_notes.insert_received_note(note);
+        }
+
+        // Where the two wallets disagree about which transaction spent a note, prefer the
+        // spending transaction that has been mined.
+        for (note_id, txid) in received_note_spends.0 {
+            let other_is_mined = self.tx_is_mined(&txid);
+            let current_is_mined = self
+                .received_note_spends
+                .0
+                .get(&note_id)
+                .map_or(false, |current| self.tx_is_mined(current));
+            match self.received_note_spends.0.entry(note_id) {
+                Entry::Occupied(mut entry) => {
+                    if other_is_mined && !current_is_mined {
+                        entry.insert(txid);
+                    }
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(txid);
+                }
+            }
+        }
+
+        for (nf, locator) in nullifiers.0 {
+            self.nullifiers.0.entry(nf).or_insert(locator);
+        }
+        for (note_id, note) in sent_notes.0 {
+            self.sent_notes.0.entry(note_id).or_insert(note);
+        }
+        for ((height, index), txid) in tx_locator.iter() {
+            if self.tx_locator.get(*height, *index).is_none() {
+                self.tx_locator.insert(*height, *index, *txid);
+            }
+        }
+
+        self.transparent_received_outputs
+            .merge(transparent_received_outputs);
+        for (outpoint, txid) in transparent_received_output_spends.0 {
+            self.transparent_received_output_spends
+                .0
+                .entry(outpoint)
+                .or_insert(txid);
+        }
+        self.transparent_spend_map.0.extend(transparent_spend_map.0);
+
+        for request in transaction_data_request_queue.0 {
+            if !self.transaction_data_request_queue.contains(&request) {
+                self.transaction_data_request_queue.0.push_back(request);
+            }
+        }
+        for (txid, height) in transaction_data_request_queue.1 {
+            let queued_at = self
+                .transaction_data_request_queue
+                .1
+                .entry(txid)
+                .or_insert(height);
+            *queued_at = min(*queued_at, height);
+        }
+        self.transaction_data_request_queue
+            .2
+            .extend(transaction_data_request_queue.2);
+
+        for (txid, height) in purged_transactions {
+            let purge_height = self.purged_transactions.entry(txid).or_insert(height);
+            *purge_height = min(*purge_height, height);
+        }
+
+        for range in scanned_heights.iter() {
+            self.scanned_heights.insert(range);
+        }
+
+        for (start, end, priority) in scan_queue.0 {
+            let range = start..end;
+            self.scan_queue.replace_queue_entries(
+                &range,
+                iter::once(ScanRange::from_parts(range.clone(), priority)),
+                false,
+            )?;
+        }
+
+        merge_shard_end_heights(
+            &mut self.sapling_tree_shard_end_heights,
+            sapling_tree_shard_end_heights,
+        );
+        #[cfg(feature = "orchard")]
+        merge_shard_end_heights(
+            &mut self.orchard_tree_shard_end_heights,
+            orchard_tree_shard_end_heights,
+        );
+
+        let fallback_range = {
+            let start = match self.get_wallet_birthday()? {
+                Some(birthday) => birthday,
+                None => self
+                    .params
+                    .activation_height(NetworkUpgrade::Sapling)
+                    .expect("Sapling activation height must be known."),
+            };
+            let end = self.chain_height()?.map_or(start, |h| h + 1);
+            start..end
+        };
+
+        for shard_root in merge_trees(&mut self.sapling_tree, sapling_tree)? {
+            report.ranges_requiring_rescan.push((
+                ShieldedProtocol::Sapling,
+                shard_block_range(
+                    &self.sapling_tree_shard_end_heights,
+                    shard_root,
+                    &fallback_range,
+                ),
+            ));
+        }
+        #[cfg(feature = "orchard")]
+        for shard_root in merge_trees(&mut self.orchard_tree, orchard_tree)? {
+            report.ranges_requiring_rescan.push((
+                ShieldedProtocol::Orchard,
+                shard_block_range(
+                    &self.orchard_tree_shard_end_heights,
+                    shard_root,
+                    &fallback_range,
+                ),
+            ));
+        }
+
+        Ok(report)
+    }
+
+    /// Checks that `other` contains exactly the same accounts, with the same viewing keys, as
+    /// this wallet.
+    fn check_same_accounts(&self, other: &MemoryWalletDb<P>) -> Result<(), Error> {
+        let account_ids = self.accounts.account_ids().collect::<BTreeSet<_>>();
+        let other_account_ids = other.accounts.account_ids().collect::<BTreeSet<_>>();
+        if let Some(account_id) = account_ids.symmetric_difference(&other_account_ids).next() {
+            return Err(Error::MergeAccountMismatch(**account_id));
+        }
+
+        for (account_id, account) in self.accounts.iter() {
+            let other_account = other
+                .accounts
+                .get(*account_id)
+                .ok_or(Error::AccountUnknown(*account_id))?;
+            let keys_match = account.uivk().encode(&self.params)
+                == other_account.uivk().encode(&self.params)
+                && account.ufvk().map(|ufvk| ufvk.encode(&self.params))
+                    == other_account.ufvk().map(|ufvk| ufvk.encode(&self.params));
+            if !keys_match {
+                return Err(Error::MergeAccountMismatch(*account_id));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn tx_is_mined(&self, txid: &TxId) -> bool {
+        self.tx_table
+            .get(txid)
+            .map_or(false, |tx| tx.mined_height().is_some())
+    }
+}
+
+fn merge_shard_end_heights(
+    end_heights: &mut BTreeMap<Address, BlockHeight>,
+    other: BTreeMap<Address, BlockHeight>,
+) {
+    for (addr, height) in other {
+        end_heights
+            .entry(addr)
+            .and_modify(|h| *h = max(*h, height))
+            .or_insert(height);
+    }
+}
+
+/// Returns the range of blocks containing the note commitments of the given shard, falling back
+/// to the provided range where the bounds of the shard are not known.
+fn shard_block_range(
+    end_heights: &BTreeMap<Address, BlockHeight>,
+    shard_root: Address,
+    fallback: &Range<BlockHeight>,
+) -> Range<BlockHeight> {
+    let start = shard_root
+        .index()
+        .checked_sub(1)
+        .and_then(|index| end_heights.get(&Address::from_parts(shard_root.level(), index)))
+        .copied()
+        .unwrap_or(fallback.start);
+    let end = end_heights
+        .get(&shard_root)
+        .map_or(fallback.end, |h| *h + 1);
+    start..end
+}
+
+/// Merges `other` into `tree`, retaining whichever of the two trees has greater coverage as the
+/// base and inserting the shards and checkpoints of the other tree into it. Returns the root
+/// addresses of any shards that conflict with the base tree and could not be merged.
+fn merge_trees<H, const DEPTH: u8, const SHARD_HEIGHT: u8>(
+    tree: &mut ShardTree<MemoryShardStore<H, BlockHeight>, DEPTH, SHARD_HEIGHT>,
+    mut other: ShardTree<MemoryShardStore<H, BlockHeight>, DEPTH, SHARD_HEIGHT>,
+) -> Result<Vec<Address>, Error>
+where
+    H: Hashable + Clone + PartialEq,
+{
+    let coverage = |t: &ShardTree<MemoryShardStore<H, BlockHeight>, DEPTH, SHARD_HEIGHT>| {
+        t.store()
+            .last_shard()
+            .map(|shard| shard.and_then(|s| s.max_position()))
+    };
+    if coverage(&other)? > coverage(tree)? {
+        std::mem::swap(tree, &mut other);
+    }
+
+    let mut conflicts = vec![];
+    for shard_root in other.store().get_shard_roots()? {
+        if let Some(shard) = other.store().get_shard(shard_root)? {
+            match tree.insert_tree(shard, BTreeMap::new()) {
+                Ok(_) => {}
+                Err(ShardTreeError::Insert(_)) => conflicts.push(shard_root),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    let mut checkpoints = vec![];
+    other
+        .store()
+        .for_each_checkpoint(other.store().checkpoint_count()?, |id, checkpoint| {
+            checkpoints.push((*id, checkpoint.clone()));
+            Ok(())
+        })?;
+    for (id, checkpoint) in checkpoints {
+        if tree.store().get_checkpoint(&id)?.is_none() {
+            tree.store_mut().add_checkpoint(id, checkpoint)?;
+        }
+    }
+
+    Ok(conflicts)
+}
+
+#[cfg(test)]
+mod tests {
+    use zcash_client_backend::data_api::{testing::AddressType, WalletRead};
+    use zcash_primitives::transaction::components::amount::NonNegativeAmount;
+    use zcash_protocol::local_consensus::LocalNetwork;
+
+    use crate::{testing::test_state, MemoryWalletDb, ReceivedNote};
+
+    fn sorted_notes(wallet: &MemoryWalletDb<LocalNetwork>) -> Vec<ReceivedNote> {
+        let mut notes = wallet.received_notes.0.clone();
+        notes.sort_by_key(|note| note.note_id());
+        notes
+    }
+
+    #[test]
+    fn merge_split_history_matches_full_history() {
+        let mut st = test_state();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+
+        // Snapshot the wallet before any blocks have been scanned.
+        let params = st.wallet().params().clone();
+        let mut initial = vec![];
+        st.wallet().encode(&mut initial).unwrap();
+        let snapshot = || MemoryWalletDb::decode_new(&initial[..], params.clone(), 100).unwrap();
+
+        let value = NonNegativeAmount::const_from_u64(60000);
+        let (h1, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        let (h2, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        let (h3, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+
+        // A wallet that sees the complete history.
+        st.scan_cached_blocks(h1, 3);
+        let full = std::mem::replace(st.wallet_mut(), snapshot());
+
+        // One device scans the recent blocks...
+        st.scan_cached_blocks(h2, 2);
+        let mut merged = std::mem::replace(st.wallet_mut(), snapshot());
+
+        // ...while another backfills the historic block.
+        st.scan_cached_blocks(h1, 1);
+        let backfill = std::mem::replace(st.wallet_mut(), snapshot());
+
+        let report = merged.merge(backfill).unwrap();
+        assert_eq!(report.blocks_added(), 1);
+        assert_eq!(report.transactions_added(), 1);
+        assert_eq!(report.notes_added(), 1);
+        assert!(report.conflicting_blocks().is_empty());
+        assert!(report.ranges_requiring_rescan().is_empty());
+
+        assert_eq!(merged.blocks, full.blocks);
+        assert_eq!(merged.tx_table, full.tx_table);
+        assert_eq!(merged.nullifiers, full.nullifiers);
+        assert_eq!(merged.tx_locator, full.tx_locator);
+        assert_eq!(sorted_notes(&merged), sorted_notes(&full));
+        assert_eq!(
+            merged.block_fully_scanned().unwrap(),
+            full.block_fully_scanned().unwrap()
+        );
+        assert_eq!(
+            merged
+                .get_wallet_summary(1)
+                .unwrap()
+                .map(|s| s.account_balances().clone()),
+            full.get_wallet_summary(1)
+                .unwrap()
+                .map(|s| s.account_balances().clone()),
+        );
+        assert_eq!(
+            merged.sapling_tree.root_at_checkpoint_id(&h3).unwrap(),
+            full.sapling_tree.root_at_checkpoint_id(&h3).unwrap()
+        );
+    }
+
+    #[test]
+    fn merge_rejects_different_accounts() {
+        let st = test_state();
+        let params = st.wallet().params().clone();
+
+        let mut empty = MemoryWalletDb::new(params.clone(), 100);
+        let mut encoded = vec![];
+        st.wallet().encode(&mut encoded).unwrap();
+        let with_account = MemoryWalletDb::decode_new(&encoded[..], params, 100).unwrap();
+
+        assert!(matches!(
+            empty.merge(with_account),
+            Err(crate::Error::MergeAccountMismatch(_))
+        ));
+    }
+}
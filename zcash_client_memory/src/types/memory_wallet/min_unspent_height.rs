@@ -0,0 +1,103 @@
+use crate::PRUNING_DEPTH;
+
+use super::*;
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    /// Returns the lowest mined height of the wallet's unspent received notes in either
+    /// shielded pool, or `None` if the wallet has no mined unspent notes.
+    pub fn get_min_unspent_height(&self) -> Result<Option<BlockHeight>, Error> {
+        let mut min_height = None;
+        for note in self.received_notes.iter() {
+            let mined_height = match self
+                .tx_table
+                .get(&note.txid())
+                .and_then(|tx| tx.mined_height())
+            {
+                Some(mined_height) => mined_height,
+                None => continue,
+            };
+            if !self.note_is_spent(note, 0)? {
+                min_height = Some(min_height.map_or(mined_height, |h| min(h, mined_height)));
+            }
+        }
+        Ok(min_height)
+    }
+
+    /// Returns an error if truncating the wallet to `requested` would discard the block
+    /// containing one of its unspent notes, unless the wallet is being rewound by at most
+    /// [`PRUNING_DEPTH`] blocks from `last_scanned_height` as part of handling a chain
+    /// reorganization.
+    pub(crate) fn check_rewind_height(
+        &self,
+        requested: BlockHeight,
+        last_scanned_height: BlockHeight,
+    ) -> Result<(), Error> {
+        if let Some(min_unspent_height) = self.get_min_unspent_height()? {
+            let safe_height = min(
+                min_unspent_height,
+                last_scanned_height.saturating_sub(PRUNING_DEPTH),
+            );
+            if requested < safe_height {
+                return Err(Error::RequestedRewindInvalid {
+                    safe_height: Some(safe_height),
+                    requested,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sapling::zip32::ExtendedSpendingKey;
+    use zcash_client_backend::data_api::{
+        testing::{AddressType, TestBuilder},
+        WalletWrite,
+    };
+    use zcash_primitives::{block::BlockHash, transaction::components::amount::NonNegativeAmount};
+
+    use crate::{error::Error, testing::TestMemDbFactory, MemBlockCache, MemoryWalletDb};
+
+    #[test]
+    fn truncation_is_bounded_by_the_min_unspent_height() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+
+        // Retain enough checkpoints that the wallet could otherwise be rewound below its note.
+        let mut snapshot = vec![];
+        st.wallet().encode(&mut snapshot).unwrap();
+        let params = st.wallet().params.clone();
+        *st.wallet_mut() = MemoryWalletDb::decode_new(&snapshot[..], params, 1000).unwrap();
+
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let not_our_key = ExtendedSpendingKey::master(&[]).to_diversifiable_full_viewing_key();
+        let value = NonNegativeAmount::const_from_u64(10000);
+        assert_eq!(st.wallet().get_min_unspent_height().unwrap(), None);
+
+        let (h, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        for _ in 0..150 {
+            st.generate_next_block(&not_our_key, AddressType::DefaultExternal, value);
+        }
+        st.scan_cached_blocks(h, 151);
+        assert_eq!(st.wallet().get_min_unspent_height().unwrap(), Some(h));
+
+        // A deep rewind may not discard the block containing the unspent note.
+        assert!(matches!(
+            st.wallet_mut().truncate_to_height(h - 1),
+            Err(Error::RequestedRewindInvalid {
+                safe_height: Some(safe_height),
+                requested,
+            }) if safe_height == h && requested == h - 1
+        ));
+        assert_eq!(st.wallet_mut().truncate_to_height(h + 60).unwrap(), h + 60);
+
+        // A rewind within `PRUNING_DEPTH` blocks of the scanned tip, as required to handle a
+        // chain reorganization, may discard it.
+        assert_eq!(st.wallet_mut().truncate_to_height(h - 1).unwrap(), h - 1);
+        assert_eq!(st.wallet().get_min_unspent_height().unwrap(), None);
+    }
+}
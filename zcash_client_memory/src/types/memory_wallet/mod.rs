@@ -1,18 +1,92 @@
 #![allow(dead_code)]
 
+mod account_ids;
+mod address_reuse;
+mod clear;
+#[cfg(feature = "diagnostics")]
+mod diagnostics;
+mod enhancement_requests;
+#[cfg(feature = "transparent-inputs")]
+mod ephemeral_rewind;
+mod fetch_plan;
+#[cfg(feature = "transparent-inputs")]
+mod forget_utxo;
+mod merge;
+mod min_unspent_height;
+mod note_export;
+mod nullifier_query;
+#[cfg(feature = "transparent-inputs")]
+mod outpoint_metadata;
+mod provenance;
+mod prune;
+mod purge;
+#[cfg(feature = "diagnostics")]
+mod redacted_diagnostics;
+mod reorg_stats;
+mod request_expiry;
+mod scan_coverage;
+mod scan_invariants;
+mod scan_progress;
+mod scan_ranges;
+mod scan_resumption;
+mod scan_tuning;
 mod serialization;
+#[cfg(feature = "snapshot-compression")]
+mod snapshot_compression;
+mod spendable;
+mod stale_proposals;
+mod strict;
+#[cfg(feature = "telemetry")]
+mod telemetry;
+mod transaction_status;
+#[cfg(feature = "transparent-inputs")]
+mod transparent_history;
+mod value_breakdown;
+mod wallet_file;
+#[cfg(feature = "metrics")]
+pub(crate) mod wallet_metrics;
+#[cfg(feature = "transparent-inputs")]
+mod watch_addresses;
+mod zero_conf;
+
+pub use address_reuse::AddressReuse;
+#[cfg(feature = "diagnostics")]
+pub use diagnostics::DiagnosticStats;
+pub use merge::MergeReport;
+#[cfg(feature = "csv-export")]
+pub use note_export::write_note_export_csv;
+pub use note_export::{NoteExportFilter, NoteExportRecord};
+pub use provenance::WalletProvenance;
+use prune::tree_checkpoint_limit;
+#[cfg(feature = "diagnostics")]
+pub use redacted_diagnostics::RedactionOptions;
+pub use reorg_stats::{ReorgStats, TruncationRecord};
+pub use scan_coverage::CoverageState;
+pub use scan_tuning::ScanTuning;
+pub use serialization::WALLET_SERIALIZATION_VERSION;
+#[cfg(feature = "snapshot-compression")]
+pub use snapshot_compression::{CompressedSnapshotMetadata, SnapshotSection, SnapshotSectionInfo};
+#[cfg(feature = "telemetry")]
+pub use telemetry::ScanTelemetry;
+#[cfg(feature = "telemetry")]
+pub(crate) use telemetry::{PhaseTimer, ScanPhase};
+pub use value_breakdown::TxValueBreakdown;
+#[cfg(feature = "metrics")]
+pub use wallet_metrics::metric_names;
 
 use std::{
     cmp::min,
     collections::{btree_map::Entry, BTreeMap, BTreeSet},
+    convert::Infallible,
     num::NonZeroU32,
     ops::{Range, RangeInclusive},
     usize,
 };
 
-use incrementalmerkletree::{Address, Level, Marking, Position, Retention};
-use scanning::ScanQueue;
+use incrementalmerkletree::{Address, Hashable, Level, Marking, Position, Retention};
+use scanning::{ScanQueue, ScannedHeights};
 use shardtree::{
+    error::ShardTreeError,
     store::{memory::MemoryShardStore, ShardStore},
     ShardTree,
 };
@@ -27,18 +101,30 @@ use zcash_client_backend::{
     },
     wallet::{NoteId, WalletSaplingOutput, WalletTransparentOutput},
 };
-use zcash_keys::keys::UnifiedFullViewingKey;
+use zcash_keys::{
+    address::UnifiedAddress,
+    keys::{UnifiedAddressRequest, UnifiedFullViewingKey},
+};
 use zcash_primitives::{
     consensus::{self, BlockHeight, NetworkUpgrade},
     legacy::TransparentAddress,
     transaction::{components::OutPoint, Transaction, TxId},
 };
 use zcash_protocol::ShieldedProtocol;
-use zip32::fingerprint::SeedFingerprint;
+use zip32::{fingerprint::SeedFingerprint, DiversifierIndex};
 
 #[cfg(feature = "orchard")]
 use zcash_client_backend::{data_api::ORCHARD_SHARD_HEIGHT, wallet::WalletOrchardOutput};
 
+#[cfg(feature = "transparent-inputs")]
+use zcash_client_backend::data_api::AccountBalance;
+
+#[cfg(feature = "hd-derivation")]
+use {
+    secrecy::{ExposeSecret, SecretVec},
+    zcash_keys::keys::UnifiedSpendingKey,
+};
+
 use crate::error::Error;
 use crate::types::*;
 
@@ -93,11 +179,48 @@ pub struct MemoryWalletDb<P: consensus::Parameters> {
     pub(crate) transaction_data_request_queue: TransactionDataRequestQueue,
     /// Queue of block ranges that should be scanned along with their priority
     pub(crate) scan_queue: ScanQueue,
+    /// The part of the range most recently begun by the scan driver that has not yet been
+    /// committed
+    pub(crate) in_progress_scan: Option<ScanRange>,
+    /// The heights of the blocks that the wallet has scanned, whether or not their block
+    /// records have been retained
+    pub(crate) scanned_heights: ScannedHeights,
+    /// Wallet-created transactions that were removed after becoming permanently invalid,
+    /// mapped to the fully-scanned height at which they were removed
+    pub(crate) purged_transactions: BTreeMap<TxId, BlockHeight>,
+    /// Diagnostic information about the creation and restoration of this wallet
+    pub(crate) provenance: WalletProvenance,
+    /// Statistics about the truncations of this wallet caused by chain reorganizations
+    pub(crate) reorg_stats: ReorgStats,
+    /// The number of note commitment tree checkpoints retained in addition to the one needed
+    /// to rewind the wallet by `PRUNING_DEPTH` blocks
+    pub(crate) max_checkpoints: usize,
+    /// Performance settings for scanning blocks into the wallet
+    pub(crate) scan_tuning: ScanTuning,
+    /// Timing and note counts accumulated over calls to `put_blocks`
+    #[cfg(feature = "telemetry")]
+    pub(crate) scan_telemetry: ScanTelemetry,
+    /// Whether unsupported protocol data produces an error rather than being skipped
+    pub(crate) strict: bool,
+    /// Whether transactions built for a target height that has already been scanned may be
+    /// stored
+    pub(crate) allow_stale_proposals: bool,
+    /// Whether the outputs of unmined, unexpired transactions may be spent when zero
+    /// confirmations are requested
+    pub(crate) allow_zero_conf_spends: bool,
+    /// The number of blocks after which unanswered requests for transactions that the wallet
+    /// did not create are dropped
+    pub(crate) request_expiry: u32,
+    /// The maximum length, in blocks, of a range returned by `suggest_scan_ranges`
+    pub(crate) max_scan_range_len: NonZeroU32,
 }
 
 impl<P: consensus::Parameters + PartialEq> PartialEq for MemoryWalletDb<P> {
     /// Tests for equality between two `MemoryWalletDb` instances.
-    /// but does NOT compare the sapling_tree and orchard_tree fields.
+    /// but does NOT compare the sapling_tree and orchard_tree fields, the wallet's
+    /// provenance or reorg statistics, its checkpoint limit, its scan tuning or telemetry,
+    /// whether it is in strict mode, whether it allows stale proposals or zero-conf spends, its
+    /// request expiry, or its maximum scan range length.
     fn eq(&self, other: &Self) -> bool {
         #[cfg(feature = "orchard")]
         let orchard_comparisons =
@@ -125,10 +248,120 @@ impl<P: consensus::Parameters + PartialEq> PartialEq for MemoryWalletDb<P> {
             && self.sent_notes == other.sent_notes
             && self.tx_locator == other.tx_locator
             && self.scan_queue == other.scan_queue
+            && self.in_progress_scan == other.in_progress_scan
+            && self.scanned_heights == other.scanned_heights
             && self.sapling_tree_shard_end_heights == other.sapling_tree_shard_end_heights
             && orchard_comparisons
             && transparent_comparisons
             && self.transaction_data_request_queue == other.transaction_data_request_queue
+            && self.purged_transactions == other.purged_transactions
+    }
+}
+
+impl<P: consensus::Parameters> Clone for MemoryWalletDb<P> {
+    fn clone(&self) -> Self {
+        Self {
+            params: self.params.clone(),
+            accounts: self.accounts.clone(),
+            blocks: self.blocks.clone(),
+            tx_table: self.tx_table.clone(),
+            received_notes: self.received_notes.clone(),
+            received_note_spends: self.received_note_spends.clone(),
+            nullifiers: self.nullifiers.clone(),
+            sent_notes: self.sent_notes.clone(),
+            tx_locator: self.tx_locator.clone(),
+            sapling_tree: clone_tree(&self.sapling_tree),
+            sapling_tree_shard_end_heights: self.sapling_tree_shard_end_heights.clone(),
+            #[cfg(feature = "orchard")]
+            orchard_tree: clone_tree(&self.orchard_tree),
+            #[cfg(feature = "orchard")]
+            orchard_tree_shard_end_heights: self.orchard_tree_shard_end_heights.clone(),
+            transparent_received_outputs: self.transparent_received_outputs.clone(),
+            transparent_received_output_spends: self.transparent_received_output_spends.clone(),
+            transparent_spend_map: self.transparent_spend_map.clone(),
+            transaction_data_request_queue: self.transaction_data_request_queue.clone(),
+            scan_queue: self.scan_queue.clone(),
+            in_progress_scan: self.in_progress_scan.clone(),
+            scanned_heights: self.scanned_heights.clone(),
+            purged_transactions: self.purged_transactions.clone(),
+            provenance: self.provenance.clone(),
+            reorg_stats: self.reorg_stats.clone(),
+            max_checkpoints: self.max_checkpoints,
+            scan_tuning: self.scan_tuning,
+            #[cfg(feature = "telemetry")]
+            scan_telemetry: self.scan_telemetry.clone(),
+            strict: self.strict,
+            allow_stale_proposals: self.allow_stale_proposals,
+            allow_zero_conf_spends: self.allow_zero_conf_spends,
+            request_expiry: self.request_expiry,
+            max_scan_range_len: self.max_scan_range_len,
+        }
+    }
+}
+
+/// Returns a copy of a note commitment tree, for which [`MemoryShardStore`] does not implement
+/// `Clone`.
+fn clone_tree<H: Hashable + Clone + PartialEq, const DEPTH: u8, const SHARD_HEIGHT: u8>(
+    tree: &ShardTree<MemoryShardStore<H, BlockHeight>, DEPTH, SHARD_HEIGHT>,
+) -> ShardTree<MemoryShardStore<H, BlockHeight>, DEPTH, SHARD_HEIGHT> {
+    fn copy<H: Clone>(
+        from: &MemoryShardStore<H, BlockHeight>,
+    ) -> Result<MemoryShardStore<H, BlockHeight>, Infallible> {
+        let mut to = MemoryShardStore::empty();
+        for root in from.get_shard_roots()? {
+            if let Some(shard) = from.get_shard(root)? {
+                to.put_shard(shard)?;
+            }
+        }
+        to.put_cap(from.get_cap()?)?;
+        from.for_each_checkpoint(usize::MAX, |id, checkpoint| {
+            to.add_checkpoint(*id, checkpoint.clone())
+        })?;
+        Ok(to)
+    }
+
+    ShardTree::new(
+        copy(tree.store()).unwrap_or_else(|e| match e {}),
+        tree.max_checkpoints(),
+    )
+}
+
+/// Returns whether a witness for the leaf at the given position can be computed at the
+/// checkpoint with the given id.
+///
+/// The checkpoint must exist and lie at or after the leaf, and the shard containing the leaf
+/// must have no missing nodes up to the checkpoint; otherwise no witness is computed.
+fn is_witnessable_at<H: Hashable + Clone + PartialEq, const DEPTH: u8, const SHARD_HEIGHT: u8>(
+    tree: &ShardTree<MemoryShardStore<H, BlockHeight>, DEPTH, SHARD_HEIGHT>,
+    position: Position,
+    checkpoint_id: &BlockHeight,
+) -> Result<bool, Error> {
+    let checkpoint_position = match tree.store().get_checkpoint(checkpoint_id)? {
+        Some(checkpoint) => checkpoint.position(),
+        None => return Ok(false),
+    };
+    if checkpoint_position.map_or(true, |checkpoint_position| checkpoint_position < position) {
+        return Ok(false);
+    }
+
+    let shard_root = Address::above_position(SHARD_HEIGHT.into(), position);
+    let shard_complete = match tree.store().get_shard(shard_root)? {
+        Some(shard) => shard.incomplete_nodes().iter().all(|addr| {
+            checkpoint_position.map_or(false, |checkpoint_position| {
+                addr.position_range_start() > checkpoint_position
+            })
+        }),
+        None => false,
+    };
+    if !shard_complete {
+        return Ok(false);
+    }
+
+    match tree.witness_at_checkpoint_id(position, checkpoint_id) {
+        Ok(witness) => Ok(witness.is_some()),
+        // Subtrees outside of the note's shard may not yet have been scanned.
+        Err(ShardTreeError::Query(_)) => Ok(false),
+        Err(e) => Err(e.into()),
     }
 }
 
@@ -138,10 +371,16 @@ impl<P: consensus::Parameters> MemoryWalletDb<P> {
             accounts: Accounts::new(),
             params,
             blocks: BTreeMap::new(),
-            sapling_tree: ShardTree::new(MemoryShardStore::empty(), max_checkpoints),
+            sapling_tree: ShardTree::new(
+                MemoryShardStore::empty(),
+                tree_checkpoint_limit(max_checkpoints),
+            ),
             sapling_tree_shard_end_heights: BTreeMap::new(),
             #[cfg(feature = "orchard")]
-            orchard_tree: ShardTree::new(MemoryShardStore::empty(), max_checkpoints),
+            orchard_tree: ShardTree::new(
+                MemoryShardStore::empty(),
+                tree_checkpoint_limit(max_checkpoints),
+            ),
             #[cfg(feature = "orchard")]
             orchard_tree_shard_end_heights: BTreeMap::new(),
             tx_table: TransactionTable::new(),
@@ -151,10 +390,24 @@ impl<P: consensus::Parameters> MemoryWalletDb<P> {
             tx_locator: TxLocatorMap::new(),
             received_note_spends: ReceievedNoteSpends::new(),
             scan_queue: ScanQueue::new(),
+            in_progress_scan: None,
+            scanned_heights: ScannedHeights::new(),
             transparent_received_outputs: TransparentReceivedOutputs::new(),
             transparent_received_output_spends: TransparentReceivedOutputSpends::new(),
             transparent_spend_map: TransparentSpendCache::new(),
             transaction_data_request_queue: TransactionDataRequestQueue::new(),
+            purged_transactions: BTreeMap::new(),
+            provenance: WalletProvenance::new(),
+            reorg_stats: ReorgStats::default(),
+            max_checkpoints,
+            scan_tuning: ScanTuning::default(),
+            #[cfg(feature = "telemetry")]
+            scan_telemetry: ScanTelemetry::default(),
+            strict: false,
+            allow_stale_proposals: false,
+            allow_zero_conf_spends: false,
+            request_expiry: request_expiry::DEFAULT_REQUEST_EXPIRY,
+            max_scan_range_len: scan_ranges::DEFAULT_MAX_SCAN_RANGE_LEN,
         }
     }
 
@@ -172,6 +425,20 @@ impl<P: consensus::Parameters> MemoryWalletDb<P> {
             self.accounts
                 .new_account(kind, viewing_key.to_owned(), birthday.clone())?;
 
+        let chain_tip = self.chain_height()?;
+        self.seed_birthday(&birthday, chain_tip)?;
+
+        Ok((id, account))
+    }
+
+    /// Inserts the note commitment tree frontiers of the given account birthday into the
+    /// wallet's trees, and updates the scan queue so that the blocks from the birthday up to
+    /// the given chain tip (if known) will be scanned.
+    fn seed_birthday(
+        &mut self,
+        birthday: &AccountBirthday,
+        chain_tip: Option<BlockHeight>,
+    ) -> Result<(), Error> {
         // If a birthday frontier is available, insert it into the note commitment tree. If the
         // birthday frontier is the empty frontier, we don't need to do anything.
         if let Some(frontier) = birthday.sapling_frontier().value() {
@@ -229,7 +496,7 @@ impl<P: consensus::Parameters> MemoryWalletDb<P> {
 
         // Rewrite the scan ranges from the birthday height up to the chain tip so that we'll ensure we
         // re-scan to find any notes that might belong to the newly added account.
-        if let Some(t) = self.chain_height()? {
+        if let Some(t) = chain_tip.filter(|t| birthday.height() <= *t) {
             let rescan_range = birthday.height()..(t + 1);
             self.scan_queue.replace_queue_entries(
                 &rescan_range,
@@ -242,7 +509,7 @@ impl<P: consensus::Parameters> MemoryWalletDb<P> {
             )?;
         }
 
-        Ok((id, account))
+        Ok(())
     }
 
     #[cfg(feature = "transparent-inputs")]
@@ -306,6 +573,39 @@ impl<P: consensus::Parameters> MemoryWalletDb<P> {
         Ok(funding_accounts)
     }
 
+    /// Returns the Unified Address for the given account at the given diversifier index.
+    ///
+    /// Addresses that were previously generated by the wallet are returned as stored. For any
+    /// other index the address is derived on demand from the account's viewing key; such
+    /// derivations are not persisted. Returns `None` if the index is not valid for the
+    /// account's keys and the requested receiver types.
+    pub fn get_address_for_index(
+        &self,
+        account: AccountId,
+        index: DiversifierIndex,
+        request: UnifiedAddressRequest,
+    ) -> Result<Option<UnifiedAddress>, Error> {
+        self.accounts
+            .get(account)
+            .ok_or(Error::AccountUnknown(account))?
+            .address_for_index(index, request)
+    }
+
+    /// Returns all of the Unified Addresses that have been generated for the given account,
+    /// ordered by diversifier index.
+    pub fn list_addresses(&self, account: AccountId) -> Vec<(DiversifierIndex, UnifiedAddress)> {
+        self.accounts
+            .get(account)
+            .map(|account| {
+                account
+                    .addresses()
+                    .iter()
+                    .map(|(index, ua)| (*index, ua.clone()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
     pub(crate) fn get_received_notes(&self) -> &ReceivedNoteTable {
         &self.received_notes
     }
@@ -316,6 +616,48 @@ impl<P: consensus::Parameters> MemoryWalletDb<P> {
         self.received_notes.iter().find(|v| v.note_id() == note_id)
     }
 
+    /// Returns the account that received the note with the given id, or `None` if the note
+    /// was not received by this wallet.
+    ///
+    /// Returns an error if the note is attributed to an account that the wallet does not
+    /// know about.
+    pub fn account_for_note(&self, note_id: NoteId) -> Result<Option<AccountId>, Error> {
+        self.get_received_note(note_id)
+            .map(|note| {
+                self.accounts
+                    .get(note.account_id)
+                    .map(|_| note.account_id)
+                    .ok_or(Error::AccountUnknown(note.account_id))
+            })
+            .transpose()
+    }
+
+    /// Returns whether the given shielded output was received by one of the wallet's
+    /// accounts.
+    pub fn is_wallet_output(
+        &self,
+        txid: &TxId,
+        protocol: ShieldedProtocol,
+        output_index: u16,
+    ) -> bool {
+        self.get_received_note(NoteId::new(*txid, protocol, output_index))
+            .is_some()
+    }
+
+    /// Returns whether the given sent output is change, i.e. was received back by the wallet
+    /// as change. All other sent outputs, including those to external transparent addresses,
+    /// count as sent in transaction counts and values.
+    ///
+    /// The wallet never creates transparent change, so transparent outputs are never change.
+    pub(crate) fn is_change_output(&self, sent_note_id: &SentNoteId) -> bool {
+        match sent_note_id {
+            SentNoteId::Shielded(note_id) => self
+                .get_received_note(*note_id)
+                .map_or(false, |received_note| received_note.is_change),
+            SentNoteId::Transparent { .. } => false,
+        }
+    }
+
     pub(crate) fn mark_sapling_note_spent(
         &mut self,
         nf: sapling::Nullifier,
@@ -350,12 +692,13 @@ impl<P: consensus::Parameters> MemoryWalletDb<P> {
                     .ok_or_else(|| Error::TransactionNotFound(*txid))?;
                 match spending_tx.status() {
                     TransactionStatus::Mined(_height) => true,
-                    TransactionStatus::TxidNotRecognized => unreachable!(),
-                    TransactionStatus::NotInMainChain => {
-                        // check the expiry
-                        spending_tx.expiry_height().is_none() // no expiry, tx could be mined any time so we consider it spent
-                            // expiry is in the future so it could still be mined
-                            || spending_tx.expiry_height() > self.summary_height(min_confirmations)?
+                    TransactionStatus::NotInMainChain | TransactionStatus::TxidNotRecognized => {
+                        // A pending spend, such as one by a transaction that this wallet has
+                        // created but that has not yet been mined, locks the note until the
+                        // spending transaction has expired; without a chain tip we cannot know
+                        // that.
+                        self.summary_height(min_confirmations)?
+                            .map_or(true, |height| !spending_tx.is_expired_unmined(height))
                     }
                 }
             }
@@ -374,9 +717,9 @@ impl<P: consensus::Parameters> MemoryWalletDb<P> {
     /// - be in a block less than or equal to the anchor height
     /// - not be in the given exclude list
     ///
-    /// Additionally the tree shard containing the node must not be in an unscanned range
+    /// Additionally the tree shard containing the note must not overlap an unscanned range,
     /// excluding ranges that start above the anchor height or end below the wallet birthday.
-    /// This is determined by looking at the scan queue
+    /// This is determined by looking at the scan queue and the end heights of the shards.
     pub(crate) fn note_is_spendable(
         &self,
         note: &ReceivedNote,
@@ -392,24 +735,13 @@ impl<P: consensus::Parameters> MemoryWalletDb<P> {
             .get(&note.txid())
             .ok_or_else(|| Error::TransactionNotFound(note.txid()))?;
 
-        let unscanned_ranges = self.unscanned_ranges();
-
-        let note_in_unscanned_range =
-            unscanned_ranges
-                .iter()
-                .any(|(start_height, end_height, start, end_exclusive)| {
-                    let in_range = note.commitment_tree_position.map_or(false, |pos| {
-                        if let (Some(start), Some(end_exclusive)) = (start, end_exclusive) {
-                            pos >= *start && pos < *end_exclusive
-                        } else {
-                            true
-                        }
-                    });
-                    in_range && *end_height > birthday_height && *start_height <= anchor_height
-                });
-
         Ok(!self.note_is_spent(note, 0)?
-            && !note_in_unscanned_range
+            && !self.note_in_unscanned_range(
+                note,
+                &self.unscanned_ranges(),
+                birthday_height,
+                anchor_height,
+            )
             && note.note.value().into_u64() > 5000
             && note_account.ufvk().is_some()
             && note.recipient_key_scope.is_some()
@@ -420,23 +752,89 @@ impl<P: consensus::Parameters> MemoryWalletDb<P> {
             && !exclude.contains(&note.note_id()))
     }
 
-    /// To be pending a note must be:
-    /// - ?
-    pub(crate) fn note_is_pending(
+    /// Returns whether the note commitment tree shard containing the given note overlaps one of
+    /// the provided unscanned ranges, excluding ranges that start above the anchor height or end
+    /// at or below the wallet birthday. A note in such a shard cannot be witnessed until the
+    /// blocks in the range have been scanned.
+    pub(crate) fn note_in_unscanned_range(
         &self,
         note: &ReceivedNote,
-        min_confirmations: u32,
+        unscanned_ranges: &[Range<BlockHeight>],
+        birthday_height: BlockHeight,
+        anchor_height: BlockHeight,
+    ) -> bool {
+        let position = match note.commitment_tree_position {
+            Some(position) => position,
+            None => return false,
+        };
+        let (shard_start, shard_end) = self.shard_block_range(note.note.protocol(), position);
+        unscanned_ranges.iter().any(|range| {
+            range.start <= anchor_height
+                && range.end > birthday_height
+                && shard_start < range.end
+                && shard_end.map_or(true, |shard_end| range.start <= shard_end)
+        })
+    }
+
+    /// Returns the range of block heights that may contain the leaves of the note commitment
+    /// tree shard containing the given position: from the end height of the previous shard (or
+    /// the pool's activation height) to the end height of the shard itself, if it is known.
+    pub(crate) fn shard_block_range(
+        &self,
+        protocol: ShieldedProtocol,
+        position: Position,
+    ) -> (BlockHeight, Option<BlockHeight>) {
+        let (shard_height, shard_end_heights, activation_height) = match protocol {
+            ShieldedProtocol::Sapling => (
+                SAPLING_SHARD_HEIGHT,
+                &self.sapling_tree_shard_end_heights,
+                self.params.activation_height(NetworkUpgrade::Sapling),
+            ),
+            #[cfg(feature = "orchard")]
+            ShieldedProtocol::Orchard => (
+                ORCHARD_SHARD_HEIGHT,
+                &self.orchard_tree_shard_end_heights,
+                self.params.activation_height(NetworkUpgrade::Nu5),
+            ),
+            #[cfg(not(feature = "orchard"))]
+            ShieldedProtocol::Orchard => return (BlockHeight::from(0), None),
+        };
+        let shard = Address::above_position(shard_height.into(), position);
+        let shard_start = shard
+            .index()
+            .checked_sub(1)
+            .and_then(|index| {
+                shard_end_heights
+                    .get(&Address::from_parts(shard_height.into(), index))
+                    .copied()
+            })
+            .or(activation_height)
+            .unwrap_or(BlockHeight::from(0));
+        (shard_start, shard_end_heights.get(&shard).copied())
+    }
+
+    /// Returns whether a witness for the given note can be computed at the checkpoint for the
+    /// given anchor height. A note for which no witness is available cannot be spent with that
+    /// anchor.
+    pub(crate) fn note_is_witnessable(
+        &self,
+        note: &ReceivedNote,
+        anchor_height: BlockHeight,
     ) -> Result<bool, Error> {
-        if let (Some(summary_height), Some(received_height)) = (
-            self.summary_height(min_confirmations)?,
-            self.tx_table
-                .get(&note.txid())
-                .ok_or_else(|| Error::TransactionNotFound(note.txid()))?
-                .mined_height(),
-        ) {
-            Ok(received_height > summary_height)
-        } else {
-            Ok(true) // no summary height or note not mined means it's pending
+        let position = match note.commitment_tree_position {
+            Some(position) => position,
+            None => return Ok(false),
+        };
+        match note.note.protocol() {
+            ShieldedProtocol::Sapling => {
+                is_witnessable_at(&self.sapling_tree, position, &anchor_height)
+            }
+            #[cfg(feature = "orchard")]
+            ShieldedProtocol::Orchard => {
+                is_witnessable_at(&self.orchard_tree, position, &anchor_height)
+            }
+            #[cfg(not(feature = "orchard"))]
+            ShieldedProtocol::Orchard => Ok(false),
         }
     }
 
@@ -470,6 +868,53 @@ impl<P: consensus::Parameters> MemoryWalletDb<P> {
         Ok(())
     }
 
+    /// Derives the spending key for a ZIP 32 account from the given seed, and adds the account
+    /// to the wallet. If no account index is given, the index following the greatest index of
+    /// any account already derived from the seed is used.
+    ///
+    /// The seed is only borrowed, and is never copied out of its zeroizing container.
+    #[cfg(feature = "hd-derivation")]
+    pub(crate) fn add_derived_account(
+        &mut self,
+        seed: &SecretVec<u8>,
+        account_index: Option<zip32::AccountId>,
+        birthday: &AccountBirthday,
+    ) -> Result<(Account, UnifiedSpendingKey), Error> {
+        let seed_fingerprint =
+            SeedFingerprint::from_seed(seed.expose_secret()).ok_or(Error::InvalidSeedLength)?;
+        let account_index = match account_index {
+            Some(account_index) => {
+                if let Some((id, _)) = self.accounts.iter().find(|(_, a)| {
+                    a.source()
+                        == AccountSource::Derived {
+                            seed_fingerprint,
+                            account_index,
+                        }
+                }) {
+                    return Err(Error::AccountCollision(*id));
+                }
+                account_index
+            }
+            None => self
+                .max_zip32_account_index(&seed_fingerprint)?
+                .map(|a| a.next().ok_or(Error::AccountOutOfRange))
+                .transpose()?
+                .unwrap_or(zip32::AccountId::ZERO),
+        };
+
+        let usk = UnifiedSpendingKey::from_seed(&self.params, seed.expose_secret(), account_index)?;
+        let (_, account) = self.add_account(
+            AccountSource::Derived {
+                seed_fingerprint,
+                account_index,
+            },
+            usk.to_unified_full_viewing_key(),
+            birthday.clone(),
+        )?;
+
+        Ok((account, usk))
+    }
+
     pub(crate) fn max_zip32_account_index(
         &self,
         seed_fingerprint: &SeedFingerprint,
@@ -527,19 +972,8 @@ impl<P: consensus::Parameters> MemoryWalletDb<P> {
                 self.nullifiers
                     .insert(block_height, *tx_index as u32, Nullifier::Sapling(*nf));
             }
-            match self.tx_locator.entry((block_height, *tx_index as u32)) {
-                Entry::Occupied(x) => {
-                    if txid == x.get() {
-                        // This is a duplicate entry
-                        continue;
-                    } else {
-                        return Err(Error::ConflictingTxLocator);
-                    }
-                }
-                Entry::Vacant(entry) => {
-                    entry.insert(*txid);
-                }
-            }
+            self.tx_locator
+                .locate(block_height, *tx_index as u32, *txid)?;
         }
         Ok(())
     }
@@ -555,20 +989,118 @@ impl<P: consensus::Parameters> MemoryWalletDb<P> {
                 self.nullifiers
                     .insert(block_height, *tx_index as u32, Nullifier::Orchard(*nf));
             }
-            match self.tx_locator.entry((block_height, *tx_index as u32)) {
-                Entry::Occupied(x) => {
-                    if txid == x.get() {
-                        // This is a duplicate entry
-                        continue;
-                    } else {
-                        return Err(Error::ConflictingTxLocator);
-                    }
-                }
-                Entry::Vacant(entry) => {
-                    entry.insert(*txid);
+            self.tx_locator
+                .locate(block_height, *tx_index as u32, *txid)?;
+        }
+        Ok(())
+    }
+
+    /// Returns whether a transaction located at the given height by scanning is in a block of
+    /// the chain that the wallet has currently scanned, rather than one that has since been
+    /// truncated.
+    fn is_scanned_location(&self, height: BlockHeight) -> bool {
+        self.blocks.contains_key(&height)
+            && self
+                .scanned_heights
+                .max_height()
+                .map_or(false, |tip| height <= tip)
+    }
+
+    /// Marks the given transaction as mined at the location recorded for it in the transaction
+    /// locator map, if the transaction table has an entry for it that is not already marked as
+    /// mined.
+    ///
+    /// Every transaction in a scanned block is located, but a transaction table entry is only
+    /// created once the wallet has a reason to store the transaction, which may be after its
+    /// block was scanned. Locations above the wallet's scanned tip, or in blocks that have
+    /// since been truncated, are ignored. Returns whether the entry was updated.
+    pub(crate) fn backfill_tx_location(&mut self, txid: &TxId) -> bool {
+        match self.tx_locator.find(txid) {
+            Some((height, index)) if self.is_scanned_location(height) => {
+                self.tx_table.backfill_mined_location(txid, height, index)
+            }
+            _ => false,
+        }
+    }
+
+    /// Applies [`Self::backfill_tx_location`] to every transaction located in the block at the
+    /// given height, returning the number of transaction table entries that were updated.
+    ///
+    /// This picks up transactions that were stored before their block was scanned, such as
+    /// those decrypted from the mempool, which are not otherwise detected by scanning unless
+    /// they involve the wallet's shielded notes.
+    pub(crate) fn backfill_block_tx_locations(&mut self, height: BlockHeight) -> usize {
+        let located = self
+            .tx_locator
+            .range((height, 0)..=(height, u32::MAX))
+            .map(|(_, txid)| *txid)
+            .collect::<Vec<_>>();
+
+        let mut updated = 0;
+        for txid in located {
+            if self.backfill_tx_location(&txid) {
+                updated += 1;
+            }
+        }
+        updated
+    }
+
+    /// Applies [`Self::backfill_tx_location`] to every located transaction, returning the
+    /// number of transaction table entries that were updated.
+    pub(crate) fn backfill_tx_locations(&mut self) -> usize {
+        let located = self
+            .tx_locator
+            .iter()
+            .filter(|((height, _), _)| self.is_scanned_location(*height))
+            .map(|((height, index), txid)| (*txid, *height, *index))
+            .collect::<Vec<_>>();
+
+        let mut updated = 0;
+        for (txid, height, index) in located {
+            if self.tx_table.backfill_mined_location(&txid, height, index) {
+                updated += 1;
+            }
+        }
+        updated
+    }
+
+    /// Checks that the location of each mined transaction in the transaction table agrees with
+    /// the transaction locator map.
+    ///
+    /// No two transactions may be mined at the same location, and a transaction that is mined in
+    /// a block in which it was located must be mined at the index at which it was located.
+    /// Locators in blocks that are no longer present are ignored, as are locators for
+    /// transactions that are not mined according to the transaction table, since the locator
+    /// map may retain the locations of transactions in blocks that have since been reorganized
+    /// out of the chain.
+    pub(crate) fn check_tx_locations(&self) -> Result<(), Error> {
+        let corrupted = |txid: &TxId, (height, index): (BlockHeight, u32)| {
+            Error::CorruptedData(format!(
+                "Transaction {} is recorded at index {} of block {}, which disagrees with the transaction locator",
+                txid, index, height
+            ))
+        };
+
+        let mut mined_at = BTreeMap::new();
+        for (txid, tx) in self.tx_table.iter() {
+            if let Some(location) = tx.mined_location() {
+                if mined_at.insert(location, txid).is_some() {
+                    return Err(corrupted(txid, location));
                 }
             }
         }
+
+        for ((height, index), txid) in self.tx_locator.iter() {
+            if !self.blocks.contains_key(height) {
+                continue;
+            }
+            let Some(location) = self.tx_table.get(txid).and_then(|tx| tx.mined_location()) else {
+                continue;
+            };
+            if location.0 == *height && location.1 != *index {
+                return Err(corrupted(txid, location));
+            }
+        }
         Ok(())
     }
 
@@ -595,77 +1127,60 @@ impl<P: consensus::Parameters> MemoryWalletDb<P> {
         self.orchard_tree_shard_end_heights.values().max().copied()
     }
 
-    pub(crate) fn get_sapling_max_checkpointed_height(
+    /// Returns the greatest height, at least `min_confirmations` blocks deep relative to the
+    /// chain tip, at which every shielded pool's note commitment tree has a checkpoint.
+    ///
+    /// Checkpoints are not always inserted into both trees at the same heights (for example,
+    /// synthetic checkpoints below a tree's minimum retained checkpoint are skipped), and an
+    /// anchor at which one of the trees has no checkpoint cannot be used to witness notes from
+    /// that pool. A tree that has no checkpoints at all does not constrain the result.
+    pub(crate) fn get_max_checkpointed_height(
         &self,
         chain_tip_height: BlockHeight,
         min_confirmations: NonZeroU32,
     ) -> Result<Option<BlockHeight>, Error> {
         let max_checkpoint_height =
             u32::from(chain_tip_height).saturating_sub(u32::from(min_confirmations) - 1);
-        // scan backward and find the first checkpoint that matches a blockheight prior to max_checkpoint_height
-        for height in (0..=max_checkpoint_height).rev() {
-            let height = BlockHeight::from_u32(height);
-            if self.sapling_tree.store().get_checkpoint(&height)?.is_some() {
-                return Ok(Some(height));
-            }
+
+        let sapling_checkpointed = self.sapling_tree.store().checkpoint_count()? > 0;
+        #[cfg(feature = "orchard")]
+        let orchard_checkpointed = self.orchard_tree.store().checkpoint_count()? > 0;
+        #[cfg(not(feature = "orchard"))]
+        let orchard_checkpointed = false;
+        if !(sapling_checkpointed || orchard_checkpointed) {
+            return Ok(None);
         }
-        Ok(None)
-    }
 
-    #[cfg(feature = "orchard")]
-    pub(crate) fn get_orchard_max_checkpointed_height(
-        &self,
-        chain_tip_height: BlockHeight,
-        min_confirmations: NonZeroU32,
-    ) -> Result<Option<BlockHeight>, Error> {
-        let max_checkpoint_height =
-            u32::from(chain_tip_height).saturating_sub(u32::from(min_confirmations) - 1);
-        // scan backward and find the first checkpoint that matches a blockheight prior to max_checkpoint_height
+        // scan backward and find the first height prior to max_checkpoint_height at which
+        // every pool that has checkpoints has one
         for height in (0..=max_checkpoint_height).rev() {
             let height = BlockHeight::from_u32(height);
-            if self.orchard_tree.store().get_checkpoint(&height)?.is_some() {
+            let sapling_ok = !sapling_checkpointed
+                || self.sapling_tree.store().get_checkpoint(&height)?.is_some();
+            #[cfg(feature = "orchard")]
+            let orchard_ok = !orchard_checkpointed
+                || self.orchard_tree.store().get_checkpoint(&height)?.is_some();
+            #[cfg(not(feature = "orchard"))]
+            let orchard_ok = true;
+
+            if sapling_ok && orchard_ok {
                 return Ok(Some(height));
             }
         }
         Ok(None)
     }
 
-    /// Get the unscanned ranges from the scan queue and their corresponding sapling tree indices
-    /// This can be used to determine if a note is in an unscanned range and therefore not spendable
-    pub(crate) fn unscanned_ranges(
-        &self,
-    ) -> Vec<(BlockHeight, BlockHeight, Option<Position>, Option<Position>)> {
+    /// Returns the block ranges in the scan queue that have not yet been scanned. These can be
+    /// used to determine whether a note lies in a shard that cannot yet be witnessed, and is
+    /// therefore not spendable.
+    pub(crate) fn unscanned_ranges(&self) -> Vec<Range<BlockHeight>> {
         self.scan_queue
             .iter()
             .filter(|(_, _, priority)| priority > &ScanPriority::Scanned)
-            .map(|(start, end, _)| {
-                (
-                    *start,
-                    *end,
-                    self.first_subtree_for_height(start)
-                        .map(|a| a.position_range_start()),
-                    self.last_subtree_for_height(end)
-                        .map(|a| a.position_range_end()),
-                )
-            })
+            .map(|(start, end, _)| *start..*end)
             .collect()
     }
 
-    /// Return the address of the last subtree in the sapling tree where note for a give block height was found
-    pub(crate) fn last_subtree_for_height(&self, height: &BlockHeight) -> Option<Address> {
-        self.sapling_tree_shard_end_heights
-            .iter()
-            .filter(|(_, h)| *h == height)
-            .map(|(a, _)| *a)
-            .max()
-    }
-
-    ///  Return the address of the first subtree in the sapling tree where note for a give block height was found
-    pub(crate) fn first_subtree_for_height(&self, height: &BlockHeight) -> Option<Address> {
-        // The first subtree is the last subtree for the previous height
-        self.last_subtree_for_height(&height.saturating_sub(1))
-    }
-
     /// Makes the required changes to the scan queue to reflect the completion of a scan
     pub(crate) fn scan_complete(
         &mut self,
@@ -783,7 +1298,7 @@ impl<P: consensus::Parameters> MemoryWalletDb<P> {
                             .cloned())
                     }
                     #[cfg(not(feature = "orchard"))]
-                    panic!("Unsupported pool")
+                    Err(Error::OrchardNotEnabled)
                 }
             }
         };
@@ -822,161 +1337,6 @@ impl<P: consensus::Parameters> MemoryWalletDb<P> {
         &self.sent_notes
     }
 
-    pub(crate) fn sapling_scan_progress(
-        &self,
-        birthday_height: &BlockHeight,
-        fully_scanned_height: &BlockHeight,
-        chain_tip_height: &BlockHeight,
-    ) -> Result<Option<Ratio<u64>>, Error> {
-        if fully_scanned_height == chain_tip_height {
-            let outputs_sum = self
-                .blocks
-                .iter()
-                .filter(|(height, _)| height >= &birthday_height)
-                .fold(0, |sum, (_, block)| {
-                    sum + block.sapling_output_count.unwrap_or(0)
-                });
-            Ok(Some(Ratio::new(outputs_sum as u64, outputs_sum as u64)))
-        } else {
-            // Get the starting note commitment tree size from the wallet birthday, or failing that
-            // from the blocks table.
-            let start_size = self
-                .accounts
-                .iter()
-                .filter_map(|(_, account)| {
-                    if account.birthday().height() == *birthday_height {
-                        Some(account.birthday().sapling_frontier().tree_size())
-                    } else {
-                        None
-                    }
-                })
-                .next()
-                .or_else(|| {
-                    self.blocks
-                        .iter()
-                        .filter(|(height, _)| height <= &birthday_height)
-                        .map(|(_, block)| {
-                            (block.sapling_commitment_tree_size.unwrap_or(0)
-                                - block.sapling_output_count.unwrap_or(0))
-                                as u64
-                        })
-                        .max()
-                });
-
-            // Compute the total blocks scanned so far above the starting height
-            let scanned_count = self
-                .blocks
-                .iter()
-                .filter(|(height, _)| height > &birthday_height)
-                .fold(0_u64, |acc, (_, block)| {
-                    acc + block.sapling_output_count.unwrap_or(0) as u64
-                });
-
-            // We don't have complete information on how many outputs will exist in the shard at
-            // the chain tip without having scanned the chain tip block, so we overestimate by
-            // computing the maximum possible number of notes directly from the shard indices.
-            //
-            // TODO: it would be nice to be able to reliably have the size of the commitment tree
-            // at the chain tip without having to have scanned that block.
-
-            let shard_index_iter = self
-                .sapling_tree_shard_end_heights
-                .iter()
-                .filter(|(_, height)| height > &birthday_height)
-                .map(|(address, _)| address.index());
-
-            let min_idx = shard_index_iter.clone().min().unwrap_or(0);
-            let max_idx = shard_index_iter.max().unwrap_or(0);
-
-            let max_tree_size = Some(min_idx << SAPLING_SHARD_HEIGHT);
-            let min_tree_size = Some((max_idx + 1) << SAPLING_SHARD_HEIGHT);
-
-            Ok(start_size.or(min_tree_size).zip(max_tree_size).map(
-                |(min_tree_size, max_tree_size)| {
-                    Ratio::new(scanned_count, max_tree_size - min_tree_size)
-                },
-            ))
-        }
-    }
-
-    #[cfg(feature = "orchard")]
-    pub(crate) fn orchard_scan_progress(
-        &self,
-        birthday_height: &BlockHeight,
-        fully_scanned_height: &BlockHeight,
-        chain_tip_height: &BlockHeight,
-    ) -> Result<Option<Ratio<u64>>, Error> {
-        if fully_scanned_height == chain_tip_height {
-            let outputs_sum = self
-                .blocks
-                .iter()
-                .filter(|(height, _)| height >= &birthday_height)
-                .fold(0, |sum, (_, block)| {
-                    sum + block.orchard_action_count.unwrap_or(0)
-                });
-            Ok(Some(Ratio::new(outputs_sum as u64, outputs_sum as u64)))
-        } else {
-            // Get the starting note commitment tree size from the wallet birthday, or failing that
-            // from the blocks table.
-            let start_size = self
-                .accounts
-                .iter()
-                .filter_map(|(_, account)| {
-                    if account.birthday().height() == *birthday_height {
-                        Some(account.birthday().sapling_frontier().tree_size())
-                    } else {
-                        None
-                    }
-                })
-                .next()
-                .or_else(|| {
-                    self.blocks
-                        .iter()
-                        .filter(|(height, _)| height <= &birthday_height)
-                        .map(|(_, block)| {
-                            (block.orchard_commitment_tree_size.unwrap_or(0)
-                                - block.orchard_action_count.unwrap_or(0))
-                                as u64
-                        })
-                        .max()
-                });
-
-            // Compute the total blocks scanned so far above the starting height
-            let scanned_count = self
-                .blocks
-                .iter()
-                .filter(|(height, _)| height > &birthday_height)
-                .fold(0_u64, |acc, (_, block)| {
-                    acc + block.orchard_action_count.unwrap_or(0) as u64
-                });
-
-            // We don't have complete information on how many outputs will exist in the shard at
-            // the chain tip without having scanned the chain tip block, so we overestimate by
-            // computing the maximum possible number of notes directly from the shard indices.
-            //
-            // TODO: it would be nice to be able to reliably have the size of the commitment tree
-            // at the chain tip without having to have scanned that block.
-
-            let shard_index_iter = self
-                .orchard_tree_shard_end_heights
-                .iter()
-                .filter(|(_, height)| height > &birthday_height)
-                .map(|(address, _)| address.index());
-
-            let min_idx = shard_index_iter.clone().min().unwrap_or(0);
-            let max_idx = shard_index_iter.max().unwrap_or(0);
-
-            let max_tree_size = Some(min_idx << ORCHARD_SHARD_HEIGHT);
-            let min_tree_size = Some((max_idx + 1) << ORCHARD_SHARD_HEIGHT);
-
-            Ok(start_size.or(min_tree_size).zip(max_tree_size).map(
-                |(min_tree_size, max_tree_size)| {
-                    Ratio::new(scanned_count, max_tree_size - min_tree_size)
-                },
-            ))
-        }
-    }
-
     #[cfg(feature = "transparent-inputs")]
     pub(crate) fn find_account_for_transparent_address(
         &self,
@@ -985,16 +1345,56 @@ impl<P: consensus::Parameters> MemoryWalletDb<P> {
         self.accounts.find_account_for_transparent_address(address)
     }
 
+    /// Adds the value of the unspent transparent outputs received by each account to its
+    /// balance, as of the next block to be mined.
+    ///
+    /// This follows the sqlite backend: an output counts if the transaction that created it
+    /// has been mined or has not expired, and it is spent once a transaction that spends it
+    /// has been mined or has not expired.
+    #[cfg(feature = "transparent-inputs")]
+    pub(crate) fn add_transparent_account_balances(
+        &self,
+        chain_tip_height: BlockHeight,
+        account_balances: &mut std::collections::HashMap<AccountId, AccountBalance>,
+    ) -> Result<(), Error> {
+        let mempool_height = chain_tip_height + 1;
+        for (outpoint, txo) in self.transparent_received_outputs.iter() {
+            let Some(tx) = self.tx_table.get(&txo.transaction_id) else {
+                continue;
+            };
+            if !tx.counts_toward_balance_at(mempool_height, chain_tip_height) {
+                continue;
+            }
+            let spent = self
+                .transparent_received_output_spends
+                .get(outpoint)
+                .and_then(|txid| self.tx_table.get(txid))
+                .map_or(false, |spending_tx| {
+                    spending_tx.is_mined_or_unexpired_spend(mempool_height)
+                });
+            if spent {
+                continue;
+            }
+            account_balances
+                .entry(txo.account_id)
+                .or_insert(AccountBalance::ZERO)
+                .add_unshielded_value(txo.txout.value)?;
+        }
+        Ok(())
+    }
+
     pub(crate) fn mark_transparent_output_spent(
         &mut self,
         spent_in_tx: &TxId,
         outpoint: &OutPoint,
     ) -> Result<bool, Error> {
-        // TODO: Remove it from the search queue
-
         self.transparent_received_output_spends
             .insert(outpoint.clone(), *spent_in_tx);
 
+        // Since we know that the output is spent, we no longer need to search for its spend.
+        self.transaction_data_request_queue
+            .remove_spend_detection(outpoint);
+
         // TODO: Check if this is an update and therefore we need to add something to transparent_spend_map
 
         Ok(false)
@@ -1020,6 +1420,7 @@ impl<P: consensus::Parameters> MemoryWalletDb<P> {
         // insert a new tx into the transactions table for the one that spent this output. If there is already one then do an update
         self.tx_table
             .put_tx_partial(&txid, &block, output.mined_height());
+        self.backfill_tx_location(&txid);
 
         // look for a spent_height for this output by querying transparent_received_output_spends.
         // If there isn't one then return None (this is an unspent output)
@@ -1050,16 +1451,20 @@ impl<P: consensus::Parameters> MemoryWalletDb<P> {
             }
         };
 
-        // insert into transparent_received_outputs table. Update if it exists
+        // insert into transparent_received_outputs table. Update if it exists, retaining the
+        // previously observed unspent height if no new observation was made.
         match self
             .transparent_received_outputs
             .entry(output.outpoint().clone())
         {
             Entry::Occupied(mut entry) => {
-                entry.get_mut().transaction_id = txid;
-                entry.get_mut().address = *address;
-                entry.get_mut().account_id = *receiving_account;
-                entry.get_mut().txout = output.txout().clone();
+                let entry = entry.get_mut();
+                entry.transaction_id = txid;
+                entry.address = *address;
+                entry.account_id = *receiving_account;
+                entry.txout = output.txout().clone();
+                entry.max_observed_unspent_height =
+                    max_observed_unspent.or(entry.max_observed_unspent_height);
             }
             Entry::Vacant(entry) => {
                 entry.insert(ReceivedTransparentOutput::new(
@@ -1067,7 +1472,7 @@ impl<P: consensus::Parameters> MemoryWalletDb<P> {
                     *receiving_account,
                     *address,
                     output.txout().clone(),
-                    max_observed_unspent.unwrap_or(BlockHeight::from(0)),
+                    max_observed_unspent,
                 ));
             }
         }
@@ -0,0 +1,388 @@
+use time::OffsetDateTime;
+use zcash_protocol::{memo::Memo, value::Zatoshis, PoolType};
+
+use super::*;
+
+/// A note or transparent output received by the wallet, as yielded by
+/// [`MemoryWalletDb::iter_received_notes`].
+///
+/// Records borrow their transaction ids, memos and addresses from the wallet, so exporting the
+/// wallet's received notes does not copy any note data.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NoteExportRecord<'a> {
+    account_id: AccountId,
+    pool: PoolType,
+    txid: &'a TxId,
+    output_index: u32,
+    value: Zatoshis,
+    mined_height: Option<BlockHeight>,
+    block_time: Option<OffsetDateTime>,
+    spent_in: Option<&'a TxId>,
+    is_change: bool,
+    memo: Option<&'a Memo>,
+    address: Option<&'a TransparentAddress>,
+}
+
+impl<'a> NoteExportRecord<'a> {
+    /// Returns the account that received the note.
+    pub fn account_id(&self) -> AccountId {
+        self.account_id
+    }
+
+    /// Returns the pool in which the note was received.
+    pub fn pool(&self) -> PoolType {
+        self.pool
+    }
+
+    /// Returns the id of the transaction that created the note.
+    pub fn txid(&self) -> &'a TxId {
+        self.txid
+    }
+
+    /// Returns the index of the output, or of the Orchard action, within its transaction.
+    pub fn output_index(&self) -> u32 {
+        self.output_index
+    }
+
+    /// Returns the value of the note.
+    pub fn value(&self) -> Zatoshis {
+        self.value
+    }
+
+    /// Returns the height at which the transaction that created the note was mined, if it
+    /// is known to have been mined.
+    pub fn mined_height(&self) -> Option<BlockHeight> {
+        self.mined_height
+    }
+
+    /// Returns the time of the block in which the note was mined, if that block has been
+    /// scanned by the wallet.
+    pub fn block_time(&self) -> Option<OffsetDateTime> {
+        self.block_time
+    }
+
+    /// Returns the id of the transaction that spends the note, if any. The spending
+    /// transaction is not necessarily mined.
+    pub fn spent_in(&self) -> Option<&'a TxId> {
+        self.spent_in
+    }
+
+    /// Returns whether the note is change created by a transaction sent from this wallet.
+    /// This is always `false` for transparent outputs.
+    pub fn is_change(&self) -> bool {
+        self.is_change
+    }
+
+    /// Returns the memo of the note, or `None` for transparent outputs.
+    pub fn memo(&self) -> Option<&'a Memo> {
+        self.memo
+    }
+
+    /// Returns the address at which a transparent output was received, or `None` for
+    /// shielded notes.
+    pub fn address(&self) -> Option<&'a TransparentAddress> {
+        self.address
+    }
+}
+
+/// Criteria for selecting the records yielded by [`MemoryWalletDb::iter_received_notes`].
+///
+/// The default filter matches every record.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NoteExportFilter {
+    account_id: Option<AccountId>,
+    mined_heights: Option<Range<BlockHeight>>,
+    spent: Option<bool>,
+}
+
+impl NoteExportFilter {
+    /// Returns a filter that matches every record.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only match notes received by the given account.
+    pub fn account(mut self, account_id: AccountId) -> Self {
+        self.account_id = Some(account_id);
+        self
+    }
+
+    /// Only match notes mined within the given range of heights. Notes that are not known
+    /// to have been mined are excluded.
+    pub fn mined_in(mut self, heights: Range<BlockHeight>) -> Self {
+        self.mined_heights = Some(heights);
+        self
+    }
+
+    /// Only match notes that are spent (if `spent` is `true`) or unspent (otherwise).
+    pub fn spent(mut self, spent: bool) -> Self {
+        self.spent = Some(spent);
+        self
+    }
+
+    /// Returns whether the given record satisfies all of the criteria of this filter.
+    pub fn matches(&self, record: &NoteExportRecord<'_>) -> bool {
+        self.account_id
+            .map_or(true, |account_id| record.account_id == account_id)
+            && self.mined_heights.as_ref().map_or(true, |heights| {
+                record
+                    .mined_height
+                    .map_or(false, |height| heights.contains(&height))
+            })
+            && self
+                .spent
+                .map_or(true, |spent| record.spent_in.is_some() == spent)
+    }
+}
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    /// Returns an iterator over every shielded note and transparent output received by the
+    /// wallet, in order of the height at which they were mined. Notes that are not known to
+    /// have been mined are yielded last.
+    ///
+    /// Use [`NoteExportFilter::matches`] to restrict the records, e.g.
+    /// `wallet.iter_received_notes().filter(|r| filter.matches(r))`.
+    pub fn iter_received_notes(&self) -> impl Iterator<Item = NoteExportRecord<'_>> + '_ {
+        let shielded = self.received_notes.iter().map(|note| {
+            self.export_record(
+                note.account_id,
+                PoolType::Shielded(note.note.protocol()),
+                &note.txid,
+                note.output_index,
+                note.note.value(),
+                self.received_note_spends.get(&note.note_id),
+                note.is_change,
+                Some(&note.memo),
+                None,
+            )
+        });
+        let transparent = self
+            .transparent_received_outputs
+            .iter()
+            .map(|(outpoint, output)| {
+                self.export_record(
+                    output.account_id,
+                    PoolType::Transparent,
+                    &output.transaction_id,
+                    outpoint.n(),
+                    output.txout.value,
+                    self.transparent_received_output_spends.get(outpoint),
+                    false,
+                    None,
+                    Some(&output.address),
+                )
+            });
+
+        // The tables are not indexed by height, so only the borrowed records are sorted. The
+        // sort is stable, preserving the insertion order of notes mined in the same block.
+        let mut records = shielded.chain(transparent).collect::<Vec<_>>();
+        records.sort_by_key(|record| (record.mined_height.is_none(), record.mined_height));
+        records.into_iter()
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn export_record<'a>(
+        &'a self,
+        account_id: AccountId,
+        pool: PoolType,
+        txid: &'a TxId,
+        output_index: u32,
+        value: Zatoshis,
+        spent_in: Option<&'a TxId>,
+        is_change: bool,
+        memo: Option<&'a Memo>,
+        address: Option<&'a TransparentAddress>,
+    ) -> NoteExportRecord<'a> {
+        let mined_height = self.tx_table.get(txid).and_then(|tx| tx.mined_height());
+        let block_time = mined_height
+            .and_then(|height| self.blocks.get(&height))
+            .and_then(|block| OffsetDateTime::from_unix_timestamp(block.block_time.into()).ok());
+        NoteExportRecord {
+            account_id,
+            pool,
+            txid,
+            output_index,
+            value,
+            mined_height,
+            block_time,
+            spent_in,
+            is_change,
+            memo,
+            address,
+        }
+    }
+}
+
+/// Writes the given records as CSV, with a header row.
+///
+/// Values are written in zatoshis, and block times as seconds since the Unix epoch. Optional
+/// fields that are absent are left empty.
+#[cfg(feature = "csv-export")]
+pub fn write_note_export_csv<'a, W: std::io::Write>(
+    records: impl IntoIterator<Item = NoteExportRecord<'a>>,
+    mut writer: W,
+) -> std::io::Result<()> {
+    fn optional<T: std::fmt::Display>(value: Option<T>) -> String {
+        value.map_or_else(String::new, |v| v.to_string())
+    }
+
+    writeln!(
+        writer,
+        "account_id,pool,txid,output_index,value,mined_height,block_time,spent_in,is_change"
+    )?;
+    for record in records {
+        writeln!(
+            writer,
+            "{},{},{},{},{},{},{},{},{}",
+            *record.account_id,
+            record.pool,
+            record.txid,
+            record.output_index,
+            record.value.into_u64(),
+            optional(record.mined_height),
+            optional(record.block_time.map(|t| t.unix_timestamp())),
+            optional(record.spent_in),
+            record.is_change,
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use zcash_client_backend::data_api::{
+        testing::{AddressType, TestBuilder},
+        Account as _,
+    };
+    use zcash_primitives::{
+        block::BlockHash,
+        transaction::{components::amount::NonNegativeAmount, TxId},
+    };
+    use zcash_protocol::{PoolType, ShieldedProtocol};
+
+    use super::{NoteExportFilter, NoteExportRecord};
+    use crate::{testing::TestMemDbFactory, MemBlockCache};
+
+    // Records borrow their transaction ids from the wallet rather than owning copies.
+    fn borrowed_txid<'a>(record: &NoteExportRecord<'a>) -> &'a TxId {
+        record.txid()
+    }
+
+    #[test]
+    fn received_notes_are_exported_in_height_order() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let account = st.test_account().cloned().unwrap();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+
+        let (h1, _, _) = st.generate_next_block(
+            &dfvk,
+            AddressType::DefaultExternal,
+            NonNegativeAmount::const_from_u64(30000),
+        );
+        let (h2, _, _) = st.generate_next_block(
+            &dfvk,
+            AddressType::DefaultExternal,
+            NonNegativeAmount::const_from_u64(20000),
+        );
+        let (h3, _, _) = st.generate_next_block(
+            &dfvk,
+            AddressType::DefaultExternal,
+            NonNegativeAmount::const_from_u64(10000),
+        );
+        // Scan the last block first so that notes are not stored in height order.
+        st.scan_cached_blocks(h3, 1);
+        st.scan_cached_blocks(h1, 2);
+
+        let wallet = st.wallet();
+        let records = wallet.iter_received_notes().collect::<Vec<_>>();
+        assert_eq!(
+            records.len(),
+            wallet.received_notes.len() + wallet.transparent_received_outputs.len()
+        );
+        assert_eq!(
+            records
+                .iter()
+                .map(|r| (r.mined_height(), r.value()))
+                .collect::<Vec<_>>(),
+            vec![
+                (Some(h1), NonNegativeAmount::const_from_u64(30000)),
+                (Some(h2), NonNegativeAmount::const_from_u64(20000)),
+                (Some(h3), NonNegativeAmount::const_from_u64(10000)),
+            ]
+        );
+        for record in &records {
+            assert_eq!(record.account_id(), account.id());
+            assert_eq!(record.pool(), PoolType::Shielded(ShieldedProtocol::Sapling));
+            assert!(record.block_time().is_some());
+            assert!(record.memo().is_some());
+            assert!(std::ptr::eq(
+                borrowed_txid(record),
+                &wallet
+                    .received_notes
+                    .iter()
+                    .find(|n| n.txid == *record.txid())
+                    .unwrap()
+                    .txid
+            ));
+        }
+
+        let filter = NoteExportFilter::new()
+            .account(account.id())
+            .mined_in(h2..h3 + 1)
+            .spent(false);
+        assert_eq!(
+            wallet
+                .iter_received_notes()
+                .filter(|r| filter.matches(r))
+                .map(|r| r.mined_height())
+                .collect::<Vec<_>>(),
+            vec![Some(h2), Some(h3)]
+        );
+        assert_eq!(
+            wallet
+                .iter_received_notes()
+                .filter(|r| NoteExportFilter::new().spent(true).matches(r))
+                .count(),
+            0
+        );
+        assert_eq!(
+            wallet
+                .iter_received_notes()
+                .filter(|r| NoteExportFilter::new()
+                    .account((*account.id() + 1).into())
+                    .matches(r))
+                .count(),
+            0
+        );
+    }
+
+    #[cfg(feature = "csv-export")]
+    #[test]
+    fn received_notes_are_written_as_csv() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let (h, _, _) = st.generate_next_block(
+            &dfvk,
+            AddressType::DefaultExternal,
+            NonNegativeAmount::const_from_u64(30000),
+        );
+        st.scan_cached_blocks(h, 1);
+
+        let mut csv = vec![];
+        super::write_note_export_csv(st.wallet().iter_received_notes(), &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines = csv.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("account_id,pool,txid"));
+        assert!(lines[1].contains(",Sapling,"));
+        assert!(lines[1].contains(&format!(",30000,{},", u32::from(h))));
+    }
+}
@@ -0,0 +1,422 @@
+use zcash_client_backend::data_api::NullifierQuery;
+
+use super::*;
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    /// Returns the nullifiers of the wallet's received notes in a single shielded pool, along
+    /// with the accounts that received them. This backs both
+    /// [`WalletRead::get_sapling_nullifiers`] and `WalletRead::get_orchard_nullifiers`.
+    ///
+    /// `pool_nullifier` returns the pool-specific nullifier of a note in the pool, and `None`
+    /// for a note in any other pool. Notes whose nullifiers are not yet known are skipped, and
+    /// each account and nullifier pair is returned at most once, even if the note has been
+    /// recorded more than once.
+    ///
+    /// For [`NullifierQuery::Unspent`], notes that have been spent by a mined transaction or by
+    /// a transaction that cannot expire are excluded. Notes spent only by unmined transactions
+    /// that may expire are included, so that scanning continues to detect their spends.
+    pub(crate) fn nullifiers_in_pool<N>(
+        &self,
+        query: NullifierQuery,
+        pool_nullifier: impl Fn(&Nullifier) -> Option<N>,
+    ) -> Vec<(AccountId, N)> {
+        let mut seen = BTreeSet::new();
+        self.received_notes
+            .iter()
+            .filter_map(|note| {
+                let nf = note.nullifier()?;
+                let pool_nf = pool_nullifier(nf)?;
+                let included = match query {
+                    NullifierQuery::All => true,
+                    NullifierQuery::Unspent => !self.spend_is_final(&note.note_id(), nf),
+                };
+                (included && seen.insert((note.account_id(), *nf)))
+                    .then_some((note.account_id(), pool_nf))
+            })
+            .collect()
+    }
+
+    /// Returns whether the received note with the given ID and nullifier has been spent by a
+    /// transaction that has been mined or that cannot expire.
+    fn spend_is_final(&self, note_id: &NoteId, nf: &Nullifier) -> bool {
+        let is_final = |tx: &TransactionEntry| {
+            matches!(tx.status(), TransactionStatus::Mined(_)) || tx.expiry_height().is_none()
+        };
+
+        // A spend observed while scanning was mined, unless a truncation has since unmined the
+        // spending transaction.
+        let observed = self.nullifiers.get(nf).map_or(false, |(height, tx_index)| {
+            self.tx_table
+                .get_by_height_and_index(*height, *tx_index)
+                .map_or(true, is_final)
+        });
+        let recorded = self
+            .received_note_spends
+            .get(note_id)
+            .and_then(|txid| self.tx_table.get(txid))
+            .map_or(false, is_final);
+
+        observed || recorded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::{prelude::*, sample::Index};
+    use zcash_client_backend::{
+        data_api::NullifierQuery,
+        wallet::{Note, NoteId},
+    };
+    use zcash_primitives::{
+        consensus::{BlockHeight, Network},
+        transaction::TxId,
+    };
+    use zcash_protocol::{memo::Memo, ShieldedProtocol};
+
+    use crate::{types::ReceivedNote, AccountId, MemoryWalletDb, Nullifier};
+
+    fn sapling_note() -> Note {
+        Note::Sapling(sapling::Note::from_parts(
+            sapling::PaymentAddress::from_bytes(&[
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x30, 0x8e, 0x11,
+                0x9d, 0x72, 0x99, 0x2b, 0x56, 0x0d, 0x26, 0x50, 0xff, 0xe0, 0xbe, 0x7f, 0x35, 0x42,
+                0xfd, 0x97, 0x00, 0x3c, 0xb7, 0xcc, 0x3a, 0xbf, 0xf8, 0x1a, 0x7f, 0x90, 0x37, 0xf3,
+                0xea,
+            ])
+            .unwrap(),
+            sapling::value::NoteValue::from_raw(99),
+            sapling::Rseed::AfterZip212([0; 32]),
+        ))
+    }
+
+    #[cfg(feature = "orchard")]
+    fn orchard_note() -> Note {
+        let sk = (0..=u8::MAX)
+            .find_map(|i| orchard::keys::SpendingKey::from_bytes([i; 32]).into_option())
+            .unwrap();
+        let recipient =
+            orchard::keys::FullViewingKey::from(&sk).address_at(0u32, zip32::Scope::External);
+        let rho = orchard::note::Rho::from_bytes(&[0; 32]).unwrap();
+        let note = (0..=u8::MAX)
+            .find_map(|i| {
+                orchard::note::RandomSeed::from_bytes([i; 32], &rho)
+                    .into_option()
+                    .and_then(|rseed| {
+                        orchard::Note::from_parts(
+                            recipient,
+                            orchard::value::NoteValue::from_raw(99),
+                            rho,
+                            rseed,
+                        )
+                        .into_option()
+                    })
+            })
+            .unwrap();
+        Note::Orchard(note)
+    }
+
+    /// Returns a distinct nullifier in the given pool for each value of `i`.
+    fn nullifier(protocol: ShieldedProtocol, i: u64) -> Nullifier {
+        let mut bytes = [0; 32];
+        bytes[..8].copy_from_slice(&(i + 1).to_le_bytes());
+        match protocol {
+            ShieldedProtocol::Sapling => Nullifier::Sapling(sapling::Nullifier(bytes)),
+            #[cfg(feature = "orchard")]
+            ShieldedProtocol::Orchard => {
+                Nullifier::Orchard(orchard::note::Nullifier::from_bytes(&bytes).unwrap())
+            }
+            #[cfg(not(feature = "orchard"))]
+            ShieldedProtocol::Orchard => unreachable!(),
+        }
+    }
+
+    fn query(
+        wallet: &MemoryWalletDb<Network>,
+        protocol: ShieldedProtocol,
+        query: NullifierQuery,
+    ) -> Vec<(AccountId, Nullifier)> {
+        match protocol {
+            ShieldedProtocol::Sapling => wallet
+                .nullifiers_in_pool(query, |nf| match nf {
+                    Nullifier::Sapling(nf) => Some(*nf),
+                    #[cfg(feature = "orchard")]
+                    _ => None,
+                })
+                .into_iter()
+                .map(|(account_id, nf)| (account_id, Nullifier::Sapling(nf)))
+                .collect(),
+            #[cfg(feature = "orchard")]
+            ShieldedProtocol::Orchard => wallet
+                .nullifiers_in_pool(query, |nf| match nf {
+                    Nullifier::Orchard(nf) => Some(*nf),
+                    _ => None,
+                })
+                .into_iter()
+                .map(|(account_id, nf)| (account_id, Nullifier::Orchard(nf)))
+                .collect(),
+            #[cfg(not(feature = "orchard"))]
+            ShieldedProtocol::Orchard => unreachable!(),
+        }
+    }
+
+    /// The expected state of a note received in a generated sequence of operations.
+    struct ExpectedNote {
+        account_id: AccountId,
+        nf: Option<Nullifier>,
+        spent: bool,
+    }
+
+    /// An operation applied to a wallet. Notes are selected by an index into the notes
+    /// received so far.
+    #[derive(Clone, Debug)]
+    enum Op {
+        /// Receive a note, in the Orchard pool if `orchard` is set and Orchard is enabled.
+        Receive {
+            orchard: bool,
+            has_nf: bool,
+            account: u32,
+        },
+        /// Record a note a second time.
+        Rerecord(Index),
+        /// Observe the spend of a note while scanning.
+        ObserveSpend(Index),
+        /// Spend a note in a transaction that is then mined.
+        MinedSpend(Index),
+    }
+
+    fn arb_op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            // Notes are received as often as all other operations together, and their
+            // nullifiers are usually known.
+            2 => (any::<bool>(), prop::bool::weighted(0.75), 0u32..2).prop_map(
+                |(orchard, has_nf, account)| Op::Receive {
+                    orchard,
+                    has_nf,
+                    account
+                }
+            ),
+            1 => any::<Index>().prop_map(Op::Rerecord),
+            1 => any::<Index>().prop_map(Op::ObserveSpend),
+            1 => any::<Index>().prop_map(Op::MinedSpend),
+        ]
+    }
+
+    /// Applies a sequence of receives, re-insertions, and mined spends of notes in both pools
+    /// to a wallet, and checks the results of the nullifier queries for each pool against the
+    /// expected state of its notes.
+    fn check_sequence(ops: &[Op]) -> Result<(), TestCaseError> {
+        let mut protocols = vec![ShieldedProtocol::Sapling];
+        #[cfg(feature = "orchard")]
+        protocols.push(ShieldedProtocol::Orchard);
+
+        let mut wallet = MemoryWalletDb::new(Network::TestNetwork, 100);
+        let mut notes: Vec<(NoteId, ShieldedProtocol, ExpectedNote)> = vec![];
+        let mut next_nf = 0;
+        let mut next_txid = 0u8;
+        let mut fresh_txid = || {
+            next_txid = next_txid.wrapping_add(1);
+            TxId::from_bytes([next_txid; 32])
+        };
+
+        for (step, op) in (0u16..).zip(ops) {
+            match op {
+                Op::Receive {
+                    orchard,
+                    has_nf,
+                    account,
+                } => {
+                    let protocol = if *orchard && cfg!(feature = "orchard") {
+                        ShieldedProtocol::Orchard
+                    } else {
+                        ShieldedProtocol::Sapling
+                    };
+                    let note_id = NoteId::new(fresh_txid(), protocol, step);
+                    let nf = has_nf.then(|| {
+                        next_nf += 1;
+                        nullifier(protocol, next_nf)
+                    });
+                    let account_id = AccountId::from(*account);
+                    wallet.received_notes.insert_received_note(ReceivedNote {
+                        note_id,
+                        txid: *note_id.txid(),
+                        output_index: step.into(),
+                        account_id,
+                        note: match protocol {
+                            ShieldedProtocol::Sapling => sapling_note(),
+                            #[cfg(feature = "orchard")]
+                            ShieldedProtocol::Orchard => orchard_note(),
+                            #[cfg(not(feature = "orchard"))]
+                            ShieldedProtocol::Orchard => unreachable!(),
+                        },
+                        nf,
+                        is_change: false,
+                        memo: Memo::Empty,
+                        commitment_tree_position: None,
+                        recipient_key_scope: None,
+                    });
+                    notes.push((
+                        note_id,
+                        protocol,
+                        ExpectedNote {
+                            account_id,
+                            nf,
+                            spent: false,
+                        },
+                    ));
+                }
+                Op::Rerecord(i) if !notes.is_empty() => {
+                    let (note_id, _, _) = &notes[i.index(notes.len())];
+                    let note = wallet
+                        .received_notes
+                        .iter()
+                        .find(|note| &note.note_id() == note_id)
+                        .unwrap()
+                        .clone();
+                    wallet.received_notes.0.push(note);
+                }
+                Op::ObserveSpend(i) if !notes.is_empty() => {
+                    let len = notes.len();
+                    let (_, _, expected) = &mut notes[i.index(len)];
+                    if let Some(nf) = expected.nf {
+                        wallet
+                            .nullifiers
+                            .insert(BlockHeight::from(u32::from(step)), 0, nf);
+                        expected.spent = true;
+                    }
+                }
+                Op::MinedSpend(i) if !notes.is_empty() => {
+                    let len = notes.len();
+                    let (note_id, _, expected) = &mut notes[i.index(len)];
+                    let txid = fresh_txid();
+                    let height = Some(BlockHeight::from(u32::from(step)));
+                    wallet.tx_table.put_tx_partial(&txid, &height, height);
+                    wallet.received_note_spends.insert_spend(*note_id, txid);
+                    expected.spent = true;
+                }
+                _ => {}
+            }
+        }
+
+        for protocol in protocols {
+            let all = query(&wallet, protocol, NullifierQuery::All);
+            let unspent = query(&wallet, protocol, NullifierQuery::Unspent);
+            for (_, _, expected) in notes.iter().filter(|(_, p, _)| *p == protocol) {
+                let Some(nf) = expected.nf else {
+                    continue;
+                };
+                let entry = (expected.account_id, nf);
+                let count = |results: &[(AccountId, Nullifier)]| {
+                    results.iter().filter(|result| **result == entry).count()
+                };
+                prop_assert_eq!(count(&all), 1, "{:?} All", protocol);
+                prop_assert_eq!(
+                    count(&unspent),
+                    usize::from(!expected.spent),
+                    "{:?} Unspent",
+                    protocol
+                );
+            }
+            let expected_count = |spent_filter: bool| {
+                notes
+                    .iter()
+                    .filter(|(_, p, expected)| {
+                        *p == protocol
+                            && expected.nf.is_some()
+                            && (!spent_filter || !expected.spent)
+                    })
+                    .count()
+            };
+            prop_assert_eq!(all.len(), expected_count(false), "{:?}", protocol);
+            prop_assert_eq!(unspent.len(), expected_count(true), "{:?}", protocol);
+        }
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn nullifier_queries_agree_across_pools(ops in prop::collection::vec(arb_op(), 0..40)) {
+            check_sequence(&ops)?;
+        }
+    }
+
+    #[test]
+    fn rescanning_a_spend_does_not_reprocess_the_note() {
+        use std::{convert::Infallible, num::NonZeroU32};
+
+        use sapling::zip32::ExtendedSpendingKey;
+        use zcash_client_backend::{
+            data_api::{
+                testing::{AddressType, TestBuilder},
+                Account as _, WalletRead, WalletTest,
+            },
+            fees::StandardFeeRule,
+            wallet::OvkPolicy,
+        };
+        use zcash_keys::address::Address;
+        use zcash_primitives::{
+            block::BlockHash, transaction::components::amount::NonNegativeAmount,
+        };
+
+        use crate::{testing::TestMemDbFactory, MemBlockCache};
+
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let account = st.test_account().cloned().unwrap();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let (h, _, nf) = st.generate_next_block(
+            &dfvk,
+            AddressType::DefaultExternal,
+            NonNegativeAmount::const_from_u64(60000),
+        );
+        st.scan_cached_blocks(h, 1);
+
+        let not_our_key = ExtendedSpendingKey::master(&[]).to_diversifiable_full_viewing_key();
+        let proposal = st
+            .propose_standard_transfer::<Infallible>(
+                account.id(),
+                StandardFeeRule::Zip317,
+                NonZeroU32::new(1).unwrap(),
+                &Address::Sapling(not_our_key.default_address().1),
+                NonNegativeAmount::const_from_u64(10000),
+                None,
+                None,
+                ShieldedProtocol::Sapling,
+            )
+            .unwrap();
+        let txid = st
+            .create_proposed_transactions::<Infallible, _, Infallible>(
+                account.usk(),
+                OvkPolicy::Sender,
+                &proposal,
+            )
+            .unwrap()[0];
+        let (spent_height, _) = st.generate_next_block_including(txid);
+        st.scan_cached_blocks(spent_height, 1);
+
+        let balance = st.get_total_balance(account.id());
+
+        // Rescanning the block containing the spend leaves the wallet unchanged.
+        for rescan in [false, true] {
+            if rescan {
+                st.scan_cached_blocks(spent_height, 1);
+            }
+            let wallet = st.wallet();
+            let entry = (account.id(), nf);
+            let all = wallet.get_sapling_nullifiers(NullifierQuery::All).unwrap();
+            assert_eq!(all.iter().filter(|e| **e == entry).count(), 1);
+            let unspent = wallet
+                .get_sapling_nullifiers(NullifierQuery::Unspent)
+                .unwrap();
+            assert!(!unspent.contains(&entry));
+            assert_eq!(wallet.received_note_spends.len(), 1);
+
+            let history = wallet.get_tx_history().unwrap();
+            let tx = history.iter().find(|tx| tx.txid() == txid).unwrap();
+            assert_eq!(tx.spent_note_count(), 1);
+            assert_eq!(st.get_total_balance(account.id()), balance);
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use zcash_client_backend::wallet::TransparentAddressMetadata;
+
+use super::*;
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    /// Returns the key derivation metadata needed to sign for each of the given transparent
+    /// outputs, in the order in which the outpoints were provided.
+    ///
+    /// Each outpoint must refer to an output known to the wallet. The key scope and child index
+    /// are resolved against the transparent receivers of the account's unified addresses, its
+    /// legacy transparent address, and its ephemeral addresses. If the receiving address of an
+    /// output cannot be resolved for the account, [`Error::AddressNotRecognized`] is returned
+    /// naming that address.
+    pub fn metadata_for_outpoints(
+        &self,
+        account_id: AccountId,
+        outpoints: &[OutPoint],
+    ) -> Result<Vec<(OutPoint, TransparentAddressMetadata)>, Error> {
+        let account = self
+            .accounts
+            .get(account_id)
+            .ok_or(Error::AccountUnknown(account_id))?;
+
+        outpoints
+            .iter()
+            .map(|outpoint| {
+                let output = self
+                    .transparent_received_outputs
+                    .get(outpoint)
+                    .ok_or_else(|| Error::TransparentOutputNotFound(outpoint.clone()))?;
+                let metadata = account
+                    .transparent_address_metadata(&output.address)?
+                    .ok_or(Error::AddressNotRecognized(output.address))?;
+                Ok((outpoint.clone(), metadata))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::SecretVec;
+    use zcash_client_backend::{
+        data_api::{testing::TestBuilder, Account as _, WalletRead, WalletWrite},
+        wallet::WalletTransparentOutput,
+    };
+    use zcash_primitives::{
+        block::BlockHash,
+        legacy::{keys::TransparentKeyScope, TransparentAddress},
+        transaction::components::{amount::NonNegativeAmount, OutPoint, TxOut},
+    };
+
+    use crate::{testing::TestMemDbFactory, Error, MemBlockCache};
+
+    fn utxo(seed: u8, address: &TransparentAddress) -> WalletTransparentOutput {
+        WalletTransparentOutput::from_parts(
+            OutPoint::new([seed; 32], 0),
+            TxOut {
+                value: NonNegativeAmount::const_from_u64(100000),
+                script_pubkey: address.script(),
+            },
+            None,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn metadata_for_legacy_and_ephemeral_outpoints() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let account_id = st.test_account().unwrap().id();
+
+        let (legacy_addr, legacy_index) = st
+            .wallet()
+            .accounts
+            .get(account_id)
+            .unwrap()
+            .get_legacy_transparent_address()
+            .unwrap()
+            .unwrap();
+        // The last known ephemeral address lies within the gap limit, beyond any reserved index.
+        let (ephemeral_addr, ephemeral_meta) = st
+            .wallet()
+            .get_known_ephemeral_addresses(account_id, None)
+            .unwrap()
+            .pop()
+            .unwrap();
+
+        let legacy_utxo = utxo(1, &legacy_addr);
+        let ephemeral_utxo = utxo(2, &ephemeral_addr);
+        st.wallet_mut()
+            .put_received_transparent_utxo(&legacy_utxo)
+            .unwrap();
+        st.wallet_mut()
+            .put_received_transparent_utxo(&ephemeral_utxo)
+            .unwrap();
+
+        let metadata = st
+            .wallet()
+            .metadata_for_outpoints(
+                account_id,
+                &[
+                    ephemeral_utxo.outpoint().clone(),
+                    legacy_utxo.outpoint().clone(),
+                ],
+            )
+            .unwrap();
+        assert_eq!(metadata.len(), 2);
+        assert_eq!(&metadata[0].0, ephemeral_utxo.outpoint());
+        assert_eq!(metadata[0].1.scope(), TransparentKeyScope::EPHEMERAL);
+        assert_eq!(
+            metadata[0].1.address_index(),
+            ephemeral_meta.address_index()
+        );
+        assert_eq!(&metadata[1].0, legacy_utxo.outpoint());
+        assert_eq!(metadata[1].1.scope(), TransparentKeyScope::EXTERNAL);
+        assert_eq!(metadata[1].1.address_index(), legacy_index);
+    }
+
+    #[test]
+    fn metadata_for_outpoint_at_unknown_address_names_address() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let account_id = st.test_account().unwrap().id();
+        let birthday = st.test_account().unwrap().birthday().clone();
+
+        // Receive an output at an address belonging to a different account.
+        let (other_id, _) = st
+            .wallet_mut()
+            .create_account("other", &SecretVec::new(vec![7u8; 32]), &birthday, None)
+            .unwrap();
+        let (other_addr, _) = st
+            .wallet()
+            .accounts
+            .get(other_id)
+            .unwrap()
+            .get_legacy_transparent_address()
+            .unwrap()
+            .unwrap();
+        let other_utxo = utxo(3, &other_addr);
+        st.wallet_mut()
+            .put_received_transparent_utxo(&other_utxo)
+            .unwrap();
+
+        assert!(matches!(
+            st.wallet()
+                .metadata_for_outpoints(account_id, &[other_utxo.outpoint().clone()]),
+            Err(Error::AddressNotRecognized(addr)) if addr == other_addr
+        ));
+        assert!(matches!(
+            st.wallet()
+                .metadata_for_outpoints(account_id, &[OutPoint::new([4; 32], 0)]),
+            Err(Error::TransparentOutputNotFound(_))
+        ));
+    }
+}
@@ -0,0 +1,171 @@
+use time::OffsetDateTime;
+
+use super::*;
+use crate::proto::memwallet as proto;
+
+/// Diagnostic information about the origin of a wallet and its history of being restored
+/// from serialized snapshots.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalletProvenance {
+    created_at: Option<OffsetDateTime>,
+    created_with_version: Option<String>,
+    restore_count: u32,
+    last_restored_at: Option<OffsetDateTime>,
+}
+
+impl WalletProvenance {
+    /// Returns the provenance of a wallet created now by this version of the crate.
+    pub(crate) fn new() -> Self {
+        Self {
+            created_at: now(),
+            created_with_version: Some(env!("CARGO_PKG_VERSION").to_owned()),
+            restore_count: 0,
+            last_restored_at: None,
+        }
+    }
+
+    /// Returns the provenance of a wallet restored from a snapshot that did not record it.
+    pub(crate) fn unknown() -> Self {
+        Self {
+            created_at: None,
+            created_with_version: None,
+            restore_count: 0,
+            last_restored_at: None,
+        }
+    }
+
+    /// Returns the time at which the wallet was created, or `None` if the wallet was created
+    /// before its provenance was recorded or on a platform without a system clock.
+    pub fn created_at(&self) -> Option<OffsetDateTime> {
+        self.created_at
+    }
+
+    /// Returns the version of this crate with which the wallet was created, or `None` if the
+    /// wallet was created before its provenance was recorded.
+    pub fn created_with_version(&self) -> Option<&str> {
+        self.created_with_version.as_deref()
+    }
+
+    /// Returns the number of times the wallet has been restored from a serialized snapshot.
+    pub fn restore_count(&self) -> u32 {
+        self.restore_count
+    }
+
+    /// Returns the time at which the wallet was most recently restored from a serialized
+    /// snapshot, if it has ever been restored on a platform with a system clock.
+    pub fn last_restored_at(&self) -> Option<OffsetDateTime> {
+        self.last_restored_at
+    }
+
+    pub(crate) fn record_restore(&mut self) {
+        self.restore_count = self.restore_count.saturating_add(1);
+        self.last_restored_at = now();
+    }
+}
+
+/// Returns the current time, truncated to whole seconds so that it survives serialization.
+///
+/// Returns `None` on `wasm32-unknown-unknown`, where the system clock is unavailable.
+pub(super) fn now() -> Option<OffsetDateTime> {
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    {
+        Some(
+            OffsetDateTime::from_unix_timestamp(OffsetDateTime::now_utc().unix_timestamp())
+                .expect("the current time is a valid timestamp"),
+        )
+    }
+    #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+    {
+        None
+    }
+}
+
+pub(super) fn timestamp_from_proto(seconds: i64) -> Result<OffsetDateTime, Error> {
+    OffsetDateTime::from_unix_timestamp(seconds)
+        .map_err(|e| Error::CorruptedData(format!("Invalid timestamp {}: {}", seconds, e)))
+}
+
+impl From<&WalletProvenance> for proto::WalletProvenance {
+    fn from(provenance: &WalletProvenance) -> Self {
+        Self {
+            created_at: provenance.created_at.map(|t| t.unix_timestamp()),
+            created_with_version: provenance.created_with_version.clone(),
+            restore_count: provenance.restore_count,
+            last_restored_at: provenance.last_restored_at.map(|t| t.unix_timestamp()),
+        }
+    }
+}
+
+impl TryFrom<proto::WalletProvenance> for WalletProvenance {
+    type Error = Error;
+
+    fn try_from(provenance: proto::WalletProvenance) -> Result<Self, Self::Error> {
+        Ok(Self {
+            created_at: provenance
+                .created_at
+                .map(timestamp_from_proto)
+                .transpose()?,
+            created_with_version: provenance.created_with_version,
+            restore_count: provenance.restore_count,
+            last_restored_at: provenance
+                .last_restored_at
+                .map(timestamp_from_proto)
+                .transpose()?,
+        })
+    }
+}
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    /// Returns diagnostic information about when and by which version of this crate the wallet
+    /// was created, and how often it has been restored from a serialized snapshot.
+    pub fn provenance(&self) -> &WalletProvenance {
+        &self.provenance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zcash_protocol::consensus::Network;
+
+    use crate::MemoryWalletDb;
+
+    fn roundtrip(wallet: &MemoryWalletDb<Network>) -> MemoryWalletDb<Network> {
+        let mut buf = vec![];
+        wallet.encode(&mut buf).unwrap();
+        MemoryWalletDb::decode_new(&buf[..], Network::MainNetwork, 100).unwrap()
+    }
+
+    #[test]
+    fn restores_are_counted() {
+        let wallet = MemoryWalletDb::new(Network::MainNetwork, 100);
+        let provenance = wallet.provenance().clone();
+        assert!(provenance.created_at().is_some());
+        assert_eq!(
+            provenance.created_with_version(),
+            Some(env!("CARGO_PKG_VERSION"))
+        );
+        assert_eq!(provenance.restore_count(), 0);
+        assert_eq!(provenance.last_restored_at(), None);
+
+        // Serializing the wallet does not alter its provenance.
+        let mut buf = vec![];
+        wallet.encode(&mut buf).unwrap();
+        assert_eq!(wallet.provenance(), &provenance);
+
+        let restored = roundtrip(&wallet);
+        assert_eq!(restored.provenance().restore_count(), 1);
+        assert!(restored.provenance().last_restored_at().is_some());
+        assert_eq!(restored.provenance().created_at(), provenance.created_at());
+        assert_eq!(
+            restored.provenance().created_with_version(),
+            provenance.created_with_version()
+        );
+
+        let restored_again = roundtrip(&restored);
+        assert_eq!(restored_again.provenance().restore_count(), 2);
+        assert_eq!(
+            restored_again.provenance().created_at(),
+            provenance.created_at()
+        );
+    }
+}
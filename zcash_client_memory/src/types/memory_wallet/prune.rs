@@ -0,0 +1,283 @@
+use std::cmp::max;
+
+use incrementalmerkletree::Hashable;
+
+use crate::PRUNING_DEPTH;
+
+use super::*;
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    /// Removes the nullifier map and transaction locator entries for blocks below the given
+    /// height.
+    ///
+    /// These entries record the spends in scanned blocks that did not match any of the wallet's
+    /// known nullifiers, so that a note discovered by scanning an earlier range of blocks can
+    /// be detected as spent. A note discovered after the wallet has scanned every block between
+    /// its birthday and the fully-scanned height can only be spent above that height, so the
+    /// entries below it are only needed to survive a rewind. Entries within [`PRUNING_DEPTH`]
+    /// blocks of the fully-scanned height are therefore always retained, as are the entries
+    /// for the nullifiers of the wallet's own notes; the given height is lowered to that bound
+    /// if necessary.
+    ///
+    /// This is invoked after each batch of scanned blocks is stored.
+    pub fn prune(&mut self, below_height: BlockHeight) -> Result<(), Error> {
+        let prune_height = match self.block_fully_scanned()? {
+            Some(metadata) => min(
+                below_height,
+                metadata.block_height().saturating_sub(PRUNING_DEPTH),
+            ),
+            None => return Ok(()),
+        };
+
+        let wallet_nullifiers = self
+            .received_notes
+            .iter()
+            .filter_map(|note| note.nullifier().copied())
+            .collect::<BTreeSet<_>>();
+        self.nullifiers
+            .0
+            .retain(|nf, (height, _)| *height >= prune_height || wallet_nullifiers.contains(nf));
+
+        let retained_locators = self
+            .nullifiers
+            .values()
+            .filter(|(height, _)| *height < prune_height)
+            .copied()
+            .collect::<BTreeSet<_>>();
+        self.tx_locator
+            .retain(|locator, _| locator.0 >= prune_height || retained_locators.contains(locator));
+
+        Ok(())
+    }
+
+    /// Removes the note commitment tree checkpoints that are no longer needed, retaining the
+    /// newest `max_checkpoints` checkpoints together with the newest checkpoint at or below
+    /// `PRUNING_DEPTH` blocks beneath the chain tip, to which the wallet may need to rewind.
+    ///
+    /// The same checkpoints are retained in the Sapling and Orchard trees, so that anchors
+    /// for spends from both pools can be selected at the same height.
+    ///
+    /// This is invoked after each batch of scanned blocks is stored.
+    pub(crate) fn prune_checkpoints(&mut self) -> Result<(), Error> {
+        let chain_tip = match self.chain_height()? {
+            Some(chain_tip) => chain_tip,
+            None => return Ok(()),
+        };
+
+        let mut tree_checkpoints = checkpoint_ids(&self.sapling_tree)?;
+        #[cfg(feature = "orchard")]
+        tree_checkpoints.extend(checkpoint_ids(&self.orchard_tree)?);
+
+        let mut retained = tree_checkpoints
+            .iter()
+            .rev()
+            .take(self.max_checkpoints)
+            .copied()
+            .collect::<BTreeSet<_>>();
+        if let Some(rewind_checkpoint) = tree_checkpoints
+            .range(..=chain_tip.saturating_sub(PRUNING_DEPTH))
+            .next_back()
+        {
+            retained.insert(*rewind_checkpoint);
+        }
+
+        remove_checkpoints(&mut self.sapling_tree, &retained)?;
+        #[cfg(feature = "orchard")]
+        remove_checkpoints(&mut self.orchard_tree, &retained)?;
+
+        Ok(())
+    }
+}
+
+/// Returns the number of checkpoints with which the note commitment trees of a wallet that
+/// retains `max_checkpoints` checkpoints are constructed.
+///
+/// The trees prune their oldest checkpoints once this many have been added, so the limit must
+/// be high enough that they never discard a checkpoint retained by
+/// [`MemoryWalletDb::prune_checkpoints`].
+pub(crate) fn tree_checkpoint_limit(max_checkpoints: usize) -> usize {
+    max(max_checkpoints, PRUNING_DEPTH as usize + 1)
+}
+
+fn checkpoint_ids<H: Hashable + Clone + PartialEq, const DEPTH: u8, const SHARD_HEIGHT: u8>(
+    tree: &ShardTree<MemoryShardStore<H, BlockHeight>, DEPTH, SHARD_HEIGHT>,
+) -> Result<BTreeSet<BlockHeight>, Error> {
+    let mut ids = BTreeSet::new();
+    tree.store().for_each_checkpoint(usize::MAX, |id, _| {
+        ids.insert(*id);
+        Ok(())
+    })?;
+    Ok(ids)
+}
+
+/// Removes the checkpoints of `tree` that are not in `retained`.
+///
+/// The removal of a mark is deferred until the checkpoint recording it is removed, so the
+/// marks recorded by the removed checkpoints are removed from the tree as well.
+fn remove_checkpoints<H: Hashable + Clone + PartialEq, const DEPTH: u8, const SHARD_HEIGHT: u8>(
+    tree: &mut ShardTree<MemoryShardStore<H, BlockHeight>, DEPTH, SHARD_HEIGHT>,
+    retained: &BTreeSet<BlockHeight>,
+) -> Result<(), Error> {
+    for id in checkpoint_ids(tree)?.difference(retained) {
+        if let Some(checkpoint) = tree.store().get_checkpoint(id)? {
+            for position in checkpoint.marks_removed() {
+                tree.remove_mark(*position, None)?;
+            }
+        }
+        tree.store_mut().remove_checkpoint(id)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, num::NonZeroU32};
+
+    use sapling::zip32::ExtendedSpendingKey;
+    use zcash_client_backend::{
+        data_api::{
+            testing::{AddressType, TestBuilder},
+            Account as _, WalletRead, WalletWrite,
+        },
+        fees::StandardFeeRule,
+        wallet::OvkPolicy,
+    };
+    use zcash_keys::address::Address;
+    use zcash_primitives::{block::BlockHash, transaction::components::amount::NonNegativeAmount};
+    use zcash_protocol::ShieldedProtocol;
+
+    use super::checkpoint_ids;
+    use crate::{testing::TestMemDbFactory, MemBlockCache, MemoryWalletDb, PRUNING_DEPTH};
+
+    #[test]
+    fn nullifier_map_stays_bounded_while_detecting_spends() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let account_id = st.test_account().unwrap().id();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let not_our_key = ExtendedSpendingKey::master(&[]).to_diversifiable_full_viewing_key();
+        let not_our_address = not_our_key.default_address().1;
+        let value = NonNegativeAmount::const_from_u64(10000);
+
+        // Each block contains a spend of a nullifier that does not belong to the wallet.
+        let block_count = 300;
+        let mut first = None;
+        for i in 0u32..block_count {
+            let mut nf = [0; 32];
+            nf[..4].copy_from_slice(&i.to_le_bytes());
+            let (h, _) = st.generate_next_block_spending(
+                &not_our_key,
+                (sapling::Nullifier(nf), value),
+                not_our_address,
+                value,
+            );
+            first.get_or_insert(h);
+        }
+        let first = first.unwrap();
+
+        // The wallet receives a note that is spent in the following block.
+        let (received, _, nf) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        let (spent, _) =
+            st.generate_next_block_spending(&dfvk, (nf, value), not_our_address, value);
+
+        st.scan_cached_blocks(first, block_count as usize);
+        let bound = PRUNING_DEPTH as usize + 1;
+        assert_eq!(
+            st.wallet()
+                .block_fully_scanned()
+                .unwrap()
+                .map(|m| m.block_height()),
+            Some(received - 1)
+        );
+        assert!(st.wallet().nullifiers.len() <= bound);
+        assert!(st.wallet().tx_locator.len() <= bound);
+
+        // Scanning the spend before the note leaves a gap, so the spend must be retained in
+        // the nullifier map until the note is found.
+        st.scan_cached_blocks(spent, 1);
+        st.scan_cached_blocks(received, 1);
+        assert_eq!(st.get_total_balance(account_id), NonNegativeAmount::ZERO);
+        assert!(st.wallet().nullifiers.len() <= bound + 1);
+        assert!(st.wallet().tx_locator.len() <= bound + 1);
+    }
+
+    #[test]
+    fn checkpoints_are_pruned_to_max_checkpoints() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+
+        let max_checkpoints = 10;
+        let mut snapshot = vec![];
+        st.wallet().encode(&mut snapshot).unwrap();
+        let params = st.wallet().params.clone();
+        *st.wallet_mut() =
+            MemoryWalletDb::decode_new(&snapshot[..], params, max_checkpoints).unwrap();
+
+        let account = st.test_account().cloned().unwrap();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let not_our_key = ExtendedSpendingKey::master(&[]).to_diversifiable_full_viewing_key();
+        let (h, _, _) = st.generate_next_block(
+            &dfvk,
+            AddressType::DefaultExternal,
+            NonNegativeAmount::const_from_u64(100000),
+        );
+        let block_count = 150;
+        for _ in 1..block_count {
+            st.generate_next_block(
+                &not_our_key,
+                AddressType::DefaultExternal,
+                NonNegativeAmount::const_from_u64(10000),
+            );
+        }
+        let chain_tip = h + block_count - 1;
+        st.wallet_mut().update_chain_tip(chain_tip).unwrap();
+        st.scan_cached_blocks(h, block_count as usize);
+
+        // The newest checkpoints are retained, along with the checkpoint needed to rewind the
+        // wallet by `PRUNING_DEPTH` blocks.
+        let sapling_checkpoints = checkpoint_ids(&st.wallet().sapling_tree).unwrap();
+        let mut expected = (0..10).map(|i| chain_tip - 9 + i).collect::<Vec<_>>();
+        expected.insert(0, chain_tip - PRUNING_DEPTH);
+        assert_eq!(
+            sapling_checkpoints.iter().copied().collect::<Vec<_>>(),
+            expected
+        );
+        #[cfg(feature = "orchard")]
+        assert_eq!(
+            checkpoint_ids(&st.wallet().orchard_tree).unwrap(),
+            sapling_checkpoints
+        );
+        assert_eq!(
+            st.wallet()
+                .get_target_and_anchor_heights(NonZeroU32::new(10).unwrap())
+                .unwrap(),
+            Some((chain_tip + 1, chain_tip - 9))
+        );
+
+        // A note can still be spent at an anchor 10 blocks below the chain tip.
+        let proposal = st
+            .propose_standard_transfer::<Infallible>(
+                account.id(),
+                StandardFeeRule::Zip317,
+                NonZeroU32::new(10).unwrap(),
+                &Address::Sapling(not_our_key.default_address().1),
+                NonNegativeAmount::const_from_u64(20000),
+                None,
+                None,
+                ShieldedProtocol::Sapling,
+            )
+            .unwrap();
+        st.create_proposed_transactions::<Infallible, _, Infallible>(
+            account.usk(),
+            OvkPolicy::Sender,
+            &proposal,
+        )
+        .unwrap();
+    }
+}
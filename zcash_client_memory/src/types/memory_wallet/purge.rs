@@ -0,0 +1,195 @@
+use zcash_client_backend::data_api::TransactionDataRequest;
+
+use super::*;
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    /// Removes wallet-created transactions that can no longer be mined.
+    ///
+    /// A transaction created by this wallet is considered permanently invalid if it has not
+    /// been mined and its expiry height is below the wallet's fully-scanned height. This
+    /// typically happens when a truncation discards the blocks containing the transaction's
+    /// anchor, and the chain is subsequently rescanned past the transaction's expiry height
+    /// without the transaction having been mined.
+    ///
+    /// The sent notes and provisional received notes of each such transaction are removed,
+    /// and any notes or transparent outputs that it spent are released so that they may be
+    /// spent again. A tombstone recording the height at which the transaction was purged is
+    /// retained and may be queried via [`Self::purged_transaction`].
+    ///
+    /// Transactions whose status can only be determined by querying the chain (those without
+    /// any shielded components) are not removed while a status request for them is pending.
+    ///
+    /// Purging is never performed implicitly: until this method is called, expired
+    /// transactions remain in the wallet and are reported as expired. Callers would
+    /// typically invoke it once the chain has been rescanned following a truncation.
+    ///
+    /// Returns the IDs of the transactions that were removed.
+    pub fn purge_invalidated_transactions(&mut self) -> Result<Vec<TxId>, Error> {
+        let fully_scanned_height = match self.block_fully_scanned()? {
+            Some(metadata) => metadata.block_height(),
+            None => return Ok(vec![]),
+        };
+
+        let invalidated = self
+            .tx_table
+            .iter()
+            .filter(|(txid, tx)| {
+                tx.is_invalidated_wallet_tx(fully_scanned_height)
+                    && !self
+                        .transaction_data_request_queue
+                        .contains(&TransactionDataRequest::GetStatus(**txid))
+            })
+            .map(|(txid, _)| *txid)
+            .collect::<Vec<_>>();
+
+        for txid in &invalidated {
+            tracing::debug!("Purging invalidated transaction {}", txid);
+            self.purge_transaction(txid);
+            self.purged_transactions.insert(*txid, fully_scanned_height);
+        }
+
+        Ok(invalidated)
+    }
+
+    /// Returns the fully-scanned height at which the given transaction was removed by
+    /// [`Self::purge_invalidated_transactions`], or `None` if it has not been purged.
+    pub fn purged_transaction(&self, txid: &TxId) -> Option<BlockHeight> {
+        self.purged_transactions.get(txid).copied()
+    }
+
+    /// Removes all records created by or referring to the given transaction.
+    fn purge_transaction(&mut self, txid: &TxId) {
+        self.tx_table.0.remove(txid);
+        self.sent_notes
+            .0
+            .retain(|note_id, _| note_id.txid() != txid);
+        self.received_notes.0.retain(|note| &note.txid() != txid);
+        self.received_note_spends
+            .0
+            .retain(|_, spending_txid| spending_txid != txid);
+        self.transparent_received_outputs
+            .0
+            .retain(|_, output| &output.transaction_id != txid);
+        self.transparent_received_output_spends
+            .0
+            .retain(|_, spending_txid| spending_txid != txid);
+        self.transparent_spend_map
+            .0
+            .retain(|(spending_txid, _)| spending_txid != txid);
+        self.transaction_data_request_queue
+            .remove_transaction_requests(txid);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, num::NonZeroU32};
+
+    use sapling::zip32::ExtendedSpendingKey;
+    use zcash_client_backend::{
+        data_api::{
+            testing::{AddressType, TestBuilder},
+            Account as _, WalletTest, WalletWrite,
+        },
+        fees::StandardFeeRule,
+        wallet::OvkPolicy,
+    };
+    use zcash_keys::address::Address;
+    use zcash_primitives::{block::BlockHash, transaction::components::amount::NonNegativeAmount};
+    use zcash_protocol::ShieldedProtocol;
+
+    use crate::{testing::TestMemDbFactory, MemBlockCache};
+
+    #[test]
+    fn purge_send_invalidated_by_truncation() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let account = st.test_account().cloned().unwrap();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let not_our_key = ExtendedSpendingKey::master(&[]).to_diversifiable_full_viewing_key();
+
+        let value = NonNegativeAmount::const_from_u64(60000);
+        let (h1, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        st.scan_cached_blocks(h1, 1);
+
+        let to = Address::Sapling(not_our_key.default_address().1);
+        let proposal = st
+            .propose_standard_transfer::<Infallible>(
+                account.id(),
+                StandardFeeRule::Zip317,
+                NonZeroU32::new(1).unwrap(),
+                &to,
+                NonNegativeAmount::const_from_u64(10000),
+                None,
+                None,
+                ShieldedProtocol::Sapling,
+            )
+            .unwrap();
+        let txid = st
+            .create_proposed_transactions::<Infallible, _, Infallible>(
+                account.usk(),
+                OvkPolicy::Sender,
+                &proposal,
+            )
+            .unwrap()[0];
+
+        // Scan a few blocks that do not include the transaction, then truncate them away.
+        for _ in 0..5 {
+            st.generate_next_block(&not_our_key, AddressType::DefaultExternal, value);
+        }
+        st.scan_cached_blocks(h1 + 1, 5);
+        st.truncate_to_height(h1);
+
+        // The transaction has not yet expired, so it survives the truncation.
+        assert_eq!(st.wallet().purged_transaction(&txid), None);
+        assert!(st
+            .wallet()
+            .get_tx_history()
+            .unwrap()
+            .iter()
+            .any(|tx| tx.txid() == txid));
+
+        // Rescan past the transaction's expiry height without it ever being mined.
+        for _ in 0..45 {
+            st.generate_next_block(&not_our_key, AddressType::DefaultExternal, value);
+        }
+        st.scan_cached_blocks(h1 + 1, 45);
+
+        // Updating the chain tip does not purge the transaction; it remains visible as expired
+        // until it is explicitly purged.
+        st.wallet_mut().update_chain_tip(h1 + 45).unwrap();
+        assert_eq!(st.wallet().purged_transaction(&txid), None);
+        assert!(st
+            .wallet()
+            .tx_table
+            .get(&txid)
+            .unwrap()
+            .is_expired_unmined(h1 + 45));
+
+        assert_eq!(
+            st.wallet_mut().purge_invalidated_transactions().unwrap(),
+            vec![txid]
+        );
+        assert!(st
+            .wallet()
+            .get_tx_history()
+            .unwrap()
+            .iter()
+            .all(|tx| tx.txid() != txid));
+        assert!(st.wallet().get_sent_outputs(&txid).unwrap().is_empty());
+        assert_eq!(st.wallet().purged_transaction(&txid), Some(h1 + 45));
+
+        // The note spent by the purged transaction is spendable again.
+        assert_eq!(st.get_spendable_balance(account.id(), 1), value);
+
+        // Purging is idempotent.
+        assert!(st
+            .wallet_mut()
+            .purge_invalidated_transactions()
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,753 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use incrementalmerkletree::Hashable;
+use prost::Message;
+use shardtree::store::{Checkpoint, TreeState};
+use zcash_client_backend::{data_api::TransactionDataRequest, wallet::Recipient};
+use zcash_primitives::block::BlockHash;
+use zcash_protocol::PoolType;
+
+use super::*;
+use crate::error::Result;
+use crate::proto::memwallet as proto;
+
+const REDACTION_PERSONALIZATION: &[u8; 16] = b"ZcashMemRedact__";
+
+/// The version of the redacted diagnostics format written by this crate.
+const REDACTED_DIAGNOSTICS_VERSION: u32 = 1;
+
+/// The length in bytes of the hashes and tokens that replace identifiers.
+const TOKEN_LENGTH: usize = 16;
+
+/// Options controlling the contents of a redacted diagnostic export. See
+/// [`MemoryWalletDb::export_redacted_diagnostics_with`].
+///
+/// This is unstable, and intended only for diagnostics.
+#[derive(Clone)]
+pub struct RedactionOptions {
+    salt: [u8; 32],
+    exact_values: bool,
+}
+
+impl RedactionOptions {
+    /// Returns options under which transaction ids and keys are hashed, and address tokens
+    /// derived, using the given salt, and values are rounded down to a power of ten.
+    ///
+    /// The salt should be chosen at random by the caller. Exports made with the same salt
+    /// replace the same identifiers with the same values, so that they can be compared.
+    /// Anyone who knows the salt can test whether a known transaction, key, or address
+    /// appears in an export, so it must not be shared with the recipient of the export.
+    pub fn new(salt: [u8; 32]) -> Self {
+        RedactionOptions {
+            salt,
+            exact_values: false,
+        }
+    }
+
+    /// Sets whether note, output, and fee values are exported exactly, rather than rounded
+    /// down to a power of ten.
+    pub fn with_exact_values(mut self, exact_values: bool) -> Self {
+        self.exact_values = exact_values;
+        self
+    }
+}
+
+impl fmt::Debug for RedactionOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RedactionOptions")
+            .field("exact_values", &self.exact_values)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Replaces the identifiers and values of a single export.
+struct Redactor<'a> {
+    options: &'a RedactionOptions,
+    /// The position of each account in the wallet, which replaces its id. Account ids are
+    /// derived from the account's keys.
+    accounts: BTreeMap<AccountId, u32>,
+}
+
+impl Redactor<'_> {
+    fn hash(&self, domain: u8, data: &[u8]) -> Vec<u8> {
+        blake2b_simd::Params::new()
+            .hash_length(TOKEN_LENGTH)
+            .key(&self.options.salt)
+            .personal(REDACTION_PERSONALIZATION)
+            .to_state()
+            .update(&[domain])
+            .update(data)
+            .finalize()
+            .as_bytes()
+            .to_vec()
+    }
+
+    fn tx_hash(&self, txid: &TxId) -> Vec<u8> {
+        let bytes: &[u8; 32] = txid.as_ref();
+        self.hash(0, bytes)
+    }
+
+    fn address_token(&self, encoding: &[u8]) -> Vec<u8> {
+        self.hash(1, encoding)
+    }
+
+    fn transparent_token(&self, address: &TransparentAddress) -> Vec<u8> {
+        self.address_token(&address.script().0)
+    }
+
+    fn key_hash(&self, encoding: &[u8]) -> Vec<u8> {
+        self.hash(2, encoding)
+    }
+
+    fn account(&self, account_id: AccountId) -> u32 {
+        self.accounts.get(&account_id).copied().unwrap_or(u32::MAX)
+    }
+
+    fn value(&self, value: u64) -> u64 {
+        if self.options.exact_values || value == 0 {
+            value
+        } else {
+            10u64.pow(value.ilog10())
+        }
+    }
+}
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    /// Returns a description of the structure of the wallet that can be shared with support,
+    /// using the given salt and rounded values. See
+    /// [`MemoryWalletDb::export_redacted_diagnostics_with`].
+    ///
+    /// This is unstable, and intended only for diagnostics.
+    pub fn export_redacted_diagnostics(&self, salt: [u8; 32]) -> Vec<u8> {
+        self.export_redacted_diagnostics_with(&RedactionOptions::new(salt))
+    }
+
+    /// Returns a protobuf-encoded description of the structure of the wallet, from which the
+    /// information that would identify its owner or their funds has been removed:
+    ///
+    /// - viewing keys are replaced by salted hashes, and account ids by the positions of the
+    ///   accounts in the wallet;
+    /// - addresses are replaced by salted tokens, which are the same for each occurrence of
+    ///   an address within the export;
+    /// - transaction ids are replaced by salted hashes;
+    /// - memos and nullifiers are omitted, and nullifiers are only counted;
+    /// - values are rounded down to a power of ten, unless exact values are requested;
+    /// - note commitment trees are reduced to the positions of their shards and checkpoints.
+    ///
+    /// Block heights and hashes, the scan queue, and the scanned ranges are exported as they
+    /// are, and can be loaded with [`MemoryWalletDb::load_redacted_diagnostics`].
+    ///
+    /// This is unstable, and intended only for diagnostics.
+    pub fn export_redacted_diagnostics_with(&self, options: &RedactionOptions) -> Vec<u8> {
+        let redactor = Redactor {
+            options,
+            accounts: self
+                .accounts
+                .accounts
+                .keys()
+                .zip(0..)
+                .map(|(account_id, position)| (*account_id, position))
+                .collect(),
+        };
+
+        let accounts = self
+            .accounts
+            .accounts
+            .values()
+            .map(|account| proto::RedactedAccount {
+                account: redactor.account(account.account_id()),
+                kind: match account.kind() {
+                    AccountSource::Derived { .. } => proto::AccountKind::Derived,
+                    AccountSource::Imported { .. } => proto::AccountKind::Imported,
+                }
+                .into(),
+                viewing_key_hash: redactor
+                    .key_hash(account.viewing_key().encode(&self.params).as_bytes()),
+                birthday_height: account.birthday().height().into(),
+                recover_until: account.birthday().recover_until().map(u32::from),
+                address_count: account.addresses().len() as u32,
+                exposed_address_count: account.exposed_addresses().len() as u32,
+                ephemeral_address_count: account.ephemeral_addresses.len() as u32,
+            })
+            .collect();
+
+        let blocks = self
+            .blocks
+            .values()
+            .map(|block| proto::RedactedBlock {
+                height: block.height.into(),
+                hash: block.hash.0.to_vec(),
+                block_time: block.block_time,
+                transaction_count: block._transactions.len() as u32,
+                sapling_commitment_tree_size: block.sapling_commitment_tree_size,
+                sapling_output_count: block.sapling_output_count,
+                #[cfg(feature = "orchard")]
+                orchard_commitment_tree_size: block.orchard_commitment_tree_size,
+                #[cfg(not(feature = "orchard"))]
+                orchard_commitment_tree_size: None,
+                #[cfg(feature = "orchard")]
+                orchard_action_count: block.orchard_action_count,
+                #[cfg(not(feature = "orchard"))]
+                orchard_action_count: None,
+            })
+            .collect();
+
+        let transactions = self
+            .tx_table
+            .iter()
+            .map(|(txid, tx)| proto::RedactedTransaction {
+                tx_hash: redactor.tx_hash(txid),
+                status: match tx.status() {
+                    TransactionStatus::TxidNotRecognized => {
+                        proto::TransactionStatus::TxidNotRecognized
+                    }
+                    TransactionStatus::NotInMainChain => proto::TransactionStatus::NotInMainChain,
+                    TransactionStatus::Mined(_) => proto::TransactionStatus::Mined,
+                }
+                .into(),
+                mined_height: tx.mined_height().map(u32::from),
+                expiry_height: tx.expiry_height().map(u32::from),
+                target_height: tx.target_height().map(u32::from),
+                fee: tx.fee().map(|fee| redactor.value(fee.into_u64())),
+                has_raw_tx: tx.raw().is_some(),
+                wallet_created: tx.is_wallet_created(),
+            })
+            .collect();
+
+        let received_notes = self
+            .received_notes
+            .iter()
+            .map(|note| proto::RedactedReceivedNote {
+                tx_hash: redactor.tx_hash(&note.txid),
+                protocol: match note.note.protocol() {
+                    ShieldedProtocol::Sapling => proto::ShieldedProtocol::Sapling,
+                    ShieldedProtocol::Orchard => proto::ShieldedProtocol::Orchard,
+                }
+                .into(),
+                output_index: note.output_index,
+                account: redactor.account(note.account_id),
+                value: redactor.value(note.note.value().into_u64()),
+                is_change: note.is_change,
+                commitment_tree_position: note.commitment_tree_position.map(u64::from),
+                has_nullifier: note.nf.is_some(),
+                spent_in_tx_hash: self
+                    .received_note_spends
+                    .get(&note.note_id)
+                    .map(|txid| redactor.tx_hash(txid)),
+            })
+            .collect();
+
+        let sent_notes = self
+            .sent_notes
+            .iter()
+            .map(|(sent_note_id, note)| {
+                let output_index = match sent_note_id {
+                    SentNoteId::Shielded(note_id) => note_id.output_index().into(),
+                    SentNoteId::Transparent { output_index, .. } => *output_index,
+                };
+                let (pool, recipient_token, receiving_account) = match &note.to {
+                    Recipient::External(address, pool) => (
+                        *pool,
+                        Some(redactor.address_token(address.encode().as_bytes())),
+                        None,
+                    ),
+                    Recipient::EphemeralTransparent {
+                        receiving_account,
+                        ephemeral_address,
+                        ..
+                    } => (
+                        PoolType::Transparent,
+                        Some(redactor.transparent_token(ephemeral_address)),
+                        Some(redactor.account(*receiving_account)),
+                    ),
+                    Recipient::InternalAccount {
+                        receiving_account,
+                        external_address,
+                        note,
+                    } => (
+                        PoolType::Shielded(note.protocol()),
+                        external_address
+                            .as_ref()
+                            .map(|address| redactor.address_token(address.encode().as_bytes())),
+                        Some(redactor.account(*receiving_account)),
+                    ),
+                };
+                proto::RedactedSentNote {
+                    tx_hash: redactor.tx_hash(sent_note_id.txid()),
+                    output_index,
+                    from_account: redactor.account(note.from_account_id),
+                    pool: proto_pool_type(pool).into(),
+                    recipient_token,
+                    receiving_account,
+                    value: redactor.value(note.value.into_u64()),
+                }
+            })
+            .collect();
+
+        let transparent_outputs = self
+            .transparent_received_outputs
+            .iter()
+            .map(|(outpoint, output)| proto::RedactedTransparentOutput {
+                tx_hash: redactor.tx_hash(&output.transaction_id),
+                output_index: outpoint.n(),
+                account: redactor.account(output.account_id),
+                address_token: redactor.transparent_token(&output.address),
+                value: redactor.value(output.txout.value.into_u64()),
+                max_observed_unspent_height: output.max_observed_unspent_height.map(u32::from),
+                spent_in_tx_hash: self
+                    .transparent_received_output_spends
+                    .get(outpoint)
+                    .map(|txid| redactor.tx_hash(txid)),
+            })
+            .collect();
+
+        let transaction_data_requests = self
+            .transaction_data_request_queue
+            .iter()
+            .map(|request| match request {
+                TransactionDataRequest::GetStatus(txid)
+                | TransactionDataRequest::Enhancement(txid) => proto::RedactedDataRequest {
+                    request_type: match request {
+                        TransactionDataRequest::GetStatus(_) => {
+                            proto::TransactionDataRequestType::GetStatus
+                        }
+                        _ => proto::TransactionDataRequestType::Enhancement,
+                    }
+                    .into(),
+                    tx_hash: Some(redactor.tx_hash(txid)),
+                    address_token: None,
+                    block_range_start: None,
+                    block_range_end: None,
+                    queued_at_height: self
+                        .transaction_data_request_queue
+                        .queued_at(txid)
+                        .map(u32::from),
+                },
+                #[cfg(feature = "transparent-inputs")]
+                TransactionDataRequest::SpendsFromAddress {
+                    address,
+                    block_range_start,
+                    block_range_end,
+                } => proto::RedactedDataRequest {
+                    request_type: proto::TransactionDataRequestType::SpendsFromAddress.into(),
+                    tx_hash: None,
+                    address_token: Some(redactor.transparent_token(address)),
+                    block_range_start: Some((*block_range_start).into()),
+                    block_range_end: block_range_end.map(u32::from),
+                    queued_at_height: None,
+                },
+            })
+            .collect();
+
+        let diagnostics = proto::RedactedDiagnostics {
+            version: REDACTED_DIAGNOSTICS_VERSION,
+            exact_values: options.exact_values,
+            accounts,
+            blocks,
+            transactions,
+            received_notes,
+            sent_notes,
+            transparent_outputs,
+            transaction_data_requests,
+            scan_queue: self
+                .scan_queue
+                .iter()
+                .map(|r| proto::ScanQueueRecord::from(*r))
+                .collect(),
+            in_progress_scan: self.in_progress_scan.as_ref().map(|range| {
+                proto::ScanQueueRecord::from((
+                    range.block_range().start,
+                    range.block_range().end,
+                    range.priority(),
+                ))
+            }),
+            scanned_heights: self.scanned_heights.iter().map(Into::into).collect(),
+            sapling_tree: Some(
+                tree_shape(&self.sapling_tree, &self.sapling_tree_shard_end_heights)
+                    .expect("the in-memory shard store is infallible"),
+            ),
+            #[cfg(feature = "orchard")]
+            orchard_tree: Some(
+                tree_shape(&self.orchard_tree, &self.orchard_tree_shard_end_heights)
+                    .expect("the in-memory shard store is infallible"),
+            ),
+            #[cfg(not(feature = "orchard"))]
+            orchard_tree: None,
+            nullifier_count: self.nullifiers.len() as u32,
+            purged_transaction_count: self.purged_transactions.len() as u32,
+            reorg_stats: Some((&self.reorg_stats).into()),
+        };
+
+        diagnostics.encode_to_vec()
+    }
+
+    /// Reconstructs a skeleton wallet from a redacted diagnostic export, for reproducing
+    /// problems with scanning and with the checkpointing of the note commitment trees.
+    ///
+    /// The skeleton has no accounts, transactions, or notes. It has the exported blocks, scan
+    /// queue, and scanned ranges, and note commitment trees that hold the exported checkpoints
+    /// and shard end heights but no nodes. Returns an error if the scan queue is not
+    /// consistent, or if a checkpoint lies beyond the size of its tree at the checkpoint's
+    /// height.
+    ///
+    /// This is unstable, and intended only for diagnostics.
+    pub fn load_redacted_diagnostics(
+        bytes: &[u8],
+        params: P,
+        max_checkpoints: usize,
+    ) -> Result<Self> {
+        let diagnostics = proto::RedactedDiagnostics::decode(bytes)?;
+        if diagnostics.version != REDACTED_DIAGNOSTICS_VERSION {
+            return Err(Error::UnsupportedProtoVersion(
+                REDACTED_DIAGNOSTICS_VERSION,
+                diagnostics.version,
+            ));
+        }
+
+        let mut wallet = MemoryWalletDb::new(params, max_checkpoints);
+        wallet.provenance = WalletProvenance::unknown();
+
+        wallet.blocks = diagnostics
+            .blocks
+            .into_iter()
+            .map(|block| {
+                let height = BlockHeight::from(block.height);
+                let hash = BlockHash(
+                    block
+                        .hash
+                        .try_into()
+                        .map_err(Error::ByteVecToArrayConversion)?,
+                );
+                Ok((
+                    height,
+                    MemoryWalletBlock {
+                        height,
+                        hash,
+                        block_time: block.block_time,
+                        _transactions: HashSet::new(),
+                        _memos: HashMap::new(),
+                        sapling_commitment_tree_size: block.sapling_commitment_tree_size,
+                        sapling_output_count: block.sapling_output_count,
+                        #[cfg(feature = "orchard")]
+                        orchard_commitment_tree_size: block.orchard_commitment_tree_size,
+                        #[cfg(feature = "orchard")]
+                        orchard_action_count: block.orchard_action_count,
+                    },
+                ))
+            })
+            .collect::<Result<_>>()?;
+
+        wallet.scan_queue = ScanQueue(
+            diagnostics
+                .scan_queue
+                .into_iter()
+                .map(|item| item.into())
+                .collect(),
+        );
+        wallet.scan_queue.check_consistency()?;
+        wallet.in_progress_scan = diagnostics.in_progress_scan.map(|record| {
+            let (start, end, priority) = record.into();
+            ScanRange::from_parts(start..end, priority)
+        });
+        wallet.scanned_heights = diagnostics
+            .scanned_heights
+            .into_iter()
+            .map(Range::from)
+            .collect();
+
+        if let Some(shape) = diagnostics.sapling_tree {
+            let tree_sizes = wallet
+                .blocks
+                .iter()
+                .map(|(height, block)| (*height, block.sapling_commitment_tree_size))
+                .collect();
+            wallet.sapling_tree_shard_end_heights =
+                restore_tree_shape(&mut wallet.sapling_tree, shape, &tree_sizes)?;
+        }
+        #[cfg(feature = "orchard")]
+        if let Some(shape) = diagnostics.orchard_tree {
+            let tree_sizes = wallet
+                .blocks
+                .iter()
+                .map(|(height, block)| (*height, block.orchard_commitment_tree_size))
+                .collect();
+            wallet.orchard_tree_shard_end_heights =
+                restore_tree_shape(&mut wallet.orchard_tree, shape, &tree_sizes)?;
+        }
+
+        wallet.reorg_stats = diagnostics
+            .reorg_stats
+            .map(ReorgStats::try_from)
+            .transpose()?
+            .unwrap_or_default();
+
+        Ok(wallet)
+    }
+}
+
+fn proto_pool_type(pool: PoolType) -> proto::PoolType {
+    match pool {
+        PoolType::Transparent => proto::PoolType::Transparent,
+        PoolType::Shielded(ShieldedProtocol::Sapling) => proto::PoolType::ShieldedSapling,
+        PoolType::Shielded(ShieldedProtocol::Orchard) => proto::PoolType::ShieldedOrchard,
+    }
+}
+
+/// Returns the positions of the shards and checkpoints of a note commitment tree.
+fn tree_shape<H: Hashable + Clone + PartialEq, const DEPTH: u8, const SHARD_HEIGHT: u8>(
+    tree: &ShardTree<MemoryShardStore<H, BlockHeight>, DEPTH, SHARD_HEIGHT>,
+    shard_end_heights: &BTreeMap<Address, BlockHeight>,
+) -> Result<proto::RedactedTreeShape> {
+    let mut shards = vec![];
+    for shard_root in tree.store().get_shard_roots()? {
+        if let Some(shard) = tree.store().get_shard(shard_root)? {
+            shards.push(proto::RedactedShard {
+                shard_index: shard_root.index(),
+                max_position: shard.max_position().map(u64::from),
+                marked_count: shard.marked_positions().len() as u32,
+            });
+        }
+    }
+
+    let mut checkpoints = vec![];
+    tree.store()
+        .for_each_checkpoint(usize::MAX, |id, checkpoint| {
+            checkpoints.push(proto::RedactedCheckpoint {
+                checkpoint_id: (*id).into(),
+                position: match checkpoint.tree_state() {
+                    TreeState::Empty => None,
+                    TreeState::AtPosition(position) => Some(position.into()),
+                },
+            });
+            Ok(())
+        })?;
+
+    Ok(proto::RedactedTreeShape {
+        shards,
+        checkpoints,
+        shard_end_heights: shard_end_heights
+            .iter()
+            .map(|(address, height)| proto::TreeEndHeightsRecord {
+                level: address.level().into(),
+                index: address.index(),
+                block_height: (*height).into(),
+            })
+            .collect(),
+    })
+}
+
+/// Adds the checkpoints of an exported tree shape to an empty note commitment tree, and returns
+/// its shard end heights.
+///
+/// `tree_sizes` maps the heights of the exported blocks to the size of the tree as of the end
+/// of each block, if known. Each checkpoint must lie within the size of the tree as of the
+/// last block at or below its height.
+fn restore_tree_shape<H: Hashable + Clone + PartialEq, const DEPTH: u8, const SHARD_HEIGHT: u8>(
+    tree: &mut ShardTree<MemoryShardStore<H, BlockHeight>, DEPTH, SHARD_HEIGHT>,
+    shape: proto::RedactedTreeShape,
+    tree_sizes: &BTreeMap<BlockHeight, Option<u32>>,
+) -> Result<BTreeMap<Address, BlockHeight>> {
+    for proto_checkpoint in shape.checkpoints {
+        let height = BlockHeight::from(proto_checkpoint.checkpoint_id);
+        let position = proto_checkpoint.position.map(Position::from);
+        if let Some((_, Some(size))) = tree_sizes.range(..=height).next_back() {
+            if position.map_or(false, |position| u64::from(position) >= u64::from(*size)) {
+                return Err(Error::CorruptedData(format!(
+                    "checkpoint at height {} is at position {:?}, beyond the tree size {}",
+                    height, position, size
+                )));
+            }
+        }
+        tree.store_mut().add_checkpoint(
+            height,
+            position.map_or_else(Checkpoint::tree_empty, Checkpoint::at_position),
+        )?;
+    }
+
+    shape
+        .shard_end_heights
+        .into_iter()
+        .map(|record| {
+            let address =
+                Address::from_parts(Level::from(u8::try_from(record.level)?), record.index);
+            Ok((address, record.block_height.into()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::Message;
+    use shardtree::store::ShardStore;
+    use zcash_client_backend::data_api::{
+        testing::{AddressType, TestBuilder, TestState},
+        WalletRead, WalletWrite,
+    };
+    use zcash_primitives::{block::BlockHash, transaction::components::amount::NonNegativeAmount};
+    use zcash_protocol::{
+        local_consensus::LocalNetwork,
+        memo::{Memo, MemoBytes},
+    };
+
+    use super::RedactionOptions;
+    use crate::{
+        proto::memwallet as proto, testing::TestMemDbFactory, MemBlockCache, MemoryWalletDb,
+    };
+
+    type MemTestState = TestState<MemBlockCache, MemoryWalletDb<LocalNetwork>, LocalNetwork>;
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack
+            .windows(needle.len())
+            .any(|window| window == needle)
+    }
+
+    /// Returns a wallet that has scanned three blocks, two of which pay it a note with a memo.
+    fn scanned_wallet() -> MemTestState {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let value = NonNegativeAmount::const_from_u64(50000);
+
+        let (h, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        st.generate_empty_block();
+        st.wallet_mut().update_chain_tip(h + 10).unwrap();
+        st.scan_cached_blocks(h, 3);
+
+        for note in st.wallet_mut().received_notes.0.iter_mut() {
+            note.memo =
+                Memo::try_from(MemoBytes::from_bytes(b"a very private memo").unwrap()).unwrap();
+        }
+        st
+    }
+
+    #[test]
+    fn redacted_export_contains_no_keys_addresses_or_memos() {
+        let st = scanned_wallet();
+        let wallet = st.wallet();
+        let export = wallet.export_redacted_diagnostics([1; 32]);
+
+        let mut secrets = vec![b"a very private memo".to_vec()];
+        for account in wallet.accounts.accounts.values() {
+            let ufvk = account.viewing_key();
+            secrets.push(ufvk.encode(&wallet.params).into_bytes());
+            secrets.push(ufvk.sapling().unwrap().to_bytes().to_vec());
+            for ua in account.addresses().values() {
+                secrets.push(ua.encode(&wallet.params).into_bytes());
+                secrets.push(ua.sapling().unwrap().to_bytes().to_vec());
+            }
+        }
+        for txid in wallet.tx_table.keys() {
+            secrets.push(txid.as_ref().to_vec());
+        }
+        assert!(!wallet.received_notes.is_empty());
+        for secret in &secrets {
+            assert!(!contains(&export, secret));
+        }
+
+        let decoded = proto::RedactedDiagnostics::decode(&export[..]).unwrap();
+        assert!(!decoded.exact_values);
+        assert_eq!(decoded.received_notes.len(), wallet.received_notes.len());
+        assert!(decoded
+            .received_notes
+            .iter()
+            .all(|note| note.value == 10000));
+        assert_eq!(decoded.nullifier_count as usize, wallet.nullifiers.len());
+    }
+
+    #[test]
+    fn redacted_export_salts_identifiers() {
+        let st = scanned_wallet();
+        let wallet = st.wallet();
+        let options = RedactionOptions::new([7; 32]);
+        let decode = |options: &RedactionOptions| {
+            proto::RedactedDiagnostics::decode(
+                &wallet.export_redacted_diagnostics_with(options)[..],
+            )
+            .unwrap()
+        };
+
+        // The same salt gives the same hashes, and a new salt gives different ones.
+        assert_eq!(decode(&options), decode(&options));
+        let tx_hashes = |diagnostics: proto::RedactedDiagnostics| {
+            diagnostics
+                .transactions
+                .into_iter()
+                .map(|tx| tx.tx_hash)
+                .collect::<Vec<_>>()
+        };
+        assert_ne!(
+            tx_hashes(decode(&options)),
+            tx_hashes(decode(&RedactionOptions::new([8; 32])))
+        );
+
+        let exact = decode(&options.with_exact_values(true));
+        assert!(exact.exact_values);
+        assert!(exact.received_notes.iter().all(|note| note.value == 50000));
+    }
+
+    #[test]
+    fn redacted_skeleton_reproduces_scan_state() {
+        let st = scanned_wallet();
+        let wallet = st.wallet();
+        let export = wallet.export_redacted_diagnostics([1; 32]);
+        let skeleton =
+            MemoryWalletDb::load_redacted_diagnostics(&export, wallet.params, 100).unwrap();
+
+        skeleton.scan_queue.check_consistency().unwrap();
+        assert_eq!(skeleton.scan_queue, wallet.scan_queue);
+        assert_eq!(skeleton.scanned_heights, wallet.scanned_heights);
+        assert_eq!(
+            skeleton.suggest_scan_ranges().unwrap(),
+            wallet.suggest_scan_ranges().unwrap()
+        );
+        assert_eq!(
+            skeleton
+                .block_fully_scanned()
+                .unwrap()
+                .map(|m| m.block_height()),
+            wallet
+                .block_fully_scanned()
+                .unwrap()
+                .map(|m| m.block_height())
+        );
+        assert_eq!(
+            skeleton.sapling_tree_shard_end_heights,
+            wallet.sapling_tree_shard_end_heights
+        );
+        assert_eq!(
+            skeleton.sapling_tree.store().checkpoint_count().unwrap(),
+            wallet.sapling_tree.store().checkpoint_count().unwrap()
+        );
+        assert_eq!(
+            skeleton.sapling_tree.store().max_checkpoint_id().unwrap(),
+            wallet.sapling_tree.store().max_checkpoint_id().unwrap()
+        );
+        assert!(skeleton.accounts.accounts.is_empty());
+        assert!(skeleton.received_notes.is_empty());
+    }
+
+    #[test]
+    fn redacted_skeleton_rejects_checkpoints_beyond_the_tree() {
+        let st = scanned_wallet();
+        let wallet = st.wallet();
+        let mut diagnostics =
+            proto::RedactedDiagnostics::decode(&wallet.export_redacted_diagnostics([1; 32])[..])
+                .unwrap();
+        let sapling_tree = diagnostics.sapling_tree.as_mut().unwrap();
+        let checkpoint = sapling_tree.checkpoints.last_mut().unwrap();
+        checkpoint.position = Some(u64::from(u32::MAX));
+
+        assert!(MemoryWalletDb::load_redacted_diagnostics(
+            &diagnostics.encode_to_vec(),
+            wallet.params,
+            100
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,202 @@
+use time::OffsetDateTime;
+
+use super::provenance::{now, timestamp_from_proto};
+use super::*;
+use crate::proto::memwallet as proto;
+
+/// Statistics about the chain reorganizations that have affected a wallet, as observed through
+/// calls to [`WalletWrite::truncate_to_height`].
+///
+/// [`WalletWrite::truncate_to_height`]: zcash_client_backend::data_api::WalletWrite::truncate_to_height
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReorgStats {
+    truncation_count: u32,
+    max_depth: u32,
+    last_truncation: Option<TruncationRecord>,
+}
+
+impl ReorgStats {
+    /// Returns the number of times the wallet has been truncated.
+    pub fn truncation_count(&self) -> u32 {
+        self.truncation_count
+    }
+
+    /// Returns the largest number of scanned blocks removed by a single truncation.
+    pub fn max_depth(&self) -> u32 {
+        self.max_depth
+    }
+
+    /// Returns the record of the most recent truncation, if the wallet has been truncated.
+    pub fn last_truncation(&self) -> Option<&TruncationRecord> {
+        self.last_truncation.as_ref()
+    }
+
+    pub(crate) fn record_truncation(&mut self, height: BlockHeight, depth: u32) {
+        self.truncation_count = self.truncation_count.saturating_add(1);
+        self.max_depth = self.max_depth.max(depth);
+        self.last_truncation = Some(TruncationRecord {
+            truncated_at: now(),
+            height,
+            depth,
+            rescanned_to: height + 1,
+        });
+    }
+
+    /// Records that the given range of blocks has been scanned.
+    pub(crate) fn record_scan(&mut self, scanned: &Range<BlockHeight>) {
+        if let Some(record) = &mut self.last_truncation {
+            if scanned.contains(&record.rescanned_to) {
+                record.rescanned_to = scanned.end;
+            }
+        }
+    }
+}
+
+/// A record of a truncation of a wallet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TruncationRecord {
+    truncated_at: Option<OffsetDateTime>,
+    height: BlockHeight,
+    depth: u32,
+    rescanned_to: BlockHeight,
+}
+
+impl TruncationRecord {
+    /// Returns the time at which the wallet was truncated, or `None` if the wallet was
+    /// truncated on a platform without a system clock.
+    pub fn truncated_at(&self) -> Option<OffsetDateTime> {
+        self.truncated_at
+    }
+
+    /// Returns the height to which the wallet was truncated. Blocks above this height were
+    /// removed from the wallet.
+    pub fn height(&self) -> BlockHeight {
+        self.height
+    }
+
+    /// Returns the number of scanned blocks that were removed.
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+
+    /// Returns the range of removed blocks once all of them have been scanned again, or
+    /// `None` if no scanned blocks were removed or the rescan has not yet completed.
+    pub fn rescanned_range(&self) -> Option<Range<BlockHeight>> {
+        let removed = self.height + 1..self.height + 1 + self.depth;
+        (!removed.is_empty() && self.rescanned_to >= removed.end).then_some(removed)
+    }
+}
+
+impl From<&ReorgStats> for proto::ReorgStats {
+    fn from(stats: &ReorgStats) -> Self {
+        Self {
+            truncation_count: stats.truncation_count,
+            max_depth: stats.max_depth,
+            last_truncation: stats
+                .last_truncation
+                .as_ref()
+                .map(|record| proto::TruncationRecord {
+                    truncated_at: record.truncated_at.map(|t| t.unix_timestamp()),
+                    height: record.height.into(),
+                    depth: record.depth,
+                    rescanned_to: record.rescanned_to.into(),
+                }),
+        }
+    }
+}
+
+impl TryFrom<proto::ReorgStats> for ReorgStats {
+    type Error = Error;
+
+    fn try_from(stats: proto::ReorgStats) -> Result<Self, Self::Error> {
+        Ok(Self {
+            truncation_count: stats.truncation_count,
+            max_depth: stats.max_depth,
+            last_truncation: stats
+                .last_truncation
+                .map(|record| {
+                    Ok::<_, Error>(TruncationRecord {
+                        truncated_at: record.truncated_at.map(timestamp_from_proto).transpose()?,
+                        height: record.height.into(),
+                        depth: record.depth,
+                        rescanned_to: record.rescanned_to.into(),
+                    })
+                })
+                .transpose()?,
+        })
+    }
+}
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    /// Returns statistics about how often, and how deeply, chain reorganizations have caused
+    /// the wallet to be truncated.
+    pub fn reorg_stats(&self) -> &ReorgStats {
+        &self.reorg_stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zcash_client_backend::data_api::{
+        testing::{AddressType, TestBuilder},
+        WalletWrite,
+    };
+    use zcash_primitives::{block::BlockHash, transaction::components::amount::NonNegativeAmount};
+
+    use crate::{testing::TestMemDbFactory, MemBlockCache, MemoryWalletDb};
+
+    #[test]
+    fn truncations_are_recorded() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let (h, _, _) = st.generate_next_block(
+            &dfvk,
+            AddressType::DefaultExternal,
+            NonNegativeAmount::const_from_u64(10000),
+        );
+        for _ in 1..10 {
+            st.generate_next_block(
+                &dfvk,
+                AddressType::DefaultExternal,
+                NonNegativeAmount::const_from_u64(10000),
+            );
+        }
+        st.scan_cached_blocks(h, 10);
+        assert_eq!(st.wallet().reorg_stats().truncation_count(), 0);
+        assert_eq!(st.wallet().reorg_stats().last_truncation(), None);
+
+        // Remove the last three blocks, and scan them again.
+        assert_eq!(st.wallet_mut().truncate_to_height(h + 6).unwrap(), h + 6);
+        let record = st.wallet().reorg_stats().last_truncation().unwrap().clone();
+        assert_eq!(record.height(), h + 6);
+        assert_eq!(record.depth(), 3);
+        assert_eq!(record.rescanned_range(), None);
+        st.scan_cached_blocks(h + 7, 3);
+        assert_eq!(
+            st.wallet()
+                .reorg_stats()
+                .last_truncation()
+                .unwrap()
+                .rescanned_range(),
+            Some(h + 7..h + 10)
+        );
+
+        // A shallower truncation leaves the maximum depth unchanged.
+        assert_eq!(st.wallet_mut().truncate_to_height(h + 8).unwrap(), h + 8);
+        let stats = st.wallet().reorg_stats().clone();
+        assert_eq!(stats.truncation_count(), 2);
+        assert_eq!(stats.max_depth(), 3);
+        assert_eq!(stats.last_truncation().unwrap().height(), h + 8);
+        assert_eq!(stats.last_truncation().unwrap().depth(), 1);
+
+        let mut buf = vec![];
+        st.wallet().encode(&mut buf).unwrap();
+        let restored =
+            MemoryWalletDb::decode_new(&buf[..], st.wallet().params.clone(), 100).unwrap();
+        assert_eq!(restored.reorg_stats(), &stats);
+    }
+}
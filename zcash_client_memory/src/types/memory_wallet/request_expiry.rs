@@ -0,0 +1,207 @@
+use zcash_client_backend::data_api::TransactionDataRequest;
+use zcash_primitives::transaction::builder::DEFAULT_TX_EXPIRY_DELTA;
+
+use super::*;
+use crate::PRUNING_DEPTH;
+
+/// The default number of blocks after which an unanswered request for the status or data of
+/// a transaction that the wallet did not create is dropped.
+pub(crate) const DEFAULT_REQUEST_EXPIRY: u32 = DEFAULT_TX_EXPIRY_DELTA + PRUNING_DEPTH;
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    /// Returns the wallet with the given request expiry, in blocks.
+    ///
+    /// Requests for the status or data of a transaction that the wallet observed but did not
+    /// create are dropped by [`WalletWrite::update_chain_tip`] once the chain tip is this many
+    /// blocks past the height at which they were first queued, if the transaction has still
+    /// not been mined. Such a transaction is most likely to have been dropped from the
+    /// mempool, and would otherwise be requested forever. Requests for transactions created
+    /// by the wallet are never expired, and are instead removed along with the transaction
+    /// once it can no longer be mined.
+    ///
+    /// The default is the default transaction expiry delta plus the wallet's pruning depth.
+    /// This is a property of the running wallet, and is not serialized.
+    ///
+    /// [`WalletWrite::update_chain_tip`]: zcash_client_backend::data_api::WalletWrite::update_chain_tip
+    pub fn with_request_expiry(mut self, blocks: u32) -> Self {
+        self.request_expiry = blocks;
+        self
+    }
+
+    /// Sets the request expiry, in blocks. See [`MemoryWalletDb::with_request_expiry`].
+    pub fn set_request_expiry(&mut self, blocks: u32) {
+        self.request_expiry = blocks;
+    }
+
+    /// Returns the request expiry, in blocks.
+    pub fn request_expiry(&self) -> u32 {
+        self.request_expiry
+    }
+
+    /// Drops the status and enhancement requests for unmined transactions that the wallet did
+    /// not create and that were queued at least [`Self::request_expiry`] blocks before the
+    /// given chain tip. Each such transaction is marked as not recognized by the chain.
+    ///
+    /// Requests whose queue height is not known, such as those restored from older snapshots,
+    /// are treated as having been queued at the given chain tip.
+    ///
+    /// Returns the IDs of the transactions whose requests were dropped.
+    pub(crate) fn expire_data_requests(&mut self, chain_tip: BlockHeight) -> Vec<TxId> {
+        let mut expired = BTreeSet::new();
+        for request in self.transaction_data_request_queue.0.iter() {
+            let txid = match request {
+                TransactionDataRequest::GetStatus(txid)
+                | TransactionDataRequest::Enhancement(txid) => txid,
+                #[cfg(feature = "transparent-inputs")]
+                TransactionDataRequest::SpendsFromAddress { .. } => continue,
+            };
+            let tx = self.tx_table.get(txid);
+            if tx.map_or(false, |tx| {
+                tx.mined_height().is_some() || tx.is_wallet_created()
+            }) {
+                continue;
+            }
+            let queued_at = *self
+                .transaction_data_request_queue
+                .1
+                .entry(*txid)
+                .or_insert(chain_tip);
+            if chain_tip >= queued_at + self.request_expiry {
+                expired.insert(*txid);
+            }
+        }
+
+        let queue = &mut self.transaction_data_request_queue;
+        queue.0.retain(|request| match request {
+            TransactionDataRequest::GetStatus(txid) | TransactionDataRequest::Enhancement(txid) => {
+                !expired.contains(txid)
+            }
+            #[cfg(feature = "transparent-inputs")]
+            TransactionDataRequest::SpendsFromAddress { .. } => true,
+        });
+        // Forget the queue heights of transactions that no longer have any pending request.
+        let pending = queue
+            .0
+            .iter()
+            .filter_map(|request| match request {
+                TransactionDataRequest::GetStatus(txid)
+                | TransactionDataRequest::Enhancement(txid) => Some(*txid),
+                #[cfg(feature = "transparent-inputs")]
+                TransactionDataRequest::SpendsFromAddress { .. } => None,
+            })
+            .collect::<BTreeSet<_>>();
+        queue.1.retain(|txid, _| pending.contains(txid));
+
+        for txid in &expired {
+            tracing::debug!("Expiring unanswered data requests for transaction {}", txid);
+            // The transaction may not be in the table if only its txid was observed.
+            let _ = self
+                .tx_table
+                .set_transaction_status(txid, TransactionStatus::TxidNotRecognized);
+        }
+
+        expired.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zcash_client_backend::data_api::{TransactionDataRequest, TransactionStatus};
+    use zcash_primitives::{
+        consensus::{BlockHeight, BranchId, Network},
+        transaction::{Authorized, Transaction, TransactionData, TxVersion},
+    };
+
+    use super::DEFAULT_REQUEST_EXPIRY;
+    use crate::MemoryWalletDb;
+
+    fn tx(expiry_height: u32) -> Transaction {
+        TransactionData::<Authorized>::from_parts(
+            TxVersion::Zip225,
+            BranchId::Nu5,
+            0,
+            BlockHeight::from_u32(expiry_height),
+            None,
+            None,
+            None,
+            None,
+        )
+        .freeze()
+        .unwrap()
+    }
+
+    #[test]
+    fn unanswered_foreign_requests_expire() {
+        let mut wallet = MemoryWalletDb::new(Network::TestNetwork, 100);
+        let queued_at = BlockHeight::from_u32(1000);
+        let foreign = tx(1040);
+        wallet.tx_table.put_tx_data(&foreign, None, None);
+        wallet
+            .transaction_data_request_queue
+            .queue_status_retrieval(&foreign.txid(), Some(queued_at));
+
+        assert!(wallet
+            .expire_data_requests(queued_at + DEFAULT_REQUEST_EXPIRY - 1)
+            .is_empty());
+        assert!(wallet
+            .transaction_data_request_queue
+            .contains(&TransactionDataRequest::GetStatus(foreign.txid())));
+
+        assert_eq!(
+            wallet.expire_data_requests(queued_at + DEFAULT_REQUEST_EXPIRY),
+            vec![foreign.txid()]
+        );
+        assert!(wallet.transaction_data_request_queue.is_empty());
+        assert_eq!(
+            wallet
+                .transaction_data_request_queue
+                .queued_at(&foreign.txid()),
+            None
+        );
+        assert_eq!(
+            wallet.tx_table.tx_status(&foreign.txid()),
+            Some(TransactionStatus::TxidNotRecognized)
+        );
+    }
+
+    #[test]
+    fn wallet_created_requests_do_not_expire() {
+        let mut wallet = MemoryWalletDb::new(Network::TestNetwork, 100).with_request_expiry(10);
+        let queued_at = BlockHeight::from_u32(1000);
+        let created = tx(1040);
+        wallet
+            .tx_table
+            .put_tx_data(&created, None, Some(BlockHeight::from_u32(1001)));
+        wallet
+            .transaction_data_request_queue
+            .queue_status_retrieval(&created.txid(), Some(queued_at));
+
+        assert!(wallet.expire_data_requests(queued_at + 1000).is_empty());
+        assert!(wallet
+            .transaction_data_request_queue
+            .contains(&TransactionDataRequest::GetStatus(created.txid())));
+        assert_eq!(
+            wallet.tx_table.tx_status(&created.txid()),
+            Some(TransactionStatus::NotInMainChain)
+        );
+    }
+
+    #[test]
+    fn requests_without_queue_height_expire_from_first_observation() {
+        let mut wallet = MemoryWalletDb::new(Network::TestNetwork, 100).with_request_expiry(10);
+        let foreign = tx(1040);
+        wallet
+            .transaction_data_request_queue
+            .queue_status_retrieval(&foreign.txid(), None);
+
+        let tip = BlockHeight::from_u32(2000);
+        assert!(wallet.expire_data_requests(tip).is_empty());
+        assert_eq!(
+            wallet
+                .transaction_data_request_queue
+                .queued_at(&foreign.txid()),
+            Some(tip)
+        );
+        assert_eq!(wallet.expire_data_requests(tip + 10), vec![foreign.txid()]);
+    }
+}
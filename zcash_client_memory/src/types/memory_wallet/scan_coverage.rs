@@ -0,0 +1,321 @@
+use std::cmp::max;
+
+use super::*;
+
+/// The scanning state of a range of blocks, as reported by [`MemoryWalletDb::scan_coverage`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoverageState {
+    /// The blocks have been scanned.
+    Scanned,
+    /// The blocks are near the chain tip, and are waiting to be scanned.
+    PendingChainTip,
+    /// The blocks must be rescanned to verify that the wallet is on the main chain.
+    PendingVerify,
+    /// The blocks are part of the wallet's historic backlog, and are waiting to be scanned.
+    /// This includes ranges that have been prioritized because they are adjacent to notes
+    /// that the wallet has found.
+    PendingHistoric,
+    /// The blocks will not be scanned.
+    Ignored,
+}
+
+impl CoverageState {
+    /// Returns the state of a range in the scan queue with the given priority, or `None` if
+    /// the range has been scanned.
+    fn of_queued(priority: ScanPriority) -> Option<Self> {
+        match priority {
+            ScanPriority::Scanned => None,
+            ScanPriority::Ignored => Some(CoverageState::Ignored),
+            ScanPriority::Historic | ScanPriority::OpenAdjacent | ScanPriority::FoundNote => {
+                Some(CoverageState::PendingHistoric)
+            }
+            ScanPriority::ChainTip => Some(CoverageState::PendingChainTip),
+            ScanPriority::Verify => Some(CoverageState::PendingVerify),
+        }
+    }
+}
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    /// Returns the scanning state of every block from the wallet birthday to the chain tip,
+    /// for rendering sync progress.
+    ///
+    /// The returned ranges are in ascending order, and tile the range from the wallet birthday
+    /// to the chain tip (inclusive) with no gaps or overlaps. Adjacent ranges always have
+    /// different states. Any block that is not waiting in the scan queue is reported as
+    /// [`CoverageState::Scanned`] if the wallet has scanned it, and as
+    /// [`CoverageState::PendingHistoric`] otherwise. Returns an empty vector if the wallet has
+    /// no accounts or does not yet know the chain tip.
+    pub fn scan_coverage(&self) -> Vec<(Range<BlockHeight>, CoverageState)> {
+        let birthday = self
+            .accounts
+            .iter()
+            .map(|(_, account)| account.birthday().height())
+            .min();
+        let chain_end = self.scan_queue.iter().map(|(_, end, _)| *end).max();
+        let covered = match birthday.zip(chain_end) {
+            Some((start, end)) if start < end => start..end,
+            _ => return vec![],
+        };
+
+        let mut pending = self
+            .scan_queue
+            .iter()
+            .filter_map(|(start, end, priority)| {
+                let state = CoverageState::of_queued(*priority)?;
+                let range = max(*start, covered.start)..min(*end, covered.end);
+                (!range.is_empty()).then_some((range, state))
+            })
+            .collect::<Vec<_>>();
+        pending.sort_by_key(|(range, _)| range.start);
+
+        let mut coverage: Vec<(Range<BlockHeight>, CoverageState)> = vec![];
+        let mut push = |range: Range<BlockHeight>, state| match coverage.last_mut() {
+            Some((last, last_state)) if *last_state == state && last.end == range.start => {
+                last.end = range.end;
+            }
+            _ => coverage.push((range, state)),
+        };
+        let mut cursor = covered.start;
+        for (range, state) in pending {
+            // Defend against overlapping queue entries by never moving backwards.
+            let start = max(range.start, cursor);
+            if start >= range.end {
+                continue;
+            }
+            for (range, state) in self.unqueued_coverage(cursor..start) {
+                push(range, state);
+            }
+            push(start..range.end, state);
+            cursor = range.end;
+        }
+        for (range, state) in self.unqueued_coverage(cursor..covered.end) {
+            push(range, state);
+        }
+
+        coverage
+    }
+
+    /// Returns the states of the blocks in the given range, none of which are waiting in the
+    /// scan queue, according to whether they have been scanned.
+    fn unqueued_coverage(
+        &self,
+        range: Range<BlockHeight>,
+    ) -> Vec<(Range<BlockHeight>, CoverageState)> {
+        let mut coverage = vec![];
+        let mut cursor = range.start;
+        for scanned in self.scanned_heights.iter() {
+            let start = max(scanned.start, cursor);
+            let end = min(scanned.end, range.end);
+            if start >= end {
+                continue;
+            }
+            if cursor < start {
+                coverage.push((cursor..start, CoverageState::PendingHistoric));
+            }
+            coverage.push((start..end, CoverageState::Scanned));
+            cursor = end;
+        }
+        if cursor < range.end {
+            coverage.push((cursor..range.end, CoverageState::PendingHistoric));
+        }
+        coverage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zcash_client_backend::data_api::{
+        testing::{AddressType, TestBuilder, TestState},
+        WalletRead, WalletWrite,
+    };
+    use zcash_primitives::{block::BlockHash, transaction::components::amount::NonNegativeAmount};
+
+    use super::CoverageState;
+    use crate::{
+        proto::memwallet as proto, testing::TestMemDbFactory, MemBlockCache, MemoryWalletDb,
+    };
+
+    #[test]
+    fn scan_coverage_tiles_the_wallet_range() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let (h, _, _) = st.generate_next_block(
+            &dfvk,
+            AddressType::DefaultExternal,
+            NonNegativeAmount::const_from_u64(10000),
+        );
+        for _ in 1..10 {
+            st.generate_next_block(
+                &dfvk,
+                AddressType::DefaultExternal,
+                NonNegativeAmount::const_from_u64(10000),
+            );
+        }
+        st.wallet_mut().update_chain_tip(h + 9).unwrap();
+
+        // Scan the start and the end of the chain, leaving a gap in the middle.
+        st.scan_cached_blocks(h, 3);
+        st.scan_cached_blocks(h + 6, 4);
+
+        let coverage = st.wallet().scan_coverage();
+        assert_eq!(coverage.first().unwrap().0.start, h);
+        assert_eq!(coverage.last().unwrap().0.end, h + 10);
+        for (a, b) in coverage.iter().zip(coverage.iter().skip(1)) {
+            assert_eq!(a.0.end, b.0.start);
+            assert_ne!(a.1, b.1);
+        }
+        assert_eq!(
+            coverage.first().unwrap(),
+            &(h..h + 3, CoverageState::Scanned)
+        );
+        assert_eq!(
+            coverage.last().unwrap(),
+            &(h + 6..h + 10, CoverageState::Scanned)
+        );
+        assert!(coverage[1..coverage.len() - 1]
+            .iter()
+            .all(|(_, state)| *state != CoverageState::Scanned));
+
+        // Once the gap has been scanned, the whole range is covered by a single segment.
+        st.scan_cached_blocks(h + 3, 3);
+        assert_eq!(
+            st.wallet().scan_coverage(),
+            vec![(h..h + 10, CoverageState::Scanned)]
+        );
+    }
+
+    #[test]
+    fn scanned_heights_survive_pruned_block_records() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let (h, _, _) = st.generate_next_block(
+            &dfvk,
+            AddressType::DefaultExternal,
+            NonNegativeAmount::const_from_u64(10000),
+        );
+        for _ in 1..10 {
+            st.generate_empty_block();
+        }
+        st.scan_cached_blocks(h, 10);
+
+        // Discard the records of all but the most recent block.
+        st.wallet_mut().blocks.retain(|height, _| *height == h + 9);
+
+        assert_eq!(
+            st.wallet()
+                .block_fully_scanned()
+                .unwrap()
+                .map(|meta| meta.block_height()),
+            Some(h + 9)
+        );
+        assert_eq!(
+            st.wallet().scan_coverage(),
+            vec![(h..h + 10, CoverageState::Scanned)]
+        );
+
+        // The scanned heights are preserved by a snapshot.
+        let restored = MemoryWalletDb::new_from_proto(
+            proto::MemoryWallet::from(st.wallet()),
+            st.wallet().params.clone(),
+            100,
+        )
+        .unwrap();
+        assert_eq!(
+            restored.scanned_heights.iter().collect::<Vec<_>>(),
+            vec![h..h + 10]
+        );
+    }
+
+    #[test]
+    fn fully_scanned_height_advances_when_gap_is_filled() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let (h, _, _) = st.generate_next_block(
+            &dfvk,
+            AddressType::DefaultExternal,
+            NonNegativeAmount::const_from_u64(10000),
+        );
+        for _ in 1..10 {
+            st.generate_empty_block();
+        }
+        let fully_scanned = |st: &TestState<_, MemoryWalletDb<_>, _>| {
+            st.wallet()
+                .block_fully_scanned()
+                .unwrap()
+                .map(|meta| meta.block_height())
+        };
+
+        // Scanning the upper half leaves a gap above the wallet birthday.
+        st.scan_cached_blocks(h + 5, 5);
+        assert_eq!(fully_scanned(&st), None);
+        assert!(st.wallet().block_metadata(h).unwrap().is_none());
+        let meta = st.wallet().block_metadata(h + 9).unwrap().unwrap();
+        assert_eq!(meta.block_height(), h + 9);
+        assert_eq!(meta.sapling_tree_size(), Some(1));
+
+        st.scan_cached_blocks(h + 1, 2);
+        assert_eq!(fully_scanned(&st), None);
+
+        // Filling the gap from the birthday joins the scanned ranges.
+        st.scan_cached_blocks(h, 1);
+        assert_eq!(fully_scanned(&st), Some(h + 2));
+        st.scan_cached_blocks(h + 3, 2);
+        assert_eq!(fully_scanned(&st), Some(h + 9));
+    }
+
+    #[test]
+    fn truncation_shrinks_scanned_heights() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let (h, _, _) = st.generate_next_block(
+            &dfvk,
+            AddressType::DefaultExternal,
+            NonNegativeAmount::const_from_u64(10000),
+        );
+        for _ in 1..10 {
+            st.generate_empty_block();
+        }
+        st.scan_cached_blocks(h, 10);
+        assert_eq!(
+            st.wallet().scanned_heights.iter().collect::<Vec<_>>(),
+            vec![h..h + 10]
+        );
+
+        st.wallet_mut().truncate_to_height(h + 5).unwrap();
+        assert_eq!(
+            st.wallet().scanned_heights.iter().collect::<Vec<_>>(),
+            vec![h..h + 6]
+        );
+        assert_eq!(
+            st.wallet()
+                .block_fully_scanned()
+                .unwrap()
+                .map(|meta| meta.block_height()),
+            Some(h + 5)
+        );
+
+        // Snapshots that predate the tracking of scanned heights have them derived from the
+        // retained block records.
+        let mut snapshot = proto::MemoryWallet::from(st.wallet());
+        snapshot.scanned_heights.clear();
+        let restored =
+            MemoryWalletDb::new_from_proto(snapshot, st.wallet().params.clone(), 100).unwrap();
+        assert_eq!(restored.scanned_heights, st.wallet().scanned_heights);
+    }
+}
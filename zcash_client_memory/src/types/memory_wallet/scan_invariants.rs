@@ -0,0 +1,87 @@
+use super::*;
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    /// Checks the invariants of the wallet's scan queue: no two entries overlap or are
+    /// adjacent ranges of equal priority, and every height from the wallet birthday to the
+    /// chain tip is covered by exactly one entry.
+    pub(crate) fn validate_scan_queue(&self) -> Result<(), Error> {
+        self.scan_queue.check_consistency()?;
+        let chain_end = self.scan_queue.iter().map(|(_, end, _)| *end).max();
+        match self.get_wallet_birthday()?.zip(chain_end) {
+            Some((birthday, chain_end)) if birthday < chain_end => {
+                self.scan_queue.check_coverage(&(birthday..chain_end))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use incrementalmerkletree::{Hashable, Level, Position};
+    use zcash_client_backend::data_api::{
+        chain::CommitmentTreeRoot,
+        scanning::{ScanPriority, ScanRange},
+        testing::TestBuilder,
+        WalletCommitmentTrees, WalletRead, WalletWrite, SAPLING_SHARD_HEIGHT,
+    };
+    use zcash_primitives::block::BlockHash;
+    use zcash_protocol::ShieldedProtocol;
+
+    use crate::{testing::TestMemDbFactory, MemBlockCache};
+
+    #[test]
+    fn found_notes_prioritize_the_unscanned_parts_of_their_shards() {
+        use ScanPriority::*;
+
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let birthday = st.sapling_activation_height();
+
+        // Set up the following situation:
+        //
+        // wallet_birthday   shard 0 end   shard 1 end       tip
+        //        |<--- 500 --->|<--- 500 --->|<--- 500 --->|
+        let shard_ends = [birthday + 500, birthday + 1000];
+        let root = sapling::Node::empty_root(Level::from(SAPLING_SHARD_HEIGHT));
+        st.wallet_mut()
+            .put_sapling_subtree_roots(
+                0,
+                &shard_ends.map(|end| CommitmentTreeRoot::from_parts(end, root)),
+            )
+            .unwrap();
+        let tip = birthday + 1500;
+        st.wallet_mut().update_chain_tip(tip).unwrap();
+        st.wallet().validate_scan_queue().unwrap();
+
+        // A scan that finds no notes only marks the scanned range.
+        let scanned_early = (birthday + 520)..(birthday + 540);
+        st.wallet_mut()
+            .scan_complete(scanned_early.clone(), &[])
+            .unwrap();
+        st.wallet().validate_scan_queue().unwrap();
+
+        // A note found in shard 1 raises the priority of the rest of that shard, but does not
+        // cause scanned blocks to be rescanned, or lower the priority of the chain tip.
+        let scanned = (birthday + 600)..(birthday + 700);
+        let position = Position::from((1 << SAPLING_SHARD_HEIGHT) + 10);
+        st.wallet_mut()
+            .scan_complete(scanned.clone(), &[(ShieldedProtocol::Sapling, position)])
+            .unwrap();
+        st.wallet().validate_scan_queue().unwrap();
+
+        assert_eq!(
+            st.wallet().suggest_scan_ranges().unwrap(),
+            vec![
+                ScanRange::from_parts(shard_ends[1]..(tip + 1), ChainTip),
+                ScanRange::from_parts(shard_ends[0]..scanned_early.start, FoundNote),
+                ScanRange::from_parts(scanned_early.end..scanned.start, FoundNote),
+                ScanRange::from_parts(scanned.end..shard_ends[1], FoundNote),
+                ScanRange::from_parts(birthday..shard_ends[0], Historic),
+            ]
+        );
+    }
+}
@@ -0,0 +1,423 @@
+use zcash_client_backend::data_api::Progress;
+
+use super::*;
+
+/// Accessors for the per-pool data used to estimate scan progress.
+struct PoolData<'a> {
+    activation_height: BlockHeight,
+    shard_height: u8,
+    shard_end_heights: &'a BTreeMap<Address, BlockHeight>,
+    output_count: fn(&MemoryWalletBlock) -> Option<u32>,
+    tree_size: fn(&MemoryWalletBlock) -> Option<u32>,
+    birthday_tree_size: fn(&AccountBirthday) -> u64,
+}
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    /// Returns the maximum recover-until height of the wallet's accounts, or `None` if no
+    /// account has a recover-until height.
+    pub(crate) fn recover_until_height(&self) -> Option<BlockHeight> {
+        self.accounts
+            .values()
+            .filter_map(|account| account.birthday().recover_until())
+            .max()
+    }
+
+    /// Returns the scan progress for the given pool.
+    ///
+    /// Outputs between the wallet birthday and the recover-until height count towards the
+    /// recovery ratio, and outputs from the recover-until height (or the wallet birthday, if
+    /// no account has a recover-until height) to the chain tip count towards the scan ratio.
+    /// If no account has a recover-until height, the recovery ratio is `0/0`.
+    ///
+    /// Returns `None` if no blocks have been scanned, if the pool is not active, or if the size
+    /// of the note commitment tree cannot be determined at the bounds of the scan range.
+    pub(crate) fn scan_progress(
+        &self,
+        protocol: ShieldedProtocol,
+        birthday_height: BlockHeight,
+        recover_until_height: Option<BlockHeight>,
+        fully_scanned_height: BlockHeight,
+        chain_tip_height: BlockHeight,
+    ) -> Result<Option<Progress>, Error> {
+        let pool = match self.pool_data(protocol) {
+            Some(pool) if self.scanned_heights.max_height().is_some() => pool,
+            _ => return Ok(None),
+        };
+        let scan_start = recover_until_height.unwrap_or(birthday_height);
+
+        if fully_scanned_height == chain_tip_height {
+            // Everything between the wallet birthday and the chain tip has been scanned, so the
+            // outputs counted on either side of the recover-until height are complete.
+            let recover = recover_until_height.map_or(Ratio::new(0, 0), |end| {
+                let recovered = self.scanned_output_count(&pool, birthday_height..end);
+                Ratio::new(recovered, recovered)
+            });
+            let scanned = self.scanned_output_count(&pool, scan_start..);
+            return Ok(Some(Progress::new(
+                Ratio::new(scanned, scanned),
+                Some(recover),
+            )));
+        }
+
+        // Get the starting note commitment tree size from the wallet birthday, or failing that
+        // from the blocks and subtrees near the birthday height.
+        let birthday_size = self
+            .accounts
+            .values()
+            .find(|account| account.birthday().height() == birthday_height)
+            .map(|account| (pool.birthday_tree_size)(account.birthday()))
+            .or_else(|| self.tree_size_near(&pool, birthday_height));
+
+        // The outer option indicates whether any account has a recover-until height; the inner
+        // option whether the tree size at that height could be determined.
+        let recover_until_size = recover_until_height.map(|h| self.tree_size_near(&pool, h));
+
+        let recover = match recover_until_height.zip(recover_until_size) {
+            Some((end, end_size)) => {
+                let recovered = self.scanned_output_count(&pool, birthday_height..end);
+                birthday_size.zip(end_size).map(|(start_size, end_size)| {
+                    Ratio::new(recovered, end_size.saturating_sub(start_size))
+                })
+            }
+            None => Some(Ratio::new(0, 0)),
+        };
+
+        // If we've scanned the block at the chain tip, we know how many notes are currently in
+        // the tree.
+        let tip_size = self
+            .blocks
+            .get(&chain_tip_height)
+            .and_then(pool.tree_size)
+            .map(u64::from)
+            .or_else(|| self.estimate_tree_size(&pool, chain_tip_height));
+
+        let scan = recover_until_size
+            .unwrap_or(birthday_size)
+            .zip(tip_size)
+            .map(|(start_size, tip_size)| {
+                Ratio::new(
+                    self.scanned_output_count(&pool, scan_start..),
+                    tip_size.saturating_sub(start_size),
+                )
+            });
+
+        Ok(scan.map(|scan| Progress::new(scan, recover)))
+    }
+
+    fn pool_data(&self, protocol: ShieldedProtocol) -> Option<PoolData<'_>> {
+        match protocol {
+            ShieldedProtocol::Sapling => Some(PoolData {
+                activation_height: self.params.activation_height(NetworkUpgrade::Sapling)?,
+                shard_height: SAPLING_SHARD_HEIGHT,
+                shard_end_heights: &self.sapling_tree_shard_end_heights,
+                output_count: |block| block.sapling_output_count,
+                tree_size: |block| block.sapling_commitment_tree_size,
+                birthday_tree_size: |birthday| birthday.sapling_frontier().tree_size(),
+            }),
+            #[cfg(feature = "orchard")]
+            ShieldedProtocol::Orchard => Some(PoolData {
+                activation_height: self.params.activation_height(NetworkUpgrade::Nu5)?,
+                shard_height: ORCHARD_SHARD_HEIGHT,
+                shard_end_heights: &self.orchard_tree_shard_end_heights,
+                output_count: |block| block.orchard_action_count,
+                tree_size: |block| block.orchard_commitment_tree_size,
+                birthday_tree_size: |birthday| birthday.orchard_frontier().tree_size(),
+            }),
+            #[cfg(not(feature = "orchard"))]
+            ShieldedProtocol::Orchard => None,
+        }
+    }
+
+    /// Returns the number of outputs of the pool in the scanned blocks within the given range.
+    fn scanned_output_count(
+        &self,
+        pool: &PoolData<'_>,
+        range: impl std::ops::RangeBounds<BlockHeight>,
+    ) -> u64 {
+        self.blocks
+            .range(range)
+            .filter_map(|(_, block)| (pool.output_count)(block))
+            .map(u64::from)
+            .sum()
+    }
+
+    /// Returns the approximate size of the note commitment tree as of the start of the block
+    /// at the given height.
+    ///
+    /// It's fine for this to be approximate; it just shifts the boundary between the recovery
+    /// and scan ratios.
+    fn tree_size_near(&self, pool: &PoolData<'_>, as_of: BlockHeight) -> Option<u64> {
+        self.blocks
+            .range(..=as_of)
+            .filter_map(|(_, block)| {
+                (pool.tree_size)(block)
+                    .zip((pool.output_count)(block))
+                    .map(|(size, count)| u64::from(size.saturating_sub(count)))
+            })
+            .max()
+            .or_else(|| {
+                // Fall back to the start of the first subtree that does not end before the
+                // given height, or to the end of the last completed subtree.
+                pool.shard_end_heights
+                    .iter()
+                    .find(|(_, end)| **end >= as_of)
+                    .map(|(addr, _)| addr.index())
+                    .or_else(|| {
+                        pool.shard_end_heights
+                            .keys()
+                            .next_back()
+                            .map(|addr| addr.index() + 1)
+                    })
+                    .map(|index| index << pool.shard_height)
+            })
+    }
+
+    /// Estimates the size of the note commitment tree at the chain tip, by extrapolating
+    /// the density of the pool's outputs in the most recent scanned blocks or subtrees.
+    fn estimate_tree_size(
+        &self,
+        pool: &PoolData<'_>,
+        chain_tip_height: BlockHeight,
+    ) -> Option<u64> {
+        let last_scanned = self.blocks.iter().rev().find_map(|(height, block)| {
+            (pool.tree_size)(block).map(|size| (*height, u64::from(size)))
+        });
+
+        let blocks_between = |from: BlockHeight, to: BlockHeight| {
+            u64::from(u32::from(to).saturating_sub(from.into()))
+        };
+        // Extrapolates from `notes` outputs in the blocks between `from` and `to` to the number
+        // of outputs between `to` and the chain tip, and adds them to the `known` tree size.
+        let extrapolate = |known: u64, from: BlockHeight, to: BlockHeight, notes: u64| {
+            (notes * blocks_between(to, chain_tip_height))
+                .checked_div(blocks_between(from, to))
+                .map(|extrapolated| known + extrapolated)
+        };
+
+        match pool.shard_end_heights.iter().next_back() {
+            Some((last_subtree, last_subtree_end)) => {
+                let complete_notes = u64::from(last_subtree.position_range_end());
+                match last_scanned {
+                    // If the last scanned block is within the incomplete subtree, extrapolate
+                    // from the notes scanned since the end of the last completed subtree.
+                    Some((height, size)) if height > *last_subtree_end => extrapolate(
+                        size,
+                        *last_subtree_end,
+                        height,
+                        size.saturating_sub(complete_notes),
+                    ),
+                    // Otherwise, extrapolate from the density of the last completed subtree.
+                    _ => {
+                        let previous_end = last_subtree
+                            .index()
+                            .checked_sub(1)
+                            .and_then(|index| {
+                                pool.shard_end_heights.get(&Address::from_parts(
+                                    Level::from(pool.shard_height),
+                                    index,
+                                ))
+                            })
+                            .copied()
+                            .unwrap_or(pool.activation_height);
+                        extrapolate(
+                            complete_notes,
+                            previous_end,
+                            *last_subtree_end,
+                            1 << pool.shard_height,
+                        )
+                    }
+                }
+            }
+            // With no completed subtrees, extrapolate from the density of the pool's outputs
+            // since its activation.
+            None => last_scanned
+                .and_then(|(height, size)| extrapolate(size, pool.activation_height, height, size)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use incrementalmerkletree::Address;
+    use sapling::zip32::ExtendedSpendingKey;
+    use zcash_client_backend::{
+        data_api::{
+            chain::ChainState,
+            testing::{AddressType, TestBuilder},
+            AccountBirthday, AccountPurpose, Ratio, WalletRead, WalletWrite, SAPLING_SHARD_HEIGHT,
+        },
+        keys::UnifiedSpendingKey,
+    };
+    use zcash_primitives::{
+        block::BlockHash,
+        consensus::{BlockHeight, Network, NetworkUpgrade, Parameters},
+        transaction::components::amount::NonNegativeAmount,
+    };
+    use zcash_protocol::ShieldedProtocol;
+
+    use crate::{testing::TestMemDbFactory, MemBlockCache, MemoryWalletBlock, MemoryWalletDb};
+
+    /// Returns a wallet with a single account whose birthday is the block after Sapling
+    /// activation, along with the birthday height.
+    fn wallet_with_account(
+        recover_until: impl FnOnce(BlockHeight) -> Option<BlockHeight>,
+    ) -> (MemoryWalletDb<Network>, BlockHeight) {
+        let params = Network::TestNetwork;
+        let prior_height = params.activation_height(NetworkUpgrade::Sapling).unwrap();
+        let birthday_height = prior_height + 1;
+        let birthday = AccountBirthday::from_parts(
+            ChainState::empty(prior_height, BlockHash([0; 32])),
+            recover_until(birthday_height),
+        );
+        let ufvk = UnifiedSpendingKey::from_seed(&params, &[1; 32], zip32::AccountId::ZERO)
+            .unwrap()
+            .to_unified_full_viewing_key();
+
+        let mut wallet = MemoryWalletDb::new(params, 100);
+        wallet
+            .import_account_ufvk(&ufvk, &birthday, AccountPurpose::ViewOnly)
+            .unwrap();
+        (wallet, birthday_height)
+    }
+
+    /// Records `count` scanned blocks from the given height, each containing two Sapling outputs.
+    fn insert_blocks(wallet: &mut MemoryWalletDb<Network>, from: BlockHeight, count: u32) {
+        for i in 0..count {
+            let height = from + i;
+            wallet.blocks.insert(
+                height,
+                MemoryWalletBlock {
+                    height,
+                    hash: BlockHash([0; 32]),
+                    block_time: 0,
+                    _transactions: Default::default(),
+                    _memos: HashMap::new(),
+                    sapling_commitment_tree_size: Some(2 * (i + 1)),
+                    sapling_output_count: Some(2),
+                    #[cfg(feature = "orchard")]
+                    orchard_commitment_tree_size: None,
+                    #[cfg(feature = "orchard")]
+                    orchard_action_count: None,
+                },
+            );
+        }
+        wallet.scanned_heights.insert(from..from + count);
+    }
+
+    #[test]
+    fn progress_is_unknown_until_a_block_is_scanned() {
+        let (mut wallet, birthday) = wallet_with_account(|_| None);
+        // Subtree roots alone would allow the tree size at the chain tip to be estimated.
+        wallet.sapling_tree_shard_end_heights.insert(
+            Address::from_parts(SAPLING_SHARD_HEIGHT.into(), 0),
+            birthday + 5,
+        );
+
+        assert!(wallet
+            .scan_progress(
+                ShieldedProtocol::Sapling,
+                birthday,
+                None,
+                birthday - 1,
+                birthday + 9
+            )
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn fully_scanned_outputs_are_split_at_the_recover_until_height() {
+        let (mut wallet, birthday) = wallet_with_account(|b| Some(b + 4));
+        insert_blocks(&mut wallet, birthday, 10);
+        let tip = birthday + 9;
+
+        let progress = wallet
+            .scan_progress(
+                ShieldedProtocol::Sapling,
+                birthday,
+                wallet.recover_until_height(),
+                tip,
+                tip,
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(progress.recovery(), Some(Ratio::new(8, 8)));
+        assert_eq!(progress.scan(), Ratio::new(12, 12));
+    }
+
+    #[test]
+    fn partial_scan_progress_is_estimated_from_the_tree_size() {
+        let (mut wallet, birthday) = wallet_with_account(|b| Some(b + 2));
+        insert_blocks(&mut wallet, birthday, 5);
+        let tip = birthday + 9;
+
+        let progress = wallet
+            .scan_progress(
+                ShieldedProtocol::Sapling,
+                birthday,
+                wallet.recover_until_height(),
+                birthday + 4,
+                tip,
+            )
+            .unwrap()
+            .unwrap();
+        // Two blocks of two outputs each precede the recover-until height.
+        assert_eq!(progress.recovery(), Some(Ratio::new(4, 4)));
+        // Ten outputs were scanned in the five blocks since Sapling activation, so ten more
+        // are expected in the five unscanned blocks below the chain tip.
+        assert_eq!(progress.scan(), Ratio::new(6, 16));
+    }
+
+    #[test]
+    fn recovery_is_empty_without_a_recover_until_height() {
+        let (mut wallet, birthday) = wallet_with_account(|_| None);
+        insert_blocks(&mut wallet, birthday, 5);
+        let tip = birthday + 4;
+
+        assert_eq!(wallet.recover_until_height(), None);
+        let progress = wallet
+            .scan_progress(ShieldedProtocol::Sapling, birthday, None, tip, tip)
+            .unwrap()
+            .unwrap();
+        assert_eq!(progress.recovery(), Some(Ratio::new(0, 0)));
+        assert_eq!(progress.scan(), Ratio::new(10, 10));
+    }
+
+    #[test]
+    fn imported_account_contributes_recovery_progress() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let not_our_key = ExtendedSpendingKey::master(&[]).to_diversifiable_full_viewing_key();
+        let value = NonNegativeAmount::const_from_u64(10000);
+        let (h, _, _) = st.generate_next_block(&not_our_key, AddressType::DefaultExternal, value);
+        st.generate_next_block(&not_our_key, AddressType::DefaultExternal, value);
+        st.generate_next_block(&not_our_key, AddressType::DefaultExternal, value);
+        st.scan_cached_blocks(h, 3);
+
+        let summary = st.wallet().get_wallet_summary(0).unwrap().unwrap();
+        assert_eq!(summary.progress().recovery(), Some(Ratio::new(0, 0)));
+
+        // Import an account whose birthday is below the fully-scanned height, and which is
+        // recovering up to the second scanned block.
+        let ufvk = UnifiedSpendingKey::from_seed(st.network(), &[1; 32], zip32::AccountId::ZERO)
+            .unwrap()
+            .to_unified_full_viewing_key();
+        let birthday =
+            AccountBirthday::from_parts(ChainState::empty(h - 1, BlockHash([0; 32])), Some(h + 1));
+        st.wallet_mut()
+            .import_account_ufvk(&ufvk, &birthday, AccountPurpose::ViewOnly)
+            .unwrap();
+
+        let summary = st.wallet().get_wallet_summary(0).unwrap().unwrap();
+        let recovery = summary.progress().recovery().unwrap();
+        assert!(recovery.denominator() > &0);
+        assert_eq!(recovery.numerator(), recovery.denominator());
+        let scan = summary.progress().scan();
+        assert_eq!(scan.numerator(), scan.denominator());
+    }
+}
@@ -0,0 +1,127 @@
+use super::*;
+
+/// The default maximum length, in blocks, of a range returned by
+/// [`WalletRead::suggest_scan_ranges`].
+pub(crate) const DEFAULT_MAX_SCAN_RANGE_LEN: NonZeroU32 = match NonZeroU32::new(10_000) {
+    Some(len) => len,
+    None => panic!("the default maximum scan range length is nonzero"),
+};
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    /// Returns the wallet with the given maximum scan range length, in blocks.
+    ///
+    /// [`WalletRead::suggest_scan_ranges`] splits any range longer than this into consecutive
+    /// ranges of at most this many blocks, so that a sync driver that scans one suggested
+    /// range at a time regularly commits its progress to the wallet.
+    ///
+    /// The default is 10,000 blocks. This is a property of the running wallet, and is not
+    /// serialized.
+    pub fn with_max_scan_range_len(mut self, blocks: NonZeroU32) -> Self {
+        self.max_scan_range_len = blocks;
+        self
+    }
+
+    /// Sets the maximum scan range length, in blocks. See
+    /// [`MemoryWalletDb::with_max_scan_range_len`].
+    pub fn set_max_scan_range_len(&mut self, blocks: NonZeroU32) {
+        self.max_scan_range_len = blocks;
+    }
+
+    /// Returns the maximum scan range length, in blocks.
+    pub fn max_scan_range_len(&self) -> NonZeroU32 {
+        self.max_scan_range_len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+
+    use incrementalmerkletree::{Hashable, Level};
+    use zcash_client_backend::data_api::{
+        chain::CommitmentTreeRoot,
+        scanning::{ScanPriority, ScanRange},
+        testing::TestBuilder,
+        WalletCommitmentTrees, WalletRead, WalletWrite, SAPLING_SHARD_HEIGHT,
+    };
+    use zcash_primitives::block::BlockHash;
+
+    use crate::{testing::TestMemDbFactory, MemBlockCache};
+
+    #[test]
+    fn suggested_ranges_are_ordered_split_and_capped() {
+        use ScanPriority::*;
+
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let birthday = st.sapling_activation_height();
+
+        // Set up the following situation:
+        //
+        // wallet_birthday   last_shard_end     tip
+        //        |<----- 1000 ----->|<--- 500 --->|
+        let shard_end = birthday + 1000;
+        st.wallet_mut()
+            .put_sapling_subtree_roots(
+                0,
+                &[CommitmentTreeRoot::from_parts(
+                    shard_end,
+                    sapling::Node::empty_root(Level::from(SAPLING_SHARD_HEIGHT)),
+                )],
+            )
+            .unwrap();
+        let tip = birthday + 1500;
+        st.wallet_mut().update_chain_tip(tip).unwrap();
+        let chain_end = tip + 1;
+
+        // The fragment of the last shard is scanned first, and then the rest of the wallet's
+        // history from its birthday.
+        assert_eq!(
+            st.wallet().suggest_scan_ranges().unwrap(),
+            vec![
+                ScanRange::from_parts(shard_end..chain_end, ChainTip),
+                ScanRange::from_parts(birthday..shard_end, Historic),
+            ]
+        );
+
+        // Scanning a range in the middle of the history splits the remaining work around it,
+        // and the scanned range itself is never suggested.
+        let scanned = (birthday + 200)..(birthday + 400);
+        st.wallet_mut()
+            .scan_queue
+            .replace_queue_entries(
+                &scanned,
+                Some(ScanRange::from_parts(scanned.clone(), Scanned)).into_iter(),
+                false,
+            )
+            .unwrap();
+        assert_eq!(
+            st.wallet().suggest_scan_ranges().unwrap(),
+            vec![
+                ScanRange::from_parts(shard_end..chain_end, ChainTip),
+                ScanRange::from_parts(birthday..scanned.start, Historic),
+                ScanRange::from_parts(scanned.end..shard_end, Historic),
+            ]
+        );
+
+        // Ranges longer than the maximum scan range length are returned in consecutive pieces.
+        assert_eq!(st.wallet().max_scan_range_len().get(), 10_000);
+        st.wallet_mut()
+            .set_max_scan_range_len(NonZeroU32::new(250).unwrap());
+        assert_eq!(
+            st.wallet().suggest_scan_ranges().unwrap(),
+            vec![
+                ScanRange::from_parts(shard_end..(shard_end + 250), ChainTip),
+                ScanRange::from_parts((shard_end + 250)..(shard_end + 500), ChainTip),
+                ScanRange::from_parts((shard_end + 500)..chain_end, ChainTip),
+                ScanRange::from_parts(birthday..scanned.start, Historic),
+                ScanRange::from_parts(scanned.end..(scanned.end + 250), Historic),
+                ScanRange::from_parts((scanned.end + 250)..(scanned.end + 500), Historic),
+                ScanRange::from_parts((scanned.end + 500)..shard_end, Historic),
+            ]
+        );
+    }
+}
@@ -0,0 +1,216 @@
+use super::*;
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    /// Records that the caller has begun to download and scan the given range of blocks.
+    ///
+    /// As blocks at the start of the range are committed by [`WalletWrite::put_blocks`], the
+    /// start of the recorded range advances, and the record is cleared once the whole range has
+    /// been committed. Until then, [`WalletRead::suggest_scan_ranges`] returns the remainder of
+    /// the range with its original priority, ahead of the other ranges of that priority, so that
+    /// a scan that was interrupted resumes exactly where it left off. The record is included in
+    /// serialized snapshots of the wallet.
+    ///
+    /// Beginning a new range scan replaces any previous record, and truncating the wallet clears
+    /// it.
+    ///
+    /// [`WalletWrite::put_blocks`]: zcash_client_backend::data_api::WalletWrite::put_blocks
+    pub fn begin_range_scan(&mut self, range: ScanRange) {
+        self.in_progress_scan = (!range.is_empty()).then_some(range);
+    }
+
+    /// Returns the remainder of the range most recently passed to
+    /// [`MemoryWalletDb::begin_range_scan`] that has not yet been committed, if any.
+    pub fn in_progress_scan(&self) -> Option<&ScanRange> {
+        self.in_progress_scan.as_ref()
+    }
+
+    /// Advances the in-progress scan past the given range of committed blocks, if the range
+    /// includes the start of the in-progress scan.
+    pub(crate) fn advance_in_progress_scan(&mut self, committed: &Range<BlockHeight>) {
+        if let Some(range) = &self.in_progress_scan {
+            if committed.contains(&range.block_range().start) {
+                self.in_progress_scan = range.truncate_start(committed.end);
+            }
+        }
+    }
+
+    /// Clears the in-progress scan if the scan queue records every block of it as scanned.
+    pub(crate) fn drop_stale_in_progress_scan(&mut self) {
+        if let Some(range) = &self.in_progress_scan {
+            let unscanned = self.scan_queue.iter().any(|(start, end, priority)| {
+                *priority != ScanPriority::Scanned
+                    && *start < range.block_range().end
+                    && range.block_range().start < *end
+            });
+            if !unscanned {
+                self.in_progress_scan = None;
+            }
+        }
+    }
+
+    /// Returns the given suggested scan ranges with the in-progress scan (if any) moved ahead of
+    /// the other ranges of the same priority, removing the blocks that it covers from the other
+    /// ranges. Ranges of a higher priority remain ahead of the in-progress scan.
+    pub(crate) fn prioritize_in_progress_scan(&self, ranges: Vec<ScanRange>) -> Vec<ScanRange> {
+        match &self.in_progress_scan {
+            Some(in_progress) => {
+                let mut ranges = ranges
+                    .into_iter()
+                    .flat_map(|range| {
+                        [
+                            range.truncate_end(in_progress.block_range().start),
+                            range.truncate_start(in_progress.block_range().end),
+                        ]
+                        .into_iter()
+                        .flatten()
+                    })
+                    .collect::<Vec<_>>();
+                let position = ranges
+                    .iter()
+                    .position(|range| range.priority() <= in_progress.priority())
+                    .unwrap_or(ranges.len());
+                ranges.insert(position, in_progress.clone());
+                ranges
+            }
+            None => ranges,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zcash_client_backend::data_api::{
+        scanning::{ScanPriority, ScanRange},
+        testing::{AddressType, TestBuilder},
+        WalletRead, WalletWrite,
+    };
+    use zcash_primitives::{block::BlockHash, transaction::components::amount::NonNegativeAmount};
+
+    use crate::{testing::TestMemDbFactory, MemBlockCache, MemoryWalletDb};
+
+    #[test]
+    fn interrupted_scan_resumes_where_it_left_off() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let (h, _, _) = st.generate_next_block(
+            &dfvk,
+            AddressType::DefaultExternal,
+            NonNegativeAmount::const_from_u64(10000),
+        );
+        for _ in 1..10 {
+            st.generate_next_block(
+                &dfvk,
+                AddressType::DefaultExternal,
+                NonNegativeAmount::const_from_u64(10000),
+            );
+        }
+        st.wallet_mut().update_chain_tip(h + 9).unwrap();
+
+        // The driver downloads the ten blocks, but only commits the first four before it is
+        // interrupted.
+        let range = ScanRange::from_parts(h..h + 10, ScanPriority::Historic);
+        st.wallet_mut().begin_range_scan(range);
+        st.scan_cached_blocks(h, 4);
+        assert_eq!(
+            st.wallet().in_progress_scan(),
+            Some(&ScanRange::from_parts(
+                h + 4..h + 10,
+                ScanPriority::Historic
+            ))
+        );
+
+        let mut snapshot = vec![];
+        st.wallet().encode(&mut snapshot).unwrap();
+        let params = st.wallet().params.clone();
+        *st.wallet_mut() = MemoryWalletDb::decode_new(&snapshot[..], params, 100).unwrap();
+
+        // The remainder of the range is suggested ahead of the other historic ranges, but not
+        // ahead of more urgent ones.
+        let suggested = st.wallet().suggest_scan_ranges().unwrap();
+        let position = suggested
+            .iter()
+            .position(|r| r == &ScanRange::from_parts(h + 4..h + 10, ScanPriority::Historic))
+            .unwrap();
+        assert!(suggested[..position]
+            .iter()
+            .all(|r| r.priority() > ScanPriority::Historic));
+        assert!(suggested
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != position)
+            .all(|(_, r)| r.block_range().end <= h + 4 || r.block_range().start >= h + 10));
+
+        // Committing the remainder of the range clears the record.
+        st.scan_cached_blocks(h + 4, 6);
+        assert_eq!(st.wallet().in_progress_scan(), None);
+    }
+
+    #[test]
+    fn stale_in_progress_scan_is_dropped_on_load() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let (h, _, _) = st.generate_next_block(
+            &dfvk,
+            AddressType::DefaultExternal,
+            NonNegativeAmount::const_from_u64(10000),
+        );
+        st.generate_next_block(
+            &dfvk,
+            AddressType::DefaultExternal,
+            NonNegativeAmount::const_from_u64(10000),
+        );
+        st.wallet_mut().update_chain_tip(h + 1).unwrap();
+        st.scan_cached_blocks(h, 2);
+
+        // Record a scan of blocks that have already been scanned, as if the wallet had been
+        // snapshotted by another process that did not observe the commit.
+        st.wallet_mut().in_progress_scan =
+            Some(ScanRange::from_parts(h..h + 2, ScanPriority::Historic));
+
+        let mut snapshot = vec![];
+        st.wallet().encode(&mut snapshot).unwrap();
+        let restored =
+            MemoryWalletDb::decode_new(&snapshot[..], st.wallet().params.clone(), 100).unwrap();
+        assert_eq!(restored.in_progress_scan(), None);
+    }
+
+    #[test]
+    fn truncation_clears_in_progress_scan() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let (h, _, _) = st.generate_next_block(
+            &dfvk,
+            AddressType::DefaultExternal,
+            NonNegativeAmount::const_from_u64(10000),
+        );
+        for _ in 1..10 {
+            st.generate_next_block(
+                &dfvk,
+                AddressType::DefaultExternal,
+                NonNegativeAmount::const_from_u64(10000),
+            );
+        }
+        st.wallet_mut().update_chain_tip(h + 9).unwrap();
+
+        st.wallet_mut()
+            .begin_range_scan(ScanRange::from_parts(h..h + 10, ScanPriority::Historic));
+        st.scan_cached_blocks(h, 6);
+        assert!(st.wallet().in_progress_scan().is_some());
+
+        // The blocks that remain to be scanned may no longer be those that were downloaded.
+        st.wallet_mut().truncate_to_height(h + 3).unwrap();
+        assert_eq!(st.wallet().in_progress_scan(), None);
+    }
+}
@@ -0,0 +1,175 @@
+use super::*;
+
+/// Performance settings for scanning blocks into a [`MemoryWalletDb`].
+///
+/// When [`WalletWrite::put_blocks`] inserts the note commitments of a batch of scanned blocks
+/// into the note commitment trees, it first splits them into chunks of
+/// [`ScanTuning::subtree_chunk_size`] commitments and builds a subtree from each chunk,
+/// optionally in parallel. The resulting trees are the same for any valid setting.
+///
+/// Recommended values:
+/// - On multi-core targets, the default (chunks of 1024 commitments, built in parallel) keeps
+///   all cores busy for typical batch sizes. Smaller chunks spread small batches over more
+///   threads.
+/// - On single-threaded targets such as `wasm32-unknown-unknown`, disable parallelism and use
+///   larger chunks (e.g. 4096 commitments), so that fewer subtrees have to be merged into the
+///   tree.
+///
+/// Scan tuning is a property of the running wallet, and is not serialized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScanTuning {
+    subtree_chunk_size: usize,
+    use_parallelism: bool,
+}
+
+impl ScanTuning {
+    /// The smallest permitted [`ScanTuning::subtree_chunk_size`].
+    pub const MIN_SUBTREE_CHUNK_SIZE: usize = 256;
+
+    /// Sets the number of note commitments from which each subtree is built.
+    ///
+    /// Returns an error unless `subtree_chunk_size` is a power of two that is at least
+    /// [`ScanTuning::MIN_SUBTREE_CHUNK_SIZE`], so that chunks are aligned with the shards of
+    /// the note commitment trees whenever the batch being inserted is.
+    pub fn with_subtree_chunk_size(mut self, subtree_chunk_size: usize) -> Result<Self, Error> {
+        if subtree_chunk_size < Self::MIN_SUBTREE_CHUNK_SIZE
+            || !subtree_chunk_size.is_power_of_two()
+        {
+            return Err(Error::InvalidSubtreeChunkSize(subtree_chunk_size));
+        }
+        self.subtree_chunk_size = subtree_chunk_size;
+        Ok(self)
+    }
+
+    /// Sets whether subtrees are built in parallel. This has no effect unless the
+    /// `multicore` feature is enabled.
+    pub fn with_parallelism(mut self, use_parallelism: bool) -> Self {
+        self.use_parallelism = use_parallelism;
+        self
+    }
+
+    /// Returns the number of note commitments from which each subtree is built.
+    pub fn subtree_chunk_size(&self) -> usize {
+        self.subtree_chunk_size
+    }
+
+    /// Returns whether subtrees are built in parallel.
+    pub fn use_parallelism(&self) -> bool {
+        self.use_parallelism && cfg!(feature = "multicore")
+    }
+}
+
+impl Default for ScanTuning {
+    fn default() -> Self {
+        Self {
+            subtree_chunk_size: 1024,
+            use_parallelism: true,
+        }
+    }
+}
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    /// Returns the wallet with the given scan tuning.
+    pub fn with_scan_tuning(mut self, scan_tuning: ScanTuning) -> Self {
+        self.scan_tuning = scan_tuning;
+        self
+    }
+
+    /// Sets the scan tuning used by subsequent calls to [`WalletWrite::put_blocks`].
+    pub fn set_scan_tuning(&mut self, scan_tuning: ScanTuning) {
+        self.scan_tuning = scan_tuning;
+    }
+
+    /// Returns the scan tuning of the wallet.
+    pub fn scan_tuning(&self) -> &ScanTuning {
+        &self.scan_tuning
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zcash_client_backend::data_api::testing::{AddressType, FakeCompactOutput, TestBuilder};
+    use zcash_primitives::{block::BlockHash, transaction::components::amount::NonNegativeAmount};
+
+    use sapling::zip32::ExtendedSpendingKey;
+
+    use super::ScanTuning;
+    use crate::{error::Error, testing::TestMemDbFactory, MemBlockCache, MemoryWalletDb};
+
+    #[test]
+    fn subtree_chunk_size_is_validated() {
+        for size in [0, 255, 300, 1000] {
+            assert!(matches!(
+                ScanTuning::default().with_subtree_chunk_size(size),
+                Err(Error::InvalidSubtreeChunkSize(s)) if s == size
+            ));
+        }
+        for size in [256, 1024, 4096] {
+            assert_eq!(
+                ScanTuning::default()
+                    .with_subtree_chunk_size(size)
+                    .unwrap()
+                    .subtree_chunk_size(),
+                size
+            );
+        }
+    }
+
+    #[test]
+    fn chunk_size_does_not_affect_scan_results() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let not_our_key = ExtendedSpendingKey::master(&[]).to_diversifiable_full_viewing_key();
+        let params = st.wallet().params.clone();
+        let mut unscanned = vec![];
+        st.wallet().encode(&mut unscanned).unwrap();
+
+        // Each block has 300 note commitments, so that the commitments of the two blocks
+        // span several chunks of 256 but fit into a single chunk of 4096.
+        let value = NonNegativeAmount::const_from_u64(10000);
+        let outputs = (0..300)
+            .map(|i| {
+                if i % 100 == 0 {
+                    FakeCompactOutput::new(dfvk.clone(), AddressType::DefaultExternal, value)
+                } else {
+                    FakeCompactOutput::new(not_our_key.clone(), AddressType::DefaultExternal, value)
+                }
+            })
+            .collect::<Vec<_>>();
+        let (h, _, _) = st.generate_next_block_multi(&outputs);
+        st.generate_next_block_multi(&outputs);
+
+        st.wallet_mut()
+            .set_scan_tuning(ScanTuning::default().with_subtree_chunk_size(256).unwrap());
+        st.scan_cached_blocks(h, 2);
+        let small_chunk_root = st
+            .wallet()
+            .sapling_tree
+            .root_at_checkpoint_id(&(h + 1))
+            .unwrap();
+        let small_chunk_notes = st.wallet().received_notes.clone();
+        assert_eq!(small_chunk_notes.len(), 6);
+
+        *st.wallet_mut() = MemoryWalletDb::decode_new(&unscanned[..], params, 100)
+            .unwrap()
+            .with_scan_tuning(
+                ScanTuning::default()
+                    .with_subtree_chunk_size(4096)
+                    .unwrap()
+                    .with_parallelism(false),
+            );
+        st.scan_cached_blocks(h, 2);
+        assert_eq!(
+            st.wallet()
+                .sapling_tree
+                .root_at_checkpoint_id(&(h + 1))
+                .unwrap(),
+            small_chunk_root
+        );
+        assert_eq!(st.wallet().received_notes, small_chunk_notes);
+    }
+}
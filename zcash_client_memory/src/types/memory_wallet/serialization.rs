@@ -1,6 +1,7 @@
 use bytes::{Buf, BufMut};
 use consensus::Parameters;
 use prost::Message;
+use zcash_client_backend::data_api::TransactionDataRequest;
 
 use super::*;
 use crate::error::Result;
@@ -8,11 +9,54 @@ use crate::proto::memwallet as proto;
 use crate::read_optional;
 use crate::wallet_commitment_trees::serialization::{tree_from_protobuf, tree_to_protobuf};
 
+/// The version of the wallet snapshot format written by [`MemoryWalletDb::encode`].
+///
+/// - Version 1: account ids were assigned in the order in which accounts were added.
+/// - Version 2: account ids are derived from the account sources.
+pub const WALLET_SERIALIZATION_VERSION: u32 = 2;
+
+/// Upgrades a wallet snapshot written by an earlier version of this library to the current
+/// [`WALLET_SERIALIZATION_VERSION`], one version at a time.
+///
+/// Returns [`Error::UnsupportedWalletVersion`] if the snapshot has no version or was written
+/// by a newer version of the library.
+pub(crate) fn migrate_wallet_proto<P: Parameters>(
+    mut proto_wallet: proto::MemoryWallet,
+    params: &P,
+    max_checkpoints: usize,
+) -> Result<proto::MemoryWallet> {
+    if proto_wallet.version == 0 || proto_wallet.version > WALLET_SERIALIZATION_VERSION {
+        return Err(Error::UnsupportedWalletVersion(proto_wallet.version));
+    }
+    while proto_wallet.version < WALLET_SERIALIZATION_VERSION {
+        let version = proto_wallet.version;
+        match version {
+            // The version 2 wire format is unchanged, but version 1 snapshots assigned account
+            // ids in the order in which accounts were added. The ids derived from the account
+            // sources depend on the decoded viewing keys, so the wallet is decoded to remap them.
+            1 => {
+                let mut wallet = MemoryWalletDb::new_from_current_proto(
+                    proto_wallet,
+                    params.clone(),
+                    max_checkpoints,
+                )?;
+                wallet.remap_account_ids();
+                proto_wallet = (&wallet).into();
+            }
+            _ => return Err(Error::UnsupportedWalletVersion(version)),
+        }
+        proto_wallet.version = version + 1;
+    }
+    Ok(proto_wallet)
+}
+
 impl<P: Parameters> MemoryWalletDb<P> {
     /// Encode a memory wallet db as a protobuf byte buffer
     /// Always uses the latest version of the wire protocol
     pub fn encode<B: BufMut>(&self, buf: &mut B) -> Result<()> {
         let proto_wallet: proto::MemoryWallet = self.into();
+        #[cfg(feature = "metrics")]
+        wallet_metrics::record_snapshot_size(proto_wallet.encoded_len());
         proto_wallet.encode(buf)?;
         Ok(())
     }
@@ -29,21 +73,24 @@ impl<P: Parameters> MemoryWalletDb<P> {
         params: P,
         max_checkpoints: usize,
     ) -> Result<Self> {
-        match proto_wallet.version {
-            1 => Self::new_from_proto_v1(proto_wallet, params, max_checkpoints),
-            _ => Err(Error::UnsupportedProtoVersion(1, proto_wallet.version)),
-        }
+        let proto_wallet = migrate_wallet_proto(proto_wallet, &params, max_checkpoints)?;
+        Self::new_from_current_proto(proto_wallet, params, max_checkpoints)
+    }
+
+    /// Returns a deep copy of the wallet, including the contents of its note commitment trees,
+    /// for example to evaluate a proposal against a speculative state of the wallet.
+    ///
+    /// The copy has the same configuration and provenance as the wallet, and changes to either
+    /// wallet do not affect the other.
+    pub fn clone_for_snapshot(&self) -> MemoryWalletDb<P> {
+        self.clone()
     }
 
-    fn new_from_proto_v1(
+    fn new_from_current_proto(
         proto_wallet: proto::MemoryWallet,
         params: P,
         max_checkpoints: usize,
     ) -> Result<Self> {
-        if proto_wallet.version != 1 {
-            return Err(Error::UnsupportedProtoVersion(1, proto_wallet.version));
-        }
-
         let mut wallet = MemoryWalletDb::new(params, max_checkpoints);
 
         wallet.accounts = {
@@ -57,10 +104,7 @@ impl<P: Parameters> MemoryWalletDb<P> {
                     Ok((AccountId::from(id), account))
                 })
                 .collect::<Result<_>>()?;
-            Ok::<Accounts, Error>(Accounts {
-                accounts,
-                nonce: proto_accounts.account_nonce,
-            })
+            Ok::<Accounts, Error>(Accounts { accounts })
         }?;
 
         wallet.blocks = proto_wallet
@@ -74,6 +118,22 @@ impl<P: Parameters> MemoryWalletDb<P> {
             })
             .collect::<Result<_>>()?;
 
+        wallet.scanned_heights = if proto_wallet.scanned_heights.is_empty() {
+            // Snapshots written before scanned heights were tracked separately retained a
+            // record of every scanned block.
+            wallet
+                .blocks
+                .keys()
+                .map(|height| *height..*height + 1)
+                .collect()
+        } else {
+            proto_wallet
+                .scanned_heights
+                .into_iter()
+                .map(Range::from)
+                .collect()
+        };
+
         wallet.tx_table = TransactionTable(
             proto_wallet
                 .tx_table
@@ -126,23 +186,23 @@ impl<P: Parameters> MemoryWalletDb<P> {
                 .map(|proto_sent_note| {
                     let sent_note_id = read_optional!(proto_sent_note, sent_note_id)?;
                     let sent_note = read_optional!(proto_sent_note, sent_note)?;
-                    Ok((sent_note_id.try_into()?, SentNote::try_from(sent_note)?))
+                    let sent_note_id = SentNoteId::try_from(sent_note_id)?;
+                    let sent_note = SentNote::try_from((&sent_note_id, sent_note))?;
+                    Ok((sent_note_id, sent_note))
                 })
                 .collect::<Result<_>>()?,
         );
 
-        wallet.tx_locator = TxLocatorMap(
-            proto_wallet
-                .tx_locator
-                .into_iter()
-                .map(|proto_locator| {
-                    let block_height = proto_locator.block_height.into();
-                    let tx_index = proto_locator.tx_index;
-                    let tx_id = read_optional!(proto_locator, tx_id)?.try_into()?;
-                    Ok(((block_height, tx_index), tx_id))
-                })
-                .collect::<Result<_>>()?,
-        );
+        wallet.tx_locator = proto_wallet
+            .tx_locator
+            .into_iter()
+            .map(|proto_locator| {
+                let block_height = proto_locator.block_height.into();
+                let tx_index = proto_locator.tx_index;
+                let tx_id = read_optional!(proto_locator, tx_id)?.try_into()?;
+                Ok(((block_height, tx_index), tx_id))
+            })
+            .collect::<Result<TxLocatorMap>>()?;
 
         wallet.scan_queue = ScanQueue(
             proto_wallet
@@ -152,8 +212,11 @@ impl<P: Parameters> MemoryWalletDb<P> {
                 .collect(),
         );
 
-        wallet.sapling_tree =
-            tree_from_protobuf(read_optional!(proto_wallet, sapling_tree)?, 100, 16.into())?;
+        wallet.sapling_tree = tree_from_protobuf(
+            read_optional!(proto_wallet, sapling_tree)?,
+            tree_checkpoint_limit(max_checkpoints),
+            16.into(),
+        )?;
 
         wallet.sapling_tree_shard_end_heights = proto_wallet
             .sapling_tree_shard_end_heights
@@ -170,8 +233,11 @@ impl<P: Parameters> MemoryWalletDb<P> {
 
         #[cfg(feature = "orchard")]
         {
-            wallet.orchard_tree =
-                tree_from_protobuf(read_optional!(proto_wallet, orchard_tree)?, 100, 16.into())?;
+            wallet.orchard_tree = tree_from_protobuf(
+                read_optional!(proto_wallet, orchard_tree)?,
+                tree_checkpoint_limit(max_checkpoints),
+                16.into(),
+            )?;
         };
 
         #[cfg(feature = "orchard")]
@@ -226,13 +292,60 @@ impl<P: Parameters> MemoryWalletDb<P> {
                 .collect::<Result<_>>()?,
         );
 
-        wallet.transaction_data_request_queue = TransactionDataRequestQueue(
-            proto_wallet
-                .transaction_data_requests
-                .into_iter()
-                .map(TryInto::try_into)
-                .collect::<Result<_>>()?,
-        );
+        let mut transaction_data_request_queue = TransactionDataRequestQueue::new();
+        for proto_request in proto_wallet.transaction_data_requests {
+            let queued_at = proto_request.queued_at_height.map(BlockHeight::from);
+            let request = TransactionDataRequest::try_from(proto_request)?;
+            if let (
+                TransactionDataRequest::GetStatus(txid) | TransactionDataRequest::Enhancement(txid),
+                Some(queued_at),
+            ) = (&request, queued_at)
+            {
+                transaction_data_request_queue
+                    .1
+                    .entry(*txid)
+                    .or_insert(queued_at);
+            }
+            transaction_data_request_queue.0.push_back(request);
+        }
+        for outpoint in proto_wallet.transparent_spend_searches {
+            transaction_data_request_queue.queue_spend_detection(OutPoint::try_from(outpoint)?);
+        }
+        wallet.transaction_data_request_queue = transaction_data_request_queue;
+
+        wallet.purged_transactions = proto_wallet
+            .purged_transactions
+            .into_iter()
+            .map(|proto_purged| {
+                let txid = read_optional!(proto_purged, tx_id)?.try_into()?;
+                Ok((txid, proto_purged.purge_height.into()))
+            })
+            .collect::<Result<_>>()?;
+
+        if let Some(provenance) = proto_wallet.provenance {
+            wallet.provenance = provenance.try_into()?;
+        } else {
+            // The snapshot predates provenance tracking, so its origin is unknown.
+            wallet.provenance = WalletProvenance::unknown();
+        }
+        wallet.provenance.record_restore();
+
+        wallet.reorg_stats = proto_wallet
+            .reorg_stats
+            .map(ReorgStats::try_from)
+            .transpose()?
+            .unwrap_or_default();
+
+        wallet.in_progress_scan = proto_wallet.in_progress_scan.map(|record| {
+            let (start, end, priority) = record.into();
+            ScanRange::from_parts(start..end, priority)
+        });
+        wallet.drop_stale_in_progress_scan();
+
+        // Snapshots written before transaction table entries were reconciled with the
+        // transaction locator map may record located transactions as unmined.
+        wallet.backfill_tx_locations();
+        wallet.check_tx_locations()?;
 
         Ok(wallet)
     }
@@ -265,7 +378,7 @@ impl TryFrom<proto::TxId> for TxId {
 impl<P: Parameters> From<&MemoryWalletDb<P>> for proto::MemoryWallet {
     fn from(wallet: &MemoryWalletDb<P>) -> Self {
         Self {
-            version: 1,
+            version: WALLET_SERIALIZATION_VERSION,
             accounts: Some(proto::Accounts {
                 accounts: wallet
                     .accounts
@@ -274,7 +387,7 @@ impl<P: Parameters> From<&MemoryWalletDb<P>> for proto::MemoryWallet {
                     .into_values()
                     .map(proto::Account::from)
                     .collect(),
-                account_nonce: wallet.accounts.nonce,
+                account_nonce: 0,
             }),
 
             blocks: wallet
@@ -423,11 +536,199 @@ impl<P: Parameters> From<&MemoryWalletDb<P>> for proto::MemoryWallet {
 
             transaction_data_requests: wallet
                 .transaction_data_request_queue
-                .0
-                .clone()
-                .into_iter()
-                .map(Into::into)
+                .iter()
+                .map(|request| {
+                    let queued_at = match request {
+                        TransactionDataRequest::GetStatus(txid)
+                        | TransactionDataRequest::Enhancement(txid) => {
+                            wallet.transaction_data_request_queue.queued_at(txid)
+                        }
+                        #[cfg(feature = "transparent-inputs")]
+                        TransactionDataRequest::SpendsFromAddress { .. } => None,
+                    };
+                    proto::TransactionDataRequest {
+                        queued_at_height: queued_at.map(u32::from),
+                        ..request.clone().into()
+                    }
+                })
                 .collect(),
+
+            purged_transactions: wallet
+                .purged_transactions
+                .iter()
+                .map(|(txid, height)| proto::PurgedTransactionRecord {
+                    tx_id: Some(txid.into()),
+                    purge_height: (*height).into(),
+                })
+                .collect(),
+
+            provenance: Some((&wallet.provenance).into()),
+
+            reorg_stats: Some((&wallet.reorg_stats).into()),
+
+            scanned_heights: wallet.scanned_heights.iter().map(Into::into).collect(),
+
+            transparent_spend_searches: wallet
+                .transaction_data_request_queue
+                .spend_detections()
+                .cloned()
+                .map(proto::OutPoint::from)
+                .collect(),
+
+            in_progress_scan: wallet.in_progress_scan.as_ref().map(|range| {
+                proto::ScanQueueRecord::from((
+                    range.block_range().start,
+                    range.block_range().end,
+                    range.priority(),
+                ))
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, num::NonZeroU32};
+
+    use sapling::zip32::ExtendedSpendingKey;
+    use zcash_client_backend::{
+        data_api::{
+            testing::{AddressType, TestBuilder},
+            Account as _, InputSource, WalletRead, WalletTest,
+        },
+        fees::StandardFeeRule,
+        wallet::OvkPolicy,
+    };
+    use zcash_keys::address::Address;
+    use zcash_primitives::{block::BlockHash, transaction::components::amount::NonNegativeAmount};
+    use zcash_protocol::{consensus::Network, ShieldedProtocol};
+
+    use super::{migrate_wallet_proto, WALLET_SERIALIZATION_VERSION};
+    use crate::{
+        error::Error, proto::memwallet as proto, testing::TestMemDbFactory, MemBlockCache,
+        MemoryWalletDb,
+    };
+
+    #[test]
+    fn old_snapshots_are_migrated_to_the_current_version() {
+        let wallet = MemoryWalletDb::new(Network::TestNetwork, 100);
+        let mut snapshot = proto::MemoryWallet::from(&wallet);
+        assert_eq!(snapshot.version, WALLET_SERIALIZATION_VERSION);
+
+        snapshot.version = 1;
+        let migrated = migrate_wallet_proto(snapshot.clone(), &Network::TestNetwork, 100).unwrap();
+        assert_eq!(migrated.version, WALLET_SERIALIZATION_VERSION);
+        assert!(
+            MemoryWalletDb::new_from_proto(snapshot, Network::TestNetwork, 100).unwrap() == wallet
+        );
+    }
+
+    #[test]
+    fn unknown_snapshot_versions_are_rejected() {
+        let wallet = MemoryWalletDb::new(Network::TestNetwork, 100);
+        for version in [0, WALLET_SERIALIZATION_VERSION + 1] {
+            let mut snapshot = proto::MemoryWallet::from(&wallet);
+            snapshot.version = version;
+            assert!(matches!(
+                MemoryWalletDb::new_from_proto(snapshot, Network::TestNetwork, 100),
+                Err(Error::UnsupportedWalletVersion(v)) if v == version
+            ));
         }
     }
+
+    #[test]
+    fn populated_wallet_roundtrip_is_lossless() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let account = st.test_account().cloned().unwrap();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let value = NonNegativeAmount::const_from_u64(60000);
+        let (h, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        st.generate_next_block(&dfvk, AddressType::Internal, value);
+        st.scan_cached_blocks(h, 2);
+
+        let to = Address::Sapling(ExtendedSpendingKey::master(&[]).default_address().1);
+        let proposal = st
+            .propose_standard_transfer::<Infallible>(
+                account.id(),
+                StandardFeeRule::Zip317,
+                NonZeroU32::new(1).unwrap(),
+                &to,
+                NonNegativeAmount::const_from_u64(10000),
+                None,
+                None,
+                ShieldedProtocol::Sapling,
+            )
+            .unwrap();
+        let txid = st
+            .create_proposed_transactions::<Infallible, _, Infallible>(
+                account.usk(),
+                OvkPolicy::Sender,
+                &proposal,
+            )
+            .unwrap()[0];
+        let (h, _) = st.generate_next_block_including(txid);
+        st.scan_cached_blocks(h, 1);
+
+        let mut buf = vec![];
+        st.wallet().encode(&mut buf).unwrap();
+        let restored = MemoryWalletDb::decode_new(&buf[..], *st.network(), 100).unwrap();
+        assert!(&restored == st.wallet());
+
+        // Compare the results of the queries that depend on the parts of the wallet that are
+        // not covered by its `PartialEq` implementation, such as the note commitment trees.
+        let summary = |wallet: &MemoryWalletDb<_>| wallet.get_wallet_summary(1).unwrap();
+        assert_eq!(summary(&restored), summary(st.wallet()));
+
+        let history = |wallet: &MemoryWalletDb<_>| {
+            wallet
+                .get_tx_history()
+                .unwrap()
+                .into_iter()
+                .map(|tx| {
+                    (
+                        tx.txid(),
+                        tx.mined_height(),
+                        tx.account_value_delta(),
+                        tx.fee_paid(),
+                        tx.sent_note_count(),
+                        tx.received_note_count(),
+                        tx.memo_count(),
+                        tx.expired_unmined(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(history(&restored), history(st.wallet()));
+
+        let checkpoints = |wallet: &MemoryWalletDb<_>| {
+            wallet
+                .get_checkpoint_history(&ShieldedProtocol::Sapling)
+                .unwrap()
+        };
+        assert_eq!(checkpoints(&restored), checkpoints(st.wallet()));
+
+        let spendable = |wallet: &MemoryWalletDb<_>| {
+            let (_, anchor_height) = wallet
+                .get_target_and_anchor_heights(NonZeroU32::new(1).unwrap())
+                .unwrap()
+                .unwrap();
+            wallet
+                .select_spendable_notes(
+                    account.id(),
+                    NonNegativeAmount::const_from_u64(100000),
+                    &[ShieldedProtocol::Sapling],
+                    anchor_height,
+                    &[],
+                )
+                .unwrap()
+                .sapling()
+                .to_vec()
+        };
+        assert!(!spendable(st.wallet()).is_empty());
+        assert_eq!(spendable(&restored), spendable(st.wallet()));
+    }
 }
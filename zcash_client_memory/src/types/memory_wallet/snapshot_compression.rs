@@ -0,0 +1,428 @@
+use std::io::{Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use prost::Message;
+
+use super::*;
+use crate::error::Result;
+use crate::proto::memwallet as proto;
+
+/// The magic bytes with which every compressed snapshot begins.
+const SNAPSHOT_MAGIC: [u8; 4] = *b"ZMWZ";
+
+/// The version of the compressed snapshot container format.
+const SNAPSHOT_FORMAT_VERSION: u8 = 1;
+
+/// The encoded length of a single entry in the section table.
+const SECTION_ENTRY_LEN: usize = 1 + 8 + 8 + 8;
+
+/// A section of a compressed snapshot.
+///
+/// Each section holds a subset of the fields of the protobuf encoding of the wallet, and is
+/// compressed independently so that it can be located and decompressed on its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SnapshotSection {
+    /// Accounts, blocks, notes, and all other wallet state not held by another section.
+    Wallet,
+    /// The transaction table, including raw transaction bytes.
+    Transactions,
+    /// The note commitment trees and their shard end heights.
+    Trees,
+}
+
+impl SnapshotSection {
+    fn code(self) -> u8 {
+        match self {
+            SnapshotSection::Wallet => 0,
+            SnapshotSection::Transactions => 1,
+            SnapshotSection::Trees => 2,
+        }
+    }
+
+    fn from_code(code: u8) -> Result<Self> {
+        match code {
+            0 => Ok(SnapshotSection::Wallet),
+            1 => Ok(SnapshotSection::Transactions),
+            2 => Ok(SnapshotSection::Trees),
+            _ => Err(corrupted(format!("unknown section {}", code))),
+        }
+    }
+
+    /// Moves the fields belonging to this section out of the given wallet encoding.
+    fn take(self, wallet: &mut proto::MemoryWallet) -> proto::MemoryWallet {
+        match self {
+            SnapshotSection::Wallet => std::mem::take(wallet),
+            SnapshotSection::Transactions => proto::MemoryWallet {
+                tx_table: std::mem::take(&mut wallet.tx_table),
+                ..Default::default()
+            },
+            SnapshotSection::Trees => proto::MemoryWallet {
+                sapling_tree: wallet.sapling_tree.take(),
+                sapling_tree_shard_end_heights: std::mem::take(
+                    &mut wallet.sapling_tree_shard_end_heights,
+                ),
+                orchard_tree: wallet.orchard_tree.take(),
+                orchard_tree_shard_end_heights: std::mem::take(
+                    &mut wallet.orchard_tree_shard_end_heights,
+                ),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// The location and size of a section within a compressed snapshot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnapshotSectionInfo {
+    section: SnapshotSection,
+    offset: u64,
+    compressed_len: u64,
+    uncompressed_len: u64,
+}
+
+impl SnapshotSectionInfo {
+    /// Returns which section this is.
+    pub fn section(&self) -> SnapshotSection {
+        self.section
+    }
+
+    /// Returns the offset of the compressed section from the start of the snapshot.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Returns the length of the compressed section.
+    pub fn compressed_len(&self) -> u64 {
+        self.compressed_len
+    }
+
+    /// Returns the length of the protobuf encoding of the section before compression.
+    pub fn uncompressed_len(&self) -> u64 {
+        self.uncompressed_len
+    }
+}
+
+/// Describes the layout of a compressed snapshot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompressedSnapshotMetadata {
+    header_len: u64,
+    sections: Vec<SnapshotSectionInfo>,
+}
+
+impl CompressedSnapshotMetadata {
+    /// Reads the header of a compressed snapshot without decompressing any of its sections.
+    pub fn read(snapshot: &[u8]) -> Result<Self> {
+        let mut header = snapshot;
+        let mut magic = [0u8; 4];
+        header
+            .read_exact(&mut magic)
+            .map_err(|_| corrupted("truncated header"))?;
+        if magic != SNAPSHOT_MAGIC {
+            return Err(corrupted("not a compressed wallet snapshot"));
+        }
+        let version = header
+            .read_u8()
+            .map_err(|_| corrupted("truncated header"))?;
+        if version != SNAPSHOT_FORMAT_VERSION {
+            return Err(Error::UnsupportedProtoVersion(
+                SNAPSHOT_FORMAT_VERSION.into(),
+                version.into(),
+            ));
+        }
+        let section_count = header
+            .read_u32::<LittleEndian>()
+            .map_err(|_| corrupted("truncated header"))?;
+
+        let header_len = (SNAPSHOT_MAGIC.len() + 1 + 4) as u64
+            + u64::from(section_count) * SECTION_ENTRY_LEN as u64;
+        if header_len > snapshot.len() as u64 {
+            return Err(corrupted("truncated section table"));
+        }
+
+        let mut sections = vec![];
+        for _ in 0..section_count {
+            let info = SnapshotSectionInfo {
+                section: SnapshotSection::from_code(header.read_u8()?)?,
+                offset: header.read_u64::<LittleEndian>()?,
+                compressed_len: header.read_u64::<LittleEndian>()?,
+                uncompressed_len: header.read_u64::<LittleEndian>()?,
+            };
+            let in_bounds = info.offset >= header_len
+                && info
+                    .offset
+                    .checked_add(info.compressed_len)
+                    .map_or(false, |end| end <= snapshot.len() as u64);
+            if !in_bounds {
+                return Err(corrupted(format!(
+                    "section {:?} lies outside of the snapshot",
+                    info.section
+                )));
+            }
+            if sections
+                .iter()
+                .any(|s: &SnapshotSectionInfo| s.section == info.section)
+            {
+                return Err(corrupted(format!(
+                    "section {:?} appears more than once",
+                    info.section
+                )));
+            }
+            sections.push(info);
+        }
+
+        Ok(Self {
+            header_len,
+            sections,
+        })
+    }
+
+    /// Returns the sections of the snapshot, in the order in which they are stored.
+    pub fn sections(&self) -> &[SnapshotSectionInfo] {
+        &self.sections
+    }
+
+    /// Returns the total length of the snapshot, including its header.
+    pub fn compressed_len(&self) -> u64 {
+        self.header_len + self.sections.iter().map(|s| s.compressed_len).sum::<u64>()
+    }
+
+    /// Returns the total length of the protobuf encoding of the wallet before compression.
+    pub fn uncompressed_len(&self) -> u64 {
+        self.sections.iter().map(|s| s.uncompressed_len).sum()
+    }
+
+    /// Returns the ratio of the uncompressed length of the wallet to the length of the
+    /// snapshot. Values greater than 1 indicate that compression reduced the size.
+    pub fn compression_ratio(&self) -> f64 {
+        self.uncompressed_len() as f64 / self.compressed_len() as f64
+    }
+}
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    /// Writes the wallet as a compressed snapshot, returning a description of its layout.
+    ///
+    /// The snapshot holds the same protobuf encoding as [`MemoryWalletDb::encode`], split into
+    /// [`SnapshotSection`]s that are each zlib-compressed and located via a table in the
+    /// snapshot header.
+    pub fn serialize_compressed<W: Write>(
+        &self,
+        mut writer: W,
+    ) -> Result<CompressedSnapshotMetadata> {
+        let mut proto_wallet: proto::MemoryWallet = self.into();
+
+        // Take the trees and transactions out before the remaining wallet state.
+        let mut sections = vec![];
+        for section in [
+            SnapshotSection::Transactions,
+            SnapshotSection::Trees,
+            SnapshotSection::Wallet,
+        ] {
+            let encoded = section.take(&mut proto_wallet).encode_to_vec();
+            let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+            encoder.write_all(&encoded)?;
+            sections.push((section, encoded.len() as u64, encoder.finish()?));
+        }
+        sections.sort_by_key(|(section, _, _)| *section);
+
+        let header_len = (SNAPSHOT_MAGIC.len() + 1 + 4 + sections.len() * SECTION_ENTRY_LEN) as u64;
+        let mut offset = header_len;
+        let mut infos = Vec::with_capacity(sections.len());
+        for (section, uncompressed_len, compressed) in &sections {
+            infos.push(SnapshotSectionInfo {
+                section: *section,
+                offset,
+                compressed_len: compressed.len() as u64,
+                uncompressed_len: *uncompressed_len,
+            });
+            offset += compressed.len() as u64;
+        }
+
+        writer.write_all(&SNAPSHOT_MAGIC)?;
+        writer.write_u8(SNAPSHOT_FORMAT_VERSION)?;
+        writer.write_u32::<LittleEndian>(u32::try_from(infos.len())?)?;
+        for info in &infos {
+            writer.write_u8(info.section.code())?;
+            writer.write_u64::<LittleEndian>(info.offset)?;
+            writer.write_u64::<LittleEndian>(info.compressed_len)?;
+            writer.write_u64::<LittleEndian>(info.uncompressed_len)?;
+        }
+        for (_, _, compressed) in &sections {
+            writer.write_all(compressed)?;
+        }
+
+        Ok(CompressedSnapshotMetadata {
+            header_len,
+            sections: infos,
+        })
+    }
+
+    /// Restores a wallet from a snapshot written by [`MemoryWalletDb::serialize_compressed`].
+    ///
+    /// Sections are decompressed one at a time and merged into the wallet encoding, so only a
+    /// single section is held in decompressed form at once. Because sections are located by
+    /// offset, a snapshot that is memory-mapped is only read where its sections lie.
+    pub fn deserialize_compressed(
+        snapshot: &[u8],
+        params: P,
+        max_checkpoints: usize,
+    ) -> Result<Self> {
+        let metadata = CompressedSnapshotMetadata::read(snapshot)?;
+        if !metadata
+            .sections
+            .iter()
+            .any(|s| s.section == SnapshotSection::Wallet)
+        {
+            return Err(corrupted("missing wallet section"));
+        }
+
+        let mut proto_wallet = proto::MemoryWallet::default();
+        for info in &metadata.sections {
+            let start = usize::try_from(info.offset)?;
+            let end = usize::try_from(info.offset + info.compressed_len)?;
+            let section = read_section(&snapshot[start..end], info)?;
+            proto_wallet.merge(&section[..])?;
+        }
+
+        Self::new_from_proto(proto_wallet, params, max_checkpoints)
+    }
+}
+
+/// Decompresses a section, checking that it has the length recorded in the section table.
+fn read_section(compressed: &[u8], info: &SnapshotSectionInfo) -> Result<Vec<u8>> {
+    // The recorded length is untrusted, so the buffer grows as the section is decompressed
+    // rather than being allocated up front. Read one byte beyond the recorded length so that
+    // overlong sections are detected without decompressing them in full.
+    let mut section = vec![];
+    ZlibDecoder::new(compressed)
+        .take(info.uncompressed_len.saturating_add(1))
+        .read_to_end(&mut section)
+        .map_err(|e| {
+            corrupted(format!(
+                "section {:?} failed to decompress: {}",
+                info.section, e
+            ))
+        })?;
+    if section.len() as u64 != info.uncompressed_len {
+        return Err(corrupted(format!(
+            "section {:?} has length {}, expected {}",
+            info.section,
+            section.len(),
+            info.uncompressed_len
+        )));
+    }
+    Ok(section)
+}
+
+fn corrupted(message: impl std::fmt::Display) -> Error {
+    Error::CorruptedData(format!("Invalid compressed snapshot: {}", message))
+}
+
+#[cfg(test)]
+mod tests {
+    use zcash_client_backend::data_api::testing::{AddressType, TestBuilder};
+    use zcash_primitives::{block::BlockHash, transaction::components::amount::NonNegativeAmount};
+
+    use super::{CompressedSnapshotMetadata, SnapshotSection};
+    use crate::{error::Error, proto::memwallet as proto, testing::TestMemDbFactory};
+    use crate::{MemBlockCache, MemoryWalletDb};
+
+    fn wallet_state<P: zcash_protocol::consensus::Parameters>(
+        wallet: &MemoryWalletDb<P>,
+    ) -> proto::MemoryWallet {
+        let mut state = proto::MemoryWallet::from(wallet);
+        // Restoring a wallet updates its provenance.
+        state.provenance = None;
+        state
+    }
+
+    #[test]
+    fn compressed_snapshot_roundtrip() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let (h, _, _) = st.generate_next_block(
+            &dfvk,
+            AddressType::DefaultExternal,
+            NonNegativeAmount::const_from_u64(50000),
+        );
+        st.generate_next_block(
+            &dfvk,
+            AddressType::DefaultExternal,
+            NonNegativeAmount::const_from_u64(30000),
+        );
+        st.scan_cached_blocks(h, 2);
+
+        let wallet = st.wallet();
+        let mut snapshot = vec![];
+        let metadata = wallet.serialize_compressed(&mut snapshot).unwrap();
+        assert_eq!(metadata.compressed_len(), snapshot.len() as u64);
+        assert_eq!(
+            CompressedSnapshotMetadata::read(&snapshot).unwrap(),
+            metadata
+        );
+        assert_eq!(
+            metadata
+                .sections()
+                .iter()
+                .map(|s| s.section())
+                .collect::<Vec<_>>(),
+            vec![
+                SnapshotSection::Wallet,
+                SnapshotSection::Transactions,
+                SnapshotSection::Trees
+            ]
+        );
+
+        let mut encoded = vec![];
+        wallet.encode(&mut encoded).unwrap();
+        assert_eq!(metadata.uncompressed_len(), encoded.len() as u64);
+        assert!(metadata.compression_ratio() > 0.0);
+
+        let restored =
+            MemoryWalletDb::deserialize_compressed(&snapshot, wallet.params.clone(), 100).unwrap();
+        assert_eq!(wallet_state(&restored), wallet_state(wallet));
+        assert_eq!(restored.provenance().restore_count(), 1);
+    }
+
+    #[test]
+    fn corrupted_snapshot_is_rejected() {
+        let wallet = MemoryWalletDb::new(zcash_protocol::consensus::Network::MainNetwork, 100);
+        let mut snapshot = vec![];
+        let metadata = wallet.serialize_compressed(&mut snapshot).unwrap();
+        let restore = |snapshot: &[u8]| {
+            MemoryWalletDb::deserialize_compressed(
+                snapshot,
+                zcash_protocol::consensus::Network::MainNetwork,
+                100,
+            )
+        };
+        assert!(restore(&snapshot).is_ok());
+
+        // Damage the compressed payload of the wallet section.
+        let mut damaged = snapshot.clone();
+        let wallet_section = metadata.sections()[0];
+        let middle = wallet_section.offset() + wallet_section.compressed_len() / 2;
+        damaged[middle as usize] ^= 0xff;
+        assert!(matches!(restore(&damaged), Err(Error::CorruptedData(_))));
+
+        // Truncate the snapshot.
+        assert!(matches!(
+            restore(&snapshot[..snapshot.len() - 1]),
+            Err(Error::CorruptedData(_))
+        ));
+
+        // Replace the magic bytes.
+        let mut damaged = snapshot.clone();
+        damaged[0] = b'X';
+        assert!(matches!(restore(&damaged), Err(Error::CorruptedData(_))));
+
+        // The uncompressed encoding is not a compressed snapshot.
+        let mut encoded = vec![];
+        wallet.encode(&mut encoded).unwrap();
+        assert!(matches!(restore(&encoded), Err(Error::CorruptedData(_))));
+    }
+}
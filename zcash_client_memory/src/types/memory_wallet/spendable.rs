@@ -0,0 +1,260 @@
+use std::iter;
+
+use zcash_client_backend::fees::StandardFeeRule;
+use zcash_primitives::transaction::fees::{transparent::InputSize, FeeRule};
+use zcash_protocol::value::{BalanceError, Zatoshis};
+
+use super::*;
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    /// Returns whether the given account has at least `at_least` in shielded notes that are
+    /// spendable with the given number of confirmations.
+    ///
+    /// Notes are considered spendable under the same rules that are applied when selecting
+    /// notes for a proposal. No allowance is made for the fee of a transaction spending them;
+    /// use [`MemoryWalletDb::max_spendable`] to obtain the amount that can actually be sent.
+    pub fn has_spendable_funds(
+        &self,
+        account: AccountId,
+        at_least: Zatoshis,
+        min_confirmations: NonZeroU32,
+    ) -> Result<bool, Error> {
+        let mut total = Zatoshis::ZERO;
+        for (_, value) in self.spendable_note_values(account, min_confirmations)? {
+            total = (total + value).ok_or(BalanceError::Overflow)?;
+            if total >= at_least {
+                return Ok(true);
+            }
+        }
+        Ok(at_least == Zatoshis::ZERO)
+    }
+
+    /// Returns the largest amount that the given account can send to a single shielded
+    /// recipient, spending only shielded notes that are spendable with the given number of
+    /// confirmations.
+    ///
+    /// Notes are accumulated from largest to smallest value; under ZIP 317 each note beyond
+    /// the grace actions adds the marginal fee, so the result is the largest net value over
+    /// all such prefixes. The fee is estimated assuming that the payment and its change
+    /// output are created in each pool from which notes are spent, so that proposing a
+    /// transfer of the returned amount does not fail for lack of funds.
+    pub fn max_spendable(
+        &self,
+        account: AccountId,
+        min_confirmations: NonZeroU32,
+        fee_rule: StandardFeeRule,
+    ) -> Result<Zatoshis, Error> {
+        let target_height = match self.get_target_and_anchor_heights(min_confirmations)? {
+            Some((target_height, _)) => target_height,
+            None => return Ok(Zatoshis::ZERO),
+        };
+
+        let mut values = self.spendable_note_values(account, min_confirmations)?;
+        values.sort_by(|(_, a), (_, b)| b.cmp(a));
+
+        let mut total = Zatoshis::ZERO;
+        let mut max = Zatoshis::ZERO;
+        let (mut sapling_count, mut orchard_count) = (0, 0);
+        for (protocol, value) in values {
+            match protocol {
+                ShieldedProtocol::Sapling => sapling_count += 1,
+                ShieldedProtocol::Orchard => orchard_count += 1,
+            }
+            total = (total + value).ok_or(BalanceError::Overflow)?;
+            let fee = self.estimate_fee(&fee_rule, target_height, sapling_count, orchard_count)?;
+            if let Some(sendable) = total - fee {
+                max = max.max(sendable);
+            }
+        }
+
+        Ok(max)
+    }
+
+    /// Returns the pool and value of each shielded note belonging to the given account that is
+    /// spendable with the given number of confirmations.
+    fn spendable_note_values(
+        &self,
+        account: AccountId,
+        min_confirmations: NonZeroU32,
+    ) -> Result<Vec<(ShieldedProtocol, Zatoshis)>, Error> {
+        self.accounts
+            .get(account)
+            .ok_or(Error::AccountUnknown(account))?;
+
+        let anchor_height = match self.get_target_and_anchor_heights(min_confirmations)? {
+            Some((_, anchor_height)) => anchor_height,
+            None => return Ok(vec![]),
+        };
+        let birthday_height = match self.get_wallet_birthday()? {
+            Some(birthday_height) => birthday_height,
+            None => return Ok(vec![]),
+        };
+
+        let mut values = vec![];
+        for note in self
+            .received_notes
+            .iter()
+            .filter(|note| note.account_id == account)
+        {
+            if self.note_is_spendable(note, birthday_height, anchor_height, &[])?
+                && self.note_is_witnessable(note, anchor_height)?
+            {
+                values.push((note.note.protocol(), note.note.value()));
+            }
+        }
+        Ok(values)
+    }
+
+    /// Estimates the fee for a transaction spending the given numbers of Sapling and Orchard
+    /// notes, with a payment and a change output in each pool from which notes are spent.
+    fn estimate_fee(
+        &self,
+        fee_rule: &StandardFeeRule,
+        target_height: BlockHeight,
+        sapling_input_count: usize,
+        orchard_input_count: usize,
+    ) -> Result<Zatoshis, Error> {
+        let output_count = |input_count: usize| if input_count > 0 { 2 } else { 0 };
+        let bundle_error = |e: &str| Error::Other(format!("Invalid bundle shape: {}", e));
+
+        let sapling_bundle = sapling::builder::BundleType::DEFAULT;
+        let sapling_spend_count = sapling_bundle
+            .num_spends(sapling_input_count)
+            .map_err(bundle_error)?;
+        let sapling_output_count = sapling_bundle
+            .num_outputs(sapling_input_count, output_count(sapling_input_count))
+            .map_err(bundle_error)?;
+
+        #[cfg(feature = "orchard")]
+        let orchard_action_count = orchard::builder::BundleType::DEFAULT
+            .num_actions(orchard_input_count, output_count(orchard_input_count))
+            .map_err(bundle_error)?;
+        #[cfg(not(feature = "orchard"))]
+        let orchard_action_count = orchard_input_count;
+
+        fee_rule
+            .fee_required(
+                &self.params,
+                target_height,
+                iter::empty::<InputSize>(),
+                iter::empty::<usize>(),
+                sapling_spend_count,
+                sapling_output_count,
+                orchard_action_count,
+            )
+            .map_err(|e| Error::Other(format!("Fee calculation failed: {}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, num::NonZeroU32};
+
+    use sapling::zip32::ExtendedSpendingKey;
+    use zcash_client_backend::{
+        data_api::{
+            error::Error as DataApiError,
+            testing::{AddressType, TestBuilder},
+            Account as _,
+        },
+        fees::StandardFeeRule,
+    };
+    use zcash_keys::address::Address;
+    use zcash_primitives::{block::BlockHash, transaction::components::amount::NonNegativeAmount};
+    use zcash_protocol::ShieldedProtocol;
+
+    use crate::{testing::TestMemDbFactory, MemBlockCache};
+
+    #[test]
+    fn max_spendable_can_be_proposed() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let account = st.test_account().cloned().unwrap();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let min_confirmations = NonZeroU32::new(1).unwrap();
+
+        assert_eq!(
+            st.wallet()
+                .max_spendable(account.id(), min_confirmations, StandardFeeRule::Zip317)
+                .unwrap(),
+            NonNegativeAmount::ZERO
+        );
+        assert!(!st
+            .wallet()
+            .has_spendable_funds(
+                account.id(),
+                NonNegativeAmount::const_from_u64(1),
+                min_confirmations
+            )
+            .unwrap());
+
+        // Three spendable notes, and one that is not worth spending under ZIP 317.
+        let (h, _, _) = st.generate_next_block(
+            &dfvk,
+            AddressType::DefaultExternal,
+            NonNegativeAmount::const_from_u64(50000),
+        );
+        for value in [30000, 20000, 4000] {
+            st.generate_next_block(
+                &dfvk,
+                AddressType::DefaultExternal,
+                NonNegativeAmount::const_from_u64(value),
+            );
+        }
+        st.scan_cached_blocks(h, 4);
+
+        // ZIP 317 charges for three logical actions when spending three notes.
+        let max = st
+            .wallet()
+            .max_spendable(account.id(), min_confirmations, StandardFeeRule::Zip317)
+            .unwrap();
+        assert_eq!(max, NonNegativeAmount::const_from_u64(100000 - 15000));
+        assert!(st
+            .wallet()
+            .has_spendable_funds(
+                account.id(),
+                NonNegativeAmount::const_from_u64(100000),
+                min_confirmations
+            )
+            .unwrap());
+        assert!(!st
+            .wallet()
+            .has_spendable_funds(
+                account.id(),
+                NonNegativeAmount::const_from_u64(100001),
+                min_confirmations
+            )
+            .unwrap());
+
+        let not_our_key = ExtendedSpendingKey::master(&[]).to_diversifiable_full_viewing_key();
+        let to = Address::Sapling(not_our_key.default_address().1);
+        assert!(st
+            .propose_standard_transfer::<Infallible>(
+                account.id(),
+                StandardFeeRule::Zip317,
+                min_confirmations,
+                &to,
+                max,
+                None,
+                None,
+                ShieldedProtocol::Sapling,
+            )
+            .is_ok());
+        assert!(matches!(
+            st.propose_standard_transfer::<Infallible>(
+                account.id(),
+                StandardFeeRule::Zip317,
+                min_confirmations,
+                &to,
+                (max + NonNegativeAmount::const_from_u64(1)).unwrap(),
+                None,
+                None,
+                ShieldedProtocol::Sapling,
+            ),
+            Err(DataApiError::InsufficientFunds { .. })
+        ));
+    }
+}
@@ -0,0 +1,145 @@
+use super::*;
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    /// Returns the wallet with the storage of transactions built from stale proposals allowed
+    /// or rejected.
+    ///
+    /// By default, [`WalletWrite::store_transactions_to_be_sent`] returns
+    /// [`Error::StaleProposal`] if any of the transactions was constructed for a target height
+    /// that the wallet has already fully scanned, because such a transaction was built against
+    /// a stale view of the chain and would otherwise lock its inputs until it expires. Allowing
+    /// stale proposals disables this check; this is intended for tests and for callers that
+    /// construct transactions for heights of their own choosing.
+    ///
+    /// This is a property of the running wallet, and is not serialized.
+    ///
+    /// [`WalletWrite::store_transactions_to_be_sent`]: zcash_client_backend::data_api::WalletWrite::store_transactions_to_be_sent
+    pub fn with_stale_proposals_allowed(mut self, allow: bool) -> Self {
+        self.allow_stale_proposals = allow;
+        self
+    }
+
+    /// Allows or rejects the storage of transactions built from stale proposals. See
+    /// [`MemoryWalletDb::with_stale_proposals_allowed`].
+    pub fn set_allow_stale_proposals(&mut self, allow: bool) {
+        self.allow_stale_proposals = allow;
+    }
+
+    /// Returns whether transactions built from stale proposals may be stored.
+    pub fn allows_stale_proposals(&self) -> bool {
+        self.allow_stale_proposals
+    }
+
+    /// Returns an error if a transaction constructed for the given target height was built
+    /// against a stale view of the chain, unless stale proposals are allowed.
+    pub(crate) fn check_target_height(&self, target: BlockHeight) -> Result<(), Error> {
+        if self.allow_stale_proposals {
+            return Ok(());
+        }
+        match self.block_fully_scanned()? {
+            Some(fully_scanned) if target <= fully_scanned.block_height() => {
+                Err(Error::StaleProposal {
+                    target,
+                    current: fully_scanned.block_height(),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, num::NonZeroU32};
+
+    use sapling::zip32::ExtendedSpendingKey;
+    use zcash_client_backend::{
+        data_api::{
+            self,
+            testing::{AddressType, TestBuilder},
+            Account as _, WalletRead,
+        },
+        fees::StandardFeeRule,
+        wallet::OvkPolicy,
+    };
+    use zcash_keys::address::Address;
+    use zcash_primitives::{block::BlockHash, transaction::components::amount::NonNegativeAmount};
+    use zcash_protocol::ShieldedProtocol;
+
+    use crate::{
+        error::Error, proto::memwallet as proto, testing::TestMemDbFactory, MemBlockCache,
+    };
+
+    #[test]
+    fn stale_proposals_are_rejected() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let (h, _, _) = st.generate_next_block(
+            &dfvk,
+            AddressType::DefaultExternal,
+            NonNegativeAmount::const_from_u64(50000),
+        );
+        st.scan_cached_blocks(h, 1);
+
+        let account = st.test_account().cloned().unwrap();
+        let not_our_key = ExtendedSpendingKey::master(&[]).to_diversifiable_full_viewing_key();
+        let proposal = st
+            .propose_standard_transfer::<Infallible>(
+                account.id(),
+                StandardFeeRule::Zip317,
+                NonZeroU32::new(1).unwrap(),
+                &Address::Sapling(not_our_key.default_address().1),
+                NonNegativeAmount::const_from_u64(20000),
+                None,
+                None,
+                ShieldedProtocol::Sapling,
+            )
+            .unwrap();
+        assert_eq!(proposal.min_target_height(), h + 1);
+
+        // The wallet scans the block at the proposal's target height before the transaction is
+        // created, so the proposal is stale.
+        st.generate_next_block(
+            &dfvk,
+            AddressType::DefaultExternal,
+            NonNegativeAmount::const_from_u64(10000),
+        );
+        st.scan_cached_blocks(h + 1, 1);
+
+        let before = proto::MemoryWallet::from(st.wallet());
+        let result = st.create_proposed_transactions::<Infallible, _, Infallible>(
+            account.usk(),
+            OvkPolicy::Sender,
+            &proposal,
+        );
+        assert!(matches!(
+            result,
+            Err(data_api::error::Error::DataSource(Error::StaleProposal { target, current }))
+                if target == h + 1 && current == h + 1
+        ));
+        assert_eq!(proto::MemoryWallet::from(st.wallet()), before);
+
+        // The check can be overridden, and the chain tip at which the transaction was stored is
+        // recorded.
+        st.wallet_mut().set_allow_stale_proposals(true);
+        let txid = st
+            .create_proposed_transactions::<Infallible, _, Infallible>(
+                account.usk(),
+                OvkPolicy::Sender,
+                &proposal,
+            )
+            .unwrap()[0];
+        assert_eq!(
+            st.wallet()
+                .tx_table
+                .get(&txid)
+                .unwrap()
+                .stored_at_chain_tip(),
+            st.wallet().chain_height().unwrap()
+        );
+    }
+}
@@ -0,0 +1,101 @@
+use super::*;
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    /// Returns the wallet with strict mode enabled or disabled.
+    ///
+    /// By default, the wallet skips protocol data that it does not support, such as Orchard
+    /// data in a wallet built without the `orchard` feature, or recipients of a kind that the
+    /// enabled features cannot store. In strict mode, each of these cases instead returns
+    /// [`Error::UnsupportedProtocolData`], so that data is not silently dropped while support
+    /// for a new pool is being developed.
+    ///
+    /// Strict mode is a property of the running wallet, and is not serialized.
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Enables or disables strict mode. See [`MemoryWalletDb::with_strict`].
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Returns whether strict mode is enabled.
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    /// Called where the wallet encounters protocol data that it does not support, described
+    /// by `context`. Returns an error in strict mode; otherwise, the data is skipped.
+    pub(crate) fn unsupported_protocol_data(&self, context: &'static str) -> Result<(), Error> {
+        if self.strict {
+            Err(Error::UnsupportedProtocolData { context })
+        } else {
+            tracing::debug!("Skipping unsupported protocol data: {}", context);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, num::NonZeroU32};
+
+    use sapling::zip32::ExtendedSpendingKey;
+    use zcash_client_backend::{
+        data_api::{
+            testing::{AddressType, TestBuilder},
+            Account as _, WalletRead,
+        },
+        fees::StandardFeeRule,
+        wallet::OvkPolicy,
+    };
+    use zcash_keys::address::Address;
+    use zcash_primitives::{block::BlockHash, transaction::components::amount::NonNegativeAmount};
+    use zcash_protocol::ShieldedProtocol;
+
+    use crate::{testing::TestMemDbFactory, MemBlockCache};
+
+    #[test]
+    fn strict_mode_accepts_supported_data() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        assert!(!st.wallet().is_strict());
+        st.wallet_mut().set_strict(true);
+
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let (h, _, _) = st.generate_next_block(
+            &dfvk,
+            AddressType::DefaultExternal,
+            NonNegativeAmount::const_from_u64(50000),
+        );
+        st.scan_cached_blocks(h, 1);
+
+        let account = st.test_account().cloned().unwrap();
+        let not_our_key = ExtendedSpendingKey::master(&[]).to_diversifiable_full_viewing_key();
+        let proposal = st
+            .propose_standard_transfer::<Infallible>(
+                account.id(),
+                StandardFeeRule::Zip317,
+                NonZeroU32::new(1).unwrap(),
+                &Address::Sapling(not_our_key.default_address().1),
+                NonNegativeAmount::const_from_u64(20000),
+                None,
+                None,
+                ShieldedProtocol::Sapling,
+            )
+            .unwrap();
+        st.create_proposed_transactions::<Infallible, _, Infallible>(
+            account.usk(),
+            OvkPolicy::Sender,
+            &proposal,
+        )
+        .unwrap();
+
+        assert!(st.wallet().is_strict());
+        assert!(st.wallet().get_wallet_summary(1).unwrap().is_some());
+    }
+}
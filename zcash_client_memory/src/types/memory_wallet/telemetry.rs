@@ -0,0 +1,244 @@
+use std::time::Duration;
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use std::time::Instant;
+
+use super::*;
+
+/// A phase of [`WalletWrite::put_blocks`] for which [`ScanTelemetry`] records wall time.
+///
+/// [`WalletWrite::put_blocks`]: zcash_client_backend::data_api::WalletWrite::put_blocks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ScanPhase {
+    BlockProcessing,
+    NullifierMap,
+    SaplingSubtrees,
+    OrchardSubtrees,
+    TreeInsertion,
+    ScanComplete,
+}
+
+impl ScanPhase {
+    fn span(self) -> tracing::Span {
+        match self {
+            ScanPhase::BlockProcessing => tracing::debug_span!("block_processing"),
+            ScanPhase::NullifierMap => tracing::debug_span!("nullifier_map"),
+            ScanPhase::SaplingSubtrees => tracing::debug_span!("sapling_subtrees"),
+            ScanPhase::OrchardSubtrees => tracing::debug_span!("orchard_subtrees"),
+            ScanPhase::TreeInsertion => tracing::debug_span!("tree_insertion"),
+            ScanPhase::ScanComplete => tracing::debug_span!("scan_complete"),
+        }
+    }
+}
+
+/// Measures the wall time of a phase of a scan, within a `tracing` span for that phase.
+///
+/// Wall time is not available on `wasm32-unknown-unknown`, where every duration is zero; the
+/// spans are still emitted, so that callers can measure them with their own collectors.
+pub(crate) struct PhaseTimer {
+    phase: Option<ScanPhase>,
+    _span: tracing::span::EnteredSpan,
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    start: Instant,
+}
+
+impl PhaseTimer {
+    /// Starts timing the given phase.
+    pub(crate) fn start(phase: ScanPhase) -> Self {
+        Self::new(Some(phase), phase.span())
+    }
+
+    /// Starts timing a call to `put_blocks` as a whole.
+    pub(crate) fn start_batch() -> Self {
+        Self::new(None, tracing::debug_span!("put_blocks"))
+    }
+
+    fn new(phase: Option<ScanPhase>, span: tracing::Span) -> Self {
+        Self {
+            phase,
+            _span: span.entered(),
+            #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+            start: Instant::now(),
+        }
+    }
+
+    fn elapsed(&self) -> Duration {
+        #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+        {
+            self.start.elapsed()
+        }
+        #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+        {
+            Duration::ZERO
+        }
+    }
+}
+
+/// Timing and note counts accumulated over calls to [`WalletWrite::put_blocks`], for use in
+/// tracking the performance of wallet sync.
+///
+/// Telemetry is a property of the running wallet, and is not serialized.
+///
+/// [`WalletWrite::put_blocks`]: zcash_client_backend::data_api::WalletWrite::put_blocks
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScanTelemetry {
+    batches: u32,
+    blocks: u32,
+    notes: u32,
+    block_processing: Duration,
+    nullifier_map: Duration,
+    sapling_subtrees: Duration,
+    orchard_subtrees: Duration,
+    tree_insertion: Duration,
+    scan_complete: Duration,
+    total: Duration,
+}
+
+impl ScanTelemetry {
+    /// Returns the number of batches of blocks that were successfully inserted.
+    pub fn batches(&self) -> u32 {
+        self.batches
+    }
+
+    /// Returns the number of blocks that were inserted.
+    pub fn blocks(&self) -> u32 {
+        self.blocks
+    }
+
+    /// Returns the number of shielded notes received by the wallet in the inserted blocks.
+    pub fn notes(&self) -> u32 {
+        self.notes
+    }
+
+    /// Returns the time spent processing the transactions of each block, including storing
+    /// received notes and marking spent notes.
+    pub fn block_processing(&self) -> Duration {
+        self.block_processing
+    }
+
+    /// Returns the time spent inserting the nullifiers of each block into the nullifier map.
+    pub fn nullifier_map(&self) -> Duration {
+        self.nullifier_map
+    }
+
+    /// Returns the time spent building subtrees from Sapling note commitments.
+    pub fn sapling_subtrees(&self) -> Duration {
+        self.sapling_subtrees
+    }
+
+    /// Returns the time spent building subtrees from Orchard note commitments.
+    pub fn orchard_subtrees(&self) -> Duration {
+        self.orchard_subtrees
+    }
+
+    /// Returns the time spent inserting subtrees and checkpoints into the note commitment
+    /// trees.
+    pub fn tree_insertion(&self) -> Duration {
+        self.tree_insertion
+    }
+
+    /// Returns the time spent updating the scan queue once each batch was inserted.
+    pub fn scan_complete(&self) -> Duration {
+        self.scan_complete
+    }
+
+    /// Returns the total time spent in `put_blocks`, including work outside the phases
+    /// above such as validating the blocks.
+    pub fn total(&self) -> Duration {
+        self.total
+    }
+
+    /// Returns the sum of the times spent in each phase.
+    pub fn phase_total(&self) -> Duration {
+        self.block_processing
+            + self.nullifier_map
+            + self.sapling_subtrees
+            + self.orchard_subtrees
+            + self.tree_insertion
+            + self.scan_complete
+    }
+
+    /// Records the end of the given phase.
+    pub(crate) fn record(&mut self, timer: PhaseTimer) {
+        let elapsed = timer.elapsed();
+        let phase_time = match timer.phase {
+            Some(ScanPhase::BlockProcessing) => &mut self.block_processing,
+            Some(ScanPhase::NullifierMap) => &mut self.nullifier_map,
+            Some(ScanPhase::SaplingSubtrees) => &mut self.sapling_subtrees,
+            Some(ScanPhase::OrchardSubtrees) => &mut self.orchard_subtrees,
+            Some(ScanPhase::TreeInsertion) => &mut self.tree_insertion,
+            Some(ScanPhase::ScanComplete) => &mut self.scan_complete,
+            None => &mut self.total,
+        };
+        *phase_time += elapsed;
+    }
+
+    /// Records the end of a successful call to `put_blocks` that inserted the given numbers of
+    /// blocks and notes.
+    pub(crate) fn record_batch(&mut self, timer: PhaseTimer, blocks: usize, notes: usize) {
+        self.record(timer);
+        self.batches = self.batches.saturating_add(1);
+        self.blocks = self
+            .blocks
+            .saturating_add(u32::try_from(blocks).unwrap_or(u32::MAX));
+        self.notes = self
+            .notes
+            .saturating_add(u32::try_from(notes).unwrap_or(u32::MAX));
+    }
+}
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    /// Returns the scan telemetry accumulated since the wallet was created or loaded, or since
+    /// the last call to this method, and resets it.
+    pub fn take_scan_telemetry(&mut self) -> ScanTelemetry {
+        std::mem::take(&mut self.scan_telemetry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zcash_client_backend::data_api::testing::{AddressType, TestBuilder};
+    use zcash_primitives::{block::BlockHash, transaction::components::amount::NonNegativeAmount};
+
+    use crate::{testing::TestMemDbFactory, MemBlockCache, ScanTelemetry};
+
+    #[test]
+    fn scan_phases_are_timed() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let (h, _, _) = st.generate_next_block(
+            &dfvk,
+            AddressType::DefaultExternal,
+            NonNegativeAmount::const_from_u64(10000),
+        );
+        for _ in 1..5 {
+            st.generate_next_block(
+                &dfvk,
+                AddressType::DefaultExternal,
+                NonNegativeAmount::const_from_u64(10000),
+            );
+        }
+        st.wallet_mut().take_scan_telemetry();
+
+        st.scan_cached_blocks(h, 3);
+        st.scan_cached_blocks(h + 3, 2);
+        let telemetry = st.wallet_mut().take_scan_telemetry();
+        assert_eq!(telemetry.batches(), 2);
+        assert_eq!(telemetry.blocks(), 5);
+        assert_eq!(telemetry.notes(), 5);
+        assert!(!telemetry.phase_total().is_zero());
+        assert!(telemetry.phase_total() <= telemetry.total());
+        assert!(telemetry.block_processing() <= telemetry.total());
+        assert!(telemetry.scan_complete() <= telemetry.total());
+
+        // Taking the telemetry resets it.
+        assert_eq!(
+            st.wallet_mut().take_scan_telemetry(),
+            ScanTelemetry::default()
+        );
+    }
+}
@@ -0,0 +1,175 @@
+use super::*;
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    /// Records the status of a transaction as reported by the chain, in response to a
+    /// [`TransactionDataRequest::GetStatus`] request.
+    ///
+    /// A [`TransactionStatus::Mined`] status is recorded as is. Otherwise, the transaction's
+    /// location in the chain is cleared, and:
+    /// - if the transaction may still be mined, a status request for it remains queued, so
+    ///   that the notes that it spends remain locked until it has been mined or has expired;
+    /// - if it has expired, its requests are dropped. The notes that it spends are then
+    ///   spendable again, and a transaction created by this wallet will be removed by
+    ///   [`Self::purge_invalidated_transactions`].
+    ///
+    /// A transaction created by this wallet that is reported as
+    /// [`TransactionStatus::TxidNotRecognized`] may not yet have reached the node that was
+    /// queried, so it is recorded as [`TransactionStatus::NotInMainChain`] until it expires.
+    ///
+    /// [`TransactionDataRequest::GetStatus`]: zcash_client_backend::data_api::TransactionDataRequest::GetStatus
+    pub(crate) fn update_transaction_status(
+        &mut self,
+        txid: TxId,
+        status: TransactionStatus,
+    ) -> Result<(), Error> {
+        self.tx_table.set_transaction_status(&txid, status)?;
+        if let TransactionStatus::Mined(_) = status {
+            return Ok(());
+        }
+
+        let chain_tip = self.chain_height()?;
+        let tx = self
+            .tx_table
+            .get(&txid)
+            .ok_or(Error::TransactionNotFound(txid))?;
+        let expired = chain_tip.map_or(false, |tip| tx.is_expired_unmined(tip));
+        let wallet_created = tx.is_wallet_created();
+
+        if expired {
+            self.transaction_data_request_queue
+                .remove_transaction_requests(&txid);
+        } else {
+            if matches!(status, TransactionStatus::TxidNotRecognized) && wallet_created {
+                self.tx_table
+                    .set_transaction_status(&txid, TransactionStatus::NotInMainChain)?;
+            }
+            self.transaction_data_request_queue
+                .queue_status_retrieval(&txid, chain_tip);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, num::NonZeroU32};
+
+    use sapling::zip32::ExtendedSpendingKey;
+    use zcash_client_backend::{
+        data_api::{
+            testing::{AddressType, TestBuilder},
+            Account as _, TransactionDataRequest, TransactionStatus, WalletRead, WalletWrite,
+        },
+        fees::StandardFeeRule,
+        wallet::OvkPolicy,
+    };
+    use zcash_keys::address::Address;
+    use zcash_primitives::{block::BlockHash, transaction::components::amount::NonNegativeAmount};
+    use zcash_protocol::ShieldedProtocol;
+
+    use crate::{testing::TestMemDbFactory, MemBlockCache, MemoryWalletDb};
+
+    #[test]
+    fn unmined_wallet_transaction_is_pending_until_expiry() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let account = st.test_account().cloned().unwrap();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let not_our_key = ExtendedSpendingKey::master(&[]).to_diversifiable_full_viewing_key();
+
+        let value = NonNegativeAmount::const_from_u64(60000);
+        let (h1, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        st.scan_cached_blocks(h1, 1);
+
+        let proposal = st
+            .propose_standard_transfer::<Infallible>(
+                account.id(),
+                StandardFeeRule::Zip317,
+                NonZeroU32::new(1).unwrap(),
+                &Address::Sapling(not_our_key.default_address().1),
+                NonNegativeAmount::const_from_u64(10000),
+                None,
+                None,
+                ShieldedProtocol::Sapling,
+            )
+            .unwrap();
+        let txid = st
+            .create_proposed_transactions::<Infallible, _, Infallible>(
+                account.usk(),
+                OvkPolicy::Sender,
+                &proposal,
+            )
+            .unwrap()[0];
+        let status_requests = |wallet: &MemoryWalletDb<_>| {
+            wallet
+                .transaction_data_requests()
+                .unwrap()
+                .into_iter()
+                .filter(|request| *request == TransactionDataRequest::GetStatus(txid))
+                .count()
+        };
+
+        // Created: the received note is locked by the pending spend.
+        assert_eq!(
+            st.get_spendable_balance(account.id(), 1),
+            NonNegativeAmount::ZERO
+        );
+
+        // Reported as not in the main chain: the transaction may still be mined, so the note
+        // remains locked and the status of the transaction is requested again.
+        for _ in 0..2 {
+            st.wallet_mut()
+                .set_transaction_status(txid, TransactionStatus::NotInMainChain)
+                .unwrap();
+            assert_eq!(status_requests(st.wallet()), 1);
+        }
+        assert_eq!(
+            st.wallet().tx_table.tx_status(&txid),
+            Some(TransactionStatus::NotInMainChain)
+        );
+        assert_eq!(
+            st.get_spendable_balance(account.id(), 1),
+            NonNegativeAmount::ZERO
+        );
+
+        // A transaction that the wallet created may not yet have reached the node that did not
+        // recognize it, so it remains pending.
+        st.wallet_mut()
+            .set_transaction_status(txid, TransactionStatus::TxidNotRecognized)
+            .unwrap();
+        assert_eq!(
+            st.wallet().tx_table.tx_status(&txid),
+            Some(TransactionStatus::NotInMainChain)
+        );
+        assert_eq!(status_requests(st.wallet()), 1);
+        assert_eq!(
+            st.get_spendable_balance(account.id(), 1),
+            NonNegativeAmount::ZERO
+        );
+
+        // Expired: once the chain has passed the transaction's expiry height, the note that it
+        // spent is spendable again, and the transaction is no longer requested.
+        for _ in 0..45 {
+            st.generate_next_block(&not_our_key, AddressType::DefaultExternal, value);
+        }
+        st.scan_cached_blocks(h1 + 1, 45);
+        assert_eq!(st.get_spendable_balance(account.id(), 1), value);
+        st.wallet_mut()
+            .set_transaction_status(txid, TransactionStatus::TxidNotRecognized)
+            .unwrap();
+        assert_eq!(
+            st.wallet().tx_table.tx_status(&txid),
+            Some(TransactionStatus::TxidNotRecognized)
+        );
+        assert_eq!(status_requests(st.wallet()), 0);
+        assert_eq!(st.get_spendable_balance(account.id(), 1), value);
+        assert_eq!(
+            st.wallet_mut().purge_invalidated_transactions().unwrap(),
+            vec![txid]
+        );
+        assert_eq!(st.get_spendable_balance(account.id(), 1), value);
+    }
+}
@@ -0,0 +1,179 @@
+use zcash_client_backend::data_api::wallet::decrypt_and_store_transaction;
+
+use super::*;
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    /// Stores a transaction that was retrieved in response to a
+    /// [`TransactionDataRequest::SpendsFromAddress`] request, such as one returned by
+    /// `lightwalletd`'s `GetTaddressTxids` method.
+    ///
+    /// `mined_height` is the height of the block that mined the transaction, or `None` if it
+    /// was found in the mempool. The transaction need not have any shielded components: its
+    /// outputs to the wallet's transparent receivers are added to the received outputs, and
+    /// its inputs that spend the wallet's outputs are recorded as spends of those outputs.
+    /// Any shielded outputs are trial-decrypted with the wallet's viewing keys and stored
+    /// as for [`WalletWrite::store_decrypted_tx`].
+    ///
+    /// Storing the same transaction more than once has no further effect.
+    ///
+    /// [`TransactionDataRequest::SpendsFromAddress`]: zcash_client_backend::data_api::TransactionDataRequest::SpendsFromAddress
+    /// [`WalletWrite::store_decrypted_tx`]: zcash_client_backend::data_api::WalletWrite::store_decrypted_tx
+    pub fn store_address_history_tx(
+        &mut self,
+        tx: &Transaction,
+        mined_height: Option<BlockHeight>,
+    ) -> Result<(), Error> {
+        let params = self.params.clone();
+        decrypt_and_store_transaction(&params, self, tx, mined_height)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use zcash_client_backend::data_api::{Account as _, WalletRead};
+    use zcash_keys::address::Address;
+    use zcash_primitives::{
+        legacy::Script,
+        transaction::components::{amount::NonNegativeAmount, OutPoint, TxIn, TxOut},
+    };
+    use zcash_protocol::memo::{Memo, MemoBytes};
+
+    use crate::testing::{external_tx, test_state, transparent_tx};
+
+    #[test]
+    fn address_history_is_stored_idempotently() {
+        let mut st = test_state();
+        let account_id = st.test_account().unwrap().id();
+        let taddr = *st
+            .wallet()
+            .get_current_address(account_id)
+            .unwrap()
+            .unwrap()
+            .transparent()
+            .unwrap();
+        let h = st.sapling_activation_height() + 10;
+
+        // A purely transparent transaction paying the wallet's address.
+        let receive_tx = transparent_tx(
+            vec![],
+            vec![TxOut {
+                value: NonNegativeAmount::const_from_u64(100000),
+                script_pubkey: taddr.script(),
+            }],
+        );
+        let outpoint = OutPoint::new(receive_tx.txid().into(), 0);
+        for _ in 0..2 {
+            st.wallet_mut()
+                .store_address_history_tx(&receive_tx, Some(h))
+                .unwrap();
+        }
+        let wallet = st.wallet();
+        assert_eq!(wallet.transparent_received_outputs.len(), 1);
+        assert!(wallet.transparent_received_outputs.get(&outpoint).is_some());
+        assert_eq!(wallet.get_tx_height(receive_tx.txid()).unwrap(), Some(h));
+        assert_eq!(
+            wallet
+                .transaction_data_request_queue
+                .spend_detections()
+                .collect::<Vec<_>>(),
+            vec![&outpoint]
+        );
+
+        // A later transaction spending that output to an external address.
+        let spend_tx = transparent_tx(
+            vec![TxIn {
+                prevout: outpoint.clone(),
+                script_sig: Script(vec![]),
+                sequence: 0,
+            }],
+            vec![],
+        );
+        for _ in 0..2 {
+            st.wallet_mut()
+                .store_address_history_tx(&spend_tx, Some(h + 1))
+                .unwrap();
+        }
+        // Resubmitting the receiving transaction does not resume the search for its spend.
+        st.wallet_mut()
+            .store_address_history_tx(&receive_tx, Some(h))
+            .unwrap();
+
+        let wallet = st.wallet();
+        assert_eq!(wallet.transparent_received_outputs.len(), 1);
+        assert_eq!(
+            wallet.transparent_received_output_spends.get(&outpoint),
+            Some(&spend_tx.txid())
+        );
+        assert_eq!(wallet.get_tx_height(spend_tx.txid()).unwrap(), Some(h + 1));
+        assert_eq!(
+            wallet
+                .transaction_data_request_queue
+                .spend_detections()
+                .count(),
+            0
+        );
+    }
+    #[test]
+    fn shielded_outputs_of_address_history_are_stored() {
+        let mut st = test_state();
+        let account_id = st.test_account().unwrap().id();
+        let taddr = *st
+            .wallet()
+            .get_current_address(account_id)
+            .unwrap()
+            .unwrap()
+            .transparent()
+            .unwrap();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let h = st.sapling_activation_height() + 10;
+
+        // A transaction paying both the wallet's transparent address and one of its Sapling
+        // addresses, found only through the history of the transparent address.
+        let memo = Memo::from_str("shielded half").unwrap();
+        let tx = external_tx(vec![
+            (
+                Address::Transparent(taddr),
+                NonNegativeAmount::const_from_u64(30000),
+                None,
+            ),
+            (
+                Address::Sapling(dfvk.default_address().1),
+                NonNegativeAmount::const_from_u64(20000),
+                Some(MemoBytes::from(&memo)),
+            ),
+        ]);
+        for _ in 0..2 {
+            st.wallet_mut()
+                .store_address_history_tx(&tx, Some(h))
+                .unwrap();
+        }
+
+        let wallet = st.wallet();
+        let vout = tx
+            .transparent_bundle()
+            .unwrap()
+            .vout
+            .iter()
+            .position(|out| out.script_pubkey == taddr.script())
+            .unwrap();
+        let outpoint = OutPoint::new(tx.txid().into(), vout.try_into().unwrap());
+        assert_eq!(wallet.transparent_received_outputs.len(), 1);
+        assert!(wallet.transparent_received_outputs.get(&outpoint).is_some());
+
+        let notes = wallet
+            .received_notes
+            .iter()
+            .filter(|note| note.txid == tx.txid())
+            .collect::<Vec<_>>();
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes[0].account_id, account_id);
+        assert_eq!(
+            notes[0].note.value(),
+            NonNegativeAmount::const_from_u64(20000)
+        );
+        assert_eq!(wallet.get_memo(notes[0].note_id).unwrap(), Some(memo));
+        assert_eq!(wallet.get_tx_height(tx.txid()).unwrap(), Some(h));
+    }
+}
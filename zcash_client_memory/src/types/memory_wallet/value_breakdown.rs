@@ -0,0 +1,512 @@
+use zcash_protocol::value::{BalanceError, ZatBalance, Zatoshis};
+
+use super::*;
+
+/// The value components of a transaction from which its fee is derived.
+///
+/// Transparent output values and shielded value balances are read directly from the
+/// transaction and are always exact. The transparent input sum depends on the values of the
+/// outputs being spent, which are not encoded in the transaction itself; it is only known if
+/// every spent output is known to the wallet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TxValueBreakdown {
+    transparent_inputs: Option<Zatoshis>,
+    transparent_outputs: Zatoshis,
+    sapling_value_balance: ZatBalance,
+    orchard_value_balance: ZatBalance,
+}
+
+impl TxValueBreakdown {
+    /// Returns the total value of the transparent outputs spent by the transaction, or `None`
+    /// if the value of any of them is not known to the wallet.
+    pub fn transparent_inputs(&self) -> Option<Zatoshis> {
+        self.transparent_inputs
+    }
+
+    /// Returns the total value of the transparent outputs created by the transaction.
+    pub fn transparent_outputs(&self) -> Zatoshis {
+        self.transparent_outputs
+    }
+
+    /// Returns the net value leaving the Sapling pool. This is negative when the transaction
+    /// moves value into the Sapling pool.
+    pub fn sapling_value_balance(&self) -> ZatBalance {
+        self.sapling_value_balance
+    }
+
+    /// Returns the net value leaving the Orchard pool. This is negative when the transaction
+    /// moves value into the Orchard pool.
+    pub fn orchard_value_balance(&self) -> ZatBalance {
+        self.orchard_value_balance
+    }
+
+    /// Returns `true` if every component of the breakdown is known.
+    pub fn is_exact(&self) -> bool {
+        self.transparent_inputs.is_some()
+    }
+
+    /// Returns the fee paid by the transaction, or `None` if the transparent input sum is not
+    /// known.
+    pub fn fee(&self) -> Result<Option<Zatoshis>, BalanceError> {
+        self.transparent_inputs
+            .map(|transparent_inputs| {
+                let fee = (ZatBalance::from(transparent_inputs)
+                    - ZatBalance::from(self.transparent_outputs)
+                    + self.sapling_value_balance
+                    + self.orchard_value_balance)
+                    .ok_or(BalanceError::Overflow)?;
+                Zatoshis::try_from(fee)
+            })
+            .transpose()
+    }
+}
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    /// Returns the value components of the given transaction from which its fee is derived.
+    ///
+    /// Returns `None` if the transaction is not known to the wallet, or if the wallet has not
+    /// stored its raw data. Transparent inputs are resolved against the outputs received by the
+    /// wallet and the outputs of other transactions for which the wallet has raw data.
+    pub fn tx_value_breakdown(&self, txid: TxId) -> Result<Option<TxValueBreakdown>, Error> {
+        self.stored_transaction(txid)?
+            .map(|tx| self.value_breakdown_of(&tx))
+            .transpose()
+    }
+
+    /// Returns the value components of the given transaction.
+    fn value_breakdown_of(&self, tx: &Transaction) -> Result<TxValueBreakdown, Error> {
+        let (transparent_inputs, transparent_outputs) = match tx.transparent_bundle() {
+            Some(bundle) => {
+                let transparent_inputs = bundle
+                    .vin
+                    .iter()
+                    .map(|txin| self.transparent_prevout_value(&txin.prevout))
+                    .collect::<Result<Option<Vec<_>>, _>>()?
+                    .map(|values| {
+                        values
+                            .into_iter()
+                            .sum::<Option<Zatoshis>>()
+                            .ok_or(BalanceError::Overflow)
+                    })
+                    .transpose()?;
+                let transparent_outputs = bundle
+                    .vout
+                    .iter()
+                    .map(|txout| txout.value)
+                    .sum::<Option<Zatoshis>>()
+                    .ok_or(BalanceError::Overflow)?;
+                (transparent_inputs, transparent_outputs)
+            }
+            None => (Some(Zatoshis::ZERO), Zatoshis::ZERO),
+        };
+
+        Ok(TxValueBreakdown {
+            transparent_inputs,
+            transparent_outputs,
+            sapling_value_balance: tx
+                .sapling_bundle()
+                .map_or(ZatBalance::zero(), |bundle| *bundle.value_balance()),
+            orchard_value_balance: tx
+                .orchard_bundle()
+                .map_or(ZatBalance::zero(), |bundle| *bundle.value_balance()),
+        })
+    }
+
+    /// Records the fee paid by the given stored transaction if it is not already known and
+    /// can be derived from the transaction's [`TxValueBreakdown`], i.e. if the values of all
+    /// of its transparent inputs are known to the wallet. Otherwise, the fee remains unknown.
+    pub(crate) fn record_derived_fee(&mut self, tx: &Transaction) -> Result<(), Error> {
+        let txid = tx.txid();
+        if self
+            .tx_table
+            .get(&txid)
+            .map_or(true, |tx| tx.fee().is_some())
+        {
+            return Ok(());
+        }
+        match self.value_breakdown_of(tx)?.fee() {
+            Ok(Some(fee)) => self.tx_table.set_fee(&txid, fee),
+            Ok(None) => {}
+            Err(e) => {
+                tracing::warn!("Unable to derive the fee of transaction {}: {}", txid, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Parses the stored raw data of the given transaction, if any.
+    fn stored_transaction(&self, txid: TxId) -> Result<Option<Transaction>, Error> {
+        if self.tx_table.get(&txid).and_then(|tx| tx.raw()).is_none() {
+            return Ok(None);
+        }
+        self.get_transaction(txid)
+    }
+
+    /// Returns the value of the given transparent output, if it is known to the wallet.
+    fn transparent_prevout_value(&self, outpoint: &OutPoint) -> Result<Option<Zatoshis>, Error> {
+        if let Some(output) = self.transparent_received_outputs.get(outpoint) {
+            return Ok(Some(output.txout.value));
+        }
+        Ok(self.stored_transaction(*outpoint.txid())?.and_then(|tx| {
+            tx.transparent_bundle()
+                .and_then(|bundle| bundle.vout.get(usize::try_from(outpoint.n()).ok()?))
+                .map(|txout| txout.value)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, num::NonZeroU32};
+
+    use sapling::zip32::ExtendedSpendingKey;
+    use zcash_client_backend::{
+        data_api::{
+            testing::{AddressType, TestBuilder},
+            Account as _,
+        },
+        fees::StandardFeeRule,
+        wallet::OvkPolicy,
+    };
+    use zcash_keys::address::Address;
+    use zcash_primitives::{
+        block::BlockHash,
+        transaction::{components::amount::NonNegativeAmount, TxId},
+    };
+    use zcash_protocol::{
+        value::{ZatBalance, Zatoshis},
+        ShieldedProtocol,
+    };
+
+    use crate::{testing::TestMemDbFactory, MemBlockCache};
+
+    #[test]
+    fn fully_shielded_send() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let account = st.test_account().cloned().unwrap();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+
+        let value = NonNegativeAmount::const_from_u64(60000);
+        let (h, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        st.scan_cached_blocks(h, 1);
+
+        let to = Address::Sapling(ExtendedSpendingKey::master(&[]).default_address().1);
+        let proposal = st
+            .propose_standard_transfer::<Infallible>(
+                account.id(),
+                StandardFeeRule::Zip317,
+                NonZeroU32::new(1).unwrap(),
+                &to,
+                NonNegativeAmount::const_from_u64(10000),
+                None,
+                None,
+                ShieldedProtocol::Sapling,
+            )
+            .unwrap();
+        let txid = st
+            .create_proposed_transactions::<Infallible, _, Infallible>(
+                account.usk(),
+                OvkPolicy::Sender,
+                &proposal,
+            )
+            .unwrap()[0];
+
+        let breakdown = st.wallet().tx_value_breakdown(txid).unwrap().unwrap();
+        assert!(breakdown.is_exact());
+        assert_eq!(breakdown.transparent_inputs(), Some(Zatoshis::ZERO));
+        assert_eq!(breakdown.transparent_outputs(), Zatoshis::ZERO);
+        assert_eq!(breakdown.orchard_value_balance(), ZatBalance::zero());
+        // Only the fee leaves the Sapling pool.
+        assert!(breakdown.sapling_value_balance().is_positive());
+        let fee = breakdown.fee().unwrap().unwrap();
+        assert_eq!(breakdown.sapling_value_balance(), fee.into());
+        assert_eq!(Some(fee), st.wallet().tx_table.get(&txid).unwrap().fee());
+    }
+
+    #[test]
+    fn unknown_transaction() {
+        let st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+
+        assert_eq!(
+            st.wallet()
+                .tx_value_breakdown(TxId::from_bytes([7; 32]))
+                .unwrap(),
+            None
+        );
+    }
+
+    #[cfg(feature = "transparent-inputs")]
+    mod transparent {
+        use zcash_client_backend::{
+            data_api::{
+                testing::{AddressType, TestBuilder, TestState},
+                wallet::input_selection::GreedyInputSelector,
+                Account as _, DecryptedTransaction, WalletRead, WalletTest, WalletWrite,
+            },
+            fees::{standard, DustOutputPolicy, StandardFeeRule},
+            wallet::WalletTransparentOutput,
+        };
+        use zcash_primitives::{
+            block::BlockHash,
+            consensus::BranchId,
+            legacy::{Script, TransparentAddress},
+            transaction::{
+                components::{amount::NonNegativeAmount, transparent, OutPoint, TxIn, TxOut},
+                Authorized, Transaction, TransactionData, TxId, TxVersion,
+            },
+        };
+        use zcash_protocol::{
+            consensus::BlockHeight,
+            local_consensus::LocalNetwork,
+            value::{ZatBalance, Zatoshis},
+            ShieldedProtocol,
+        };
+
+        use crate::{testing::TestMemDbFactory, MemBlockCache, MemoryWalletDb};
+
+        /// Receives a transparent output of the given value and shields it, returning the
+        /// outpoint that was spent and the ID of the shielding transaction.
+        fn shield(
+            st: &mut TestState<MemBlockCache, MemoryWalletDb<LocalNetwork>, LocalNetwork>,
+            value: NonNegativeAmount,
+        ) -> (OutPoint, TxId) {
+            let account = st.test_account().cloned().unwrap();
+            let dfvk = st.test_account_sapling().unwrap().clone();
+            let taddr = *st
+                .wallet()
+                .get_current_address(account.id())
+                .unwrap()
+                .unwrap()
+                .transparent()
+                .unwrap();
+
+            let (h, _, _) = st.generate_next_block(
+                &dfvk,
+                AddressType::DefaultExternal,
+                NonNegativeAmount::const_from_u64(10000),
+            );
+            st.scan_cached_blocks(h, 1);
+
+            let outpoint = OutPoint::fake();
+            let utxo = WalletTransparentOutput::from_parts(
+                outpoint.clone(),
+                TxOut {
+                    value,
+                    script_pubkey: taddr.script(),
+                },
+                Some(h),
+            )
+            .unwrap();
+            st.wallet_mut()
+                .put_received_transparent_utxo(&utxo)
+                .unwrap();
+
+            let change_strategy = standard::SingleOutputChangeStrategy::new(
+                StandardFeeRule::Zip317,
+                None,
+                ShieldedProtocol::Sapling,
+                DustOutputPolicy::default(),
+            );
+            let txid = st
+                .shield_transparent_funds(
+                    &GreedyInputSelector::new(),
+                    &change_strategy,
+                    value,
+                    account.usk(),
+                    &[taddr],
+                    account.id(),
+                    1,
+                )
+                .unwrap()[0];
+
+            (outpoint, txid)
+        }
+
+        #[test]
+        fn shielding_transaction() {
+            let mut st = TestBuilder::new()
+                .with_data_store_factory(TestMemDbFactory::new())
+                .with_block_cache(MemBlockCache::new())
+                .with_account_from_sapling_activation(BlockHash([0; 32]))
+                .build();
+            let value = NonNegativeAmount::const_from_u64(100000);
+            let (_, txid) = shield(&mut st, value);
+
+            let breakdown = st.wallet().tx_value_breakdown(txid).unwrap().unwrap();
+            assert!(breakdown.is_exact());
+            assert_eq!(breakdown.transparent_inputs(), Some(value));
+            assert_eq!(breakdown.transparent_outputs(), Zatoshis::ZERO);
+            assert_eq!(breakdown.orchard_value_balance(), ZatBalance::zero());
+            // Value enters the Sapling pool, so the Sapling value balance is negative.
+            assert!(breakdown.sapling_value_balance().is_negative());
+
+            let fee = breakdown.fee().unwrap().unwrap();
+            assert_eq!(Some(fee), st.wallet().tx_table.get(&txid).unwrap().fee());
+            assert_eq!(
+                -breakdown.sapling_value_balance(),
+                (ZatBalance::from(value) - ZatBalance::from(fee)).unwrap()
+            );
+        }
+
+        #[test]
+        fn unknown_transparent_inputs() {
+            let mut st = TestBuilder::new()
+                .with_data_store_factory(TestMemDbFactory::new())
+                .with_block_cache(MemBlockCache::new())
+                .with_account_from_sapling_activation(BlockHash([0; 32]))
+                .build();
+            let value = NonNegativeAmount::const_from_u64(100000);
+            let (outpoint, txid) = shield(&mut st, value);
+
+            // Forget the output being spent, as if the shielding transaction had been
+            // restored from the chain by a wallet that never observed it.
+            st.wallet_mut()
+                .transparent_received_outputs
+                .0
+                .remove(&outpoint);
+
+            let breakdown = st.wallet().tx_value_breakdown(txid).unwrap().unwrap();
+            assert!(!breakdown.is_exact());
+            assert_eq!(breakdown.transparent_inputs(), None);
+            assert_eq!(breakdown.fee(), Ok(None));
+            // The components that are read from the transaction itself are unaffected.
+            assert_eq!(breakdown.transparent_outputs(), Zatoshis::ZERO);
+            assert!(breakdown.sapling_value_balance().is_negative());
+            assert_eq!(breakdown.orchard_value_balance(), ZatBalance::zero());
+        }
+
+        fn transparent_tx(
+            vin: Vec<TxIn<transparent::Authorized>>,
+            vout: Vec<TxOut>,
+        ) -> Transaction {
+            TransactionData::<Authorized>::from_parts(
+                TxVersion::Zip225,
+                BranchId::Nu5,
+                0,
+                BlockHeight::from_u32(0),
+                Some(transparent::Bundle {
+                    vin,
+                    vout,
+                    authorization: transparent::Authorized,
+                }),
+                None,
+                None,
+                None,
+            )
+            .freeze()
+            .unwrap()
+        }
+
+        fn store(
+            st: &mut TestState<MemBlockCache, MemoryWalletDb<LocalNetwork>, LocalNetwork>,
+            tx: &Transaction,
+            mined_height: BlockHeight,
+        ) {
+            st.wallet_mut()
+                .store_decrypted_tx(DecryptedTransaction::new(
+                    Some(mined_height),
+                    tx,
+                    vec![],
+                    #[cfg(feature = "orchard")]
+                    vec![],
+                ))
+                .unwrap();
+        }
+
+        #[test]
+        fn fee_of_received_fully_transparent_transaction() {
+            let mut st = TestBuilder::new()
+                .with_data_store_factory(TestMemDbFactory::new())
+                .with_block_cache(MemBlockCache::new())
+                .with_account_from_sapling_activation(BlockHash([0; 32]))
+                .build();
+            let account_id = st.test_account().unwrap().id();
+            let taddr = *st
+                .wallet()
+                .get_current_address(account_id)
+                .unwrap()
+                .unwrap()
+                .transparent()
+                .unwrap();
+            let h = st.sapling_activation_height() + 10;
+
+            // The wallet does not know the value of the output spent by the funding
+            // transaction, so its fee remains unknown.
+            let funding_tx = transparent_tx(
+                vec![TxIn {
+                    prevout: OutPoint::fake(),
+                    script_sig: Script(vec![]),
+                    sequence: 0,
+                }],
+                vec![TxOut {
+                    value: NonNegativeAmount::const_from_u64(100000),
+                    script_pubkey: taddr.script(),
+                }],
+            );
+            store(&mut st, &funding_tx, h);
+            assert_eq!(
+                st.wallet().tx_table.get(&funding_tx.txid()).unwrap().fee(),
+                None
+            );
+
+            // A transaction spending the received output to an external address.
+            let external = TransparentAddress::PublicKeyHash([7; 20]);
+            let spend_tx = transparent_tx(
+                vec![TxIn {
+                    prevout: OutPoint::new(funding_tx.txid().into(), 0),
+                    script_sig: Script(vec![]),
+                    sequence: 0,
+                }],
+                vec![TxOut {
+                    value: NonNegativeAmount::const_from_u64(85000),
+                    script_pubkey: external.script(),
+                }],
+            );
+            store(&mut st, &spend_tx, h + 1);
+            let fee = Zatoshis::const_from_u64(15000);
+            assert_eq!(
+                st.wallet().tx_table.get(&spend_tx.txid()).unwrap().fee(),
+                Some(fee)
+            );
+            let summary = st
+                .wallet()
+                .get_tx_history()
+                .unwrap()
+                .into_iter()
+                .find(|summary| summary.txid() == spend_tx.txid())
+                .unwrap();
+            assert_eq!(summary.fee_paid(), Some(fee));
+        }
+
+        #[test]
+        fn fee_of_received_mixed_transaction() {
+            let mut st = TestBuilder::new()
+                .with_data_store_factory(TestMemDbFactory::new())
+                .with_block_cache(MemBlockCache::new())
+                .with_account_from_sapling_activation(BlockHash([0; 32]))
+                .build();
+            let value = NonNegativeAmount::const_from_u64(100000);
+            let (_, txid) = shield(&mut st, value);
+            let fee = st.wallet().tx_table.get(&txid).unwrap().fee();
+            assert!(fee.is_some());
+
+            // Forget the shielding transaction, as if it had been created by another wallet
+            // with the same seed, and then observe it on chain.
+            let tx = st.wallet().get_transaction(txid).unwrap().unwrap();
+            st.wallet_mut().tx_table.0.remove(&txid);
+            let h = st.latest_cached_block().unwrap().height() + 1;
+            store(&mut st, &tx, h);
+
+            // The fee is derived from the value of the spent output and the Sapling value
+            // balance of the transaction.
+            assert_eq!(st.wallet().tx_table.get(&txid).unwrap().fee(), fee);
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use std::io::{Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use sha2::{Digest, Sha256};
+
+use super::*;
+use crate::error::Result;
+
+/// The magic bytes with which every wallet file begins.
+const WALLET_FILE_MAGIC: [u8; 4] = *b"ZMWF";
+
+/// The version of the wallet file container format.
+const WALLET_FILE_FORMAT_VERSION: u8 = 1;
+
+/// The length of the SHA-256 checksum that follows the encoded wallet.
+const CHECKSUM_LEN: usize = 32;
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    /// Writes the wallet to the given writer in a form suitable for persisting it to a file.
+    ///
+    /// The wallet file consists of a magic header and format version, the length of the
+    /// protobuf encoding of the wallet produced by [`MemoryWalletDb::encode`], the encoding
+    /// itself, and a SHA-256 checksum of the encoding. Use [`MemoryWalletDb::read_from`] to
+    /// restore the wallet.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
+        let mut encoded = vec![];
+        self.encode(&mut encoded)?;
+
+        writer.write_all(&WALLET_FILE_MAGIC)?;
+        writer.write_u8(WALLET_FILE_FORMAT_VERSION)?;
+        writer.write_u64::<LittleEndian>(u64::try_from(encoded.len())?)?;
+        writer.write_all(&encoded)?;
+        writer.write_all(&Sha256::digest(&encoded))?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Restores a wallet written by [`MemoryWalletDb::write_to`].
+    ///
+    /// Returns [`Error::MagicMismatch`] if the data is not a wallet file,
+    /// [`Error::CorruptedData`] if it is truncated, and [`Error::ChecksumMismatch`] if the
+    /// encoded wallet does not match its checksum. In none of these cases is the encoded
+    /// wallet decoded.
+    pub fn read_from<R: Read>(params: P, max_checkpoints: usize, mut reader: R) -> Result<Self> {
+        let mut magic = [0u8; 4];
+        reader
+            .read_exact(&mut magic)
+            .map_err(|_| truncated("header"))?;
+        if magic != WALLET_FILE_MAGIC {
+            return Err(Error::MagicMismatch {
+                expected: WALLET_FILE_MAGIC,
+                found: magic,
+            });
+        }
+        let version = reader.read_u8().map_err(|_| truncated("header"))?;
+        if version != WALLET_FILE_FORMAT_VERSION {
+            return Err(Error::UnsupportedProtoVersion(
+                WALLET_FILE_FORMAT_VERSION.into(),
+                version.into(),
+            ));
+        }
+        let len = reader
+            .read_u64::<LittleEndian>()
+            .map_err(|_| truncated("header"))?;
+
+        // The length is not trusted until the checksum has been verified, so the buffer is
+        // grown as the encoding is read rather than allocated up front.
+        let mut encoded = vec![];
+        reader.by_ref().take(len).read_to_end(&mut encoded)?;
+        if encoded.len() as u64 != len {
+            return Err(truncated("wallet encoding"));
+        }
+        let mut checksum = [0u8; CHECKSUM_LEN];
+        reader
+            .read_exact(&mut checksum)
+            .map_err(|_| truncated("checksum"))?;
+        if Sha256::digest(&encoded)[..] != checksum[..] {
+            return Err(Error::ChecksumMismatch);
+        }
+
+        Self::decode_new(&encoded[..], params, max_checkpoints)
+    }
+}
+
+fn truncated(part: &str) -> Error {
+    Error::CorruptedData(format!("Invalid wallet file: truncated {}", part))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Seek, SeekFrom, Write};
+
+    use zcash_client_backend::data_api::testing::{AddressType, TestBuilder};
+    use zcash_primitives::{block::BlockHash, transaction::components::amount::NonNegativeAmount};
+
+    use crate::{error::Error, proto::memwallet as proto, testing::TestMemDbFactory};
+    use crate::{MemBlockCache, MemoryWalletDb};
+
+    #[test]
+    fn wallet_file_roundtrip_and_corruption() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let (h, _, _) = st.generate_next_block(
+            &dfvk,
+            AddressType::DefaultExternal,
+            NonNegativeAmount::const_from_u64(50000),
+        );
+        st.scan_cached_blocks(h, 1);
+        let wallet = st.wallet();
+
+        let mut file = tempfile::tempfile().unwrap();
+        wallet.write_to(&mut file).unwrap();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        let mut contents = vec![];
+        file.read_to_end(&mut contents).unwrap();
+
+        file.seek(SeekFrom::Start(0)).unwrap();
+        let restored = MemoryWalletDb::read_from(wallet.params.clone(), 100, &mut file).unwrap();
+        let mut state = proto::MemoryWallet::from(&restored);
+        let mut expected = proto::MemoryWallet::from(wallet);
+        // Restoring a wallet updates its provenance.
+        state.provenance = None;
+        expected.provenance = None;
+        assert_eq!(state, expected);
+
+        let restore = |contents: &[u8]| {
+            let mut file = tempfile::tempfile().unwrap();
+            file.write_all(contents).unwrap();
+            file.seek(SeekFrom::Start(0)).unwrap();
+            MemoryWalletDb::read_from(wallet.params.clone(), 100, file)
+        };
+
+        // A flipped byte in the encoded wallet is reported rather than decoded.
+        let mut damaged = contents.clone();
+        damaged[contents.len() / 2] ^= 0x01;
+        assert!(matches!(restore(&damaged), Err(Error::ChecksumMismatch)));
+
+        // So is a truncated file.
+        assert!(matches!(
+            restore(&contents[..contents.len() - 1]),
+            Err(Error::CorruptedData(_))
+        ));
+
+        // And a file that is not a wallet file.
+        let mut damaged = contents.clone();
+        damaged[0] = b'X';
+        assert!(matches!(
+            restore(&damaged),
+            Err(Error::MagicMismatch { found, .. }) if &found == b"XMWF"
+        ));
+    }
+}
@@ -0,0 +1,130 @@
+use super::*;
+
+/// The names of the metrics emitted through the [`metrics`] facade when the `metrics` feature
+/// is enabled.
+///
+/// Metrics are only recorded if the embedding application has installed a recorder, such as a
+/// Prometheus exporter. Counters are cumulative over the lifetime of the process, and are not
+/// serialized with the wallet.
+///
+/// [`metrics`]: https://docs.rs/metrics
+pub mod metric_names {
+    /// Counter of the blocks inserted by `put_blocks`.
+    pub const BLOCKS_SCANNED: &str = "zcash_wallet_blocks_scanned_total";
+    /// Counter of the shielded notes received by the wallet in the blocks inserted by
+    /// `put_blocks`.
+    pub const NOTES_SCANNED: &str = "zcash_wallet_notes_scanned_total";
+    /// Gauge of the greatest height that the wallet has scanned.
+    pub const SCANNED_HEIGHT: &str = "zcash_wallet_scanned_height";
+    /// Gauge of the chain tip height most recently passed to `update_chain_tip`.
+    pub const CHAIN_TIP_HEIGHT: &str = "zcash_wallet_chain_tip_height";
+    /// Gauge of the number of transaction data requests that the wallet has outstanding, as of
+    /// the last call to `transaction_data_requests`.
+    pub const DATA_REQUESTS_OUTSTANDING: &str = "zcash_wallet_data_requests_outstanding";
+    /// Counter of the calls to `truncate_to_height` that completed successfully.
+    pub const TRUNCATIONS: &str = "zcash_wallet_truncations_total";
+    /// Counter of the scanned blocks discarded by truncations.
+    pub const TRUNCATED_BLOCKS: &str = "zcash_wallet_truncated_blocks_total";
+    /// Gauge of the size in bytes of the most recently encoded wallet snapshot, before any
+    /// compression.
+    pub const SNAPSHOT_BYTES: &str = "zcash_wallet_snapshot_bytes";
+}
+
+use metric_names::*;
+
+/// Records a batch of blocks inserted by `put_blocks`.
+pub(crate) fn record_put_blocks(blocks: usize, notes: usize, scanned_height: Option<BlockHeight>) {
+    ::metrics::counter!(BLOCKS_SCANNED).increment(blocks as u64);
+    ::metrics::counter!(NOTES_SCANNED).increment(notes as u64);
+    if let Some(height) = scanned_height {
+        ::metrics::gauge!(SCANNED_HEIGHT).set(u32::from(height));
+    }
+}
+
+/// Records the chain tip passed to `update_chain_tip`.
+pub(crate) fn record_chain_tip(tip_height: BlockHeight) {
+    ::metrics::gauge!(CHAIN_TIP_HEIGHT).set(u32::from(tip_height));
+}
+
+/// Records the number of transaction data requests returned by `transaction_data_requests`.
+pub(crate) fn record_data_requests(outstanding: usize) {
+    ::metrics::gauge!(DATA_REQUESTS_OUTSTANDING).set(outstanding as f64);
+}
+
+/// Records a truncation that discarded `depth` scanned blocks.
+pub(crate) fn record_truncation(depth: u32) {
+    ::metrics::counter!(TRUNCATIONS).increment(1);
+    ::metrics::counter!(TRUNCATED_BLOCKS).increment(u64::from(depth));
+}
+
+/// Records the size of an encoded wallet snapshot.
+pub(crate) fn record_snapshot_size(bytes: usize) {
+    ::metrics::gauge!(SNAPSHOT_BYTES).set(bytes as f64);
+}
+
+#[cfg(test)]
+mod tests {
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+    use zcash_client_backend::data_api::{
+        testing::{AddressType, TestBuilder},
+        WalletRead, WalletWrite,
+    };
+    use zcash_primitives::{block::BlockHash, transaction::components::amount::NonNegativeAmount};
+
+    use super::metric_names::*;
+    use crate::{testing::TestMemDbFactory, MemBlockCache};
+
+    #[test]
+    fn metrics_are_recorded_during_sync() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+
+        ::metrics::with_local_recorder(&recorder, || {
+            let mut st = TestBuilder::new()
+                .with_data_store_factory(TestMemDbFactory::new())
+                .with_block_cache(MemBlockCache::new())
+                .with_account_from_sapling_activation(BlockHash([0; 32]))
+                .build();
+            let dfvk = st.test_account_sapling().unwrap().clone();
+            let value = NonNegativeAmount::const_from_u64(50000);
+
+            let (h, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+            st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+            st.generate_empty_block();
+            st.wallet_mut().update_chain_tip(h + 2).unwrap();
+            st.scan_cached_blocks(h, 3);
+
+            st.wallet().transaction_data_requests().unwrap();
+            st.wallet_mut().truncate_to_height(h + 1).unwrap();
+
+            let mut buf = vec![];
+            st.wallet().encode(&mut buf).unwrap();
+        });
+
+        let values = snapshotter
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .map(|(key, _, _, value)| (key.key().name().to_owned(), value))
+            .collect::<std::collections::BTreeMap<_, _>>();
+        let value = |name: &str| values.get(name).cloned();
+
+        assert_eq!(value(BLOCKS_SCANNED), Some(DebugValue::Counter(3)));
+        assert_eq!(value(NOTES_SCANNED), Some(DebugValue::Counter(2)));
+        assert_eq!(value(TRUNCATIONS), Some(DebugValue::Counter(1)));
+        assert_eq!(value(TRUNCATED_BLOCKS), Some(DebugValue::Counter(1)));
+        assert!(matches!(
+            value(CHAIN_TIP_HEIGHT),
+            Some(DebugValue::Gauge(_))
+        ));
+        assert!(matches!(value(SCANNED_HEIGHT), Some(DebugValue::Gauge(_))));
+        assert!(matches!(
+            value(DATA_REQUESTS_OUTSTANDING),
+            Some(DebugValue::Gauge(_))
+        ));
+        assert!(matches!(
+            value(SNAPSHOT_BYTES),
+            Some(DebugValue::Gauge(bytes)) if bytes.into_inner() > 0.0
+        ));
+    }
+}
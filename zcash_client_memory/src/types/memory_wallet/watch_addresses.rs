@@ -0,0 +1,193 @@
+use zcash_client_backend::wallet::TransparentAddressMetadata;
+use zcash_primitives::legacy::keys::{
+    IncomingViewingKey, NonHardenedChildIndex, TransparentKeyScope,
+};
+
+use super::*;
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    /// Returns every transparent address at which the given account could have received funds,
+    /// along with the metadata describing how each address was derived.
+    ///
+    /// The result includes the transparent receivers of the unified addresses generated so far,
+    /// the account's legacy transparent address, the next [`GAP_LIMIT`] external addresses
+    /// beyond the highest external index that has been generated, the first [`GAP_LIMIT`]
+    /// internal addresses, and all stored ephemeral addresses (including those in the gap
+    /// window beyond the last reserved index). Each address appears only once.
+    ///
+    /// This is intended for watch-only tools that sweep the transparent addresses of an
+    /// account. It does not reserve or generate any addresses, and does not modify the wallet.
+    pub fn enumerate_watch_addresses(
+        &self,
+        account_id: AccountId,
+    ) -> Result<Vec<(TransparentAddress, TransparentAddressMetadata)>, Error> {
+        let account = self
+            .accounts
+            .get(account_id)
+            .ok_or(Error::AccountUnknown(account_id))?;
+
+        let mut seen = BTreeSet::new();
+        let mut result = vec![];
+        let mut push = |address: TransparentAddress, metadata: TransparentAddressMetadata| {
+            if seen.insert(address) {
+                result.push((address, metadata));
+            }
+        };
+
+        // External addresses that have been generated, tracking the end of the derived range.
+        let mut external_end = 0u32;
+        for (diversifier_index, ua) in account.addresses() {
+            if let Some((taddr, address_index)) = ua.transparent().zip(
+                u32::try_from(*diversifier_index)
+                    .ok()
+                    .and_then(NonHardenedChildIndex::from_index),
+            ) {
+                external_end = external_end.max(address_index.index() + 1);
+                push(
+                    *taddr,
+                    TransparentAddressMetadata::new(TransparentKeyScope::EXTERNAL, address_index),
+                );
+            }
+        }
+        if let Some((legacy_taddr, address_index)) = account.get_legacy_transparent_address()? {
+            external_end = external_end.max(address_index.index() + 1);
+            push(
+                legacy_taddr,
+                TransparentAddressMetadata::new(TransparentKeyScope::EXTERNAL, address_index),
+            );
+        }
+
+        if let Some(account_pubkey) = account.ufvk().and_then(|ufvk| ufvk.transparent()) {
+            let external_ivk = account_pubkey.derive_external_ivk()?;
+            for (address, metadata) in derive_window(
+                &external_ivk,
+                TransparentKeyScope::EXTERNAL,
+                external_end..external_end.saturating_add(GAP_LIMIT),
+            ) {
+                push(address, metadata);
+            }
+
+            // The wallet never generates internal transparent addresses, so only the gap window
+            // from the first index is covered.
+            let internal_ivk = account_pubkey.derive_internal_ivk()?;
+            for (address, metadata) in
+                derive_window(&internal_ivk, TransparentKeyScope::INTERNAL, 0..GAP_LIMIT)
+            {
+                push(address, metadata);
+            }
+        }
+
+        for (address, metadata) in account.ephemeral_addresses()? {
+            push(address, metadata);
+        }
+
+        Ok(result)
+    }
+}
+
+/// Derives the addresses at the given indices of a transparent key scope, skipping any index
+/// that is hardened or at which no valid address exists.
+fn derive_window<K: IncomingViewingKey>(
+    ivk: &K,
+    scope: TransparentKeyScope,
+    indices: Range<u32>,
+) -> impl Iterator<Item = (TransparentAddress, TransparentAddressMetadata)> + '_ {
+    indices
+        .filter_map(NonHardenedChildIndex::from_index)
+        .filter_map(move |address_index| {
+            ivk.derive_address(address_index).ok().map(|address| {
+                (
+                    address,
+                    TransparentAddressMetadata::new(scope, address_index),
+                )
+            })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use zcash_client_backend::data_api::{
+        testing::TestBuilder, Account as _, WalletRead, WalletWrite,
+    };
+    use zcash_keys::keys::UnifiedAddressRequest;
+    use zcash_primitives::{
+        block::BlockHash,
+        legacy::keys::{IncomingViewingKey, NonHardenedChildIndex, TransparentKeyScope},
+    };
+
+    use crate::{testing::TestMemDbFactory, MemBlockCache};
+
+    #[test]
+    fn watch_addresses_cover_each_derivation_path() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let account = st.test_account().cloned().unwrap();
+
+        let mut before = vec![];
+        st.wallet().encode(&mut before).unwrap();
+        let watched = st.wallet().enumerate_watch_addresses(account.id()).unwrap();
+        assert_eq!(
+            st.wallet().enumerate_watch_addresses(account.id()).unwrap(),
+            watched
+        );
+        let mut after = vec![];
+        st.wallet().encode(&mut after).unwrap();
+        assert_eq!(before, after);
+
+        let contains = |address, scope| {
+            watched
+                .iter()
+                .any(|(a, meta)| a == address && meta.scope() == scope)
+        };
+        let mut addresses = watched.iter().map(|(a, _)| *a).collect::<Vec<_>>();
+        addresses.sort();
+        addresses.dedup();
+        assert_eq!(addresses.len(), watched.len());
+
+        // The receiver of the current address, and the legacy address.
+        let current = st
+            .wallet()
+            .get_current_address(account.id())
+            .unwrap()
+            .unwrap();
+        assert!(contains(
+            current.transparent().unwrap(),
+            TransparentKeyScope::EXTERNAL
+        ));
+        let account_pubkey = account.usk().transparent().to_account_pubkey();
+        let (legacy_addr, _) = account_pubkey
+            .derive_external_ivk()
+            .unwrap()
+            .default_address();
+        assert!(contains(&legacy_addr, TransparentKeyScope::EXTERNAL));
+
+        // An internal address.
+        let internal_addr = account_pubkey
+            .derive_internal_ivk()
+            .unwrap()
+            .derive_address(NonHardenedChildIndex::ZERO)
+            .unwrap();
+        assert!(contains(&internal_addr, TransparentKeyScope::INTERNAL));
+
+        // Addresses generated or reserved after the enumeration fall within the gap windows.
+        let next = st
+            .wallet_mut()
+            .get_next_available_address(account.id(), UnifiedAddressRequest::all().unwrap())
+            .unwrap()
+            .unwrap();
+        assert!(contains(
+            next.transparent().unwrap(),
+            TransparentKeyScope::EXTERNAL
+        ));
+        let reserved = st
+            .wallet_mut()
+            .reserve_next_n_ephemeral_addresses(account.id(), 1)
+            .unwrap();
+        for (address, _) in &reserved {
+            assert!(contains(address, TransparentKeyScope::EPHEMERAL));
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use super::*;
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    /// Returns the wallet with zero-conf spends of transparent outputs allowed or disallowed.
+    ///
+    /// By default, a transparent output is only spendable once the transaction that created it
+    /// has been mined. If zero-conf spends are allowed, a caller that requests
+    /// `min_confirmations = 0` may also spend the outputs of an unmined transaction that cannot
+    /// expire or has not expired as of the target height, such as the change used to fund a
+    /// transaction to a TEX address.
+    ///
+    /// This is a property of the running wallet, and is not serialized.
+    pub fn with_zero_conf_spends_allowed(mut self, allow: bool) -> Self {
+        self.allow_zero_conf_spends = allow;
+        self
+    }
+
+    /// Allows or disallows zero-conf spends of transparent outputs. See
+    /// [`MemoryWalletDb::with_zero_conf_spends_allowed`].
+    pub fn set_allow_zero_conf_spends(&mut self, allow: bool) {
+        self.allow_zero_conf_spends = allow;
+    }
+
+    /// Returns whether zero-conf spends of transparent outputs are allowed.
+    pub fn allows_zero_conf_spends(&self) -> bool {
+        self.allow_zero_conf_spends
+    }
+}
+
+#[cfg(all(test, feature = "transparent-inputs"))]
+mod tests {
+    use std::{convert::Infallible, num::NonZeroU32};
+
+    use zcash_client_backend::{
+        data_api::{
+            testing::{AddressType, TestBuilder},
+            Account as _, DecryptedTransaction, InputSource, WalletRead, WalletWrite,
+        },
+        fees::StandardFeeRule,
+        wallet::OvkPolicy,
+    };
+    use zcash_keys::address::Address;
+    use zcash_primitives::{
+        block::BlockHash,
+        transaction::components::{amount::NonNegativeAmount, OutPoint},
+    };
+    use zcash_protocol::{consensus::BlockHeight, local_consensus::LocalNetwork, ShieldedProtocol};
+
+    use crate::{testing::TestMemDbFactory, MemBlockCache, MemoryWalletDb};
+
+    #[test]
+    fn zero_conf_spends_of_unmined_outputs() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let account = st.test_account().cloned().unwrap();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let taddr = *st
+            .wallet()
+            .get_current_address(account.id())
+            .unwrap()
+            .unwrap()
+            .transparent()
+            .unwrap();
+
+        let (h, _, _) = st.generate_next_block(
+            &dfvk,
+            AddressType::DefaultExternal,
+            NonNegativeAmount::const_from_u64(50000),
+        );
+        st.scan_cached_blocks(h, 1);
+
+        // Send funds to the wallet's own transparent address, and record the resulting output
+        // as the transaction would be observed in the mempool.
+        let proposal = st
+            .propose_standard_transfer::<Infallible>(
+                account.id(),
+                StandardFeeRule::Zip317,
+                NonZeroU32::new(1).unwrap(),
+                &Address::Transparent(taddr),
+                NonNegativeAmount::const_from_u64(10000),
+                None,
+                None,
+                ShieldedProtocol::Sapling,
+            )
+            .unwrap();
+        let txid = st
+            .create_proposed_transactions::<Infallible, _, Infallible>(
+                account.usk(),
+                OvkPolicy::Sender,
+                &proposal,
+            )
+            .unwrap()[0];
+        let tx = st.wallet().get_transaction(txid).unwrap().unwrap();
+        let expiry_height = tx.expiry_height();
+        st.wallet_mut()
+            .store_decrypted_tx(DecryptedTransaction::new(
+                None,
+                &tx,
+                vec![],
+                #[cfg(feature = "orchard")]
+                vec![],
+            ))
+            .unwrap();
+        let outpoint = OutPoint::new(txid.into(), 0);
+        assert!(st
+            .wallet()
+            .transparent_received_outputs
+            .get(&outpoint)
+            .is_some());
+
+        let spendable = |wallet: &MemoryWalletDb<LocalNetwork>,
+                         target_height: BlockHeight,
+                         min_confirmations: u32| {
+            wallet
+                .get_spendable_transparent_outputs(&taddr, target_height, min_confirmations)
+                .unwrap()
+                .iter()
+                .map(|output| output.outpoint().clone())
+                .collect::<Vec<_>>()
+        };
+
+        // Zero-conf spends are disallowed by default.
+        assert!(!st.wallet().allows_zero_conf_spends());
+        assert_eq!(spendable(st.wallet(), h + 1, 0), vec![]);
+
+        // Once allowed, the unmined output is only spendable with zero confirmations, and only
+        // until its transaction expires.
+        st.wallet_mut().set_allow_zero_conf_spends(true);
+        let wallet = st.wallet();
+        assert_eq!(spendable(wallet, h + 1, 0), vec![outpoint.clone()]);
+        assert_eq!(spendable(wallet, h + 1, 1), vec![]);
+        assert_eq!(spendable(wallet, expiry_height, 0), vec![outpoint.clone()]);
+        assert_eq!(spendable(wallet, expiry_height + 1, 0), vec![]);
+    }
+}
@@ -4,12 +4,49 @@ mod sent;
 pub(crate) use received::{
     to_spendable_notes, ReceievedNoteSpends, ReceivedNote, ReceivedNoteTable,
 };
-#[cfg(test)]
-pub(crate) use sent::SentNoteId;
-pub(crate) use sent::{SentNote, SentNoteTable};
+pub(crate) use sent::{SentNote, SentNoteId, SentNoteTable};
+
+use zcash_client_backend::wallet::NoteId;
+use zcash_primitives::transaction::TxId;
+use zcash_protocol::memo::{Memo, MemoBytes};
+
+use crate::error::Error;
+
+/// Converts the index of a shielded output within its transaction into the `u16` used by
+/// [`NoteId`](zcash_client_backend::wallet::NoteId), failing if the index is out of range.
+pub(crate) fn output_index_u16(txid: TxId, index: usize) -> Result<u16, Error> {
+    u16::try_from(index).map_err(|_| Error::OutputIndexOutOfRange { txid, index })
+}
+
+/// Returns the memo of a note given the memos recorded for it as a received and as a sent
+/// note.
+///
+/// A note discovered by scanning a compact block has no memo until its transaction is
+/// decrypted, so for change we prefer the memo recorded when the wallet sent it.
+pub(crate) fn preferred_memo(received: Option<&Memo>, sent: Option<&Memo>) -> Option<Memo> {
+    match (received, sent) {
+        (Some(Memo::Empty), Some(sent)) => Some(sent.clone()),
+        (received, sent) => received.or(sent).cloned(),
+    }
+}
+
+/// Decodes the memo of the note with the given id (`None` for a transparent output), failing
+/// if `bytes` is longer than 512 bytes or is not a valid memo.
+///
+/// Memos are stored in decoded form and always encoded as the canonical 512 bytes, so memos
+/// that differ only in trailing padding are stored and serialized identically.
+pub(crate) fn decode_memo(note_id: Option<NoteId>, bytes: &[u8]) -> Result<Memo, Error> {
+    MemoBytes::from_bytes(bytes)
+        .and_then(Memo::try_from)
+        .map_err(|_| Error::InvalidMemo {
+            note_id,
+            len: bytes.len(),
+        })
+}
 
 mod serialization {
-    use crate::error::Error;
+    use super::{output_index_u16, TxId};
+    use crate::error::{read_array, read_enum, Error};
     use crate::proto::memwallet::{self as proto};
     use crate::read_optional;
     use jubjub::Fr;
@@ -23,14 +60,11 @@ mod serialization {
                     zcash_protocol::ShieldedProtocol::Sapling => {
                         proto::PoolType::ShieldedSapling.into()
                     }
-                    #[cfg(feature = "orchard")]
                     zcash_protocol::ShieldedProtocol::Orchard => {
                         proto::PoolType::ShieldedOrchard.into()
                     }
-                    #[cfg(not(feature = "orchard"))]
-                    zcash_protocol::ShieldedProtocol::Orchard => panic!("Attempting to deserialize orchard supporting wallet using library built without orchard feature"),
                 },
-                output_index: note_id.output_index() as u32,
+                output_index: note_id.output_index().into(),
             }
         }
     }
@@ -38,15 +72,24 @@ mod serialization {
     impl TryFrom<proto::NoteId> for NoteId {
         type Error = Error;
         fn try_from(note_id: proto::NoteId) -> Result<Self, Self::Error> {
+            let txid: TxId = read_optional!(note_id.clone(), tx_id)?.try_into()?;
             Ok(Self::new(
-                read_optional!(note_id.clone(), tx_id)?.try_into()?,
-                match note_id.pool() {
+                txid,
+                match read_enum("pool", note_id.pool)? {
                     proto::PoolType::ShieldedSapling => zcash_protocol::ShieldedProtocol::Sapling,
                     #[cfg(feature = "orchard")]
                     proto::PoolType::ShieldedOrchard => zcash_protocol::ShieldedProtocol::Orchard,
-                    _ => panic!("invalid pool"),
+                    #[cfg(not(feature = "orchard"))]
+                    proto::PoolType::ShieldedOrchard => return Err(Error::OrchardNotEnabled),
+                    // A shielded note cannot be identified by a transparent output.
+                    proto::PoolType::Transparent => {
+                        return Err(Error::InvalidProtoEnum {
+                            field: "pool",
+                            value: note_id.pool,
+                        })
+                    }
                 },
-                note_id.output_index.try_into()?,
+                output_index_u16(txid, note_id.output_index as usize)?,
             ))
         }
     }
@@ -85,48 +128,55 @@ mod serialization {
         }
     }
 
-    impl From<proto::Note> for Note {
-        fn from(note: proto::Note) -> Self {
-            match note.protocol() {
+    impl TryFrom<proto::Note> for Note {
+        type Error = Error;
+
+        fn try_from(note: proto::Note) -> Result<Self, Self::Error> {
+            match read_enum("protocol", note.protocol)? {
                 proto::ShieldedProtocol::Sapling => {
-                    let recipient =
-                        sapling::PaymentAddress::from_bytes(&note.recipient.try_into().unwrap())
-                            .unwrap();
+                    let recipient = sapling::PaymentAddress::from_bytes(&read_array(
+                        "recipient",
+                        note.recipient,
+                    )?)
+                    .ok_or(Error::NonCanonicalEncoding("recipient"))?;
                     let value = sapling::value::NoteValue::from_raw(note.value);
-                    let rseed = match note.rseed {
-                        Some(proto::RSeed {
-                            rseed_type: Some(0),
-                            payload,
-                        }) => sapling::Rseed::BeforeZip212(
-                            Fr::from_bytes(&payload.try_into().unwrap()).unwrap(),
+                    let rseed = read_optional!(note, rseed)?;
+                    let rseed = match read_enum("rseed_type", read_optional!(rseed, rseed_type)?)? {
+                        proto::RSeedType::BeforeZip212 => sapling::Rseed::BeforeZip212(
+                            Option::from(Fr::from_bytes(&read_array("rseed", rseed.payload)?))
+                                .ok_or(Error::NonCanonicalEncoding("rseed"))?,
                         ),
-                        Some(proto::RSeed {
-                            rseed_type: Some(1),
-                            payload,
-                        }) => sapling::Rseed::AfterZip212(payload.try_into().unwrap()),
-                        _ => panic!("rseed is required"),
+                        proto::RSeedType::AfterZip212 => {
+                            sapling::Rseed::AfterZip212(read_array("rseed", rseed.payload)?)
+                        }
                     };
-                    Self::Sapling(sapling::Note::from_parts(recipient, value, rseed))
+                    Ok(Self::Sapling(sapling::Note::from_parts(
+                        recipient, value, rseed,
+                    )))
                 }
                 #[cfg(feature = "orchard")]
                 proto::ShieldedProtocol::Orchard => {
-                    let recipient = orchard::Address::from_raw_address_bytes(
-                        &note.recipient.try_into().unwrap(),
-                    )
-                    .unwrap();
+                    let recipient = Option::from(orchard::Address::from_raw_address_bytes(
+                        &read_array("recipient", note.recipient)?,
+                    ))
+                    .ok_or(Error::NonCanonicalEncoding("recipient"))?;
                     let value = orchard::value::NoteValue::from_raw(note.value);
-                    let rho =
-                        orchard::note::Rho::from_bytes(&note.rho.unwrap().try_into().unwrap())
-                            .unwrap();
-                    let rseed = orchard::note::RandomSeed::from_bytes(
-                        note.rseed.unwrap().payload.try_into().unwrap(),
+                    let rho = Option::from(orchard::note::Rho::from_bytes(&read_array(
+                        "rho",
+                        read_optional!(note, rho)?,
+                    )?))
+                    .ok_or(Error::NonCanonicalEncoding("rho"))?;
+                    let rseed = Option::from(orchard::note::RandomSeed::from_bytes(
+                        read_array("rseed", read_optional!(note, rseed)?.payload)?,
                         &rho,
-                    )
-                    .unwrap();
-                    Self::Orchard(orchard::Note::from_parts(recipient, value, rho, rseed).unwrap())
+                    ))
+                    .ok_or(Error::NonCanonicalEncoding("rseed"))?;
+                    Option::from(orchard::Note::from_parts(recipient, value, rho, rseed))
+                        .map(Self::Orchard)
+                        .ok_or(Error::NonCanonicalEncoding("note"))
                 }
                 #[cfg(not(feature = "orchard"))]
-                _ => panic!("invalid protocol"),
+                proto::ShieldedProtocol::Orchard => Err(Error::OrchardNotEnabled),
             }
         }
     }
@@ -137,9 +187,8 @@ mod serialization {
         use crate::proto::memwallet as proto;
         use pretty_assertions::assert_eq;
 
-        #[test]
-        fn test_note_roundtrip() {
-            let note = Note::Sapling(sapling::note::Note::from_parts(
+        fn sapling_note() -> Note {
+            Note::Sapling(sapling::note::Note::from_parts(
                 sapling::PaymentAddress::from_bytes(&[
                     0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x30, 0x8e,
                     0x11, 0x9d, 0x72, 0x99, 0x2b, 0x56, 0x0d, 0x26, 0x50, 0xff, 0xe0, 0xbe, 0x7f,
@@ -149,12 +198,113 @@ mod serialization {
                 .unwrap(),
                 sapling::value::NoteValue::from_raw(99),
                 sapling::Rseed::AfterZip212([0; 32]),
-            ));
+            ))
+        }
+
+        #[cfg(feature = "orchard")]
+        fn orchard_note() -> Note {
+            let sk = (0..=u8::MAX)
+                .find_map(|i| orchard::keys::SpendingKey::from_bytes([i; 32]).into_option())
+                .unwrap();
+            let recipient =
+                orchard::keys::FullViewingKey::from(&sk).address_at(0u32, zip32::Scope::External);
+            let rho = orchard::note::Rho::from_bytes(&[0; 32]).unwrap();
+            let note = (0..=u8::MAX)
+                .find_map(|i| {
+                    orchard::note::RandomSeed::from_bytes([i; 32], &rho)
+                        .into_option()
+                        .and_then(|rseed| {
+                            orchard::Note::from_parts(
+                                recipient,
+                                orchard::value::NoteValue::from_raw(99),
+                                rho,
+                                rseed,
+                            )
+                            .into_option()
+                        })
+                })
+                .unwrap();
+            Note::Orchard(note)
+        }
 
+        #[test]
+        fn test_note_roundtrip() {
+            let note = sapling_note();
             let proto_note: proto::Note = note.clone().into();
-            let recovered: Note = proto_note.into();
+            let recovered = Note::try_from(proto_note).unwrap();
 
             assert_eq!(note, recovered);
         }
+
+        #[test]
+        fn malformed_notes_are_rejected() {
+            let mut proto_note: proto::Note = sapling_note().into();
+            proto_note.recipient.pop();
+            assert!(matches!(
+                Note::try_from(proto_note),
+                Err(Error::InvalidProtoFieldLength {
+                    field: "recipient",
+                    expected: 43,
+                    actual: 42,
+                })
+            ));
+
+            let mut proto_note: proto::Note = sapling_note().into();
+            proto_note.protocol = 7;
+            assert!(matches!(
+                Note::try_from(proto_note),
+                Err(Error::InvalidProtoEnum {
+                    field: "protocol",
+                    value: 7,
+                })
+            ));
+
+            let mut proto_note: proto::Note = sapling_note().into();
+            proto_note.rseed = None;
+            assert!(matches!(
+                Note::try_from(proto_note),
+                Err(Error::ProtoMissingField("rseed"))
+            ));
+
+            #[cfg(feature = "orchard")]
+            {
+                let mut proto_note: proto::Note = orchard_note().into();
+                proto_note.rho = None;
+                assert!(matches!(
+                    Note::try_from(proto_note),
+                    Err(Error::ProtoMissingField("rho"))
+                ));
+            }
+        }
+
+        #[cfg(not(feature = "orchard"))]
+        #[test]
+        fn orchard_notes_require_orchard_feature() {
+            let mut proto_note: proto::Note = sapling_note().into();
+            proto_note.protocol = proto::ShieldedProtocol::Orchard.into();
+            assert!(matches!(
+                Note::try_from(proto_note),
+                Err(Error::OrchardNotEnabled)
+            ));
+        }
+
+        #[test]
+        fn orchard_note_ids_encode_regardless_of_orchard_feature() {
+            let note_id = NoteId::new(
+                TxId::from_bytes([7; 32]),
+                zcash_protocol::ShieldedProtocol::Orchard,
+                3,
+            );
+            let proto_note_id: proto::NoteId = note_id.into();
+            assert_eq!(proto_note_id.pool, proto::PoolType::ShieldedOrchard as i32);
+
+            #[cfg(feature = "orchard")]
+            assert_eq!(NoteId::try_from(proto_note_id).unwrap(), note_id);
+            #[cfg(not(feature = "orchard"))]
+            assert!(matches!(
+                NoteId::try_from(proto_note_id),
+                Err(Error::OrchardNotEnabled)
+            ));
+        }
     }
 }
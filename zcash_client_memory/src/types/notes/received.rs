@@ -13,8 +13,10 @@ use zcash_protocol::{memo::Memo, PoolType, ShieldedProtocol::Sapling};
 use zcash_client_backend::{
     data_api::{SentTransactionOutput, SpendableNotes},
     wallet::{Note, NoteId, Recipient, WalletSaplingOutput},
+    DecryptedOutput,
 };
 
+use super::{decode_memo, output_index_u16};
 use crate::AccountId;
 
 #[cfg(feature = "orchard")]
@@ -98,48 +100,101 @@ impl ReceivedNote {
                 receiving_account,
                 note: Note::Sapling(note),
                 ..
-            } => Ok(ReceivedNote {
-                note_id: NoteId::new(txid, Sapling, output.output_index() as u16),
-                txid,
-                output_index: output.output_index() as u32,
-                account_id: *receiving_account,
-                note: Note::Sapling(note.clone()),
-                nf: None,
-                is_change: true,
-                memo: output
-                    .memo()
-                    .map(Memo::try_from)
-                    .transpose()?
-                    .expect("expected a memo for a non-transparent output"),
-                commitment_tree_position: None,
-                recipient_key_scope: Some(Scope::Internal),
-            }),
+            } => {
+                let note_id = NoteId::new(
+                    txid,
+                    Sapling,
+                    output_index_u16(txid, output.output_index())?,
+                );
+                Ok(ReceivedNote {
+                    note_id,
+                    txid,
+                    output_index: note_id.output_index().into(),
+                    account_id: *receiving_account,
+                    note: Note::Sapling(note.clone()),
+                    nf: None,
+                    is_change: true,
+                    memo: output
+                        .memo()
+                        .map(|m| decode_memo(Some(note_id), m.as_slice()))
+                        .transpose()?
+                        .expect("expected a memo for a non-transparent output"),
+                    commitment_tree_position: None,
+                    recipient_key_scope: Some(Scope::Internal),
+                })
+            }
             #[cfg(feature = "orchard")]
             Recipient::InternalAccount {
                 receiving_account,
                 note: Note::Orchard(note),
                 ..
-            } => Ok(ReceivedNote {
-                note_id: NoteId::new(txid, Orchard, output.output_index() as u16),
-                txid,
-                output_index: output.output_index() as u32,
-                account_id: *receiving_account,
-                note: Note::Orchard(*note),
-                nf: None,
-                is_change: true,
-                memo: output
-                    .memo()
-                    .map(Memo::try_from)
-                    .transpose()?
-                    .expect("expected a memo for a non-transparent output"),
-                commitment_tree_position: None,
-                recipient_key_scope: Some(Scope::Internal),
-            }),
+            } => {
+                let note_id = NoteId::new(
+                    txid,
+                    Orchard,
+                    output_index_u16(txid, output.output_index())?,
+                );
+                Ok(ReceivedNote {
+                    note_id,
+                    txid,
+                    output_index: note_id.output_index().into(),
+                    account_id: *receiving_account,
+                    note: Note::Orchard(*note),
+                    nf: None,
+                    is_change: true,
+                    memo: output
+                        .memo()
+                        .map(|m| decode_memo(Some(note_id), m.as_slice()))
+                        .transpose()?
+                        .expect("expected a memo for a non-transparent output"),
+                    commitment_tree_position: None,
+                    recipient_key_scope: Some(Scope::Internal),
+                })
+            }
             _ => Err(Error::Other(
                 "Recipient is not an internal shielded account".to_owned(),
             )),
         }
     }
+    /// Constructs a note received from outside the wallet, as recovered by decrypting the
+    /// full transaction with an external incoming viewing key.
+    pub fn from_incoming_sapling_output(
+        txid: TxId,
+        output: &DecryptedOutput<sapling::Note, AccountId>,
+    ) -> Result<Self, Error> {
+        let note_id = NoteId::new(txid, Sapling, output_index_u16(txid, output.index())?);
+        Ok(ReceivedNote {
+            note_id,
+            txid,
+            output_index: note_id.output_index().into(),
+            account_id: *output.account(),
+            note: Note::Sapling(output.note().clone()),
+            nf: None,
+            is_change: false,
+            memo: decode_memo(Some(note_id), output.memo().as_slice())?,
+            commitment_tree_position: None,
+            recipient_key_scope: Some(Scope::External),
+        })
+    }
+    #[cfg(feature = "orchard")]
+    pub fn from_incoming_orchard_output(
+        txid: TxId,
+        output: &DecryptedOutput<orchard::note::Note, AccountId>,
+    ) -> Result<Self, Error> {
+        let note_id = NoteId::new(txid, Orchard, output_index_u16(txid, output.index())?);
+        Ok(ReceivedNote {
+            note_id,
+            txid,
+            output_index: note_id.output_index().into(),
+            account_id: *output.account(),
+            note: Note::Orchard(*output.note()),
+            nf: None,
+            is_change: false,
+            memo: decode_memo(Some(note_id), output.memo().as_slice())?,
+            commitment_tree_position: None,
+            recipient_key_scope: Some(Scope::External),
+        })
+    }
     pub fn from_wallet_sapling_output(
         note_id: NoteId,
         output: &WalletSaplingOutput<AccountId>,
@@ -147,7 +202,7 @@ impl ReceivedNote {
         ReceivedNote {
             note_id,
             txid: *note_id.txid(),
-            output_index: output.index() as u32,
+            output_index: note_id.output_index().into(),
             account_id: *output.account_id(),
             note: Note::Sapling(output.note().clone()),
             nf: output.nf().map(|nf| Nullifier::Sapling(*nf)),
@@ -165,7 +220,7 @@ impl ReceivedNote {
         ReceivedNote {
             note_id,
             txid: *note_id.txid(),
-            output_index: output.index() as u32,
+            output_index: note_id.output_index().into(),
             account_id: *output.account_id(),
             note: Note::Orchard(*output.note()),
             nf: output.nf().map(|nf| Nullifier::Orchard(*nf)),
@@ -197,30 +252,6 @@ impl ReceivedNoteTable {
         Self(Vec::new())
     }
 
-    pub fn get_sapling_nullifiers(
-        &self,
-    ) -> impl Iterator<Item = (AccountId, TxId, sapling::Nullifier)> + '_ {
-        self.0.iter().filter_map(|entry| {
-            if let Some(Nullifier::Sapling(nf)) = entry.nullifier() {
-                Some((entry.account_id(), entry.txid(), *nf))
-            } else {
-                None
-            }
-        })
-    }
-    #[cfg(feature = "orchard")]
-    pub fn get_orchard_nullifiers(
-        &self,
-    ) -> impl Iterator<Item = (AccountId, TxId, orchard::note::Nullifier)> + '_ {
-        self.0.iter().filter_map(|entry| {
-            if let Some(Nullifier::Orchard(nf)) = entry.nullifier() {
-                Some((entry.account_id(), entry.txid(), *nf))
-            } else {
-                None
-            }
-        })
-    }
-
     pub fn insert_received_note(&mut self, note: ReceivedNote) {
         // ensure note_id is unique.
         // follow upsert rules to update the note if it already exists
@@ -233,6 +264,11 @@ impl ReceivedNoteTable {
                 n.is_change = note.is_change || n.is_change;
                 n.commitment_tree_position =
                     note.commitment_tree_position.or(n.commitment_tree_position);
+                // A note discovered by scanning a compact block has no memo, so it must not
+                // replace one recovered by decrypting the full transaction.
+                if note.memo != Memo::Empty {
+                    n.memo = note.memo.clone();
+                }
             })
             .is_none();
 
@@ -303,7 +339,7 @@ pub(crate) fn to_spendable_notes(
                 Ok(zcash_client_backend::wallet::ReceivedNote::from_parts(
                     note.note_id,
                     note.txid(),
-                    note.output_index.try_into().unwrap(), // this overflow can never happen or else the chain is broken
+                    output_index_u16(note.txid(), note.output_index as usize)?,
                     inner.clone(),
                     note.recipient_key_scope
                         .ok_or(Error::Missing("recipient key scope".into()))?,
@@ -324,7 +360,7 @@ pub(crate) fn to_spendable_notes(
                 Ok(zcash_client_backend::wallet::ReceivedNote::from_parts(
                     note.note_id,
                     note.txid(),
-                    note.output_index.try_into().unwrap(), // this overflow can never happen or else the chain is broken
+                    output_index_u16(note.txid(), note.output_index as usize)?,
                     *inner,
                     note.recipient_key_scope
                         .ok_or(Error::Missing("recipient key scope".into()))?,
@@ -390,20 +426,28 @@ mod serialization {
         type Error = Error;
 
         fn try_from(value: proto::ReceivedNote) -> Result<ReceivedNote, Error> {
+            let txid = read_optional!(value, tx_id)?.try_into()?;
+            let note_id = read_optional!(value, note_id)?.try_into()?;
             Ok(Self {
-                note_id: read_optional!(value, note_id)?.try_into()?,
-                txid: read_optional!(value, tx_id)?.try_into()?,
-                output_index: value.output_index,
+                note_id,
+                txid,
+                output_index: output_index_u16(txid, value.output_index as usize)?.into(),
                 account_id: value.account_id.into(),
-                note: read_optional!(value, note)?.into(),
+                note: read_optional!(value, note)?.try_into()?,
                 nf: value.nullifier.map(|nf| nf.try_into()).transpose()?,
                 is_change: value.is_change,
-                memo: Memo::from_bytes(&value.memo)?,
+                memo: decode_memo(Some(note_id), &value.memo)?,
                 commitment_tree_position: value.commitment_tree_position.map(|pos| pos.into()),
                 recipient_key_scope: match value.recipient_key_scope {
                     Some(0) => Some(Scope::Internal),
                     Some(1) => Some(Scope::External),
-                    _ => None,
+                    Some(value) => {
+                        return Err(Error::InvalidProtoEnum {
+                            field: "recipient_key_scope",
+                            value,
+                        })
+                    }
+                    None => None,
                 },
             })
         }
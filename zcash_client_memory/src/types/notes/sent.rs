@@ -8,7 +8,8 @@ use zcash_client_backend::{
     wallet::{Note, NoteId, Recipient},
 };
 
-use crate::AccountId;
+use super::{decode_memo, output_index_u16};
+use crate::{error::Error, AccountId};
 
 #[cfg(feature = "orchard")]
 use zcash_protocol::ShieldedProtocol::Orchard;
@@ -52,7 +53,7 @@ impl SentNoteTable {
         &mut self,
         tx: &SentTransaction<AccountId>,
         output: &SentTransactionOutput<AccountId>,
-    ) {
+    ) -> Result<(), Error> {
         let pool_type = match output.recipient() {
             Recipient::External(_, pool_type) => *pool_type,
             Recipient::EphemeralTransparent { .. } => PoolType::Transparent,
@@ -64,7 +65,12 @@ impl SentNoteTable {
                 // just make it a sapling one for now until we figure out a better way to represent this
                 let note_id = SentNoteId::Transparent {
                     txid: tx.tx().txid(),
-                    output_index: output.output_index().try_into().unwrap(),
+                    output_index: u32::try_from(output.output_index()).map_err(|_| {
+                        Error::OutputIndexOutOfRange {
+                            txid: tx.tx().txid(),
+                            index: output.output_index(),
+                        }
+                    })?,
                 };
                 self.0.insert(
                     note_id,
@@ -80,7 +86,7 @@ impl SentNoteTable {
                 let note_id = NoteId::new(
                     tx.tx().txid(),
                     protocol,
-                    output.output_index().try_into().unwrap(),
+                    output_index_u16(tx.tx().txid(), output.output_index())?,
                 );
                 self.0.insert(
                     note_id.into(),
@@ -88,11 +94,16 @@ impl SentNoteTable {
                         from_account_id: *tx.account_id(),
                         to: output.recipient().clone(),
                         value: output.value(),
-                        memo: output.memo().map(|m| Memo::try_from(m).unwrap()).unwrap(),
+                        memo: output
+                            .memo()
+                            .map(|m| decode_memo(Some(note_id), m.as_slice()))
+                            .transpose()?
+                            .expect("expected a memo for a non-transparent output"),
                     },
                 );
             }
         }
+        Ok(())
     }
 
     pub fn put_sent_output(
@@ -100,7 +111,7 @@ impl SentNoteTable {
         txid: TxId,
         from_account_id: AccountId,
         output: &SentTransactionOutput<AccountId>,
-    ) {
+    ) -> Result<(), Error> {
         let pool_type = match output.recipient() {
             Recipient::External(_, pool_type) => *pool_type,
             Recipient::EphemeralTransparent { .. } => PoolType::Transparent,
@@ -112,7 +123,12 @@ impl SentNoteTable {
                 // just make it a sapling one for now until we figure out a better way to represent this
                 let note_id = SentNoteId::Transparent {
                     txid,
-                    output_index: output.output_index().try_into().unwrap(),
+                    output_index: u32::try_from(output.output_index()).map_err(|_| {
+                        Error::OutputIndexOutOfRange {
+                            txid,
+                            index: output.output_index(),
+                        }
+                    })?,
                 };
                 self.0.insert(
                     note_id,
@@ -125,19 +141,27 @@ impl SentNoteTable {
                 );
             }
             PoolType::Shielded(protocol) => {
-                let note_id =
-                    NoteId::new(txid, protocol, output.output_index().try_into().unwrap());
+                let note_id = NoteId::new(
+                    txid,
+                    protocol,
+                    output_index_u16(txid, output.output_index())?,
+                );
                 self.0.insert(
                     note_id.into(),
                     SentNote {
                         from_account_id,
                         to: output.recipient().clone(),
                         value: output.value(),
-                        memo: output.memo().map(|m| Memo::try_from(m).unwrap()).unwrap(),
+                        memo: output
+                            .memo()
+                            .map(|m| decode_memo(Some(note_id), m.as_slice()))
+                            .transpose()?
+                            .expect("expected a memo for a non-transparent output"),
                     },
                 );
             }
         }
+        Ok(())
     }
 
     pub fn get_sent_note(&self, note_id: &NoteId) -> Option<&SentNote> {
@@ -163,7 +187,11 @@ pub(crate) struct SentNote {
 
 mod serialization {
     use super::*;
-    use crate::{error::Error, proto::memwallet as proto, read_optional};
+    use crate::{
+        error::{read_enum, Error},
+        proto::memwallet as proto,
+        read_optional,
+    };
     use zcash_address::ZcashAddress;
     use zcash_keys::encoding::AddressCodec;
     use zcash_primitives::{
@@ -182,15 +210,19 @@ mod serialization {
         }
     }
 
-    impl TryFrom<proto::SentNote> for SentNote {
+    impl TryFrom<(&SentNoteId, proto::SentNote)> for SentNote {
         type Error = crate::Error;
 
-        fn try_from(note: proto::SentNote) -> Result<Self, Self::Error> {
+        fn try_from((note_id, note): (&SentNoteId, proto::SentNote)) -> Result<Self, Self::Error> {
+            let note_id = match note_id {
+                SentNoteId::Shielded(note_id) => Some(*note_id),
+                SentNoteId::Transparent { .. } => None,
+            };
             Ok(Self {
                 from_account_id: note.from_account_id.into(),
                 to: read_optional!(note, to)?.try_into()?,
                 value: Zatoshis::from_u64(note.value)?,
-                memo: Memo::from_bytes(&note.memo)?,
+                memo: decode_memo(note_id, &note.memo)?,
             })
         }
     }
@@ -219,7 +251,7 @@ mod serialization {
         type Error = Error;
 
         fn try_from(note_id: proto::NoteId) -> Result<Self, Self::Error> {
-            Ok(match note_id.pool() {
+            Ok(match read_enum("pool", note_id.pool)? {
                 proto::PoolType::ShieldedSapling => SentNoteId::Shielded(NoteId::new(
                     read_optional!(note_id, tx_id)?.try_into()?,
                     Sapling,
@@ -254,7 +286,11 @@ mod serialization {
         type Error = Error;
 
         fn try_from(outpoint: proto::OutPoint) -> Result<Self, Self::Error> {
-            Ok(Self::new(outpoint.hash.try_into()?, outpoint.n))
+            let hash: [u8; 32] = outpoint
+                .hash
+                .try_into()
+                .map_err(|hash: Vec<u8>| Error::InvalidOutPointHash(hash.len()))?;
+            Ok(Self::new(hash, outpoint.n))
         }
     }
 
@@ -268,10 +304,9 @@ mod serialization {
                     pool_type: Some(match pool_type {
                         PoolType::Transparent => proto::PoolType::Transparent,
                         PoolType::Shielded(Sapling) => proto::PoolType::ShieldedSapling,
-                        #[cfg(feature = "orchard")]
-                        PoolType::Shielded(Orchard) => proto::PoolType::ShieldedOrchard,
-                        #[cfg(not(feature = "orchard"))]
-                        _ => panic!("Orchard not enabled"),
+                        PoolType::Shielded(ShieldedProtocol::Orchard) => {
+                            proto::PoolType::ShieldedOrchard
+                        }
                     } as i32),
 
                     account_id: None,
@@ -313,38 +348,41 @@ mod serialization {
     impl TryFrom<proto::Recipient> for Recipient<AccountId, Note, OutPoint> {
         type Error = Error;
         fn try_from(recipient: proto::Recipient) -> Result<Self, Self::Error> {
-            Ok(match recipient.recipient_type() {
-                proto::RecipientType::ExternalRecipient => {
-                    let address_str = read_optional!(recipient.clone(), address)?;
-                    let address = ZcashAddress::try_from_encoded(&address_str)?;
-                    Recipient::External(
-                        address,
-                        match recipient.pool_type() {
-                            proto::PoolType::Transparent => PoolType::Transparent,
-                            proto::PoolType::ShieldedSapling => PoolType::Shielded(Sapling),
-                            #[cfg(feature = "orchard")]
-                            proto::PoolType::ShieldedOrchard => PoolType::Shielded(Orchard),
-                            #[cfg(not(feature = "orchard"))]
-                            proto::PoolType::ShieldedOrchard => {
-                                return Err(Error::OrchardNotEnabled)
-                            }
-                        },
-                    )
-                }
-                proto::RecipientType::EphemeralTransparent => Recipient::EphemeralTransparent {
-                    receiving_account: read_optional!(recipient, account_id)?.into(),
-                    ephemeral_address: TransparentAddress::decode(
-                        &EncodingParams,
-                        &read_optional!(recipient, address)?,
-                    )?,
-                    outpoint_metadata: read_optional!(recipient, outpoint_metadata)?.try_into()?,
-                },
-                proto::RecipientType::InternalAccount => Recipient::InternalAccount {
-                    receiving_account: read_optional!(recipient, account_id)?.into(),
-                    external_address: recipient.address.map(|a| a.parse()).transpose()?,
-                    note: read_optional!(recipient, note)?.into(),
+            Ok(
+                match read_enum("recipient_type", recipient.recipient_type)? {
+                    proto::RecipientType::ExternalRecipient => {
+                        let address_str = read_optional!(recipient.clone(), address)?;
+                        let address = ZcashAddress::try_from_encoded(&address_str)?;
+                        Recipient::External(
+                            address,
+                            match read_enum("pool_type", read_optional!(recipient, pool_type)?)? {
+                                proto::PoolType::Transparent => PoolType::Transparent,
+                                proto::PoolType::ShieldedSapling => PoolType::Shielded(Sapling),
+                                #[cfg(feature = "orchard")]
+                                proto::PoolType::ShieldedOrchard => PoolType::Shielded(Orchard),
+                                #[cfg(not(feature = "orchard"))]
+                                proto::PoolType::ShieldedOrchard => {
+                                    return Err(Error::OrchardNotEnabled)
+                                }
+                            },
+                        )
+                    }
+                    proto::RecipientType::EphemeralTransparent => Recipient::EphemeralTransparent {
+                        receiving_account: read_optional!(recipient, account_id)?.into(),
+                        ephemeral_address: TransparentAddress::decode(
+                            &EncodingParams,
+                            &read_optional!(recipient, address)?,
+                        )?,
+                        outpoint_metadata: read_optional!(recipient, outpoint_metadata)?
+                            .try_into()?,
+                    },
+                    proto::RecipientType::InternalAccount => Recipient::InternalAccount {
+                        receiving_account: read_optional!(recipient, account_id)?.into(),
+                        external_address: recipient.address.map(|a| a.parse()).transpose()?,
+                        note: read_optional!(recipient, note)?.try_into()?,
+                    },
                 },
-            })
+            )
         }
     }
 
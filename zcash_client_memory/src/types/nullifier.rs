@@ -18,6 +18,12 @@ impl NullifierMap {
     pub fn get(&self, nullifier: &Nullifier) -> Option<&(BlockHeight, u32)> {
         self.0.get(nullifier)
     }
+
+    /// Removes the entries with a locator referencing a block above the given height.
+    pub fn truncate(&mut self, height: BlockHeight) {
+        self.0
+            .retain(|_, (locator_height, _)| *locator_height <= height);
+    }
 }
 
 impl Deref for NullifierMap {
@@ -60,7 +66,11 @@ impl From<orchard::note::Nullifier> for Nullifier {
 
 mod serialization {
     use super::*;
-    use crate::{proto::memwallet as proto, Error};
+    use crate::{
+        error::{read_array, read_enum},
+        proto::memwallet as proto,
+        Error,
+    };
 
     impl From<Nullifier> for proto::Nullifier {
         fn from(nullifier: Nullifier) -> Self {
@@ -82,17 +92,47 @@ mod serialization {
         type Error = Error;
 
         fn try_from(nullifier: proto::Nullifier) -> Result<Self, Self::Error> {
-            Ok(match nullifier.protocol() {
-                proto::ShieldedProtocol::Sapling => {
-                    Nullifier::Sapling(sapling::Nullifier::from_slice(&nullifier.nullifier)?)
-                }
+            Ok(match read_enum("protocol", nullifier.protocol)? {
+                proto::ShieldedProtocol::Sapling => Nullifier::Sapling(sapling::Nullifier(
+                    read_array("nullifier", nullifier.nullifier)?,
+                )),
                 #[cfg(feature = "orchard")]
                 proto::ShieldedProtocol::Orchard => Nullifier::Orchard(
-                    orchard::note::Nullifier::from_bytes(&nullifier.nullifier.try_into()?)
-                        .into_option()
-                        .ok_or(Error::CorruptedData("Invalid Orchard nullifier".into()))?,
+                    orchard::note::Nullifier::from_bytes(&read_array(
+                        "nullifier",
+                        nullifier.nullifier,
+                    )?)
+                    .into_option()
+                    .ok_or(Error::NonCanonicalEncoding("nullifier"))?,
                 ),
+                #[cfg(not(feature = "orchard"))]
+                proto::ShieldedProtocol::Orchard => return Err(Error::OrchardNotEnabled),
             })
         }
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn nullifier_roundtrip() {
+            let nf = Nullifier::Sapling(sapling::Nullifier([7; 32]));
+            let recovered = Nullifier::try_from(proto::Nullifier::from(nf)).unwrap();
+            assert_eq!(nf, recovered);
+        }
+
+        #[cfg(not(feature = "orchard"))]
+        #[test]
+        fn orchard_nullifier_requires_orchard_feature() {
+            let nf = proto::Nullifier {
+                protocol: proto::ShieldedProtocol::Orchard.into(),
+                nullifier: vec![0; 32],
+            };
+            assert!(matches!(
+                Nullifier::try_from(nf),
+                Err(Error::OrchardNotEnabled)
+            ));
+        }
+    }
 }
@@ -1,4 +1,9 @@
-use std::ops::{Deref, DerefMut, Range};
+use std::{
+    cmp::{max, min},
+    collections::BTreeMap,
+    num::NonZeroU32,
+    ops::{Deref, DerefMut, Range},
+};
 
 use zcash_client_backend::data_api::scanning::{
     spanning_tree::SpanningTree, ScanPriority, ScanRange,
@@ -17,25 +22,45 @@ impl ScanQueue {
         ScanQueue(Vec::new())
     }
 
-    pub(crate) fn suggest_scan_ranges(&self, min_priority: ScanPriority) -> Vec<ScanRange> {
-        let mut priorities: Vec<_> = self
+    /// Returns the ranges in the queue with at least the given priority, in descending order
+    /// of priority and ascending order of height within each priority.
+    ///
+    /// Adjacent ranges of equal priority are coalesced, and the result is then split so that
+    /// no returned range is longer than `max_range_len` blocks.
+    pub(crate) fn suggest_scan_ranges(
+        &self,
+        min_priority: ScanPriority,
+        max_range_len: NonZeroU32,
+    ) -> Vec<ScanRange> {
+        let mut queue = self.clone();
+        queue.coalesce();
+        let mut priorities: Vec<_> = queue
             .0
-            .iter()
+            .into_iter()
             .filter(|(_, _, p)| *p >= min_priority)
             .collect();
         priorities.sort_by(|(_, _, a), (_, _, b)| b.cmp(a));
 
         priorities
             .into_iter()
-            .map(|(start, end, priority)| {
-                let range = Range {
-                    start: *start,
-                    end: *end,
-                };
-                ScanRange::from_parts(range, *priority)
+            .flat_map(|(start, end, priority)| {
+                let mut chunks = vec![];
+                let mut chunk_start = start;
+                while chunk_start < end {
+                    let chunk_end = min(
+                        end,
+                        BlockHeight::from(
+                            u32::from(chunk_start).saturating_add(max_range_len.get()),
+                        ),
+                    );
+                    chunks.push(ScanRange::from_parts(chunk_start..chunk_end, priority));
+                    chunk_start = chunk_end;
+                }
+                chunks
             })
             .collect()
     }
+
     fn insert_queue_entries<'a>(
         &mut self,
         entries: impl Iterator<Item = &'a ScanRange>,
@@ -128,6 +153,89 @@ impl ScanQueue {
             let scan_ranges = tree.into_vec();
             self.insert_queue_entries(scan_ranges.iter())?;
         }
+        self.coalesce();
+        Ok(())
+    }
+
+    /// Sorts the queue by start height, and merges any entries of equal priority that overlap
+    /// or are adjacent, so that repeated insertions of similar ranges do not grow the queue.
+    pub(crate) fn coalesce(&mut self) {
+        self.0.retain(|(start, end, _)| start < end);
+        self.0.sort_by_key(|(start, _, _)| *start);
+        let mut coalesced: Vec<(BlockHeight, BlockHeight, ScanPriority)> =
+            Vec::with_capacity(self.0.len());
+        for (start, end, priority) in self.0.drain(..) {
+            match coalesced.last_mut() {
+                Some((_, last_end, last_priority))
+                    if *last_priority == priority && start <= *last_end =>
+                {
+                    *last_end = (*last_end).max(end);
+                }
+                _ => coalesced.push((start, end, priority)),
+            }
+        }
+        self.0 = coalesced;
+    }
+
+    /// Checks that every entry in the queue is non-empty, that no two entries overlap, and that
+    /// no two entries with the same priority are adjacent.
+    pub(crate) fn check_consistency(&self) -> Result<(), Error> {
+        let mut entries = self.0.clone();
+        entries.sort_by_key(|(start, _, _)| *start);
+        for (start, end, _) in &entries {
+            if start >= end {
+                return Err(Error::InvalidScanRange(
+                    *start,
+                    *end,
+                    "start must be less than end".to_string(),
+                ));
+            }
+        }
+        for pair in entries.windows(2) {
+            let ((_, prev_end, prev_priority), (start, end, priority)) = (pair[0], pair[1]);
+            if start < prev_end {
+                return Err(Error::InvalidScanRange(
+                    start,
+                    end,
+                    "range overlaps the preceding range".to_string(),
+                ));
+            }
+            if start == prev_end && priority == prev_priority {
+                return Err(Error::InvalidScanRange(
+                    start,
+                    end,
+                    "range is adjacent to a preceding range of the same priority".to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that every height in the given range is covered by an entry in the queue.
+    pub(crate) fn check_coverage(&self, range: &Range<BlockHeight>) -> Result<(), Error> {
+        let mut entries = self.0.clone();
+        entries.sort_by_key(|(start, _, _)| *start);
+        let mut cursor = range.start;
+        for (start, end, _) in entries {
+            if cursor >= range.end {
+                break;
+            }
+            if start > cursor {
+                return Err(Error::InvalidScanRange(
+                    cursor,
+                    min(start, range.end),
+                    "range is not covered by the scan queue".to_string(),
+                ));
+            }
+            cursor = max(cursor, end);
+        }
+        if cursor < range.end {
+            return Err(Error::InvalidScanRange(
+                cursor,
+                range.end,
+                "range is not covered by the scan queue".to_string(),
+            ));
+        }
         Ok(())
     }
 
@@ -158,10 +266,103 @@ impl DerefMut for ScanQueue {
     }
 }
 
+/// The set of block heights that the wallet has scanned, stored as disjoint, non-adjacent
+/// ranges keyed by their start heights. Each range is end-exclusive.
+///
+/// This is maintained independently of the wallet's block records, so that whether a block has
+/// been scanned does not depend upon its record having been retained.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct ScannedHeights(BTreeMap<BlockHeight, BlockHeight>);
+
+impl ScannedHeights {
+    pub(crate) fn new() -> Self {
+        ScannedHeights(BTreeMap::new())
+    }
+
+    /// Records the given range of heights as scanned, merging it with any scanned ranges that
+    /// it overlaps or adjoins.
+    pub(crate) fn insert(&mut self, range: Range<BlockHeight>) {
+        if range.is_empty() {
+            return;
+        }
+        let (mut start, mut end) = (range.start, range.end);
+        let merged = self
+            .0
+            .range(..=end)
+            .rev()
+            .take_while(|(_, range_end)| **range_end >= start)
+            .map(|(range_start, range_end)| (*range_start, *range_end))
+            .collect::<Vec<_>>();
+        for (range_start, range_end) in merged {
+            self.0.remove(&range_start);
+            start = min(start, range_start);
+            end = max(end, range_end);
+        }
+        self.0.insert(start, end);
+    }
+
+    /// Returns the scanned range containing the given height, if the height has been scanned.
+    pub(crate) fn range_containing(&self, height: BlockHeight) -> Option<Range<BlockHeight>> {
+        self.0
+            .range(..=height)
+            .next_back()
+            .filter(|(_, end)| height < **end)
+            .map(|(start, end)| *start..*end)
+    }
+
+    /// Returns whether the block at the given height has been scanned.
+    pub(crate) fn contains(&self, height: BlockHeight) -> bool {
+        self.range_containing(height).is_some()
+    }
+
+    /// Returns the greatest height that has been scanned.
+    pub(crate) fn max_height(&self) -> Option<BlockHeight> {
+        self.0.values().next_back().map(|end| *end - 1)
+    }
+
+    /// Removes all heights greater than the given height.
+    pub(crate) fn truncate(&mut self, height: BlockHeight) {
+        self.0.retain(|start, _| *start <= height);
+        if let Some(end) = self.0.values_mut().next_back() {
+            *end = min(*end, height + 1);
+        }
+    }
+
+    /// Returns the scanned ranges in ascending order.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = Range<BlockHeight>> + '_ {
+        self.0.iter().map(|(start, end)| *start..*end)
+    }
+}
+
+impl FromIterator<Range<BlockHeight>> for ScannedHeights {
+    fn from_iter<I: IntoIterator<Item = Range<BlockHeight>>>(iter: I) -> Self {
+        let mut heights = ScannedHeights::new();
+        for range in iter {
+            heights.insert(range);
+        }
+        heights
+    }
+}
+
 mod serialization {
     use super::*;
     use crate::proto::memwallet as proto;
 
+    impl From<Range<BlockHeight>> for proto::ScannedRangeRecord {
+        fn from(range: Range<BlockHeight>) -> Self {
+            Self {
+                start_height: range.start.into(),
+                end_height: range.end.into(),
+            }
+        }
+    }
+
+    impl From<proto::ScannedRangeRecord> for Range<BlockHeight> {
+        fn from(record: proto::ScannedRangeRecord) -> Self {
+            record.start_height.into()..record.end_height.into()
+        }
+    }
+
     impl From<(BlockHeight, BlockHeight, ScanPriority)> for proto::ScanQueueRecord {
         fn from(
             (start_height, end_height, priority): (BlockHeight, BlockHeight, ScanPriority),
@@ -200,3 +401,119 @@ mod serialization {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+
+    use zcash_client_backend::data_api::scanning::{ScanPriority, ScanRange};
+    use zcash_primitives::consensus::BlockHeight;
+
+    use super::{ScanQueue, ScannedHeights};
+
+    #[test]
+    fn overlapping_found_note_ranges_are_coalesced() {
+        let height = BlockHeight::from_u32;
+        let mut queue = ScanQueue(vec![(height(1000), height(2000), ScanPriority::Historic)]);
+
+        // Successive scans that find notes in the same shards each request nearly the same
+        // range of blocks to be scanned.
+        for i in 0..1000 {
+            let found = ScanRange::from_parts(
+                height(1100 + i % 7)..height(1200 + i % 13),
+                ScanPriority::FoundNote,
+            );
+            let query_range = found.block_range().clone();
+            queue
+                .replace_queue_entries(&query_range, Some(found).into_iter(), false)
+                .unwrap();
+            queue.check_consistency().unwrap();
+        }
+
+        assert_eq!(
+            queue.0,
+            vec![
+                (height(1000), height(1100), ScanPriority::Historic),
+                (height(1100), height(1212), ScanPriority::FoundNote),
+                (height(1212), height(2000), ScanPriority::Historic),
+            ]
+        );
+        assert_eq!(
+            queue.suggest_scan_ranges(ScanPriority::Historic, NonZeroU32::new(10000).unwrap()),
+            vec![
+                ScanRange::from_parts(height(1100)..height(1212), ScanPriority::FoundNote),
+                ScanRange::from_parts(height(1000)..height(1100), ScanPriority::Historic),
+                ScanRange::from_parts(height(1212)..height(2000), ScanPriority::Historic),
+            ]
+        );
+    }
+
+    #[test]
+    fn adjacent_ranges_of_equal_priority_are_inconsistent() {
+        let height = BlockHeight::from_u32;
+        let mut queue = ScanQueue(vec![
+            (height(10), height(20), ScanPriority::Historic),
+            (height(1), height(10), ScanPriority::Historic),
+        ]);
+        assert!(queue.check_consistency().is_err());
+
+        queue.coalesce();
+        assert_eq!(
+            queue.0,
+            vec![(height(1), height(20), ScanPriority::Historic)]
+        );
+        queue.check_consistency().unwrap();
+    }
+
+    #[test]
+    fn gaps_in_coverage_are_detected() {
+        let height = BlockHeight::from_u32;
+        let queue = ScanQueue(vec![
+            (height(30), height(40), ScanPriority::ChainTip),
+            (height(10), height(20), ScanPriority::Historic),
+            (height(20), height(25), ScanPriority::Scanned),
+        ]);
+        queue.check_consistency().unwrap();
+
+        queue.check_coverage(&(height(10)..height(25))).unwrap();
+        queue.check_coverage(&(height(12)..height(22))).unwrap();
+        assert!(queue.check_coverage(&(height(5)..height(25))).is_err());
+        assert!(queue.check_coverage(&(height(10)..height(40))).is_err());
+        assert!(queue.check_coverage(&(height(30)..height(41))).is_err());
+    }
+
+    #[test]
+    fn scanned_heights_are_merged_and_truncated() {
+        let height = BlockHeight::from_u32;
+        let mut scanned = ScannedHeights::new();
+        scanned.insert(height(10)..height(20));
+        scanned.insert(height(30)..height(40));
+        // Adjoining ranges are merged.
+        scanned.insert(height(20)..height(25));
+        // A range overlapping several others replaces them.
+        scanned.insert(height(5)..height(12));
+        assert_eq!(
+            scanned.iter().collect::<Vec<_>>(),
+            vec![height(5)..height(25), height(30)..height(40)]
+        );
+        assert!(scanned.contains(height(24)));
+        assert!(!scanned.contains(height(25)));
+        assert_eq!(
+            scanned.range_containing(height(35)),
+            Some(height(30)..height(40))
+        );
+
+        scanned.insert(height(22)..height(31));
+        assert_eq!(
+            scanned.iter().collect::<Vec<_>>(),
+            vec![height(5)..height(40)]
+        );
+
+        scanned.insert(height(50)..height(60));
+        scanned.truncate(height(34));
+        assert_eq!(
+            scanned.iter().collect::<Vec<_>>(),
+            vec![height(5)..height(35)]
+        );
+    }
+}
@@ -13,15 +13,29 @@ use zcash_protocol::value::Zatoshis;
 use crate::error::Error;
 use crate::AccountId;
 
-/// Maps a block height and transaction index to a transaction ID.
+/// Maps a block height and transaction index to a transaction ID, and each transaction ID back
+/// to its location.
 #[derive(Debug, Clone, PartialEq)]
-pub(crate) struct TxLocatorMap(pub(crate) BTreeMap<(BlockHeight, u32), TxId>);
+pub(crate) struct TxLocatorMap {
+    locations: BTreeMap<(BlockHeight, u32), TxId>,
+    txids: BTreeMap<TxId, (BlockHeight, u32)>,
+}
 
 impl Deref for TxLocatorMap {
     type Target = BTreeMap<(BlockHeight, u32), TxId>;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.locations
+    }
+}
+
+impl FromIterator<((BlockHeight, u32), TxId)> for TxLocatorMap {
+    fn from_iter<I: IntoIterator<Item = ((BlockHeight, u32), TxId)>>(iter: I) -> Self {
+        let mut map = Self::new();
+        for ((height, index), txid) in iter {
+            map.insert(height, index, txid);
+        }
+        map
     }
 }
 
@@ -41,19 +55,26 @@ pub(crate) struct TransactionEntry {
     ///   will only be set for transactions created using this wallet specifically, and not any
     ///   other wallet that uses the same seed (including previous installations of the same
     ///   wallet application.)
-    _target_height: Option<BlockHeight>,
+    target_height: Option<BlockHeight>,
+    /// - `stored_at_chain_tip`: the wallet's view of the chain tip when this wallet stored the
+    ///   transaction for sending, for diagnostics.
+    stored_at_chain_tip: Option<BlockHeight>,
 }
 impl TransactionEntry {
-    pub fn new_from_tx_meta(tx_meta: WalletTx<AccountId>, height: BlockHeight) -> Self {
-        Self {
+    pub fn new_from_tx_meta(
+        tx_meta: WalletTx<AccountId>,
+        height: BlockHeight,
+    ) -> Result<Self, Error> {
+        Ok(Self {
             tx_status: TransactionStatus::Mined(height),
-            tx_index: Some(tx_meta.block_index() as u32),
+            tx_index: Some(u32::try_from(tx_meta.block_index())?),
             block: Some(height),
             expiry_height: None,
             raw: None,
             fee: None,
-            _target_height: None,
-        }
+            target_height: None,
+            stored_at_chain_tip: None,
+        })
     }
     pub(crate) fn expiry_height(&self) -> Option<BlockHeight> {
         self.expiry_height
@@ -69,15 +90,117 @@ impl TransactionEntry {
         }
     }
 
-    #[cfg(test)]
     pub(crate) fn fee(&self) -> Option<Zatoshis> {
         self.fee
     }
 
+    #[cfg(any(feature = "diagnostics", feature = "transparent-inputs"))]
+    pub(crate) fn target_height(&self) -> Option<BlockHeight> {
+        self.target_height
+    }
+
+    /// Returns the height and index within its block at which this transaction was mined, if
+    /// both are known.
+    pub(crate) fn mined_location(&self) -> Option<(BlockHeight, u32)> {
+        self.mined_height().zip(self.tx_index)
+    }
+
     pub(crate) fn raw(&self) -> Option<&[u8]> {
         self.raw.as_deref()
     }
 
+    pub(crate) fn stored_at_chain_tip(&self) -> Option<BlockHeight> {
+        self.stored_at_chain_tip
+    }
+
+    /// Returns `true` if this transaction was constructed by this wallet.
+    pub(crate) fn is_wallet_created(&self) -> bool {
+        self.target_height.is_some()
+    }
+
+    /// Combines the information known about this transaction with that recorded by another
+    /// snapshot of the same wallet. A mined status takes precedence over an unmined one, and
+    /// known values take precedence over unknown ones.
+    pub(crate) fn merge(&mut self, other: TransactionEntry) {
+        fn status_rank(status: &TransactionStatus) -> u8 {
+            match status {
+                TransactionStatus::TxidNotRecognized => 0,
+                TransactionStatus::NotInMainChain => 1,
+                TransactionStatus::Mined(_) => 2,
+            }
+        }
+        if status_rank(&other.tx_status) > status_rank(&self.tx_status) {
+            self.tx_status = other.tx_status;
+            self.block = other.block;
+            self.tx_index = other.tx_index;
+        }
+        self.block = self.block.or(other.block);
+        self.tx_index = self.tx_index.or(other.tx_index);
+        self.expiry_height = self.expiry_height.or(other.expiry_height);
+        self.raw = self.raw.take().or(other.raw);
+        self.fee = self.fee.or(other.fee);
+        self.target_height = self.target_height.or(other.target_height);
+        self.stored_at_chain_tip = self.stored_at_chain_tip.or(other.stored_at_chain_tip);
+    }
+
+    /// Returns `true` if this transaction was created by this wallet, has not been mined, and
+    /// has an expiry height below the given height, meaning that it can never be mined.
+    pub(crate) fn is_invalidated_wallet_tx(&self, height: BlockHeight) -> bool {
+        self.target_height.is_some()
+            && !matches!(self.tx_status, TransactionStatus::Mined(_))
+            && self.expiry_height.map_or(false, |expiry_height| {
+                expiry_height > BlockHeight::from(0) && expiry_height < height
+            })
+    }
+
+    /// Returns `true` if this transaction has not been mined and has an expiry height at or
+    /// below the given height, meaning that it can no longer be mined once the wallet has
+    /// scanned up to that height.
+    pub(crate) fn is_expired_unmined(&self, height: BlockHeight) -> bool {
+        !matches!(self.tx_status, TransactionStatus::Mined(_))
+            && self.expiry_height.map_or(false, |expiry_height| {
+                expiry_height > BlockHeight::from(0) && expiry_height <= height
+            })
+    }
+
+    /// Returns `true` if this transaction has not been mined, and cannot expire or has not
+    /// expired as of the given height, meaning that it may still be mined at that height.
+    pub(crate) fn is_unmined_and_unexpired_at(&self, height: BlockHeight) -> bool {
+        !matches!(self.tx_status, TransactionStatus::Mined(_))
+            && self.expiry_height.map_or(false, |expiry_height| {
+                u32::from(expiry_height) == 0 || expiry_height >= height
+            })
+    }
+
+    /// Returns `true` if the outputs of this transaction count toward a balance computed as of
+    /// `summary_height`. This is the case if the transaction was mined at or below that height,
+    /// or if the caller has requested zero-conf funds by passing a summary height above the
+    /// chain tip, and the transaction cannot expire or has not expired as of that height.
+    pub(crate) fn counts_toward_balance_at(
+        &self,
+        summary_height: BlockHeight,
+        chain_tip_height: BlockHeight,
+    ) -> bool {
+        match self.tx_status {
+            TransactionStatus::Mined(tx_height) => tx_height <= summary_height,
+            _ => {
+                summary_height > chain_tip_height
+                    && self.expiry_height.map_or(false, |expiry_height| {
+                        u32::from(expiry_height) == 0 || expiry_height >= summary_height
+                    })
+            }
+        }
+    }
+
+    /// Returns `true` if this transaction spends its inputs for the purposes of computing a
+    /// balance: it has been mined, cannot expire, or has not expired as of `expiry_height`.
+    pub(crate) fn is_mined_or_unexpired_spend(&self, expiry_height: BlockHeight) -> bool {
+        matches!(self.tx_status, TransactionStatus::Mined(_))
+            || self.expiry_height.map_or(false, |tx_expiry_height| {
+                u32::from(tx_expiry_height) == 0 || tx_expiry_height >= expiry_height
+            })
+    }
+
     pub(crate) fn is_mined_or_unexpired_at(&self, height: BlockHeight) -> bool {
         match self.tx_status {
             TransactionStatus::Mined(tx_height) => tx_height <= height,
@@ -89,7 +212,7 @@ impl TransactionEntry {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub(crate) struct TransactionTable(pub(crate) BTreeMap<TxId, TransactionEntry>);
 
 impl TransactionTable {
@@ -118,16 +241,22 @@ impl TransactionTable {
 
     /// Inserts information about a MINED transaction that was observed to
     /// contain a note related to this wallet
-    pub(crate) fn put_tx_meta(&mut self, tx_meta: WalletTx<AccountId>, height: BlockHeight) {
+    pub(crate) fn put_tx_meta(
+        &mut self,
+        tx_meta: WalletTx<AccountId>,
+        height: BlockHeight,
+    ) -> Result<(), Error> {
         match self.0.entry(tx_meta.txid()) {
             Entry::Occupied(mut entry) => {
-                entry.get_mut().tx_index = Some(tx_meta.block_index() as u32);
+                entry.get_mut().tx_index = Some(u32::try_from(tx_meta.block_index())?);
+                entry.get_mut().block = Some(height);
                 entry.get_mut().tx_status = TransactionStatus::Mined(height);
             }
             Entry::Vacant(entry) => {
-                entry.insert(TransactionEntry::new_from_tx_meta(tx_meta, height));
+                entry.insert(TransactionEntry::new_from_tx_meta(tx_meta, height)?);
             }
         }
+        Ok(())
     }
 
     #[cfg(feature = "transparent-inputs")]
@@ -157,7 +286,8 @@ impl TransactionTable {
                     expiry_height: None,
                     raw: None,
                     fee: None,
-                    _target_height: None,
+                    target_height: None,
+                    stored_at_chain_tip: None,
                 });
             }
         }
@@ -172,7 +302,9 @@ impl TransactionTable {
     ) {
         match self.0.entry(tx.txid()) {
             Entry::Occupied(mut entry) => {
-                entry.get_mut().fee = fee;
+                // A fee recorded when the transaction was created or derived when it was
+                // first stored is retained if the caller does not know it.
+                entry.get_mut().fee = fee.or(entry.get().fee);
                 entry.get_mut().expiry_height = Some(tx.expiry_height());
 
                 let mut raw = Vec::new();
@@ -189,12 +321,55 @@ impl TransactionTable {
                     expiry_height: Some(tx.expiry_height()),
                     raw: Some(raw),
                     fee,
-                    _target_height: target_height,
+                    target_height: target_height,
+                    stored_at_chain_tip: None,
                 });
             }
         }
     }
 
+    /// Records the wallet's view of the chain tip at the time that it stored the given
+    /// transaction for sending.
+    pub(crate) fn set_stored_at_chain_tip(&mut self, txid: &TxId, chain_tip: Option<BlockHeight>) {
+        if let Some(entry) = self.0.get_mut(txid) {
+            entry.stored_at_chain_tip = chain_tip;
+        }
+    }
+
+    /// Records the fee paid by the given transaction, if the transaction is known.
+    pub(crate) fn set_fee(&mut self, txid: &TxId, fee: Zatoshis) {
+        if let Some(entry) = self.0.get_mut(txid) {
+            entry.fee = Some(fee);
+        }
+    }
+
+    /// Records that a known transaction was mined at the given height and index within its
+    /// block, unless it is already known to have been mined at a different height. A
+    /// transaction that is known to have been mined at this height, such as one whose status
+    /// was set from enhancement data, has its location filled in. Returns whether the entry
+    /// was updated.
+    pub(crate) fn backfill_mined_location(
+        &mut self,
+        txid: &TxId,
+        height: BlockHeight,
+        index: u32,
+    ) -> bool {
+        match self.0.get_mut(txid) {
+            Some(entry)
+                if entry.mined_height().map_or(true, |h| h == height)
+                    && (entry.mined_height().is_none()
+                        || entry.block != Some(height)
+                        || entry.tx_index != Some(index)) =>
+            {
+                entry.tx_status = TransactionStatus::Mined(height);
+                entry.block = Some(height);
+                entry.tx_index = Some(index);
+                true
+            }
+            _ => false,
+        }
+    }
+
     pub(crate) fn set_transaction_status(
         &mut self,
         txid: &TxId,
@@ -202,22 +377,48 @@ impl TransactionTable {
     ) -> Result<(), Error> {
         if let Some(entry) = self.0.get_mut(txid) {
             entry.tx_status = status;
+            // A transaction that is not mined has no location in the chain.
+            if !matches!(status, TransactionStatus::Mined(_)) {
+                entry.block = None;
+                entry.tx_index = None;
+            }
             Ok(())
         } else {
             Err(Error::TransactionNotFound(*txid))
         }
     }
 
-    pub(crate) fn unmine_transactions_greater_than(&mut self, height: BlockHeight) {
-        self.0.iter_mut().for_each(|(_, entry)| {
+    /// Merges the transactions from another snapshot of the same wallet into this table,
+    /// returning the number of transactions that were not previously known.
+    pub(crate) fn merge(&mut self, other: TransactionTable) -> usize {
+        let mut added = 0;
+        for (txid, other_entry) in other.0 {
+            match self.0.entry(txid) {
+                Entry::Occupied(mut entry) => entry.get_mut().merge(other_entry),
+                Entry::Vacant(entry) => {
+                    entry.insert(other_entry);
+                    added += 1;
+                }
+            }
+        }
+        added
+    }
+
+    /// Marks the transactions mined above the given height as not in the main chain, and
+    /// returns their IDs.
+    pub(crate) fn unmine_transactions_greater_than(&mut self, height: BlockHeight) -> Vec<TxId> {
+        let mut unmined = vec![];
+        self.0.iter_mut().for_each(|(txid, entry)| {
             if let TransactionStatus::Mined(tx_height) = entry.tx_status {
                 if tx_height > height {
                     entry.tx_status = TransactionStatus::NotInMainChain;
                     entry.block = None;
                     entry.tx_index = None;
+                    unmined.push(*txid);
                 }
             }
         });
+        unmined
     }
 }
 
@@ -245,17 +446,62 @@ impl Deref for TransactionTable {
 
 impl TxLocatorMap {
     pub(crate) fn new() -> Self {
-        Self(BTreeMap::new())
+        Self {
+            locations: BTreeMap::new(),
+            txids: BTreeMap::new(),
+        }
     }
-    pub(crate) fn _insert(&mut self, height: BlockHeight, index: u32, txid: TxId) {
-        self.0.insert((height, index), txid);
+
+    /// Records the location of a transaction, replacing any transaction previously recorded
+    /// at that location.
+    pub(crate) fn insert(&mut self, height: BlockHeight, index: u32, txid: TxId) {
+        if let Some(replaced) = self.locations.insert((height, index), txid) {
+            if self.txids.get(&replaced) == Some(&(height, index)) {
+                self.txids.remove(&replaced);
+            }
+        }
+        self.txids.insert(txid, (height, index));
+    }
+
+    /// Records the location of a transaction found by scanning. Locating a transaction again
+    /// at the same location has no effect, but a different transaction may not be located
+    /// where one has already been.
+    pub(crate) fn locate(
+        &mut self,
+        height: BlockHeight,
+        index: u32,
+        txid: TxId,
+    ) -> Result<(), Error> {
+        match self.locations.get(&(height, index)) {
+            Some(located) if *located == txid => Ok(()),
+            Some(_) => Err(Error::ConflictingTxLocator),
+            None => {
+                self.insert(height, index, txid);
+                Ok(())
+            }
+        }
     }
 
     pub(crate) fn get(&self, height: BlockHeight, index: u32) -> Option<&TxId> {
-        self.0.get(&(height, index))
+        self.locations.get(&(height, index))
     }
-    pub(crate) fn entry(&mut self, k: (BlockHeight, u32)) -> Entry<(BlockHeight, u32), TxId> {
-        self.0.entry(k)
+
+    /// Returns the block height and index within the block of the given transaction, if it
+    /// has been located by scanning.
+    pub(crate) fn find(&self, txid: &TxId) -> Option<(BlockHeight, u32)> {
+        self.txids.get(txid).copied()
+    }
+
+    /// Retains only the locations for which `f` returns `true`.
+    pub(crate) fn retain(&mut self, mut f: impl FnMut(&(BlockHeight, u32), &TxId) -> bool) {
+        self.locations.retain(|location, txid| f(location, txid));
+        self.txids
+            .retain(|txid, location| self.locations.get(location) == Some(txid));
+    }
+
+    /// Removes the locations of transactions in blocks above the given height.
+    pub(crate) fn truncate(&mut self, height: BlockHeight) {
+        self.retain(|(locator_height, _), _| *locator_height <= height);
     }
 }
 
@@ -280,7 +526,8 @@ mod serialization {
                 expiry_height: entry.expiry_height.map(Into::into),
                 raw_tx: entry.raw,
                 fee: entry.fee.map(Into::into),
-                target_height: entry._target_height.map(Into::into),
+                target_height: entry.target_height.map(Into::into),
+                stored_at_chain_tip: entry.stored_at_chain_tip.map(Into::into),
                 mined_height: match entry.tx_status {
                     TransactionStatus::Mined(height) => Some(height.into()),
                     _ => None,
@@ -308,8 +555,88 @@ mod serialization {
                 expiry_height: entry.expiry_height.map(Into::into),
                 raw: entry.raw_tx,
                 fee: entry.fee.map(|fee| fee.try_into()).transpose()?,
-                _target_height: entry.target_height.map(Into::into),
+                target_height: entry.target_height.map(Into::into),
+                stored_at_chain_tip: entry.stored_at_chain_tip.map(Into::into),
             })
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use zcash_client_backend::data_api::TransactionStatus;
+    use zcash_primitives::{
+        consensus::{BlockHeight, BranchId},
+        transaction::{Authorized, Transaction, TransactionData, TxId, TxVersion},
+    };
+
+    use super::{TransactionTable, TxLocatorMap};
+    use crate::error::Error;
+
+    fn tx(expiry_height: u32) -> Transaction {
+        TransactionData::<Authorized>::from_parts(
+            TxVersion::Zip225,
+            BranchId::Nu5,
+            0,
+            BlockHeight::from_u32(expiry_height),
+            None,
+            None,
+            None,
+            None,
+        )
+        .freeze()
+        .unwrap()
+    }
+
+    #[test]
+    fn unmined_transaction_expires_once_expiry_height_is_scanned() {
+        let mut table = TransactionTable::new();
+        let expiring = tx(100);
+        let unexpiring = tx(0);
+        table.put_tx_data(&expiring, None, None);
+        table.put_tx_data(&unexpiring, None, None);
+
+        let height = BlockHeight::from_u32;
+        let expiring_entry = table.get(&expiring.txid()).unwrap();
+        assert!(!expiring_entry.is_expired_unmined(height(99)));
+        assert!(expiring_entry.is_expired_unmined(height(100)));
+        assert!(!table
+            .get(&unexpiring.txid())
+            .unwrap()
+            .is_expired_unmined(height(1000)));
+
+        // A mined transaction is never reported as expired.
+        table
+            .set_transaction_status(&expiring.txid(), TransactionStatus::Mined(height(95)))
+            .unwrap();
+        assert!(!table
+            .get(&expiring.txid())
+            .unwrap()
+            .is_expired_unmined(height(1000)));
+    }
+
+    #[test]
+    fn locator_reverse_index_follows_updates() {
+        let (a, b) = (TxId::from_bytes([1; 32]), TxId::from_bytes([2; 32]));
+        let h = BlockHeight::from_u32(100);
+        let mut locator = TxLocatorMap::new();
+
+        locator.locate(h, 0, a).unwrap();
+        locator.locate(h, 0, a).unwrap();
+        assert!(matches!(
+            locator.locate(h, 0, b),
+            Err(Error::ConflictingTxLocator)
+        ));
+        locator.locate(h + 1, 0, b).unwrap();
+        assert_eq!(locator.find(&a), Some((h, 0)));
+        assert_eq!(locator.find(&b), Some((h + 1, 0)));
+
+        locator.truncate(h);
+        assert_eq!(locator.find(&b), None);
+        assert_eq!(locator.get(h + 1, 0), None);
+
+        locator.insert(h, 0, b);
+        assert_eq!(locator.find(&a), None);
+        assert_eq!(locator.find(&b), Some((h, 0)));
+    }
+}
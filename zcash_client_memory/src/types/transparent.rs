@@ -1,5 +1,5 @@
 use std::{
-    collections::{BTreeMap, BTreeSet},
+    collections::{btree_map::Entry, BTreeMap, BTreeSet},
     ops::{Deref, DerefMut},
 };
 
@@ -29,6 +29,25 @@ impl TransparentReceivedOutputs {
         self.0.get(outpoint)
     }
 
+    /// Merges the outputs from another snapshot of the same wallet into this set, retaining
+    /// the most recent height at which each output was observed to be unspent.
+    pub(crate) fn merge(&mut self, other: TransparentReceivedOutputs) {
+        for (outpoint, output) in other.0 {
+            match self.0.entry(outpoint) {
+                Entry::Occupied(mut entry) => {
+                    if output.max_observed_unspent_height > entry.get().max_observed_unspent_height
+                    {
+                        entry.get_mut().max_observed_unspent_height =
+                            output.max_observed_unspent_height;
+                    }
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(output);
+                }
+            }
+        }
+    }
+
     pub fn detect_spending_accounts<'a>(
         &self,
         spent: impl Iterator<Item = &'a OutPoint>,
@@ -58,7 +77,7 @@ impl DerefMut for TransparentReceivedOutputs {
 }
 
 /// A junction table between received transparent outputs and the transactions that spend them.
-#[derive(Debug, Default, PartialEq)]
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct TransparentReceivedOutputSpends(pub(crate) BTreeMap<OutPoint, TxId>);
 
 impl TransparentReceivedOutputSpends {
@@ -108,14 +127,14 @@ impl ReceivedTransparentOutput {
         account_id: AccountId,
         address: TransparentAddress,
         txout: TxOut,
-        max_observed_unspent_height: BlockHeight,
+        max_observed_unspent_height: Option<BlockHeight>,
     ) -> Self {
         Self {
             transaction_id,
             account_id,
             address,
             txout,
-            max_observed_unspent_height: Some(max_observed_unspent_height),
+            max_observed_unspent_height,
         }
     }
 
@@ -133,7 +152,7 @@ impl ReceivedTransparentOutput {
 ///
 /// Output may be attempted to be spent in multiple transactions, even though only one will ever be mined
 /// which is why can cannot just rely on TransparentReceivedOutputSpends or implement this as as map
-#[derive(Debug, Default, PartialEq)]
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct TransparentSpendCache(pub(crate) BTreeSet<(TxId, OutPoint)>);
 
 impl TransparentSpendCache {
@@ -177,12 +196,26 @@ mod serialization {
     impl TryFrom<proto::ReceivedTransparentOutput> for ReceivedTransparentOutput {
         type Error = crate::Error;
 
+        /// Rejects outputs whose script does not pay to the recorded address, as the rest of the
+        /// wallet assumes that the two agree.
         fn try_from(output: proto::ReceivedTransparentOutput) -> Result<Self, Self::Error> {
+            let address = TransparentAddress::decode(&EncodingParams, &output.address)?;
+            let txout = TxOut::try_from(read_optional!(output, txout)?)?;
+            let script_address = txout
+                .recipient_address()
+                .ok_or_else(|| Error::UnrecognizedTransparentScript(txout.script_pubkey.clone()))?;
+            if script_address != address {
+                return Err(Error::TransparentAddressScriptMismatch {
+                    address,
+                    script_address,
+                });
+            }
+
             Ok(Self {
                 transaction_id: TxId::from_bytes(output.transaction_id.clone().try_into()?),
                 account_id: output.account_id.into(),
-                address: TransparentAddress::decode(&EncodingParams, &output.address)?,
-                txout: read_optional!(output, txout)?.try_into()?,
+                address,
+                txout,
                 max_observed_unspent_height: output.max_observed_unspent_height.map(|h| h.into()),
             })
         }
@@ -203,8 +236,90 @@ mod serialization {
         fn try_from(txout: proto::TxOut) -> Result<Self, Self::Error> {
             Ok(Self {
                 script_pubkey: Script(txout.script),
-                value: Zatoshis::try_from(txout.value)?,
+                value: Zatoshis::from_u64(txout.value)
+                    .map_err(|_| Error::InvalidTransparentValue(txout.value))?,
             })
         }
     }
+
+    #[cfg(test)]
+    mod tests {
+        use zcash_protocol::value::MAX_MONEY;
+
+        use super::*;
+
+        fn output(
+            address: &TransparentAddress,
+            script: Script,
+            value: u64,
+        ) -> proto::ReceivedTransparentOutput {
+            proto::ReceivedTransparentOutput {
+                transaction_id: vec![1; 32],
+                account_id: 0,
+                address: address.encode(&EncodingParams),
+                txout: Some(proto::TxOut {
+                    value,
+                    script: script.0,
+                }),
+                max_observed_unspent_height: Some(100),
+            }
+        }
+
+        #[test]
+        fn proto_roundtrip_received_transparent_output() {
+            let address = TransparentAddress::PublicKeyHash([7; 20]);
+            let proto = output(&address, address.script(), MAX_MONEY);
+            let decoded = ReceivedTransparentOutput::try_from(proto.clone()).unwrap();
+            assert_eq!(decoded.address, address);
+            assert_eq!(proto::ReceivedTransparentOutput::from(decoded), proto);
+        }
+
+        #[test]
+        fn oversized_value_is_rejected() {
+            let address = TransparentAddress::PublicKeyHash([7; 20]);
+            let proto = output(&address, address.script(), MAX_MONEY + 1);
+            assert!(matches!(
+                ReceivedTransparentOutput::try_from(proto),
+                Err(Error::InvalidTransparentValue(value)) if value == MAX_MONEY + 1
+            ));
+        }
+
+        #[test]
+        fn unknown_script_kind_is_rejected() {
+            let address = TransparentAddress::PublicKeyHash([7; 20]);
+            // An OP_RETURN output, which does not pay to any address.
+            let script = Script(vec![0x6a, 0x01, 0x00]);
+            let proto = output(&address, script.clone(), 1000);
+            assert!(matches!(
+                ReceivedTransparentOutput::try_from(proto),
+                Err(Error::UnrecognizedTransparentScript(s)) if s == script
+            ));
+        }
+
+        #[test]
+        fn address_script_mismatch_is_rejected() {
+            let address = TransparentAddress::PublicKeyHash([7; 20]);
+            let script_address = TransparentAddress::ScriptHash([7; 20]);
+            let proto = output(&address, script_address.script(), 1000);
+            assert!(matches!(
+                ReceivedTransparentOutput::try_from(proto),
+                Err(Error::TransparentAddressScriptMismatch {
+                    address: a,
+                    script_address: s,
+                }) if a == address && s == script_address
+            ));
+        }
+
+        #[test]
+        fn short_outpoint_hash_is_rejected() {
+            let outpoint = proto::OutPoint {
+                hash: vec![0; 31],
+                n: 0,
+            };
+            assert!(matches!(
+                OutPoint::try_from(outpoint),
+                Err(Error::InvalidOutPointHash(31))
+            ));
+        }
+    }
 }
@@ -8,6 +8,7 @@ use std::{
 use nonempty::NonEmpty;
 use secrecy::{ExposeSecret, SecretVec};
 use shardtree::store::ShardStore as _;
+use subtle::ConstantTimeEq;
 use zcash_client_backend::data_api::{
     scanning::ScanRange, BlockMetadata, NullifierQuery, WalletRead, WalletSummary,
 };
@@ -25,23 +26,31 @@ use zcash_keys::keys::UnifiedIncomingViewingKey;
 use zcash_primitives::{
     block::BlockHash,
     consensus::BlockHeight,
-    transaction::{Transaction, TransactionData, TxId},
+    transaction::{Transaction, TxId},
 };
 use zcash_protocol::{
     consensus::{self, BranchId},
     memo::Memo,
     value::Zatoshis,
+    ShieldedProtocol,
 };
 use zip32::fingerprint::SeedFingerprint;
 use zip32::Scope;
 
 #[cfg(feature = "transparent-inputs")]
 use {
+    std::cmp::min,
     zcash_client_backend::wallet::TransparentAddressMetadata,
-    zcash_primitives::legacy::TransparentAddress,
+    zcash_primitives::legacy::{keys::NonHardenedChildIndex, TransparentAddress},
+    zcash_primitives::transaction::builder::DEFAULT_TX_EXPIRY_DELTA,
 };
 
-use crate::{error::Error, Account, AccountId, MemoryWalletBlock, MemoryWalletDb, Nullifier};
+use crate::{
+    error::Error, preferred_memo, Account, AccountId, MemoryWalletBlock, MemoryWalletDb, Nullifier,
+};
+
+#[cfg(feature = "metrics")]
+use crate::types::wallet_metrics;
 
 impl<P: consensus::Parameters> WalletRead for MemoryWalletDb<P> {
     type Error = Error;
@@ -181,6 +190,9 @@ impl<P: consensus::Parameters> WalletRead for MemoryWalletDb<P> {
         }))
     }
 
+    /// Returns the Unified Address at the highest diversifier index that has been generated
+    /// for the given account. An account that has never recorded an address returns its
+    /// default address.
     fn get_current_address(
         &self,
         account: Self::AccountId,
@@ -233,28 +245,30 @@ impl<P: consensus::Parameters> WalletRead for MemoryWalletDb<P> {
             .map(|(_id, account)| (account.account_id(), AccountBalance::ZERO))
             .collect::<HashMap<AccountId, AccountBalance>>();
 
+        let summary_height = self
+            .summary_height(min_confirmations)?
+            .expect("The chain tip height is known.");
+        let unscanned_ranges = self.unscanned_ranges();
+
         for note in self.get_received_notes().iter() {
+            let note_tx = self
+                .tx_table
+                .get(&note.txid())
+                .ok_or_else(|| Error::TransactionNotFound(note.txid()))?;
+            let received_height = note_tx.mined_height();
+
+            // don't count notes in unmined transactions that have expired
+            if received_height.is_none()
+                && note_tx
+                    .expiry_height()
+                    .map_or(false, |h| h != BlockHeight::from(0) && h < summary_height)
+            {
+                continue;
+            }
             // don't count spent notes
             if self.note_is_spent(note, min_confirmations)? {
                 continue;
             }
-            // don't count notes in unscanned ranges
-            let unscanned_ranges = self.unscanned_ranges();
-            for (_, _, start_position, end_position_exclusive) in unscanned_ranges {
-                if note.commitment_tree_position >= start_position
-                    && note.commitment_tree_position < end_position_exclusive
-                {
-                    continue; // note is in an unscanned range. Skip it
-                }
-            }
-            // don't count notes in unmined transactions or that have expired
-            if let Ok(Some(note_tx)) = self.get_transaction(note.txid) {
-                if note_tx.expiry_height()
-                    < self.summary_height(min_confirmations)?.unwrap_or(0.into())
-                {
-                    continue;
-                }
-            }
 
             let account_id = note.account_id();
             // if this is the first note for this account add a new balance record
@@ -265,16 +279,29 @@ impl<P: consensus::Parameters> WalletRead for MemoryWalletDb<P> {
                 .get_mut(&account_id)
                 .expect("Account balance should exist");
 
-            // Given a note update the balance for the account
-            // This includes determining if it is change, spendable, etc
+            // A note is spendable once it has been mined at or below the summary height,
+            // has a known position, and does not fall within an unscanned range. Change
+            // that has not yet been mined at that depth (including change created by
+            // transactions that the wallet has constructed but not yet seen mined) is
+            // pending change; all remaining value is pending spendability.
+            let is_spendable = received_height.iter().any(|h| h <= &summary_height)
+                && note.commitment_tree_position.is_some()
+                && !self.note_in_unscanned_range(
+                    note,
+                    &unscanned_ranges,
+                    birthday_height,
+                    summary_height,
+                );
+            let is_pending_change =
+                note.is_change && received_height.iter().all(|h| h > &summary_height);
+
             let update_balance_with_note = |b: &mut Balance| -> Result<(), Error> {
-                match (
-                    self.note_is_pending(note, min_confirmations)?,
-                    note.is_change,
-                ) {
-                    (true, true) => b.add_pending_change_value(note.note.value()),
-                    (true, false) => b.add_pending_spendable_value(note.note.value()),
-                    (false, _) => b.add_spendable_value(note.note.value()),
+                if is_spendable {
+                    b.add_spendable_value(note.note.value())
+                } else if is_pending_change {
+                    b.add_pending_change_value(note.note.value())
+                } else {
+                    b.add_pending_spendable_value(note.note.value())
                 }?;
                 Ok(())
             };
@@ -291,13 +318,7 @@ impl<P: consensus::Parameters> WalletRead for MemoryWalletDb<P> {
         }
 
         #[cfg(feature = "transparent-inputs")]
-        for (account, balance) in account_balances.iter_mut() {
-            let transparent_balances =
-                self.get_transparent_balances(*account, fully_scanned_height)?;
-            for (_, value) in transparent_balances {
-                balance.add_unshielded_value(value)?;
-            }
-        }
+        self.add_transparent_account_balances(chain_tip_height, &mut account_balances)?;
 
         let next_sapling_subtree_index = self
             .sapling_tree
@@ -314,34 +335,50 @@ impl<P: consensus::Parameters> WalletRead for MemoryWalletDb<P> {
             .map(|s| s.root_addr().index())
             .unwrap_or(0);
 
-        // Treat Sapling and Orchard outputs as having the same cost to scan.
-        let sapling_scan_progress =
-            self.sapling_scan_progress(&birthday_height, &fully_scanned_height, &chain_tip_height)?;
+        let recover_until_height = self.recover_until_height();
+        let sapling_progress = self.scan_progress(
+            ShieldedProtocol::Sapling,
+            birthday_height,
+            recover_until_height,
+            fully_scanned_height,
+            chain_tip_height,
+        )?;
         #[cfg(feature = "orchard")]
-        let orchard_scan_progress =
-            self.orchard_scan_progress(&birthday_height, &fully_scanned_height, &chain_tip_height)?;
+        let orchard_progress = self.scan_progress(
+            ShieldedProtocol::Orchard,
+            birthday_height,
+            recover_until_height,
+            fully_scanned_height,
+            chain_tip_height,
+        )?;
         #[cfg(not(feature = "orchard"))]
-        let orchard_scan_progress: Option<Ratio<u64>> = None;
-
-        let scan_progress = sapling_scan_progress
-            .zip(orchard_scan_progress)
-            .map(|(s, o)| {
-                Ratio::new(
-                    s.numerator() + o.numerator(),
-                    s.denominator() + o.denominator(),
-                )
-            })
-            .or(sapling_scan_progress)
-            .or(orchard_scan_progress);
+        let orchard_progress: Option<Progress> = None;
 
-        // TODO: This won't work
-        let scan_progress = Progress::new(scan_progress.unwrap(), Some(Ratio::new(0, 0)));
+        // Treat Sapling and Orchard outputs as having the same cost to scan.
+        let sum = |a: Ratio<u64>, b: Ratio<u64>| {
+            Ratio::new(
+                a.numerator() + b.numerator(),
+                a.denominator() + b.denominator(),
+            )
+        };
+        let progress = match (sapling_progress, orchard_progress) {
+            (Some(s), Some(o)) => Progress::new(
+                sum(s.scan(), o.scan()),
+                s.recovery()
+                    .zip(o.recovery())
+                    .map(|(s, o)| sum(s, o))
+                    .or_else(|| s.recovery())
+                    .or_else(|| o.recovery()),
+            ),
+            (Some(p), None) | (None, Some(p)) => p,
+            (None, None) => return Ok(None),
+        };
 
         let summary = WalletSummary::new(
             account_balances,
             chain_tip_height,
             fully_scanned_height,
-            scan_progress,
+            progress,
             next_sapling_subtree_index,
             #[cfg(feature = "orchard")]
             next_orchard_subtree_index,
@@ -394,45 +431,19 @@ impl<P: consensus::Parameters> WalletRead for MemoryWalletDb<P> {
     fn block_fully_scanned(&self) -> Result<Option<BlockMetadata>, Self::Error> {
         tracing::debug!("block_fully_scanned");
         if let Some(birthday_height) = self.get_wallet_birthday()? {
-            // We assume that the only way we get a contiguous range of block heights in the `blocks` table
-            // starting with the birthday block, is if all scanning operations have been performed on those
-            // blocks. This holds because the `blocks` table is only altered by `WalletDb::put_blocks` via
-            // `put_block`, and the effective combination of intra-range linear scanning and the nullifier
-            // map ensures that we discover all wallet-related information within the contiguous range.
+            // The intra-range linear scanning and the nullifier map ensure that we discover all
+            // wallet-related information within a contiguous range of scanned blocks. The
+            // fully-scanned height is therefore the last height of the contiguous scanned range
+            // that includes the wallet birthday; if the birthday has not been scanned, there is
+            // an unscanned range between the birthday and any scanned block, so there is no
+            // fully-scanned height.
             //
-            // We also assume that every contiguous range of block heights in the `blocks` table has a
-            // single matching entry in the `scan_queue` table with priority "Scanned". This requires no
-            // bugs in the scan queue update logic, which we have had before. However, a bug here would
-            // mean that we return a more conservative fully-scanned height, which likely just causes a
-            // performance regression.
-            //
-            // The fully-scanned height is therefore the last height that falls within the first range in
-            // the scan queue with priority "Scanned".
-            // SQL query problems.
-
-            let mut scanned_ranges: Vec<_> = self
-                .scan_queue
-                .iter()
-                .filter(|(_, _, p)| p == &ScanPriority::Scanned)
-                .collect();
-            scanned_ranges.sort_by(|(start_a, _, _), (start_b, _, _)| start_a.cmp(start_b));
-            if let Some(fully_scanned_height) = scanned_ranges.first().and_then(
-                |(block_range_start, block_range_end, _priority)| {
-                    // If the start of the earliest scanned range is greater than
-                    // the birthday height, then there is an unscanned range between
-                    // the wallet birthday and that range, so there is no fully
-                    // scanned height.
-                    if *block_range_start <= birthday_height {
-                        // Scan ranges are end-exclusive.
-                        Some(*block_range_end - 1)
-                    } else {
-                        None
-                    }
-                },
-            ) {
-                self.block_metadata(fully_scanned_height)
-            } else {
-                Ok(None)
+            // Scanned heights are recorded independently of the block records, so that this
+            // does not depend upon the records of earlier blocks having been retained.
+            match self.scanned_heights.range_containing(birthday_height) {
+                // Scanned ranges are end-exclusive.
+                Some(range) => self.block_metadata(range.end - 1),
+                None => Ok(None),
             }
         } else {
             Ok(None)
@@ -459,7 +470,10 @@ impl<P: consensus::Parameters> WalletRead for MemoryWalletDb<P> {
 
     fn suggest_scan_ranges(&self) -> Result<Vec<ScanRange>, Self::Error> {
         tracing::debug!("suggest_scan_ranges");
-        Ok(self.scan_queue.suggest_scan_ranges(ScanPriority::Historic))
+        Ok(self.prioritize_in_progress_scan(
+            self.scan_queue
+                .suggest_scan_ranges(ScanPriority::Historic, self.max_scan_range_len),
+        ))
     }
 
     fn get_target_and_anchor_heights(
@@ -467,20 +481,10 @@ impl<P: consensus::Parameters> WalletRead for MemoryWalletDb<P> {
         min_confirmations: NonZeroU32,
     ) -> Result<Option<(BlockHeight, BlockHeight)>, Self::Error> {
         if let Some(chain_tip_height) = self.chain_height()? {
-            let sapling_anchor_height =
-                self.get_sapling_max_checkpointed_height(chain_tip_height, min_confirmations)?;
-
-            #[cfg(feature = "orchard")]
-            let orchard_anchor_height =
-                self.get_orchard_max_checkpointed_height(chain_tip_height, min_confirmations)?;
-            #[cfg(not(feature = "orchard"))]
-            let orchard_anchor_height: Option<BlockHeight> = None;
-
-            let anchor_height = sapling_anchor_height
-                .zip(orchard_anchor_height)
-                .map(|(s, o)| std::cmp::min(s, o))
-                .or(sapling_anchor_height)
-                .or(orchard_anchor_height);
+            // Both pools must have a checkpoint at the anchor height, so that a proposal
+            // spending notes from either pool can be witnessed at that anchor.
+            let anchor_height =
+                self.get_max_checkpointed_height(chain_tip_height, min_confirmations)?;
 
             Ok(anchor_height.map(|h| (chain_tip_height + 1, h)))
         } else {
@@ -497,6 +501,11 @@ impl<P: consensus::Parameters> WalletRead for MemoryWalletDb<P> {
         }
     }
 
+    /// Returns the unified full viewing key of every account in the wallet.
+    ///
+    /// Accounts for which the wallet holds only an incoming viewing key have no UFVK, and are
+    /// omitted from the result rather than causing an error. Every account that this wallet
+    /// can currently import holds a UFVK, so no account is omitted in practice.
     fn get_unified_full_viewing_keys(
         &self,
     ) -> Result<HashMap<Self::AccountId, UnifiedFullViewingKey>, Self::Error> {
@@ -511,73 +520,54 @@ impl<P: consensus::Parameters> WalletRead for MemoryWalletDb<P> {
     fn get_memo(&self, id_note: NoteId) -> Result<Option<Memo>, Self::Error> {
         tracing::debug!("get_memo: {:?}", id_note);
         // look in both the received and sent notes
-        Ok(self
-            .get_received_note(id_note)
-            .map(|note| note.memo.clone())
-            .or_else(|| {
-                self.sent_notes
-                    .get_sent_note(&id_note)
-                    .map(|note| note.memo.clone())
-            }))
+        let received = self.get_received_note(id_note).map(|note| &note.memo);
+        let sent = self
+            .sent_notes
+            .get_sent_note(&id_note)
+            .map(|note| &note.memo);
+        Ok(preferred_memo(received, sent))
     }
 
     fn get_transaction(&self, txid: TxId) -> Result<Option<Transaction>, Self::Error> {
         tracing::debug!("get_transaction: {:?}", txid);
-        self.tx_table
-            .get(&txid)
-            .map(|tx| (tx.status(), tx.expiry_height(), tx.raw()))
-            .map(|(status, expiry_height, raw)| {
-                let raw = raw.ok_or_else(|| {
-                    Self::Error::CorruptedData("Transaction raw data not found".to_string())
-                })?;
-
-                // We need to provide a consensus branch ID so that pre-v5 `Transaction` structs
-                // (which don't commit directly to one) can store it internally.
-                // - If the transaction is mined, we use the block height to get the correct one.
-                // - If the transaction is unmined and has a cached non-zero expiry height, we use
-                //   that (relying on the invariant that a transaction can't be mined across a network
-                //   upgrade boundary, so the expiry height must be in the same epoch).
-                // - Otherwise, we use a placeholder for the initial transaction parse (as the
-                //   consensus branch ID is not used there), and then either use its non-zero expiry
-                //   height or return an error.
-                if let TransactionStatus::Mined(height) = status {
-                    return Ok(Transaction::read(
-                        raw,
-                        BranchId::for_height(&self.params, height),
-                    )?);
-                }
-                if let Some(height) = expiry_height.filter(|h| h > &BlockHeight::from(0)) {
-                    return Ok(Transaction::read(
-                        raw,
-                        BranchId::for_height(&self.params, height),
-                    )?);
-                }
+        let Some(tx) = self.tx_table.get(&txid) else {
+            return Ok(None);
+        };
+        let raw = tx
+            .raw()
+            .ok_or(Self::Error::TransactionRawDataMissing(txid))?;
 
-                let tx_data = Transaction::read(raw, BranchId::Sprout)
-                    .map_err(Self::Error::from)?
-                    .into_data();
-
-                let expiry_height = tx_data.expiry_height();
-                if expiry_height > BlockHeight::from(0) {
-                    Ok(TransactionData::from_parts(
-                        tx_data.version(),
-                        BranchId::for_height(&self.params, expiry_height),
-                        tx_data.lock_time(),
-                        expiry_height,
-                        tx_data.transparent_bundle().cloned(),
-                        tx_data.sprout_bundle().cloned(),
-                        tx_data.sapling_bundle().cloned(),
-                        tx_data.orchard_bundle().cloned(),
-                    )
-                        .freeze()?)
-                } else {
-                    Err(Self::Error::CorruptedData(
-                        "Consensus branch ID not known, cannot parse this transaction until it is mined"
-                            .to_string(),
-                    ))
+        // We need to provide a consensus branch ID so that pre-v5 `Transaction` structs
+        // (which don't commit directly to one) can store it internally.
+        // - If the transaction is mined, we use the block height to get the correct one.
+        // - If the transaction is unmined and has a non-zero expiry height, we use that
+        //   (relying on the invariant that a transaction can't be mined across a network
+        //   upgrade boundary, so the expiry height must be in the same epoch). If the expiry
+        //   height was not cached, we use a placeholder for an initial parse to obtain it, as
+        //   the consensus branch ID is not used there.
+        // - Otherwise, we use the height of the next block to be mined, as that is the
+        //   earliest height at which the transaction could now be mined.
+        let height = match tx.status() {
+            TransactionStatus::Mined(height) => height,
+            _ => match tx.expiry_height().filter(|h| h > &BlockHeight::from(0)) {
+                Some(height) => height,
+                None => {
+                    let expiry_height = Transaction::read(raw, BranchId::Sprout)?.expiry_height();
+                    if expiry_height > BlockHeight::from(0) {
+                        expiry_height
+                    } else {
+                        self.chain_height()?
+                            .ok_or(Self::Error::ChainHeightUnknown)?
+                            + 1
+                    }
                 }
-            })
-            .transpose()
+            },
+        };
+
+        Ok(Some(Transaction::read(
+            raw,
+            BranchId::for_height(&self.params, height),
+        )?))
     }
 
     fn get_sapling_nullifiers(
@@ -585,34 +575,11 @@ impl<P: consensus::Parameters> WalletRead for MemoryWalletDb<P> {
         query: NullifierQuery,
     ) -> Result<Vec<(Self::AccountId, sapling::Nullifier)>, Self::Error> {
         tracing::debug!("get_sapling_nullifiers");
-        let nullifiers = self.received_notes.get_sapling_nullifiers();
-        Ok(match query {
-            NullifierQuery::All => nullifiers
-                .map(|(account_id, _, nf)| (account_id, nf))
-                .collect(),
-            NullifierQuery::Unspent => nullifiers
-                .filter_map(|(account_id, _, nf)| {
-                    // find any tx we know of that spends this nullifier and if so require that it is unmined or expired
-                    if let Some((height, tx_index)) = self.nullifiers.get(&Nullifier::Sapling(nf)) {
-                        if let Some(spending_tx) =
-                            self.tx_table.get_by_height_and_index(*height, *tx_index)
-                        {
-                            if matches!(spending_tx.status(), TransactionStatus::Mined(_))
-                                || spending_tx.expiry_height().is_none()
-                            {
-                                None
-                            } else {
-                                Some((account_id, nf))
-                            }
-                        } else {
-                            None
-                        }
-                    } else {
-                        Some((account_id, nf))
-                    }
-                })
-                .collect(),
-        })
+        Ok(self.nullifiers_in_pool(query, |nf| match nf {
+            Nullifier::Sapling(nf) => Some(*nf),
+            #[cfg(feature = "orchard")]
+            _ => None,
+        }))
     }
 
     #[cfg(feature = "orchard")]
@@ -621,35 +588,12 @@ impl<P: consensus::Parameters> WalletRead for MemoryWalletDb<P> {
         query: NullifierQuery,
     ) -> Result<Vec<(Self::AccountId, orchard::note::Nullifier)>, Self::Error> {
         tracing::debug!("get_orchard_nullifiers");
-        let nullifiers = self.received_notes.get_orchard_nullifiers();
-        Ok(match query {
-            NullifierQuery::All => nullifiers
-                .map(|(account_id, _, nf)| (account_id, nf))
-                .collect(),
-            NullifierQuery::Unspent => nullifiers
-                .filter_map(|(account_id, _, nf)| {
-                    // find any tx we know of that spends this nullifier and if so require that it is unmined or expired
-                    if let Some((height, tx_index)) = self.nullifiers.get(&Nullifier::Orchard(nf)) {
-                        if let Some(spending_tx) =
-                            self.tx_table.get_by_height_and_index(*height, *tx_index)
-                        {
-                            if matches!(spending_tx.status(), TransactionStatus::Mined(_))
-                                || spending_tx.expiry_height().is_none()
-                            {
-                                None
-                            } else {
-                                Some((account_id, nf))
-                            }
-                        } else {
-                            None
-                        }
-                    } else {
-                        Some((account_id, nf))
-                    }
-                })
-                .collect(),
-        })
+        Ok(self.nullifiers_in_pool(query, |nf| match nf {
+            Nullifier::Orchard(nf) => Some(*nf),
+            _ => None,
+        }))
     }
+
     #[cfg(feature = "transparent-inputs")]
     fn get_known_ephemeral_addresses(
         &self,
@@ -671,30 +615,70 @@ impl<P: consensus::Parameters> WalletRead for MemoryWalletDb<P> {
             .collect::<Vec<_>>())
     }
 
+    /// Returns the transparent receivers of the unified addresses generated for the given
+    /// account, along with the account's legacy transparent address, as the sqlite backend does.
+    ///
+    /// A transparent receiver of an address whose diversifier index is not a valid
+    /// non-hardened child index cannot have been derived by this wallet, and is skipped.
     #[cfg(feature = "transparent-inputs")]
     fn get_transparent_receivers(
         &self,
         account_id: Self::AccountId,
     ) -> Result<HashMap<TransparentAddress, Option<TransparentAddressMetadata>>, Self::Error> {
+        tracing::debug!("get_transparent_receivers");
         let account = self
             .get_account(account_id)?
             .ok_or(Error::AccountUnknown(account_id))?;
 
-        let t_addresses = account
-            .addresses()
-            .iter()
-            .filter_map(|(diversifier_index, ua)| {
-                ua.transparent().map(|ta| {
-                    let metadata =
-                        zcash_primitives::legacy::keys::NonHardenedChildIndex::from_index(
-                            (*diversifier_index).try_into().unwrap(),
-                        )
-                        .map(|i| TransparentAddressMetadata::new(Scope::External.into(), i));
-                    (*ta, metadata)
-                })
-            })
-            .collect();
-        Ok(t_addresses)
+        let mut receivers = HashMap::new();
+        for (diversifier_index, ua) in account.addresses().iter() {
+            let Some(taddr) = ua.transparent() else {
+                continue;
+            };
+            let Some(address_index) = u32::try_from(*diversifier_index)
+                .ok()
+                .and_then(NonHardenedChildIndex::from_index)
+            else {
+                tracing::warn!(
+                    "Skipping transparent receiver of account {:?} at diversifier index {:?}, \
+                     which is not a valid non-hardened child index",
+                    account_id,
+                    diversifier_index
+                );
+                continue;
+            };
+            receivers.insert(
+                *taddr,
+                Some(TransparentAddressMetadata::new(
+                    Scope::External.into(),
+                    address_index,
+                )),
+            );
+        }
+
+        if let Some((taddr, address_index)) = account.get_legacy_transparent_address()? {
+            receivers.insert(
+                taddr,
+                Some(TransparentAddressMetadata::new(
+                    Scope::External.into(),
+                    address_index,
+                )),
+            );
+        }
+
+        Ok(receivers)
+    }
+
+    #[cfg(feature = "transparent-inputs")]
+    fn get_transparent_address_metadata(
+        &self,
+        account_id: Self::AccountId,
+        address: &TransparentAddress,
+    ) -> Result<Option<TransparentAddressMetadata>, Self::Error> {
+        tracing::debug!("get_transparent_address_metadata");
+        self.accounts.get(account_id).map_or(Ok(None), |account| {
+            account.transparent_address_metadata(address)
+        })
     }
 
     /// Returns a mapping from each transparent receiver associated with the specified account
@@ -711,6 +695,11 @@ impl<P: consensus::Parameters> WalletRead for MemoryWalletDb<P> {
     ) -> Result<HashMap<TransparentAddress, Zatoshis>, Self::Error> {
         tracing::debug!("get_transparent_balances");
 
+        let chain_tip_height = self.chain_height()?.ok_or(Error::ChainHeightUnknown)?;
+        // A spend by an unmined transaction is only disregarded once that transaction has
+        // expired as of both the summary height and the next block to be mined.
+        let spend_expiry_height = std::cmp::min(summary_height, chain_tip_height + 1);
+
         let mut balances = HashMap::new();
 
         for (outpoint, txo) in self.transparent_received_outputs.iter().filter(|(_, txo)| {
@@ -722,13 +711,17 @@ impl<P: consensus::Parameters> WalletRead for MemoryWalletDb<P> {
                 false
             }
         }) {
-            let tx = self
-                .tx_table
-                .get(&txo.transaction_id)
-                .ok_or(Error::TransactionNotFound(txo.transaction_id))?;
-            if tx.is_mined_or_unexpired_at(summary_height)
-                && self.utxo_is_spendable(outpoint, summary_height, 0)?
-            {
+            let Some(tx) = self.tx_table.get(&txo.transaction_id) else {
+                continue;
+            };
+            let spent = self
+                .transparent_received_output_spends
+                .get(outpoint)
+                .and_then(|txid| self.tx_table.get(txid))
+                .map_or(false, |spending_tx| {
+                    spending_tx.is_mined_or_unexpired_spend(spend_expiry_height)
+                });
+            if tx.counts_toward_balance_at(summary_height, chain_tip_height) && !spent {
                 let address = txo.address;
                 let balance = balances.entry(address).or_insert(Zatoshis::ZERO);
                 *balance = balance.add(txo.txout.value).expect("balance overflow");
@@ -740,15 +733,57 @@ impl<P: consensus::Parameters> WalletRead for MemoryWalletDb<P> {
 
     fn transaction_data_requests(&self) -> Result<Vec<TransactionDataRequest>, Self::Error> {
         tracing::debug!("transaction_data_requests");
-        Ok(self
+        #[allow(unused_mut)]
+        let mut requests: Vec<_> = self
             .transaction_data_request_queue
             .iter()
             .cloned()
-            .collect())
+            .collect();
+
+        #[cfg(feature = "transparent-inputs")]
+        {
+            // `lightwalletd` will return an error for `GetTaddressTxids` requests having an end
+            // height greater than the current chain tip height, so we take the chain tip height
+            // into account here.
+            let chain_tip_height = self.chain_height()?;
+            for outpoint in self.transaction_data_request_queue.spend_detections() {
+                let Some(txo) = self.transparent_received_outputs.get(outpoint) else {
+                    continue;
+                };
+                // We cannot construct an address-based request without a height at which to
+                // begin the search.
+                let Some(block_range_start) = self
+                    .tx_table
+                    .get(&txo.transaction_id)
+                    .and_then(|tx| tx.target_height().or_else(|| tx.mined_height()))
+                else {
+                    continue;
+                };
+                let max_end_height = block_range_start + DEFAULT_TX_EXPIRY_DELTA + 1;
+                let request = TransactionDataRequest::SpendsFromAddress {
+                    address: txo.address,
+                    block_range_start,
+                    block_range_end: Some(
+                        chain_tip_height.map_or(max_end_height, |h| min(h + 1, max_end_height)),
+                    ),
+                };
+                // Outputs of the same transaction to the same address are found by the same
+                // search.
+                if !requests.contains(&request) {
+                    requests.push(request);
+                }
+            }
+        }
+        #[cfg(feature = "metrics")]
+        wallet_metrics::record_data_requests(requests.len());
+        Ok(requests)
     }
 }
 
-/// Copied from zcash_client_sqlite::wallet::seed_matches_derived_account
+/// Adapted from zcash_client_sqlite::wallet::seed_matches_derived_account
+///
+/// Key material derived from the seed is held only in containers that are zeroized on
+/// drop, and is compared in constant time.
 fn seed_matches_derived_account<P: consensus::Parameters>(
     params: &P,
     seed: &SecretVec<u8>,
@@ -756,35 +791,784 @@ fn seed_matches_derived_account<P: consensus::Parameters>(
     account_index: zip32::AccountId,
     uivk: &UnifiedIncomingViewingKey,
 ) -> Result<bool, Error> {
-    let seed_fingerprint_match =
-        &SeedFingerprint::from_seed(seed.expose_secret()).ok_or_else(|| {
-            Error::BadAccountData("Seed must be between 32 and 252 bytes in length.".to_owned())
-        })? == seed_fingerprint;
+    let seed_fingerprint_match: bool = SeedFingerprint::from_seed(seed.expose_secret())
+        .ok_or(Error::InvalidSeedLength)?
+        .to_bytes()[..]
+        .ct_eq(&seed_fingerprint.to_bytes()[..])
+        .into();
 
-    // Keys are not comparable with `Eq`, but addresses are, so we derive what should
-    // be equivalent addresses for each key and use those to check for key equality.
+    // Keys are not comparable with `Eq`, so we compare the encodings of the derived and
+    // stored UIVKs instead.
     let uivk_match =
-        match UnifiedSpendingKey::from_seed(params, &seed.expose_secret()[..], account_index) {
+        match UnifiedSpendingKey::from_seed(params, seed.expose_secret(), account_index) {
             // If we can't derive a USK from the given seed with the account's ZIP 32
             // account index, then we immediately know the UIVK won't match because wallet
             // accounts are required to have a known UIVK.
             Err(_) => false,
             Ok(usk) => {
-                UnifiedAddressRequest::all().map_or(Ok::<_, Error>(false), |ua_request| {
-                    Ok(usk
-                        .to_unified_full_viewing_key()
-                        .default_address(ua_request)?
-                        == uivk.default_address(ua_request)?)
-                })?
+                let derived = SecretVec::new(
+                    usk.to_unified_full_viewing_key()
+                        .to_unified_incoming_viewing_key()
+                        .encode(params)
+                        .into_bytes(),
+                );
+                let stored = SecretVec::new(uivk.encode(params).into_bytes());
+                derived
+                    .expose_secret()
+                    .as_slice()
+                    .ct_eq(stored.expose_secret().as_slice())
+                    .into()
             }
         };
 
     if seed_fingerprint_match != uivk_match {
         // If these mismatch, it suggests database corruption.
-        Err(Error::CorruptedData(format!(
-            "Seed fingerprint match: {seed_fingerprint_match}, uivk match: {uivk_match}"
-        )))
+        Err(Error::CorruptedData(
+            "Seed fingerprint and derived viewing key disagree on account match".to_owned(),
+        ))
     } else {
         Ok(seed_fingerprint_match && uivk_match)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use secrecy::SecretVec;
+    use zcash_client_backend::data_api::{
+        Account as _, AccountBirthday, AccountPurpose, WalletRead, WalletWrite,
+    };
+    use zcash_primitives::{block::BlockHash, consensus::Network};
+
+    use crate::{error::Error, MemoryWalletDb};
+
+    fn birthday() -> AccountBirthday {
+        AccountBirthday::from_sapling_activation(&Network::TestNetwork, BlockHash([0; 32]))
+    }
+
+    #[test]
+    fn unified_full_viewing_keys_and_current_addresses() {
+        use zcash_client_backend::keys::{UnifiedAddressRequest, UnifiedFullViewingKey};
+
+        let mut wallet = MemoryWalletDb::new(Network::TestNetwork, 100);
+        let (account_a, usk_a) = wallet
+            .create_account(&SecretVec::new(vec![1u8; 32]), &birthday())
+            .unwrap();
+        let (account_b, usk_b) = wallet
+            .create_account(&SecretVec::new(vec![2u8; 32]), &birthday())
+            .unwrap();
+
+        let ufvks = wallet.get_unified_full_viewing_keys().unwrap();
+        assert_eq!(ufvks.len(), 2);
+        assert_eq!(
+            ufvks[&account_a].encode(&Network::TestNetwork),
+            usk_a
+                .to_unified_full_viewing_key()
+                .encode(&Network::TestNetwork)
+        );
+        assert_eq!(
+            ufvks[&account_b].encode(&Network::TestNetwork),
+            usk_b
+                .to_unified_full_viewing_key()
+                .encode(&Network::TestNetwork)
+        );
+
+        // Each account starts out at its default address.
+        let default_address = |ufvk: &UnifiedFullViewingKey| {
+            let request = ufvk
+                .to_unified_incoming_viewing_key()
+                .to_address_request()
+                .unwrap();
+            ufvk.default_address(request).unwrap().0
+        };
+        let default_a = wallet.get_current_address(account_a).unwrap().unwrap();
+        assert_eq!(default_a, default_address(&ufvks[&account_a]));
+
+        // Generating an address for one account advances only that account's current address.
+        let request = UnifiedAddressRequest::new(false, true, false).unwrap();
+        let next_a = wallet
+            .get_next_available_address(account_a, request)
+            .unwrap()
+            .unwrap();
+        assert_ne!(next_a, default_a);
+        assert_eq!(wallet.get_current_address(account_a).unwrap(), Some(next_a));
+        assert_eq!(
+            wallet.get_current_address(account_b).unwrap(),
+            Some(default_address(&ufvks[&account_b]))
+        );
+    }
+
+    #[test]
+    fn transactions_are_parsed_with_the_branch_at_their_height() {
+        use zcash_primitives::transaction::{Authorized, Transaction, TransactionData, TxVersion};
+        use zcash_protocol::consensus::{BlockHeight, BranchId};
+
+        fn tx(branch_id: BranchId, lock_time: u32, expiry_height: u32) -> Transaction {
+            TransactionData::<Authorized>::from_parts(
+                TxVersion::Sapling,
+                branch_id,
+                lock_time,
+                BlockHeight::from_u32(expiry_height),
+                None,
+                None,
+                None,
+                None,
+            )
+            .freeze()
+            .unwrap()
+        }
+
+        let mut wallet = MemoryWalletDb::new(Network::TestNetwork, 100);
+        let seed = SecretVec::new(vec![1u8; 32]);
+        wallet.create_account(&seed, &birthday()).unwrap();
+
+        // Sapling activates at height 280000 and Blossom at height 584000 on testnet.
+        let mined = tx(BranchId::Sapling, 0, 0);
+        let expiring = tx(BranchId::Sapling, 1, 300_000);
+        let pending = tx(BranchId::Blossom, 2, 0);
+        for tx in [&mined, &expiring, &pending] {
+            wallet.tx_table.put_tx_data(tx, None, None);
+        }
+        wallet
+            .tx_table
+            .put_tx_partial(&mined.txid(), &None, Some(BlockHeight::from_u32(290_000)));
+        let branch_id = |wallet: &MemoryWalletDb<Network>, tx: &Transaction| {
+            wallet
+                .get_transaction(tx.txid())
+                .map(|tx| tx.unwrap().consensus_branch_id())
+        };
+
+        assert_eq!(branch_id(&wallet, &mined).unwrap(), BranchId::Sapling);
+        assert_eq!(branch_id(&wallet, &expiring).unwrap(), BranchId::Sapling);
+
+        // A transaction without an expiry height cannot be parsed until the chain tip is known.
+        assert!(matches!(
+            branch_id(&wallet, &pending),
+            Err(Error::ChainHeightUnknown)
+        ));
+        wallet
+            .update_chain_tip(BlockHeight::from_u32(600_000))
+            .unwrap();
+        assert_eq!(branch_id(&wallet, &pending).unwrap(), BranchId::Blossom);
+
+        // A transaction known only by its txid has no raw data to parse.
+        let unknown = tx(BranchId::Sapling, 3, 0).txid();
+        wallet
+            .tx_table
+            .put_tx_partial(&unknown, &None, Some(BlockHeight::from_u32(290_000)));
+        assert!(matches!(
+            wallet.get_transaction(unknown),
+            Err(Error::TransactionRawDataMissing(txid)) if txid == unknown
+        ));
+        assert!(wallet
+            .get_transaction(tx(BranchId::Sapling, 4, 0).txid())
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn validate_seed_matching_seed() {
+        let mut wallet = MemoryWalletDb::new(Network::TestNetwork, 100);
+        let seed = SecretVec::new(vec![1u8; 32]);
+        let (account_id, _) = wallet.create_account(&seed, &birthday()).unwrap();
+
+        assert!(wallet.validate_seed(account_id, &seed).unwrap());
+    }
+
+    #[test]
+    fn validate_seed_non_matching_seed() {
+        let mut wallet = MemoryWalletDb::new(Network::TestNetwork, 100);
+        let seed = SecretVec::new(vec![1u8; 32]);
+        let (account_id, _) = wallet.create_account(&seed, &birthday()).unwrap();
+
+        let other_seed = SecretVec::new(vec![2u8; 32]);
+        assert!(!wallet.validate_seed(account_id, &other_seed).unwrap());
+    }
+
+    #[test]
+    fn validate_seed_wrong_length() {
+        let mut wallet = MemoryWalletDb::new(Network::TestNetwork, 100);
+        let seed = SecretVec::new(vec![1u8; 32]);
+        let (account_id, _) = wallet.create_account(&seed, &birthday()).unwrap();
+
+        let short_seed = SecretVec::new(vec![1u8; 16]);
+        assert!(matches!(
+            wallet.validate_seed(account_id, &short_seed),
+            Err(Error::InvalidSeedLength)
+        ));
+    }
+
+    #[test]
+    fn validate_seed_imported_account() {
+        let mut wallet = MemoryWalletDb::new(Network::TestNetwork, 100);
+        let seed = SecretVec::new(vec![1u8; 32]);
+        let (_, usk) = wallet.create_account(&seed, &birthday()).unwrap();
+
+        let mut imported_wallet = MemoryWalletDb::new(Network::TestNetwork, 100);
+        let account = imported_wallet
+            .import_account_ufvk(
+                &usk.to_unified_full_viewing_key(),
+                &birthday(),
+                AccountPurpose::Spending,
+            )
+            .unwrap();
+
+        assert!(matches!(
+            imported_wallet.validate_seed(account.id(), &seed),
+            Err(Error::UnknownZip32Derivation)
+        ));
+    }
+
+    #[test]
+    fn received_notes_are_attributed_to_their_account() {
+        use zcash_client_backend::{
+            data_api::testing::{AddressType, TestBuilder},
+            wallet::NoteId,
+        };
+        use zcash_primitives::transaction::components::amount::NonNegativeAmount;
+        use zcash_protocol::ShieldedProtocol;
+
+        use crate::{testing::TestMemDbFactory, MemBlockCache};
+
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let account = st.test_account().cloned().unwrap();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let (h, _, _) = st.generate_next_block(
+            &dfvk,
+            AddressType::DefaultExternal,
+            NonNegativeAmount::const_from_u64(50000),
+        );
+        st.scan_cached_blocks(h, 1);
+
+        let wallet = st.wallet();
+        let note_id = wallet.received_notes[0].note_id;
+        assert_eq!(
+            wallet.account_for_note(note_id).unwrap(),
+            Some(account.id())
+        );
+        assert!(wallet.is_wallet_output(
+            note_id.txid(),
+            note_id.protocol(),
+            note_id.output_index()
+        ));
+
+        // Outputs of the same transaction that the wallet did not receive are not attributed.
+        let other_output = NoteId::new(
+            *note_id.txid(),
+            ShieldedProtocol::Sapling,
+            note_id.output_index() + 1,
+        );
+        assert_eq!(wallet.account_for_note(other_output).unwrap(), None);
+        assert!(!wallet.is_wallet_output(
+            other_output.txid(),
+            other_output.protocol(),
+            other_output.output_index()
+        ));
+    }
+
+    #[test]
+    fn transparent_payment_counts_as_sent() {
+        use std::{convert::Infallible, num::NonZeroU32};
+
+        use zcash_client_backend::{
+            data_api::{
+                testing::{AddressType, TestBuilder},
+                WalletTest,
+            },
+            fees::StandardFeeRule,
+            wallet::OvkPolicy,
+        };
+        use zcash_keys::address::Address;
+        use zcash_primitives::{
+            legacy::TransparentAddress, transaction::components::amount::NonNegativeAmount,
+        };
+        use zcash_protocol::{value::ZatBalance, ShieldedProtocol};
+
+        use crate::{testing::TestMemDbFactory, MemBlockCache};
+
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let account = st.test_account().cloned().unwrap();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let (h, _, _) = st.generate_next_block(
+            &dfvk,
+            AddressType::DefaultExternal,
+            NonNegativeAmount::const_from_u64(60000),
+        );
+        st.scan_cached_blocks(h, 1);
+
+        let to = Address::Transparent(TransparentAddress::PublicKeyHash([7; 20]));
+        let value = NonNegativeAmount::const_from_u64(10000);
+        let proposal = st
+            .propose_standard_transfer::<Infallible>(
+                account.id(),
+                StandardFeeRule::Zip317,
+                NonZeroU32::new(1).unwrap(),
+                &to,
+                value,
+                None,
+                None,
+                ShieldedProtocol::Sapling,
+            )
+            .unwrap();
+        let txid = st
+            .create_proposed_transactions::<Infallible, _, Infallible>(
+                account.usk(),
+                OvkPolicy::Sender,
+                &proposal,
+            )
+            .unwrap()[0];
+
+        let history = st.wallet().get_tx_history().unwrap();
+        let tx = history.iter().find(|tx| tx.txid() == txid).unwrap();
+        let fee = tx.fee_paid().unwrap();
+        // The transparent payment is the only sent output; the shielded change is not.
+        assert_eq!(tx.sent_note_count(), 1);
+        assert!(tx.has_change());
+        assert_eq!(tx.spent_note_count(), 1);
+        assert_eq!(
+            tx.account_value_delta(),
+            -ZatBalance::from((value + fee).unwrap())
+        );
+    }
+
+    #[test]
+    fn memos_are_counted_in_history() {
+        use std::{convert::Infallible, num::NonZeroU32, str::FromStr};
+
+        use sapling::zip32::ExtendedSpendingKey;
+        use zcash_client_backend::{
+            data_api::{
+                testing::{AddressType, TestBuilder},
+                WalletTest,
+            },
+            fees::StandardFeeRule,
+            wallet::OvkPolicy,
+        };
+        use zcash_keys::address::Address;
+        use zcash_primitives::transaction::components::amount::NonNegativeAmount;
+        use zcash_protocol::{memo::Memo, ShieldedProtocol};
+
+        use crate::{testing::TestMemDbFactory, MemBlockCache};
+
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let account = st.test_account().cloned().unwrap();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let (h, _, _) = st.generate_next_block(
+            &dfvk,
+            AddressType::DefaultExternal,
+            NonNegativeAmount::const_from_u64(60000),
+        );
+        st.scan_cached_blocks(h, 1);
+
+        let to = Address::Sapling(ExtendedSpendingKey::master(&[]).default_address().1);
+        let memo = Memo::from_str("payment").unwrap();
+        let change_memo = Memo::from_str("change").unwrap();
+        let proposal = st
+            .propose_standard_transfer::<Infallible>(
+                account.id(),
+                StandardFeeRule::Zip317,
+                NonZeroU32::new(1).unwrap(),
+                &to,
+                NonNegativeAmount::const_from_u64(10000),
+                Some(memo.encode()),
+                Some(change_memo.encode()),
+                ShieldedProtocol::Sapling,
+            )
+            .unwrap();
+        let txid = st
+            .create_proposed_transactions::<Infallible, _, Infallible>(
+                account.usk(),
+                OvkPolicy::Sender,
+                &proposal,
+            )
+            .unwrap()[0];
+
+        let memo_count = |wallet: &MemoryWalletDb<_>| {
+            wallet
+                .get_tx_history()
+                .unwrap()
+                .into_iter()
+                .find(|tx| tx.txid() == txid)
+                .unwrap()
+                .memo_count()
+        };
+        // The payment memo and the change memo are both counted.
+        assert_eq!(memo_count(st.wallet()), 2);
+
+        // Scanning the mined transaction does not discard the change memo.
+        let (h, _) = st.generate_next_block_including(txid);
+        st.scan_cached_blocks(h, 1);
+        assert_eq!(memo_count(st.wallet()), 2);
+        let change = st
+            .wallet()
+            .received_notes
+            .iter()
+            .find(|note| note.txid() == txid && note.is_change)
+            .unwrap()
+            .note_id();
+        assert_eq!(st.wallet().get_memo(change).unwrap(), Some(change_memo));
+    }
+
+    #[cfg(feature = "orchard")]
+    #[test]
+    fn anchor_is_checkpointed_in_both_pools() {
+        use std::{convert::Infallible, num::NonZeroU32};
+
+        use sapling::zip32::ExtendedSpendingKey;
+        use shardtree::store::ShardStore;
+        use zcash_client_backend::{
+            data_api::testing::{AddressType, TestBuilder},
+            fees::StandardFeeRule,
+            wallet::OvkPolicy,
+        };
+        use zcash_keys::address::Address;
+        use zcash_primitives::transaction::components::amount::NonNegativeAmount;
+        use zcash_protocol::ShieldedProtocol;
+
+        use crate::{testing::TestMemDbFactory, MemBlockCache};
+
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let account = st.test_account().cloned().unwrap();
+        let sapling_fvk = st.test_account_sapling().unwrap().clone();
+        let orchard_fvk = st.test_account_orchard().unwrap().clone();
+        let not_our_key = ExtendedSpendingKey::master(&[]).to_diversifiable_full_viewing_key();
+
+        let value = NonNegativeAmount::const_from_u64(50000);
+        let (h, _, _) = st.generate_next_block(&sapling_fvk, AddressType::DefaultExternal, value);
+        st.generate_next_block(&orchard_fvk, AddressType::DefaultExternal, value);
+        st.generate_next_block(&not_our_key, AddressType::DefaultExternal, value);
+        st.generate_next_block(&not_our_key, AddressType::DefaultExternal, value);
+        st.scan_cached_blocks(h, 4);
+
+        // Leave the two most recent heights checkpointed in only one pool each, as happens
+        // when a synthetic checkpoint falls below a tree's minimum retained checkpoint.
+        st.wallet_mut()
+            .orchard_tree
+            .store_mut()
+            .remove_checkpoint(&(h + 3))
+            .unwrap();
+        st.wallet_mut()
+            .sapling_tree
+            .store_mut()
+            .remove_checkpoint(&(h + 2))
+            .unwrap();
+
+        let min_confirmations = NonZeroU32::new(1).unwrap();
+        assert_eq!(
+            st.wallet()
+                .get_target_and_anchor_heights(min_confirmations)
+                .unwrap(),
+            Some((h + 4, h + 1))
+        );
+
+        // Spending notes from both pools requires witnesses in both trees at the anchor.
+        let to = Address::Sapling(not_our_key.default_address().1);
+        let proposal = st
+            .propose_standard_transfer::<Infallible>(
+                account.id(),
+                StandardFeeRule::Zip317,
+                min_confirmations,
+                &to,
+                NonNegativeAmount::const_from_u64(70000),
+                None,
+                None,
+                ShieldedProtocol::Sapling,
+            )
+            .unwrap();
+        assert_eq!(
+            proposal
+                .steps()
+                .head
+                .shielded_inputs()
+                .map(|inputs| inputs.anchor_height()),
+            Some(h + 1)
+        );
+        assert!(st
+            .create_proposed_transactions::<Infallible, _, Infallible>(
+                account.usk(),
+                OvkPolicy::Sender,
+                &proposal,
+            )
+            .is_ok());
+    }
+
+    /// Returns a transaction without any bundles, distinguished from others by its lock time.
+    #[cfg(feature = "transparent-inputs")]
+    fn bare_tx(lock_time: u32, expiry_height: u32) -> zcash_primitives::transaction::Transaction {
+        use zcash_primitives::{
+            consensus::BranchId,
+            transaction::{Authorized, TransactionData, TxVersion},
+        };
+        use zcash_protocol::consensus::BlockHeight;
+
+        TransactionData::<Authorized>::from_parts(
+            TxVersion::Zip225,
+            BranchId::Nu5,
+            lock_time,
+            BlockHeight::from_u32(expiry_height),
+            None,
+            None,
+            None,
+            None,
+        )
+        .freeze()
+        .unwrap()
+    }
+
+    /// Returns a wallet with a single account, together with the account's transparent address.
+    #[cfg(feature = "transparent-inputs")]
+    fn transparent_wallet() -> (
+        MemoryWalletDb<Network>,
+        crate::AccountId,
+        zcash_primitives::legacy::TransparentAddress,
+    ) {
+        let mut wallet = MemoryWalletDb::new(Network::TestNetwork, 100);
+        let seed = SecretVec::new(vec![1u8; 32]);
+        let (account_id, _) = wallet.create_account(&seed, &birthday()).unwrap();
+        let taddr = *wallet
+            .get_current_address(account_id)
+            .unwrap()
+            .unwrap()
+            .transparent()
+            .unwrap();
+        (wallet, account_id, taddr)
+    }
+
+    /// Records the first output of `tx` as a receipt of `value` by the wallet's transparent
+    /// address, returning its outpoint.
+    #[cfg(feature = "transparent-inputs")]
+    fn receive_transparent(
+        wallet: &mut MemoryWalletDb<Network>,
+        tx: &zcash_primitives::transaction::Transaction,
+        mined_height: Option<zcash_protocol::consensus::BlockHeight>,
+        value: u64,
+    ) -> zcash_primitives::transaction::components::OutPoint {
+        use zcash_primitives::transaction::components::{OutPoint, TxOut};
+        use zcash_protocol::value::Zatoshis;
+
+        use crate::types::transparent::ReceivedTransparentOutput;
+
+        let account_id = wallet.get_account_ids().unwrap()[0];
+        let taddr = *wallet
+            .get_current_address(account_id)
+            .unwrap()
+            .unwrap()
+            .transparent()
+            .unwrap();
+        wallet.tx_table.put_tx_data(tx, None, None);
+        if mined_height.is_some() {
+            wallet
+                .tx_table
+                .put_tx_partial(&tx.txid(), &None, mined_height);
+        }
+        let outpoint = OutPoint::new(*tx.txid().as_ref(), 0);
+        wallet.transparent_received_outputs.0.insert(
+            outpoint.clone(),
+            ReceivedTransparentOutput::new(
+                tx.txid(),
+                account_id,
+                taddr,
+                TxOut {
+                    value: Zatoshis::const_from_u64(value),
+                    script_pubkey: taddr.script(),
+                },
+                mined_height,
+            ),
+        );
+        outpoint
+    }
+
+    #[test]
+    #[cfg(feature = "transparent-inputs")]
+    fn transparent_balances_respect_zero_conf_expiry() {
+        use zcash_protocol::consensus::BlockHeight;
+
+        let (mut wallet, account_id, taddr) = transparent_wallet();
+        let tip = birthday().height() + 100;
+        wallet.update_chain_tip(tip).unwrap();
+
+        receive_transparent(&mut wallet, &bare_tx(1, 0), Some(tip - 10), 1000);
+        // Unmined outputs only count toward zero-conf balances, while they are unexpired.
+        receive_transparent(&mut wallet, &bare_tx(2, 0), None, 2000);
+        receive_transparent(&mut wallet, &bare_tx(3, u32::from(tip) + 5), None, 4000);
+        // Spent by a transaction that expires at the chain tip.
+        let spent_by_expiring =
+            receive_transparent(&mut wallet, &bare_tx(4, 0), Some(tip - 10), 8000);
+        // Spent by a mined transaction.
+        let spent_by_mined =
+            receive_transparent(&mut wallet, &bare_tx(5, 0), Some(tip - 10), 16000);
+
+        let expiring_spend = bare_tx(6, u32::from(tip));
+        wallet.tx_table.put_tx_data(&expiring_spend, None, None);
+        wallet
+            .transparent_received_output_spends
+            .insert(spent_by_expiring, expiring_spend.txid());
+        let mined_spend = bare_tx(7, 0);
+        wallet.tx_table.put_tx_data(&mined_spend, None, None);
+        wallet
+            .tx_table
+            .put_tx_partial(&mined_spend.txid(), &None, Some(tip - 5));
+        wallet
+            .transparent_received_output_spends
+            .insert(spent_by_mined, mined_spend.txid());
+
+        let balance = |summary_height: BlockHeight| {
+            wallet
+                .get_transparent_balances(account_id, summary_height)
+                .unwrap()
+                .get(&taddr)
+                .map(|value| value.into_u64())
+        };
+        assert_eq!(balance(tip - 11), None);
+        assert_eq!(balance(tip), Some(1000));
+        assert_eq!(balance(tip + 1), Some(1000 + 2000 + 4000 + 8000));
+        assert_eq!(balance(tip + 5), Some(1000 + 2000 + 4000 + 8000));
+        assert_eq!(balance(tip + 6), Some(1000 + 2000 + 8000));
+    }
+
+    #[test]
+    #[cfg(feature = "transparent-inputs")]
+    fn summary_includes_unspent_transparent_outputs() {
+        use std::collections::HashMap;
+
+        let (mut wallet, account_id, _) = transparent_wallet();
+        let tip = birthday().height() + 100;
+
+        receive_transparent(&mut wallet, &bare_tx(1, 0), Some(tip - 10), 1000);
+        // An unmined output that cannot expire counts toward the balance...
+        receive_transparent(&mut wallet, &bare_tx(2, 0), None, 2000);
+        // ...but one whose transaction has expired as of the next block does not.
+        receive_transparent(&mut wallet, &bare_tx(3, u32::from(tip)), None, 4000);
+        // Nor does one spent by a transaction that has not expired.
+        let spent = receive_transparent(&mut wallet, &bare_tx(4, 0), Some(tip - 10), 8000);
+        let spend = bare_tx(5, u32::from(tip) + 1);
+        wallet.tx_table.put_tx_data(&spend, None, None);
+        wallet
+            .transparent_received_output_spends
+            .insert(spent, spend.txid());
+
+        let mut balances = HashMap::new();
+        wallet
+            .add_transparent_account_balances(tip, &mut balances)
+            .unwrap();
+        assert_eq!(balances[&account_id].unshielded().into_u64(), 1000 + 2000);
+    }
+
+    #[test]
+    #[cfg(feature = "transparent-inputs")]
+    fn transparent_receivers_include_legacy_address() {
+        let mut wallet = MemoryWalletDb::new(Network::TestNetwork, 100);
+        let seed = SecretVec::new(vec![1u8; 32]);
+        let (account_id, _) = wallet.create_account(&seed, &birthday()).unwrap();
+        let current = *wallet
+            .get_current_address(account_id)
+            .unwrap()
+            .unwrap()
+            .transparent()
+            .unwrap();
+        let (legacy, legacy_index) = wallet
+            .get_account(account_id)
+            .unwrap()
+            .unwrap()
+            .get_legacy_transparent_address()
+            .unwrap()
+            .unwrap();
+
+        let receivers = wallet.get_transparent_receivers(account_id).unwrap();
+        assert!(receivers[&current].is_some());
+        assert_eq!(
+            receivers[&legacy].as_ref().map(|m| m.address_index()),
+            Some(legacy_index)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "transparent-inputs")]
+    fn transparent_address_metadata_covers_all_derived_addresses() {
+        use zcash_primitives::legacy::{
+            keys::{IncomingViewingKey, NonHardenedChildIndex, TransparentKeyScope},
+            TransparentAddress,
+        };
+
+        let mut wallet = MemoryWalletDb::new(Network::TestNetwork, 100);
+        let seed = SecretVec::new(vec![1u8; 32]);
+        let (account_id, _) = wallet.create_account(&seed, &birthday()).unwrap();
+        let (other_account_id, _) = wallet
+            .create_account(&SecretVec::new(vec![2u8; 32]), &birthday())
+            .unwrap();
+        let current = *wallet
+            .get_current_address(account_id)
+            .unwrap()
+            .unwrap()
+            .transparent()
+            .unwrap();
+        let (legacy, legacy_index) = wallet
+            .get_account(account_id)
+            .unwrap()
+            .unwrap()
+            .get_legacy_transparent_address()
+            .unwrap()
+            .unwrap();
+        let ephemeral = wallet
+            .reserve_next_n_ephemeral_addresses(account_id, 2)
+            .unwrap()[1]
+            .0;
+        let internal = wallet
+            .get_account(account_id)
+            .unwrap()
+            .unwrap()
+            .ufvk()
+            .unwrap()
+            .transparent()
+            .unwrap()
+            .derive_internal_ivk()
+            .unwrap()
+            .derive_address(NonHardenedChildIndex::from_index(3).unwrap())
+            .unwrap();
+
+        let metadata = |account_id, address: &TransparentAddress| {
+            wallet
+                .get_transparent_address_metadata(account_id, address)
+                .unwrap()
+                .map(|m| (m.scope(), m.address_index().index()))
+        };
+        assert_eq!(
+            metadata(account_id, &current).map(|(scope, _)| scope),
+            Some(TransparentKeyScope::EXTERNAL)
+        );
+        assert_eq!(
+            metadata(account_id, &legacy),
+            Some((TransparentKeyScope::EXTERNAL, legacy_index.index()))
+        );
+        assert_eq!(
+            metadata(account_id, &ephemeral),
+            Some((TransparentKeyScope::EPHEMERAL, 1))
+        );
+        assert_eq!(
+            metadata(account_id, &internal),
+            Some((TransparentKeyScope::INTERNAL, 3))
+        );
+
+        // Addresses that the account did not derive are unknown to it.
+        assert_eq!(metadata(other_account_id, &current), None);
+        assert_eq!(
+            metadata(account_id, &TransparentAddress::PublicKeyHash([0; 20])),
+            None
+        );
+    }
+}
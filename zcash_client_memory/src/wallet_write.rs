@@ -6,7 +6,6 @@ use std::{
 
 use incrementalmerkletree::{Marking, Position, Retention};
 use rayon::prelude::*;
-use secrecy::ExposeSecret;
 use secrecy::SecretVec;
 use shardtree::store::ShardStore;
 use zcash_client_backend::{
@@ -14,7 +13,7 @@ use zcash_client_backend::{
     data_api::{
         chain::ChainState,
         scanning::{ScanPriority, ScanRange},
-        AccountPurpose, AccountSource, TransactionStatus, WalletCommitmentTrees as _,
+        Account as _, AccountPurpose, AccountSource, TransactionStatus, WalletCommitmentTrees as _,
         SAPLING_SHARD_HEIGHT,
     },
     data_api::{
@@ -22,7 +21,7 @@ use zcash_client_backend::{
         SentTransactionOutput, WalletRead, WalletWrite,
     },
     keys::{UnifiedAddressRequest, UnifiedFullViewingKey, UnifiedSpendingKey},
-    wallet::{NoteId, Recipient, WalletTransparentOutput},
+    wallet::{NoteId, Recipient, WalletTransparentOutput, WalletTx},
     TransferType,
 };
 use zcash_primitives::{
@@ -34,10 +33,10 @@ use zcash_primitives::{
 };
 use zcash_protocol::{
     consensus::{self, NetworkUpgrade},
+    memo::Memo,
     PoolType,
     ShieldedProtocol::{self, Sapling},
 };
-use zip32::fingerprint::SeedFingerprint;
 #[cfg(feature = "orchard")]
 use {
     shardtree::error::ShardTreeError, std::collections::BTreeMap,
@@ -45,13 +44,19 @@ use {
 };
 
 use crate::{
-    error::Error, MemoryWalletBlock, MemoryWalletDb, Nullifier, ReceivedNote, PRUNING_DEPTH,
+    error::Error, output_index_u16, preferred_memo, AccountId, MemoryWalletBlock, MemoryWalletDb,
+    Nullifier, NullifierMap, ReceivedNote, ScanTuning, SentNoteTable, TxLocatorMap, PRUNING_DEPTH,
     VERIFY_LOOKAHEAD,
 };
 
 #[cfg(feature = "orchard")]
 use zcash_protocol::ShieldedProtocol::Orchard;
 
+#[cfg(feature = "metrics")]
+use crate::types::wallet_metrics;
+#[cfg(feature = "telemetry")]
+use crate::types::{PhaseTimer, ScanPhase};
+
 #[cfg(feature = "transparent-inputs")]
 use {
     zcash_client_backend::wallet::TransparentAddressMetadata,
@@ -66,34 +71,16 @@ impl<P: consensus::Parameters> WalletWrite for MemoryWalletDb<P> {
         seed: &SecretVec<u8>,
         birthday: &AccountBirthday,
     ) -> Result<(Self::AccountId, UnifiedSpendingKey), Self::Error> {
-        if cfg!(not(test)) {
-            unimplemented!(
-                "Memwallet does not support adding accounts from seed phrases. 
-    Instead derive the ufvk in the calling code and import it using `import_account_ufvk`"
-            )
-        } else {
-            let seed_fingerprint = SeedFingerprint::from_seed(seed.expose_secret())
-                .ok_or_else(|| Self::Error::InvalidSeedLength)?;
-            let account_index = self
-                .max_zip32_account_index(&seed_fingerprint)?
-                .map(|a| a.next().ok_or_else(|| Self::Error::AccountOutOfRange))
-                .transpose()?
-                .unwrap_or(zip32::AccountId::ZERO);
-
-            let usk =
-                UnifiedSpendingKey::from_seed(&self.params, seed.expose_secret(), account_index)?;
-            let ufvk = usk.to_unified_full_viewing_key();
-
-            let (id, _account) = self.add_account(
-                AccountSource::Derived {
-                    seed_fingerprint,
-                    account_index,
-                },
-                ufvk,
-                birthday.clone(),
-            )?;
-
-            Ok((id, usk))
+        tracing::debug!("create_account");
+        #[cfg(feature = "hd-derivation")]
+        {
+            let (account, usk) = self.add_derived_account(seed, None, birthday)?;
+            Ok((account.id(), usk))
+        }
+        #[cfg(not(feature = "hd-derivation"))]
+        {
+            let _ = (seed, birthday);
+            Err(Error::HdDerivationDisabled)
         }
     }
 
@@ -112,6 +99,8 @@ impl<P: consensus::Parameters> WalletWrite for MemoryWalletDb<P> {
 
     fn update_chain_tip(&mut self, tip_height: BlockHeight) -> Result<(), Self::Error> {
         tracing::debug!("update_chain_tip");
+        #[cfg(feature = "metrics")]
+        wallet_metrics::record_chain_tip(tip_height);
         // If the caller provided a chain tip that is before Sapling activation, do nothing.
         let sapling_activation = match self.params.activation_height(NetworkUpgrade::Sapling) {
             Some(h) if h <= tip_height => h,
@@ -142,7 +131,12 @@ impl<P: consensus::Parameters> WalletWrite for MemoryWalletDb<P> {
         let chain_end = tip_height + 1;
 
         let sapling_shard_tip = self.sapling_tip_shard_end_height();
-        // TODO: Handle orchard case as well. See zcash_client_sqlite scanning.rs update_chain_tip
+        #[cfg(feature = "orchard")]
+        let min_shard_tip = match (sapling_shard_tip, self.orchard_tip_shard_end_height()) {
+            (Some(s), Some(o)) => Some(std::cmp::min(s, o)),
+            (s, o) => s.or(o),
+        };
+        #[cfg(not(feature = "orchard"))]
         let min_shard_tip = sapling_shard_tip;
 
         // Create a scanning range for the fragment of the last shard leading up to new tip.
@@ -262,6 +256,10 @@ impl<P: consensus::Parameters> WalletWrite for MemoryWalletDb<P> {
             tip_shard_entry.into_iter().chain(Some(tip_entry)),
             false,
         )?;
+
+        // Drop unanswered requests for transactions that we did not create and that appear to
+        // have been dropped from the mempool.
+        self.expire_data_requests(tip_height);
         Ok(())
     }
 
@@ -274,6 +272,87 @@ impl<P: consensus::Parameters> WalletWrite for MemoryWalletDb<P> {
         blocks: Vec<ScannedBlock<Self::AccountId>>,
     ) -> Result<(), Self::Error> {
         tracing::debug!("put_blocks");
+        #[cfg(feature = "telemetry")]
+        let batch_timer = PhaseTimer::start_batch();
+        #[cfg(any(feature = "telemetry", feature = "metrics"))]
+        let block_count = blocks.len();
+
+        // Ensure that the chain state supplied by the caller is consistent with both the
+        // blocks being inserted and the blocks that we have previously scanned; otherwise
+        // the frontier checkpoint would be inserted at the wrong height.
+        if let Some(first_block) = blocks.first() {
+            if first_block.height() != from_state.block_height() + 1 {
+                return Err(Error::ChainStateHeightMismatch(
+                    from_state.block_height(),
+                    first_block.height(),
+                ));
+            }
+        }
+        // The hash of a scanned block can only be checked if its record has been retained.
+        if self.scanned_heights.contains(from_state.block_height()) {
+            if let Some(block) = self.blocks.get(&from_state.block_height()) {
+                if block.hash != from_state.block_hash() {
+                    return Err(Error::ChainStateMismatch(
+                        from_state.block_height(),
+                        block.hash,
+                        from_state.block_hash(),
+                    ));
+                }
+            }
+        }
+
+        // Each block must directly follow its predecessor, and must not replace a block that
+        // was previously scanned with a different hash. In the latter case the chain has been
+        // reorganized beneath the wallet, which must be truncated below the height at which the
+        // hashes diverge before these blocks can be inserted.
+        let mut prev_height = from_state.block_height();
+        for block in &blocks {
+            if block.height() != prev_height + 1 {
+                return Err(Error::NonSequentialBlocks);
+            }
+            if let Some(stored) = self.blocks.get(&block.height()) {
+                if stored.hash != block.block_hash() {
+                    return Err(Error::PrevHashMismatch {
+                        at_height: block.height(),
+                        stored: stored.hash,
+                        received: block.block_hash(),
+                    });
+                }
+            }
+            prev_height = block.height();
+        }
+
+        // Validate the indices of all outputs and resolve the wallet state associated with
+        // them before modifying any wallet state, so that a malformed block cannot leave the
+        // wallet partially updated. This only reads the wallet, so it is performed in parallel
+        // if so configured.
+        for block in &blocks {
+            for transaction in block.transactions() {
+                u32::try_from(transaction.block_index())?;
+            }
+        }
+        let output_records = {
+            let lookups = OutputLookups::new(self, &blocks);
+            let resolve_block = |block: &ScannedBlock<AccountId>| {
+                block
+                    .transactions()
+                    .iter()
+                    .map(|transaction| lookups.resolve(transaction))
+                    .collect::<Result<Vec<_>, Error>>()
+            };
+            if self.scan_tuning.use_parallelism() {
+                blocks
+                    .par_iter()
+                    .map(resolve_block)
+                    .collect::<Result<Vec<_>, Error>>()?
+            } else {
+                blocks
+                    .iter()
+                    .map(resolve_block)
+                    .collect::<Result<Vec<_>, Error>>()?
+            }
+        };
+
         let mut last_scanned_height = None;
         struct BlockPositions {
             height: BlockHeight,
@@ -281,39 +360,39 @@ impl<P: consensus::Parameters> WalletWrite for MemoryWalletDb<P> {
             #[cfg(feature = "orchard")]
             orchard_start_position: Position,
         }
-        let start_positions = blocks.first().map(|block| BlockPositions {
-            height: block.height(),
-            sapling_start_position: Position::from(
-                u64::from(block.sapling().final_tree_size())
-                    - u64::try_from(block.sapling().commitments().len()).unwrap(),
-            ),
-            #[cfg(feature = "orchard")]
-            orchard_start_position: Position::from(
-                u64::from(block.orchard().final_tree_size())
-                    - u64::try_from(block.orchard().commitments().len()).unwrap(),
-            ),
-        });
+        let start_positions = blocks
+            .first()
+            .map(|block| {
+                Ok::<_, Error>(BlockPositions {
+                    height: block.height(),
+                    sapling_start_position: start_position(
+                        block.sapling().final_tree_size(),
+                        block.sapling().commitments().len(),
+                    )?,
+                    #[cfg(feature = "orchard")]
+                    orchard_start_position: start_position(
+                        block.orchard().final_tree_size(),
+                        block.orchard().commitments().len(),
+                    )?,
+                })
+            })
+            .transpose()?;
 
         let mut sapling_commitments = vec![];
         #[cfg(feature = "orchard")]
         let mut orchard_commitments = vec![];
         let mut note_positions = vec![];
-        for block in blocks.into_iter() {
+        for (block, block_records) in blocks.into_iter().zip(output_records) {
+            #[cfg(feature = "telemetry")]
+            let timer = PhaseTimer::start(ScanPhase::BlockProcessing);
             let mut transactions = HashMap::new();
             let mut memos = HashMap::new();
-            if last_scanned_height
-                .iter()
-                .any(|prev| block.height() != *prev + 1)
-            {
-                return Err(Error::NonSequentialBlocks);
-            }
-
-            for transaction in block.transactions().iter() {
+            for (transaction, records) in block.transactions().iter().zip(block_records) {
                 let txid = transaction.txid();
 
                 // Mark the Sapling nullifiers of the spent notes as spent in the `sapling_spends` map.
                 for spend in transaction.sapling_spends() {
-                    println!(
+                    tracing::debug!(
                         "marking note {:?} as spent in transaction {:?}",
                         spend.nf(),
                         txid
@@ -327,58 +406,34 @@ impl<P: consensus::Parameters> WalletWrite for MemoryWalletDb<P> {
                     self.mark_orchard_note_spent(*spend.nf(), txid)?;
                 }
 
-                for output in transaction.sapling_outputs() {
-                    // Insert the memo into the `memos` map.
-                    let note_id = NoteId::new(
-                        txid,
-                        Sapling,
-                        u16::try_from(output.index())
-                            .expect("output indices are representable as u16"),
-                    );
-                    if let Ok(Some(memo)) = self.get_memo(note_id) {
-                        memos.insert(note_id, memo.encode());
+                for (output, record) in transaction.sapling_outputs().iter().zip(records.sapling) {
+                    if let Some(memo) = record.memo {
+                        memos.insert(record.note_id, memo.encode());
                     }
-                    // Check whether this note was spent in a later block range that
-                    // we previously scanned.
-                    let spent_in = output
-                        .nf()
-                        .and_then(|nf| self.nullifiers.get(&Nullifier::Sapling(*nf)))
-                        .and_then(|(height, tx_idx)| self.tx_locator.get(*height, *tx_idx))
-                        .copied();
-
-                    self.insert_received_sapling_note(note_id, output, spent_in);
+                    self.insert_received_sapling_note(record.note_id, output, record.spent_in);
                 }
 
                 #[cfg(feature = "orchard")]
-                for output in transaction.orchard_outputs().iter() {
-                    // Insert the memo into the `memos` map.
-                    let note_id = NoteId::new(
-                        txid,
-                        Orchard,
-                        u16::try_from(output.index())
-                            .expect("output indices are representable as u16"),
-                    );
-                    if let Ok(Some(memo)) = self.get_memo(note_id) {
-                        memos.insert(note_id, memo.encode());
+                for (output, record) in transaction.orchard_outputs().iter().zip(records.orchard) {
+                    if let Some(memo) = record.memo {
+                        memos.insert(record.note_id, memo.encode());
                     }
-                    // Check whether this note was spent in a later block range that
-                    // we previously scanned.
-                    let spent_in = output
-                        .nf()
-                        .and_then(|nf| self.nullifiers.get(&Nullifier::Orchard(*nf)))
-                        .and_then(|(height, tx_idx)| self.tx_locator.get(*height, *tx_idx))
-                        .copied();
-
-                    self.insert_received_orchard_note(note_id, output, spent_in)
+                    self.insert_received_orchard_note(record.note_id, output, record.spent_in)
                 }
 
                 transactions.insert(txid, transaction.clone());
             }
+            #[cfg(feature = "telemetry")]
+            self.scan_telemetry.record(timer);
 
             // Insert the new nullifiers from this block into the nullifier map
+            #[cfg(feature = "telemetry")]
+            let timer = PhaseTimer::start(ScanPhase::NullifierMap);
             self.insert_sapling_nullifier_map(block.height(), block.sapling().nullifier_map())?;
             #[cfg(feature = "orchard")]
             self.insert_orchard_nullifier_map(block.height(), block.orchard().nullifier_map())?;
+            #[cfg(feature = "telemetry")]
+            self.scan_telemetry.record(timer);
             note_positions.extend(block.transactions().iter().flat_map(|wtx| {
                 let iter = wtx.sapling_outputs().iter().map(|out| {
                     (
@@ -404,20 +459,28 @@ impl<P: consensus::Parameters> WalletWrite for MemoryWalletDb<P> {
                 _transactions: transactions.keys().cloned().collect(),
                 _memos: memos,
                 sapling_commitment_tree_size: Some(block.sapling().final_tree_size()),
-                sapling_output_count: Some(block.sapling().commitments().len().try_into().unwrap()),
+                sapling_output_count: Some(block.sapling().commitments().len().try_into()?),
                 #[cfg(feature = "orchard")]
                 orchard_commitment_tree_size: Some(block.orchard().final_tree_size()),
                 #[cfg(feature = "orchard")]
-                orchard_action_count: Some(block.orchard().commitments().len().try_into().unwrap()),
+                orchard_action_count: Some(block.orchard().commitments().len().try_into()?),
             };
 
-            // Insert transaction metadata into the transaction table
-            transactions
-                .into_iter()
-                .for_each(|(_id, tx)| self.tx_table.put_tx_meta(tx, block.height()));
+            // Insert transaction metadata into the transaction table, and request the full
+            // data of any transaction that the wallet does not already have.
+            let txids = transactions.keys().copied().collect::<Vec<_>>();
+            for (_id, tx) in transactions {
+                self.tx_table.put_tx_meta(tx, block.height())?;
+            }
+            self.queue_missing_enhancements(&txids)?;
 
-            // Insert the block into the block map
+            // Insert the block into the block map, and record that its height has been scanned
             self.blocks.insert(block.height(), memory_block);
+            self.scanned_heights
+                .insert(block.height()..block.height() + 1);
+
+            // Transactions that were stored before this block was scanned are now mined.
+            self.backfill_block_tx_locations(block.height());
             last_scanned_height = Some(block.height());
 
             let block_commitments = block.into_commitments();
@@ -429,13 +492,37 @@ impl<P: consensus::Parameters> WalletWrite for MemoryWalletDb<P> {
         if let Some((start_positions, last_scanned_height)) =
             start_positions.zip(last_scanned_height)
         {
-            // Create subtrees from the note commitments in parallel.
-            const CHUNK_SIZE: usize = 1024;
-            let sapling_subtrees = sapling_commitments
-                .par_chunks_mut(CHUNK_SIZE)
-                .enumerate()
-                .filter_map(|(i, chunk)| {
-                    let start = start_positions.sapling_start_position + (i * CHUNK_SIZE) as u64;
+            // Mark the leaves of the wallet's received notes explicitly, so that they are
+            // never pruned from the trees regardless of the retention with which the
+            // commitments were scanned.
+            for (protocol, position) in &note_positions {
+                match protocol {
+                    ShieldedProtocol::Sapling => mark_commitment(
+                        &mut sapling_commitments,
+                        start_positions.sapling_start_position,
+                        *position,
+                    ),
+                    #[cfg(feature = "orchard")]
+                    ShieldedProtocol::Orchard => mark_commitment(
+                        &mut orchard_commitments,
+                        start_positions.orchard_start_position,
+                        *position,
+                    ),
+                    #[cfg(not(feature = "orchard"))]
+                    ShieldedProtocol::Orchard => {
+                        self.unsupported_protocol_data("position of a received Orchard note")?
+                    }
+                }
+            }
+
+            // Create subtrees from the note commitments, in parallel if so configured.
+            let scan_tuning = self.scan_tuning;
+            let chunk_size = scan_tuning.subtree_chunk_size();
+            #[cfg(feature = "telemetry")]
+            let timer = PhaseTimer::start(ScanPhase::SaplingSubtrees);
+            let sapling_subtrees =
+                map_chunks(&mut sapling_commitments, &scan_tuning, |i, chunk| {
+                    let start = start_positions.sapling_start_position + (i * chunk_size) as u64;
                     let end = start + chunk.len() as u64;
 
                     shardtree::LocatedTree::from_iter(
@@ -443,16 +530,17 @@ impl<P: consensus::Parameters> WalletWrite for MemoryWalletDb<P> {
                         SAPLING_SHARD_HEIGHT.into(),
                         chunk.iter_mut().map(|n| n.take().expect("always Some")),
                     )
-                })
-                .map(|res| (res.subtree, res.checkpoints))
-                .collect::<Vec<_>>();
+                    .map(|res| (res.subtree, res.checkpoints))
+                });
+            #[cfg(feature = "telemetry")]
+            self.scan_telemetry.record(timer);
 
+            #[cfg(all(feature = "orchard", feature = "telemetry"))]
+            let timer = PhaseTimer::start(ScanPhase::OrchardSubtrees);
             #[cfg(feature = "orchard")]
-            let orchard_subtrees = orchard_commitments
-                .par_chunks_mut(CHUNK_SIZE)
-                .enumerate()
-                .filter_map(|(i, chunk)| {
-                    let start = start_positions.orchard_start_position + (i * CHUNK_SIZE) as u64;
+            let orchard_subtrees =
+                map_chunks(&mut orchard_commitments, &scan_tuning, |i, chunk| {
+                    let start = start_positions.orchard_start_position + (i * chunk_size) as u64;
                     let end = start + chunk.len() as u64;
 
                     shardtree::LocatedTree::from_iter(
@@ -460,9 +548,10 @@ impl<P: consensus::Parameters> WalletWrite for MemoryWalletDb<P> {
                         ORCHARD_SHARD_HEIGHT.into(),
                         chunk.iter_mut().map(|n| n.take().expect("always Some")),
                     )
-                })
-                .map(|res| (res.subtree, res.checkpoints))
-                .collect::<Vec<_>>();
+                    .map(|res| (res.subtree, res.checkpoints))
+                });
+            #[cfg(all(feature = "orchard", feature = "telemetry"))]
+            self.scan_telemetry.record(timer);
 
             // Collect the complete set of Sapling checkpoints
             #[cfg(feature = "orchard")]
@@ -479,21 +568,42 @@ impl<P: consensus::Parameters> WalletWrite for MemoryWalletDb<P> {
                 .map(|(k, v)| (*k, *v))
                 .collect();
 
+            // The size of each tree at the end of each block in this batch, which bounds the
+            // positions of the checkpoints created for those blocks.
+            #[cfg(feature = "orchard")]
+            let (sapling_tree_sizes, orchard_tree_sizes): (
+                BTreeMap<_, _>,
+                BTreeMap<_, _>,
+            ) = self
+                .blocks
+                .range(start_positions.height..=last_scanned_height)
+                .map(|(height, block)| {
+                    (
+                        (*height, block.sapling_commitment_tree_size),
+                        (*height, block.orchard_commitment_tree_size),
+                    )
+                })
+                .unzip();
+
             #[cfg(feature = "orchard")]
             let (missing_sapling_checkpoints, missing_orchard_checkpoints) = (
                 ensure_checkpoints(
                     orchard_checkpoint_positions.keys(),
                     &sapling_checkpoint_positions,
+                    &sapling_tree_sizes,
                     from_state.final_sapling_tree(),
                 ),
                 ensure_checkpoints(
                     sapling_checkpoint_positions.keys(),
                     &orchard_checkpoint_positions,
+                    &orchard_tree_sizes,
                     from_state.final_orchard_tree(),
                 ),
             );
 
             // Update the Sapling note commitment tree with all newly read note commitments
+            #[cfg(feature = "telemetry")]
+            let timer = PhaseTimer::start(ScanPhase::TreeInsertion);
             {
                 let mut sapling_subtrees_iter = sapling_subtrees.into_iter();
                 self.with_sapling_tree_mut::<_, _, Self::Error>(|sapling_tree| {
@@ -574,15 +684,37 @@ impl<P: consensus::Parameters> WalletWrite for MemoryWalletDb<P> {
                 })?;
             }
 
-            self.scan_complete(
-                Range {
-                    start: start_positions.height,
-                    end: last_scanned_height + 1,
-                },
-                &note_positions,
-            )?;
+            #[cfg(feature = "telemetry")]
+            self.scan_telemetry.record(timer);
+
+            let scanned_range = Range {
+                start: start_positions.height,
+                end: last_scanned_height + 1,
+            };
+            #[cfg(feature = "telemetry")]
+            let timer = PhaseTimer::start(ScanPhase::ScanComplete);
+            self.scan_complete(scanned_range.clone(), &note_positions)?;
+            #[cfg(feature = "telemetry")]
+            self.scan_telemetry.record(timer);
+            self.advance_in_progress_scan(&scanned_range);
+            self.reorg_stats.record_scan(&scanned_range);
         }
 
+        // Prune the nullifier map of entries we no longer need.
+        if let Some(metadata) = self.block_fully_scanned()? {
+            self.prune(metadata.block_height().saturating_sub(PRUNING_DEPTH))?;
+        }
+        self.prune_checkpoints()?;
+
+        #[cfg(feature = "telemetry")]
+        self.scan_telemetry
+            .record_batch(batch_timer, block_count, note_positions.len());
+        #[cfg(feature = "metrics")]
+        wallet_metrics::record_put_blocks(
+            block_count,
+            note_positions.len(),
+            self.scanned_heights.max_height(),
+        );
         Ok(())
     }
 
@@ -596,7 +728,18 @@ impl<P: consensus::Parameters> WalletWrite for MemoryWalletDb<P> {
         {
             let address = output.recipient_address();
             if let Some(account_id) = self.find_account_for_transparent_address(address)? {
-                self.put_transparent_output(output, &account_id, false)
+                let outpoint = self.put_transparent_output(output, &account_id, false)?;
+
+                // The spend of this output may not otherwise be detected if the transaction
+                // that spends it is purely transparent.
+                if !self
+                    .transparent_received_output_spends
+                    .contains_key(&outpoint)
+                {
+                    self.transaction_data_request_queue
+                        .queue_spend_detection(outpoint.clone());
+                }
+                Ok(outpoint)
             } else {
                 Err(Error::AddressNotRecognized(*address))
             }
@@ -613,9 +756,13 @@ impl<P: consensus::Parameters> WalletWrite for MemoryWalletDb<P> {
     ) -> Result<(), Self::Error> {
         tracing::debug!("store_decrypted_tx");
         self.tx_table.put_tx_data(d_tx.tx(), None, None);
+        self.transaction_data_request_queue
+            .remove_enhancement(&d_tx.tx().txid());
         if let Some(height) = d_tx.mined_height() {
             self.set_transaction_status(d_tx.tx().txid(), TransactionStatus::Mined(height))?
         }
+        self.backfill_tx_location(&d_tx.tx().txid());
+        self.record_derived_fee(d_tx.tx())?;
 
         let funding_accounts = self.get_funding_accounts(d_tx.tx())?;
         // TODO(#1305): Correctly track accounts that fund each transaction output.
@@ -669,7 +816,7 @@ impl<P: consensus::Parameters> WalletWrite for MemoryWalletDb<P> {
                         d_tx.tx().txid(),
                         *output.account(),
                         &sent_tx_output,
-                    );
+                    )?;
                 }
                 TransferType::WalletInternal => {
                     let recipient = Recipient::InternalAccount {
@@ -694,10 +841,12 @@ impl<P: consensus::Parameters> WalletWrite for MemoryWalletDb<P> {
                         d_tx.tx().txid(),
                         *output.account(),
                         &sent_tx_output,
-                    );
+                    )?;
                 }
                 TransferType::Incoming => {
-                    todo!("store decrypted tx sapling incoming")
+                    self.received_notes.insert_received_note(
+                        ReceivedNote::from_incoming_sapling_output(d_tx.tx().txid(), output)?,
+                    );
                 }
             }
         }
@@ -737,7 +886,7 @@ impl<P: consensus::Parameters> WalletWrite for MemoryWalletDb<P> {
                         d_tx.tx().txid(),
                         *output.account(),
                         &sent_tx_output,
-                    );
+                    )?;
                 }
                 TransferType::WalletInternal => {
                     let recipient = Recipient::InternalAccount {
@@ -762,10 +911,12 @@ impl<P: consensus::Parameters> WalletWrite for MemoryWalletDb<P> {
                         d_tx.tx().txid(),
                         *output.account(),
                         &sent_tx_output,
-                    );
+                    )?;
                 }
                 TransferType::Incoming => {
-                    todo!("store decrypted tx orchard incoming")
+                    self.received_notes.insert_received_note(
+                        ReceivedNote::from_incoming_orchard_output(d_tx.tx().txid(), output)?,
+                    );
                 }
             }
         }
@@ -803,8 +954,11 @@ impl<P: consensus::Parameters> WalletWrite for MemoryWalletDb<P> {
                     // advance the gap regardless of whether it is mined, but an output in
                     // an unmined transaction won't advance the range of safe indices.
                     #[cfg(feature = "transparent-inputs")]
-                    self.accounts
-                        .mark_ephemeral_address_as_seen(&address, d_tx.tx().txid())?;
+                    self.accounts.mark_ephemeral_address_as_seen(
+                        &self.tx_table,
+                        &address,
+                        d_tx.tx().txid(),
+                    )?;
 
                     // If the output belongs to the wallet, add it to `transparent_received_outputs`.
                     #[cfg(feature = "transparent-inputs")]
@@ -821,13 +975,18 @@ impl<P: consensus::Parameters> WalletWrite for MemoryWalletDb<P> {
                         let wallet_transparent_output = WalletTransparentOutput::from_parts(
                             OutPoint::new(
                                 d_tx.tx().txid().into(),
-                                u32::try_from(output_index).unwrap(),
+                                u32::try_from(output_index).map_err(|_| {
+                                    Error::OutputIndexOutOfRange {
+                                        txid: d_tx.tx().txid(),
+                                        index: output_index,
+                                    }
+                                })?,
                             ),
                             txout.clone(),
                             d_tx.mined_height(),
                         )
                         .unwrap();
-                        self.put_transparent_output(
+                        let outpoint = self.put_transparent_output(
                             &wallet_transparent_output,
                             &account_id,
                             false,
@@ -837,6 +996,16 @@ impl<P: consensus::Parameters> WalletWrite for MemoryWalletDb<P> {
                         // that any transparent inputs belonging to the wallet will be
                         // discovered.
                         tx_has_wallet_outputs = true;
+
+                        // The spend of this output may not otherwise be detected if the
+                        // transaction that spends it is purely transparent.
+                        if !self
+                            .transparent_received_output_spends
+                            .contains_key(&outpoint)
+                        {
+                            self.transaction_data_request_queue
+                                .queue_spend_detection(outpoint);
+                        }
                     } else {
                         tracing::debug!(
                             "Address {} is not recognized as belonging to any of our accounts.",
@@ -878,7 +1047,7 @@ impl<P: consensus::Parameters> WalletWrite for MemoryWalletDb<P> {
                             d_tx.tx().txid(),
                             account_id,
                             &sent_tx_output,
-                        );
+                        )?;
                         // Even though we know the funding account, we don't know that we have
                         // information for all of the transparent inputs to the transaction.
                         #[cfg(feature = "transparent-inputs")]
@@ -903,8 +1072,9 @@ impl<P: consensus::Parameters> WalletWrite for MemoryWalletDb<P> {
         #[cfg(feature = "transparent-inputs")]
         if tx_has_wallet_outputs && d_tx.tx().transparent_bundle().is_some() {
             // queue the transparent inputs for enhancement
+            let chain_height = self.chain_height()?;
             self.transaction_data_request_queue
-                .queue_status_retrieval(&d_tx.tx().txid());
+                .queue_status_retrieval(&d_tx.tx().txid(), chain_height);
         }
 
         #[cfg(feature = "transparent-inputs")]
@@ -915,8 +1085,9 @@ impl<P: consensus::Parameters> WalletWrite for MemoryWalletDb<P> {
                 detectable_via_scanning | d_tx.tx().orchard_bundle().is_some();
 
             if d_tx.mined_height().is_none() && !detectable_via_scanning {
+                let chain_height = self.chain_height()?;
                 self.transaction_data_request_queue
-                    .queue_status_retrieval(&d_tx.tx().txid());
+                    .queue_status_retrieval(&d_tx.tx().txid(), chain_height);
             }
         }
         Ok(())
@@ -927,8 +1098,21 @@ impl<P: consensus::Parameters> WalletWrite for MemoryWalletDb<P> {
     /// If the requested height is greater than or equal to the height of the last scanned
     /// block, this function does nothing.
     ///
+    /// Returns [`Error::RequestedRewindInvalid`] if the wallet would be rewound by more than
+    /// `PRUNING_DEPTH` blocks to below the height of its earliest unspent note, or if no
+    /// checkpoint at or below the requested height has been retained.
+    ///
     /// This should only be executed inside a transactional context.
     fn truncate_to_height(&mut self, max_height: BlockHeight) -> Result<BlockHeight, Self::Error> {
+        // Recall where we synced up to previously.
+        let last_scanned_height = self.scanned_heights.max_height().unwrap_or_else(|| {
+            self.params
+                .activation_height(NetworkUpgrade::Sapling)
+                .expect("Sapling activation height must be available.")
+                - 1
+        });
+        self.check_rewind_height(max_height, last_scanned_height)?;
+
         let truncation_height = {
             // This is the intersection of all the checkpoint heights from the sapling and orchard tree.
             let mut checkpoint_heights = BTreeSet::new();
@@ -962,21 +1146,13 @@ impl<P: consensus::Parameters> WalletWrite for MemoryWalletDb<P> {
             } else {
                 // If there are no checkpoints that are less than or equal to the truncation height
                 // then we can't truncate the tree.
-                Err(Error::RequestedRewindInvalid(
-                    over.first().copied().map(Into::into),
-                    max_height,
-                ))
+                Err(Error::RequestedRewindInvalid {
+                    safe_height: over.first().copied().map(Into::into),
+                    requested: max_height,
+                })
             }
         }?;
 
-        // Recall where we synced up to previously.
-        let last_scanned_height = self.blocks.keys().max().copied().unwrap_or_else(|| {
-            self.params
-                .activation_height(NetworkUpgrade::Sapling)
-                .expect("Sapling activation height must be available.")
-                - 1
-        });
-
         // Delete from the scanning queue any range with a start height greater than the
         // truncation height, and then truncate any remaining range by setting the end
         // equal to the truncation height + 1. This sets our view of the chain tip back
@@ -984,6 +1160,8 @@ impl<P: consensus::Parameters> WalletWrite for MemoryWalletDb<P> {
         self.scan_queue
             .delete_starts_greater_than_equal_to(truncation_height + 1);
         self.scan_queue.truncate_ends_to(truncation_height + 1);
+        // The blocks of an interrupted range scan may no longer be those that were downloaded.
+        self.in_progress_scan = None;
 
         // Mark transparent utxos as un-mined. Since the TXO is now not mined, it would ideally be
         // considered to have been returned to the mempool; it _might_ be spendable in this state, but
@@ -1008,7 +1186,8 @@ impl<P: consensus::Parameters> WalletWrite for MemoryWalletDb<P> {
 
         // Un-mine transactions. This must be done outside of the last_scanned_height check because
         // transaction entries may be created as a consequence of receiving transparent TXOs.
-        self.tx_table
+        let unmined = self
+            .tx_table
             .unmine_transactions_greater_than(truncation_height);
 
         // If we're removing scanned blocks, we need to truncate the note commitment tree and remove
@@ -1031,24 +1210,46 @@ impl<P: consensus::Parameters> WalletWrite for MemoryWalletDb<P> {
 
             // Now that they aren't depended on, delete un-mined blocks.
             self.blocks.retain(|height, _| *height <= truncation_height);
+            self.scanned_heights.truncate(truncation_height);
 
             // Delete from the nullifier map any entries with a locator referencing a block
             // height greater than the truncation height.
-            // Willem: We don't need to do this I think..
+            self.nullifiers.truncate(truncation_height);
+            // The transactions at those locators may be replaced by others when the blocks
+            // are rescanned, so their locations are no longer known.
+            self.tx_locator.truncate(truncation_height);
         }
+
+        // Revert the observations of ephemeral addresses in the un-mined transactions, so that
+        // they no longer advance the gap.
+        #[cfg(feature = "transparent-inputs")]
+        self.rewind_ephemeral_addresses(&unmined, truncation_height);
+        #[cfg(not(feature = "transparent-inputs"))]
+        let _ = unmined;
+
+        let depth = u32::from(last_scanned_height).saturating_sub(u32::from(truncation_height));
+        self.reorg_stats.record_truncation(truncation_height, depth);
+        #[cfg(feature = "metrics")]
+        wallet_metrics::record_truncation(depth);
         Ok(truncation_height)
     }
 
     fn import_account_hd(
         &mut self,
-        _seed: &SecretVec<u8>,
-        _account_index: zip32::AccountId,
-        _birthday: &AccountBirthday,
+        seed: &SecretVec<u8>,
+        account_index: zip32::AccountId,
+        birthday: &AccountBirthday,
     ) -> Result<(Self::Account, UnifiedSpendingKey), Self::Error> {
-        unimplemented!(
-            "Memwallet does not support adding accounts from seed phrases. 
-Instead derive the ufvk in the calling code and import it using `import_account_ufvk`"
-        )
+        tracing::debug!("import_account_hd");
+        #[cfg(feature = "hd-derivation")]
+        {
+            self.add_derived_account(seed, Some(account_index), birthday)
+        }
+        #[cfg(not(feature = "hd-derivation"))]
+        {
+            let _ = (seed, account_index, birthday);
+            Err(Error::HdDerivationDisabled)
+        }
     }
 
     fn import_account_ufvk(
@@ -1071,12 +1272,28 @@ Instead derive the ufvk in the calling code and import it using `import_account_
         transactions: &[SentTransaction<Self::AccountId>],
     ) -> Result<(), Self::Error> {
         tracing::debug!("store_transactions_to_be_sent");
+        // Reject transactions built against a stale view of the chain before locking any of
+        // their inputs.
+        for sent_tx in transactions {
+            self.check_target_height(sent_tx.target_height())?;
+            // Without Orchard support the wallet cannot track the notes spent by Orchard
+            // actions, so such transactions are rejected before anything is stored.
+            #[cfg(not(feature = "orchard"))]
+            if sent_tx.tx().orchard_bundle().is_some() {
+                return Err(Error::OrchardNotEnabled);
+            }
+        }
+        let chain_tip = self.chain_height()?;
+
         for sent_tx in transactions {
             self.tx_table.put_tx_data(
                 sent_tx.tx(),
                 Some(sent_tx.fee_amount()),
                 Some(sent_tx.target_height()),
             );
+            self.tx_table
+                .set_stored_at_chain_tip(&sent_tx.tx().txid(), chain_tip);
+            self.backfill_tx_location(&sent_tx.tx().txid());
             let mut detectable_via_scanning = false;
             // Mark sapling notes as spent
             if let Some(bundle) = sent_tx.tx().sapling_bundle() {
@@ -1086,25 +1303,19 @@ Instead derive the ufvk in the calling code and import it using `import_account_
                 }
             }
             // Mark orchard notes as spent
-            if let Some(_bundle) = sent_tx.tx().orchard_bundle() {
-                #[cfg(feature = "orchard")]
-                {
-                    detectable_via_scanning = true;
-                    for action in _bundle.actions() {
-                        match self.mark_orchard_note_spent(*action.nullifier(), sent_tx.tx().txid())
-                        {
-                            Ok(()) => {}
-                            Err(Error::NoteNotFound) => {
-                                // This is expected as some of the actions will be new outputs we don't have notes for
-                                // The ones we do recognize will be marked as spent
-                            }
-                            Err(e) => return Err(e),
+            #[cfg(feature = "orchard")]
+            if let Some(bundle) = sent_tx.tx().orchard_bundle() {
+                detectable_via_scanning = true;
+                for action in bundle.actions() {
+                    match self.mark_orchard_note_spent(*action.nullifier(), sent_tx.tx().txid()) {
+                        Ok(()) => {}
+                        Err(Error::NoteNotFound) => {
+                            // This is expected as some of the actions will be new outputs we don't have notes for
+                            // The ones we do recognize will be marked as spent
                         }
+                        Err(e) => return Err(e),
                     }
                 }
-
-                #[cfg(not(feature = "orchard"))]
-                panic!("Sent a transaction with Orchard Actions without `orchard` enabled?");
             }
             // Mark transparent UTXOs as spent
             #[cfg(feature = "transparent-inputs")]
@@ -1113,7 +1324,7 @@ Instead derive the ufvk in the calling code and import it using `import_account_
             }
 
             for output in sent_tx.outputs() {
-                self.sent_notes.insert_sent_output(sent_tx, output);
+                self.sent_notes.insert_sent_output(sent_tx, output)?;
 
                 match output.recipient() {
                     Recipient::InternalAccount { .. } => {
@@ -1144,7 +1355,10 @@ Instead derive the ufvk in the calling code and import it using `import_account_
                             )?
                         }
                     }
-                    _ => {}
+                    #[cfg(not(feature = "transparent-inputs"))]
+                    Recipient::EphemeralTransparent { .. } => self
+                        .unsupported_protocol_data("output to an ephemeral transparent address")?,
+                    Recipient::External(..) => {}
                 }
             }
 
@@ -1153,7 +1367,7 @@ Instead derive the ufvk in the calling code and import it using `import_account_
             // component will be detected via ordinary chain scanning and/or nullifier checking.
             if !detectable_via_scanning {
                 self.transaction_data_request_queue
-                    .queue_status_retrieval(&sent_tx.tx().txid());
+                    .queue_status_retrieval(&sent_tx.tx().txid(), chain_tip);
             }
         }
 
@@ -1166,7 +1380,7 @@ Instead derive the ufvk in the calling code and import it using `import_account_
         status: TransactionStatus,
     ) -> Result<(), Self::Error> {
         tracing::debug!("set_transaction_status");
-        self.tx_table.set_transaction_status(&txid, status)
+        self.update_transaction_status(txid, status)
     }
 
     #[cfg(feature = "transparent-inputs")]
@@ -1175,7 +1389,6 @@ Instead derive the ufvk in the calling code and import it using `import_account_
         account_id: Self::AccountId,
         n: usize,
     ) -> Result<Vec<(TransparentAddress, TransparentAddressMetadata)>, Self::Error> {
-        // TODO: We need to implement first_unsafe_index to make sure we dont violate gap invarient
         let first_unsafe = self.first_unsafe_index(account_id)?;
         if let Some(account) = self.accounts.get_mut(account_id) {
             let first_unreserved = account.first_unreserved_index()?;
@@ -1186,10 +1399,10 @@ Instead derive the ufvk in the calling code and import it using `import_account_
                 return Err(AddressGenerationError::DiversifierSpaceExhausted.into());
             }
             if allocation.end > first_unsafe {
-                return Err(Error::ReachedGapLimit(
-                    account_id,
-                    max(first_unreserved, first_unsafe),
-                ));
+                return Err(Error::ReachedGapLimit {
+                    account: account_id,
+                    bad_index: max(first_unreserved, first_unsafe),
+                });
             }
             let _reserved = account.reserve_until(allocation.end)?;
             self.get_known_ephemeral_addresses(account_id, Some(allocation))
@@ -1199,6 +1412,20 @@ Instead derive the ufvk in the calling code and import it using `import_account_
     }
 }
 
+/// Returns the position of the first note commitment in a block, given the size of the note
+/// commitment tree at the end of the block and the number of commitments in the block.
+fn start_position(final_tree_size: u32, commitment_count: usize) -> Result<Position, Error> {
+    u64::from(final_tree_size)
+        .checked_sub(u64::try_from(commitment_count)?)
+        .map(Position::from)
+        .ok_or_else(|| {
+            Error::CorruptedData(format!(
+                "Block contains {} note commitments, but the final tree size is {}",
+                commitment_count, final_tree_size
+            ))
+        })
+}
+
 #[cfg(feature = "transparent-inputs")]
 fn range_from(i: u32, n: u32) -> Range<u32> {
     let first = min(1 << 31, i);
@@ -1213,6 +1440,156 @@ use zcash_keys::keys::AddressGenerationError;
 #[cfg(feature = "orchard")]
 use {incrementalmerkletree::frontier::Frontier, shardtree::store::Checkpoint};
 
+/// The wallet state associated with an output received in a batch of scanned blocks.
+struct OutputRecord {
+    note_id: NoteId,
+    /// The transaction in which the note was spent, if its spend was found by scanning a later
+    /// range of blocks before the note itself.
+    spent_in: Option<TxId>,
+    /// The memo recorded for the note if it was received or sent by the wallet before this
+    /// batch was scanned.
+    memo: Option<Memo>,
+}
+
+/// The [`OutputRecord`]s of the outputs of a transaction, in output order.
+struct TransactionOutputRecords {
+    sapling: Vec<OutputRecord>,
+    #[cfg(feature = "orchard")]
+    orchard: Vec<OutputRecord>,
+}
+
+/// The wallet state consulted to resolve the [`OutputRecord`]s of a batch of scanned blocks.
+///
+/// Notes cannot be spent in a block earlier than the one that created them, and the spends
+/// within the batch of notes received in the batch are detected by the scanner itself, so the
+/// records can be resolved against the wallet state as it was before the batch is stored.
+struct OutputLookups<'a> {
+    nullifiers: &'a NullifierMap,
+    tx_locator: &'a TxLocatorMap,
+    sent_notes: &'a SentNoteTable,
+    /// The memos of the notes already received in the transactions of the batch, which are
+    /// only present when a range of blocks is rescanned.
+    received_memos: HashMap<NoteId, &'a Memo>,
+}
+
+impl<'a> OutputLookups<'a> {
+    fn new<P: consensus::Parameters>(
+        wallet: &'a MemoryWalletDb<P>,
+        blocks: &[ScannedBlock<AccountId>],
+    ) -> Self {
+        let txids = blocks
+            .iter()
+            .flat_map(|block| block.transactions().iter().map(|tx| tx.txid()))
+            .collect::<BTreeSet<_>>();
+        let received_memos = wallet
+            .received_notes
+            .iter()
+            .filter(|note| txids.contains(&note.txid()))
+            .map(|note| (note.note_id(), &note.memo))
+            .collect();
+
+        Self {
+            nullifiers: &wallet.nullifiers,
+            tx_locator: &wallet.tx_locator,
+            sent_notes: &wallet.sent_notes,
+            received_memos,
+        }
+    }
+
+    fn resolve(
+        &self,
+        transaction: &WalletTx<AccountId>,
+    ) -> Result<TransactionOutputRecords, Error> {
+        let txid = transaction.txid();
+        Ok(TransactionOutputRecords {
+            sapling: transaction
+                .sapling_outputs()
+                .iter()
+                .map(|output| {
+                    self.record(
+                        NoteId::new(txid, Sapling, output_index_u16(txid, output.index())?),
+                        output.nf().map(|nf| Nullifier::Sapling(*nf)),
+                    )
+                })
+                .collect::<Result<_, _>>()?,
+            #[cfg(feature = "orchard")]
+            orchard: transaction
+                .orchard_outputs()
+                .iter()
+                .map(|output| {
+                    self.record(
+                        NoteId::new(txid, Orchard, output_index_u16(txid, output.index())?),
+                        output.nf().map(|nf| Nullifier::Orchard(*nf)),
+                    )
+                })
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    fn record(&self, note_id: NoteId, nf: Option<Nullifier>) -> Result<OutputRecord, Error> {
+        let spent_in = nf
+            .and_then(|nf| self.nullifiers.get(&nf))
+            .and_then(|(height, tx_idx)| self.tx_locator.get(*height, *tx_idx))
+            .copied();
+        let memo = preferred_memo(
+            self.received_memos.get(&note_id).copied(),
+            self.sent_notes
+                .get_sent_note(&note_id)
+                .map(|note| &note.memo),
+        );
+
+        Ok(OutputRecord {
+            note_id,
+            spent_in,
+            memo,
+        })
+    }
+}
+
+/// Applies `f` to each chunk of `items` along with the chunk's index, as configured by the
+/// given scan tuning, and collects the results in chunk order.
+fn map_chunks<T: Send, R: Send>(
+    items: &mut [T],
+    scan_tuning: &ScanTuning,
+    f: impl Fn(usize, &mut [T]) -> Option<R> + Sync,
+) -> Vec<R> {
+    let chunk_size = scan_tuning.subtree_chunk_size();
+    if scan_tuning.use_parallelism() {
+        items
+            .par_chunks_mut(chunk_size)
+            .enumerate()
+            .filter_map(|(i, chunk)| f(i, chunk))
+            .collect()
+    } else {
+        items
+            .chunks_mut(chunk_size)
+            .enumerate()
+            .filter_map(|(i, chunk)| f(i, chunk))
+            .collect()
+    }
+}
+
+/// Sets the retention of the commitment at `position` to be marked, preserving any checkpoint.
+/// `commitments` holds the commitments of a batch of blocks, starting at `start_position`.
+fn mark_commitment<H>(
+    commitments: &mut [Option<(H, Retention<BlockHeight>)>],
+    start_position: Position,
+    position: Position,
+) {
+    let index = u64::from(position)
+        .checked_sub(u64::from(start_position))
+        .and_then(|index| usize::try_from(index).ok());
+    if let Some(Some((_, retention))) = index.and_then(|index| commitments.get_mut(index)) {
+        *retention = match retention {
+            Retention::Checkpoint { id, .. } => Retention::Checkpoint {
+                id: *id,
+                marking: Marking::Marked,
+            },
+            _ => Retention::Marked,
+        };
+    }
+}
+
 #[cfg(feature = "orchard")]
 fn ensure_checkpoints<'a, H, I: Iterator<Item = &'a BlockHeight>, const DEPTH: u8>(
     // An iterator of checkpoints heights for which we wish to ensure that
@@ -1221,37 +1598,1464 @@ fn ensure_checkpoints<'a, H, I: Iterator<Item = &'a BlockHeight>, const DEPTH: u
     // The map of checkpoint positions from which we will draw note commitment tree
     // position information for the newly created checkpoints.
     existing_checkpoint_positions: &BTreeMap<BlockHeight, Position>,
+    // The size of the note commitment tree at the end of each block in the batch, where
+    // known. A created checkpoint never refers to a position beyond the commitments
+    // inserted at or before its height.
+    inserted_tree_sizes: &BTreeMap<BlockHeight, Option<u32>>,
     // The frontier whose position will be used for an inserted checkpoint when
     // there is no preceding checkpoint in existing_checkpoint_positions.
     state_final_tree: &Frontier<H, DEPTH>,
 ) -> Vec<(BlockHeight, Checkpoint)> {
     ensure_heights
-        .flat_map(|ensure_height| {
-            existing_checkpoint_positions
+        .filter_map(|ensure_height| {
+            let position = match existing_checkpoint_positions
                 .range::<BlockHeight, _>(..=*ensure_height)
                 .last()
-                .map_or_else(
-                    || {
-                        Some((
-                            *ensure_height,
-                            state_final_tree
-                                .value()
-                                .map_or_else(Checkpoint::tree_empty, |t| {
-                                    Checkpoint::at_position(t.position())
-                                }),
-                        ))
-                    },
-                    |(existing_checkpoint_height, position)| {
-                        if *existing_checkpoint_height < *ensure_height {
-                            Some((*ensure_height, Checkpoint::at_position(*position)))
-                        } else {
-                            // The checkpoint already exists, so we don't need to
-                            // do anything.
-                            None
+            {
+                // The checkpoint already exists, so we don't need to do anything.
+                Some((existing_checkpoint_height, _))
+                    if existing_checkpoint_height == ensure_height =>
+                {
+                    return None;
+                }
+                Some((_, position)) => Some(*position),
+                None => state_final_tree.value().map(|t| t.position()),
+            };
+
+            // When the frontier is ahead of the commitments inserted for the batch, the
+            // checkpoint would otherwise refer to tree data that does not exist at its height,
+            // and witnesses computed at it would be incorrect.
+            let max_position = inserted_tree_sizes
+                .range::<BlockHeight, _>(..=*ensure_height)
+                .last()
+                .and_then(|(_, size)| *size)
+                .map(|size| u64::from(size).checked_sub(1).map(Position::from));
+            let position = match max_position {
+                Some(max_position) if position > max_position => {
+                    tracing::warn!(
+                        "Clamping checkpoint at height {} from position {:?} to {:?}",
+                        ensure_height,
+                        position,
+                        max_position
+                    );
+                    max_position
+                }
+                _ => position,
+            };
+
+            Some((
+                *ensure_height,
+                position.map_or_else(Checkpoint::tree_empty, Checkpoint::at_position),
+            ))
+        })
+        .collect::<Vec<_>>()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use prost::Message;
+    use sapling::{value::NoteValue, zip32::ExtendedSpendingKey, Rseed};
+    use zcash_client_backend::{
+        data_api::{chain::ChainState, ScannedBlock, SentTransactionOutput, WalletWrite},
+        proto::compact_formats::{ChainMetadata, CompactBlock},
+        scanning::{scan_block, Nullifiers, ScanningKeys},
+        wallet::{Note, Recipient},
+    };
+    use zcash_primitives::{
+        block::BlockHash,
+        consensus::{BlockHeight, Network, NetworkUpgrade, Parameters},
+        memo::MemoBytes,
+        transaction::{components::amount::NonNegativeAmount, TxId},
+    };
+
+    use crate::{
+        error::Error, proto::memwallet as proto, AccountId, MemoryWalletBlock, MemoryWalletDb,
+        ReceivedNote,
+    };
+
+    fn scanned_block(height: BlockHeight, hash: BlockHash) -> ScannedBlock<AccountId> {
+        let block = CompactBlock {
+            height: u64::from(u32::from(height)),
+            hash: hash.0.to_vec(),
+            chain_metadata: Some(ChainMetadata {
+                sapling_commitment_tree_size: 0,
+                orchard_commitment_tree_size: 0,
+            }),
+            ..Default::default()
+        };
+        scan_block(
+            &Network::TestNetwork,
+            block,
+            &ScanningKeys::<AccountId, (AccountId, zip32::Scope)>::empty(),
+            &Nullifiers::empty(),
+            None,
+        )
+        .unwrap()
+    }
+
+    fn stored_block(height: BlockHeight, hash: BlockHash) -> MemoryWalletBlock {
+        MemoryWalletBlock {
+            height,
+            hash,
+            block_time: 0,
+            _transactions: Default::default(),
+            _memos: HashMap::new(),
+            sapling_commitment_tree_size: Some(0),
+            sapling_output_count: Some(0),
+            #[cfg(feature = "orchard")]
+            orchard_commitment_tree_size: Some(0),
+            #[cfg(feature = "orchard")]
+            orchard_action_count: Some(0),
+        }
+    }
+
+    fn wallet_with_block(height: BlockHeight, hash: BlockHash) -> MemoryWalletDb<Network> {
+        let mut wallet = MemoryWalletDb::new(Network::TestNetwork, 100);
+        wallet.blocks.insert(height, stored_block(height, hash));
+        wallet.scanned_heights.insert(height..height + 1);
+        wallet
+    }
+
+    #[test]
+    fn put_blocks_rejects_mismatched_chain_state_hash() {
+        let height = Network::TestNetwork
+            .activation_height(NetworkUpgrade::Sapling)
+            .unwrap();
+        let mut wallet = wallet_with_block(height, BlockHash([1; 32]));
+        let before = proto::MemoryWallet::from(&wallet);
+
+        let from_state = ChainState::empty(height, BlockHash([2; 32]));
+        let result = wallet.put_blocks(
+            &from_state,
+            vec![scanned_block(height + 1, BlockHash([3; 32]))],
+        );
+
+        assert!(matches!(
+            result,
+            Err(Error::ChainStateMismatch(h, stored, given))
+                if h == height && stored == BlockHash([1; 32]) && given == BlockHash([2; 32])
+        ));
+        assert_eq!(proto::MemoryWallet::from(&wallet), before);
+    }
+
+    #[test]
+    fn put_blocks_rejects_gapped_batch() {
+        let height = Network::TestNetwork
+            .activation_height(NetworkUpgrade::Sapling)
+            .unwrap();
+        let mut wallet = wallet_with_block(height, BlockHash([1; 32]));
+        let before = proto::MemoryWallet::from(&wallet);
+
+        let from_state = ChainState::empty(height, BlockHash([1; 32]));
+        let result = wallet.put_blocks(
+            &from_state,
+            vec![scanned_block(height + 2, BlockHash([3; 32]))],
+        );
+
+        assert!(matches!(
+            result,
+            Err(Error::ChainStateHeightMismatch(state_height, first_height))
+                if state_height == height && first_height == height + 2
+        ));
+        assert_eq!(proto::MemoryWallet::from(&wallet), before);
+    }
+
+    #[test]
+    fn put_blocks_reports_divergence_from_stored_blocks() {
+        let height = Network::TestNetwork
+            .activation_height(NetworkUpgrade::Sapling)
+            .unwrap();
+        let mut wallet = wallet_with_block(height, BlockHash([1; 32]));
+        wallet
+            .blocks
+            .insert(height + 1, stored_block(height + 1, BlockHash([2; 32])));
+        wallet
+            .blocks
+            .insert(height + 2, stored_block(height + 2, BlockHash([3; 32])));
+        let before = proto::MemoryWallet::from(&wallet);
+
+        // A batch from a reorganized chain that shares the block at `height + 1` but diverges
+        // at `height + 2`.
+        let from_state = ChainState::empty(height, BlockHash([1; 32]));
+        let result = wallet.put_blocks(
+            &from_state,
+            vec![
+                scanned_block(height + 1, BlockHash([2; 32])),
+                scanned_block(height + 2, BlockHash([4; 32])),
+                scanned_block(height + 3, BlockHash([5; 32])),
+            ],
+        );
+
+        assert!(matches!(
+            result,
+            Err(Error::PrevHashMismatch { at_height, stored, received })
+                if at_height == height + 2
+                    && stored == BlockHash([3; 32])
+                    && received == BlockHash([4; 32])
+        ));
+        assert_eq!(proto::MemoryWallet::from(&wallet), before);
+    }
+
+    #[test]
+    fn put_blocks_rejects_non_sequential_batch() {
+        let height = Network::TestNetwork
+            .activation_height(NetworkUpgrade::Sapling)
+            .unwrap();
+        let mut wallet = wallet_with_block(height, BlockHash([1; 32]));
+        let before = proto::MemoryWallet::from(&wallet);
+
+        let from_state = ChainState::empty(height, BlockHash([1; 32]));
+        let result = wallet.put_blocks(
+            &from_state,
+            vec![
+                scanned_block(height + 1, BlockHash([2; 32])),
+                scanned_block(height + 3, BlockHash([3; 32])),
+            ],
+        );
+
+        assert!(matches!(result, Err(Error::NonSequentialBlocks)));
+        assert_eq!(proto::MemoryWallet::from(&wallet), before);
+    }
+
+    #[test]
+    fn sent_output_with_out_of_range_index_is_rejected() {
+        let mut wallet = MemoryWalletDb::new(Network::TestNetwork, 100);
+        let before = proto::MemoryWallet::from(&wallet);
+
+        let txid = TxId::from_bytes([7; 32]);
+        let index = usize::from(u16::MAX) + 1;
+        let (_, address) = ExtendedSpendingKey::master(&[0; 32]).default_address();
+        let output = SentTransactionOutput::from_parts(
+            index,
+            Recipient::InternalAccount {
+                receiving_account: AccountId::from(0),
+                external_address: None,
+                note: Note::Sapling(sapling::Note::from_parts(
+                    address,
+                    NoteValue::from_raw(10000),
+                    Rseed::AfterZip212([0; 32]),
+                )),
+            },
+            NonNegativeAmount::const_from_u64(10000),
+            Some(MemoBytes::empty()),
+        );
+
+        assert!(matches!(
+            ReceivedNote::from_sent_tx_output(txid, &output),
+            Err(Error::OutputIndexOutOfRange { txid: t, index: i }) if t == txid && i == index
+        ));
+        assert!(matches!(
+            wallet
+                .sent_notes
+                .put_sent_output(txid, AccountId::from(0), &output),
+            Err(Error::OutputIndexOutOfRange { txid: t, index: i }) if t == txid && i == index
+        ));
+        assert_eq!(proto::MemoryWallet::from(&wallet), before);
+    }
+
+    #[test]
+    fn snapshot_with_out_of_range_output_index_is_rejected() {
+        let height = Network::TestNetwork
+            .activation_height(NetworkUpgrade::Sapling)
+            .unwrap();
+        let wallet = wallet_with_block(height, BlockHash([1; 32]));
+
+        let mut snapshot = proto::MemoryWallet::from(&wallet);
+        snapshot.blocks[0].memos.push(proto::Memo {
+            note_id: Some(proto::NoteId {
+                tx_id: Some(proto::TxId { hash: vec![7; 32] }),
+                pool: proto::PoolType::ShieldedSapling.into(),
+                output_index: u32::from(u16::MAX) + 1,
+            }),
+            memo: MemoBytes::empty().as_slice().to_vec(),
+        });
+
+        assert!(matches!(
+            MemoryWalletDb::decode_new(
+                &snapshot.encode_to_vec()[..],
+                Network::TestNetwork,
+                100
+            ),
+            Err(Error::OutputIndexOutOfRange { index, .. }) if index == usize::from(u16::MAX) + 1
+        ));
+    }
+
+    fn snapshot_with_block_memo(memo: Vec<u8>) -> proto::MemoryWallet {
+        let height = Network::TestNetwork
+            .activation_height(NetworkUpgrade::Sapling)
+            .unwrap();
+        let wallet = wallet_with_block(height, BlockHash([1; 32]));
+
+        let mut snapshot = proto::MemoryWallet::from(&wallet);
+        snapshot.blocks[0].memos.push(proto::Memo {
+            note_id: Some(proto::NoteId {
+                tx_id: Some(proto::TxId { hash: vec![7; 32] }),
+                pool: proto::PoolType::ShieldedSapling.into(),
+                output_index: 0,
+            }),
+            memo,
+        });
+        snapshot
+    }
+
+    #[test]
+    fn snapshot_with_oversized_memo_is_rejected() {
+        let snapshot = snapshot_with_block_memo(vec![0xf6; 513]);
+        assert!(matches!(
+            MemoryWalletDb::decode_new(
+                &snapshot.encode_to_vec()[..],
+                Network::TestNetwork,
+                100
+            ),
+            Err(Error::InvalidMemo { note_id: Some(note_id), len: 513 })
+                if note_id.txid() == &TxId::from_bytes([7; 32])
+        ));
+    }
+
+    #[test]
+    fn memos_are_stored_in_canonical_form() {
+        let reencode = |memo: Vec<u8>| {
+            let snapshot = snapshot_with_block_memo(memo);
+            let wallet = MemoryWalletDb::decode_new(
+                &snapshot.encode_to_vec()[..],
+                Network::TestNetwork,
+                100,
+            )
+            .unwrap();
+            // Compare only the blocks, as the wallet's provenance records when it was restored.
+            proto::MemoryWallet::from(&wallet).blocks
+        };
+
+        let mut padded = b"hello".to_vec();
+        padded.resize(512, 0);
+        assert_eq!(reencode(b"hello".to_vec()), reencode(padded));
+    }
+
+    #[test]
+    fn located_transactions_are_backfilled_as_mined() {
+        use zcash_client_backend::data_api::{
+            testing::TestBuilder, DecryptedTransaction, TransactionStatus, WalletRead,
+        };
+        use zcash_primitives::{
+            consensus::BranchId,
+            transaction::{Authorized, TransactionData, TxVersion},
+        };
+        use zcash_protocol::consensus::BlockHeight;
+
+        use crate::{testing::TestMemDbFactory, MemBlockCache};
+
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+
+        // A transaction that has nothing to do with the wallet is located when its block is
+        // scanned, but is not stored.
+        let tx = TransactionData::<Authorized>::from_parts(
+            TxVersion::Zip225,
+            BranchId::Nu5,
+            0,
+            BlockHeight::from_u32(0),
+            None,
+            None,
+            None,
+            None,
+        )
+        .freeze()
+        .unwrap();
+        let txid = tx.txid();
+        let (h, _) = st.generate_next_block_from_tx(1, &tx);
+        st.scan_cached_blocks(h, 1);
+        assert_eq!(st.wallet().tx_locator.find(&txid), Some((h, 1)));
+        assert!(st.wallet().tx_table.get(&txid).is_none());
+
+        // Storing the transaction without a mined height picks up its location.
+        st.wallet_mut()
+            .store_decrypted_tx(DecryptedTransaction::new(
+                None,
+                &tx,
+                vec![],
+                #[cfg(feature = "orchard")]
+                vec![],
+            ))
+            .unwrap();
+        assert_eq!(st.wallet().get_tx_height(txid).unwrap(), Some(h));
+
+        // Snapshots that record a located transaction as unmined are reconciled on load.
+        st.wallet_mut()
+            .tx_table
+            .set_transaction_status(&txid, TransactionStatus::NotInMainChain)
+            .unwrap();
+        let mut buf = vec![];
+        st.wallet().encode(&mut buf).unwrap();
+        let restored =
+            MemoryWalletDb::decode_new(&buf[..], st.wallet().params.clone(), 100).unwrap();
+        assert_eq!(restored.get_tx_height(txid).unwrap(), Some(h));
+    }
+
+    #[test]
+    #[cfg(feature = "transparent-inputs")]
+    fn unmined_decrypted_transactions_are_marked_mined_when_scanned() {
+        use zcash_client_backend::data_api::{
+            testing::TestBuilder, Account as _, DecryptedTransaction, WalletRead, WalletTest,
+        };
+        use zcash_primitives::{
+            consensus::BranchId,
+            transaction::{
+                components::{transparent, TxOut},
+                Authorized, TransactionData, TxVersion,
+            },
+        };
+        use zcash_protocol::consensus::BlockHeight;
+
+        use crate::{testing::TestMemDbFactory, MemBlockCache};
+
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let account_id = st.test_account().unwrap().id();
+        let taddr = *st
+            .wallet()
+            .get_current_address(account_id)
+            .unwrap()
+            .unwrap()
+            .transparent()
+            .unwrap();
+
+        // A transaction paying the wallet's transparent address is decrypted from the mempool.
+        let tx = TransactionData::<Authorized>::from_parts(
+            TxVersion::Zip225,
+            BranchId::Nu5,
+            0,
+            BlockHeight::from_u32(0),
+            Some(transparent::Bundle {
+                vin: vec![],
+                vout: vec![TxOut {
+                    value: NonNegativeAmount::const_from_u64(100000),
+                    script_pubkey: taddr.script(),
+                }],
+                authorization: transparent::Authorized,
+            }),
+            None,
+            None,
+            None,
+        )
+        .freeze()
+        .unwrap();
+        let txid = tx.txid();
+        st.wallet_mut()
+            .store_decrypted_tx(DecryptedTransaction::new(
+                None,
+                &tx,
+                vec![],
+                #[cfg(feature = "orchard")]
+                vec![],
+            ))
+            .unwrap();
+        let mined_height = |wallet: &MemoryWalletDb<Network>| {
+            wallet
+                .get_tx_history()
+                .unwrap()
+                .into_iter()
+                .find(|summary| summary.txid() == txid)
+                .unwrap()
+                .mined_height()
+        };
+        assert_eq!(mined_height(st.wallet()), None);
+
+        // Scanning the block that mines it does not decrypt anything, but the transaction is
+        // located in the block and so is marked as mined.
+        let (h, _) = st.generate_next_block_from_tx(1, &tx);
+        st.scan_cached_blocks(h, 1);
+        assert_eq!(mined_height(st.wallet()), Some(h));
+        assert_eq!(
+            st.wallet().tx_table.get(&txid).unwrap().mined_location(),
+            Some((h, 1))
+        );
+        st.wallet().check_tx_locations().unwrap();
+    }
+
+    #[test]
+    fn transactions_in_one_block_are_ordered_by_index() {
+        use zcash_client_backend::data_api::{
+            testing::{AddressType, FakeCompactOutput, TestBuilder},
+            WalletTest,
+        };
+        use zcash_primitives::transaction::{components::amount::NonNegativeAmount, TxId};
+
+        use crate::{testing::TestMemDbFactory, MemBlockCache};
+
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let output = FakeCompactOutput::new(
+            dfvk,
+            AddressType::DefaultExternal,
+            NonNegativeAmount::const_from_u64(50000),
+        );
+        let (h, _, _) = st.generate_next_block_multi(&[output.clone(), output]);
+
+        // Split the generated transaction into two wallet transactions in the same block, whose
+        // txids sort in the opposite order to their positions in the block.
+        let first = TxId::from_bytes([0xff; 32]);
+        let second = TxId::from_bytes([0x01; 32]);
+        {
+            let mut blocks = st.cache().blocks.write().unwrap();
+            let block = blocks.get_mut(&h).unwrap();
+            let mut first_tx = block.vtx.remove(0);
+            let mut second_tx = first_tx.clone();
+            second_tx.outputs = vec![first_tx.outputs.pop().unwrap()];
+            first_tx.hash = vec![0xff; 32];
+            first_tx.index = 0;
+            second_tx.hash = vec![0x01; 32];
+            second_tx.index = 1;
+            block.vtx = vec![first_tx, second_tx];
+        }
+        st.scan_cached_blocks(h, 1);
+
+        let history = st.wallet().get_tx_history().unwrap();
+        assert_eq!(
+            history.iter().map(|tx| tx.txid()).collect::<Vec<_>>(),
+            vec![second, first]
+        );
+
+        for (txid, index) in [(first, 0), (second, 1)] {
+            assert_eq!(st.wallet().tx_locator.find(&txid), Some((h, index)));
+            assert_eq!(
+                st.wallet().tx_table.get(&txid).unwrap().mined_location(),
+                Some((h, index))
+            );
+        }
+        st.wallet().check_tx_locations().unwrap();
+
+        // A transaction table entry that disagrees with the locator is detected.
+        st.wallet_mut().tx_locator.insert(h, 0, second);
+        assert!(st.wallet().check_tx_locations().is_err());
+        st.wallet_mut().tx_locator.insert(h, 0, first);
+        st.wallet_mut().tx_locator.insert(h, 1, second);
+
+        // A stale locator for a transaction that the wallet has not stored is ignored.
+        let stale = TxId::from_bytes([0x02; 32]);
+        st.wallet_mut().tx_locator.insert(h, 0, stale);
+        st.wallet().check_tx_locations().unwrap();
+        st.wallet_mut().tx_locator.insert(h, 0, first);
+    }
+
+    #[test]
+    fn received_notes_remain_witnessable_after_pruning() {
+        use incrementalmerkletree::{Hashable, Marking, Position, Retention};
+        use shardtree::{store::memory::MemoryShardStore, LocatedTree, ShardTree};
+        use zcash_client_backend::data_api::SAPLING_SHARD_HEIGHT;
+
+        use super::mark_commitment;
+
+        // Four blocks of four commitments each, checkpointed at the end of every block. The
+        // wallet's notes were scanned without being marked: one in the middle of the first
+        // block, and one at the position of the second block's checkpoint.
+        let start = Position::from(0);
+        let (note, checkpointed_note) = (Position::from(1), Position::from(7));
+        let commitments = || {
+            (0u64..16)
+                .map(|i| {
+                    let retention = if i % 4 == 3 {
+                        Retention::Checkpoint {
+                            id: BlockHeight::from(u32::try_from(i / 4).unwrap() + 1),
+                            marking: Marking::None,
                         }
+                    } else {
+                        Retention::Ephemeral
+                    };
+                    Some((sapling::Node::empty_leaf(), retention))
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Builds a tree retaining only two checkpoints, the way `put_blocks` does, and
+        // returns whether the note at `position` can still be witnessed.
+        fn witnessable(
+            mut commitments: Vec<Option<(sapling::Node, Retention<BlockHeight>)>>,
+            position: Position,
+        ) -> bool {
+            let mut tree = ShardTree::<
+                MemoryShardStore<sapling::Node, BlockHeight>,
+                { sapling::NOTE_COMMITMENT_TREE_DEPTH },
+                SAPLING_SHARD_HEIGHT,
+            >::new(MemoryShardStore::empty(), 2);
+            let end = Position::from(commitments.len() as u64);
+            let res = LocatedTree::from_iter(
+                Position::from(0)..end,
+                SAPLING_SHARD_HEIGHT.into(),
+                commitments.iter_mut().map(|n| n.take().unwrap()),
+            )
+            .unwrap();
+            tree.insert_tree(res.subtree, res.checkpoints).unwrap();
+            matches!(tree.witness_at_checkpoint_depth(position, 0), Ok(Some(_)))
+        }
+
+        // Without marking, the note's leaf is pruned along with the checkpoints of the first
+        // blocks.
+        assert!(!witnessable(commitments(), note));
+
+        let mut marked = commitments();
+        mark_commitment(&mut marked, start, note);
+        mark_commitment(&mut marked, start, checkpointed_note);
+        assert_eq!(marked[1].as_ref().unwrap().1, Retention::Marked);
+        assert_eq!(
+            marked[7].as_ref().unwrap().1,
+            Retention::Checkpoint {
+                id: BlockHeight::from(2),
+                marking: Marking::Marked,
+            }
+        );
+        assert_eq!(marked[0].as_ref().unwrap().1, Retention::Ephemeral);
+
+        // Positions outside of the batch are left alone.
+        let mut unchanged = commitments();
+        mark_commitment(&mut unchanged, start + 4, Position::from(2));
+        mark_commitment(&mut unchanged, start, Position::from(16));
+        assert_eq!(unchanged, commitments());
+
+        assert!(witnessable(marked.clone(), note));
+        assert!(witnessable(marked, checkpointed_note));
+    }
+
+    #[test]
+    fn truncation_removes_nullifier_locators_above_height() {
+        use zcash_client_backend::data_api::testing::{AddressType, TestBuilder};
+
+        use crate::{testing::TestMemDbFactory, MemBlockCache, Nullifier};
+
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let value = NonNegativeAmount::const_from_u64(50000);
+        let (h, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        st.scan_cached_blocks(h, 3);
+
+        let retained = Nullifier::Sapling(sapling::Nullifier([1; 32]));
+        let removed = Nullifier::Sapling(sapling::Nullifier([2; 32]));
+        st.wallet_mut().nullifiers.insert(h + 1, 0, retained);
+        st.wallet_mut().nullifiers.insert(h + 2, 0, removed);
+
+        assert_eq!(st.wallet_mut().truncate_to_height(h + 1).unwrap(), h + 1);
+        assert_eq!(st.wallet().nullifiers.get(&retained), Some(&(h + 1, 0)));
+        assert_eq!(st.wallet().nullifiers.get(&removed), None);
+    }
+
+    #[test]
+    fn truncated_blocks_can_be_rescanned_with_different_transactions() {
+        use zcash_client_backend::data_api::testing::{AddressType, TestBuilder};
+
+        use crate::{testing::TestMemDbFactory, MemBlockCache};
+
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let value = NonNegativeAmount::const_from_u64(50000);
+        let (h, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        st.scan_cached_blocks(h, 2);
+        let reorged = *st.wallet().tx_locator.get(h + 1, 0).unwrap();
+
+        st.truncate_to_height(h);
+        assert_eq!(st.wallet().tx_locator.get(h + 1, 0), None);
+
+        // The replacement block holds a different transaction at the same index.
+        let value = NonNegativeAmount::const_from_u64(60000);
+        st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        st.scan_cached_blocks(h + 1, 1);
+        let replacement = *st.wallet().tx_locator.get(h + 1, 0).unwrap();
+        assert_ne!(replacement, reorged);
+        assert_eq!(
+            st.wallet()
+                .tx_table
+                .get(&replacement)
+                .unwrap()
+                .mined_location(),
+            Some((h + 1, 0))
+        );
+    }
+
+    #[cfg(feature = "transparent-inputs")]
+    #[test]
+    fn put_received_transparent_utxo_is_idempotent() {
+        use zcash_client_backend::{
+            data_api::{testing::TestBuilder, Account as _, WalletRead},
+            wallet::WalletTransparentOutput,
+        };
+        use zcash_primitives::transaction::components::{OutPoint, TxOut};
+
+        use crate::{testing::TestMemDbFactory, MemBlockCache};
+
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let account_id = st.test_account().unwrap().id();
+        let taddr = *st
+            .wallet()
+            .get_current_address(account_id)
+            .unwrap()
+            .unwrap()
+            .transparent()
+            .unwrap();
+
+        let utxo = |mined_height| {
+            WalletTransparentOutput::from_parts(
+                OutPoint::new([1; 32], 0),
+                TxOut {
+                    value: NonNegativeAmount::const_from_u64(100000),
+                    script_pubkey: taddr.script(),
+                },
+                mined_height,
+            )
+            .unwrap()
+        };
+        let mined_height = st.sapling_activation_height() + 10;
+        let max_observed = |wallet: &MemoryWalletDb<_>| {
+            wallet
+                .transparent_received_outputs
+                .get(&OutPoint::new([1; 32], 0))
+                .unwrap()
+                .max_observed_unspent_height
+        };
+
+        let first = st
+            .wallet_mut()
+            .put_received_transparent_utxo(&utxo(None))
+            .unwrap();
+        assert_eq!(max_observed(st.wallet()), None);
+
+        // Observing that the output was spent records the height below the spend.
+        let spending_txid = TxId::from_bytes([2; 32]);
+        st.wallet_mut()
+            .tx_table
+            .put_tx_partial(&spending_txid, &None, Some(mined_height + 5));
+        st.wallet_mut()
+            .transparent_received_output_spends
+            .insert(first.clone(), spending_txid);
+
+        let second = st
+            .wallet_mut()
+            .put_received_transparent_utxo(&utxo(Some(mined_height)))
+            .unwrap();
+        assert_eq!(first, second);
+        assert_eq!(st.wallet().transparent_received_outputs.len(), 1);
+        assert_eq!(max_observed(st.wallet()), Some(mined_height + 4));
+        assert_eq!(
+            st.wallet()
+                .tx_table
+                .get(first.txid())
+                .and_then(|tx| tx.mined_height()),
+            Some(mined_height)
+        );
+    }
+
+    #[cfg(feature = "transparent-inputs")]
+    #[test]
+    fn received_transparent_utxo_spend_is_searched_for() {
+        use zcash_client_backend::{
+            data_api::{testing::TestBuilder, Account as _, TransactionDataRequest, WalletRead},
+            wallet::WalletTransparentOutput,
+        };
+        use zcash_primitives::transaction::components::{OutPoint, TxOut};
+
+        use crate::{testing::TestMemDbFactory, MemBlockCache};
+
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let account_id = st.test_account().unwrap().id();
+        let taddr = *st
+            .wallet()
+            .get_current_address(account_id)
+            .unwrap()
+            .unwrap()
+            .transparent()
+            .unwrap();
+
+        let mined_height = st.sapling_activation_height() + 10;
+        let utxo = WalletTransparentOutput::from_parts(
+            OutPoint::new([1; 32], 0),
+            TxOut {
+                value: NonNegativeAmount::const_from_u64(100000),
+                script_pubkey: taddr.script(),
+            },
+            Some(mined_height),
+        )
+        .unwrap();
+        let outpoint = st
+            .wallet_mut()
+            .put_received_transparent_utxo(&utxo)
+            .unwrap();
+
+        let is_searched_for = |wallet: &MemoryWalletDb<_>| {
+            wallet.transaction_data_requests().unwrap().iter().any(|r| {
+                matches!(
+                    r,
+                    TransactionDataRequest::SpendsFromAddress {
+                        address,
+                        block_range_start,
+                        ..
+                    } if *address == taddr && *block_range_start == mined_height
+                )
+            })
+        };
+        assert!(is_searched_for(st.wallet()));
+
+        // Once the spend has been observed, the search is no longer needed.
+        st.wallet_mut()
+            .mark_transparent_output_spent(&TxId::from_bytes([2; 32]), &outpoint)
+            .unwrap();
+        assert!(!is_searched_for(st.wallet()));
+    }
+
+    #[cfg(feature = "transparent-inputs")]
+    #[test]
+    fn transparent_spend_searches_survive_serialization() {
+        use zcash_client_backend::{
+            data_api::{testing::TestBuilder, Account as _, TransactionDataRequest, WalletRead},
+            wallet::WalletTransparentOutput,
+        };
+        use zcash_primitives::transaction::components::{OutPoint, TxOut};
+
+        use crate::{testing::TestMemDbFactory, MemBlockCache};
+
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let account_id = st.test_account().unwrap().id();
+        let taddr = *st
+            .wallet()
+            .get_current_address(account_id)
+            .unwrap()
+            .unwrap()
+            .transparent()
+            .unwrap();
+        let utxo = WalletTransparentOutput::from_parts(
+            OutPoint::new([1; 32], 0),
+            TxOut {
+                value: NonNegativeAmount::const_from_u64(100000),
+                script_pubkey: taddr.script(),
+            },
+            Some(st.sapling_activation_height() + 10),
+        )
+        .unwrap();
+        st.wallet_mut()
+            .put_received_transparent_utxo(&utxo)
+            .unwrap();
+
+        let mut buf = vec![];
+        st.wallet().encode(&mut buf).unwrap();
+        let restored = MemoryWalletDb::decode_new(&buf[..], *st.network(), 100).unwrap();
+
+        let requests = restored.transaction_data_requests().unwrap();
+        assert_eq!(requests, st.wallet().transaction_data_requests().unwrap());
+        assert!(requests.iter().any(|request| matches!(
+            request,
+            TransactionDataRequest::SpendsFromAddress { address, .. } if *address == taddr
+        )));
+    }
+
+    #[cfg(feature = "transparent-inputs")]
+    #[test]
+    fn ephemeral_gap_advances_when_seen_transaction_is_mined() {
+        use zcash_client_backend::data_api::{
+            testing::TestBuilder, Account as _, TransactionStatus, WalletRead, GAP_LIMIT,
+        };
+        use zcash_primitives::legacy::keys::TransparentKeyScope;
+
+        use crate::{testing::TestMemDbFactory, MemBlockCache};
+
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let account_id = st.test_account().unwrap().id();
+        let gap_limit = usize::try_from(GAP_LIMIT).unwrap();
+
+        let reserved = st
+            .wallet_mut()
+            .reserve_next_n_ephemeral_addresses(account_id, gap_limit)
+            .unwrap();
+        assert_eq!(reserved.len(), gap_limit);
+        assert!(reserved
+            .iter()
+            .all(|(_, meta)| meta.scope() == TransparentKeyScope::EPHEMERAL));
+        assert!(matches!(
+            st.wallet_mut().reserve_next_n_ephemeral_addresses(account_id, 1),
+            Err(Error::ReachedGapLimit { account, bad_index })
+            if account == account_id && bad_index == GAP_LIMIT
+        ));
+
+        // An output to the address at index 4 in an unmined transaction does not make any
+        // further addresses safe to reserve.
+        let address = reserved[4].0;
+        let seen_txid = TxId::from_bytes([1; 32]);
+        let later_txid = TxId::from_bytes([2; 32]);
+        let mined_height = st.sapling_activation_height() + 1;
+        {
+            let wallet = st.wallet_mut();
+            wallet.tx_table.put_tx_partial(&seen_txid, &None, None);
+            wallet
+                .tx_table
+                .put_tx_partial(&later_txid, &None, Some(mined_height + 1));
+            wallet
+                .accounts
+                .mark_ephemeral_address_as_seen(&wallet.tx_table, &address, seen_txid)
+                .unwrap();
+        }
+        assert!(st
+            .wallet_mut()
+            .reserve_next_n_ephemeral_addresses(account_id, 1)
+            .is_err());
+
+        // Once the transaction is mined, the gap advances past the seen address.
+        st.wallet_mut()
+            .set_transaction_status(seen_txid, TransactionStatus::Mined(mined_height))
+            .unwrap();
+        assert_eq!(
+            st.wallet_mut()
+                .reserve_next_n_ephemeral_addresses(account_id, 5)
+                .unwrap()
+                .len(),
+            5
+        );
+        assert!(matches!(
+            st.wallet_mut().reserve_next_n_ephemeral_addresses(account_id, 1),
+            Err(Error::ReachedGapLimit { bad_index, .. }) if bad_index == 5 + GAP_LIMIT
+        ));
+
+        // A later observation in a transaction mined afterwards does not replace the earlier one.
+        {
+            let wallet = st.wallet_mut();
+            wallet
+                .accounts
+                .mark_ephemeral_address_as_seen(&wallet.tx_table, &address, later_txid)
+                .unwrap();
+        }
+        assert_eq!(
+            st.wallet()
+                .accounts
+                .get(account_id)
+                .unwrap()
+                .ephemeral_addresses[&4]
+                .seen,
+            Some(seen_txid)
+        );
+        assert_eq!(
+            st.wallet()
+                .get_known_ephemeral_addresses(account_id, Some(4..6))
+                .unwrap()
+                .iter()
+                .map(|(_, meta)| meta.address_index().index())
+                .collect::<Vec<_>>(),
+            vec![4, 5]
+        );
+    }
+
+    #[cfg(feature = "transparent-inputs")]
+    #[test]
+    fn sent_ephemeral_outputs_report_ephemeral_index() {
+        use zcash_client_backend::data_api::{
+            testing::TestBuilder, Account as _, OutputOfSentTx, WalletTest,
+        };
+        use zcash_primitives::{legacy::TransparentAddress, transaction::components::OutPoint};
+        use zcash_protocol::memo::Memo;
+
+        use crate::{testing::TestMemDbFactory, MemBlockCache, SentNote, SentNoteId};
+
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let account_id = st.test_account().unwrap().id();
+        let ephemeral_address = st
+            .wallet_mut()
+            .reserve_next_n_ephemeral_addresses(account_id, 3)
+            .unwrap()[2]
+            .0;
+
+        let value = NonNegativeAmount::const_from_u64(10000);
+        let send_to = |wallet: &mut MemoryWalletDb<_>, txid: TxId, address| {
+            wallet.sent_notes.0.insert(
+                SentNoteId::Transparent {
+                    txid,
+                    output_index: 0,
+                },
+                SentNote {
+                    from_account_id: account_id,
+                    to: Recipient::EphemeralTransparent {
+                        receiving_account: account_id,
+                        ephemeral_address: address,
+                        outpoint_metadata: OutPoint::new(*txid.as_ref(), 0),
                     },
+                    value,
+                    memo: Memo::Empty,
+                },
+            );
+        };
+
+        let txid = TxId::from_bytes([1; 32]);
+        send_to(st.wallet_mut(), txid, ephemeral_address);
+        let outputs = st.wallet().get_sent_outputs(&txid).unwrap();
+        assert_eq!(
+            format!("{:?}", outputs),
+            format!(
+                "{:?}",
+                vec![OutputOfSentTx::from_parts(
+                    value,
+                    Some(ephemeral_address.into()),
+                    Some((ephemeral_address.into(), 2)),
+                )]
+            )
+        );
+
+        // An ephemeral address that the account never reserved is reported as an error.
+        let unknown = TransparentAddress::PublicKeyHash([7; 20]);
+        let txid = TxId::from_bytes([2; 32]);
+        send_to(st.wallet_mut(), txid, unknown);
+        assert!(matches!(
+            st.wallet().get_sent_outputs(&txid),
+            Err(Error::AddressNotRecognized(address)) if address == unknown
+        ));
+    }
+
+    #[cfg(feature = "hd-derivation")]
+    #[test]
+    fn derived_accounts_match_the_seed_derivation() {
+        use secrecy::{ExposeSecret, SecretVec};
+        use zcash_client_backend::{
+            data_api::{Account as _, AccountBirthday, AccountSource, WalletRead},
+            keys::UnifiedSpendingKey,
+        };
+        use zip32::fingerprint::SeedFingerprint;
+
+        let params = Network::TestNetwork;
+        let mut wallet = MemoryWalletDb::new(params, 100);
+        let seed = SecretVec::new(vec![1u8; 32]);
+        let birthday = AccountBirthday::from_sapling_activation(&params, BlockHash([0; 32]));
+        let expected_ufvk = |index| {
+            UnifiedSpendingKey::from_seed(&params, seed.expose_secret(), index)
+                .unwrap()
+                .to_unified_full_viewing_key()
+                .encode(&params)
+        };
+
+        let (account_id, usk) = wallet.create_account(&seed, &birthday).unwrap();
+        assert_eq!(
+            usk.to_unified_full_viewing_key().encode(&params),
+            expected_ufvk(zip32::AccountId::ZERO)
+        );
+        assert_eq!(
+            wallet
+                .get_account(account_id)
+                .unwrap()
+                .unwrap()
+                .ufvk()
+                .unwrap()
+                .encode(&params),
+            expected_ufvk(zip32::AccountId::ZERO)
+        );
+
+        let index = zip32::AccountId::try_from(5).unwrap();
+        let (account, _) = wallet.import_account_hd(&seed, index, &birthday).unwrap();
+        assert_eq!(
+            account.ufvk().unwrap().encode(&params),
+            expected_ufvk(index)
+        );
+        assert_eq!(
+            account.source(),
+            AccountSource::Derived {
+                seed_fingerprint: SeedFingerprint::from_seed(seed.expose_secret()).unwrap(),
+                account_index: index,
+            }
+        );
+        assert_eq!(
+            wallet
+                .get_derived_account(
+                    &SeedFingerprint::from_seed(seed.expose_secret()).unwrap(),
+                    index
                 )
-                .into_iter()
-        })
-        .collect::<Vec<_>>()
+                .unwrap()
+                .map(|a| a.id()),
+            Some(account.id())
+        );
+
+        // Importing an index that has already been derived from the seed is rejected.
+        assert!(matches!(
+            wallet.import_account_hd(&seed, zip32::AccountId::ZERO, &birthday),
+            Err(Error::AccountCollision(id)) if id == account_id
+        ));
+    }
+
+    #[cfg(feature = "hd-derivation")]
+    #[test]
+    fn accounts_created_from_a_seed_use_consecutive_indices() {
+        use secrecy::{ExposeSecret, SecretVec};
+        use zcash_client_backend::data_api::{
+            Account as _, AccountBirthday, AccountSource, WalletRead,
+        };
+        use zip32::fingerprint::SeedFingerprint;
+
+        let params = Network::TestNetwork;
+        let mut wallet = MemoryWalletDb::new(params, 100);
+        let seed = SecretVec::new(vec![1u8; 32]);
+        let seed_fingerprint = SeedFingerprint::from_seed(seed.expose_secret()).unwrap();
+        let birthday = AccountBirthday::from_sapling_activation(&params, BlockHash([0; 32]));
+
+        let (first, first_usk) = wallet.create_account(&seed, &birthday).unwrap();
+        let (second, second_usk) = wallet.create_account(&seed, &birthday).unwrap();
+        assert_ne!(first, second);
+        assert_ne!(
+            first_usk.to_unified_full_viewing_key().encode(&params),
+            second_usk.to_unified_full_viewing_key().encode(&params)
+        );
+
+        let source = |id| wallet.get_account(id).unwrap().unwrap().source();
+        assert_eq!(
+            source(first),
+            AccountSource::Derived {
+                seed_fingerprint,
+                account_index: zip32::AccountId::ZERO,
+            }
+        );
+        assert_eq!(
+            source(second),
+            AccountSource::Derived {
+                seed_fingerprint,
+                account_index: zip32::AccountId::ZERO.next().unwrap(),
+            }
+        );
+    }
+
+    #[cfg(not(feature = "hd-derivation"))]
+    #[test]
+    fn seed_derivation_requires_feature() {
+        use secrecy::SecretVec;
+        use zcash_client_backend::data_api::AccountBirthday;
+
+        let params = Network::TestNetwork;
+        let mut wallet = MemoryWalletDb::new(params, 100);
+        let seed = SecretVec::new(vec![1u8; 32]);
+        let birthday = AccountBirthday::from_sapling_activation(&params, BlockHash([0; 32]));
+
+        assert!(matches!(
+            wallet.create_account(&seed, &birthday),
+            Err(Error::HdDerivationDisabled)
+        ));
+        assert!(matches!(
+            wallet.import_account_hd(&seed, zip32::AccountId::ZERO, &birthday),
+            Err(Error::HdDerivationDisabled)
+        ));
+        assert!(wallet.accounts.is_empty());
+    }
+
+    #[cfg(feature = "orchard")]
+    #[test]
+    fn ensure_checkpoints_do_not_exceed_inserted_positions() {
+        use std::collections::BTreeMap;
+
+        use incrementalmerkletree::{
+            frontier::{Frontier, NonEmptyFrontier},
+            Hashable, Position,
+        };
+        use shardtree::store::Checkpoint;
+
+        use super::ensure_checkpoints;
+
+        // A frontier at position 99, ahead of the 50 commitments inserted for the batch.
+        let mut frontier = NonEmptyFrontier::new(sapling::Node::empty_leaf());
+        for _ in 0..99 {
+            frontier.append(sapling::Node::empty_leaf());
+        }
+        let frontier =
+            Frontier::<_, { sapling::NOTE_COMMITMENT_TREE_DEPTH }>::try_from(frontier).unwrap();
+
+        let h = BlockHeight::from(100);
+        let existing = BTreeMap::from([(h + 2, Position::from(60))]);
+        let sizes = BTreeMap::from([(h, Some(50)), (h + 1, Some(50)), (h + 2, Some(61))]);
+        assert_eq!(
+            ensure_checkpoints([h, h + 1, h + 2].iter(), &existing, &sizes, &frontier),
+            vec![
+                (h, Checkpoint::at_position(Position::from(49))),
+                (h + 1, Checkpoint::at_position(Position::from(49))),
+            ]
+        );
+
+        // A batch that inserted no commitments can only be checkpointed at the empty tree.
+        let sizes = BTreeMap::from([(h, Some(0))]);
+        assert_eq!(
+            ensure_checkpoints([h].iter(), &BTreeMap::new(), &sizes, &frontier),
+            vec![(h, Checkpoint::tree_empty())]
+        );
+    }
+
+    #[cfg(feature = "orchard")]
+    #[test]
+    fn chain_tip_range_starts_at_the_lowest_shard_tip() {
+        use incrementalmerkletree::Address;
+        use zcash_client_backend::{
+            data_api::{
+                scanning::ScanPriority, AccountBirthday, AccountPurpose, WalletRead,
+                ORCHARD_SHARD_HEIGHT, SAPLING_SHARD_HEIGHT,
+            },
+            keys::UnifiedSpendingKey,
+        };
+
+        let params = Network::TestNetwork;
+        let prior_height = params.activation_height(NetworkUpgrade::Nu5).unwrap();
+        let birthday =
+            AccountBirthday::from_parts(ChainState::empty(prior_height, BlockHash([0; 32])), None);
+        let ufvk = UnifiedSpendingKey::from_seed(&params, &[1; 32], zip32::AccountId::ZERO)
+            .unwrap()
+            .to_unified_full_viewing_key();
+        let mut wallet = MemoryWalletDb::new(params, 100);
+        wallet
+            .import_account_ufvk(&ufvk, &birthday, AccountPurpose::ViewOnly)
+            .unwrap();
+
+        // The Orchard tree's last complete shard ends below that of the Sapling tree, so the
+        // Orchard notes above it cannot be witnessed until the rest of the shard is scanned.
+        let h = birthday.height();
+        wallet
+            .sapling_tree_shard_end_heights
+            .insert(Address::from_parts(SAPLING_SHARD_HEIGHT.into(), 0), h + 200);
+        wallet
+            .orchard_tree_shard_end_heights
+            .insert(Address::from_parts(ORCHARD_SHARD_HEIGHT.into(), 0), h + 100);
+
+        wallet.update_chain_tip(h + 300).unwrap();
+
+        let ranges = wallet.suggest_scan_ranges().unwrap();
+        assert_eq!(ranges[0].block_range(), &((h + 100)..(h + 301)));
+        assert_eq!(ranges[0].priority(), ScanPriority::ChainTip);
+    }
+
+    #[cfg(feature = "orchard")]
+    #[test]
+    fn checkpoints_are_witnessable_after_mid_shard_start() {
+        use std::num::NonZeroU8;
+
+        use incrementalmerkletree::frontier::Frontier;
+        use zcash_client_backend::data_api::{
+            chain::CommitmentTreeRoot,
+            testing::{AddressType, FakeCompactOutput, InitialChainState, TestBuilder},
+        };
+
+        use crate::{testing::TestMemDbFactory, MemBlockCache};
+
+        // Start scanning 5 notes into the fourth shard of each tree, after the roots of the
+        // prior subtrees have been inserted.
+        let prior_block_hash = BlockHash([0; 32]);
+        let initial_tree_size: u32 = (0x1 << 16) * 3 + 5;
+        let initial_height_offset = 310;
+
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestMemDbFactory::new())
+            .with_block_cache(MemBlockCache::new())
+            .with_initial_chain_state(|rng, network| {
+                let sapling_activation_height =
+                    network.activation_height(NetworkUpgrade::Sapling).unwrap();
+
+                let (prior_sapling_roots, sapling_initial_tree) =
+                    Frontier::random_with_prior_subtree_roots(
+                        rng,
+                        initial_tree_size.into(),
+                        NonZeroU8::new(16).unwrap(),
+                    );
+                let prior_sapling_roots = prior_sapling_roots
+                    .into_iter()
+                    .zip(1u32..)
+                    .map(|(root, i)| {
+                        CommitmentTreeRoot::from_parts(sapling_activation_height + (100 * i), root)
+                    })
+                    .collect::<Vec<_>>();
+
+                let (prior_orchard_roots, orchard_initial_tree) =
+                    Frontier::random_with_prior_subtree_roots(
+                        rng,
+                        initial_tree_size.into(),
+                        NonZeroU8::new(16).unwrap(),
+                    );
+                let prior_orchard_roots = prior_orchard_roots
+                    .into_iter()
+                    .zip(1u32..)
+                    .map(|(root, i)| {
+                        CommitmentTreeRoot::from_parts(sapling_activation_height + (100 * i), root)
+                    })
+                    .collect::<Vec<_>>();
+
+                InitialChainState {
+                    chain_state: ChainState::new(
+                        sapling_activation_height + initial_height_offset - 1,
+                        prior_block_hash,
+                        sapling_initial_tree,
+                        orchard_initial_tree,
+                    ),
+                    prior_sapling_roots,
+                    prior_orchard_roots,
+                }
+            })
+            .with_account_having_current_birthday()
+            .build();
+
+        // Alternate the pool that receives a note, so that each block is missing a
+        // checkpoint in one of the trees that must be created by `ensure_checkpoints`.
+        let sapling_fvk = st.test_account_sapling().unwrap().clone();
+        let orchard_fvk = st.test_account_orchard().unwrap().clone();
+        let value = NonNegativeAmount::const_from_u64(50000);
+        let initial_height = st.sapling_activation_height() + initial_height_offset;
+        st.generate_block_at(
+            initial_height,
+            prior_block_hash,
+            &[FakeCompactOutput::new(
+                sapling_fvk.clone(),
+                AddressType::DefaultExternal,
+                value,
+            )],
+            initial_tree_size,
+            initial_tree_size,
+            false,
+        );
+        st.generate_next_block(&orchard_fvk, AddressType::DefaultExternal, value);
+        st.generate_next_block(&sapling_fvk, AddressType::DefaultExternal, value);
+        st.scan_cached_blocks(initial_height, 3);
+
+        let wallet = st.wallet();
+        assert_eq!(wallet.received_notes.len(), 3);
+        for offset in 0..3 {
+            let height = initial_height + offset;
+            let sapling_root = wallet
+                .sapling_tree
+                .root_at_checkpoint_id(&height)
+                .unwrap()
+                .expect("a Sapling checkpoint exists at every scanned height");
+            let orchard_root = wallet
+                .orchard_tree
+                .root_at_checkpoint_id(&height)
+                .unwrap()
+                .expect("an Orchard checkpoint exists at every scanned height");
+
+            for note in wallet.received_notes.iter() {
+                let mined_height = wallet
+                    .tx_table
+                    .get(&note.txid)
+                    .and_then(|tx| tx.mined_height())
+                    .unwrap();
+                if mined_height > height {
+                    continue;
+                }
+                let position = note.commitment_tree_position.unwrap();
+                match note.note {
+                    Note::Sapling(_) => {
+                        let leaf = wallet.sapling_tree.get_marked_leaf(position).unwrap();
+                        let path = wallet
+                            .sapling_tree
+                            .witness_at_checkpoint_id(position, &height)
+                            .unwrap();
+                        assert_eq!(path.unwrap().root(leaf.unwrap()), sapling_root);
+                    }
+                    Note::Orchard(_) => {
+                        let leaf = wallet.orchard_tree.get_marked_leaf(position).unwrap();
+                        let path = wallet
+                            .orchard_tree
+                            .witness_at_checkpoint_id(position, &height)
+                            .unwrap();
+                        assert_eq!(path.unwrap().root(leaf.unwrap()), orchard_root);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Compares the throughput of scanning and storing a large number of blocks that pay the
+    /// wallet when the state of received outputs is resolved sequentially and when it is
+    /// resolved in parallel. The timings are logged at the `info` level; run with
+    /// `cargo test --release -- --ignored scan_throughput` under a `tracing` subscriber.
+    #[test]
+    #[ignore]
+    fn scan_throughput() {
+        use std::time::Instant;
+
+        use zcash_client_backend::data_api::testing::{AddressType, FakeCompactOutput};
+
+        use crate::{testing::test_state, ScanTuning};
+
+        let mut st = test_state();
+        let params = st.wallet().params.clone();
+        let mut unscanned = vec![];
+        st.wallet().encode(&mut unscanned).unwrap();
+
+        let dfvk = st.test_account_sapling().unwrap().clone();
+        let not_our_key = ExtendedSpendingKey::master(&[]).to_diversifiable_full_viewing_key();
+        let value = NonNegativeAmount::const_from_u64(10000);
+        let outputs = [
+            FakeCompactOutput::new(dfvk, AddressType::DefaultExternal, value),
+            FakeCompactOutput::new(not_our_key, AddressType::DefaultExternal, value),
+        ];
+
+        let block_count = 10_000u32;
+        let batch_size = 1_000;
+        let (from, _, _) = st.generate_next_block_multi(&outputs);
+        for _ in 1..block_count {
+            st.generate_next_block_multi(&outputs);
+        }
+
+        // Scans every block into a copy of the unscanned wallet.
+        let mut scan = |use_parallelism: bool| {
+            *st.wallet_mut() = MemoryWalletDb::decode_new(&unscanned[..], params.clone(), 100)
+                .unwrap()
+                .with_scan_tuning(ScanTuning::default().with_parallelism(use_parallelism));
+            let start = Instant::now();
+            for i in (0..block_count).step_by(batch_size) {
+                st.scan_cached_blocks(from + i, batch_size);
+            }
+            let elapsed = start.elapsed();
+            assert_eq!(st.wallet().received_notes.len(), block_count as usize);
+            (elapsed, st.wallet().received_notes.clone())
+        };
+        let (sequential, sequential_notes) = scan(false);
+        let (parallel, parallel_notes) = scan(true);
+        assert_eq!(parallel_notes, sequential_notes);
+
+        for (path, elapsed) in [("sequential", sequential), ("parallel", parallel)] {
+            tracing::info!(
+                "{} scan of {} blocks took {:?} ({:.0} blocks/s)",
+                path,
+                block_count,
+                elapsed,
+                f64::from(block_count) / elapsed.as_secs_f64()
+            );
+        }
+        tracing::info!(
+            "parallel resolution speedup: {:.2}x",
+            sequential.as_secs_f64() / parallel.as_secs_f64()
+        );
+    }
 }
@@ -16,7 +16,10 @@ use crate::{wallet::commitment_tree, AccountUuid};
 #[cfg(feature = "transparent-inputs")]
 use {
     zcash_client_backend::encoding::TransparentCodecError,
-    zcash_primitives::{legacy::TransparentAddress, transaction::TxId},
+    zcash_primitives::{
+        legacy::TransparentAddress,
+        transaction::{components::OutPoint, TxId},
+    },
 };
 
 /// The primary error type for the SQLite wallet backend.
@@ -131,6 +134,16 @@ pub enum SqliteClientError {
     /// used.
     #[cfg(feature = "transparent-inputs")]
     EphemeralAddressReuse(String, TxId),
+
+    /// The transparent output with the given outpoint is not known to the wallet.
+    #[cfg(feature = "transparent-inputs")]
+    TransparentOutputNotFound(OutPoint),
+
+    /// The transparent output with the given outpoint is spent by the wallet-created
+    /// transaction with the given txid, and cannot be removed until that transaction has been
+    /// removed.
+    #[cfg(feature = "transparent-inputs")]
+    TransparentOutputSpentByWalletTx(OutPoint, TxId),
 }
 
 impl error::Error for SqliteClientError {
@@ -193,6 +206,10 @@ impl fmt::Display for SqliteClientError {
             ),
             #[cfg(feature = "transparent-inputs")]
             SqliteClientError::EphemeralAddressReuse(address_str, txid) => write!(f, "The ephemeral address {address_str} previously used in txid {txid} would be reused."),
+            #[cfg(feature = "transparent-inputs")]
+            SqliteClientError::TransparentOutputNotFound(outpoint) => write!(f, "The transparent output {}:{} is not known to the wallet.", outpoint.txid(), outpoint.n()),
+            #[cfg(feature = "transparent-inputs")]
+            SqliteClientError::TransparentOutputSpentByWalletTx(outpoint, txid) => write!(f, "The transparent output {}:{} is spent by transaction {txid} created by this wallet, which must be removed first.", outpoint.txid(), outpoint.n()),
         }
     }
 }
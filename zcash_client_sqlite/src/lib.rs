@@ -246,6 +246,7 @@ struct TxRef(pub i64);
 pub struct WalletDb<C, P> {
     conn: C,
     params: P,
+    allow_zero_conf_spends: bool,
 }
 
 /// A wrapper for a SQLite transaction affecting the wallet database.
@@ -262,7 +263,11 @@ impl<P: consensus::Parameters + Clone> WalletDb<Connection, P> {
     pub fn for_path<F: AsRef<Path>>(path: F, params: P) -> Result<Self, rusqlite::Error> {
         Connection::open(path).and_then(move |conn| {
             rusqlite::vtab::array::load_module(&conn)?;
-            Ok(WalletDb { conn, params })
+            Ok(WalletDb {
+                conn,
+                params,
+                allow_zero_conf_spends: false,
+            })
         })
     }
 
@@ -274,6 +279,7 @@ impl<P: consensus::Parameters + Clone> WalletDb<Connection, P> {
         let mut wdb = WalletDb {
             conn: SqlTransaction(&tx),
             params: self.params.clone(),
+            allow_zero_conf_spends: self.allow_zero_conf_spends,
         };
         let result = f(&mut wdb)?;
         tx.commit()?;
@@ -281,6 +287,109 @@ impl<P: consensus::Parameters + Clone> WalletDb<Connection, P> {
     }
 }
 
+impl<C, P> WalletDb<C, P> {
+    /// Returns the wallet with zero-conf spends of transparent outputs allowed or disallowed.
+    ///
+    /// By default, a transparent output is only spendable once the transaction that created
+    /// it has been mined. If zero-conf spends are allowed, a caller that requests
+    /// `min_confirmations = 0` may also spend the outputs of an unmined transaction that
+    /// cannot expire or has not expired as of the target height, such as the change used to
+    /// fund a transaction to a TEX address.
+    ///
+    /// This is a property of the open wallet, and is not stored in the database.
+    pub fn with_zero_conf_spends_allowed(mut self, allow: bool) -> Self {
+        self.allow_zero_conf_spends = allow;
+        self
+    }
+
+    /// Allows or disallows zero-conf spends of transparent outputs. See
+    /// [`WalletDb::with_zero_conf_spends_allowed`].
+    pub fn set_allow_zero_conf_spends(&mut self, allow: bool) {
+        self.allow_zero_conf_spends = allow;
+    }
+
+    /// Returns whether zero-conf spends of transparent outputs are allowed.
+    pub fn allows_zero_conf_spends(&self) -> bool {
+        self.allow_zero_conf_spends
+    }
+}
+
+#[cfg(feature = "transparent-inputs")]
+impl<C: Borrow<rusqlite::Connection>, P: consensus::Parameters> WalletDb<C, P> {
+    /// Returns the key derivation metadata needed to sign for each of the given transparent
+    /// outputs, in the order in which the outpoints were provided.
+    ///
+    /// Each outpoint must refer to an output known to the wallet. If the receiving address of
+    /// an output cannot be resolved for the given account, this returns
+    /// [`SqliteClientError::AddressNotRecognized`] naming that address.
+    pub fn metadata_for_outpoints(
+        &self,
+        account: AccountUuid,
+        outpoints: &[OutPoint],
+    ) -> Result<Vec<(OutPoint, TransparentAddressMetadata)>, SqliteClientError> {
+        wallet::transparent::get_metadata_for_outpoints(
+            self.conn.borrow(),
+            &self.params,
+            account,
+            outpoints,
+        )
+    }
+
+    /// Returns the transparent receivers of the given account, as
+    /// [`WalletRead::get_transparent_receivers`] does.
+    ///
+    /// If `include_internal` is set, this also returns the internal-scope (change) transparent
+    /// address at the address index of each external receiver, with metadata carrying
+    /// [`zip32::Scope::Internal`]. Internal addresses can only be derived for accounts that
+    /// have a UFVK.
+    pub fn transparent_receivers(
+        &self,
+        account: AccountUuid,
+        include_internal: bool,
+    ) -> Result<HashMap<TransparentAddress, Option<TransparentAddressMetadata>>, SqliteClientError>
+    {
+        wallet::transparent::get_transparent_receivers(
+            self.conn.borrow(),
+            &self.params,
+            account,
+            include_internal,
+        )
+    }
+
+    /// Returns every transparent address at which the given account could have received
+    /// funds, along with the metadata describing how each address was derived.
+    ///
+    /// This covers the transparent receivers of generated unified addresses, the legacy
+    /// transparent address, the external and internal gap windows, and all known ephemeral
+    /// addresses. It is intended for watch-only tools, and does not modify the wallet.
+    pub fn enumerate_watch_addresses(
+        &self,
+        account: AccountUuid,
+    ) -> Result<Vec<(TransparentAddress, TransparentAddressMetadata)>, SqliteClientError> {
+        wallet::transparent::enumerate_watch_addresses(self.conn.borrow(), &self.params, account)
+    }
+}
+
+#[cfg(feature = "transparent-inputs")]
+impl<P: consensus::Parameters + Clone> WalletDb<Connection, P> {
+    /// Removes a transparent output that was erroneously recorded as having been received by
+    /// the wallet, along with any record of its spend and any pending search for its spend.
+    ///
+    /// Returns [`SqliteClientError::TransparentOutputSpentByWalletTx`] without modifying the
+    /// wallet if the output is spent by a transaction that was created by this wallet. Spends
+    /// by other transactions are removed along with the output.
+    ///
+    /// Returns whether any record of the output was removed.
+    pub fn forget_transparent_utxo(
+        &mut self,
+        outpoint: &OutPoint,
+    ) -> Result<bool, SqliteClientError> {
+        self.transactionally(|wdb| {
+            wallet::transparent::forget_transparent_utxo(wdb.conn.0, outpoint)
+        })
+    }
+}
+
 impl<C: Borrow<rusqlite::Connection>, P: consensus::Parameters> InputSource for WalletDb<C, P> {
     type Error = SqliteClientError;
     type NoteRef = ReceivedNoteId;
@@ -358,7 +467,12 @@ impl<C: Borrow<rusqlite::Connection>, P: consensus::Parameters> InputSource for
         &self,
         outpoint: &OutPoint,
     ) -> Result<Option<WalletTransparentOutput>, Self::Error> {
-        wallet::transparent::get_wallet_transparent_output(self.conn.borrow(), outpoint, false)
+        wallet::transparent::get_wallet_transparent_output(
+            self.conn.borrow(),
+            outpoint,
+            false,
+            self.allow_zero_conf_spends,
+        )
     }
 
     #[cfg(feature = "transparent-inputs")]
@@ -374,6 +488,7 @@ impl<C: Borrow<rusqlite::Connection>, P: consensus::Parameters> InputSource for
             address,
             target_height,
             min_confirmations,
+            self.allow_zero_conf_spends,
         )
     }
 
@@ -632,7 +747,12 @@ impl<C: Borrow<rusqlite::Connection>, P: consensus::Parameters> WalletRead for W
         &self,
         account: Self::AccountId,
     ) -> Result<HashMap<TransparentAddress, Option<TransparentAddressMetadata>>, Self::Error> {
-        wallet::transparent::get_transparent_receivers(self.conn.borrow(), &self.params, account)
+        wallet::transparent::get_transparent_receivers(
+            self.conn.borrow(),
+            &self.params,
+            account,
+            false,
+        )
     }
 
     #[cfg(feature = "transparent-inputs")]
@@ -800,6 +920,7 @@ impl<C: Borrow<rusqlite::Connection>, P: consensus::Parameters> WalletTest for W
             self.conn.borrow(),
             outpoint,
             allow_unspendable,
+            self.allow_zero_conf_spends,
         )
     }
 
@@ -2261,6 +2382,54 @@ mod tests {
         assert!(receivers.contains_key(&taddr));
     }
 
+    #[cfg(feature = "transparent-inputs")]
+    #[test]
+    fn transparent_receivers_including_internal() {
+        use zcash_primitives::legacy::keys::{IncomingViewingKey, TransparentKeyScope};
+
+        use crate::testing::BlockCache;
+        let st = TestBuilder::new()
+            .with_data_store_factory(TestDbFactory::default())
+            .with_block_cache(BlockCache::new())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let account = st.test_account().unwrap();
+        let (_, address_index) = account.usk().default_transparent_address();
+        let internal_taddr = account
+            .usk()
+            .transparent()
+            .to_account_pubkey()
+            .derive_internal_ivk()
+            .unwrap()
+            .derive_address(address_index)
+            .unwrap();
+
+        // By default, only external receivers are returned.
+        let external = st
+            .wallet()
+            .transparent_receivers(account.id(), false)
+            .unwrap();
+        assert_eq!(
+            external,
+            st.wallet().get_transparent_receivers(account.id()).unwrap()
+        );
+        assert!(!external.contains_key(&internal_taddr));
+        assert!(external.values().all(|metadata| {
+            metadata.as_ref().unwrap().scope() == TransparentKeyScope::EXTERNAL
+        }));
+
+        let all = st
+            .wallet()
+            .transparent_receivers(account.id(), true)
+            .unwrap();
+        let metadata = all.get(&internal_taddr).unwrap().as_ref().unwrap();
+        assert_eq!(metadata.scope(), TransparentKeyScope::INTERNAL);
+        assert_eq!(metadata.address_index(), address_index);
+        for (taddr, metadata) in external {
+            assert_eq!(all.get(&taddr), Some(&metadata));
+        }
+    }
+
     #[cfg(feature = "unstable")]
     #[test]
     pub(crate) fn fsblockdb_api() {
@@ -2521,6 +2521,38 @@ pub(crate) fn truncate_to_height<P: consensus::Parameters>(
         named_params![":height": u32::from(truncation_height)],
     )?;
 
+    // Revert observations of ephemeral addresses in transactions that are about to be un-mined.
+    // Such an observation no longer extends the range of addresses that are safe to reserve, so
+    // `first_unsafe_index` returns to the bound implied by the remaining mined observations. A
+    // transaction created by this wallet remains pending after the truncation, so `used_in_tx`
+    // is retained and the address continues to be treated as seen in that transaction. Each
+    // transaction whose observation is cleared is queued for enhancement, so that the address
+    // is marked as seen again if the transaction is mined in the new chain.
+    #[cfg(feature = "transparent-inputs")]
+    {
+        conn.execute(
+            "INSERT INTO tx_retrieval_queue (txid, query_type, dependent_transaction_id)
+             SELECT DISTINCT t.txid, :enhancement_type, NULL
+             FROM ephemeral_addresses e
+             JOIN transactions t ON t.id_tx = e.seen_in_tx
+             WHERE t.mined_height > :height
+             AND e.seen_in_tx IS NOT e.used_in_tx
+             ON CONFLICT (txid) DO UPDATE
+             SET query_type = :enhancement_type",
+            named_params![
+                ":height": u32::from(truncation_height),
+                ":enhancement_type": TxQueryType::Enhancement.code(),
+            ],
+        )?;
+        conn.execute(
+            "UPDATE ephemeral_addresses
+             SET seen_in_tx = used_in_tx
+             WHERE seen_in_tx IN (SELECT id_tx FROM transactions WHERE mined_height > :height)
+             AND seen_in_tx IS NOT used_in_tx",
+            named_params![":height": u32::from(truncation_height)],
+        )?;
+    }
+
     // Un-mine transactions. This must be done outside of the last_scanned_height check because
     // transaction entries may be created as a consequence of receiving transparent TXOs.
     conn.execute(
@@ -2537,6 +2569,7 @@ pub(crate) fn truncate_to_height<P: consensus::Parameters>(
         let mut wdb = WalletDb {
             conn: SqlTransaction(conn),
             params: params.clone(),
+            allow_zero_conf_spends: false,
         };
         wdb.with_sapling_tree_mut(|tree| {
             tree.truncate_to_checkpoint(&truncation_height)?;
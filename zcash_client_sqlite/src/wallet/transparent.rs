@@ -1,5 +1,6 @@
 //! Functions for transparent input support in the wallet.
 use std::collections::{HashMap, HashSet};
+use std::ops::Range;
 
 use rusqlite::OptionalExtension;
 use rusqlite::{named_params, Connection, Row};
@@ -9,20 +10,23 @@ use zip32::{DiversifierIndex, Scope};
 
 use zcash_address::unified::{Ivk, Uivk};
 use zcash_client_backend::{
-    data_api::AccountBalance,
+    data_api::{Account as _, AccountBalance, GAP_LIMIT},
     wallet::{TransparentAddressMetadata, WalletTransparentOutput},
 };
 use zcash_keys::{address::Address, encoding::AddressCodec};
 use zcash_primitives::{
     legacy::{
-        keys::{IncomingViewingKey, NonHardenedChildIndex},
+        keys::{IncomingViewingKey, NonHardenedChildIndex, TransparentKeyScope},
         Script, TransparentAddress,
     },
-    transaction::components::{amount::NonNegativeAmount, Amount, OutPoint, TxOut},
+    transaction::{
+        components::{amount::NonNegativeAmount, Amount, OutPoint, TxOut},
+        TxId,
+    },
 };
 use zcash_protocol::consensus::{self, BlockHeight};
 
-use super::{chain_tip_height, get_account_ids};
+use super::{chain_tip_height, get_account, get_account_ids, get_account_ref};
 use crate::AccountUuid;
 use crate::{error::SqliteClientError, TxRef, UtxoId};
 
@@ -79,10 +83,17 @@ fn address_index_from_diversifier_index_be(
     })
 }
 
+/// Returns the transparent receivers of the unified addresses generated for the given account,
+/// along with the account's legacy transparent address.
+///
+/// If `include_internal` is set, the internal-scope transparent address at the address index of
+/// each of those receivers is also returned, if the account has a UFVK from which it can be
+/// derived.
 pub(crate) fn get_transparent_receivers<P: consensus::Parameters>(
     conn: &rusqlite::Connection,
     params: &P,
     account_uuid: AccountUuid,
+    include_internal: bool,
 ) -> Result<HashMap<TransparentAddress, Option<TransparentAddressMetadata>>, SqliteClientError> {
     let mut ret: HashMap<TransparentAddress, Option<TransparentAddressMetadata>> = HashMap::new();
 
@@ -125,6 +136,27 @@ pub(crate) fn get_transparent_receivers<P: consensus::Parameters>(
         ret.insert(taddr, Some(metadata));
     }
 
+    if include_internal {
+        let internal_ivk = get_account(conn, params, account_uuid)?
+            .and_then(|account| account.ufvk().and_then(|ufvk| ufvk.transparent().cloned()))
+            .map(|account_pubkey| account_pubkey.derive_internal_ivk())
+            .transpose()?;
+        if let Some(internal_ivk) = internal_ivk {
+            let address_indices = ret
+                .values()
+                .flatten()
+                .map(|metadata| metadata.address_index())
+                .collect::<Vec<_>>();
+            for address_index in address_indices {
+                if let Ok(taddr) = internal_ivk.derive_address(address_index) {
+                    let metadata =
+                        TransparentAddressMetadata::new(Scope::Internal.into(), address_index);
+                    ret.insert(taddr, Some(metadata));
+                }
+            }
+        }
+    }
+
     Ok(ret)
 }
 
@@ -217,6 +249,7 @@ pub(crate) fn get_wallet_transparent_output(
     conn: &rusqlite::Connection,
     outpoint: &OutPoint,
     allow_unspendable: bool,
+    allow_zero_conf: bool,
 ) -> Result<Option<WalletTransparentOutput>, SqliteClientError> {
     let chain_tip_height = chain_tip_height(conn)?;
 
@@ -238,9 +271,13 @@ pub(crate) fn get_wallet_transparent_output(
              OR (
                  (
                     t.mined_height IS NOT NULL -- tx is mined
-                    -- TODO: uncomment the following two lines in order to enable zero-conf spends
-                    -- OR t.expiry_height = 0 -- tx will not expire
-                    -- OR t.expiry_height >= :mempool_height -- tx has not yet expired
+                    OR (
+                        :allow_zero_conf -- the wallet allows zero-conf spends
+                        AND (
+                            t.expiry_height = 0 -- tx will not expire
+                            OR t.expiry_height >= :mempool_height -- tx has not yet expired
+                        )
+                    )
                  )
                  -- and the output is unspent
                  AND u.id NOT IN (
@@ -261,7 +298,8 @@ pub(crate) fn get_wallet_transparent_output(
                 ":txid": outpoint.hash(),
                 ":output_index": outpoint.n(),
                 ":mempool_height": chain_tip_height.map(|h| u32::from(h) + 1),
-                ":allow_unspendable": allow_unspendable
+                ":allow_unspendable": allow_unspendable,
+                ":allow_zero_conf": allow_zero_conf,
             ],
             to_unspent_transparent_output,
         )?
@@ -277,6 +315,9 @@ pub(crate) fn get_wallet_transparent_output(
 ///   confirmations; and
 /// * the output is unspent as of the current chain tip.
 ///
+/// If `allow_zero_conf` is set and `min_confirmations` is zero, the outputs of unmined
+/// transactions that will not expire or have not expired as of `target_height` are included.
+///
 /// An output that is potentially spent by an unmined transaction in the mempool is excluded
 /// iff the spending transaction will not be expired at `target_height`.
 ///
@@ -290,6 +331,7 @@ pub(crate) fn get_spendable_transparent_outputs<P: consensus::Parameters>(
     address: &TransparentAddress,
     target_height: BlockHeight,
     min_confirmations: u32,
+    allow_zero_conf: bool,
 ) -> Result<Vec<WalletTransparentOutput>, SqliteClientError> {
     let confirmed_height = target_height - min_confirmations;
 
@@ -302,14 +344,15 @@ pub(crate) fn get_spendable_transparent_outputs<P: consensus::Parameters>(
          -- the transaction that created the output is mined or unexpired as of `confirmed_height`
          AND (
             t.mined_height <= :confirmed_height -- tx is mined
-            -- TODO: uncomment the following lines in order to enable zero-conf spends
-            -- OR (
-            --     :min_confirmations = 0
-            --     AND (
-            --         t.expiry_height = 0 -- tx will not expire
-            --         OR t.expiry_height >= :target_height
-            --     )
-            -- )
+            OR (
+                :allow_zero_conf -- the wallet allows zero-conf spends
+                AND :min_confirmations = 0
+                AND t.mined_height IS NULL
+                AND (
+                    t.expiry_height = 0 -- tx will not expire
+                    OR t.expiry_height >= :target_height -- tx has not yet expired
+                )
+            )
          )
          -- and the output is unspent
          AND u.id NOT IN (
@@ -330,7 +373,8 @@ pub(crate) fn get_spendable_transparent_outputs<P: consensus::Parameters>(
         ":address": addr_str,
         ":confirmed_height": u32::from(confirmed_height),
         ":target_height": u32::from(target_height),
-        //":min_confirmations": min_confirmations
+        ":min_confirmations": min_confirmations,
+        ":allow_zero_conf": allow_zero_conf,
     ])?;
 
     let mut utxos = Vec::<WalletTransparentOutput>::new();
@@ -511,6 +555,96 @@ pub(crate) fn mark_transparent_utxo_spent(
     Ok(affected_rows > 0)
 }
 
+/// Removes the given transparent output, along with any record of its spend and any pending
+/// search for its spend, from the wallet.
+///
+/// Returns [`SqliteClientError::TransparentOutputSpentByWalletTx`] without modifying the
+/// wallet if the output is spent by a transaction that was created by this wallet.
+///
+/// Returns `true` if any record of the output was removed.
+pub(crate) fn forget_transparent_utxo(
+    conn: &rusqlite::Transaction<'_>,
+    outpoint: &OutPoint,
+) -> Result<bool, SqliteClientError> {
+    let outpoint_params = named_params![
+        ":prevout_txid": outpoint.hash().as_ref(),
+        ":prevout_idx": outpoint.n(),
+    ];
+
+    let wallet_spend = conn
+        .query_row(
+            "SELECT t.txid
+             FROM transactions t
+             WHERE t.target_height IS NOT NULL
+             AND (
+                t.id_tx IN (
+                    SELECT txos.transaction_id
+                    FROM transparent_received_output_spends txos
+                    JOIN transparent_received_outputs txo
+                        ON txo.id = txos.transparent_received_output_id
+                    JOIN transactions pt ON pt.id_tx = txo.transaction_id
+                    WHERE pt.txid = :prevout_txid
+                    AND txo.output_index = :prevout_idx
+                )
+                OR t.id_tx IN (
+                    SELECT spending_transaction_id
+                    FROM transparent_spend_map
+                    WHERE prevout_txid = :prevout_txid
+                    AND prevout_output_index = :prevout_idx
+                )
+             )",
+            outpoint_params,
+            |row| row.get::<_, Vec<u8>>(0),
+        )
+        .optional()?;
+    if let Some(txid_bytes) = wallet_spend {
+        let txid = TxId::from_bytes(txid_bytes.try_into().map_err(|_| {
+            SqliteClientError::CorruptedData("Transaction ID is not 32 bytes".to_owned())
+        })?);
+        return Err(SqliteClientError::TransparentOutputSpentByWalletTx(
+            outpoint.clone(),
+            txid,
+        ));
+    }
+
+    // Rows referring to the output must be removed before the output itself.
+    let removed_spends = conn.execute(
+        "DELETE FROM transparent_received_output_spends
+         WHERE transparent_received_output_id IN (
+            SELECT txo.id
+            FROM transparent_received_outputs txo
+            JOIN transactions t ON t.id_tx = txo.transaction_id
+            WHERE t.txid = :prevout_txid
+            AND txo.output_index = :prevout_idx
+         )",
+        outpoint_params,
+    )?;
+    let removed_searches = conn.execute(
+        "DELETE FROM transparent_spend_search_queue
+         WHERE output_index = :prevout_idx
+         AND transaction_id IN (
+            SELECT id_tx FROM transactions WHERE txid = :prevout_txid
+         )",
+        outpoint_params,
+    )?;
+    let removed_spend_map = conn.execute(
+        "DELETE FROM transparent_spend_map
+         WHERE prevout_txid = :prevout_txid
+         AND prevout_output_index = :prevout_idx",
+        outpoint_params,
+    )?;
+    let removed_outputs = conn.execute(
+        "DELETE FROM transparent_received_outputs
+         WHERE output_index = :prevout_idx
+         AND transaction_id IN (
+            SELECT id_tx FROM transactions WHERE txid = :prevout_txid
+         )",
+        outpoint_params,
+    )?;
+
+    Ok(removed_spends + removed_searches + removed_spend_map + removed_outputs > 0)
+}
+
 /// Adds the given received UTXO to the datastore.
 pub(crate) fn put_received_transparent_utxo<P: consensus::Parameters>(
     conn: &rusqlite::Connection,
@@ -624,6 +758,145 @@ pub(crate) fn get_transparent_address_metadata<P: consensus::Parameters>(
     Ok(None)
 }
 
+/// Returns every transparent address at which the given account could have received funds,
+/// along with the metadata describing how each address was derived.
+///
+/// The result includes the transparent receivers of the unified addresses generated so far,
+/// the account's legacy transparent address, the next [`GAP_LIMIT`] external addresses beyond
+/// the highest external index that has been generated, the first [`GAP_LIMIT`] internal
+/// addresses, and all known ephemeral addresses (including those in the gap window beyond the
+/// last reserved index). Each address appears only once.
+///
+/// This does not reserve or generate any addresses.
+pub(crate) fn enumerate_watch_addresses<P: consensus::Parameters>(
+    conn: &rusqlite::Connection,
+    params: &P,
+    account_uuid: AccountUuid,
+) -> Result<Vec<(TransparentAddress, TransparentAddressMetadata)>, SqliteClientError> {
+    let account =
+        get_account(conn, params, account_uuid)?.ok_or(SqliteClientError::AccountUnknown)?;
+
+    let mut seen = HashSet::new();
+    let mut result = vec![];
+    let mut push = |address: TransparentAddress, metadata: TransparentAddressMetadata| {
+        if seen.insert(address) {
+            result.push((address, metadata));
+        }
+    };
+
+    // External addresses that have been generated, in order of their address index.
+    let mut receivers = get_transparent_receivers(conn, params, account_uuid, false)?
+        .into_iter()
+        .filter_map(|(address, metadata)| metadata.map(|metadata| (address, metadata)))
+        .collect::<Vec<_>>();
+    receivers.sort_by_key(|(_, metadata)| metadata.address_index().index());
+    let external_end = receivers
+        .last()
+        .map_or(0, |(_, metadata)| metadata.address_index().index() + 1);
+    for (address, metadata) in receivers {
+        push(address, metadata);
+    }
+
+    if let Some(account_pubkey) = account.ufvk().and_then(|ufvk| ufvk.transparent()) {
+        let external_ivk = account_pubkey.derive_external_ivk()?;
+        for (address, metadata) in derive_window(
+            &external_ivk,
+            TransparentKeyScope::EXTERNAL,
+            external_end..external_end.saturating_add(GAP_LIMIT),
+        ) {
+            push(address, metadata);
+        }
+
+        // Internal transparent addresses are never generated by the wallet, so only the gap
+        // window from the first index is covered.
+        let internal_ivk = account_pubkey.derive_internal_ivk()?;
+        for (address, metadata) in
+            derive_window(&internal_ivk, TransparentKeyScope::INTERNAL, 0..GAP_LIMIT)
+        {
+            push(address, metadata);
+        }
+    }
+
+    let account_ref = get_account_ref(conn, account_uuid)?;
+    for (address, metadata) in
+        ephemeral::get_known_ephemeral_addresses(conn, params, account_ref, None)?
+    {
+        push(address, metadata);
+    }
+
+    Ok(result)
+}
+
+/// Derives the addresses at the given indices of a transparent key scope, skipping any index
+/// that is hardened or at which no valid address exists.
+fn derive_window<K: IncomingViewingKey>(
+    ivk: &K,
+    scope: TransparentKeyScope,
+    indices: Range<u32>,
+) -> impl Iterator<Item = (TransparentAddress, TransparentAddressMetadata)> + '_ {
+    indices
+        .filter_map(NonHardenedChildIndex::from_index)
+        .filter_map(move |address_index| {
+            ivk.derive_address(address_index).ok().map(|address| {
+                (
+                    address,
+                    TransparentAddressMetadata::new(scope, address_index),
+                )
+            })
+        })
+}
+
+/// Returns the key derivation metadata for each of the given transparent outputs, in the
+/// order in which the outpoints were provided.
+///
+/// The receiving address of each output is resolved against the specified account using
+/// [`get_transparent_address_metadata`], looking up each distinct address only once.
+pub(crate) fn get_metadata_for_outpoints<P: consensus::Parameters>(
+    conn: &rusqlite::Connection,
+    params: &P,
+    account_uuid: AccountUuid,
+    outpoints: &[OutPoint],
+) -> Result<Vec<(OutPoint, TransparentAddressMetadata)>, SqliteClientError> {
+    let mut stmt_select_address = conn.prepare_cached(
+        "SELECT u.address
+         FROM transparent_received_outputs u
+         JOIN transactions t ON t.id_tx = u.transaction_id
+         WHERE t.txid = :txid
+         AND u.output_index = :output_index",
+    )?;
+
+    let mut metadata_cache = HashMap::<TransparentAddress, TransparentAddressMetadata>::new();
+    outpoints
+        .iter()
+        .map(|outpoint| {
+            let address_str = stmt_select_address
+                .query_row(
+                    named_params![
+                        ":txid": outpoint.hash(),
+                        ":output_index": outpoint.n(),
+                    ],
+                    |row| row.get::<_, String>(0),
+                )
+                .optional()?
+                .ok_or_else(|| SqliteClientError::TransparentOutputNotFound(outpoint.clone()))?;
+            let address = TransparentAddress::decode(params, &address_str)?;
+
+            let metadata = match metadata_cache.get(&address) {
+                Some(metadata) => metadata.clone(),
+                None => {
+                    let metadata =
+                        get_transparent_address_metadata(conn, params, account_uuid, &address)?
+                            .ok_or(SqliteClientError::AddressNotRecognized(address))?;
+                    metadata_cache.insert(address, metadata.clone());
+                    metadata
+                }
+            };
+
+            Ok((outpoint.clone(), metadata))
+        })
+        .collect()
+}
+
 /// Attempts to determine the account that received the given transparent output.
 ///
 /// The following three locations in the wallet's key tree are searched:
@@ -854,7 +1127,39 @@ pub(crate) fn queue_transparent_spend_detection<P: consensus::Parameters>(
 
 #[cfg(test)]
 mod tests {
-    use crate::testing::{db::TestDbFactory, BlockCache};
+    use secrecy::SecretVec;
+    use zcash_client_backend::{
+        data_api::{testing::TestBuilder, Account as _, InputSource, WalletRead, WalletWrite},
+        keys::UnifiedAddressRequest,
+        wallet::WalletTransparentOutput,
+    };
+    use zcash_primitives::{
+        block::BlockHash,
+        legacy::{
+            keys::{IncomingViewingKey, NonHardenedChildIndex, TransparentKeyScope},
+            TransparentAddress,
+        },
+        transaction::components::{amount::NonNegativeAmount, OutPoint, TxOut},
+    };
+    use zcash_protocol::local_consensus::LocalNetwork;
+
+    use crate::{
+        error::SqliteClientError,
+        testing::{db::TestDbFactory, BlockCache},
+        TxRef, WalletDb,
+    };
+
+    fn fake_utxo(seed: u8, address: &TransparentAddress) -> WalletTransparentOutput {
+        WalletTransparentOutput::from_parts(
+            OutPoint::new([seed; 32], 0),
+            TxOut {
+                value: NonNegativeAmount::const_from_u64(100000),
+                script_pubkey: address.script(),
+            },
+            None,
+        )
+        .unwrap()
+    }
 
     #[test]
     fn put_received_transparent_utxo() {
@@ -870,4 +1175,321 @@ mod tests {
             BlockCache::new(),
         );
     }
+
+    #[test]
+    fn multiple_outputs_to_same_address() {
+        zcash_client_backend::data_api::testing::transparent::multiple_outputs_to_same_address(
+            TestDbFactory::default(),
+        );
+    }
+
+    #[test]
+    fn ephemeral_address_gap_rewound_on_truncation() {
+        zcash_client_backend::data_api::testing::transparent::ephemeral_address_gap_rewound_on_truncation(
+            TestDbFactory::default(),
+            BlockCache::new(),
+        );
+    }
+
+    #[test]
+    fn metadata_for_outpoints() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestDbFactory::default())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let account = st.test_account().cloned().unwrap();
+
+        let (legacy_addr, legacy_index) = account
+            .usk()
+            .transparent()
+            .to_account_pubkey()
+            .derive_external_ivk()
+            .unwrap()
+            .default_address();
+        // The last known ephemeral address lies within the gap limit, beyond any reserved index.
+        let (ephemeral_addr, ephemeral_meta) = st
+            .wallet()
+            .get_known_ephemeral_addresses(account.id(), None)
+            .unwrap()
+            .pop()
+            .unwrap();
+
+        let legacy_utxo = fake_utxo(1, &legacy_addr);
+        let ephemeral_utxo = fake_utxo(2, &ephemeral_addr);
+        st.wallet_mut()
+            .put_received_transparent_utxo(&legacy_utxo)
+            .unwrap();
+        st.wallet_mut()
+            .put_received_transparent_utxo(&ephemeral_utxo)
+            .unwrap();
+
+        let metadata = st
+            .wallet()
+            .db()
+            .metadata_for_outpoints(
+                account.id(),
+                &[
+                    ephemeral_utxo.outpoint().clone(),
+                    legacy_utxo.outpoint().clone(),
+                ],
+            )
+            .unwrap();
+        assert_eq!(metadata.len(), 2);
+        assert_eq!(&metadata[0].0, ephemeral_utxo.outpoint());
+        assert_eq!(metadata[0].1.scope(), TransparentKeyScope::EPHEMERAL);
+        assert_eq!(
+            metadata[0].1.address_index(),
+            ephemeral_meta.address_index()
+        );
+        assert_eq!(&metadata[1].0, legacy_utxo.outpoint());
+        assert_eq!(metadata[1].1.scope(), TransparentKeyScope::EXTERNAL);
+        assert_eq!(metadata[1].1.address_index(), legacy_index);
+
+        // An output received at an address belonging to another account cannot be signed for
+        // by this account, and the error names the address.
+        let (_, other_usk) = st
+            .wallet_mut()
+            .create_account(
+                "other",
+                &SecretVec::new(vec![7u8; 32]),
+                account.birthday(),
+                None,
+            )
+            .unwrap();
+        let (other_addr, _) = other_usk
+            .transparent()
+            .to_account_pubkey()
+            .derive_external_ivk()
+            .unwrap()
+            .default_address();
+        let other_utxo = fake_utxo(3, &other_addr);
+        st.wallet_mut()
+            .put_received_transparent_utxo(&other_utxo)
+            .unwrap();
+
+        assert_matches!(
+            st.wallet()
+                .db()
+                .metadata_for_outpoints(account.id(), &[other_utxo.outpoint().clone()]),
+            Err(SqliteClientError::AddressNotRecognized(addr)) if addr == other_addr
+        );
+        assert_matches!(
+            st.wallet()
+                .db()
+                .metadata_for_outpoints(account.id(), &[OutPoint::new([4; 32], 0)]),
+            Err(SqliteClientError::TransparentOutputNotFound(_))
+        );
+    }
+
+    #[test]
+    fn watch_addresses_cover_each_derivation_path() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestDbFactory::default())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let account = st.test_account().cloned().unwrap();
+
+        let tables_before = dump_address_tables(st.wallet().conn());
+        let before = st
+            .wallet()
+            .db()
+            .enumerate_watch_addresses(account.id())
+            .unwrap();
+        let watched = st
+            .wallet()
+            .db()
+            .enumerate_watch_addresses(account.id())
+            .unwrap();
+        assert_eq!(watched, before);
+        assert_eq!(dump_address_tables(st.wallet().conn()), tables_before);
+
+        let contains = |address, scope| {
+            watched
+                .iter()
+                .any(|(a, meta)| a == address && meta.scope() == scope)
+        };
+        let mut addresses = watched.iter().map(|(a, _)| *a).collect::<Vec<_>>();
+        addresses.sort();
+        addresses.dedup();
+        assert_eq!(addresses.len(), watched.len());
+
+        let account_pubkey = account.usk().transparent().to_account_pubkey();
+        let (legacy_addr, _) = account_pubkey
+            .derive_external_ivk()
+            .unwrap()
+            .default_address();
+        assert!(contains(&legacy_addr, TransparentKeyScope::EXTERNAL));
+        let internal_addr = account_pubkey
+            .derive_internal_ivk()
+            .unwrap()
+            .derive_address(NonHardenedChildIndex::ZERO)
+            .unwrap();
+        assert!(contains(&internal_addr, TransparentKeyScope::INTERNAL));
+
+        // Addresses generated or reserved after the enumeration fall within the gap windows,
+        // and funds received at them are detected.
+        let next = st
+            .wallet_mut()
+            .get_next_available_address(account.id(), UnifiedAddressRequest::all().unwrap())
+            .unwrap()
+            .unwrap();
+        let next_taddr = next.transparent().unwrap();
+        assert!(contains(next_taddr, TransparentKeyScope::EXTERNAL));
+        st.wallet_mut()
+            .put_received_transparent_utxo(&fake_utxo(5, next_taddr))
+            .unwrap();
+
+        let (ephemeral_addr, _) = st
+            .wallet_mut()
+            .reserve_next_n_ephemeral_addresses(account.id(), 1)
+            .unwrap()
+            .pop()
+            .unwrap();
+        assert!(contains(&ephemeral_addr, TransparentKeyScope::EPHEMERAL));
+        st.wallet_mut()
+            .put_received_transparent_utxo(&fake_utxo(6, &ephemeral_addr))
+            .unwrap();
+    }
+
+    fn dump_address_tables(conn: &rusqlite::Connection) -> Vec<String> {
+        let mut stmt = conn
+            .prepare(
+                "SELECT 'a' || address FROM addresses
+                 UNION ALL
+                 SELECT 'e' || address FROM ephemeral_addresses
+                 ORDER BY 1",
+            )
+            .unwrap();
+        stmt.query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    fn record_spend(
+        conn: &rusqlite::Connection,
+        seed: u8,
+        target_height: Option<u32>,
+        outpoint: &OutPoint,
+    ) {
+        conn.execute(
+            "INSERT INTO transactions (txid, target_height) VALUES (:txid, :target_height)",
+            rusqlite::named_params![":txid": &[seed; 32][..], ":target_height": target_height],
+        )
+        .unwrap();
+        super::mark_transparent_utxo_spent(conn, TxRef(conn.last_insert_rowid()), outpoint)
+            .unwrap();
+    }
+
+    #[test]
+    fn forget_transparent_utxo() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestDbFactory::default())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let account = st.test_account().cloned().unwrap();
+        let (taddr, _) = account
+            .usk()
+            .transparent()
+            .to_account_pubkey()
+            .derive_external_ivk()
+            .unwrap()
+            .default_address();
+
+        let unspent = fake_utxo(1, &taddr);
+        let foreign_spent = fake_utxo(2, &taddr);
+        let wallet_spent = fake_utxo(3, &taddr);
+        for utxo in [&unspent, &foreign_spent, &wallet_spent] {
+            st.wallet_mut().put_received_transparent_utxo(utxo).unwrap();
+        }
+
+        // Record spends of two of the outputs, one by a transaction that was not created by
+        // this wallet and one by a transaction that was.
+        record_spend(st.wallet().conn(), 12, None, foreign_spent.outpoint());
+        record_spend(st.wallet().conn(), 13, Some(100), wallet_spent.outpoint());
+
+        let db = st.wallet_mut().db_mut();
+        assert!(db.forget_transparent_utxo(unspent.outpoint()).unwrap());
+        assert!(!db.forget_transparent_utxo(unspent.outpoint()).unwrap());
+        assert!(db
+            .forget_transparent_utxo(foreign_spent.outpoint())
+            .unwrap());
+        assert_matches!(
+            db.forget_transparent_utxo(wallet_spent.outpoint()),
+            Err(SqliteClientError::TransparentOutputSpentByWalletTx(outpoint, txid))
+                if &outpoint == wallet_spent.outpoint() && txid.as_ref() == &[13; 32]
+        );
+
+        assert!(db
+            .get_unspent_transparent_output(unspent.outpoint())
+            .unwrap()
+            .is_none());
+        assert!(db
+            .get_unspent_transparent_output(foreign_spent.outpoint())
+            .unwrap()
+            .is_none());
+        let remaining_spends: i64 = db
+            .conn
+            .query_row(
+                "SELECT COUNT(*) FROM transparent_received_output_spends",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(remaining_spends, 1);
+    }
+
+    #[test]
+    fn zero_conf_transparent_spends() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestDbFactory::default())
+            .with_account_from_sapling_activation(BlockHash([0; 32]))
+            .build();
+        let account = st.test_account().cloned().unwrap();
+        let (taddr, _) = account
+            .usk()
+            .transparent()
+            .to_account_pubkey()
+            .derive_external_ivk()
+            .unwrap()
+            .default_address();
+        let target_height = account.birthday().height() + 10;
+
+        // Two outputs of unmined transactions: one that expires just before the target
+        // height, and one that will not expire.
+        let expiring = fake_utxo(1, &taddr);
+        let unexpiring = fake_utxo(2, &taddr);
+        for (utxo, expiry_height) in [(&expiring, u32::from(target_height) - 1), (&unexpiring, 0)] {
+            st.wallet_mut().put_received_transparent_utxo(utxo).unwrap();
+            st.wallet()
+                .conn()
+                .execute(
+                    "UPDATE transactions SET expiry_height = :expiry_height WHERE txid = :txid",
+                    rusqlite::named_params![
+                        ":expiry_height": expiry_height,
+                        ":txid": utxo.outpoint().hash(),
+                    ],
+                )
+                .unwrap();
+        }
+
+        let spendable = |db: &WalletDb<rusqlite::Connection, LocalNetwork>,
+                         min_confirmations: u32| {
+            db.get_spendable_transparent_outputs(&taddr, target_height, min_confirmations)
+                .unwrap()
+                .into_iter()
+                .map(|utxo| *utxo.outpoint())
+                .collect::<Vec<_>>()
+        };
+
+        // Zero-conf spends are disallowed by default.
+        assert!(!st.wallet().db().allows_zero_conf_spends());
+        assert_eq!(spendable(st.wallet().db(), 0), vec![]);
+
+        // Once allowed, only the unexpired output is spendable, and only with zero
+        // confirmations.
+        st.wallet_mut().db_mut().set_allow_zero_conf_spends(true);
+        assert_eq!(spendable(st.wallet().db(), 0), vec![*unexpiring.outpoint()]);
+        assert_eq!(spendable(st.wallet().db(), 1), vec![]);
+    }
 }